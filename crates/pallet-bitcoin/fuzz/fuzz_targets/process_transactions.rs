@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pallet_bitcoin::fuzzing::{run_transactions, FuzzTransaction};
+
+fuzz_target!(|txs: Vec<FuzzTransaction>| {
+    run_transactions(&txs);
+});
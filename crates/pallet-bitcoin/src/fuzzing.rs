@@ -0,0 +1,263 @@
+//! Fuzzing harness of the transaction processing.
+//!
+//! The transactions are applied to both the pallet on the mock runtime and a reference model of
+//! the UTXO set, the resulting coins must be identical. The pallet panics on the transactions
+//! that fail to decode or spend the coins not in the UTXO set, both of which are ruled out by
+//! the verification outside the runtime, so the harness skips such transactions instead of
+//! feeding them into the pallet. Any other panic is a bug.
+
+use crate::mock::{new_test_ext, Test};
+use crate::{Coins, Pallet};
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::Decodable;
+use bitcoin::hashes::Hash;
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+use std::collections::{BTreeMap, BTreeSet};
+use subcoin_runtime_primitives::{stored_script_pubkey, Coin};
+
+/// Reference model of the UTXO set maintained by the pallet.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UtxoModel {
+    coins: BTreeMap<OutPoint, Coin>,
+}
+
+impl UtxoModel {
+    /// Applies the transaction included at `height`.
+    ///
+    /// Returns `false` without touching the model if the transaction spends a coin not in the
+    /// set, or the same coin twice.
+    pub fn apply(&mut self, tx: &Transaction, height: u32) -> bool {
+        let is_coinbase = tx.is_coinbase();
+
+        if !is_coinbase {
+            let mut spent = BTreeSet::new();
+
+            if !tx.input.iter().all(|input| {
+                self.coins.contains_key(&input.previous_output)
+                    && spent.insert(input.previous_output)
+            }) {
+                return false;
+            }
+
+            for out_point in spent {
+                self.coins.remove(&out_point);
+            }
+        }
+
+        let txid = tx.compute_txid();
+
+        for (index, txout) in tx.output.iter().enumerate() {
+            self.coins.insert(
+                OutPoint {
+                    txid,
+                    vout: index as u32,
+                },
+                Coin {
+                    is_coinbase,
+                    amount: txout.value.to_sat(),
+                    script_pubkey: stored_script_pubkey(
+                        txout.script_pubkey.to_bytes(),
+                        crate::max_script_size::<Test>(),
+                        sp_io::hashing::sha2_256,
+                    ),
+                    height,
+                },
+            );
+        }
+
+        true
+    }
+
+    /// Returns the coins in the set.
+    pub fn coins(&self) -> &BTreeMap<OutPoint, Coin> {
+        &self.coins
+    }
+}
+
+/// Output of a generated transaction.
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+#[derive(Debug, Clone)]
+pub struct FuzzOutput {
+    pub amount: u64,
+    pub script_pubkey: Vec<u8>,
+}
+
+/// Input of a generated transaction.
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+#[derive(Debug, Clone)]
+pub enum FuzzInput {
+    /// Coin at the index in the UTXO set, modulo the size of the set.
+    Existing(u16),
+    /// Arbitrary outpoint, most likely not in the UTXO set.
+    Random { txid: [u8; 32], vout: u32 },
+}
+
+/// Transaction generated structurally.
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+#[derive(Debug, Clone)]
+pub enum FuzzTransaction {
+    Coinbase {
+        script_sig: Vec<u8>,
+        outputs: Vec<FuzzOutput>,
+    },
+    Spend {
+        inputs: Vec<FuzzInput>,
+        outputs: Vec<FuzzOutput>,
+    },
+}
+
+impl FuzzTransaction {
+    /// Builds the transaction against the current UTXO set.
+    ///
+    /// Returns `None` for a transaction without any input, which does not even decode.
+    fn build(&self, model: &UtxoModel) -> Option<Transaction> {
+        let tx_in = |previous_output, script_sig| TxIn {
+            previous_output,
+            script_sig,
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+
+        let (input, outputs) = match self {
+            Self::Coinbase {
+                script_sig,
+                outputs,
+            } => (
+                vec![tx_in(
+                    OutPoint::null(),
+                    ScriptBuf::from_bytes(script_sig.clone()),
+                )],
+                outputs,
+            ),
+            Self::Spend { inputs, outputs } => {
+                let input = inputs
+                    .iter()
+                    .filter_map(|input| match input {
+                        FuzzInput::Existing(index) => {
+                            let len = model.coins.len();
+                            (len > 0).then(|| {
+                                *model
+                                    .coins
+                                    .keys()
+                                    .nth(usize::from(*index) % len)
+                                    .expect("Index is within the set; qed")
+                            })
+                        }
+                        FuzzInput::Random { txid, vout } => Some(OutPoint {
+                            txid: bitcoin::Txid::from_byte_array(*txid),
+                            vout: *vout,
+                        }),
+                    })
+                    .map(|out_point| tx_in(out_point, ScriptBuf::new()))
+                    .collect::<Vec<_>>();
+                (input, outputs)
+            }
+        };
+
+        if input.is_empty() {
+            return None;
+        }
+
+        Some(Transaction {
+            version: Version::ONE,
+            lock_time: LockTime::ZERO,
+            input,
+            output: outputs
+                .iter()
+                .map(|output| TxOut {
+                    value: Amount::from_sat(output.amount),
+                    script_pubkey: ScriptBuf::from_bytes(output.script_pubkey.clone()),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Returns the coins in the pallet storage.
+fn pallet_coins() -> BTreeMap<OutPoint, Coin> {
+    Coins::<Test>::iter()
+        .map(|(txid, vout, coin)| {
+            (
+                OutPoint {
+                    txid: txid.into_bitcoin_txid(),
+                    vout,
+                },
+                coin,
+            )
+        })
+        .collect()
+}
+
+/// Dispatches the encoded transaction in the block at `height`.
+fn transact(btc_tx: Vec<u8>, height: u32) {
+    frame_system::Pallet::<Test>::set_block_number(height.into());
+    Pallet::<Test>::process_bitcoin_transaction(Pallet::<Test>::decode_transaction(btc_tx));
+}
+
+/// Feeds the arbitrary bytes into the pallet as the encoded transaction.
+///
+/// The coins spent by the transaction are created beforehand. Returns whether the transaction
+/// has been applied.
+pub fn run_raw(data: &[u8]) -> bool {
+    let Ok(tx) = Transaction::consensus_decode(&mut &data[..]) else {
+        return false;
+    };
+
+    new_test_ext().execute_with(|| {
+        let mut model = UtxoModel::default();
+
+        if !tx.is_coinbase() {
+            for input in &tx.input {
+                let coin = Coin {
+                    is_coinbase: false,
+                    amount: 1,
+                    script_pubkey: Vec::new(),
+                    height: 0,
+                };
+                let crate::OutPointInner { txid, vout } = input.previous_output.into();
+                Coins::<Test>::insert(txid, vout, coin.clone());
+                model.coins.insert(input.previous_output, coin);
+            }
+        }
+
+        if !model.apply(&tx, 1) {
+            return false;
+        }
+
+        transact(data.to_vec(), 1);
+
+        assert_eq!(&pallet_coins(), model.coins());
+
+        true
+    })
+}
+
+/// Feeds the generated transactions into the pallet, each in its own block.
+///
+/// Returns the number of the transactions applied.
+pub fn run_transactions(txs: &[FuzzTransaction]) -> usize {
+    new_test_ext().execute_with(|| {
+        let mut model = UtxoModel::default();
+        let mut applied = 0;
+
+        for (index, tx) in txs.iter().enumerate() {
+            let height = index as u32 + 1;
+
+            let Some(tx) = tx.build(&model) else {
+                continue;
+            };
+
+            if !model.apply(&tx, height) {
+                continue;
+            }
+
+            transact(bitcoin::consensus::serialize(&tx), height);
+            applied += 1;
+
+            assert_eq!(&pallet_coins(), model.coins());
+        }
+
+        applied
+    })
+}
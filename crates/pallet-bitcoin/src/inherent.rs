@@ -0,0 +1,79 @@
+//! Inherent data of the Bitcoin block wrapped into the Substrate block.
+//!
+//! The block builder supplies the transactions of the Bitcoin block as the inherent data, each
+//! transaction is then included in the block as a [`crate::Call::transact`] inherent in the
+//! original order. The inherent of the coinbase is created by the `ProvideInherent` impl of the
+//! pallet and the following ones by [`crate::Pallet::create_inherents`].
+
+use codec::{Decode, Encode};
+use frame_support::inherent::{InherentIdentifier, IsFatalError};
+use sp_runtime::RuntimeDebug;
+use sp_std::vec::Vec;
+
+/// Identifier of the Bitcoin block inherent data.
+pub const INHERENT_IDENTIFIER: InherentIdentifier = *b"bitcoin0";
+
+/// Consensus-encoded transactions of the Bitcoin block, in the block order.
+pub type InherentType = Vec<Vec<u8>>;
+
+/// Errors of checking the inherents against the inherent data.
+#[derive(Encode, Decode, RuntimeDebug, PartialEq, Eq)]
+pub enum InherentError {
+    /// The Bitcoin block inherent data is missing.
+    MissingInherentData,
+    /// The Bitcoin transactions in the block differ from the inherent data.
+    TransactionsMismatch,
+    /// The block has no Bitcoin transaction while the inherent data has a coinbase.
+    MissingCoinbase,
+}
+
+impl IsFatalError for InherentError {
+    fn is_fatal_error(&self) -> bool {
+        true
+    }
+}
+
+/// Provides the Bitcoin block as the inherent data.
+#[cfg(feature = "std")]
+pub struct InherentDataProvider(InherentType);
+
+#[cfg(feature = "std")]
+impl InherentDataProvider {
+    /// Constructs a new instance of [`InherentDataProvider`] for `block`.
+    pub fn new(block: &bitcoin::Block) -> Self {
+        Self(
+            block
+                .txdata
+                .iter()
+                .map(bitcoin::consensus::serialize)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+#[async_trait::async_trait]
+impl sp_inherents::InherentDataProvider for InherentDataProvider {
+    async fn provide_inherent_data(
+        &self,
+        inherent_data: &mut sp_inherents::InherentData,
+    ) -> Result<(), sp_inherents::Error> {
+        inherent_data.put_data(INHERENT_IDENTIFIER, &self.0)
+    }
+
+    async fn try_handle_error(
+        &self,
+        identifier: &InherentIdentifier,
+        mut error: &[u8],
+    ) -> Option<Result<(), sp_inherents::Error>> {
+        if *identifier != INHERENT_IDENTIFIER {
+            return None;
+        }
+
+        let error = InherentError::decode(&mut error).ok()?;
+
+        Some(Err(sp_inherents::Error::Application(Box::from(format!(
+            "{error:?}"
+        )))))
+    }
+}
@@ -10,13 +10,23 @@
 // Ensure we're `no_std` when compiling for Wasm.
 #![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzzing;
+pub mod inherent;
+pub mod migrations;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod mock;
 #[cfg(test)]
 mod tests;
 
+use crate::inherent::{InherentError, InherentType, INHERENT_IDENTIFIER};
+use crate::migrations::{CoinsMigrationCursor, CoinsTranslation};
 use bitcoin::consensus::{Decodable, Encodable};
 use bitcoin::{OutPoint, Transaction as BitcoinTransaction};
 use codec::{Decode, Encode, MaxEncodedLen};
 use frame_support::dispatch::DispatchResult;
+use frame_support::inherent::InherentData;
+use frame_support::traits::{Get, GetStorageVersion};
 use frame_support::weights::Weight;
 use scale_info::TypeInfo;
 use sp_core::H256;
@@ -24,7 +34,7 @@ use sp_runtime::traits::BlockNumberProvider;
 use sp_runtime::SaturatedConversion;
 use sp_std::prelude::*;
 use sp_std::vec::Vec;
-use subcoin_runtime_primitives::Coin;
+use subcoin_runtime_primitives::{AttestationError, Coin, UtxoAttestation, MAX_SCRIPT_SIZE};
 
 // Re-export pallet items so that they can be accessed from the crate namespace.
 pub use pallet::*;
@@ -33,7 +43,7 @@ pub use pallet::*;
 pub type Vout = u32;
 
 /// Wrapper type for Bitcoin txid in runtime as `bitcoin::Txid` does not implement codec.
-#[derive(Clone, TypeInfo, Encode, Decode, MaxEncodedLen)]
+#[derive(Clone, PartialEq, Eq, TypeInfo, Encode, Decode, MaxEncodedLen)]
 pub struct Txid(H256);
 
 impl Txid {
@@ -86,17 +96,62 @@ pub mod pallet {
     use frame_support::pallet_prelude::*;
     use frame_system::pallet_prelude::*;
 
+    /// The in-code storage version.
+    const STORAGE_VERSION: StorageVersion = StorageVersion::new(2);
+
     #[pallet::config]
     pub trait Config: frame_system::Config {
         /// The overarching event type.
         type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
 
         type WeightInfo: frame_system::WeightInfo;
+
+        /// Translation of the coins migrated across the blocks on runtime upgrade, `()` if none.
+        ///
+        /// The migration starts once the on-chain storage version is
+        /// [`CoinsTranslation::FROM`], [`Call::transact`] keeps working meanwhile by
+        /// translating the coins not migrated yet on access.
+        type CoinsMigration: CoinsTranslation;
+
+        /// Maximum number of coins migrated per block.
+        type MaxCoinsMigratedPerBlock: Get<u32>;
+
+        /// Maximum size of the scripts stored in the UTXO set as is, at most
+        /// [`MAX_SCRIPT_SIZE`], the larger ones are replaced by their hash.
+        ///
+        /// The off-runtime execution reads it from the runtime through
+        /// [`max_script_size`](crate::max_script_size).
+        #[pallet::constant]
+        type MaxScriptSize: Get<u32>;
     }
 
     #[pallet::pallet]
+    #[pallet::storage_version(STORAGE_VERSION)]
     pub struct Pallet<T>(_);
 
+    #[pallet::hooks]
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        fn on_runtime_upgrade() -> Weight {
+            Self::start_coins_migration()
+        }
+
+        fn on_initialize(_n: BlockNumberFor<T>) -> Weight {
+            Self::migrate_coins()
+        }
+
+        fn integrity_test() {
+            assert!(
+                T::MaxScriptSize::get() as usize <= MAX_SCRIPT_SIZE,
+                "MaxScriptSize exceeds the bound of the encoded coins"
+            );
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn try_state(_n: BlockNumberFor<T>) -> Result<(), sp_runtime::TryRuntimeError> {
+            Self::do_try_state()
+        }
+    }
+
     #[pallet::call(weight(<T as Config>::WeightInfo))]
     impl<T: Config> Pallet<T> {
         /// An internal unsigned extrinsic for including a Bitcoin transaction into the block.
@@ -112,6 +167,54 @@ pub mod pallet {
         }
     }
 
+    #[pallet::validate_unsigned]
+    impl<T: Config> ValidateUnsigned for Pallet<T> {
+        type Call = Call<T>;
+
+        /// [`Call::transact`] is only ever authored by the block import pipeline from the
+        /// Bitcoin blocks, it's never valid as a pool transaction regardless of the source.
+        fn validate_unsigned(
+            _source: TransactionSource,
+            _call: &Self::Call,
+        ) -> TransactionValidity {
+            InvalidTransaction::Call.into()
+        }
+
+        // The Bitcoin transactions in a block have been verified outside the runtime.
+        fn pre_dispatch(call: &Self::Call) -> Result<(), TransactionValidityError> {
+            match call {
+                Call::transact { .. } => Ok(()),
+                _ => Err(InvalidTransaction::Call.into()),
+            }
+        }
+    }
+
+    #[pallet::inherent]
+    impl<T: Config> ProvideInherent for Pallet<T> {
+        type Call = Call<T>;
+        type Error = InherentError;
+        const INHERENT_IDENTIFIER: InherentIdentifier = INHERENT_IDENTIFIER;
+
+        // A Bitcoin block is wrapped into one inherent per transaction while `ProvideInherent`
+        // creates a single one per pallet, the coinbase. The following transactions are created
+        // by `Pallet::create_inherents()`.
+        fn create_inherent(data: &InherentData) -> Option<Self::Call> {
+            Pallet::<T>::bitcoin_transactions(data)?
+                .into_iter()
+                .next()
+                .map(|btc_tx| Call::transact { btc_tx })
+        }
+
+        // Every Bitcoin block has a coinbase.
+        fn is_inherent_required(data: &InherentData) -> Result<Option<Self::Error>, Self::Error> {
+            Ok(Pallet::<T>::bitcoin_transactions(data).map(|_| InherentError::MissingCoinbase))
+        }
+
+        fn is_inherent(call: &Self::Call) -> bool {
+            matches!(call, Call::transact { .. })
+        }
+    }
+
     #[pallet::genesis_config]
     pub struct GenesisConfig<T> {
         pub genesis_tx: Vec<u8>,
@@ -159,7 +262,9 @@ pub mod pallet {
                     let coin = Coin {
                         is_coinbase: true,
                         amount: txout.value.to_sat(),
-                        script_pubkey: txout.script_pubkey.clone().into_bytes(),
+                        script_pubkey: Pallet::<T>::stored_script_pubkey(
+                            txout.script_pubkey.clone().into_bytes(),
+                        ),
                         height: 0u32,
                     };
                     Coins::<T>::insert(txid.clone(), index as u32, coin);
@@ -175,6 +280,68 @@ pub mod pallet {
     /// (Txid, Vout, Coin)
     #[pallet::storage]
     pub type Coins<T> = StorageDoubleMap<_, Identity, Txid, Identity, Vout, Coin, OptionQuery>;
+
+    /// Cursor of the ongoing coins migration.
+    #[pallet::storage]
+    pub type OngoingCoinsMigration<T> = StorageValue<_, CoinsMigrationCursor, OptionQuery>;
+
+    /// Coins created ahead of the cursor of the ongoing migration, already in the new layout.
+    #[pallet::storage]
+    pub type MigratedAheadCoins<T> =
+        StorageDoubleMap<_, Identity, Txid, Identity, Vout, (), OptionQuery>;
+}
+
+/// Attestation of the coins in the UTXO set, for the other pallets to build on the Bitcoin
+/// state, e.g., using a coin as collateral.
+pub trait UtxoAttestor {
+    /// Attests that the coin `(txid, vout)` is locked by `script_pubkey` and holds at least
+    /// `min_amount` satoshis in the current state.
+    ///
+    /// `txid` is in the consensus byte order. `script_pubkey` is the original script of the
+    /// output, an oversized one is matched against its hash stored in the UTXO set.
+    fn attest_utxo(
+        txid: [u8; 32],
+        vout: Vout,
+        script_pubkey: &[u8],
+        min_amount: u64,
+    ) -> Result<UtxoAttestation, AttestationError>;
+}
+
+impl<T: Config> UtxoAttestor for Pallet<T> {
+    fn attest_utxo(
+        txid: [u8; 32],
+        vout: Vout,
+        script_pubkey: &[u8],
+        min_amount: u64,
+    ) -> Result<UtxoAttestation, AttestationError> {
+        let runtime_txid = Txid(H256::from(txid));
+
+        let coin = Self::coin(&OngoingCoinsMigration::<T>::get(), &runtime_txid, vout)
+            .ok_or(AttestationError::CoinNotFound)?;
+
+        if coin.script_pubkey != Self::stored_script_pubkey(script_pubkey.to_vec()) {
+            return Err(AttestationError::ScriptPubkeyMismatch);
+        }
+
+        if coin.amount < min_amount {
+            return Err(AttestationError::InsufficientAmount {
+                amount: coin.amount,
+            });
+        }
+
+        // The coins not migrated yet are committed in the current layout as well.
+        let commitment = coin.using_encoded(sp_io::hashing::blake2_256);
+
+        Ok(UtxoAttestation {
+            txid,
+            vout,
+            amount: coin.amount,
+            height: coin.height,
+            block_number: frame_system::Pallet::<T>::current_block_number().saturated_into(),
+            storage_key: Coins::<T>::hashed_key_for(&runtime_txid, vout),
+            commitment,
+        })
+    }
 }
 
 /// Returns the storage key for the referenced output.
@@ -192,7 +359,189 @@ pub fn coin_storage_prefix<T: Config>() -> [u8; 32] {
     Coins::<T>::final_prefix()
 }
 
+/// Returns the maximum size of the scripts stored in the UTXO set as is.
+pub fn max_script_size<T: Config>() -> usize {
+    T::MaxScriptSize::get() as usize
+}
+
+/// Returns the storage key of the storage item `OngoingCoinsMigration`.
+pub fn ongoing_coins_migration_key<T: Config>() -> [u8; 32] {
+    OngoingCoinsMigration::<T>::hashed_key()
+}
+
 impl<T: Config> Pallet<T> {
+    /// Returns the transactions of the Bitcoin block in the inherent data.
+    fn bitcoin_transactions(data: &InherentData) -> Option<InherentType> {
+        data.get_data::<InherentType>(&INHERENT_IDENTIFIER)
+            .ok()
+            .flatten()
+    }
+
+    /// Creates the inherents wrapping the transactions of the Bitcoin block in the inherent
+    /// data following the coinbase, in the block order.
+    ///
+    /// The inherent of the coinbase is created by [`ProvideInherent::create_inherent`].
+    pub fn create_inherents(data: &InherentData) -> Vec<Call<T>> {
+        Self::bitcoin_transactions(data)
+            .unwrap_or_default()
+            .into_iter()
+            .skip(1)
+            .map(|btc_tx| Call::transact { btc_tx })
+            .collect()
+    }
+
+    /// Checks that the Bitcoin transactions wrapped in `calls` are exactly the ones of the
+    /// Bitcoin block in the inherent data.
+    pub fn check_inherents<'a>(
+        calls: impl IntoIterator<Item = &'a Call<T>>,
+        data: &InherentData,
+    ) -> Result<(), InherentError>
+    where
+        T: 'a,
+    {
+        let btc_txs = Self::bitcoin_transactions(data).ok_or(InherentError::MissingInherentData)?;
+
+        let wrapped_txs = calls.into_iter().filter_map(|call| match call {
+            Call::transact { btc_tx } => Some(btc_tx),
+            _ => None,
+        });
+
+        if wrapped_txs.ne(btc_txs.iter()) {
+            return Err(InherentError::TransactionsMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Ensures no coin exceeds the height of the chain and the supply does not exceed the
+    /// maximum money.
+    ///
+    /// Skipped while the coins migration is in progress as the coins not migrated yet are in
+    /// the old layout.
+    #[cfg(any(feature = "try-runtime", test))]
+    pub(crate) fn do_try_state() -> Result<(), sp_runtime::TryRuntimeError> {
+        if OngoingCoinsMigration::<T>::exists() {
+            return Ok(());
+        }
+
+        let height: u32 = frame_system::Pallet::<T>::current_block_number().saturated_into();
+
+        for coin in Coins::<T>::iter_values() {
+            frame_support::ensure!(
+                coin.amount <= migrations::MAX_MONEY,
+                "Coin amount exceeds the maximum money"
+            );
+            frame_support::ensure!(coin.height <= height, "Coin created in a future block");
+        }
+
+        let summary = migrations::CoinsSummary::compute::<T>();
+        frame_support::ensure!(
+            summary.supply <= migrations::MAX_MONEY,
+            "Supply exceeds the maximum money"
+        );
+
+        Ok(())
+    }
+
+    fn start_coins_migration() -> Weight {
+        let from = <T::CoinsMigration as CoinsTranslation>::FROM;
+        let to = <T::CoinsMigration as CoinsTranslation>::TO;
+
+        if from == to
+            || Self::on_chain_storage_version() != u16::from(from)
+            || OngoingCoinsMigration::<T>::exists()
+        {
+            return T::DbWeight::get().reads(2);
+        }
+
+        log::info!(target: "runtime::bitcoin", "Starting coins migration from v{from} to v{to}");
+
+        OngoingCoinsMigration::<T>::put(CoinsMigrationCursor::Start);
+
+        T::DbWeight::get().reads_writes(2, 1)
+    }
+
+    /// Translates the next batch of coins if a migration is ongoing.
+    fn migrate_coins() -> Weight {
+        let Some(cursor) = OngoingCoinsMigration::<T>::get() else {
+            return T::DbWeight::get().reads(1);
+        };
+
+        let max_coins = T::MaxCoinsMigratedPerBlock::get();
+        let mut migrated = 0u32;
+
+        let next_cursor = migrations::translate_coins::<T, T::CoinsMigration>(cursor, || {
+            migrated += 1;
+            migrated <= max_coins
+        });
+
+        match next_cursor {
+            Some(cursor) => OngoingCoinsMigration::<T>::put(cursor),
+            None => {
+                OngoingCoinsMigration::<T>::kill();
+                log::info!(target: "runtime::bitcoin", "Coins migration completed");
+            }
+        }
+
+        let migrated = u64::from(migrated.min(max_coins));
+
+        // Each coin is read, checked against the coins created ahead and rewritten.
+        T::DbWeight::get().reads_writes(2 * migrated + 1, migrated + 1)
+    }
+
+    /// Removes the coin from the UTXO set, translating it if it has not been migrated yet.
+    fn take_coin(migration: &Option<CoinsMigrationCursor>, txid: Txid, vout: Vout) -> Option<Coin> {
+        let Some(cursor) = migration else {
+            return Coins::<T>::take(txid, vout);
+        };
+
+        if cursor.has_passed::<T>(&txid, vout)
+            || MigratedAheadCoins::<T>::take(&txid, vout).is_some()
+        {
+            return Coins::<T>::take(txid, vout);
+        }
+
+        frame_support::storage::unhashed::take::<<T::CoinsMigration as CoinsTranslation>::OldCoin>(
+            &Coins::<T>::hashed_key_for(&txid, vout),
+        )
+        .map(<T::CoinsMigration as CoinsTranslation>::translate)
+    }
+
+    /// Reads the coin from the UTXO set, translating it if it has not been migrated yet.
+    fn coin(migration: &Option<CoinsMigrationCursor>, txid: &Txid, vout: Vout) -> Option<Coin> {
+        let Some(cursor) = migration else {
+            return Coins::<T>::get(txid, vout);
+        };
+
+        if cursor.has_passed::<T>(txid, vout) || MigratedAheadCoins::<T>::contains_key(txid, vout) {
+            return Coins::<T>::get(txid, vout);
+        }
+
+        frame_support::storage::unhashed::get::<<T::CoinsMigration as CoinsTranslation>::OldCoin>(
+            &Coins::<T>::hashed_key_for(txid, vout),
+        )
+        .map(<T::CoinsMigration as CoinsTranslation>::translate)
+    }
+
+    /// Adds the coin to the UTXO set, marking it migrated if it's ahead of the cursor.
+    fn insert_coin(migration: &Option<CoinsMigrationCursor>, txid: Txid, vout: Vout, coin: Coin) {
+        if let Some(cursor) = migration {
+            if !cursor.has_passed::<T>(&txid, vout) {
+                MigratedAheadCoins::<T>::insert(&txid, vout, ());
+            }
+        }
+        Coins::<T>::insert(txid, vout, coin);
+    }
+
+    /// Returns the script of an output to store in the UTXO set.
+    fn stored_script_pubkey(script_pubkey: Vec<u8>) -> Vec<u8> {
+        subcoin_runtime_primitives::stored_script_pubkey(
+            script_pubkey,
+            max_script_size::<T>(),
+            sp_io::hashing::sha2_256,
+        )
+    }
+
     fn decode_transaction(btc_tx: Vec<u8>) -> BitcoinTransaction {
         BitcoinTransaction::consensus_decode(&mut btc_tx.as_slice()).unwrap_or_else(|_| {
             panic!("Transaction constructed internally must be decoded successfully; qed")
@@ -205,6 +554,8 @@ impl<T: Config> Pallet<T> {
 
         let height = frame_system::Pallet::<T>::current_block_number();
 
+        let migration = OngoingCoinsMigration::<T>::get();
+
         let new_coins = tx
             .output
             .into_iter()
@@ -217,7 +568,7 @@ impl<T: Config> Pallet<T> {
                 let coin = Coin {
                     is_coinbase,
                     amount: txout.value.to_sat(),
-                    script_pubkey: txout.script_pubkey.into_bytes(),
+                    script_pubkey: Self::stored_script_pubkey(txout.script_pubkey.into_bytes()),
                     height: height.saturated_into(),
                 };
 
@@ -228,7 +579,18 @@ impl<T: Config> Pallet<T> {
         if is_coinbase {
             for (out_point, coin) in new_coins {
                 let OutPointInner { txid, vout } = OutPointInner::from(out_point);
-                Coins::<T>::insert(txid, vout, coin);
+
+                // The historical duplicate coinbases (blocks 91842 and 91880, see BIP30) overwrite
+                // the unspent outputs of the original ones, as what Bitcoin Core does.
+                if let Some(overwritten) = Self::take_coin(&migration, txid.clone(), vout) {
+                    log::debug!(
+                        target: "runtime::bitcoin",
+                        "Coinbase output {out_point:?} overwrites the coin created at #{}",
+                        overwritten.height,
+                    );
+                }
+
+                Self::insert_coin(&migration, txid, vout, coin);
             }
             return;
         }
@@ -237,7 +599,7 @@ impl<T: Config> Pallet<T> {
         for input in tx.input {
             let previous_output = input.previous_output;
             let OutPointInner { txid, vout } = OutPointInner::from(previous_output);
-            if let Some(_spent) = Coins::<T>::take(txid, vout) {
+            if let Some(_spent) = Self::take_coin(&migration, txid, vout) {
             } else {
                 panic!("Corruputed state, UTXO {previous_output:?} not found");
             }
@@ -246,7 +608,7 @@ impl<T: Config> Pallet<T> {
         // Process outputs.
         for (out_point, coin) in new_coins {
             let OutPointInner { txid, vout } = OutPointInner::from(out_point);
-            Coins::<T>::insert(txid, vout, coin);
+            Self::insert_coin(&migration, txid, vout, coin);
         }
     }
 }
@@ -0,0 +1,259 @@
+//! Storage migrations of the Bitcoin pallet.
+//!
+//! The UTXO set is far too large to be migrated within a single block, a change to the layout
+//! of [`Coin`] is shipped as a [`CoinsTranslation`] and a bounded number of coins are translated
+//! per block, either by the pallet itself (see [`Config::CoinsMigration`]) or by the multi-block
+//! migrator of the runtime with [`SteppedCoinsMigration`].
+
+use crate::{Coins, Config, MigratedAheadCoins, Pallet, Txid, Vout};
+use codec::{Decode, Encode, MaxEncodedLen};
+use frame_support::migrations::{MigrationId, SteppedMigration, SteppedMigrationError};
+use frame_support::traits::{Get, GetStorageVersion, OnRuntimeUpgrade, StorageVersion};
+use frame_support::weights::{Weight, WeightMeter};
+use scale_info::TypeInfo;
+use sp_std::marker::PhantomData;
+use subcoin_runtime_primitives::Coin;
+
+#[cfg(feature = "try-runtime")]
+use {sp_runtime::TryRuntimeError, sp_std::vec::Vec};
+
+/// Identifier of the pallet in the migration ids.
+const PALLET_MIGRATIONS_ID: &[u8; 14] = b"pallet-bitcoin";
+
+/// Maximum amount of satoshis that can ever exist.
+pub(crate) const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+
+/// Number of coins and their total amount, which must be preserved by the migrations.
+#[derive(Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct CoinsSummary {
+    /// Number of the unspent outputs.
+    pub count: u64,
+    /// Total amount of the unspent outputs in satoshis.
+    pub supply: u64,
+}
+
+impl CoinsSummary {
+    /// Iterates over the whole UTXO set, only meant for the try-runtime checks and tests.
+    pub fn compute<T: Config>() -> Self {
+        Coins::<T>::iter_values().fold(Self::default(), |mut summary, coin| {
+            summary.count += 1;
+            summary.supply = summary.supply.saturating_add(coin.amount);
+            summary
+        })
+    }
+}
+
+pub mod v1 {
+    use super::*;
+
+    /// Storage version 1.
+    ///
+    /// The layout of [`Coin`] is unchanged from the unversioned storage, only the storage
+    /// version is set so that the future migrations can be ordered.
+    pub struct MigrateToV1<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for MigrateToV1<T> {
+        fn on_runtime_upgrade() -> Weight {
+            let on_chain_version = Pallet::<T>::on_chain_storage_version();
+
+            if on_chain_version != 0 {
+                log::info!(
+                    target: "runtime::bitcoin",
+                    "Skipping migration to v1, on-chain storage version is {on_chain_version:?}",
+                );
+                return T::DbWeight::get().reads(1);
+            }
+
+            StorageVersion::new(1).put::<Pallet<T>>();
+
+            log::info!(target: "runtime::bitcoin", "Migrated storage to v1");
+
+            T::DbWeight::get().reads_writes(1, 1)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<Vec<u8>, TryRuntimeError> {
+            Ok(CoinsSummary::compute::<T>().encode())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade(state: Vec<u8>) -> Result<(), TryRuntimeError> {
+            let summary = CoinsSummary::decode(&mut state.as_slice())
+                .map_err(|_| TryRuntimeError::Other("Invalid coins summary"))?;
+
+            frame_support::ensure!(
+                summary == CoinsSummary::compute::<T>(),
+                "Coins changed by the migration to v1"
+            );
+            frame_support::ensure!(
+                Pallet::<T>::on_chain_storage_version() == 1,
+                "Storage version not updated to v1"
+            );
+
+            Ok(())
+        }
+    }
+}
+
+pub mod v2 {
+    use super::*;
+
+    /// Storage version 2.
+    ///
+    /// The scripts larger than [`Config::MaxScriptSize`] are replaced by their hash, see
+    /// [`stored_script_pubkey`]. Driven by [`Config::CoinsMigration`].
+    ///
+    /// [`stored_script_pubkey`]: subcoin_runtime_primitives::stored_script_pubkey
+    pub struct HashOversizedScripts<T>(PhantomData<T>);
+
+    impl<T: Config> CoinsTranslation for HashOversizedScripts<T> {
+        type OldCoin = Coin;
+
+        const FROM: u8 = 1;
+        const TO: u8 = 2;
+
+        fn translate(old: Coin) -> Coin {
+            Coin {
+                script_pubkey: subcoin_runtime_primitives::stored_script_pubkey(
+                    old.script_pubkey,
+                    crate::max_script_size::<T>(),
+                    sp_io::hashing::sha2_256,
+                ),
+                ..old
+            }
+        }
+    }
+}
+
+/// Translation of the coins from the previous layout.
+pub trait CoinsTranslation {
+    /// Layout of the coins in storage version [`Self::FROM`].
+    type OldCoin: Decode;
+
+    /// Storage version the coins are migrated from.
+    const FROM: u8;
+
+    /// Storage version the coins are migrated to.
+    const TO: u8;
+
+    fn translate(old: Self::OldCoin) -> Coin;
+}
+
+/// No coins migration.
+impl CoinsTranslation for () {
+    type OldCoin = Coin;
+
+    const FROM: u8 = 0;
+    const TO: u8 = 0;
+
+    fn translate(old: Self::OldCoin) -> Coin {
+        old
+    }
+}
+
+/// Position of an ongoing coins migration.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, TypeInfo, MaxEncodedLen)]
+pub enum CoinsMigrationCursor {
+    /// No coin has been translated yet.
+    Start,
+    /// The coins up to and including this one in the storage order have been translated.
+    After(Txid, Vout),
+}
+
+impl CoinsMigrationCursor {
+    /// Returns `true` if the coin precedes the cursor in the storage order.
+    pub(crate) fn has_passed<T: Config>(&self, txid: &Txid, vout: Vout) -> bool {
+        match self {
+            Self::Start => false,
+            Self::After(cursor_txid, cursor_vout) => {
+                Coins::<T>::hashed_key_for(txid, vout)
+                    <= Coins::<T>::hashed_key_for(cursor_txid, cursor_vout)
+            }
+        }
+    }
+}
+
+/// Translates the coins following `cursor` as long as `proceed` returns `true`.
+///
+/// The coins created since the migration started are already in the new layout and skipped.
+/// Returns `None` once all the coins have been translated, the storage version is updated to
+/// [`CoinsTranslation::TO`] by then.
+pub(crate) fn translate_coins<T: Config, M: CoinsTranslation>(
+    cursor: CoinsMigrationCursor,
+    mut proceed: impl FnMut() -> bool,
+) -> Option<CoinsMigrationCursor> {
+    let mut keys = match &cursor {
+        CoinsMigrationCursor::Start => Coins::<T>::iter_keys(),
+        CoinsMigrationCursor::After(txid, vout) => {
+            Coins::<T>::iter_keys_from(Coins::<T>::hashed_key_for(txid, vout))
+        }
+    };
+
+    let mut cursor = cursor;
+
+    while proceed() {
+        let Some((txid, vout)) = keys.next() else {
+            StorageVersion::new(M::TO.into()).put::<Pallet<T>>();
+            return None;
+        };
+
+        if MigratedAheadCoins::<T>::take(&txid, vout).is_none() {
+            let key = Coins::<T>::hashed_key_for(&txid, vout);
+
+            // Overwriting the value of an existing key does not affect the iteration.
+            match frame_support::storage::unhashed::get::<M::OldCoin>(&key) {
+                Some(old) => Coins::<T>::insert(&txid, vout, M::translate(old)),
+                None => log::error!(
+                    target: "runtime::bitcoin",
+                    "Failed to decode coin {txid:?}:{vout} in storage version {}",
+                    M::FROM,
+                ),
+            }
+        }
+
+        cursor = CoinsMigrationCursor::After(txid, vout);
+    }
+
+    Some(cursor)
+}
+
+/// Multi-block migration translating every coin in the UTXO set with `M`, driven by the
+/// multi-block migrator of the runtime.
+///
+/// The migrator forbids the extrinsics while the migration is ongoing, see
+/// [`Config::CoinsMigration`] for the migration driven by the pallet itself which keeps the
+/// transactions flowing.
+pub struct SteppedCoinsMigration<T, M>(PhantomData<(T, M)>);
+
+impl<T: Config, M: CoinsTranslation> SteppedMigration for SteppedCoinsMigration<T, M> {
+    type Cursor = CoinsMigrationCursor;
+    type Identifier = MigrationId<14>;
+
+    fn id() -> Self::Identifier {
+        MigrationId {
+            pallet_id: *PALLET_MIGRATIONS_ID,
+            version_from: M::FROM,
+            version_to: M::TO,
+        }
+    }
+
+    fn step(
+        cursor: Option<Self::Cursor>,
+        meter: &mut WeightMeter,
+    ) -> Result<Option<Self::Cursor>, SteppedMigrationError> {
+        if Pallet::<T>::on_chain_storage_version() != M::FROM as u16 {
+            return Ok(None);
+        }
+
+        let required = T::DbWeight::get().reads_writes(1, 1);
+
+        if meter.remaining().any_lt(required) {
+            return Err(SteppedMigrationError::InsufficientWeight { required });
+        }
+
+        Ok(translate_coins::<T, M>(
+            cursor.unwrap_or(CoinsMigrationCursor::Start),
+            || meter.try_consume(required).is_ok(),
+        ))
+    }
+}
@@ -0,0 +1,65 @@
+//! Mock runtime of the pallet.
+
+use crate::migrations::CoinsTranslation;
+use codec::{Decode, Encode};
+use frame_support::derive_impl;
+use subcoin_runtime_primitives::Coin;
+
+type Block = frame_system::mocking::MockBlock<Test>;
+
+frame_support::construct_runtime!(
+    pub enum Test {
+        System: frame_system,
+        Bitcoin: crate,
+    }
+);
+
+#[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+impl frame_system::Config for Test {
+    type Block = Block;
+    type DbWeight = frame_support::weights::constants::RocksDbWeight;
+}
+
+impl crate::Config for Test {
+    type RuntimeEvent = RuntimeEvent;
+    type WeightInfo = ();
+    type CoinsMigration = AddHeight;
+    type MaxCoinsMigratedPerBlock = frame_support::traits::ConstU32<2>;
+    type MaxScriptSize = MaxScriptSize;
+}
+
+frame_support::parameter_types! {
+    pub static MaxScriptSize: u32 = subcoin_runtime_primitives::MAX_SCRIPT_SIZE as u32;
+}
+
+/// Layout of the coins prior to the height being added.
+#[derive(Encode, Decode)]
+pub struct OldCoin {
+    pub is_coinbase: bool,
+    pub amount: u64,
+    pub script_pubkey: Vec<u8>,
+}
+
+pub struct AddHeight;
+
+impl CoinsTranslation for AddHeight {
+    type OldCoin = OldCoin;
+    const FROM: u8 = 1;
+    const TO: u8 = 2;
+
+    fn translate(old: OldCoin) -> Coin {
+        Coin {
+            is_coinbase: old.is_coinbase,
+            amount: old.amount,
+            height: 0,
+            script_pubkey: old.script_pubkey,
+        }
+    }
+}
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    frame_system::GenesisConfig::<Test>::default()
+        .build_storage()
+        .unwrap()
+        .into()
+}
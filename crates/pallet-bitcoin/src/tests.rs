@@ -1,3 +1,4 @@
+use crate::mock;
 use bitcoin::consensus::Encodable;
 use sp_core::Encode;
 
@@ -14,3 +15,595 @@ fn test_runtime_txid_type() {
         .expect("txid must be encoded correctly; qed");
     assert_eq!(d, runtime_txid.encode());
 }
+
+#[test]
+fn test_duplicate_coinbase_overwrites_unspent_outputs() {
+    use crate::{Coins, Pallet};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+    use mock::{new_test_ext, Test};
+
+    // A coinbase without the block height produces the same txid in different blocks.
+    let coinbase = |script_sig: Vec<u8>| Transaction {
+        version: Version::ONE,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::from_bytes(script_sig),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_int_btc(50),
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+
+    // Coinbase of block 91722, duplicated in block 91880.
+    let coinbase_91722 = coinbase(vec![0x04, 0x56, 0x72, 0x0e, 0x1b, 0x00]);
+    // Coinbase of block 91812, duplicated in block 91842.
+    let coinbase_91812 = coinbase(vec![0x04, 0x74, 0x6e, 0x0d, 0x1b]);
+
+    let txid_91722 = crate::Txid::from_bitcoin_txid(coinbase_91722.compute_txid());
+    let txid_91812 = crate::Txid::from_bitcoin_txid(coinbase_91812.compute_txid());
+
+    new_test_ext().execute_with(|| {
+        for (height, tx) in [
+            (91722, &coinbase_91722),
+            (91812, &coinbase_91812),
+            (91842, &coinbase_91812),
+            (91880, &coinbase_91722),
+        ] {
+            frame_system::Pallet::<Test>::set_block_number(height);
+            Pallet::<Test>::process_bitcoin_transaction(tx.clone());
+        }
+
+        // The original outputs are gone, only the duplicates remain spendable.
+        assert_eq!(Coins::<Test>::iter().count(), 2);
+
+        for (txid, height) in [(txid_91722, 91880), (txid_91812, 91842)] {
+            let coin = Coins::<Test>::get(txid, 0).unwrap();
+            assert!(coin.is_coinbase);
+            assert_eq!(coin.amount, Amount::from_int_btc(50).to_sat());
+            assert_eq!(coin.height, height);
+        }
+    });
+}
+
+#[test]
+fn test_oversized_scripts() {
+    use crate::{Coins, Pallet};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+    use mock::{new_test_ext, Test};
+    use subcoin_runtime_primitives::MAX_SCRIPT_SIZE;
+
+    let oversized = vec![0x51; MAX_SCRIPT_SIZE + 1];
+    let coinbase = Transaction {
+        version: Version::ONE,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::from_bytes(vec![0x01, 0x01]),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![
+            TxOut {
+                value: Amount::from_int_btc(50),
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51; MAX_SCRIPT_SIZE]),
+            },
+            TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::from_bytes(oversized.clone()),
+            },
+        ],
+    };
+    let txid = crate::Txid::from_bitcoin_txid(coinbase.compute_txid());
+
+    new_test_ext().execute_with(|| {
+        Pallet::<Test>::process_bitcoin_transaction(coinbase.clone());
+
+        let coin = Coins::<Test>::get(&txid, 0).unwrap();
+        assert_eq!(coin.script_pubkey.len(), MAX_SCRIPT_SIZE);
+
+        let coin = Coins::<Test>::get(&txid, 1).unwrap();
+        let mut expected = vec![0x6a, 0x20];
+        expected.extend(sp_io::hashing::sha2_256(&oversized));
+        assert_eq!(coin.script_pubkey, expected);
+    });
+}
+
+#[test]
+fn test_configured_max_script_size() {
+    use crate::{Coins, Pallet};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+    use mock::{new_test_ext, MaxScriptSize, Test};
+
+    MaxScriptSize::set(100);
+
+    let oversized = vec![0x51; 101];
+    let coinbase = Transaction {
+        version: Version::ONE,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::from_bytes(vec![0x01, 0x01]),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![
+            TxOut {
+                value: Amount::from_int_btc(50),
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51; 100]),
+            },
+            TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::from_bytes(oversized.clone()),
+            },
+        ],
+    };
+    let txid = crate::Txid::from_bitcoin_txid(coinbase.compute_txid());
+
+    new_test_ext().execute_with(|| {
+        Pallet::<Test>::process_bitcoin_transaction(coinbase.clone());
+
+        let coin = Coins::<Test>::get(&txid, 0).unwrap();
+        assert_eq!(coin.script_pubkey, vec![0x51; 100]);
+
+        let coin = Coins::<Test>::get(&txid, 1).unwrap();
+        let mut expected = vec![0x6a, 0x20];
+        expected.extend(sp_io::hashing::sha2_256(&oversized));
+        assert_eq!(coin.script_pubkey, expected);
+    });
+}
+
+#[test]
+fn test_hash_oversized_scripts_translation() {
+    use crate::migrations::{v2::HashOversizedScripts, CoinsTranslation};
+    use mock::Test;
+    use subcoin_runtime_primitives::{Coin, MAX_SCRIPT_SIZE};
+
+    let coin = |script_pubkey| Coin {
+        is_coinbase: false,
+        amount: 1000,
+        script_pubkey,
+        height: 100,
+    };
+
+    let small = coin(vec![0x51; MAX_SCRIPT_SIZE]);
+    assert_eq!(
+        HashOversizedScripts::<Test>::translate(small.clone()),
+        small
+    );
+
+    let oversized = vec![0x51; MAX_SCRIPT_SIZE + 1];
+    let mut expected = vec![0x6a, 0x20];
+    expected.extend(sp_io::hashing::sha2_256(&oversized));
+    assert_eq!(
+        HashOversizedScripts::<Test>::translate(coin(oversized)),
+        coin(expected)
+    );
+}
+
+#[test]
+fn test_migrate_to_v1() {
+    use crate::migrations::v1::MigrateToV1;
+    use crate::Pallet;
+    use frame_support::traits::{GetStorageVersion, OnRuntimeUpgrade};
+    use mock::{new_test_ext, Test};
+
+    new_test_ext().execute_with(|| {
+        let genesis_tx = crate::GenesisConfig::<Test>::default().genesis_tx;
+        Pallet::<Test>::process_bitcoin_transaction(Pallet::<Test>::decode_transaction(genesis_tx));
+        let summary = crate::migrations::CoinsSummary::compute::<Test>();
+
+        assert_eq!(Pallet::<Test>::on_chain_storage_version(), 0);
+        MigrateToV1::<Test>::on_runtime_upgrade();
+        assert_eq!(Pallet::<Test>::on_chain_storage_version(), 1);
+
+        assert_eq!(crate::migrations::CoinsSummary::compute::<Test>(), summary);
+        assert!(Pallet::<Test>::do_try_state().is_ok());
+    });
+}
+
+#[test]
+fn test_stepped_coins_migration() {
+    use crate::migrations::{CoinsSummary, SteppedCoinsMigration};
+    use crate::{Coins, Pallet, Txid};
+    use frame_support::migrations::SteppedMigration;
+    use frame_support::traits::{Get, GetStorageVersion, StorageVersion};
+    use frame_support::weights::WeightMeter;
+    use mock::{new_test_ext, AddHeight, OldCoin, Test};
+    use sp_core::H256;
+
+    new_test_ext().execute_with(|| {
+        StorageVersion::new(1).put::<Pallet<Test>>();
+
+        for i in 0..10u8 {
+            let old_coin = OldCoin {
+                is_coinbase: false,
+                amount: i.into(),
+                script_pubkey: vec![i],
+            };
+            frame_support::storage::unhashed::put(
+                &Coins::<Test>::hashed_key_for(Txid(H256::repeat_byte(i)), u32::from(i)),
+                &old_coin,
+            );
+        }
+
+        // 3 coins per step.
+        let weight_per_step =
+            <<Test as frame_system::Config>::DbWeight as Get<_>>::get().reads_writes(3, 3);
+
+        let mut cursor = None;
+        let mut steps = 0;
+        loop {
+            let mut meter = WeightMeter::with_limit(weight_per_step);
+            cursor = SteppedCoinsMigration::<Test, AddHeight>::step(cursor, &mut meter).unwrap();
+            steps += 1;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(steps, 4);
+        assert_eq!(Pallet::<Test>::on_chain_storage_version(), 2);
+        assert_eq!(
+            CoinsSummary::compute::<Test>(),
+            CoinsSummary {
+                count: 10,
+                supply: 45
+            }
+        );
+    });
+}
+
+#[test]
+fn test_coins_migration_across_blocks() {
+    use crate::migrations::CoinsSummary;
+    use crate::{Coins, MigratedAheadCoins, OngoingCoinsMigration, Pallet, Txid};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+    use frame_support::traits::{GetStorageVersion, Hooks, StorageVersion};
+    use mock::{new_test_ext, OldCoin, Test};
+    use sp_core::H256;
+
+    new_test_ext().execute_with(|| {
+        StorageVersion::new(1).put::<Pallet<Test>>();
+
+        for i in 1..=5u8 {
+            let old_coin = OldCoin {
+                is_coinbase: false,
+                amount: i.into(),
+                script_pubkey: vec![i],
+            };
+            frame_support::storage::unhashed::put(
+                &Coins::<Test>::hashed_key_for(Txid(H256::repeat_byte(i)), 0),
+                &old_coin,
+            );
+        }
+
+        Pallet::<Test>::on_runtime_upgrade();
+        assert!(OngoingCoinsMigration::<Test>::exists());
+
+        // Coins #1 and #2 are migrated.
+        Pallet::<Test>::on_initialize(1);
+        assert_eq!(Pallet::<Test>::on_chain_storage_version(), 1);
+        assert!(Pallet::<Test>::do_try_state().is_ok());
+
+        // Spend the coin #4 which is not migrated yet.
+        frame_system::Pallet::<Test>::set_block_number(1);
+        let tx = Transaction {
+            version: Version::ONE,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: bitcoin::Txid::from_byte_array([4; 32]),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(4),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        Pallet::<Test>::process_bitcoin_transaction(tx);
+
+        for n in 2..10 {
+            Pallet::<Test>::on_initialize(n);
+        }
+
+        assert_eq!(Pallet::<Test>::on_chain_storage_version(), 2);
+        assert!(!OngoingCoinsMigration::<Test>::exists());
+        assert_eq!(MigratedAheadCoins::<Test>::iter().count(), 0);
+        assert_eq!(
+            CoinsSummary::compute::<Test>(),
+            CoinsSummary {
+                count: 5,
+                supply: 15
+            }
+        );
+        assert_eq!(
+            Coins::<Test>::iter_values()
+                .filter(|coin| coin.height == 1)
+                .count(),
+            1
+        );
+    });
+}
+
+#[test]
+fn test_attest_utxo() {
+    use crate::{Coins, Pallet, Txid, UtxoAttestor};
+    use bitcoin::hashes::Hash;
+    use mock::{new_test_ext, Test};
+    use sp_core::H256;
+    use subcoin_runtime_primitives::{AttestationError, Coin, MAX_SCRIPT_SIZE};
+
+    new_test_ext().execute_with(|| {
+        frame_system::Pallet::<Test>::set_block_number(10);
+
+        let coin = Coin {
+            is_coinbase: false,
+            amount: 1000,
+            height: 5,
+            script_pubkey: vec![1, 2, 3],
+        };
+        let encoded_coin = coin.encode();
+        Coins::<Test>::insert(Txid(H256::repeat_byte(1)), 0, coin);
+
+        let attestation = Pallet::<Test>::attest_utxo([1; 32], 0, &[1, 2, 3], 1000).unwrap();
+        assert_eq!(attestation.amount, 1000);
+        assert_eq!(attestation.height, 5);
+        assert_eq!(attestation.block_number, 10);
+        assert_eq!(
+            attestation.storage_key,
+            crate::coin_storage_key::<Test>(bitcoin::Txid::from_byte_array([1; 32]), 0)
+        );
+        assert_eq!(
+            attestation.commitment,
+            sp_io::hashing::blake2_256(&encoded_coin)
+        );
+
+        assert_eq!(
+            Pallet::<Test>::attest_utxo([1; 32], 0, &[1, 2, 3], 1001),
+            Err(AttestationError::InsufficientAmount { amount: 1000 })
+        );
+        assert_eq!(
+            Pallet::<Test>::attest_utxo([1; 32], 0, &[1, 2], 1000),
+            Err(AttestationError::ScriptPubkeyMismatch)
+        );
+        assert_eq!(
+            Pallet::<Test>::attest_utxo([1; 32], 1, &[1, 2, 3], 1000),
+            Err(AttestationError::CoinNotFound)
+        );
+
+        // The oversized script is stored as its hash.
+        let oversized = vec![0x51; MAX_SCRIPT_SIZE + 1];
+        let mut stored = vec![0x6a, 0x20];
+        stored.extend(sp_io::hashing::sha2_256(&oversized));
+        Coins::<Test>::insert(
+            Txid(H256::repeat_byte(2)),
+            0,
+            Coin {
+                is_coinbase: false,
+                amount: 1000,
+                height: 5,
+                script_pubkey: stored.clone(),
+            },
+        );
+        assert!(Pallet::<Test>::attest_utxo([2; 32], 0, &oversized, 1000).is_ok());
+        assert_eq!(
+            Pallet::<Test>::attest_utxo([2; 32], 0, &stored, 1000),
+            Err(AttestationError::ScriptPubkeyMismatch)
+        );
+    });
+}
+
+#[test]
+fn test_attest_utxo_during_coins_migration() {
+    use crate::migrations::CoinsMigrationCursor;
+    use crate::{Coins, OngoingCoinsMigration, Pallet, Txid, UtxoAttestor};
+    use mock::{new_test_ext, OldCoin, Test};
+    use sp_core::H256;
+    use subcoin_runtime_primitives::Coin;
+
+    new_test_ext().execute_with(|| {
+        let old_coin = OldCoin {
+            is_coinbase: false,
+            amount: 1000,
+            script_pubkey: vec![1, 2, 3],
+        };
+        frame_support::storage::unhashed::put(
+            &Coins::<Test>::hashed_key_for(Txid(H256::repeat_byte(1)), 0),
+            &old_coin,
+        );
+        OngoingCoinsMigration::<Test>::put(CoinsMigrationCursor::Start);
+
+        let attestation = Pallet::<Test>::attest_utxo([1; 32], 0, &[1, 2, 3], 1000).unwrap();
+
+        let translated = Coin {
+            is_coinbase: false,
+            amount: 1000,
+            height: 0,
+            script_pubkey: vec![1, 2, 3],
+        };
+        assert_eq!(
+            attestation.commitment,
+            sp_io::hashing::blake2_256(&translated.encode())
+        );
+    });
+}
+
+#[test]
+fn test_transactions_against_reference_model() {
+    use crate::fuzzing::{run_transactions, FuzzInput, FuzzOutput, FuzzTransaction};
+
+    let output = |amount| FuzzOutput {
+        amount,
+        script_pubkey: vec![0x51],
+    };
+
+    let txs = vec![
+        FuzzTransaction::Coinbase {
+            script_sig: vec![1],
+            outputs: vec![output(50), output(25)],
+        },
+        // Double spend in the same transaction.
+        FuzzTransaction::Spend {
+            inputs: vec![FuzzInput::Existing(0), FuzzInput::Existing(0)],
+            outputs: vec![output(50)],
+        },
+        // Missing input.
+        FuzzTransaction::Spend {
+            inputs: vec![FuzzInput::Random {
+                txid: [7; 32],
+                vout: 0,
+            }],
+            outputs: vec![output(1)],
+        },
+        // No input.
+        FuzzTransaction::Spend {
+            inputs: vec![],
+            outputs: vec![output(1)],
+        },
+        FuzzTransaction::Spend {
+            inputs: vec![FuzzInput::Existing(0), FuzzInput::Existing(1)],
+            outputs: vec![output(70), output(5)],
+        },
+        // Duplicate coinbase.
+        FuzzTransaction::Coinbase {
+            script_sig: vec![1],
+            outputs: vec![output(50), output(25)],
+        },
+        FuzzTransaction::Spend {
+            inputs: vec![FuzzInput::Existing(3)],
+            outputs: vec![],
+        },
+    ];
+
+    assert_eq!(run_transactions(&txs), 4);
+}
+
+#[test]
+fn test_raw_transactions_against_reference_model() {
+    use crate::fuzzing::run_raw;
+
+    let genesis_block = bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin);
+    let genesis_tx = bitcoin::consensus::serialize(&genesis_block.txdata[0]);
+    assert!(run_raw(&genesis_tx));
+    assert!(!run_raw(&genesis_tx[..genesis_tx.len() - 1]));
+
+    let raw_block = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../test_data/btc_mainnet_385044.data"
+    ))
+    .unwrap();
+    let block: bitcoin::Block =
+        bitcoin::consensus::encode::deserialize_hex(raw_block.trim()).unwrap();
+    for tx in &block.txdata {
+        assert!(run_raw(&bitcoin::consensus::serialize(tx)));
+    }
+}
+
+#[test]
+fn test_transact_rejected_by_transaction_pool() {
+    use crate::{Call, Pallet};
+    use frame_support::pallet_prelude::{InvalidTransaction, TransactionSource};
+    use frame_support::unsigned::ValidateUnsigned;
+    use mock::Test;
+
+    let genesis_block = bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin);
+    let call = Call::<Test>::transact {
+        btc_tx: bitcoin::consensus::serialize(&genesis_block.txdata[0]),
+    };
+
+    for source in [
+        TransactionSource::External,
+        TransactionSource::Local,
+        TransactionSource::InBlock,
+    ] {
+        assert_eq!(
+            Pallet::<Test>::validate_unsigned(source, &call),
+            Err(InvalidTransaction::Call.into())
+        );
+    }
+
+    assert_eq!(Pallet::<Test>::pre_dispatch(&call), Ok(()));
+}
+
+#[test]
+fn test_bitcoin_block_inherents() {
+    use crate::inherent::{InherentDataProvider, InherentError};
+    use crate::{Call, Pallet};
+    use frame_support::inherent::ProvideInherent;
+    use mock::Test;
+
+    let mut block = bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin);
+    let mut tx = block.txdata[0].clone();
+    tx.lock_time = bitcoin::absolute::LockTime::from_consensus(1);
+    block.txdata.push(tx);
+    let btc_txs = block
+        .txdata
+        .iter()
+        .map(bitcoin::consensus::serialize)
+        .collect::<Vec<_>>();
+
+    let inherent_data =
+        futures::executor::block_on(sp_inherents::InherentDataProvider::create_inherent_data(
+            &InherentDataProvider::new(&block),
+        ))
+        .unwrap();
+
+    let coinbase = Pallet::<Test>::create_inherent(&inherent_data).unwrap();
+    assert_eq!(
+        coinbase,
+        Call::<Test>::transact {
+            btc_tx: btc_txs[0].clone()
+        }
+    );
+    assert_eq!(
+        Pallet::<Test>::create_inherents(&inherent_data),
+        vec![Call::<Test>::transact {
+            btc_tx: btc_txs[1].clone()
+        }]
+    );
+    assert_eq!(
+        Pallet::<Test>::is_inherent_required(&inherent_data),
+        Ok(Some(InherentError::MissingCoinbase))
+    );
+    assert_eq!(Pallet::<Test>::create_inherent(&Default::default()), None);
+    assert_eq!(
+        Pallet::<Test>::is_inherent_required(&Default::default()),
+        Ok(None)
+    );
+
+    let calls = std::iter::once(coinbase)
+        .chain(Pallet::<Test>::create_inherents(&inherent_data))
+        .collect::<Vec<_>>();
+    assert!(calls.iter().all(Pallet::<Test>::is_inherent));
+    assert_eq!(
+        Pallet::<Test>::check_inherents(&calls, &inherent_data),
+        Ok(())
+    );
+
+    let calls = vec![calls[0].clone(), calls[0].clone()];
+    assert_eq!(
+        Pallet::<Test>::check_inherents(&calls, &inherent_data),
+        Err(InherentError::TransactionsMismatch)
+    );
+    assert_eq!(
+        Pallet::<Test>::check_inherents(&calls, &Default::default()),
+        Err(InherentError::MissingInherentData)
+    );
+}
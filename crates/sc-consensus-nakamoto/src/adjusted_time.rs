@@ -0,0 +1,28 @@
+//! Network-adjusted time.
+//!
+//! The offset is maintained by the networking from the timestamps in the `version` messages
+//! of the peers, as what Bitcoin Core does.
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static TIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// Returns the offset of the network time from the local clock, in seconds.
+pub fn time_offset() -> i64 {
+    TIME_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Sets the offset of the network time from the local clock, in seconds.
+pub fn set_time_offset(offset: i64) {
+    TIME_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+/// Returns the seconds since the UNIX epoch adjusted by the network time offset.
+pub fn adjusted_time() -> u64 {
+    let local_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    local_time.saturating_add_signed(time_offset())
+}
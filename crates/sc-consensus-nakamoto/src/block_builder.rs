@@ -0,0 +1,138 @@
+//! Construction of the Substrate block from a Bitcoin block.
+//!
+//! The mapping is part of the consensus of Subcoin, the Substrate block hashes commit to it.
+//! Any client reproducing the same Substrate blocks from the same Bitcoin blocks must follow
+//! it exactly:
+//!
+//! - Extrinsics: each Bitcoin transaction is wrapped into one `pallet_bitcoin::Call::transact`
+//!   inherent in the block order, the coinbase first. The extrinsic index is the index of the
+//!   transaction in the block. These are the inherents created by the runtime from the
+//!   [`BitcoinBlockBuilder::inherent_data`] of the Bitcoin block, which is checked for the
+//!   blocks not built locally, see [`BitcoinBlockBuilder::check_inherents`].
+//! - Extrinsics root: ordered trie root of the encoded extrinsics, state version `V0`.
+//! - Digest: two pre-runtime items in this order, the Bitcoin block hash in the consensus byte
+//!   order under [`NAKAMOTO_HASH_ENGINE_ID`] and the 80-byte consensus encoded Bitcoin header
+//!   under [`NAKAMOTO_HEADER_ENGINE_ID`].
+//! - Number: the height of the Bitcoin block.
+//! - Parent hash: the hash of the Substrate block of the parent Bitcoin block, default for the
+//!   genesis block.
+//! - State root: the state root after executing the block on top of the parent state, the
+//!   genesis state root for the genesis block.
+//!
+//! [`NAKAMOTO_HASH_ENGINE_ID`]: subcoin_primitives::runtime::NAKAMOTO_HASH_ENGINE_ID
+//! [`NAKAMOTO_HEADER_ENGINE_ID`]: subcoin_primitives::runtime::NAKAMOTO_HEADER_ENGINE_ID
+
+use bitcoin::Block as BitcoinBlock;
+use codec::Encode;
+use sp_api::ProvideRuntimeApi;
+use sp_block_builder::BlockBuilder as BlockBuilderApi;
+use sp_inherents::InherentData;
+use sp_runtime::traits::{
+    Block as BlockT, Hash as HashT, HashingFor, Header as HeaderT, NumberFor,
+};
+use std::marker::PhantomData;
+use subcoin_primitives::{
+    convert_to_bitcoin_block, substrate_header_digest, BitcoinTransactionAdapter,
+};
+
+/// Builds the Substrate blocks from the Bitcoin blocks deterministically.
+///
+/// See the [module docs](self) for the mapping.
+pub struct BitcoinBlockBuilder<Block, TransactionAdapter> {
+    _phantom: PhantomData<(Block, TransactionAdapter)>,
+}
+
+impl<Block, TransactionAdapter> BitcoinBlockBuilder<Block, TransactionAdapter>
+where
+    Block: BlockT,
+    TransactionAdapter: BitcoinTransactionAdapter<Block>,
+{
+    /// Returns the extrinsics wrapping the transactions of `block`.
+    pub fn extrinsics(block: &BitcoinBlock) -> Vec<Block::Extrinsic> {
+        block
+            .txdata
+            .iter()
+            .map(TransactionAdapter::bitcoin_transaction_into_extrinsic)
+            .collect()
+    }
+
+    /// Returns the inherent data of `block`, the runtime creates the same inherents as
+    /// [`Self::extrinsics`] from it.
+    pub async fn inherent_data(block: &BitcoinBlock) -> Result<InherentData, sp_inherents::Error> {
+        TransactionAdapter::inherent_data_provider(block)
+            .create_inherent_data()
+            .await
+    }
+
+    /// Checks the extrinsics of `block` against the inherent data of the Bitcoin block they
+    /// wrap, i.e., the runtime creates the same inherents from it.
+    ///
+    /// The blocks built by [`Self::build`] pass the check by construction, only the blocks
+    /// from other sources, e.g., the Substrate networking, need to be checked.
+    pub async fn check_inherents<Client>(client: &Client, block: Block) -> sp_blockchain::Result<()>
+    where
+        Client: ProvideRuntimeApi<Block>,
+        Client::Api: BlockBuilderApi<Block>,
+    {
+        let parent_hash = *block.header().parent_hash();
+
+        let bitcoin_block = convert_to_bitcoin_block::<Block, TransactionAdapter>(block.clone())
+            .map_err(|err| {
+                sp_blockchain::Error::Application(
+                    format!("Failed to convert to Bitcoin block: {err:?}").into(),
+                )
+            })?;
+
+        let inherent_data = Self::inherent_data(&bitcoin_block)
+            .await
+            .map_err(|err| sp_blockchain::Error::Application(Box::new(err)))?;
+
+        let result = client
+            .runtime_api()
+            .check_inherents(parent_hash, block, inherent_data)?;
+
+        if result.ok() {
+            return Ok(());
+        }
+
+        let failed = result
+            .into_errors()
+            .map(|(identifier, _)| String::from_utf8_lossy(&identifier).into_owned())
+            .collect::<Vec<_>>();
+
+        Err(sp_blockchain::Error::Application(
+            format!("Inherents check failed: {}", failed.join(", ")).into(),
+        ))
+    }
+
+    /// Returns the extrinsics root of `extrinsics`.
+    pub fn extrinsics_root(extrinsics: &[Block::Extrinsic]) -> Block::Hash {
+        HashingFor::<Block>::ordered_trie_root(
+            extrinsics.iter().map(|xt| xt.encode()).collect(),
+            sp_core::storage::StateVersion::V0,
+        )
+    }
+
+    /// Builds the Substrate block of `block`.
+    ///
+    /// The state root is only known after executing the block, it can be set later if the
+    /// block is built before the execution.
+    pub fn build(
+        block: &BitcoinBlock,
+        number: NumberFor<Block>,
+        parent_hash: Block::Hash,
+        state_root: Block::Hash,
+    ) -> Block {
+        let extrinsics = Self::extrinsics(block);
+
+        let header = <<Block as BlockT>::Header as HeaderT>::new(
+            number,
+            Self::extrinsics_root(&extrinsics),
+            state_root,
+            parent_hash,
+            substrate_header_digest(&block.header),
+        );
+
+        Block::new(header, extrinsics)
+    }
+}
@@ -1,5 +1,7 @@
+use crate::state_root_diagnostics::diagnose_state_root_mismatch;
 use async_trait::async_trait;
-use bitcoin::OutPoint;
+use bitcoin::hashes::Hash;
+use bitcoin::{OutPoint, Transaction};
 use sc_client_api::{AuxStore, Backend, BlockBackend, HeaderBackend, StorageProvider};
 use sc_consensus::{BlockImport, BlockImportParams, ImportResult, StateAction, StorageChanges};
 use sp_api::{ApiExt, CallApiAt, CallContext, Core, ProvideRuntimeApi};
@@ -7,8 +9,9 @@ use sp_runtime::traits::{Block as BlockT, HashingFor, Header as HeaderT};
 use sp_runtime::SaturatedConversion;
 use sp_state_machine::{StorageKey, StorageValue};
 use std::marker::PhantomData;
-use std::sync::Arc;
-use subcoin_primitives::runtime::{Coin, Subcoin};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use subcoin_primitives::runtime::{stored_script_pubkey, Coin, Subcoin};
 use subcoin_primitives::{BitcoinTransactionAdapter, CoinStorageKey};
 
 /// A simply way to track the overall execution info for optimization purpose.
@@ -71,6 +74,9 @@ pub enum BlockExecutionStrategy {
     ///
     /// Check out the log for the performance details.
     BenchmarkAll,
+    /// Executes the blocks off-runtime in memory during the major sync, switches to the
+    /// runtime execution on disk once within `switch_depth` blocks of the network tip.
+    Adaptive { switch_depth: u32 },
 }
 
 impl BlockExecutionStrategy {
@@ -90,7 +96,8 @@ impl BlockExecutionStrategy {
                 matches!(exec_backend, ExecutionBackend::InMemory)
             }
             BlockExecutionStrategy::BenchmarkRuntimeExecution
-            | BlockExecutionStrategy::BenchmarkAll => true,
+            | BlockExecutionStrategy::BenchmarkAll
+            | BlockExecutionStrategy::Adaptive { .. } => true,
         }
     }
 }
@@ -201,32 +208,7 @@ where
         parent_hash: Block::Hash,
         block: Block,
     ) -> sp_blockchain::Result<ExecuteBlockResult<Block>> {
-        let mut runtime_api = self.client.runtime_api();
-        runtime_api.set_call_context(CallContext::Onchain);
-
-        let mut exec_info = ExecutionInfo::new(block.extrinsics().len());
-
-        let now = std::time::Instant::now();
-        runtime_api.execute_block_without_state_root_check(parent_hash, block)?;
-        exec_info.execute_block_time = now.elapsed().as_nanos();
-
-        let now = std::time::Instant::now();
-        let state = self.client.state_at(parent_hash)?;
-        exec_info.fetch_state_time = now.elapsed().as_nanos();
-
-        let now = std::time::Instant::now();
-        let storage_changes = runtime_api
-            .into_storage_changes(&state, parent_hash)
-            .map_err(sp_blockchain::Error::StorageChanges)?;
-        exec_info.into_storage_changes_time = now.elapsed().as_nanos();
-
-        let state_root = storage_changes.transaction_storage_root;
-
-        Ok(ExecuteBlockResult {
-            state_root,
-            storage_changes,
-            exec_info,
-        })
+        execute_block_in_runtime::<Block, _, BE>(&*self.client, parent_hash, block)
     }
 
     async fn import_block(
@@ -245,6 +227,46 @@ where
     }
 }
 
+/// Executes the block with the runtime api `execute_block`.
+fn execute_block_in_runtime<Block, Client, BE>(
+    client: &Client,
+    parent_hash: Block::Hash,
+    block: Block,
+) -> sp_blockchain::Result<ExecuteBlockResult<Block>>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: ProvideRuntimeApi<Block> + StorageProvider<Block, BE> + CallApiAt<Block>,
+    Client::Api: Core<Block>,
+{
+    let mut runtime_api = client.runtime_api();
+    runtime_api.set_call_context(CallContext::Onchain);
+
+    let mut exec_info = ExecutionInfo::new(block.extrinsics().len());
+
+    let now = std::time::Instant::now();
+    runtime_api.execute_block_without_state_root_check(parent_hash, block)?;
+    exec_info.execute_block_time = now.elapsed().as_nanos();
+
+    let now = std::time::Instant::now();
+    let state = client.state_at(parent_hash)?;
+    exec_info.fetch_state_time = now.elapsed().as_nanos();
+
+    let now = std::time::Instant::now();
+    let storage_changes = runtime_api
+        .into_storage_changes(&state, parent_hash)
+        .map_err(sp_blockchain::Error::StorageChanges)?;
+    exec_info.into_storage_changes_time = now.elapsed().as_nanos();
+
+    let state_root = storage_changes.transaction_storage_root;
+
+    Ok(ExecuteBlockResult {
+        state_root,
+        storage_changes,
+        exec_info,
+    })
+}
+
 /// Block executor using custom `apply_extrinsics`, for the initial sync process.
 pub struct OffRuntimeBlockExecutor<Block, Client, BE, TransactionAdapter, BI> {
     client: Arc<Client>,
@@ -271,7 +293,7 @@ impl<Block, Client, BE, TransactionAdapter, BI>
     }
 }
 
-type StorageEntry = (StorageKey, Option<StorageValue>);
+pub(crate) type StorageEntry = (StorageKey, Option<StorageValue>);
 
 #[allow(unused)]
 fn execute_block_off_runtime<Block: BlockT>(
@@ -325,40 +347,56 @@ fn apply_extrinsics_off_runtime<
     coin_storage_key: &Arc<dyn CoinStorageKey>,
     height: u32,
 ) -> Vec<(Vec<StorageEntry>, Option<u32>)> {
-    use codec::Encode;
-
     extrinsics
         .iter()
         .enumerate()
         .map(|(index, extrinsic)| {
             let tx = TransactionAdapter::extrinsic_to_bitcoin_transaction(extrinsic);
+            (
+                transaction_storage_changes(tx, &**coin_storage_key, height),
+                Some(index as u32),
+            )
+        })
+        .collect()
+}
 
-            let mut changes = Vec::with_capacity(tx.input.len() + tx.output.len());
+/// Returns the changes of the coins made by the transaction in the block at `height`.
+pub(crate) fn transaction_storage_changes(
+    tx: Transaction,
+    coin_storage_key: &dyn CoinStorageKey,
+    height: u32,
+) -> Vec<StorageEntry> {
+    use codec::Encode;
 
-            for input in &tx.input {
-                let OutPoint { txid, vout } = input.previous_output;
-                let storage_key = coin_storage_key.storage_key(txid, vout);
-                changes.push((storage_key, None));
-            }
+    let mut changes = Vec::with_capacity(tx.input.len() + tx.output.len());
 
-            let txid = tx.compute_txid();
-            let is_coinbase = tx.is_coinbase();
+    for input in &tx.input {
+        let OutPoint { txid, vout } = input.previous_output;
+        let storage_key = coin_storage_key.storage_key(txid, vout);
+        changes.push((storage_key, None));
+    }
 
-            for (index, txout) in tx.output.into_iter().enumerate() {
-                let storage_key = coin_storage_key.storage_key(txid, index as u32);
-                let coin = Coin {
-                    is_coinbase,
-                    amount: txout.value.to_sat(),
-                    script_pubkey: txout.script_pubkey.into_bytes(),
-                    height,
-                };
+    let txid = tx.compute_txid();
+    let is_coinbase = tx.is_coinbase();
 
-                changes.push((storage_key, Some(coin.encode())));
-            }
+    for (index, txout) in tx.output.into_iter().enumerate() {
+        let storage_key = coin_storage_key.storage_key(txid, index as u32);
+        let script_pubkey = stored_script_pubkey(
+            txout.script_pubkey.into_bytes(),
+            coin_storage_key.max_script_size(),
+            |script| bitcoin::hashes::sha256::Hash::hash(script).to_byte_array(),
+        );
+        let coin = Coin {
+            is_coinbase,
+            amount: txout.value.to_sat(),
+            script_pubkey,
+            height,
+        };
 
-            (changes, Some(index as u32))
-        })
-        .collect()
+        changes.push((storage_key, Some(coin.encode())));
+    }
+
+    changes
 }
 
 fn format_time(nanoseconds: u128) -> String {
@@ -416,6 +454,19 @@ where
         parent_hash: Block::Hash,
         block: Block,
     ) -> sp_blockchain::Result<ExecuteBlockResult<Block>> {
+        // The coins are written in the final layout off the runtime, which diverges from the
+        // runtime while the coins migration steps through the existing coins.
+        let ongoing_coins_migration_key =
+            sc_client_api::StorageKey(self.coin_storage_key.ongoing_coins_migration_key().to_vec());
+        if self
+            .client
+            .storage(parent_hash, &ongoing_coins_migration_key)?
+            .is_some()
+        {
+            tracing::debug!("Coins migration in progress, executing block in the runtime");
+            return execute_block_in_runtime::<Block, _, BE>(&*self.client, parent_hash, block);
+        }
+
         let mut runtime_api = self.client.runtime_api();
         runtime_api.set_call_context(CallContext::Onchain);
 
@@ -614,14 +665,6 @@ impl<Block, DiskRuntime, InMemoryRuntime, DiskOffRuntime, InMemoryOffRuntime>
     }
 }
 
-fn display_main_changes(changes: &[(Vec<u8>, Option<Vec<u8>>)]) -> String {
-    changes
-        .iter()
-        .map(|(key, value)| format!("{}, {value:?}", sp_core::hexdisplay::HexDisplay::from(key)))
-        .collect::<Vec<String>>()
-        .join("\n")
-}
-
 #[async_trait]
 impl<Block, DiskRuntime, InMemoryRuntime, DiskOffRuntime, InMemoryOffRuntime> BlockExecutor<Block>
     for BenchmarkAllExecutor<
@@ -649,7 +692,7 @@ where
     ) -> sp_blockchain::Result<ExecuteBlockResult<Block>> {
         let ExecuteBlockResult {
             state_root: in_memory_state_root,
-            storage_changes: c1,
+            storage_changes: _,
             exec_info: in_memory_runtime_exec_info,
         } = self
             .in_memory_runtime_block_executor
@@ -657,22 +700,22 @@ where
 
         let ExecuteBlockResult {
             state_root: in_memory_off_runtime_state_root,
-            storage_changes: c2,
+            storage_changes: _,
             exec_info: in_memory_off_runtime_exec_info,
         } = self
             .in_memory_off_runtime_block_executor
             .execute_block(parent_hash, block.clone())?;
 
         if in_memory_state_root != in_memory_off_runtime_state_root {
-            tracing::debug!(
-                "    runtime changes: \n{}",
-                display_main_changes(&c1.main_storage_changes)
-            );
-            tracing::debug!(
-                "off_runtime changes: \n{}",
-                display_main_changes(&c2.main_storage_changes)
-            );
-            panic!("Off runtime state root does not match the runtime state root");
+            let mismatch = diagnose_state_root_mismatch(
+                parent_hash,
+                block,
+                &self.in_memory_runtime_block_executor,
+                &self.in_memory_off_runtime_block_executor,
+            )?;
+            if let Some(mismatch) = mismatch {
+                return Err(sp_blockchain::Error::Application(Box::new(mismatch)));
+            }
         }
 
         let ExecuteBlockResult {
@@ -761,3 +804,135 @@ where
             .map_err(|err| sp_consensus::Error::ClientImport(err.to_string()))
     }
 }
+
+/// Block executor for [`BlockExecutionStrategy::Adaptive`].
+///
+/// The blocks are executed by the major sync executor until the block being imported is within
+/// `switch_depth` blocks of the network tip, the tip executor takes over from then on. The state
+/// root of the block at the switch is verified by both executors, the major sync executor is
+/// released afterwards.
+pub struct AdaptiveBlockExecutor<Block: BlockT> {
+    major_sync_executor: Mutex<Option<Box<dyn BlockExecutor<Block>>>>,
+    tip_executor: Box<dyn BlockExecutor<Block>>,
+    network_tip: Arc<AtomicU32>,
+    switch_depth: u32,
+}
+
+impl<Block: BlockT> AdaptiveBlockExecutor<Block> {
+    /// Constructs a new instance of [`AdaptiveBlockExecutor`].
+    ///
+    /// `network_tip` is the best block number known in the network, `0` if unknown.
+    pub fn new(
+        major_sync_executor: Box<dyn BlockExecutor<Block>>,
+        tip_executor: Box<dyn BlockExecutor<Block>>,
+        network_tip: Arc<AtomicU32>,
+        switch_depth: u32,
+    ) -> Self {
+        Self {
+            major_sync_executor: Mutex::new(Some(major_sync_executor)),
+            tip_executor,
+            network_tip,
+            switch_depth,
+        }
+    }
+
+    fn major_sync_executor(&self) -> MutexGuard<'_, Option<Box<dyn BlockExecutor<Block>>>> {
+        self.major_sync_executor
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn is_near_tip(block_number: u32, network_tip: u32, switch_depth: u32) -> bool {
+    network_tip > 0 && block_number.saturating_add(switch_depth) >= network_tip
+}
+
+#[async_trait]
+impl<Block: BlockT> BlockExecutor<Block> for AdaptiveBlockExecutor<Block> {
+    fn execution_strategy(&self) -> BlockExecutionStrategy {
+        match self.major_sync_executor().as_ref() {
+            Some(executor) => executor.execution_strategy(),
+            None => self.tip_executor.execution_strategy(),
+        }
+    }
+
+    fn execute_block(
+        &self,
+        parent_hash: Block::Hash,
+        block: Block,
+    ) -> sp_blockchain::Result<ExecuteBlockResult<Block>> {
+        let mut major_sync_executor = self.major_sync_executor();
+
+        let Some(executor) = major_sync_executor.as_ref() else {
+            return self.tip_executor.execute_block(parent_hash, block);
+        };
+
+        let block_number: u32 = (*block.header().number()).saturated_into();
+        let network_tip = self.network_tip.load(Ordering::Relaxed);
+
+        if !is_near_tip(block_number, network_tip, self.switch_depth) {
+            return executor.execute_block(parent_hash, block);
+        }
+
+        let major_sync_state_root = executor
+            .execute_block(parent_hash, block.clone())?
+            .state_root;
+        let result = self
+            .tip_executor
+            .execute_block(parent_hash, block.clone())?;
+
+        if major_sync_state_root != result.state_root {
+            let mismatch =
+                diagnose_state_root_mismatch(parent_hash, block, &*self.tip_executor, &**executor)?;
+            if let Some(mismatch) = mismatch {
+                return Err(sp_blockchain::Error::Application(Box::new(mismatch)));
+            }
+        }
+
+        tracing::info!(
+            "Switching to {:?} at block #{block_number}, the network tip is #{network_tip}",
+            self.tip_executor.execution_strategy(),
+        );
+
+        major_sync_executor.take();
+
+        Ok(result)
+    }
+
+    fn is_in_memory_backend_used(&self) -> bool {
+        match self.major_sync_executor().as_ref() {
+            Some(executor) => executor.is_in_memory_backend_used(),
+            None => self.tip_executor.is_in_memory_backend_used(),
+        }
+    }
+
+    async fn import_block(
+        &mut self,
+        import_params: BlockImportParams<Block>,
+    ) -> Result<ImportResult, sp_consensus::Error> {
+        let executor = self
+            .major_sync_executor
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match executor {
+            Some(executor) => executor.import_block(import_params).await,
+            None => self.tip_executor.import_block(import_params).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_near_tip() {
+        // Unknown network tip.
+        assert!(!is_near_tip(800_000, 0, 144));
+        assert!(!is_near_tip(799_855, 800_000, 144));
+        assert!(is_near_tip(799_856, 800_000, 144));
+        assert!(is_near_tip(800_001, 800_000, 144));
+        assert!(is_near_tip(u32::MAX, u32::MAX, 144));
+    }
+}
@@ -2,7 +2,7 @@
 //!
 //! Each Bitcoin block is converted into a Substrate block and imported into the database.
 //! The Bitcoin header is included in the Substrate header as a `DigestItem`, each Bitcoin
-//! transaction is wrapped into an unsigned extrinsic defined in pallet-bitcoin.
+//! transaction is wrapped into an inherent defined in pallet-bitcoin, see [`BitcoinBlockBuilder`].
 //!
 //! Key components:
 //!
@@ -18,12 +18,19 @@
 //! - [`ImportStatus`]
 //!     An enum representing the result of an import operation, with variants for different import outcomes.
 
+use crate::block_builder::BitcoinBlockBuilder;
 use crate::block_executor::{BlockExecutor, ExecuteBlockResult};
+use crate::coin_prefetch::CoinPrefetcher;
+use crate::differential::{DifferentialValidator, DivergenceReport, ReferenceNode, UtxoDiff};
+use crate::finality_conflict::FinalityConflict;
+use crate::import_record::{ImportRecorder, ImportTimings};
+use crate::invalid_blocks::{is_invalid_block, mark_invalid_blocks};
 use crate::metrics::Metrics;
+use crate::state_root_diagnostics::diagnose_state_root_mismatch;
 use crate::verification::{BlockVerification, BlockVerifier};
 use bitcoin::hashes::Hash;
-use bitcoin::{Block as BitcoinBlock, BlockHash, Network};
-use codec::Encode;
+use bitcoin::{Block as BitcoinBlock, BlockHash, Network, Work};
+use codec::{Decode, Encode};
 use sc_client_api::{AuxStore, Backend, BlockBackend, HeaderBackend, StorageProvider};
 use sc_consensus::{
     BlockImport, BlockImportParams, ForkChoiceStrategy, ImportResult, ImportedAux, StateAction,
@@ -32,16 +39,16 @@ use sc_consensus::{
 use sp_api::{CallApiAt, Core, ProvideRuntimeApi};
 use sp_blockchain::HashAndNumber;
 use sp_consensus::{BlockOrigin, BlockStatus};
-use sp_runtime::traits::{
-    Block as BlockT, Hash as HashT, HashingFor, Header as HeaderT, NumberFor,
-};
+use sp_runtime::traits::{Block as BlockT, HashingFor, Header as HeaderT, NumberFor};
 use sp_runtime::{SaturatedConversion, Saturating};
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::time::Instant;
-use subcoin_primitives::runtime::Subcoin;
+use subcoin_consensus_verification::ChainParams;
+use subcoin_primitives::runtime::{block_subsidy, Coin, Subcoin};
 use subcoin_primitives::{
-    substrate_header_digest, BackendExt, BitcoinTransactionAdapter, CoinStorageKey,
+    block_stats_key, chain_work_key, BackendExt, BitcoinTransactionAdapter, BlockStats,
+    CoinStorageKey, HeaderEntry,
 };
 use substrate_prometheus_endpoint::Registry;
 
@@ -77,12 +84,17 @@ fn clone_block_import_params<Block: BlockT>(
 pub struct ImportConfig {
     /// Bitcoin network type.
     pub network: Network,
+    /// Chain params overriding the ones of `network`, e.g., of a custom network.
+    pub chain_params: Option<ChainParams>,
     /// Specify the block verification level.
     pub block_verification: BlockVerification,
     /// Whether to execute the transactions in the block.
     pub execute_block: bool,
     /// Whether to verify the Bitcoin script.
     pub verify_script: bool,
+    /// Number of the verification threads, the scripts are verified in order on the import
+    /// queue if `None`.
+    pub verification_threads: Option<usize>,
 }
 
 #[derive(Debug, Default)]
@@ -124,6 +136,14 @@ impl Stats {
     }
 }
 
+/// Returns the fork choice of a block with `chain_work` over the best block with
+/// `best_chain_work`, regardless of the heights.
+///
+/// The best block is kept in case of equal work, i.e., the first seen block wins.
+fn most_work_fork_choice(chain_work: Work, best_chain_work: Work) -> ForkChoiceStrategy {
+    ForkChoiceStrategy::Custom(chain_work > best_chain_work)
+}
+
 /// Bitcoin specific block import implementation.
 pub struct BitcoinBlockImporter<Block, Client, BE, BI, TransactionAdapter> {
     client: Arc<Client>,
@@ -132,8 +152,13 @@ pub struct BitcoinBlockImporter<Block, Client, BE, BI, TransactionAdapter> {
     config: ImportConfig,
     verifier: BlockVerifier<Block, Client, BE>,
     block_executor: Box<dyn BlockExecutor<Block>>,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    differential: Option<DifferentialValidator>,
+    state_root_diagnostics: Option<Box<dyn BlockExecutor<Block>>>,
     metrics: Option<Metrics>,
     last_block_execution_report: Instant,
+    import_recorder: ImportRecorder,
+    coin_prefetcher: CoinPrefetcher,
     _phantom: PhantomData<TransactionAdapter>,
 }
 
@@ -163,13 +188,19 @@ where
         block_executor: Box<dyn BlockExecutor<Block>>,
         registry: Option<&Registry>,
     ) -> Self {
-        let verifier = BlockVerifier::new(
+        let mut verifier = BlockVerifier::new(
             client.clone(),
             config.network,
             config.block_verification,
-            coin_storage_key,
+            coin_storage_key.clone(),
             config.verify_script,
         );
+        if let Some(chain_params) = config.chain_params {
+            verifier = verifier.with_chain_params(chain_params);
+        }
+        if let Some(threads) = config.verification_threads {
+            verifier = verifier.with_verification_threads(threads);
+        }
         let metrics = match registry {
             Some(registry) => Metrics::register(registry)
                 .map_err(|err| {
@@ -182,15 +213,46 @@ where
             client,
             inner: block_import,
             stats: Stats::default(),
-            config,
             verifier,
             block_executor,
+            coin_storage_key,
+            differential: None,
+            state_root_diagnostics: None,
             metrics,
             last_block_execution_report: Instant::now(),
+            import_recorder: ImportRecorder::default(),
+            coin_prefetcher: CoinPrefetcher::new(config.verification_threads.unwrap_or(1)),
+            config,
             _phantom: Default::default(),
         }
     }
 
+    /// Validates each block against the trusted `reference` node before importing it, the
+    /// import is halted on divergence.
+    ///
+    /// The UTXO set changes are only compared if the blocks are executed.
+    pub fn with_differential_validation(mut self, reference: ReferenceNode) -> Self {
+        if !self.config.execute_block {
+            tracing::warn!("Block execution is disabled, only the block verdicts are compared");
+        }
+        self.differential
+            .replace(DifferentialValidator::new(reference));
+        self
+    }
+
+    /// Cross-checks the state root of each executed block with `reference_executor`, the
+    /// mismatch is bisected over the transactions and the block is rejected.
+    ///
+    /// `reference_executor` must use the disk backend and a different execution strategy,
+    /// every block is executed twice.
+    pub fn with_state_root_diagnostics(
+        mut self,
+        reference_executor: Box<dyn BlockExecutor<Block>>,
+    ) -> Self {
+        self.state_root_diagnostics.replace(reference_executor);
+        self
+    }
+
     /// Sets new block executor.
     pub fn set_block_executor(&mut self, new_executor: Box<dyn BlockExecutor<Block>>) {
         self.block_executor = new_executor;
@@ -224,6 +286,26 @@ where
         }))
     }
 
+    /// Returns the fork choice for a block with the given cumulative chainwork.
+    ///
+    /// The block becomes the new best block only if it has more work than the current best
+    /// block, the first seen block wins in case of equal work.
+    fn fork_choice(&self, chain_work: Work) -> sp_blockchain::Result<ForkChoiceStrategy> {
+        let best_hash = self.client.info().best_hash;
+
+        let best_chain_work = self
+            .client
+            .bitcoin_block_hash_for(best_hash)
+            .and_then(|bitcoin_block_hash| self.client.chain_work(bitcoin_block_hash))
+            .ok_or_else(|| {
+                sp_blockchain::Error::UnknownBlock(format!(
+                    "Chainwork of best block#{best_hash} not found"
+                ))
+            })?;
+
+        Ok(most_work_fork_choice(chain_work, best_chain_work))
+    }
+
     fn execute_block_at(
         &mut self,
         block_number: NumberFor<Block>,
@@ -272,39 +354,43 @@ where
         &mut self,
         block: BitcoinBlock,
         substrate_parent_block: HashAndNumber<Block>,
-    ) -> sp_blockchain::Result<(BlockImportParams<Block>, Option<BlockImportParams<Block>>)> {
+        block_stats: Option<BlockStats>,
+        timings: &mut ImportTimings,
+    ) -> sp_blockchain::Result<(
+        BlockImportParams<Block>,
+        Option<BlockImportParams<Block>>,
+        Option<UtxoDiff>,
+    )> {
         let HashAndNumber {
             number: parent_block_number,
             hash: parent_hash,
         } = substrate_parent_block;
 
-        let block_number = parent_block_number.saturating_add(1u32.into());
-
-        let extrinsics = block
-            .txdata
-            .iter()
-            .map(TransactionAdapter::bitcoin_transaction_into_extrinsic)
-            .collect::<Vec<_>>();
+        let parent_chain_work = self
+            .client
+            .chain_work(block.header.prev_blockhash)
+            .ok_or_else(|| {
+                sp_blockchain::Error::UnknownBlock(format!(
+                    "Chainwork of parent block#{parent_hash} not found"
+                ))
+            })?;
+        let chain_work = parent_chain_work + block.header.work();
+        let fork_choice = self.fork_choice(chain_work)?;
 
-        let extrinsics_root = HashingFor::<Block>::ordered_trie_root(
-            extrinsics.iter().map(|xt| xt.encode()).collect(),
-            sp_core::storage::StateVersion::V0,
-        );
-
-        let digest = substrate_header_digest(&block.header);
+        let block_number = parent_block_number.saturating_add(1u32.into());
 
         // We know everything for the Substrate header except the state root, which will be
         // obtained after executing the block manually.
-        let mut header = <<Block as BlockT>::Header as HeaderT>::new(
+        let (mut header, extrinsics) = BitcoinBlockBuilder::<Block, TransactionAdapter>::build(
+            &block,
             block_number,
-            extrinsics_root,
-            Default::default(),
             parent_hash,
-            digest,
-        );
+            Default::default(),
+        )
+        .deconstruct();
 
         // subcoin bootstrap node must execute every block.
-        let (state_action, maybe_changes) = if self.config.execute_block {
+        let (state_action, maybe_changes, utxo_diff) = if self.config.execute_block {
             let now = std::time::Instant::now();
 
             let tx_count = extrinsics.len();
@@ -315,18 +401,58 @@ where
                 Block::new(header.clone(), extrinsics.clone()),
             )?;
 
-            let execution_time = now.elapsed().as_millis();
+            if let Some(reference_executor) = &self.state_root_diagnostics {
+                let block = Block::new(header.clone(), extrinsics.clone());
+                let ExecuteBlockResult {
+                    state_root: expected_state_root,
+                    ..
+                } = reference_executor.execute_block(parent_hash, block.clone())?;
+
+                if expected_state_root != state_root {
+                    if let Some(mismatch) = diagnose_state_root_mismatch(
+                        parent_hash,
+                        block,
+                        &**reference_executor,
+                        &*self.block_executor,
+                    )? {
+                        tracing::error!("{mismatch}");
+                        return Err(sp_blockchain::Error::Application(Box::new(mismatch)));
+                    }
+                }
+            }
+
+            let execution_time = now.elapsed();
+            timings.execution.replace(execution_time);
 
             self.stats.record_new_block_execution::<Block>(
                 block_number,
                 header.hash(),
                 tx_count,
-                execution_time,
+                execution_time.as_millis(),
             );
 
             // Now it's a normal Substrate header after setting the state root.
             header.set_state_root(state_root);
 
+            let utxo_diff = self
+                .differential
+                .is_some()
+                .then(|| {
+                    UtxoDiff::from_storage_changes(
+                        &storage_changes.main_storage_changes,
+                        &*self.coin_storage_key,
+                        |key| {
+                            self.client
+                                .storage(parent_hash, &sc_client_api::StorageKey(key.to_vec()))
+                                .ok()
+                                .flatten()
+                                .and_then(|data| Coin::decode(&mut data.0.as_slice()).ok())
+                        },
+                    )
+                })
+                .transpose()
+                .map_err(|err| sp_blockchain::Error::Application(Box::new(err)))?;
+
             let changes_for_block_executor = if self.block_executor.is_in_memory_backend_used() {
                 Some(StorageChanges::Changes(clone_storage_changes::<Block>(
                     &storage_changes,
@@ -338,9 +464,10 @@ where
             (
                 StateAction::ApplyChanges(StorageChanges::<Block>::Changes(storage_changes)),
                 changes_for_block_executor,
+                utxo_diff,
             )
         } else {
-            (StateAction::Skip, None)
+            (StateAction::Skip, None, None)
         };
 
         let substrate_block_hash = header.hash();
@@ -348,7 +475,7 @@ where
 
         let mut block_import_params = BlockImportParams::new(BlockOrigin::Own, header);
         block_import_params.body = Some(extrinsics);
-        block_import_params.fork_choice = Some(ForkChoiceStrategy::LongestChain);
+        block_import_params.fork_choice = Some(fork_choice);
         block_import_params.state_action = state_action;
 
         insert_bitcoin_block_hash_mapping(
@@ -356,12 +483,35 @@ where
             bitcoin_block_hash,
             substrate_block_hash,
         );
+        block_import_params.auxiliary.push((
+            chain_work_key(bitcoin_block_hash),
+            Some(chain_work.to_be_bytes().to_vec()),
+        ));
+        let (header_entry_key, header_entry) = HeaderEntry {
+            header: block.header,
+            height: block_number.saturated_into(),
+            chain_work,
+        }
+        .aux_item();
+        block_import_params
+            .auxiliary
+            .push((header_entry_key, Some(header_entry)));
+        if let Some(block_stats) = block_stats {
+            block_import_params.auxiliary.push((
+                block_stats_key(bitcoin_block_hash),
+                Some(block_stats.encode()),
+            ));
+        }
 
         let import_params_for_block_executor = maybe_changes.map(|changes| {
             clone_block_import_params(&block_import_params, StateAction::ApplyChanges(changes))
         });
 
-        Ok((block_import_params, import_params_for_block_executor))
+        Ok((
+            block_import_params,
+            import_params_for_block_executor,
+            utxo_diff,
+        ))
     }
 }
 
@@ -379,6 +529,12 @@ pub fn insert_bitcoin_block_hash_mapping<Block: BlockT>(
     )];
 }
 
+/// Converts the divergence to the import error halting the import queue.
+fn halt_on_divergence(report: DivergenceReport) -> sp_consensus::Error {
+    tracing::error!("{report}");
+    sp_consensus::Error::Other(Box::new(report))
+}
+
 /// Result of the operation of importing a Bitcoin block.
 ///
 /// Same semantic with [`sc_consensus::ImportResult`] with additional information
@@ -416,6 +572,10 @@ pub trait BitcoinBlockImport: Send + Sync + 'static {
         &mut self,
         block: BitcoinBlock,
     ) -> Result<ImportStatus, sp_consensus::Error>;
+
+    /// Hints the block queued right after the block passed to the next
+    /// [`Self::import_block`], allowing its spent coins to be read during that import.
+    fn set_next_block(&mut self, _block: &BitcoinBlock) {}
 }
 
 #[async_trait::async_trait]
@@ -448,6 +608,12 @@ where
 
         let import_err = sp_consensus::Error::ClientImport;
 
+        if is_invalid_block(&*self.client, block.block_hash())
+            || is_invalid_block(&*self.client, bitcoin_parent_hash)
+        {
+            return Ok(ImportStatus::KnownBad);
+        }
+
         let Some(substrate_parent_block) = self
             .fetch_substrate_block_info(bitcoin_parent_hash)
             .map_err(|err| import_err(err.to_string()))?
@@ -473,33 +639,178 @@ where
         let block_number = substrate_parent_block.number.saturated_into::<u32>() + 1u32;
         let block_hash = block.block_hash();
 
+        let mut timings = ImportTimings::default();
+        let tx_count = block.txdata.len();
+
+        let prefetched_coins = self.coin_prefetcher.take(block_hash);
+
+        if self.config.block_verification == BlockVerification::Full {
+            self.coin_prefetcher.start::<Block, _, BE>(
+                &block,
+                &self.client,
+                &self.coin_storage_key,
+                substrate_parent_block.hash,
+            );
+        }
+
         // Consensus-level Bitcoin block verification.
-        self.verifier
-            .verify_block(block_number, &block)
-            .map_err(|err| import_err(format!("{err:?}")))?;
+        let now = Instant::now();
+        let verify_result =
+            self.verifier
+                .verify_block_with_coins(block_number, &block, &prefetched_coins);
+        timings.script_verify = now.elapsed();
+
+        let tx_fees = match verify_result {
+            Ok(tx_fees) => tx_fees,
+            Err(err) => {
+                if err.invalidates_block() {
+                    if let Some(differential) = &self.differential {
+                        differential
+                            .check_rejected(block_number, block_hash, err.to_string())
+                            .await
+                            .map_err(halt_on_divergence)?;
+                    }
+
+                    if let Err(err) = mark_invalid_blocks(&*self.client, [block_hash]) {
+                        tracing::error!("Failed to mark block {block_hash} invalid: {err:?}");
+                    }
+                }
+
+                let Some(verification_error) = err.block_verification_error() else {
+                    return Err(sp_consensus::Error::Other(Box::new(err)));
+                };
+
+                if let Some(metrics) = &self.metrics {
+                    metrics.report_verification_failure(verification_error.reason());
+                }
+
+                return Err(sp_consensus::Error::Other(Box::new(verification_error)));
+            }
+        };
+
+        if let Some(conflict) = FinalityConflict::detect::<Block, _>(
+            &self.client,
+            block_hash,
+            block_number,
+            bitcoin_parent_hash,
+        ) {
+            match conflict.record(&*self.client) {
+                Ok(true) => {
+                    tracing::error!(
+                        block_hash = ?conflict.block_hash,
+                        fork_height = conflict.fork_height,
+                        finalized_height = conflict.finalized_height,
+                        "🚨 {conflict}"
+                    );
+                    if let Some(metrics) = &self.metrics {
+                        metrics.report_finality_conflict();
+                    }
+                }
+                Ok(false) => {}
+                Err(err) => tracing::error!(?err, "Failed to record the finality conflict"),
+            }
+            return Err(sp_consensus::Error::Other(Box::new(conflict)));
+        }
 
-        let (block_import_params, maybe_import_params_for_block_executor) = self
-            .prepare_substrate_block_import(block, substrate_parent_block)
+        // The fees are only known if the transactions have been verified.
+        let subsidy = block_subsidy(
+            block_number,
+            self.verifier.chain_params().subsidy_halving_interval,
+        );
+        let block_stats = tx_fees.map(|tx_fees| BlockStats::compute(&block, subsidy, &tx_fees));
+
+        let (block_import_params, maybe_import_params_for_block_executor, utxo_diff) = self
+            .prepare_substrate_block_import(
+                block,
+                substrate_parent_block,
+                block_stats,
+                &mut timings,
+            )
             .map_err(|err| import_err(err.to_string()))?;
 
+        if let (Some(differential), Some(utxo_diff)) = (&self.differential, &utxo_diff) {
+            differential
+                .check_accepted(block_number, block_hash, utxo_diff)
+                .await
+                .map_err(halt_on_divergence)?;
+        }
+
+        let now = Instant::now();
+
         if let Some(import_params) = maybe_import_params_for_block_executor {
             self.block_executor.import_block(import_params).await?;
         }
 
-        self.inner
+        let import_result = self
+            .inner
             .import_block(block_import_params)
             .await
-            .map(|import_result| match import_result {
-                ImportResult::Imported(aux) => ImportStatus::Imported {
-                    block_number,
-                    block_hash,
-                    aux,
-                },
-                ImportResult::AlreadyInChain => ImportStatus::AlreadyInChain(block_number),
-                ImportResult::KnownBad => ImportStatus::KnownBad,
-                ImportResult::UnknownParent => ImportStatus::UnknownParent,
-                ImportResult::MissingState => ImportStatus::MissingState,
+            .map_err(|err| import_err(err.to_string()))?;
+
+        timings.flush = now.elapsed();
+
+        let import_status = match import_result {
+            ImportResult::Imported(aux) => ImportStatus::Imported {
+                block_number,
+                block_hash,
+                aux,
+            },
+            ImportResult::AlreadyInChain => ImportStatus::AlreadyInChain(block_number),
+            ImportResult::KnownBad => ImportStatus::KnownBad,
+            ImportResult::UnknownParent => ImportStatus::UnknownParent,
+            ImportResult::MissingState => ImportStatus::MissingState,
+        };
+
+        if matches!(import_status, ImportStatus::Imported { .. }) {
+            self.coin_prefetcher.finish(block_hash);
+            self.import_recorder
+                .record(block_number, block_hash, tx_count, timings);
+        }
+
+        Ok(import_status)
+    }
+
+    fn set_next_block(&mut self, block: &BitcoinBlock) {
+        self.coin_prefetcher.set_next(block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{CompactTarget, Target};
+
+    fn chain_work(bits: &[u32]) -> Work {
+        bits.iter()
+            .map(|bits| Target::from_compact(CompactTarget::from_consensus(*bits)).to_work())
+            .fold(Work::from_be_bytes([0u8; 32]), |chain_work, work| {
+                chain_work + work
             })
-            .map_err(|err| import_err(err.to_string()))
+    }
+
+    #[test]
+    fn test_most_work_fork_choice() {
+        let best_chain_work = chain_work(&[0x1d00ffff; 3]);
+
+        // Equal work, the best block seen first is kept.
+        assert_eq!(
+            most_work_fork_choice(chain_work(&[0x1d00ffff; 3]), best_chain_work),
+            ForkChoiceStrategy::Custom(false)
+        );
+
+        // More blocks with less work.
+        assert_eq!(
+            most_work_fork_choice(
+                chain_work(&[0x1d00ffff, 0x1d01fffe, 0x1d01fffe, 0x1d01fffe]),
+                best_chain_work
+            ),
+            ForkChoiceStrategy::Custom(false)
+        );
+
+        // Reorg to the fork with more work at a lower height.
+        assert_eq!(
+            most_work_fork_choice(chain_work(&[0x1d00ffff, 0x1c00ffff]), best_chain_work),
+            ForkChoiceStrategy::Custom(true)
+        );
     }
 }
@@ -0,0 +1,382 @@
+//! Deterministic replay of an imported block.
+//!
+//! [`BlockReplayer`] re-executes an imported block on top of its parent state with each of the
+//! given [`BlockExecutor`]s, the state roots are checked against the imported header and the
+//! storage changes of the executors are compared with each other. The transactions can be
+//! traced as well, reporting the coins accessed by each transaction and the result of the
+//! script verification of each input. The parent state must not have been pruned.
+
+use crate::block_executor::{transaction_storage_changes, BlockExecutor, ExecuteBlockResult};
+use crate::state_root_diagnostics::{diagnose_state_root_mismatch, StateRootMismatch};
+use crate::{BlockExecutionStrategy, ExecutionInfo};
+use bitcoin::consensus::serialize;
+use bitcoin::{Amount, Block as BitcoinBlock, BlockHash, OutPoint, ScriptBuf, TxOut, Txid};
+use codec::Decode;
+use sc_client_api::{AuxStore, Backend, BlockBackend, HeaderBackend, StorageProvider};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_runtime::SaturatedConversion;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use subcoin_consensus_verification::{
+    get_block_script_flags, ChainParams, ScriptError, ScriptFamily, ScriptInterpreters,
+};
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{
+    convert_to_bitcoin_block, BackendExt, BitcoinTransactionAdapter, CoinStorageKey, HeaderError,
+};
+
+/// Block replay error.
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("Block {0} not found")]
+    UnknownBlock(BlockHash),
+    #[error("Body of block {0} not found, it may have been pruned")]
+    MissingBody(BlockHash),
+    #[error("Invalid Bitcoin header: {0:?}")]
+    Header(HeaderError),
+    #[error(transparent)]
+    Client(#[from] sp_blockchain::Error),
+}
+
+/// Where the coin spent by an input was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinSource {
+    /// State of the parent block.
+    ParentState,
+    /// Output of a previous transaction in the same block.
+    Block { tx_index: usize },
+    /// The coin does not exist.
+    Missing,
+}
+
+/// Coin read by an input.
+#[derive(Debug, Clone)]
+pub struct CoinRead {
+    pub out_point: OutPoint,
+    pub storage_key: Vec<u8>,
+    pub source: CoinSource,
+    pub coin: Option<TxOut>,
+}
+
+/// Script verification of an input.
+#[derive(Debug)]
+pub struct ScriptTrace {
+    pub input_index: usize,
+    pub family: ScriptFamily,
+    pub result: Result<(), ScriptError>,
+    pub elapsed: Duration,
+}
+
+/// Trace of a transaction in the replayed block.
+#[derive(Debug)]
+pub struct TransactionTrace {
+    pub index: usize,
+    pub txid: Txid,
+    /// Coins read by the inputs.
+    pub reads: Vec<CoinRead>,
+    /// Coins deleted and inserted in storage.
+    pub writes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    /// Empty for the coinbase.
+    pub scripts: Vec<ScriptTrace>,
+    pub elapsed: Duration,
+}
+
+/// Outcome of the execution by one executor.
+#[derive(Debug)]
+pub struct ExecutionTrace<Block: BlockT> {
+    pub strategy: BlockExecutionStrategy,
+    pub state_root: Block::Hash,
+    pub main_storage_changes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    pub exec_info: ExecutionInfo,
+}
+
+/// Result of replaying a block.
+#[derive(Debug)]
+pub struct BlockReplay<Block: BlockT> {
+    pub block_number: u32,
+    pub block_hash: BlockHash,
+    /// State root in the imported header.
+    pub imported_state_root: Block::Hash,
+    pub executions: Vec<ExecutionTrace<Block>>,
+    /// Empty unless the transactions are traced.
+    pub transactions: Vec<TransactionTrace>,
+}
+
+impl<Block: BlockT> BlockReplay<Block> {
+    /// Returns the executions producing a different state root from the imported one.
+    pub fn state_root_mismatches(&self) -> impl Iterator<Item = &ExecutionTrace<Block>> {
+        self.executions
+            .iter()
+            .filter(|execution| execution.state_root != self.imported_state_root)
+    }
+
+    /// Returns the storage keys changed differently by the executors.
+    pub fn diverging_keys(&self) -> BTreeSet<Vec<u8>> {
+        let changes = self
+            .executions
+            .iter()
+            .map(|execution| {
+                execution
+                    .main_storage_changes
+                    .iter()
+                    .cloned()
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .collect::<Vec<_>>();
+
+        let Some((first, rest)) = changes.split_first() else {
+            return BTreeSet::new();
+        };
+
+        rest.iter()
+            .flat_map(|other| {
+                first
+                    .keys()
+                    .chain(other.keys())
+                    .filter(|key| first.get(*key) != other.get(*key))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+/// Replays the imported blocks.
+pub struct BlockReplayer<Block, Client, BE, TransactionAdapter> {
+    client: Arc<Client>,
+    chain_params: ChainParams,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    script_interpreters: ScriptInterpreters,
+    _phantom: PhantomData<(Block, BE, TransactionAdapter)>,
+}
+
+impl<Block, Client, BE, TransactionAdapter> BlockReplayer<Block, Client, BE, TransactionAdapter>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block> + BlockBackend<Block> + StorageProvider<Block, BE> + AuxStore,
+    TransactionAdapter: BitcoinTransactionAdapter<Block>,
+{
+    /// Constructs a new instance of [`BlockReplayer`].
+    pub fn new(
+        client: Arc<Client>,
+        network: bitcoin::Network,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+    ) -> Self {
+        Self {
+            client,
+            chain_params: ChainParams::new(network),
+            coin_storage_key,
+            script_interpreters: ScriptInterpreters::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Sets the interpreters verifying the scripts of the traced transactions.
+    pub fn with_script_interpreters(mut self, script_interpreters: ScriptInterpreters) -> Self {
+        self.script_interpreters = script_interpreters;
+        self
+    }
+
+    /// Replays the block with each of the `executors`, tracing the transactions if `trace`.
+    ///
+    /// The executors must use the disk backend, the in-memory backend only has the latest state.
+    pub fn replay(
+        &self,
+        block_hash: BlockHash,
+        executors: &[&dyn BlockExecutor<Block>],
+        trace: bool,
+    ) -> Result<BlockReplay<Block>, ReplayError> {
+        let block = self.imported_block(block_hash)?;
+
+        let block_number: u32 = (*block.header().number()).saturated_into();
+        let parent_hash = *block.header().parent_hash();
+        let imported_state_root = *block.header().state_root();
+
+        let executions = executors
+            .iter()
+            .map(|executor| {
+                let ExecuteBlockResult {
+                    state_root,
+                    storage_changes,
+                    exec_info,
+                } = executor.execute_block(parent_hash, block.clone())?;
+
+                Ok(ExecutionTrace {
+                    strategy: executor.execution_strategy(),
+                    state_root,
+                    main_storage_changes: storage_changes.main_storage_changes,
+                    exec_info,
+                })
+            })
+            .collect::<Result<Vec<_>, sp_blockchain::Error>>()?;
+
+        let transactions = if trace {
+            let bitcoin_block = convert_to_bitcoin_block::<Block, TransactionAdapter>(block)
+                .map_err(ReplayError::Header)?;
+            self.trace_transactions(block_number, parent_hash, &bitcoin_block)
+        } else {
+            Vec::new()
+        };
+
+        Ok(BlockReplay {
+            block_number,
+            block_hash,
+            imported_state_root,
+            executions,
+            transactions,
+        })
+    }
+
+    /// Bisects the state root mismatch between `expected_executor` and `executor` over the
+    /// transactions of the block.
+    pub fn diagnose_state_root_mismatch(
+        &self,
+        block_hash: BlockHash,
+        expected_executor: &dyn BlockExecutor<Block>,
+        executor: &dyn BlockExecutor<Block>,
+    ) -> Result<Option<StateRootMismatch<Block>>, ReplayError> {
+        let block = self.imported_block(block_hash)?;
+        let parent_hash = *block.header().parent_hash();
+        Ok(diagnose_state_root_mismatch(
+            parent_hash,
+            block,
+            expected_executor,
+            executor,
+        )?)
+    }
+
+    fn imported_block(&self, block_hash: BlockHash) -> Result<Block, ReplayError> {
+        let substrate_block_hash = self
+            .client
+            .substrate_block_hash_for(block_hash)
+            .ok_or(ReplayError::UnknownBlock(block_hash))?;
+
+        let header = self
+            .client
+            .header(substrate_block_hash)?
+            .ok_or(ReplayError::UnknownBlock(block_hash))?;
+
+        let extrinsics = self
+            .client
+            .block_body(substrate_block_hash)?
+            .ok_or(ReplayError::MissingBody(block_hash))?;
+
+        Ok(Block::new(header, extrinsics))
+    }
+
+    fn trace_transactions(
+        &self,
+        block_number: u32,
+        parent_hash: Block::Hash,
+        block: &BitcoinBlock,
+    ) -> Vec<TransactionTrace> {
+        let flags = get_block_script_flags(block_number, block.block_hash(), &self.chain_params);
+
+        // Outputs created by the previous transactions in the block.
+        let mut created = HashMap::<OutPoint, (usize, TxOut)>::new();
+
+        block
+            .txdata
+            .iter()
+            .enumerate()
+            .map(|(index, tx)| {
+                let now = Instant::now();
+                let txid = tx.compute_txid();
+
+                let mut reads = Vec::new();
+                let mut scripts = Vec::new();
+
+                if !tx.is_coinbase() {
+                    let spending_transaction = serialize(tx);
+
+                    for (input_index, input) in tx.input.iter().enumerate() {
+                        let out_point = input.previous_output;
+                        let storage_key = self
+                            .coin_storage_key
+                            .storage_key(out_point.txid, out_point.vout);
+
+                        let (source, coin) = match created.remove(&out_point) {
+                            Some((tx_index, txout)) => {
+                                (CoinSource::Block { tx_index }, Some(txout))
+                            }
+                            None => match self.coin_in_parent_state(parent_hash, &storage_key) {
+                                Some(txout) => (CoinSource::ParentState, Some(txout)),
+                                None => (CoinSource::Missing, None),
+                            },
+                        };
+
+                        if let Some(spent_output) = &coin {
+                            let now = Instant::now();
+                            let result = self.script_interpreters.verify(
+                                spent_output,
+                                &input.script_sig,
+                                &spending_transaction,
+                                input_index,
+                                flags,
+                            );
+                            scripts.push(ScriptTrace {
+                                input_index,
+                                family: ScriptFamily::of(
+                                    &spent_output.script_pubkey,
+                                    &input.script_sig,
+                                ),
+                                result,
+                                elapsed: now.elapsed(),
+                            });
+                        }
+
+                        reads.push(CoinRead {
+                            out_point,
+                            storage_key,
+                            source,
+                            coin,
+                        });
+                    }
+                }
+
+                for (vout, txout) in tx.output.iter().enumerate() {
+                    created.insert(
+                        OutPoint {
+                            txid,
+                            vout: vout as u32,
+                        },
+                        (index, txout.clone()),
+                    );
+                }
+
+                let writes =
+                    transaction_storage_changes(tx.clone(), &*self.coin_storage_key, block_number);
+
+                TransactionTrace {
+                    index,
+                    txid,
+                    reads,
+                    writes,
+                    scripts,
+                    elapsed: now.elapsed(),
+                }
+            })
+            .collect()
+    }
+
+    fn coin_in_parent_state(&self, parent_hash: Block::Hash, storage_key: &[u8]) -> Option<TxOut> {
+        let data = self
+            .client
+            .storage(
+                parent_hash,
+                &sc_client_api::StorageKey(storage_key.to_vec()),
+            )
+            .ok()
+            .flatten()?;
+
+        let coin = Coin::decode(&mut data.0.as_slice()).ok()?;
+
+        Some(TxOut {
+            value: Amount::from_sat(coin.amount),
+            script_pubkey: ScriptBuf::from_bytes(coin.script_pubkey),
+        })
+    }
+}
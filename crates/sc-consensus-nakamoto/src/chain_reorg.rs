@@ -0,0 +1,106 @@
+//! Detection of the best chain reorganizations.
+//!
+//! An imported block becomes the new best block once its chain has more work than the current
+//! best chain. If the block does not build on the current best block, the blocks of the old
+//! best chain after the common ancestor are disconnected, which is reported as a [`ChainReorg`].
+
+use bitcoin::BlockHash;
+use futures::{Stream, StreamExt};
+use sc_client_api::{AuxStore, BlockImportNotification, BlockchainEvents};
+use sp_blockchain::{HashAndNumber, HeaderBackend};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, SaturatedConversion};
+use std::sync::Arc;
+use subcoin_primitives::BackendExt;
+
+/// Block involved in a reorg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReorgBlock {
+    pub hash: BlockHash,
+    pub height: u32,
+}
+
+/// Switch of the best chain to a fork with more work.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainReorg {
+    /// Best block before the reorg.
+    pub old_tip: ReorgBlock,
+    /// Best block after the reorg.
+    pub new_tip: ReorgBlock,
+    /// Last block shared by the old and new best chains.
+    pub common_ancestor: ReorgBlock,
+    /// Blocks removed from the best chain, ordered from the old tip.
+    pub disconnected: Vec<BlockHash>,
+    /// Blocks added to the best chain, ordered from the common ancestor, ending with the new tip.
+    pub connected: Vec<BlockHash>,
+}
+
+impl ChainReorg {
+    /// Returns the reorg caused by the imported block, `None` if the block did not become the
+    /// new best block or simply extended the best chain.
+    pub fn from_import_notification<Block, Client>(
+        client: &Arc<Client>,
+        notification: &BlockImportNotification<Block>,
+    ) -> Option<Self>
+    where
+        Block: BlockT,
+        Client: HeaderBackend<Block> + AuxStore,
+    {
+        if !notification.is_new_best {
+            return None;
+        }
+
+        // The tree route goes from the old best block to the parent of the new best block.
+        let tree_route = notification.tree_route.as_ref()?;
+
+        let retracted = tree_route.retracted();
+
+        if retracted.is_empty() {
+            return None;
+        }
+
+        let reorg_block = |block: &HashAndNumber<Block>| {
+            Some(ReorgBlock {
+                hash: client.bitcoin_block_hash_for(block.hash)?,
+                height: block.number.saturated_into(),
+            })
+        };
+
+        let new_tip = ReorgBlock {
+            hash: client.bitcoin_block_hash_for(notification.hash)?,
+            height: (*notification.header.number()).saturated_into(),
+        };
+
+        let disconnected = retracted
+            .iter()
+            .map(|block| client.bitcoin_block_hash_for(block.hash))
+            .collect::<Option<Vec<_>>>()?;
+
+        let connected = tree_route
+            .enacted()
+            .iter()
+            .map(|block| client.bitcoin_block_hash_for(block.hash))
+            .chain(std::iter::once(Some(new_tip.hash)))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            old_tip: reorg_block(&retracted[0])?,
+            new_tip,
+            common_ancestor: reorg_block(tree_route.common_block())?,
+            disconnected,
+            connected,
+        })
+    }
+}
+
+/// Returns the stream of the best chain reorgs, including the ones during the major sync.
+pub fn chain_reorg_stream<Block, Client>(client: Arc<Client>) -> impl Stream<Item = ChainReorg>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + BlockchainEvents<Block> + AuxStore,
+{
+    client
+        .every_import_notification_stream()
+        .filter_map(move |notification| {
+            futures::future::ready(ChainReorg::from_import_notification(&client, &notification))
+        })
+}
@@ -0,0 +1,276 @@
+//! Prefetch of the coins spent by the next block in the import queue.
+//!
+//! While a block is imported, the coins spent by the block queued after it are read on a
+//! background thread from the same state the block is imported on. Once the block is imported,
+//! the coins it spent or created are dropped from the prefetched ones as they may have changed,
+//! the others are the same in the state of the next block's parent. The verification of the
+//! next block then finds most of its coins in memory instead of reading them one by one from
+//! the database.
+
+use crate::verification::find_coin_in_state;
+use bitcoin::{Block as BitcoinBlock, BlockHash, OutPoint};
+use sc_client_api::{Backend, StorageProvider};
+use sp_runtime::traits::Block as BlockT;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::CoinStorageKey;
+
+/// Minimum number of the coins read by each prefetch thread.
+const MIN_COINS_PER_THREAD: usize = 64;
+
+/// Coins read ahead of the block verification, `None` if the coin is not in the state.
+pub(crate) type PrefetchedCoins = HashMap<OutPoint, Option<Coin>>;
+
+/// Block queued right after the block being imported.
+#[derive(Debug)]
+struct NextBlock {
+    block_hash: BlockHash,
+    parent_hash: BlockHash,
+    spent: Vec<OutPoint>,
+}
+
+/// Prefetch running alongside the import of the parent block.
+#[derive(Debug)]
+struct PendingPrefetch {
+    /// Block being imported.
+    parent_hash: BlockHash,
+    /// Coins spent or created by the block being imported.
+    touched: Vec<OutPoint>,
+    /// Block the coins are prefetched for.
+    block_hash: BlockHash,
+    handle: JoinHandle<PrefetchedCoins>,
+}
+
+/// Reads the coins spent by the next block while the current one is being imported.
+#[derive(Debug)]
+pub(crate) struct CoinPrefetcher {
+    threads: usize,
+    next: Option<NextBlock>,
+    pending: Option<PendingPrefetch>,
+    ready: Option<(BlockHash, PrefetchedCoins)>,
+}
+
+impl CoinPrefetcher {
+    /// Constructs a new instance reading the coins of a block on up to `threads` threads.
+    pub(crate) fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            next: None,
+            pending: None,
+            ready: None,
+        }
+    }
+
+    /// Sets the block queued right after the block to be imported next.
+    pub(crate) fn set_next(&mut self, block: &BitcoinBlock) {
+        let spent = block
+            .txdata
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+            .collect();
+
+        self.next = Some(NextBlock {
+            block_hash: block.block_hash(),
+            parent_hash: block.header.prev_blockhash,
+            spent,
+        });
+    }
+
+    /// Starts prefetching the coins of the next block from the state at `state_hash`, on which
+    /// `block` is being imported. No-op if the next block is not a child of `block`.
+    pub(crate) fn start<Block, Client, BE>(
+        &mut self,
+        block: &BitcoinBlock,
+        client: &Arc<Client>,
+        coin_storage_key: &Arc<dyn CoinStorageKey>,
+        state_hash: Block::Hash,
+    ) where
+        Block: BlockT,
+        BE: Backend<Block> + 'static,
+        Client: StorageProvider<Block, BE> + Send + Sync + 'static,
+    {
+        let block_hash = block.block_hash();
+
+        let Some(next) = self
+            .next
+            .take()
+            .filter(|next| next.parent_hash == block_hash && !next.spent.is_empty())
+        else {
+            return;
+        };
+
+        let client = client.clone();
+        let coin_storage_key = coin_storage_key.clone();
+        let threads = self.threads;
+
+        let spawn_result = std::thread::Builder::new()
+            .name("coin-prefetch".into())
+            .spawn(move || {
+                read_coins::<Block, _, BE>(
+                    &*client,
+                    &*coin_storage_key,
+                    state_hash,
+                    &next.spent,
+                    threads,
+                )
+            });
+
+        match spawn_result {
+            Ok(handle) => {
+                self.pending = Some(PendingPrefetch {
+                    parent_hash: block_hash,
+                    touched: touched_coins(block),
+                    block_hash: next.block_hash,
+                    handle,
+                });
+            }
+            Err(err) => tracing::debug!("Failed to spawn the coin prefetch thread: {err}"),
+        }
+    }
+
+    /// Makes the coins prefetched alongside the import of `block_hash` available to the next
+    /// block, must only be called once the block is imported.
+    pub(crate) fn finish(&mut self, block_hash: BlockHash) {
+        let Some(pending) = self
+            .pending
+            .take()
+            .filter(|pending| pending.parent_hash == block_hash)
+        else {
+            return;
+        };
+
+        let Ok(mut coins) = pending.handle.join() else {
+            tracing::debug!("Coin prefetch thread panicked");
+            return;
+        };
+
+        for out_point in &pending.touched {
+            coins.remove(out_point);
+        }
+
+        self.ready = Some((pending.block_hash, coins));
+    }
+
+    /// Takes the coins prefetched for the block, empty if none.
+    pub(crate) fn take(&mut self, block_hash: BlockHash) -> PrefetchedCoins {
+        self.ready
+            .take()
+            .filter(|(hash, _)| *hash == block_hash)
+            .map(|(_, coins)| coins)
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the coins spent or created by the block.
+fn touched_coins(block: &BitcoinBlock) -> Vec<OutPoint> {
+    let mut touched = Vec::new();
+
+    for tx in &block.txdata {
+        if !tx.is_coinbase() {
+            touched.extend(tx.input.iter().map(|input| input.previous_output));
+        }
+
+        let txid = tx.compute_txid();
+        touched.extend((0..tx.output.len()).map(|vout| OutPoint::new(txid, vout as u32)));
+    }
+
+    touched
+}
+
+/// Reads the coins from the state at `block_hash` concurrently on up to `threads` threads.
+fn read_coins<Block, Client, BE>(
+    client: &Client,
+    coin_storage_key: &dyn CoinStorageKey,
+    block_hash: Block::Hash,
+    out_points: &[OutPoint],
+    threads: usize,
+) -> PrefetchedCoins
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: StorageProvider<Block, BE> + Sync,
+{
+    let read_chunk = |out_points: &[OutPoint]| {
+        out_points
+            .iter()
+            .map(|out_point| {
+                let coin = find_coin_in_state::<Block, _, BE>(
+                    client,
+                    coin_storage_key,
+                    block_hash,
+                    *out_point,
+                );
+                (*out_point, coin)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let chunk_size = out_points.len().div_ceil(threads).max(MIN_COINS_PER_THREAD);
+
+    if chunk_size >= out_points.len() {
+        return read_chunk(out_points).into_iter().collect();
+    }
+
+    std::thread::scope(|scope| {
+        let handles = out_points
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || read_chunk(chunk)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{absolute, transaction, Amount, ScriptBuf, Sequence, Transaction, TxIn, TxOut};
+
+    fn transaction(inputs: Vec<OutPoint>) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: inputs
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Default::default(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(1),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_touched_coins() {
+        let coinbase = transaction(vec![OutPoint::null()]);
+        let spent = OutPoint::new(bitcoin::Txid::from_byte_array([1; 32]), 0);
+        let tx = transaction(vec![spent]);
+
+        let block = BitcoinBlock {
+            header: bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header,
+            txdata: vec![coinbase.clone(), tx.clone()],
+        };
+
+        assert_eq!(
+            touched_coins(&block),
+            vec![
+                OutPoint::new(coinbase.compute_txid(), 0),
+                spent,
+                OutPoint::new(tx.compute_txid(), 0),
+            ]
+        );
+    }
+}
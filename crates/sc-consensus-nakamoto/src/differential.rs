@@ -0,0 +1,750 @@
+//! Differential validation against a trusted bitcoind.
+//!
+//! Once enabled with [`crate::BitcoinBlockImporter::with_differential_validation`], the UTXO
+//! set changes computed by executing each block are compared with the ones reported by the
+//! reference node before the block is written to the database, so is the verdict of the
+//! block verification. The import is halted on divergence with a [`DivergenceReport`].
+//!
+//! The reference is queried through the REST interface of Bitcoin Core (`-rest`), its
+//! `/rest/block/<hash>.json` endpoint includes the outputs spent by each input. The
+//! unspendable outputs kept in the state of Subcoin are ignored as Bitcoin Core never adds
+//! them to its UTXO set.
+
+use bitcoin::hex::FromHex;
+use bitcoin::{BlockHash, OutPoint, ScriptBuf, Txid};
+use codec::Decode;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::{Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use subcoin_primitives::runtime::{Coin, MAX_SCRIPT_SIZE};
+use subcoin_primitives::CoinStorageKey;
+
+/// URL scheme of the reference node.
+const BITCOIND_SCHEME: &str = "bitcoind://";
+
+/// Timeout of a request to the reference node.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum delay between the retries of a failed request.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Differential validation error.
+#[derive(Debug, thiserror::Error)]
+pub enum DifferentialError {
+    #[error("Invalid reference node url {0}, expected bitcoind://host:port")]
+    InvalidUrl(String),
+    #[error("Request to the reference node timed out")]
+    Timeout,
+    #[error("Reference node responded with {0}")]
+    BadStatus(StatusCode),
+    #[error("Invalid response of the reference node: {0}")]
+    InvalidResponse(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Http(#[from] hyper::Error),
+}
+
+/// Trusted bitcoind queried through its REST interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferenceNode {
+    /// `host:port` of the REST interface.
+    addr: String,
+}
+
+impl FromStr for ReferenceNode {
+    type Err = DifferentialError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let addr = s
+            .strip_prefix(BITCOIND_SCHEME)
+            .map(|addr| addr.trim_end_matches('/'))
+            .filter(|addr| {
+                addr.rsplit_once(':')
+                    .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+            })
+            .ok_or_else(|| DifferentialError::InvalidUrl(s.to_string()))?;
+
+        Ok(Self {
+            addr: addr.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ReferenceNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{BITCOIND_SCHEME}{}", self.addr)
+    }
+}
+
+impl ReferenceNode {
+    /// Fetches `path`, `None` if not found.
+    async fn get(&self, path: &str) -> Result<Option<Bytes>, DifferentialError> {
+        let request = async {
+            let stream = tokio::net::TcpStream::connect(&self.addr).await?;
+            let (mut sender, connection) =
+                hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+
+            tokio::spawn(async move {
+                if let Err(err) = connection.await {
+                    tracing::debug!("Connection to the reference node failed: {err:?}");
+                }
+            });
+
+            let request = Request::get(path)
+                .header(hyper::header::HOST, &self.addr)
+                .body(Empty::<Bytes>::new())
+                .map_err(|err| DifferentialError::InvalidResponse(err.to_string()))?;
+
+            let response = sender.send_request(request).await?;
+
+            match response.status() {
+                StatusCode::OK => Ok(Some(response.into_body().collect().await?.to_bytes())),
+                StatusCode::NOT_FOUND => Ok(None),
+                status => Err(DifferentialError::BadStatus(status)),
+            }
+        };
+
+        tokio::time::timeout(REQUEST_TIMEOUT, request)
+            .await
+            .map_err(|_| DifferentialError::Timeout)?
+    }
+
+    /// Fetches the block from the reference node, `None` if the block is unknown to it.
+    pub async fn block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<ReferenceBlock>, DifferentialError> {
+        let Some(body) = self.get(&format!("/rest/block/{block_hash}.json")).await? else {
+            return Ok(None);
+        };
+
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|err| DifferentialError::InvalidResponse(err.to_string()))
+    }
+
+    /// Returns the height and hash of the tip of the active chain of the reference node.
+    pub async fn best_block(&self) -> Result<(u32, BlockHash), DifferentialError> {
+        let body = self.get("/rest/chaininfo.json").await?.ok_or_else(|| {
+            DifferentialError::InvalidResponse("Chain info not found".to_string())
+        })?;
+
+        let chain_info: ChainInfoJson = serde_json::from_slice(&body)
+            .map_err(|err| DifferentialError::InvalidResponse(err.to_string()))?;
+
+        Ok((chain_info.blocks, chain_info.bestblockhash))
+    }
+
+    /// Returns the hash of the block at `height` in the active chain of the reference node,
+    /// `None` if the height is above the tip.
+    pub async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>, DifferentialError> {
+        let Some(body) = self
+            .get(&format!("/rest/blockhashbyheight/{height}.json"))
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        serde_json::from_slice::<BlockHashJson>(&body)
+            .map(|json| Some(json.blockhash))
+            .map_err(|err| DifferentialError::InvalidResponse(err.to_string()))
+    }
+
+    /// Fetches the serialized block from the reference node, `None` if the block is unknown
+    /// to it.
+    pub async fn raw_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<bitcoin::Block>, DifferentialError> {
+        let Some(body) = self.get(&format!("/rest/block/{block_hash}.bin")).await? else {
+            return Ok(None);
+        };
+
+        bitcoin::consensus::deserialize(&body)
+            .map(Some)
+            .map_err(|err| DifferentialError::InvalidResponse(err.to_string()))
+    }
+
+    /// Fetches the block, retrying until the reference node is reachable.
+    ///
+    /// The blocks can not be validated while the reference node is down, the import waits.
+    async fn block_with_retry(&self, block_hash: BlockHash) -> Option<ReferenceBlock> {
+        let mut delay = Duration::from_secs(1);
+
+        loop {
+            match self.block(block_hash).await {
+                Ok(block) => return block,
+                Err(err) => {
+                    tracing::warn!(
+                        "Failed to fetch block {block_hash} from {self}: {err}, retrying in {}s",
+                        delay.as_secs()
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainInfoJson {
+    blocks: u32,
+    bestblockhash: BlockHash,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockHashJson {
+    blockhash: BlockHash,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScriptPubKeyJson {
+    hex: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrevoutJson {
+    generated: bool,
+    height: u32,
+    value: f64,
+    #[serde(rename = "scriptPubKey")]
+    script_pubkey: ScriptPubKeyJson,
+}
+
+#[derive(Debug, Deserialize)]
+struct InputJson {
+    coinbase: Option<String>,
+    txid: Option<Txid>,
+    vout: Option<u32>,
+    prevout: Option<PrevoutJson>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OutputJson {
+    value: f64,
+    n: u32,
+    #[serde(rename = "scriptPubKey")]
+    script_pubkey: ScriptPubKeyJson,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionJson {
+    txid: Txid,
+    vin: Vec<InputJson>,
+    vout: Vec<OutputJson>,
+}
+
+/// Block as reported by the reference node.
+#[derive(Debug, Deserialize)]
+pub struct ReferenceBlock {
+    /// `-1` if the block is not in the active chain of the reference node.
+    confirmations: i64,
+    height: u32,
+    tx: Vec<TransactionJson>,
+}
+
+fn btc_to_sat(value: f64) -> u64 {
+    (value * 100_000_000.0).round() as u64
+}
+
+fn parse_script(script_pubkey: &ScriptPubKeyJson) -> Result<ScriptBuf, DifferentialError> {
+    Vec::<u8>::from_hex(&script_pubkey.hex)
+        .map(ScriptBuf::from_bytes)
+        .map_err(|err| DifferentialError::InvalidResponse(err.to_string()))
+}
+
+impl ReferenceBlock {
+    /// Returns `true` if the block is in the active chain of the reference node.
+    pub fn is_in_active_chain(&self) -> bool {
+        self.confirmations > 0
+    }
+
+    /// Returns the UTXO set changes of the block according to the reference node.
+    pub fn utxo_diff(&self) -> Result<UtxoDiff, DifferentialError> {
+        let mut diff = UtxoDiff::default();
+
+        for tx in &self.tx {
+            let is_coinbase = tx.vin.iter().any(|input| input.coinbase.is_some());
+
+            for input in tx.vin.iter().filter(|input| input.coinbase.is_none()) {
+                let (Some(txid), Some(vout), Some(prevout)) =
+                    (input.txid, input.vout, input.prevout.as_ref())
+                else {
+                    return Err(DifferentialError::InvalidResponse(format!(
+                        "Spent output missing in the input of {}, \
+                         is the undo data of the reference node available?",
+                        tx.txid
+                    )));
+                };
+
+                let out_point = OutPoint { txid, vout };
+
+                // Spent in the same block.
+                if diff.created.remove(&out_point).is_some() {
+                    continue;
+                }
+
+                diff.spent.insert(
+                    out_point,
+                    UtxoEntry {
+                        amount: btc_to_sat(prevout.value),
+                        height: prevout.height,
+                        is_coinbase: prevout.generated,
+                        script_pubkey: parse_script(&prevout.script_pubkey)?,
+                    },
+                );
+            }
+
+            for output in &tx.vout {
+                diff.created.insert(
+                    OutPoint {
+                        txid: tx.txid,
+                        vout: output.n,
+                    },
+                    UtxoEntry {
+                        amount: btc_to_sat(output.value),
+                        height: self.height,
+                        is_coinbase,
+                        script_pubkey: parse_script(&output.script_pubkey)?,
+                    },
+                );
+            }
+        }
+
+        diff.created.retain(|_, entry| !entry.is_unspendable());
+
+        Ok(diff)
+    }
+}
+
+/// Entry of the UTXO set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxoEntry {
+    pub amount: u64,
+    pub height: u32,
+    pub is_coinbase: bool,
+    pub script_pubkey: ScriptBuf,
+}
+
+impl From<Coin> for UtxoEntry {
+    fn from(coin: Coin) -> Self {
+        Self {
+            amount: coin.amount,
+            height: coin.height,
+            is_coinbase: coin.is_coinbase,
+            script_pubkey: ScriptBuf::from_bytes(coin.script_pubkey),
+        }
+    }
+}
+
+impl fmt::Display for UtxoEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{ amount: {}, height: {}, coinbase: {}, script_pubkey: {} }}",
+            self.amount,
+            self.height,
+            self.is_coinbase,
+            self.script_pubkey.to_hex_string()
+        )
+    }
+}
+
+impl UtxoEntry {
+    /// Same as `CScript::IsUnspendable()` in Bitcoin Core.
+    fn is_unspendable(&self) -> bool {
+        self.script_pubkey.is_op_return() || self.script_pubkey.len() > MAX_SCRIPT_SIZE
+    }
+}
+
+/// Changes of the UTXO set made by a block.
+///
+/// The outputs created and spent within the block are not included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UtxoDiff {
+    pub spent: BTreeMap<OutPoint, UtxoEntry>,
+    pub created: BTreeMap<OutPoint, UtxoEntry>,
+}
+
+impl UtxoDiff {
+    /// Collects the changes of the coins from the storage changes of a block.
+    ///
+    /// `spent_coin` returns the coin in the parent state.
+    pub fn from_storage_changes<'a>(
+        main_storage_changes: impl IntoIterator<Item = &'a (Vec<u8>, Option<Vec<u8>>)>,
+        coin_storage_key: &dyn CoinStorageKey,
+        mut spent_coin: impl FnMut(&[u8]) -> Option<Coin>,
+    ) -> Result<Self, codec::Error> {
+        let prefix = coin_storage_key.storage_prefix();
+
+        let mut diff = Self::default();
+
+        for (key, value) in main_storage_changes {
+            if !key.starts_with(&prefix) {
+                continue;
+            }
+
+            let Some(out_point) = coin_storage_key.outpoint(key) else {
+                continue;
+            };
+
+            match value {
+                Some(value) => {
+                    let entry = UtxoEntry::from(Coin::decode(&mut value.as_slice())?);
+                    if !entry.is_unspendable() {
+                        diff.created.insert(out_point, entry);
+                    }
+                }
+                // The coins absent from the parent state are created and spent in the block.
+                None => {
+                    if let Some(coin) = spent_coin(key) {
+                        diff.spent.insert(out_point, coin.into());
+                    }
+                }
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// Returns the differences with the changes of the reference node.
+    pub fn mismatches(&self, reference: &Self) -> Vec<UtxoMismatch> {
+        fn compare(
+            kind: UtxoChange,
+            ours: &BTreeMap<OutPoint, UtxoEntry>,
+            theirs: &BTreeMap<OutPoint, UtxoEntry>,
+            mismatches: &mut Vec<UtxoMismatch>,
+        ) {
+            let out_points = ours.keys().chain(theirs.keys()).collect::<HashSet<_>>();
+
+            let mut out_points = out_points.into_iter().collect::<Vec<_>>();
+            out_points.sort();
+
+            for out_point in out_points {
+                let (ours, reference) = (ours.get(out_point), theirs.get(out_point));
+                if ours != reference {
+                    mismatches.push(UtxoMismatch {
+                        kind,
+                        out_point: *out_point,
+                        ours: ours.cloned(),
+                        reference: reference.cloned(),
+                    });
+                }
+            }
+        }
+
+        let mut mismatches = Vec::new();
+        compare(
+            UtxoChange::Spent,
+            &self.spent,
+            &reference.spent,
+            &mut mismatches,
+        );
+        compare(
+            UtxoChange::Created,
+            &self.created,
+            &reference.created,
+            &mut mismatches,
+        );
+        mismatches
+    }
+}
+
+/// Kind of the UTXO set change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UtxoChange {
+    Spent,
+    Created,
+}
+
+/// An output changed differently by Subcoin and the reference node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxoMismatch {
+    pub kind: UtxoChange,
+    pub out_point: OutPoint,
+    /// `None` if the change is missing in Subcoin.
+    pub ours: Option<UtxoEntry>,
+    /// `None` if the change is missing in the reference node.
+    pub reference: Option<UtxoEntry>,
+}
+
+/// Divergence from the reference node.
+#[derive(Debug, Clone)]
+pub enum Divergence {
+    /// The block was rejected by Subcoin but is in the active chain of the reference node.
+    Rejected { reason: String },
+    /// The UTXO set changes differ.
+    UtxoDiff(Vec<UtxoMismatch>),
+}
+
+/// Report of a divergence from the reference node, the import is halted once produced.
+#[derive(Debug, Clone)]
+pub struct DivergenceReport {
+    pub block_number: u32,
+    pub block_hash: BlockHash,
+    pub divergence: Divergence,
+}
+
+impl fmt::Display for DivergenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            block_number,
+            block_hash,
+            divergence,
+        } = self;
+
+        write!(
+            f,
+            "Divergence from the reference node at block #{block_number},{block_hash}: "
+        )?;
+
+        match divergence {
+            Divergence::Rejected { reason } => {
+                write!(
+                    f,
+                    "block rejected ({reason}) but accepted by the reference node"
+                )
+            }
+            Divergence::UtxoDiff(mismatches) => {
+                write!(f, "{} mismatched UTXO set changes", mismatches.len())?;
+                for UtxoMismatch {
+                    kind,
+                    out_point,
+                    ours,
+                    reference,
+                } in mismatches
+                {
+                    let display = |entry: &Option<UtxoEntry>| {
+                        entry
+                            .as_ref()
+                            .map_or_else(|| "none".to_string(), ToString::to_string)
+                    };
+                    write!(
+                        f,
+                        "\n  {kind:?} {out_point}: ours: {}, reference: {}",
+                        display(ours),
+                        display(reference)
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DivergenceReport {}
+
+/// Validates the imported blocks against a [`ReferenceNode`].
+pub(crate) struct DifferentialValidator {
+    reference: ReferenceNode,
+}
+
+impl DifferentialValidator {
+    pub(crate) fn new(reference: ReferenceNode) -> Self {
+        tracing::info!("Validating the imported blocks against {reference}");
+        Self { reference }
+    }
+
+    /// Checks a block rejected by the verification.
+    pub(crate) async fn check_rejected(
+        &self,
+        block_number: u32,
+        block_hash: BlockHash,
+        reason: String,
+    ) -> Result<(), DivergenceReport> {
+        match self.reference.block_with_retry(block_hash).await {
+            Some(reference_block) if reference_block.is_in_active_chain() => {
+                Err(DivergenceReport {
+                    block_number,
+                    block_hash,
+                    divergence: Divergence::Rejected { reason },
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the UTXO set changes of a block accepted by the verification.
+    pub(crate) async fn check_accepted(
+        &self,
+        block_number: u32,
+        block_hash: BlockHash,
+        utxo_diff: &UtxoDiff,
+    ) -> Result<(), DivergenceReport> {
+        let Some(reference_block) = self.reference.block_with_retry(block_hash).await else {
+            tracing::warn!(
+                "Block #{block_number},{block_hash} unknown to the reference node, \
+                 skipping differential validation"
+            );
+            return Ok(());
+        };
+
+        if !reference_block.is_in_active_chain() {
+            tracing::warn!(
+                "Block #{block_number},{block_hash} is not in the active chain of \
+                 the reference node, it's either stale or invalid there"
+            );
+        }
+
+        let reference_diff = match reference_block.utxo_diff() {
+            Ok(reference_diff) => reference_diff,
+            Err(err) => {
+                tracing::warn!(
+                    "Skipping differential validation of block #{block_number},{block_hash}: {err}"
+                );
+                return Ok(());
+            }
+        };
+
+        let mismatches = utxo_diff.mismatches(&reference_diff);
+
+        if mismatches.is_empty() {
+            tracing::trace!("Block #{block_number},{block_hash} matches the reference node");
+            Ok(())
+        } else {
+            Err(DivergenceReport {
+                block_number,
+                block_hash,
+                divergence: Divergence::UtxoDiff(mismatches),
+            })
+        }
+    }
+}
+
+/// Returns `true` if the import error is caused by a divergence from the reference node.
+pub(crate) fn is_divergence(err: &sp_consensus::Error) -> bool {
+    match err {
+        sp_consensus::Error::Other(err) => err.downcast_ref::<DivergenceReport>().is_some(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    fn entry(amount: u64) -> UtxoEntry {
+        UtxoEntry {
+            amount,
+            height: 1,
+            is_coinbase: false,
+            script_pubkey: ScriptBuf::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_reference_node() {
+        let reference: ReferenceNode = "bitcoind://127.0.0.1:8332".parse().unwrap();
+        assert_eq!(reference.addr, "127.0.0.1:8332");
+        assert_eq!(reference.to_string(), "bitcoind://127.0.0.1:8332");
+        assert!("http://127.0.0.1:8332".parse::<ReferenceNode>().is_err());
+        assert!("bitcoind://127.0.0.1".parse::<ReferenceNode>().is_err());
+    }
+
+    #[test]
+    fn test_reference_utxo_diff() {
+        let block: ReferenceBlock = serde_json::from_value(serde_json::json!({
+            "confirmations": 1,
+            "height": 2,
+            "tx": [
+                {
+                    "txid": "0000000000000000000000000000000000000000000000000000000000000001",
+                    "vin": [{ "coinbase": "00" }],
+                    "vout": [
+                        { "value": 50.0, "n": 0, "scriptPubKey": { "hex": "51" } },
+                        { "value": 0.0, "n": 1, "scriptPubKey": { "hex": "6a" } },
+                    ],
+                },
+                {
+                    "txid": "0000000000000000000000000000000000000000000000000000000000000002",
+                    "vin": [{
+                        "txid": "0000000000000000000000000000000000000000000000000000000000000003",
+                        "vout": 0,
+                        "prevout": {
+                            "generated": true,
+                            "height": 1,
+                            "value": 0.1,
+                            "scriptPubKey": { "hex": "51" },
+                        },
+                    }],
+                    "vout": [{ "value": 0.1, "n": 0, "scriptPubKey": { "hex": "51" } }],
+                },
+                {
+                    "txid": "0000000000000000000000000000000000000000000000000000000000000004",
+                    "vin": [{
+                        "txid": "0000000000000000000000000000000000000000000000000000000000000002",
+                        "vout": 0,
+                        "prevout": {
+                            "generated": false,
+                            "height": 2,
+                            "value": 0.1,
+                            "scriptPubKey": { "hex": "51" },
+                        },
+                    }],
+                    "vout": [{ "value": 0.09, "n": 0, "scriptPubKey": { "hex": "51" } }],
+                },
+            ],
+        }))
+        .unwrap();
+
+        let diff = block.utxo_diff().unwrap();
+
+        let txid = |n: u8| {
+            let mut bytes = [0u8; 32];
+            bytes[31] = n;
+            Txid::from_byte_array(bytes)
+        };
+
+        // The OP_RETURN output and the output spent in the block are not included.
+        assert_eq!(
+            diff.created.keys().copied().collect::<Vec<_>>(),
+            vec![OutPoint::new(txid(1), 0), OutPoint::new(txid(4), 0)]
+        );
+        assert_eq!(diff.created[&OutPoint::new(txid(4), 0)].amount, 9_000_000);
+        assert_eq!(
+            diff.spent.keys().copied().collect::<Vec<_>>(),
+            vec![OutPoint::new(txid(3), 0)]
+        );
+        assert!(diff.spent[&OutPoint::new(txid(3), 0)].is_coinbase);
+    }
+
+    #[test]
+    fn test_utxo_mismatches() {
+        let out_point = |vout| OutPoint::new(Txid::all_zeros(), vout);
+
+        let ours = UtxoDiff {
+            spent: BTreeMap::from([(out_point(0), entry(1))]),
+            created: BTreeMap::from([(out_point(1), entry(2)), (out_point(2), entry(3))]),
+        };
+        let reference = UtxoDiff {
+            spent: BTreeMap::from([(out_point(0), entry(1))]),
+            created: BTreeMap::from([(out_point(1), entry(5)), (out_point(3), entry(3))]),
+        };
+
+        assert!(ours.mismatches(&ours).is_empty());
+
+        let mismatches = ours.mismatches(&reference);
+        assert_eq!(
+            mismatches
+                .iter()
+                .map(|mismatch| (mismatch.kind, mismatch.out_point.vout))
+                .collect::<Vec<_>>(),
+            vec![
+                (UtxoChange::Created, 1),
+                (UtxoChange::Created, 2),
+                (UtxoChange::Created, 3)
+            ]
+        );
+        assert_eq!(mismatches[1].reference, None);
+        assert_eq!(mismatches[2].ours, None);
+    }
+}
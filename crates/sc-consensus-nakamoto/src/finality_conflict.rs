@@ -0,0 +1,112 @@
+//! Detection of the Bitcoin reorgs beyond the finalized block.
+//!
+//! The finalized Substrate blocks are never reverted, whereas a Bitcoin reorg deeper than the
+//! finality policy anticipated is still possible. A block of such a fork can't be imported,
+//! the conflict is recorded in the aux-db and reported until the finalized blocks are reverted
+//! with `subcoin revert-finalized --force`.
+
+use bitcoin::BlockHash;
+use sc_client_api::AuxStore;
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::{Block as BlockT, SaturatedConversion};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subcoin_primitives::BackendExt;
+
+/// Aux-db key of the last detected conflict.
+const FINALITY_CONFLICT_KEY: &[u8] = b"finality_conflict";
+
+/// Block of a chain forking from the best chain below the finalized block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[serde(rename_all = "camelCase")]
+#[error(
+    "Block #{height},{block_hash} forks from the best chain at #{fork_height}, below the \
+    finalized block #{finalized_height},{finalized_hash}. Run `subcoin revert-finalized --force` \
+    to follow the fork"
+)]
+pub struct FinalityConflict {
+    /// Block of the conflicting fork.
+    pub block_hash: BlockHash,
+    pub height: u32,
+    /// Last block shared by the fork and the best chain.
+    pub fork_hash: BlockHash,
+    pub fork_height: u32,
+    /// Finalized block at the time of the detection.
+    pub finalized_hash: BlockHash,
+    pub finalized_height: u32,
+    /// UNIX timestamp of the detection in seconds.
+    pub detected_at: u64,
+}
+
+impl FinalityConflict {
+    /// Returns the conflict if the block at `height` on top of `parent_hash` forks from the
+    /// best chain below the finalized block.
+    pub fn detect<Block, Client>(
+        client: &Arc<Client>,
+        block_hash: BlockHash,
+        height: u32,
+        parent_hash: BlockHash,
+    ) -> Option<Self>
+    where
+        Block: BlockT,
+        Client: HeaderBackend<Block> + AuxStore,
+    {
+        let info = client.info();
+        let finalized_height: u32 = info.finalized_number.saturated_into();
+
+        let mut fork_hash = parent_hash;
+        let mut fork_height = height.checked_sub(1)?;
+
+        // Walk back the fork until it meets the best chain.
+        while BackendExt::<Block>::block_hash(client, fork_height) != Some(fork_hash) {
+            fork_hash = client.block_header(fork_hash)?.prev_blockhash;
+            fork_height = fork_height.checked_sub(1)?;
+        }
+
+        if fork_height >= finalized_height {
+            return None;
+        }
+
+        Some(Self {
+            block_hash,
+            height,
+            fork_hash,
+            fork_height,
+            finalized_hash: client.bitcoin_block_hash_for(info.finalized_hash)?,
+            finalized_height,
+            detected_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Records the conflict, returns `false` if the conflict of the same block has been
+    /// recorded.
+    pub fn record<Client: AuxStore>(&self, client: &Client) -> sp_blockchain::Result<bool> {
+        if Self::load(client).is_some_and(|recorded| recorded.block_hash == self.block_hash) {
+            return Ok(false);
+        }
+
+        let encoded = serde_json::to_vec(self)
+            .map_err(|err| sp_blockchain::Error::Application(Box::new(err)))?;
+        client.insert_aux(&[(FINALITY_CONFLICT_KEY, encoded.as_slice())], &[])?;
+
+        Ok(true)
+    }
+
+    /// Returns the last detected conflict, if not resolved yet.
+    pub fn load<Client: AuxStore>(client: &Client) -> Option<Self> {
+        client
+            .get_aux(FINALITY_CONFLICT_KEY)
+            .ok()
+            .flatten()
+            .and_then(|encoded| serde_json::from_slice(&encoded).ok())
+    }
+
+    /// Removes the recorded conflict once resolved.
+    pub fn clear<Client: AuxStore>(client: &Client) -> sp_blockchain::Result<()> {
+        client.insert_aux(&[], &[FINALITY_CONFLICT_KEY])
+    }
+}
@@ -4,7 +4,10 @@
 //! the database. Import results are then communicated back.
 
 use crate::block_import::{BitcoinBlockImport, ImportStatus};
+use crate::differential::is_divergence;
+use crate::verification::block_verification_failure;
 use bitcoin::{Block as BitcoinBlock, BlockHash};
+use futures::channel::oneshot;
 use futures::prelude::*;
 use futures::task::{Context, Poll};
 use futures::StreamExt;
@@ -24,14 +27,59 @@ pub struct ImportBlocks {
     pub blocks: Vec<BitcoinBlock>,
 }
 
+/// Block produced locally, e.g., mined externally and submitted via RPC.
+#[derive(Debug)]
+struct LocalBlock {
+    block: BitcoinBlock,
+    result_sender: oneshot::Sender<Result<ImportStatus, sp_consensus::Error>>,
+}
+
+/// Handle for importing the blocks produced locally through the import queue.
+///
+/// Unlike the blocks downloaded from the network, the result of each block is returned to
+/// the caller directly.
+#[derive(Debug, Clone)]
+pub struct LocalBlockImport {
+    local_block_sender: TracingUnboundedSender<LocalBlock>,
+}
+
+impl LocalBlockImport {
+    /// Imports the block, returns the import status once the block has been processed.
+    pub async fn import_block(
+        &self,
+        block: BitcoinBlock,
+    ) -> Result<ImportStatus, sp_consensus::Error> {
+        let (result_sender, result_receiver) = oneshot::channel();
+
+        self.local_block_sender
+            .unbounded_send(LocalBlock {
+                block,
+                result_sender,
+            })
+            .map_err(|_| sp_consensus::Error::Other("Import queue is not running".into()))?;
+
+        result_receiver
+            .await
+            .map_err(|_| sp_consensus::Error::Other("Import queue is not running".into()))?
+    }
+}
+
 /// Import queue for processing Bitcoin blocks.
 #[derive(Debug)]
 pub struct BlockImportQueue {
     block_import_sender: TracingUnboundedSender<ImportBlocks>,
     import_result_receiver: TracingUnboundedReceiver<ImportManyBlocksResult>,
+    local_block_sender: TracingUnboundedSender<LocalBlock>,
 }
 
 impl BlockImportQueue {
+    /// Returns a handle for importing the blocks produced locally.
+    pub fn local_block_import(&self) -> LocalBlockImport {
+        LocalBlockImport {
+            local_block_sender: self.local_block_sender.clone(),
+        }
+    }
+
     /// Sends a batch of blocks to the worker of import queue for processing.
     pub fn import_blocks(&self, incoming_blocks: ImportBlocks) {
         let _ = self.block_import_sender.unbounded_send(incoming_blocks);
@@ -60,11 +108,15 @@ where
     let (block_import_sender, block_import_receiver) =
         tracing_unbounded("mpsc_import_queue_worker_blocks", 100_000);
 
+    let (local_block_sender, local_block_receiver) =
+        tracing_unbounded("mpsc_import_queue_local_blocks", 1_000);
+
     let future = async move {
         let block_import_process = block_import_process(
             &mut block_import,
             import_result_sender.clone(),
             block_import_receiver,
+            local_block_receiver,
         );
         futures::pin_mut!(block_import_process);
 
@@ -94,6 +146,51 @@ where
     BlockImportQueue {
         block_import_sender,
         import_result_receiver,
+        local_block_sender,
+    }
+}
+
+/// Creates an import queue for the node never importing the blocks, i.e., the light client.
+///
+/// The blocks sent to the queue are dropped and the local blocks are rejected.
+pub fn disabled_import_queue(spawner: &impl SpawnEssentialNamed) -> BlockImportQueue {
+    let (import_result_sender, import_result_receiver) =
+        tracing_unbounded("mpsc_import_queue_result", 1);
+
+    let (block_import_sender, block_import_receiver) =
+        tracing_unbounded("mpsc_import_queue_worker_blocks", 1_000);
+
+    let (local_block_sender, local_block_receiver) =
+        tracing_unbounded("mpsc_import_queue_local_blocks", 1_000);
+
+    let future = async move {
+        // No result is ever sent, the channel is kept open for the receiver to stay pending.
+        let _import_result_sender = import_result_sender;
+
+        let mut requests = futures::stream::select(
+            block_import_receiver.map(ImportRequest::Blocks),
+            local_block_receiver.map(ImportRequest::Local),
+        );
+
+        while let Some(request) = requests.next().await {
+            if let ImportRequest::Local(LocalBlock { result_sender, .. }) = request {
+                let _ = result_sender.send(Err(sp_consensus::Error::Other(
+                    "Block import is disabled".into(),
+                )));
+            }
+        }
+    };
+
+    spawner.spawn_essential_blocking(
+        "disabled-block-import-worker",
+        Some("block-import"),
+        future.boxed(),
+    );
+
+    BlockImportQueue {
+        block_import_sender,
+        import_result_receiver,
+        local_block_sender,
     }
 }
 
@@ -110,27 +207,60 @@ impl<Block: BlockT> sc_consensus::Verifier<Block> for VerifyNothing {
     }
 }
 
+/// Request received by the import worker.
+enum ImportRequest {
+    Blocks(ImportBlocks),
+    Local(LocalBlock),
+}
+
 /// The process of importing blocks.
 ///
-/// This polls the `block_import_receiver` for new blocks to import and than awaits on
-/// importing these blocks. After each block is imported, this async function yields once
-/// to give other futures the possibility to be run.
+/// This polls the `block_import_receiver` and `local_block_receiver` for new blocks to import
+/// and than awaits on importing these blocks. After each block is imported, this async
+/// function yields once to give other futures the possibility to be run.
 ///
-/// Returns when `block_import` ended.
+/// Returns when `block_import` ended or diverged from the reference node, which shuts down
+/// the node as the import worker is an essential task.
 async fn block_import_process(
     block_import: &mut dyn BitcoinBlockImport,
     result_sender: TracingUnboundedSender<ImportManyBlocksResult>,
-    mut block_import_receiver: TracingUnboundedReceiver<ImportBlocks>,
+    block_import_receiver: TracingUnboundedReceiver<ImportBlocks>,
+    local_block_receiver: TracingUnboundedReceiver<LocalBlock>,
 ) {
+    let mut requests = futures::stream::select(
+        block_import_receiver.map(ImportRequest::Blocks),
+        local_block_receiver.map(ImportRequest::Local),
+    );
+
     loop {
-        let Some(ImportBlocks { origin, blocks }) = block_import_receiver.next().await else {
+        let Some(request) = requests.next().await else {
             tracing::debug!("Stopping block import because the import channel was closed!",);
             return;
         };
 
-        let res = import_many_blocks(block_import, origin, blocks).await;
+        let diverged = match request {
+            ImportRequest::Blocks(ImportBlocks { origin, blocks }) => {
+                let (res, diverged) = import_many_blocks(block_import, origin, blocks).await;
+                let _ = result_sender.unbounded_send(res);
+                diverged
+            }
+            ImportRequest::Local(LocalBlock {
+                block,
+                result_sender,
+            }) => {
+                let block_hash = block.block_hash();
+                let import_result = block_import.import_block(block).await;
+                let diverged = import_result.as_ref().is_err_and(is_divergence);
+                tracing::debug!(?import_result, "Imported local block {block_hash}");
+                let _ = result_sender.send(import_result);
+                diverged
+            }
+        };
 
-        let _ = result_sender.unbounded_send(res);
+        if diverged {
+            tracing::error!("Halting block import due to the divergence from the reference node");
+            return;
+        }
     }
 }
 
@@ -150,26 +280,34 @@ pub struct ImportManyBlocksResult {
 /// Import several blocks at once, returning import result for each block.
 ///
 /// This will yield after each imported block once, to ensure that other futures can
-/// be called as well.
+/// be called as well. The import stops at the first error, the returned flag indicates whether
+/// the error is a divergence from the reference node.
 async fn import_many_blocks(
     import_handle: &mut dyn BitcoinBlockImport,
     _blocks_origin: BlockOrigin,
     blocks: Vec<BitcoinBlock>,
-) -> ImportManyBlocksResult {
+) -> (ImportManyBlocksResult, bool) {
     tracing::trace!("[import_many_blocks] importing {} blocks", blocks.len());
     let count = blocks.len();
 
     let mut imported = 0;
     let mut results = vec![];
     let mut has_error = false;
+    let mut diverged = false;
 
     // Blocks in the response/drain should be in ascending order.
-    for block in blocks {
+    let mut blocks = blocks.into_iter().peekable();
+
+    while let Some(block) = blocks.next() {
         let block_hash = block.block_hash();
 
         let block_import_result = if has_error {
             Err(BlockImportError::Cancelled)
         } else {
+            if let Some(next_block) = blocks.peek() {
+                import_handle.set_next_block(next_block);
+            }
+
             // The actual import.
             let import_result = import_handle.import_block(block).await;
 
@@ -196,7 +334,14 @@ async fn import_many_blocks(
                     Err(BlockImportError::BadBlock(None))
                 }
                 Err(err) => {
-                    tracing::error!(?err, "Error importing block: {block_hash:?}");
+                    diverged = is_divergence(&err);
+                    match block_verification_failure(&err) {
+                        Some(verification_error) => tracing::warn!(
+                            reason = verification_error.reason(),
+                            "Rejected invalid block {block_hash:?}: {verification_error}"
+                        ),
+                        None => tracing::error!(?err, "Error importing block: {block_hash:?}"),
+                    }
                     Err(BlockImportError::Other(err))
                 }
             }
@@ -216,11 +361,13 @@ async fn import_many_blocks(
     }
 
     // No block left to import, success!
-    ImportManyBlocksResult {
+    let result = ImportManyBlocksResult {
         block_count: count,
         imported,
         results,
-    }
+    };
+
+    (result, diverged)
 }
 
 /// A future that will always `yield` on the first call of `poll` but schedules the
@@ -0,0 +1,112 @@
+//! Structured records of the block imports.
+//!
+//! A record is emitted at the debug level under the `subcoin::import` target at most once per
+//! [`IMPORT_RECORD_INTERVAL`], so that the logs are not flooded during the major sync. The
+//! blocks taking longer than the interval to import are always recorded as these are the ones
+//! worth looking into.
+
+use bitcoin::BlockHash;
+use std::time::{Duration, Instant};
+
+const IMPORT_RECORD_TARGET: &str = "subcoin::import";
+
+const IMPORT_RECORD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time spent in each stage of the block import.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ImportTimings {
+    /// Block verification, dominated by the script verification.
+    pub script_verify: Duration,
+    /// Block execution, `None` if the block is not executed.
+    pub execution: Option<Duration>,
+    /// Writing the block and its state changes to the database.
+    pub flush: Duration,
+}
+
+impl ImportTimings {
+    fn total(&self) -> Duration {
+        self.script_verify + self.execution.unwrap_or_default() + self.flush
+    }
+}
+
+/// Rate-limited emitter of the block import records.
+#[derive(Debug, Default)]
+pub(crate) struct ImportRecorder {
+    last_record: Option<Instant>,
+    /// Number of the imported blocks not recorded since the last record.
+    skipped: usize,
+}
+
+impl ImportRecorder {
+    /// Emits the record of an imported block unless rate-limited.
+    pub(crate) fn record(
+        &mut self,
+        height: u32,
+        hash: BlockHash,
+        tx_count: usize,
+        timings: ImportTimings,
+    ) {
+        let Some(skipped) = self.should_record(Instant::now(), timings.total()) else {
+            return;
+        };
+
+        tracing::debug!(
+            target: IMPORT_RECORD_TARGET,
+            height,
+            %hash,
+            tx_count,
+            execution_ms = timings.execution.map(|t| t.as_millis() as u64),
+            script_verify_ms = timings.script_verify.as_millis() as u64,
+            flush_ms = timings.flush.as_millis() as u64,
+            skipped,
+            "Imported block#{height}",
+        );
+    }
+
+    /// Returns the number of the skipped blocks since the last record if the block at `now`
+    /// should be recorded.
+    fn should_record(&mut self, now: Instant, import_time: Duration) -> Option<usize> {
+        let due = match self.last_record {
+            Some(last_record) => {
+                now.saturating_duration_since(last_record) >= IMPORT_RECORD_INTERVAL
+            }
+            None => true,
+        };
+
+        if due || import_time >= IMPORT_RECORD_INTERVAL {
+            self.last_record = Some(now);
+            Some(std::mem::take(&mut self.skipped))
+        } else {
+            self.skipped += 1;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_records_are_rate_limited() {
+        let mut recorder = ImportRecorder::default();
+        let start = Instant::now();
+        let fast = Duration::from_millis(10);
+
+        assert_eq!(recorder.should_record(start, fast), Some(0));
+        assert_eq!(recorder.should_record(start + fast, fast), None);
+        assert_eq!(recorder.should_record(start + fast * 2, fast), None);
+
+        // Slow blocks are always recorded.
+        assert_eq!(
+            recorder.should_record(start + fast * 3, IMPORT_RECORD_INTERVAL),
+            Some(2)
+        );
+        assert_eq!(recorder.should_record(start + fast * 4, fast), None);
+
+        assert_eq!(
+            recorder.should_record(start + fast * 3 + IMPORT_RECORD_INTERVAL, fast),
+            Some(1)
+        );
+    }
+}
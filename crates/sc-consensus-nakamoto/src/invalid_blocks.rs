@@ -0,0 +1,270 @@
+//! Blocks marked invalid, either on import or manually by the operator.
+//!
+//! Each invalid block is flagged under [`invalid_block_key`] in the aux-db. The blocks failing
+//! the verification are flagged on import, the blocks invalidated manually are flagged along
+//! with all their descendants in the database. The import pipeline rejects the flagged blocks
+//! and their children.
+//!
+//! [`InvalidBlocks`] implements the `invalidateblock` and `reconsiderblock` operations of
+//! Bitcoin Core, the best block is switched to the valid tip with the most work afterwards.
+
+use bitcoin::{BlockHash, Work};
+use sc_client_api::backend::BlockImportOperation;
+use sc_client_api::{AuxStore, Backend, HeaderBackend, LockImportRun};
+use sp_blockchain::Backend as _;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, Zero};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use subcoin_primitives::{invalid_block_key, BackendExt};
+
+/// Value of the invalid block flag.
+const INVALID: &[u8] = &[1];
+
+/// Invalid block error.
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidBlockError {
+    #[error("Block {0} not found")]
+    UnknownBlock(BlockHash),
+    #[error("Genesis block cannot be invalidated")]
+    Genesis,
+    #[error("Finalized block {0} cannot be invalidated")]
+    Finalized(BlockHash),
+    #[error(transparent)]
+    Client(#[from] sp_blockchain::Error),
+}
+
+/// Returns `true` if the block has been marked invalid.
+pub fn is_invalid_block<Client: AuxStore>(client: &Client, block_hash: BlockHash) -> bool {
+    client
+        .get_aux(&invalid_block_key(block_hash))
+        .ok()
+        .flatten()
+        .is_some()
+}
+
+/// Marks the given blocks invalid.
+pub(crate) fn mark_invalid_blocks<Client: AuxStore>(
+    client: &Client,
+    block_hashes: impl IntoIterator<Item = BlockHash>,
+) -> sp_blockchain::Result<()> {
+    let keys = block_hashes
+        .into_iter()
+        .map(invalid_block_key)
+        .collect::<Vec<_>>();
+    let insert = keys
+        .iter()
+        .map(|key| (key.as_slice(), INVALID))
+        .collect::<Vec<_>>();
+    client.insert_aux(&insert, &[])
+}
+
+/// Manages the blocks invalidated manually.
+pub struct InvalidBlocks<Block, Client, BE> {
+    client: Arc<Client>,
+    backend: Arc<BE>,
+    _phantom: PhantomData<Block>,
+}
+
+impl<Block, Client, BE> Clone for InvalidBlocks<Block, Client, BE> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            backend: self.backend.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<Block, Client, BE> InvalidBlocks<Block, Client, BE>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block> + AuxStore + LockImportRun<Block, BE>,
+{
+    /// Constructs a new instance of [`InvalidBlocks`].
+    pub fn new(client: Arc<Client>, backend: Arc<BE>) -> Self {
+        Self {
+            client,
+            backend,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Marks the block and its descendants invalid.
+    ///
+    /// Returns the new best block hash.
+    pub fn invalidate_block(&self, block_hash: BlockHash) -> Result<BlockHash, InvalidBlockError> {
+        let substrate_block_hash = self
+            .client
+            .substrate_block_hash_for(block_hash)
+            .ok_or(InvalidBlockError::UnknownBlock(block_hash))?;
+
+        let block_number = self
+            .client
+            .number(substrate_block_hash)?
+            .ok_or(InvalidBlockError::UnknownBlock(block_hash))?;
+
+        if block_number.is_zero() {
+            return Err(InvalidBlockError::Genesis);
+        }
+
+        if block_number <= self.client.info().finalized_number {
+            return Err(InvalidBlockError::Finalized(block_hash));
+        }
+
+        let invalid_blocks = self.with_descendants(substrate_block_hash)?;
+
+        tracing::info!(
+            "Invalidating block {block_hash} and {} descendants",
+            invalid_blocks.len() - 1
+        );
+
+        mark_invalid_blocks(&*self.client, invalid_blocks)?;
+
+        self.select_best_chain()
+    }
+
+    /// Removes the invalid mark of the block, its descendants and ancestors.
+    ///
+    /// Returns the new best block hash.
+    pub fn reconsider_block(&self, block_hash: BlockHash) -> Result<BlockHash, InvalidBlockError> {
+        let mut reconsidered = vec![block_hash];
+
+        // The block may have been marked invalid without being imported.
+        if let Some(substrate_block_hash) = self.client.substrate_block_hash_for(block_hash) {
+            reconsidered = self.with_descendants(substrate_block_hash)?;
+
+            let finalized_number = self.client.info().finalized_number;
+            let mut parent_hash = self.parent_hash(substrate_block_hash)?;
+
+            while let Some((substrate_hash, number)) = parent_hash {
+                let Some(bitcoin_block_hash) = self.client.bitcoin_block_hash_for(substrate_hash)
+                else {
+                    break;
+                };
+
+                if number <= finalized_number
+                    || !is_invalid_block(&*self.client, bitcoin_block_hash)
+                {
+                    break;
+                }
+
+                reconsidered.push(bitcoin_block_hash);
+                parent_hash = self.parent_hash(substrate_hash)?;
+            }
+        }
+
+        tracing::info!(
+            "Reconsidering {} blocks: {reconsidered:?}",
+            reconsidered.len()
+        );
+
+        let keys = reconsidered
+            .into_iter()
+            .map(invalid_block_key)
+            .collect::<Vec<_>>();
+        let delete = keys.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        self.client.insert_aux(&[], &delete)?;
+
+        self.select_best_chain()
+    }
+
+    /// Returns the hash and number of the parent block.
+    fn parent_hash(
+        &self,
+        substrate_block_hash: Block::Hash,
+    ) -> sp_blockchain::Result<Option<(Block::Hash, <Block::Header as HeaderT>::Number)>> {
+        let Some(header) = self.client.header(substrate_block_hash)? else {
+            return Ok(None);
+        };
+
+        if header.number().is_zero() {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            *header.parent_hash(),
+            *header.number() - 1u32.into(),
+        )))
+    }
+
+    /// Returns the Bitcoin block hashes of the block and all its descendants in the database.
+    fn with_descendants(
+        &self,
+        substrate_block_hash: Block::Hash,
+    ) -> sp_blockchain::Result<Vec<BlockHash>> {
+        let mut queue = VecDeque::from([substrate_block_hash]);
+        let mut block_hashes = Vec::new();
+
+        while let Some(hash) = queue.pop_front() {
+            if let Some(bitcoin_block_hash) = self.client.bitcoin_block_hash_for(hash) {
+                block_hashes.push(bitcoin_block_hash);
+            }
+            queue.extend(self.backend.blockchain().children(hash)?);
+        }
+
+        Ok(block_hashes)
+    }
+
+    /// Returns the most recent block of the chain ending at `leaf` which is not marked invalid.
+    fn best_valid_ancestor(
+        &self,
+        leaf: Block::Hash,
+    ) -> sp_blockchain::Result<Option<(Block::Hash, BlockHash)>> {
+        let mut hash = leaf;
+
+        loop {
+            let Some(bitcoin_block_hash) = self.client.bitcoin_block_hash_for(hash) else {
+                return Ok(None);
+            };
+
+            if !is_invalid_block(&*self.client, bitcoin_block_hash) {
+                return Ok(Some((hash, bitcoin_block_hash)));
+            }
+
+            match self.parent_hash(hash)? {
+                Some((parent_hash, _)) => hash = parent_hash,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Sets the best block to the valid tip with the most work.
+    fn select_best_chain(&self) -> Result<BlockHash, InvalidBlockError> {
+        let best_hash = self.client.info().best_hash;
+
+        let mut best: Option<(Work, Block::Hash, BlockHash)> = None;
+
+        for leaf in self.backend.blockchain().leaves()? {
+            let Some((hash, bitcoin_block_hash)) = self.best_valid_ancestor(leaf)? else {
+                continue;
+            };
+
+            let Some(chain_work) = self.client.chain_work(bitcoin_block_hash) else {
+                continue;
+            };
+
+            // The current best block wins in case of equal work.
+            let is_better = best.map_or(true, |(best_work, _, _)| {
+                chain_work > best_work || (chain_work == best_work && hash == best_hash)
+            });
+
+            if is_better {
+                best.replace((chain_work, hash, bitcoin_block_hash));
+            }
+        }
+
+        let (_, new_best_hash, new_best_bitcoin_hash) = best
+            .ok_or_else(|| sp_blockchain::Error::Backend("No valid chain tip found".to_string()))?;
+
+        if new_best_hash != best_hash {
+            self.client
+                .lock_import_and_run(|operation| operation.op.mark_head(new_best_hash))?;
+
+            tracing::info!("Best block switched to {new_best_bitcoin_hash}");
+        }
+
+        Ok(new_best_bitcoin_hash)
+    }
+}
@@ -1,22 +1,64 @@
+mod adjusted_time;
+mod block_builder;
 mod block_executor;
 mod block_import;
-mod chain_params;
+mod block_replay;
+mod chain_reorg;
+mod coin_prefetch;
+mod differential;
+mod finality_conflict;
 mod import_queue;
+mod import_record;
+mod invalid_blocks;
 mod metrics;
+mod state_root_audit;
+mod state_root_diagnostics;
+mod utxo_diff;
 mod verification;
 
+pub use adjusted_time::{adjusted_time, set_time_offset, time_offset};
+pub use block_builder::BitcoinBlockBuilder;
 pub use block_executor::{
-    BenchmarkAllExecutor, BenchmarkRuntimeBlockExecutor, BlockExecutionStrategy, BlockExecutor,
-    ClientContext, ExecutionBackend, OffRuntimeBlockExecutor, RuntimeBlockExecutor,
+    AdaptiveBlockExecutor, BenchmarkAllExecutor, BenchmarkRuntimeBlockExecutor,
+    BlockExecutionStrategy, BlockExecutor, ClientContext, ExecutionBackend, ExecutionInfo,
+    OffRuntimeBlockExecutor, RuntimeBlockExecutor,
 };
 pub use block_import::{
     insert_bitcoin_block_hash_mapping, BitcoinBlockImport, BitcoinBlockImporter, ImportConfig,
     ImportStatus,
 };
+pub use block_replay::{
+    BlockReplay, BlockReplayer, CoinRead, CoinSource, ExecutionTrace, ReplayError, ScriptTrace,
+    TransactionTrace,
+};
+pub use chain_reorg::{chain_reorg_stream, ChainReorg, ReorgBlock};
+pub use differential::{
+    DifferentialError, Divergence, DivergenceReport, ReferenceBlock, ReferenceNode, UtxoChange,
+    UtxoDiff, UtxoEntry, UtxoMismatch,
+};
+pub use finality_conflict::FinalityConflict;
 pub use import_queue::{
-    bitcoin_import_queue, BlockImportQueue, ImportBlocks, ImportManyBlocksResult,
+    bitcoin_import_queue, disabled_import_queue, BlockImportQueue, ImportBlocks,
+    ImportManyBlocksResult, LocalBlockImport,
+};
+pub use invalid_blocks::{is_invalid_block, InvalidBlockError, InvalidBlocks};
+pub use state_root_audit::{AuditMismatch, StateRootAudit, StateRootAuditStatus, StateRootAuditor};
+pub use state_root_diagnostics::{
+    diagnose_state_root_mismatch, ConflictingKey, KeyChange, StateRootMismatch,
+};
+#[cfg(feature = "bitcoinconsensus")]
+pub use subcoin_consensus_verification::CoreInterpreter;
+pub use subcoin_consensus_verification::{
+    ChainParams, Error as ConsensusError, ScriptError, ScriptFamily, ScriptInterpreter,
+    ScriptInterpreters, TxError,
+};
+pub use utxo_diff::{utxo_set_diff, UtxoSetDiff, UtxoSetDiffError};
+pub use verification::{
+    block_verification_failure, BlockTemplate, BlockVerification, BlockVerificationError,
+    BlockVerifier, Error as VerificationError, HeaderError, HeaderProvider, HeaderVerifier,
+    PackageAcceptance, PackageError, PackageTransaction, PackageTransactionStatus,
+    TemplateTransaction, MAX_PACKAGE_COUNT, MIN_RELAY_FEE_RATE,
 };
-pub use verification::{BlockVerification, BlockVerifier, HeaderVerifier};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
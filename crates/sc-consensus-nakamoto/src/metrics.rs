@@ -1,9 +1,12 @@
+use substrate_prometheus_endpoint::prometheus::{IntCounter, IntCounterVec};
 use substrate_prometheus_endpoint::{register, GaugeVec, Opts, PrometheusError, Registry, U64};
 
 pub struct Metrics {
     block_execution_time: GaugeVec<U64>,
     block_transactions_count: GaugeVec<U64>,
     block_size: GaugeVec<U64>,
+    block_verification_failures: IntCounterVec,
+    finality_conflicts: IntCounter,
 }
 
 impl Metrics {
@@ -36,6 +39,23 @@ impl Metrics {
                 )?,
                 registry,
             )?,
+            block_verification_failures: register(
+                IntCounterVec::new(
+                    Opts::new(
+                        "subcoin_block_verification_failures_total",
+                        "Number of blocks failing the verification",
+                    ),
+                    &["reason"],
+                )?,
+                registry,
+            )?,
+            finality_conflicts: register(
+                IntCounter::new(
+                    "subcoin_finality_conflicts_total",
+                    "Number of the forks detected below the finalized block",
+                )?,
+                registry,
+            )?,
         })
     }
 
@@ -57,4 +77,14 @@ impl Metrics {
             .with_label_values(&[&block_height])
             .set(execution_time as u64);
     }
+
+    pub fn report_verification_failure(&self, reason: &str) {
+        self.block_verification_failures
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    pub fn report_finality_conflict(&self) {
+        self.finality_conflicts.inc();
+    }
 }
@@ -0,0 +1,339 @@
+//! Background audit of the state roots computed by the off-runtime execution.
+//!
+//! The off-runtime executor bypasses the runtime, the state roots of the imported blocks are
+//! therefore never checked against the runtime during the sync. [`StateRootAuditor`] catches up
+//! lazily on the finalized blocks, re-executing every `sample_rate`-th block with the runtime on
+//! top of its parent state and comparing the state root with the imported header. The progress
+//! is persisted in the aux-db so that an audit resumes across restarts.
+//!
+//! The parent state of an audited block must still be available, the blocks whose parent state
+//! has been pruned are skipped. Running the node with the archive state pruning enables the
+//! audit of the whole chain.
+
+use crate::block_executor::BlockExecutor;
+use bitcoin::BlockHash;
+use codec::{Decode, Encode};
+use sc_client_api::{AuxStore, BlockBackend, HeaderBackend};
+use serde::{Deserialize, Serialize};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_runtime::SaturatedConversion;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use subcoin_primitives::extract_bitcoin_block_hash;
+use substrate_prometheus_endpoint::{register, Counter, Gauge, PrometheusError, Registry, U64};
+
+/// Key of the highest audited block number in the aux-db.
+const AUDITED_NUMBER_KEY: &[u8] = b"state_root_audit_number";
+
+/// Interval between two checks for new finalized blocks once the audit has caught up.
+const IDLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of audited blocks between two writes of the progress to the aux-db.
+const PERSIST_INTERVAL: u64 = 100;
+
+/// Maximum number of mismatches kept in [`StateRootAuditStatus`].
+const MAX_REPORTED_MISMATCHES: usize = 100;
+
+/// State root mismatch found by the audit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditMismatch<Hash> {
+    pub block_number: u32,
+    pub block_hash: BlockHash,
+    /// State root in the imported header.
+    pub imported_state_root: Hash,
+    /// State root computed by the runtime.
+    pub state_root: Hash,
+}
+
+/// Progress of the state root audit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateRootAuditStatus<Hash> {
+    /// Every `sample_rate`-th block is audited.
+    pub sample_rate: u32,
+    /// Highest block number audited or skipped.
+    pub audited_number: u32,
+    /// Highest finalized block number, the audit is complete once reached.
+    pub finalized_number: u32,
+    /// Number of blocks audited since the node started.
+    pub audited_blocks: u64,
+    /// Number of blocks skipped since the node started as their parent state is unavailable.
+    pub skipped_blocks: u64,
+    /// The latest mismatches found since the node started.
+    pub mismatches: Vec<AuditMismatch<Hash>>,
+}
+
+/// Shared handle of the audit progress.
+#[derive(Debug, Clone)]
+pub struct StateRootAudit<Hash> {
+    status: Arc<Mutex<StateRootAuditStatus<Hash>>>,
+}
+
+impl<Hash: Clone> StateRootAudit<Hash> {
+    /// Returns the current progress of the audit.
+    pub fn status(&self) -> StateRootAuditStatus<Hash> {
+        self.status
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut StateRootAuditStatus<Hash>)) {
+        f(&mut self
+            .status
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+struct Metrics {
+    audited_number: Gauge<U64>,
+    audited_blocks: Counter<U64>,
+    skipped_blocks: Counter<U64>,
+    mismatches: Counter<U64>,
+}
+
+impl Metrics {
+    fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+        Ok(Self {
+            audited_number: register(
+                Gauge::new(
+                    "subcoin_state_root_audit_number",
+                    "Highest block number audited or skipped by the state root audit",
+                )?,
+                registry,
+            )?,
+            audited_blocks: register(
+                Counter::new(
+                    "subcoin_state_root_audit_blocks_total",
+                    "Number of blocks audited",
+                )?,
+                registry,
+            )?,
+            skipped_blocks: register(
+                Counter::new(
+                    "subcoin_state_root_audit_skipped_blocks_total",
+                    "Number of blocks skipped as their parent state is unavailable",
+                )?,
+                registry,
+            )?,
+            mismatches: register(
+                Counter::new(
+                    "subcoin_state_root_audit_mismatches_total",
+                    "Number of state root mismatches found by the audit",
+                )?,
+                registry,
+            )?,
+        })
+    }
+}
+
+/// Outcome of auditing a block.
+enum Audit<Hash> {
+    Verified,
+    Mismatch(AuditMismatch<Hash>),
+    Skipped(sp_blockchain::Error),
+}
+
+/// Re-executes the finalized blocks with the runtime in the background.
+pub struct StateRootAuditor<Block: BlockT, Client> {
+    client: Arc<Client>,
+    executor: Box<dyn BlockExecutor<Block>>,
+    sample_rate: u32,
+    audit: StateRootAudit<Block::Hash>,
+    metrics: Option<Metrics>,
+}
+
+impl<Block, Client> StateRootAuditor<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + BlockBackend<Block> + AuxStore,
+{
+    /// Constructs a new instance of [`StateRootAuditor`].
+    ///
+    /// `executor` must execute the blocks with the runtime on the disk backend, every
+    /// `sample_rate`-th block is audited, all the blocks if `1`.
+    pub fn new(
+        client: Arc<Client>,
+        executor: Box<dyn BlockExecutor<Block>>,
+        sample_rate: u32,
+        registry: Option<&Registry>,
+    ) -> Self {
+        let audited_number = client
+            .get_aux(AUDITED_NUMBER_KEY)
+            .ok()
+            .flatten()
+            .and_then(|encoded| u32::decode(&mut encoded.as_slice()).ok())
+            .unwrap_or(0);
+
+        let metrics = registry.and_then(|registry| {
+            Metrics::register(registry)
+                .map_err(|err| tracing::error!("Failed to register metrics: {err:?}"))
+                .ok()
+        });
+
+        if let Some(metrics) = &metrics {
+            metrics.audited_number.set(audited_number.into());
+        }
+
+        let audit = StateRootAudit {
+            status: Arc::new(Mutex::new(StateRootAuditStatus {
+                sample_rate: sample_rate.max(1),
+                audited_number,
+                finalized_number: client.info().finalized_number.saturated_into(),
+                audited_blocks: 0,
+                skipped_blocks: 0,
+                mismatches: Vec::new(),
+            })),
+        };
+
+        Self {
+            client,
+            executor,
+            sample_rate: sample_rate.max(1),
+            audit,
+            metrics,
+        }
+    }
+
+    /// Returns the handle of the audit progress.
+    pub fn audit(&self) -> StateRootAudit<Block::Hash> {
+        self.audit.clone()
+    }
+
+    /// Runs the audit forever.
+    pub async fn run(self) {
+        let mut audited_number = self.audit.status().audited_number;
+        let mut unpersisted = 0;
+
+        tracing::info!(
+            "Starting the state root audit of every {} block(s) from #{audited_number}",
+            self.sample_rate
+        );
+
+        loop {
+            let finalized_number: u32 = self.client.info().finalized_number.saturated_into();
+
+            self.audit
+                .update(|status| status.finalized_number = finalized_number);
+
+            let next = audited_number.saturating_add(self.sample_rate);
+
+            if next > finalized_number {
+                if unpersisted > 0 {
+                    self.persist(audited_number);
+                    unpersisted = 0;
+                }
+                tokio::time::sleep(IDLE_INTERVAL).await;
+                continue;
+            }
+
+            match self.audit_block(next) {
+                Ok(audit) => self.report(audit),
+                Err(err) => {
+                    tracing::error!("Failed to audit block #{next}: {err:?}");
+                    tokio::time::sleep(IDLE_INTERVAL).await;
+                    continue;
+                }
+            }
+
+            audited_number = next;
+
+            self.audit
+                .update(|status| status.audited_number = audited_number);
+
+            if let Some(metrics) = &self.metrics {
+                metrics.audited_number.set(audited_number.into());
+            }
+
+            unpersisted += 1;
+            if unpersisted >= PERSIST_INTERVAL {
+                self.persist(audited_number);
+                unpersisted = 0;
+            }
+        }
+    }
+
+    fn audit_block(&self, block_number: u32) -> sp_blockchain::Result<Audit<Block::Hash>> {
+        let block_hash = self.client.hash(block_number.into())?.ok_or_else(|| {
+            sp_blockchain::Error::UnknownBlock(format!("Missing block #{block_number}"))
+        })?;
+
+        let block = self
+            .client
+            .block(block_hash)?
+            .ok_or_else(|| {
+                sp_blockchain::Error::UnknownBlock(format!("Missing body of block #{block_number}"))
+            })?
+            .block;
+
+        let parent_hash = *block.header().parent_hash();
+        let imported_state_root = *block.header().state_root();
+        let bitcoin_block_hash = extract_bitcoin_block_hash::<Block>(block.header())
+            .map_err(|err| sp_blockchain::Error::Backend(format!("{err:?}")))?;
+
+        let state_root = match self.executor.execute_block(parent_hash, block) {
+            Ok(result) => result.state_root,
+            Err(err) => return Ok(Audit::Skipped(err)),
+        };
+
+        if state_root == imported_state_root {
+            Ok(Audit::Verified)
+        } else {
+            Ok(Audit::Mismatch(AuditMismatch {
+                block_number,
+                block_hash: bitcoin_block_hash,
+                imported_state_root,
+                state_root,
+            }))
+        }
+    }
+
+    fn report(&self, audit: Audit<Block::Hash>) {
+        match audit {
+            Audit::Verified => {
+                self.audit.update(|status| status.audited_blocks += 1);
+                if let Some(metrics) = &self.metrics {
+                    metrics.audited_blocks.inc();
+                }
+            }
+            Audit::Mismatch(mismatch) => {
+                tracing::error!(
+                    "State root mismatch at block #{} {}: imported {}, runtime computed {}",
+                    mismatch.block_number,
+                    mismatch.block_hash,
+                    mismatch.imported_state_root,
+                    mismatch.state_root,
+                );
+                if let Some(metrics) = &self.metrics {
+                    metrics.audited_blocks.inc();
+                    metrics.mismatches.inc();
+                }
+                self.audit.update(|status| {
+                    status.audited_blocks += 1;
+                    if status.mismatches.len() == MAX_REPORTED_MISMATCHES {
+                        status.mismatches.remove(0);
+                    }
+                    status.mismatches.push(mismatch);
+                });
+            }
+            Audit::Skipped(err) => {
+                tracing::debug!("Skipped the state root audit of a block: {err}");
+                self.audit.update(|status| status.skipped_blocks += 1);
+                if let Some(metrics) = &self.metrics {
+                    metrics.skipped_blocks.inc();
+                }
+            }
+        }
+    }
+
+    fn persist(&self, audited_number: u32) {
+        if let Err(err) = self.client.insert_aux(
+            &[(AUDITED_NUMBER_KEY, audited_number.encode().as_slice())],
+            [],
+        ) {
+            tracing::error!("Failed to persist the state root audit progress: {err:?}");
+        }
+    }
+}
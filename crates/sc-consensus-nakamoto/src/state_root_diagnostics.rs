@@ -0,0 +1,224 @@
+//! Diagnostics of the state root mismatches between the block executors.
+//!
+//! When two executors compute different state roots for the same block, the mismatch is
+//! bisected over the extrinsics of the block: the block truncated to its first `n` extrinsics
+//! is executed by both executors, the smallest `n` whose storage changes differ locates the
+//! first divergent extrinsic. The bisection assumes that the executors never converge again
+//! once diverged.
+
+use crate::block_executor::{BlockExecutor, StorageEntry};
+use crate::BlockExecutionStrategy;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, NumberFor};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Change of a storage key made by the block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyChange {
+    /// The key is left untouched.
+    Unchanged,
+    /// The key is removed.
+    Deleted,
+    /// The key is set to the value.
+    Set(Vec<u8>),
+}
+
+impl fmt::Display for KeyChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unchanged => write!(f, "unchanged"),
+            Self::Deleted => write!(f, "deleted"),
+            Self::Set(value) => write!(f, "0x{}", sp_core::hexdisplay::HexDisplay::from(value)),
+        }
+    }
+}
+
+/// Storage key changed differently by the executors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictingKey {
+    pub key: Vec<u8>,
+    /// Change made by the executor producing the expected state root.
+    pub expected: KeyChange,
+    /// Change made by the executor producing the mismatched state root.
+    pub got: KeyChange,
+}
+
+/// State root mismatch between two executors.
+#[derive(Debug)]
+pub struct StateRootMismatch<Block: BlockT> {
+    pub block_number: NumberFor<Block>,
+    pub expected_strategy: BlockExecutionStrategy,
+    pub expected_state_root: Block::Hash,
+    pub strategy: BlockExecutionStrategy,
+    pub state_root: Block::Hash,
+    /// Index of the first extrinsic after which the storage changes diverge.
+    ///
+    /// `None` if the storage changes diverge before any extrinsic is applied, i.e., in the
+    /// initialization or finalization of the block.
+    pub divergent_extrinsic: Option<usize>,
+    /// Keys changed differently once the first divergent extrinsic is applied.
+    pub conflicting_keys: Vec<ConflictingKey>,
+}
+
+impl<Block: BlockT> fmt::Display for StateRootMismatch<Block> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "State root mismatch at block #{}: {:?} computed {}, {:?} computed {}",
+            self.block_number,
+            self.strategy,
+            self.state_root,
+            self.expected_strategy,
+            self.expected_state_root,
+        )?;
+
+        match self.divergent_extrinsic {
+            Some(index) => write!(f, ", first divergent extrinsic: #{index}")?,
+            None => write!(f, ", diverged before any extrinsic")?,
+        }
+
+        for ConflictingKey { key, expected, got } in &self.conflicting_keys {
+            write!(
+                f,
+                "\n  0x{}: {got}, expected {expected}",
+                sp_core::hexdisplay::HexDisplay::from(key)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Block: BlockT> std::error::Error for StateRootMismatch<Block> {}
+
+/// Executes `block` with both executors and bisects the state root mismatch if any.
+///
+/// Returns `None` if both executors compute the same state root.
+pub fn diagnose_state_root_mismatch<Block: BlockT>(
+    parent_hash: Block::Hash,
+    block: Block,
+    expected_executor: &dyn BlockExecutor<Block>,
+    executor: &dyn BlockExecutor<Block>,
+) -> sp_blockchain::Result<Option<StateRootMismatch<Block>>> {
+    let (header, extrinsics) = block.deconstruct();
+
+    let execute = |executor: &dyn BlockExecutor<Block>, extrinsics_count: usize| {
+        executor
+            .execute_block(
+                parent_hash,
+                Block::new(header.clone(), extrinsics[..extrinsics_count].to_vec()),
+            )
+            .map(|result| {
+                (
+                    result.state_root,
+                    result.storage_changes.main_storage_changes,
+                )
+            })
+    };
+
+    let conflicting_keys = |extrinsics_count: usize| -> sp_blockchain::Result<Vec<ConflictingKey>> {
+        let (_, expected_changes) = execute(expected_executor, extrinsics_count)?;
+        let (_, changes) = execute(executor, extrinsics_count)?;
+        Ok(compare_changes(expected_changes, changes))
+    };
+
+    let (expected_state_root, expected_changes) = execute(expected_executor, extrinsics.len())?;
+    let (state_root, changes) = execute(executor, extrinsics.len())?;
+
+    if expected_state_root == state_root {
+        return Ok(None);
+    }
+
+    let mut keys = compare_changes(expected_changes, changes);
+
+    // Smallest number of extrinsics whose storage changes diverge, which is within `low..=high`.
+    // The storage changes of the whole block may only match if the child tries diverge.
+    let mut low = 0;
+    let mut high = if keys.is_empty() { 0 } else { extrinsics.len() };
+
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let mid_keys = conflicting_keys(mid)?;
+        if mid_keys.is_empty() {
+            low = mid + 1;
+        } else {
+            high = mid;
+            keys = mid_keys;
+        }
+    }
+
+    Ok(Some(StateRootMismatch {
+        block_number: *header.number(),
+        expected_strategy: expected_executor.execution_strategy(),
+        expected_state_root,
+        strategy: executor.execution_strategy(),
+        state_root,
+        divergent_extrinsic: low.checked_sub(1),
+        conflicting_keys: keys,
+    }))
+}
+
+fn compare_changes(expected: Vec<StorageEntry>, got: Vec<StorageEntry>) -> Vec<ConflictingKey> {
+    let expected = expected.into_iter().collect::<BTreeMap<_, _>>();
+    let got = got.into_iter().collect::<BTreeMap<_, _>>();
+
+    let key_change =
+        |changes: &BTreeMap<Vec<u8>, Option<Vec<u8>>>, key: &Vec<u8>| match changes.get(key) {
+            None => KeyChange::Unchanged,
+            Some(None) => KeyChange::Deleted,
+            Some(Some(value)) => KeyChange::Set(value.clone()),
+        };
+
+    expected
+        .keys()
+        .chain(got.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|key| expected.get(*key) != got.get(*key))
+        .map(|key| ConflictingKey {
+            key: key.clone(),
+            expected: key_change(&expected, key),
+            got: key_change(&got, key),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_changes() {
+        let expected = vec![
+            (vec![1], Some(vec![1])),
+            (vec![2], None),
+            (vec![3], Some(vec![3])),
+        ];
+        let got = vec![
+            (vec![1], Some(vec![1])),
+            (vec![3], Some(vec![4])),
+            (vec![4], None),
+        ];
+
+        assert_eq!(
+            compare_changes(expected, got),
+            vec![
+                ConflictingKey {
+                    key: vec![2],
+                    expected: KeyChange::Deleted,
+                    got: KeyChange::Unchanged,
+                },
+                ConflictingKey {
+                    key: vec![3],
+                    expected: KeyChange::Set(vec![3]),
+                    got: KeyChange::Set(vec![4]),
+                },
+                ConflictingKey {
+                    key: vec![4],
+                    expected: KeyChange::Unchanged,
+                    got: KeyChange::Deleted,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Difference of the UTXO sets at two blocks.
+//!
+//! The blocks from the first block back to the common ancestor are disconnected and then the
+//! blocks from the common ancestor up to the second block are connected. The coins spent by
+//! each block are read from the state of its parent, which serves as the undo data, so the
+//! parent states of all the blocks involved must not have been pruned.
+
+use bitcoin::{Block as BitcoinBlock, BlockHash, OutPoint, Transaction, TxOut};
+use codec::Decode;
+use sc_client_api::{AuxStore, Backend, BlockBackend, HeaderBackend, StorageProvider};
+use sp_blockchain::HeaderMetadata;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, SaturatedConversion};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{
+    convert_to_bitcoin_block, BackendExt, BitcoinTransactionAdapter, CoinStorageKey, HeaderError,
+};
+
+/// UTXO diff error.
+#[derive(Debug, thiserror::Error)]
+pub enum UtxoSetDiffError {
+    #[error("Block {0} not found")]
+    UnknownBlock(BlockHash),
+    #[error("Body of block {0} not found, it may have been pruned")]
+    MissingBody(BlockHash),
+    #[error("Coin {0} spent by block {1} not found, the parent state may have been pruned")]
+    MissingCoin(OutPoint, BlockHash),
+    #[error("Invalid Bitcoin header: {0:?}")]
+    Header(HeaderError),
+    #[error(transparent)]
+    Client(#[from] sp_blockchain::Error),
+}
+
+/// Coins created and spent between two blocks.
+#[derive(Debug, Clone)]
+pub struct UtxoSetDiff {
+    pub common_ancestor: BlockHash,
+    /// Blocks disconnected from the first block, ordered from the first block.
+    pub disconnected: Vec<BlockHash>,
+    /// Blocks connected from the common ancestor, ending with the second block.
+    pub connected: Vec<BlockHash>,
+    /// Coins in the UTXO set of the second block but not of the first one.
+    pub created: Vec<(OutPoint, Coin)>,
+    /// Coins in the UTXO set of the first block but not of the second one.
+    pub spent: Vec<(OutPoint, Coin)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Change {
+    Created(Coin),
+    Spent(Coin),
+}
+
+/// Net changes of the UTXO set, a coin created and spent afterwards cancels out.
+#[derive(Debug, Default)]
+struct UtxoChanges(BTreeMap<OutPoint, Change>);
+
+fn new_coin(tx: &Transaction, txout: &TxOut, height: u32) -> Coin {
+    Coin {
+        is_coinbase: tx.is_coinbase(),
+        amount: txout.value.to_sat(),
+        script_pubkey: txout.script_pubkey.to_bytes(),
+        height,
+    }
+}
+
+impl UtxoChanges {
+    fn add(&mut self, out_point: OutPoint, coin: Coin) {
+        if let Some(Change::Spent(_)) = self.0.get(&out_point) {
+            self.0.remove(&out_point);
+        } else {
+            self.0.insert(out_point, Change::Created(coin));
+        }
+    }
+
+    /// Returns `false` if the coin was neither added earlier nor found by `find_coin`.
+    fn spend(&mut self, out_point: OutPoint, find_coin: impl FnOnce() -> Option<Coin>) -> bool {
+        if let Some(Change::Created(_)) = self.0.get(&out_point) {
+            self.0.remove(&out_point);
+            return true;
+        }
+
+        match find_coin() {
+            Some(coin) => {
+                self.0.insert(out_point, Change::Spent(coin));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies the block on top of its parent, returns the missing coin on error.
+    fn connect_block(
+        &mut self,
+        block: &BitcoinBlock,
+        height: u32,
+        parent_coin: impl Fn(&OutPoint) -> Option<Coin>,
+    ) -> Result<(), OutPoint> {
+        for tx in &block.txdata {
+            if !tx.is_coinbase() {
+                for input in &tx.input {
+                    let out_point = input.previous_output;
+                    if !self.spend(out_point, || parent_coin(&out_point)) {
+                        return Err(out_point);
+                    }
+                }
+            }
+
+            let txid = tx.compute_txid();
+
+            for (vout, txout) in tx.output.iter().enumerate() {
+                self.add(
+                    OutPoint::new(txid, vout as u32),
+                    new_coin(tx, txout, height),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reverts the block back to its parent, returns the missing coin on error.
+    fn disconnect_block(
+        &mut self,
+        block: &BitcoinBlock,
+        height: u32,
+        parent_coin: impl Fn(&OutPoint) -> Option<Coin>,
+    ) -> Result<(), OutPoint> {
+        // Coins spent within the block are restored from the block itself.
+        let block_coins = block
+            .txdata
+            .iter()
+            .flat_map(|tx| {
+                let txid = tx.compute_txid();
+                tx.output.iter().enumerate().map(move |(vout, txout)| {
+                    (
+                        OutPoint::new(txid, vout as u32),
+                        new_coin(tx, txout, height),
+                    )
+                })
+            })
+            .collect::<HashMap<_, _>>();
+
+        for tx in block.txdata.iter().rev() {
+            let txid = tx.compute_txid();
+
+            for vout in 0..tx.output.len() {
+                let out_point = OutPoint::new(txid, vout as u32);
+                self.spend(out_point, || block_coins.get(&out_point).cloned());
+            }
+
+            if !tx.is_coinbase() {
+                for input in &tx.input {
+                    let out_point = input.previous_output;
+                    let coin = parent_coin(&out_point)
+                        .or_else(|| block_coins.get(&out_point).cloned())
+                        .ok_or(out_point)?;
+                    self.add(out_point, coin);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn into_diff(self) -> (Vec<(OutPoint, Coin)>, Vec<(OutPoint, Coin)>) {
+        let mut created = Vec::new();
+        let mut spent = Vec::new();
+
+        for (out_point, change) in self.0 {
+            match change {
+                Change::Created(coin) => created.push((out_point, coin)),
+                Change::Spent(coin) => spent.push((out_point, coin)),
+            }
+        }
+
+        (created, spent)
+    }
+}
+
+/// Computes the coins created and spent from block `from` to block `to`, which are not
+/// necessarily on the same chain.
+pub fn utxo_set_diff<Block, Client, BE, TransactionAdapter>(
+    client: &Arc<Client>,
+    coin_storage_key: &dyn CoinStorageKey,
+    from: BlockHash,
+    to: BlockHash,
+) -> Result<UtxoSetDiff, UtxoSetDiffError>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block>
+        + HeaderMetadata<Block, Error = sp_blockchain::Error>
+        + BlockBackend<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore,
+    TransactionAdapter: BitcoinTransactionAdapter<Block>,
+{
+    let substrate_block_hash = |block_hash| {
+        client
+            .substrate_block_hash_for(block_hash)
+            .ok_or(UtxoSetDiffError::UnknownBlock(block_hash))
+    };
+
+    let tree_route = sp_blockchain::tree_route(
+        client.as_ref(),
+        substrate_block_hash(from)?,
+        substrate_block_hash(to)?,
+    )?;
+
+    let bitcoin_block_hash = |substrate_block_hash: Block::Hash| {
+        client
+            .bitcoin_block_hash_for(substrate_block_hash)
+            .ok_or_else(|| {
+                UtxoSetDiffError::Client(sp_blockchain::Error::UnknownBlock(
+                    substrate_block_hash.to_string(),
+                ))
+            })
+    };
+
+    let load_block = |substrate_block_hash: Block::Hash| -> Result<_, UtxoSetDiffError> {
+        let block_hash = bitcoin_block_hash(substrate_block_hash)?;
+
+        let block = client
+            .block(substrate_block_hash)?
+            .ok_or(UtxoSetDiffError::MissingBody(block_hash))?
+            .block;
+
+        let parent_hash = *block.header().parent_hash();
+        let height: u32 = (*block.header().number()).saturated_into();
+
+        let block = convert_to_bitcoin_block::<Block, TransactionAdapter>(block)
+            .map_err(UtxoSetDiffError::Header)?;
+
+        let parent_coin = move |out_point: &OutPoint| {
+            let storage_key = coin_storage_key.storage_key(out_point.txid, out_point.vout);
+            client
+                .storage(parent_hash, &sc_client_api::StorageKey(storage_key))
+                .ok()
+                .flatten()
+                .and_then(|data| Coin::decode(&mut data.0.as_slice()).ok())
+        };
+
+        Ok((block_hash, block, height, parent_coin))
+    };
+
+    let mut changes = UtxoChanges::default();
+    let mut disconnected = Vec::new();
+    let mut connected = Vec::new();
+
+    for retracted in tree_route.retracted() {
+        let (block_hash, block, height, parent_coin) = load_block(retracted.hash)?;
+        changes
+            .disconnect_block(&block, height, parent_coin)
+            .map_err(|out_point| UtxoSetDiffError::MissingCoin(out_point, block_hash))?;
+        disconnected.push(block_hash);
+    }
+
+    for enacted in tree_route.enacted() {
+        let (block_hash, block, height, parent_coin) = load_block(enacted.hash)?;
+        changes
+            .connect_block(&block, height, parent_coin)
+            .map_err(|out_point| UtxoSetDiffError::MissingCoin(out_point, block_hash))?;
+        connected.push(block_hash);
+    }
+
+    let (created, spent) = changes.into_diff();
+
+    Ok(UtxoSetDiff {
+        common_ancestor: bitcoin_block_hash(tree_route.common_block().hash)?,
+        disconnected,
+        connected,
+        created,
+        spent,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, ScriptBuf, TxIn, Txid};
+
+    fn transaction(inputs: &[OutPoint], value: u64) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: if inputs.is_empty() {
+                vec![TxIn::default()]
+            } else {
+                inputs
+                    .iter()
+                    .map(|previous_output| TxIn {
+                        previous_output: *previous_output,
+                        ..Default::default()
+                    })
+                    .collect()
+            },
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn block(txdata: Vec<Transaction>) -> BitcoinBlock {
+        BitcoinBlock {
+            header: bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header,
+            txdata,
+        }
+    }
+
+    #[test]
+    fn test_utxo_changes() {
+        let old_out_point = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        let old_coin = Coin {
+            is_coinbase: false,
+            amount: 1000,
+            script_pubkey: Vec::new(),
+            height: 1,
+        };
+        let parent_coin =
+            |out_point: &OutPoint| (*out_point == old_out_point).then(|| old_coin.clone());
+
+        let coinbase = transaction(&[], 5000);
+        let spend_old = transaction(&[old_out_point], 900);
+        let spend_new = transaction(&[OutPoint::new(spend_old.compute_txid(), 0)], 800);
+        let block = block(vec![coinbase.clone(), spend_old, spend_new.clone()]);
+
+        let mut changes = UtxoChanges::default();
+        changes.connect_block(&block, 2, parent_coin).unwrap();
+        let (created, spent) = changes.into_diff();
+
+        // The output spent within the block cancels out.
+        let created_out_points = created
+            .iter()
+            .map(|(out_point, _)| *out_point)
+            .collect::<Vec<_>>();
+        assert_eq!(created_out_points.len(), 2);
+        assert!(created_out_points.contains(&OutPoint::new(coinbase.compute_txid(), 0)));
+        assert!(created_out_points.contains(&OutPoint::new(spend_new.compute_txid(), 0)));
+        assert_eq!(spent, vec![(old_out_point, old_coin.clone())]);
+
+        // Disconnecting the block after connecting it results in no change.
+        let mut changes = UtxoChanges::default();
+        changes.connect_block(&block, 2, parent_coin).unwrap();
+        changes.disconnect_block(&block, 2, parent_coin).unwrap();
+        assert!(changes.0.is_empty());
+
+        // Disconnecting the block alone restores the old coin.
+        let mut changes = UtxoChanges::default();
+        changes.disconnect_block(&block, 2, parent_coin).unwrap();
+        let (created, spent) = changes.into_diff();
+        assert_eq!(created, vec![(old_out_point, old_coin)]);
+        assert_eq!(spent.len(), 2);
+
+        let mut changes = UtxoChanges::default();
+        assert_eq!(
+            changes.connect_block(&block, 2, |_| None),
+            Err(old_out_point)
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use super::{Error, HeaderError};
+use bitcoin::{OutPoint, Txid};
+use subcoin_consensus_verification::{Error as ConsensusError, TxError};
+
+/// Reason of a block failing the verification, named after the reject reasons in Bitcoin Core.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BlockVerificationError {
+    #[error("bad-diffbits")]
+    BadDiffBits,
+    #[error("high-hash")]
+    HighHash,
+    #[error("time-too-new")]
+    TimeTooNew,
+    #[error("time-too-old")]
+    TimeTooOld,
+    #[error("bad-version")]
+    BadVersion,
+    #[error("bad-txnmrklroot")]
+    BadTxnMrklRoot,
+    #[error("bad-witness-merkle-match")]
+    BadWitnessMerkleMatch,
+    #[error("bad-blk-length")]
+    BadBlkLength,
+    #[error("bad-blk-sigops")]
+    BadBlkSigops,
+    #[error("bad-cb-missing")]
+    BadCbMissing,
+    #[error("bad-cb-multiple")]
+    BadCbMultiple,
+    #[error("bad-cb-height")]
+    BadCbHeight,
+    #[error("bad-cb-length")]
+    BadCbLength,
+    #[error("bad-cb-amount")]
+    BadCbAmount,
+    #[error("bad-txns-nonfinal")]
+    BadTxnsNonFinal,
+    #[error("bad-txns-duplicate")]
+    BadTxnsDuplicate,
+    #[error("bad-txns-BIP30")]
+    BadTxnsBip30,
+    #[error("bad-txns-vin-empty")]
+    BadTxnsVinEmpty,
+    #[error("bad-txns-vout-empty")]
+    BadTxnsVoutEmpty,
+    #[error("bad-txns-oversize")]
+    BadTxnsOversize,
+    #[error("bad-txns-inputs-duplicate")]
+    BadTxnsInputsDuplicate,
+    #[error("bad-txns-vout-toolarge")]
+    BadTxnsVoutTooLarge,
+    #[error("bad-txns-txouttotal-toolarge")]
+    BadTxnsTxoutTotalTooLarge,
+    #[error("bad-txns-prevout-null")]
+    BadTxnsPrevoutNull,
+    #[error("bad-txns-inputs-missingorspent ({out_point} spent by {txid})")]
+    BadTxnsInputsMissingOrSpent { txid: Txid, out_point: OutPoint },
+    #[error("bad-txns-premature-spend-of-coinbase")]
+    BadTxnsPrematureSpendOfCoinbase,
+    #[error("bad-txns-in-belowout")]
+    BadTxnsInBelowOut,
+    #[error("block-script-verify-flag-failed (input {input_index} of {txid}: {error})")]
+    ScriptVerifyFailed {
+        txid: Txid,
+        input_index: usize,
+        error: String,
+    },
+}
+
+impl BlockVerificationError {
+    /// Returns the reject reason without the details, e.g., for labelling the metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::BadDiffBits => "bad-diffbits",
+            Self::HighHash => "high-hash",
+            Self::TimeTooNew => "time-too-new",
+            Self::TimeTooOld => "time-too-old",
+            Self::BadVersion => "bad-version",
+            Self::BadTxnMrklRoot => "bad-txnmrklroot",
+            Self::BadWitnessMerkleMatch => "bad-witness-merkle-match",
+            Self::BadBlkLength => "bad-blk-length",
+            Self::BadBlkSigops => "bad-blk-sigops",
+            Self::BadCbMissing => "bad-cb-missing",
+            Self::BadCbMultiple => "bad-cb-multiple",
+            Self::BadCbHeight => "bad-cb-height",
+            Self::BadCbLength => "bad-cb-length",
+            Self::BadCbAmount => "bad-cb-amount",
+            Self::BadTxnsNonFinal => "bad-txns-nonfinal",
+            Self::BadTxnsDuplicate => "bad-txns-duplicate",
+            Self::BadTxnsBip30 => "bad-txns-BIP30",
+            Self::BadTxnsVinEmpty => "bad-txns-vin-empty",
+            Self::BadTxnsVoutEmpty => "bad-txns-vout-empty",
+            Self::BadTxnsOversize => "bad-txns-oversize",
+            Self::BadTxnsInputsDuplicate => "bad-txns-inputs-duplicate",
+            Self::BadTxnsVoutTooLarge => "bad-txns-vout-toolarge",
+            Self::BadTxnsTxoutTotalTooLarge => "bad-txns-txouttotal-toolarge",
+            Self::BadTxnsPrevoutNull => "bad-txns-prevout-null",
+            Self::BadTxnsInputsMissingOrSpent { .. } => "bad-txns-inputs-missingorspent",
+            Self::BadTxnsPrematureSpendOfCoinbase => "bad-txns-premature-spend-of-coinbase",
+            Self::BadTxnsInBelowOut => "bad-txns-in-belowout",
+            Self::ScriptVerifyFailed { .. } => "block-script-verify-flag-failed",
+        }
+    }
+}
+
+impl Error {
+    /// Returns the reason of the block failing the verification, `None` if the failure is not
+    /// caused by the block, e.g., an error in the client.
+    pub fn block_verification_error(&self) -> Option<BlockVerificationError> {
+        let err = match self {
+            Self::Header(err) => match err {
+                HeaderError::BadDifficultyBits { .. } => BlockVerificationError::BadDiffBits,
+                HeaderError::InvalidProofOfWork(_) => BlockVerificationError::HighHash,
+                HeaderError::TooFarInFuture => BlockVerificationError::TimeTooNew,
+                HeaderError::TimeTooOld => BlockVerificationError::TimeTooOld,
+                HeaderError::BadVersion => BlockVerificationError::BadVersion,
+                HeaderError::Client(_) => return None,
+            },
+            Self::Consensus(err) => match err {
+                ConsensusError::BadMerkleRoot => BlockVerificationError::BadTxnMrklRoot,
+                ConsensusError::BadWitnessCommitment => {
+                    BlockVerificationError::BadWitnessMerkleMatch
+                }
+                ConsensusError::EmptyTransactionList | ConsensusError::BadBlockLength => {
+                    BlockVerificationError::BadBlkLength
+                }
+                ConsensusError::TooManySigOps { .. } => BlockVerificationError::BadBlkSigops,
+                ConsensusError::FirstTransactionIsNotCoinbase => {
+                    BlockVerificationError::BadCbMissing
+                }
+                ConsensusError::MultipleCoinbase | ConsensusError::UnexpectedCoinbase => {
+                    BlockVerificationError::BadCbMultiple
+                }
+                ConsensusError::BadCoinbaseBlockHeight { .. } | ConsensusError::Bip34(_) => {
+                    BlockVerificationError::BadCbHeight
+                }
+                ConsensusError::InvalidBlockReward => BlockVerificationError::BadCbAmount,
+                ConsensusError::TransactionNotFinal => BlockVerificationError::BadTxnsNonFinal,
+                ConsensusError::DuplicateTransaction(_) => BlockVerificationError::BadTxnsDuplicate,
+                ConsensusError::OverwriteUnspentOutput { .. } => {
+                    BlockVerificationError::BadTxnsBip30
+                }
+                ConsensusError::UtxoNotFound { txid, utxo, .. }
+                | ConsensusError::AlreadySpentInCurrentBlock { txid, utxo, .. } => {
+                    BlockVerificationError::BadTxnsInputsMissingOrSpent {
+                        txid: *txid,
+                        out_point: *utxo,
+                    }
+                }
+                ConsensusError::PrematureSpendOfCoinbase => {
+                    BlockVerificationError::BadTxnsPrematureSpendOfCoinbase
+                }
+                ConsensusError::InsufficientFunds { .. } => {
+                    BlockVerificationError::BadTxnsInBelowOut
+                }
+                ConsensusError::Transaction(err) => match err {
+                    TxError::EmptyInput => BlockVerificationError::BadTxnsVinEmpty,
+                    TxError::EmptyOutput => BlockVerificationError::BadTxnsVoutEmpty,
+                    TxError::TransactionOversize => BlockVerificationError::BadTxnsOversize,
+                    TxError::DuplicateTxInput(_) => BlockVerificationError::BadTxnsInputsDuplicate,
+                    TxError::OutputValueTooLarge(_) => BlockVerificationError::BadTxnsVoutTooLarge,
+                    TxError::TotalOutputValueTooLarge(_) => {
+                        BlockVerificationError::BadTxnsTxoutTotalTooLarge
+                    }
+                    TxError::BadCoinbaseLength(_) => BlockVerificationError::BadCbLength,
+                    TxError::PreviousOutputNull => BlockVerificationError::BadTxnsPrevoutNull,
+                },
+                ConsensusError::InvalidInputScript {
+                    txid,
+                    input_index,
+                    error,
+                    ..
+                } => BlockVerificationError::ScriptVerifyFailed {
+                    txid: *txid,
+                    input_index: *input_index,
+                    error: error.to_string(),
+                },
+                ConsensusError::Script(_) | ConsensusError::BitcoinCodec(_) => return None,
+            },
+            Self::Client(_) => return None,
+        };
+
+        Some(err)
+    }
+}
+
+/// Returns the reason of the block failing the verification if the import failed due to it.
+pub fn block_verification_failure(err: &sp_consensus::Error) -> Option<&BlockVerificationError> {
+    match err {
+        sp_consensus::Error::Other(err) => err.downcast_ref::<BlockVerificationError>(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_block_verification_error() {
+        let err = Error::Header(HeaderError::TimeTooOld);
+        assert_eq!(
+            err.block_verification_error(),
+            Some(BlockVerificationError::TimeTooOld)
+        );
+
+        let txid = Txid::from_byte_array([1; 32]);
+        let err = Error::Consensus(ConsensusError::UtxoNotFound {
+            block_number: 100,
+            txid,
+            utxo: OutPoint::new(txid, 1),
+        });
+        let reason = err.block_verification_error().unwrap();
+        assert_eq!(reason.reason(), "bad-txns-inputs-missingorspent");
+        assert!(reason.to_string().starts_with(reason.reason()));
+
+        let err = Error::Client(sp_blockchain::Error::Backend("io".into()));
+        assert_eq!(err.block_verification_error(), None);
+    }
+}
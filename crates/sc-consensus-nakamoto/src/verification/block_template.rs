@@ -0,0 +1,513 @@
+//! Assembling the candidate block on top of the best block from the unconfirmed transactions.
+//!
+//! The transactions are selected in packages by the ancestor fee rate, similar to the
+//! `BlockAssembler` in Bitcoin Core, so that a transaction with a high fee pulls in its low
+//! fee ancestors (CPFP).
+//!
+//! <https://github.com/bitcoin/bitcoin/blob/6f9db1ebcab4064065ccd787161bf2b87e03cc1f/src/node/miner.cpp>
+
+use super::{BlockVerifier, Error};
+use bitcoin::block::Version;
+use bitcoin::blockdata::constants::MAX_BLOCK_SIGOPS_COST;
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    Amount, Block as BitcoinBlock, BlockHash, CompactTarget, OutPoint, ScriptBuf, Target,
+    Transaction, TxOut, Txid, WitnessMerkleNode, Wtxid,
+};
+use sc_client_api::{AuxStore, Backend, StorageProvider};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use subcoin_consensus_verification::{DeploymentState, MAX_BLOCK_WEIGHT};
+use subcoin_primitives::runtime::{block_subsidy, Coin};
+
+/// Weight reserved for the block header and the coinbase transaction.
+const COINBASE_RESERVED_WEIGHT: u64 = 4000;
+
+/// Sigops cost reserved for the coinbase transaction.
+const COINBASE_RESERVED_SIGOPS: u64 = 400;
+
+/// Version of the template, the BIP9 top bits without signalling any deployment.
+const TEMPLATE_VERSION: i32 = 0x2000_0000;
+
+/// Header of the witness commitment in the coinbase output, BIP141.
+const WITNESS_COMMITMENT_HEADER: [u8; 4] = [0xaa, 0x21, 0xa9, 0xed];
+
+/// Transaction selected into the block template.
+#[derive(Debug, Clone)]
+pub struct TemplateTransaction {
+    pub transaction: Transaction,
+    pub txid: Txid,
+    pub wtxid: Wtxid,
+    /// Transaction fee in satoshis.
+    pub fee: u64,
+    /// Sigops cost of the transaction.
+    pub sigops: u64,
+    /// Weight of the transaction.
+    pub weight: u64,
+    /// Indices of the transactions in the template this transaction spends.
+    pub depends: Vec<usize>,
+}
+
+/// Candidate block on top of the best block, the coinbase is left to the miner.
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub version: Version,
+    pub previous_block_hash: BlockHash,
+    pub height: u32,
+    /// Transactions in the block order, excluding the coinbase.
+    pub transactions: Vec<TemplateTransaction>,
+    /// Maximum total value of the coinbase outputs, i.e., block subsidy plus the fees.
+    pub coinbase_value: u64,
+    pub bits: CompactTarget,
+    pub target: Target,
+    /// Minimum timestamp of the block.
+    pub min_time: u32,
+    /// Current timestamp, never earlier than `min_time`.
+    pub cur_time: u32,
+    /// Maximum weight of the block.
+    pub weight_limit: u64,
+    /// Maximum sigops cost of the block.
+    pub sigop_limit: u64,
+    /// Witness commitment output of the coinbase, present once segwit is active.
+    pub default_witness_commitment: Option<ScriptBuf>,
+}
+
+/// Unconfirmed transaction eligible for the block.
+#[derive(Debug, Clone)]
+struct MempoolEntry {
+    transaction: Transaction,
+    txid: Txid,
+    fee: u64,
+    weight: u64,
+    sigops: u64,
+    /// Indices of the unconfirmed transactions this transaction spends.
+    parents: Vec<usize>,
+}
+
+/// Fee, weight and sigops cost of a package.
+#[derive(Debug, Clone, Copy, Default)]
+struct PackageStats {
+    fee: u64,
+    weight: u64,
+    sigops: u64,
+}
+
+impl PackageStats {
+    fn add(&mut self, entry: &MempoolEntry) {
+        self.fee += entry.fee;
+        self.weight += entry.weight;
+        self.sigops += entry.sigops;
+    }
+
+    fn cmp_fee_rate(&self, other: &Self) -> Ordering {
+        (u128::from(self.fee) * u128::from(other.weight))
+            .cmp(&(u128::from(other.fee) * u128::from(self.weight)))
+    }
+}
+
+/// Returns the indices of the ancestors of each entry.
+fn compute_ancestors(entries: &[MempoolEntry]) -> Vec<BTreeSet<usize>> {
+    fn visit(
+        index: usize,
+        entries: &[MempoolEntry],
+        ancestors: &mut [Option<BTreeSet<usize>>],
+    ) -> BTreeSet<usize> {
+        if let Some(known) = &ancestors[index] {
+            return known.clone();
+        }
+
+        let mut result = BTreeSet::new();
+        for &parent in &entries[index].parents {
+            result.insert(parent);
+            result.extend(visit(parent, entries, ancestors));
+        }
+
+        ancestors[index] = Some(result.clone());
+        result
+    }
+
+    let mut ancestors = vec![None; entries.len()];
+
+    (0..entries.len())
+        .map(|index| visit(index, entries, &mut ancestors))
+        .collect()
+}
+
+/// Selects the packages with the highest ancestor fee rate until the block is full.
+///
+/// Returns the indices of the selected entries in the block order, the ancestors of an entry
+/// always come before it.
+fn select_packages(entries: &[MempoolEntry], max_weight: u64, max_sigops: u64) -> Vec<usize> {
+    let ancestors = compute_ancestors(entries);
+
+    let mut included = vec![false; entries.len()];
+    let mut failed = vec![false; entries.len()];
+    let mut spent = HashSet::<OutPoint>::new();
+    let mut block = PackageStats::default();
+    let mut selected = Vec::new();
+
+    loop {
+        let best = (0..entries.len())
+            .filter(|&index| !included[index] && !failed[index])
+            .map(|index| {
+                let package = ancestors[index]
+                    .iter()
+                    .copied()
+                    .chain(std::iter::once(index))
+                    .filter(|&member| !included[member])
+                    .collect::<Vec<_>>();
+
+                let mut stats = PackageStats::default();
+                package
+                    .iter()
+                    .for_each(|&member| stats.add(&entries[member]));
+
+                (index, package, stats)
+            })
+            // Prefer the earlier received transaction on the same fee rate.
+            .max_by(|(a_index, _, a), (b_index, _, b)| {
+                a.cmp_fee_rate(b).then_with(|| b_index.cmp(a_index))
+            });
+
+        let Some((index, mut package, stats)) = best else {
+            break;
+        };
+
+        if block.weight + stats.weight > max_weight || block.sigops + stats.sigops > max_sigops {
+            failed[index] = true;
+            continue;
+        }
+
+        let mut package_spent = HashSet::new();
+        let conflicted = package.iter().any(|&member| {
+            entries[member].transaction.input.iter().any(|input| {
+                spent.contains(&input.previous_output)
+                    || !package_spent.insert(input.previous_output)
+            })
+        });
+
+        if conflicted {
+            failed[index] = true;
+            continue;
+        }
+
+        // Fewer ancestors come first, which orders the package topologically.
+        package.sort_by_key(|&member| ancestors[member].len());
+
+        for member in package {
+            included[member] = true;
+            block.add(&entries[member]);
+            selected.push(member);
+        }
+        spent.extend(package_spent);
+    }
+
+    selected
+}
+
+fn witness_commitment_script(transactions: &[TemplateTransaction]) -> ScriptBuf {
+    // The wtxid of coinbase is always zero.
+    let hashes = std::iter::once(Wtxid::all_zeros())
+        .chain(transactions.iter().map(|tx| tx.wtxid))
+        .map(|wtxid| wtxid.to_raw_hash());
+
+    let witness_root = bitcoin::merkle_tree::calculate_root(hashes)
+        .map(WitnessMerkleNode::from_raw_hash)
+        .expect("Hashes are non-empty as the coinbase is included; qed");
+
+    // The default witness reserved value is zero.
+    let commitment = BitcoinBlock::compute_witness_commitment(&witness_root, &[0u8; 32]);
+
+    let mut data = [0u8; 36];
+    data[..4].copy_from_slice(&WITNESS_COMMITMENT_HEADER);
+    data[4..].copy_from_slice(commitment.as_byte_array());
+
+    ScriptBuf::new_op_return(data)
+}
+
+impl<Block, Client, BE> BlockVerifier<Block, Client, BE>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block> + StorageProvider<Block, BE> + AuxStore,
+{
+    /// Assembles the template of the next block from the unconfirmed `transactions`.
+    ///
+    /// The transactions failing the verification against the UTXO set of the best block and
+    /// the other unconfirmed transactions are excluded, so are their descendants. `now` is the
+    /// current timestamp in seconds.
+    pub fn create_block_template(
+        &self,
+        transactions: Vec<Transaction>,
+        now: u32,
+    ) -> Result<BlockTemplate, Error> {
+        let (best_hash, best_number, best_header) = self.best_block()?;
+        let height = best_number + 1;
+
+        let mtp = self
+            .header_verifier
+            .calculate_median_time_past(&best_header);
+        let min_time = mtp + 1;
+        let cur_time = now.max(min_time);
+
+        let target = self
+            .header_verifier
+            .next_target(best_number, &best_header, cur_time)?;
+
+        let deployment_state = DeploymentState {
+            height,
+            lock_time_cutoff: if height >= self.params.chain_params.csv_height {
+                mtp
+            } else {
+                cur_time
+            },
+        };
+
+        let mut seen = HashSet::new();
+        let transactions = transactions
+            .into_iter()
+            .map(|tx| (tx.compute_txid(), tx))
+            .filter(|(txid, _)| seen.insert(*txid))
+            .collect::<Vec<_>>();
+
+        let positions = transactions
+            .iter()
+            .enumerate()
+            .map(|(index, (txid, _))| (*txid, index))
+            .collect::<HashMap<_, _>>();
+
+        let unconfirmed_coins = transactions
+            .iter()
+            .flat_map(|(txid, tx)| {
+                tx.output.iter().enumerate().map(|(vout, txout)| {
+                    (
+                        OutPoint::new(*txid, vout as u32),
+                        Coin {
+                            is_coinbase: false,
+                            amount: txout.value.to_sat(),
+                            script_pubkey: txout.script_pubkey.to_bytes(),
+                            height,
+                        },
+                    )
+                })
+            })
+            .collect::<HashMap<_, _>>();
+
+        let view = |out_point: &OutPoint| {
+            unconfirmed_coins
+                .get(out_point)
+                .cloned()
+                .or_else(|| self.find_utxo_in_state(best_hash, *out_point))
+        };
+
+        let mut entries = transactions
+            .into_iter()
+            .map(|(txid, tx)| {
+                let fee = match subcoin_consensus_verification::verify_transaction(
+                    &tx,
+                    &view,
+                    &deployment_state,
+                    &self.params,
+                ) {
+                    Ok(fee) => fee.to_sat(),
+                    Err(err) => {
+                        tracing::debug!(?err, "Excluding {txid} from the block template");
+                        return None;
+                    }
+                };
+
+                let sigops = tx.total_sigop_cost(|out_point: &OutPoint| {
+                    view(out_point).map(|coin| TxOut {
+                        value: Amount::from_sat(coin.amount),
+                        script_pubkey: ScriptBuf::from_bytes(coin.script_pubkey),
+                    })
+                }) as u64;
+
+                let mut parents = tx
+                    .input
+                    .iter()
+                    .filter_map(|input| positions.get(&input.previous_output.txid).copied())
+                    .collect::<Vec<_>>();
+                parents.sort_unstable();
+                parents.dedup();
+
+                Some(MempoolEntry {
+                    txid,
+                    fee,
+                    weight: tx.weight().to_wu(),
+                    sigops,
+                    parents,
+                    transaction: tx,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Exclude the descendants of the excluded transactions.
+        loop {
+            let orphans = (0..entries.len())
+                .filter(|&index| {
+                    entries[index].as_ref().is_some_and(|entry| {
+                        entry
+                            .parents
+                            .iter()
+                            .any(|&parent| entries[parent].is_none())
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            if orphans.is_empty() {
+                break;
+            }
+
+            for index in orphans {
+                entries[index] = None;
+            }
+        }
+
+        // Reindex the remaining entries.
+        let new_indices = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_some())
+            .enumerate()
+            .map(|(new_index, (index, _))| (index, new_index))
+            .collect::<HashMap<_, _>>();
+
+        let entries = entries
+            .into_iter()
+            .flatten()
+            .map(|mut entry| {
+                entry.parents = entry
+                    .parents
+                    .iter()
+                    .map(|parent| new_indices[parent])
+                    .collect();
+                entry
+            })
+            .collect::<Vec<_>>();
+
+        let weight_limit = MAX_BLOCK_WEIGHT.to_wu();
+        let sigop_limit = MAX_BLOCK_SIGOPS_COST as u64;
+
+        let selected = select_packages(
+            &entries,
+            weight_limit - COINBASE_RESERVED_WEIGHT,
+            sigop_limit - COINBASE_RESERVED_SIGOPS,
+        );
+
+        let template_indices = selected
+            .iter()
+            .enumerate()
+            .map(|(template_index, &index)| (index, template_index))
+            .collect::<HashMap<_, _>>();
+
+        let transactions = selected
+            .iter()
+            .map(|&index| {
+                let entry = &entries[index];
+                TemplateTransaction {
+                    wtxid: entry.transaction.compute_wtxid(),
+                    transaction: entry.transaction.clone(),
+                    txid: entry.txid,
+                    fee: entry.fee,
+                    sigops: entry.sigops,
+                    weight: entry.weight,
+                    depends: entry
+                        .parents
+                        .iter()
+                        .map(|parent| template_indices[parent])
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let coinbase_value =
+            block_subsidy(height, self.params.chain_params.subsidy_halving_interval)
+                + transactions.iter().map(|tx| tx.fee).sum::<u64>();
+
+        let default_witness_commitment = (height >= self.params.chain_params.segwit_height)
+            .then(|| witness_commitment_script(&transactions));
+
+        Ok(BlockTemplate {
+            version: Version::from_consensus(TEMPLATE_VERSION),
+            previous_block_hash: best_header.block_hash(),
+            height,
+            transactions,
+            coinbase_value,
+            bits: target.to_compact_lossy(),
+            target,
+            min_time,
+            cur_time,
+            weight_limit,
+            sigop_limit,
+            default_witness_commitment,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version as TxVersion;
+    use bitcoin::{Sequence, TxIn, Witness};
+
+    fn entry(inputs: &[OutPoint], fee: u64, weight: u64, parents: Vec<usize>) -> MempoolEntry {
+        let transaction = Transaction {
+            version: TxVersion::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|previous_output| TxIn {
+                    previous_output: *previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![],
+        };
+
+        MempoolEntry {
+            txid: transaction.compute_txid(),
+            transaction,
+            fee,
+            weight,
+            sigops: 4,
+            parents,
+        }
+    }
+
+    fn out_point(byte: u8) -> OutPoint {
+        OutPoint::new(Txid::from_byte_array([byte; 32]), 0)
+    }
+
+    #[test]
+    fn test_select_packages() {
+        let parent = entry(&[out_point(1)], 1, 1000, vec![]);
+        let child = entry(&[OutPoint::new(parent.txid, 0)], 100, 1000, vec![0]);
+        let other = entry(&[out_point(2)], 30, 1000, vec![]);
+        let entries = vec![parent, child, other];
+
+        // The child pays for the parent.
+        assert_eq!(select_packages(&entries, 10_000, 1000), vec![0, 1, 2]);
+
+        // The other transaction does not fit after the package.
+        assert_eq!(select_packages(&entries, 2500, 1000), vec![0, 1]);
+
+        // Sigops limit.
+        assert_eq!(select_packages(&entries, 10_000, 4), vec![2]);
+    }
+
+    #[test]
+    fn test_select_packages_excludes_conflicts() {
+        let entries = vec![
+            entry(&[out_point(1)], 10, 1000, vec![]),
+            entry(&[out_point(1)], 20, 1000, vec![]),
+            entry(&[out_point(2)], 10, 1000, vec![]),
+        ];
+        assert_eq!(select_packages(&entries, 10_000, 1000), vec![1, 2]);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::chain_params::{ChainParams, MEDIAN_TIME_SPAN};
+use crate::adjusted_time::adjusted_time;
 use bitcoin::blockdata::block::{Header as BitcoinHeader, ValidationError};
 use bitcoin::consensus::Params;
 use bitcoin::hashes::Hash;
@@ -7,14 +7,19 @@ use bitcoin::{BlockHash, Target};
 use sc_client_api::AuxStore;
 use sp_blockchain::HeaderBackend;
 use sp_runtime::traits::Block as BlockT;
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::ops::Range;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use subcoin_primitives::BackendExt;
+use subcoin_consensus_verification::{ChainParams, MEDIAN_TIME_SPAN};
+use subcoin_primitives::HeaderChain;
 
 // 2 hours
 const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
 
+/// Minimum number of headers verified by a dedicated thread.
+const MIN_WINDOW_SIZE: usize = 128;
+
 /// Block header error.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -37,58 +42,111 @@ pub enum Error {
     Client(#[from] sp_blockchain::Error),
 }
 
+/// Provides the ancestor headers required for verifying a header.
+///
+/// The ancestors are not necessarily in the database yet, e.g., the headers downloaded
+/// during the headers-first sync are verified before the block bodies are requested.
+pub trait HeaderProvider {
+    /// Returns the header of given block.
+    fn header(&self, block_hash: BlockHash) -> Option<BitcoinHeader>;
+}
+
+/// [`HeaderProvider`] looking up the headers being verified first, then the ancestors.
+struct ChainedHeaders<'a, P> {
+    headers: HashMap<BlockHash, BitcoinHeader>,
+    ancestors: &'a P,
+}
+
+impl<'a, P: HeaderProvider> HeaderProvider for ChainedHeaders<'a, P> {
+    fn header(&self, block_hash: BlockHash) -> Option<BitcoinHeader> {
+        self.headers
+            .get(&block_hash)
+            .copied()
+            .or_else(|| self.ancestors.header(block_hash))
+    }
+}
+
+impl<Block, Client> HeaderProvider for HeaderChain<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    fn header(&self, block_hash: BlockHash) -> Option<BitcoinHeader> {
+        HeaderChain::header(self, block_hash)
+    }
+}
+
 /// A struct responsible for verifying block header.
-#[derive(Clone)]
 pub struct HeaderVerifier<Block, Client> {
     client: Arc<Client>,
     chain_params: ChainParams,
+    /// Number of threads verifying the headers in parallel, all the cores if `None`.
+    threads: Option<usize>,
     _phantom: PhantomData<Block>,
 }
 
+impl<Block, Client> Clone for HeaderVerifier<Block, Client> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            chain_params: self.chain_params.clone(),
+            threads: self.threads,
+            _phantom: PhantomData,
+        }
+    }
+}
+
 impl<Block, Client> HeaderVerifier<Block, Client> {
     /// Constructs a new instance of [`HeaderVerifier`].
     pub fn new(client: Arc<Client>, chain_params: ChainParams) -> Self {
         Self {
             client,
             chain_params,
+            threads: None,
             _phantom: Default::default(),
         }
     }
-}
 
-impl<Block, Client> HeaderVerifier<Block, Client>
-where
-    Block: BlockT,
-    Client: HeaderBackend<Block> + AuxStore,
-{
-    /// Validates the header and returns the block time, which is used for verifying the finality of
-    /// transactions.
+    /// Sets the number of threads verifying the headers in parallel.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    pub(crate) fn with_chain_params(mut self, chain_params: ChainParams) -> Self {
+        self.chain_params = chain_params;
+        self
+    }
+
+    /// Validates the header on top of the ancestors provided by `headers` and returns the
+    /// block time, which is used for verifying the finality of transactions.
     ///
     /// The validation process includes:
+    /// - Checking the difficulty bits, including the min-difficulty rule on testnet.
     /// - Checking the proof of work.
     /// - Validating the block's timestamp:
-    ///     - The time must not be more than 2 hours in the future.
+    ///     - The time must not be more than 2 hours ahead of the network-adjusted time.
     ///     - The time must be greater than the median time of the last 11 blocks.
+    /// - Rejecting the outdated block versions.
     ///
     /// <https://github.com/bitcoin/bitcoin/blob/6f9db1ebcab4064065ccd787161bf2b87e03cc1f/src/validation.cpp#L4146>
-    pub fn verify_header(&self, header: &BitcoinHeader) -> Result<u32, Error> {
-        let prev_block_hash = header.prev_blockhash;
+    pub fn verify_header_with(
+        &self,
+        header: &BitcoinHeader,
+        prev_block_height: u32,
+        headers: &impl HeaderProvider,
+    ) -> Result<u32, Error> {
+        let params = &self.chain_params.params;
 
-        let prev_block_header = self.client.block_header(prev_block_hash).ok_or(
-            sp_blockchain::Error::MissingHeader(prev_block_hash.to_string()),
-        )?;
-
-        let prev_block_height = self
-            .client
-            .block_number(prev_block_hash)
-            .expect("Prev block must exist as we checked before; qed");
+        let prev_block_header = ancestor_header(headers, header.prev_blockhash)?;
 
         let expected_target = get_next_work_required(
             prev_block_height,
-            prev_block_header,
-            &self.chain_params.params,
-            &self.client,
-        );
+            &prev_block_header,
+            header.time,
+            params,
+            headers,
+        )?;
         let expected_bits = expected_target.to_compact_lossy().to_consensus();
 
         let actual_target = header.target();
@@ -104,13 +162,7 @@ where
             .validate_pow(actual_target)
             .map_err(Error::InvalidProofOfWork)?;
 
-        // Get the seconds since the UNIX epoch
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs() as u32;
-
-        if header.time > current_time + MAX_FUTURE_BLOCK_TIME {
+        if u64::from(header.time) > adjusted_time() + u64::from(MAX_FUTURE_BLOCK_TIME) {
             return Err(Error::TooFarInFuture);
         }
 
@@ -118,19 +170,21 @@ where
 
         let version = header.version.to_consensus();
 
-        if version < 2 && block_number >= self.chain_params.params.bip34_height
-            || version < 3 && block_number >= self.chain_params.params.bip66_height
-            || version < 4 && block_number >= self.chain_params.params.bip65_height
+        if version < 2 && block_number >= params.bip34_height
+            || version < 3 && block_number >= params.bip66_height
+            || version < 4 && block_number >= params.bip65_height
         {
             return Err(Error::BadVersion);
         }
 
+        let mtp = median_time_past(&prev_block_header, headers)?;
+
+        if header.time <= mtp {
+            return Err(Error::TimeTooOld);
+        }
+
         // BIP 113
         let lock_time_cutoff = if block_number >= self.chain_params.csv_height {
-            let mtp = self.calculate_median_time_past(header);
-            if header.time <= mtp {
-                return Err(Error::TimeTooOld);
-            }
             mtp
         } else {
             header.time
@@ -139,57 +193,204 @@ where
         Ok(lock_time_cutoff)
     }
 
-    /// Calculates the median time of the previous few blocks prior to the header (inclusive).
-    fn calculate_median_time_past(&self, header: &BitcoinHeader) -> u32 {
-        let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+    /// Validates a chain of headers in parallel, see [`Self::verify_header_with`].
+    ///
+    /// `headers` must be connected in ascending order, with the first header building on
+    /// the block at `prev_block_height`. `ancestors` provides the headers prior to the
+    /// chain, the headers in the chain are looked up internally.
+    ///
+    /// The chain is split into windows which never cross a retarget boundary, each window
+    /// is verified in a worker thread. Returns the index and error of the first invalid
+    /// header in the chain.
+    pub fn verify_headers_parallel(
+        &self,
+        headers: &[BitcoinHeader],
+        prev_block_height: u32,
+        ancestors: &(impl HeaderProvider + Sync),
+    ) -> Result<(), (usize, Error)>
+    where
+        Self: Sync,
+    {
+        let chained_headers = ChainedHeaders {
+            headers: headers
+                .iter()
+                .map(|header| (header.block_hash(), *header))
+                .collect(),
+            ancestors,
+        };
 
-        timestamps.push(header.time);
+        let verify_window = |window: Range<usize>| {
+            window.into_iter().try_for_each(|index| {
+                self.verify_header_with(
+                    &headers[index],
+                    prev_block_height + index as u32,
+                    &chained_headers,
+                )
+                .map(|_| ())
+                .map_err(|err| (index, err))
+            })
+        };
 
-        let zero_hash = BlockHash::all_zeros();
+        let parallelism = self.threads.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        });
+        let max_window_size = headers.len().div_ceil(parallelism).max(MIN_WINDOW_SIZE);
+
+        let windows = split_into_windows(
+            headers.len(),
+            prev_block_height + 1,
+            self.chain_params.params.difficulty_adjustment_interval() as u32,
+            max_window_size,
+        );
 
-        let mut block_hash = header.prev_blockhash;
+        if windows.len() <= 1 {
+            return windows.into_iter().try_for_each(verify_window);
+        }
 
-        for _ in 0..MEDIAN_TIME_SPAN - 1 {
-            // Genesis block
-            if block_hash == zero_hash {
-                break;
-            }
+        std::thread::scope(|scope| {
+            let handles = windows
+                .into_iter()
+                .map(|window| scope.spawn(|| verify_window(window)))
+                .collect::<Vec<_>>();
+
+            // Merge the results in order so that the first invalid header is reported.
+            handles.into_iter().try_for_each(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+        })
+    }
+}
+
+/// Splits `len` headers starting at `first_height` into the windows of at most
+/// `max_window_size` headers, a new window is always started at a retarget boundary.
+fn split_into_windows(
+    len: usize,
+    first_height: u32,
+    retarget_interval: u32,
+    max_window_size: usize,
+) -> Vec<Range<usize>> {
+    let mut windows = Vec::new();
+    let mut start = 0;
+
+    for index in 1..len {
+        let height = first_height + index as u32;
+        if height % retarget_interval == 0 || index - start == max_window_size {
+            windows.push(start..index);
+            start = index;
+        }
+    }
+
+    if start < len {
+        windows.push(start..len);
+    }
+
+    windows
+}
+
+impl<Block, Client> HeaderVerifier<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    /// Validates the header on top of the chain in the database, see [`Self::verify_header_with`].
+    pub fn verify_header(&self, header: &BitcoinHeader) -> Result<u32, Error> {
+        let prev_block_hash = header.prev_blockhash;
+
+        let header_chain = HeaderChain::<Block, Client>::new(self.client.clone());
+
+        let prev_block_height =
+            header_chain
+                .height(prev_block_hash)
+                .ok_or(sp_blockchain::Error::MissingHeader(
+                    prev_block_hash.to_string(),
+                ))?;
+
+        self.verify_header_with(header, prev_block_height, &header_chain)
+    }
+
+    /// Returns the target required for the block at `block_time` on top of `prev_block_header`.
+    pub(crate) fn next_target(
+        &self,
+        prev_block_height: u32,
+        prev_block_header: &BitcoinHeader,
+        block_time: u32,
+    ) -> Result<Target, Error> {
+        get_next_work_required(
+            prev_block_height,
+            prev_block_header,
+            block_time,
+            &self.chain_params.params,
+            &HeaderChain::<Block, Client>::new(self.client.clone()),
+        )
+    }
 
-            let header = self
-                .client
-                .block_header(block_hash)
-                .expect("Parent header must exist; qed");
+    /// Calculates the median time of the previous few blocks prior to the header (inclusive).
+    pub(crate) fn calculate_median_time_past(&self, header: &BitcoinHeader) -> u32 {
+        median_time_past(
+            header,
+            &HeaderChain::<Block, Client>::new(self.client.clone()),
+        )
+        .expect("Parent header must exist; qed")
+    }
+}
 
-            timestamps.push(header.time);
+fn ancestor_header(
+    headers: &impl HeaderProvider,
+    block_hash: BlockHash,
+) -> Result<BitcoinHeader, Error> {
+    headers
+        .header(block_hash)
+        .ok_or_else(|| sp_blockchain::Error::MissingHeader(block_hash.to_string()).into())
+}
 
-            block_hash = header.prev_blockhash;
+/// Calculates the median time of the previous few blocks prior to the header (inclusive).
+fn median_time_past(header: &BitcoinHeader, headers: &impl HeaderProvider) -> Result<u32, Error> {
+    let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+
+    timestamps.push(header.time);
+
+    let zero_hash = BlockHash::all_zeros();
+
+    let mut block_hash = header.prev_blockhash;
+
+    for _ in 0..MEDIAN_TIME_SPAN - 1 {
+        // Genesis block
+        if block_hash == zero_hash {
+            break;
         }
 
-        timestamps.sort_unstable();
+        let header = ancestor_header(headers, block_hash)?;
 
-        timestamps
-            .get(timestamps.len() / 2)
-            .copied()
-            .expect("Timestamps must be non-empty; qed")
+        timestamps.push(header.time);
+
+        block_hash = header.prev_blockhash;
     }
+
+    timestamps.sort_unstable();
+
+    Ok(timestamps
+        .get(timestamps.len() / 2)
+        .copied()
+        .expect("Timestamps must be non-empty; qed"))
 }
 
 /// Usually, it's just the target of last block. However, if we are in a retarget period,
 /// it will be calculated from the last 2016 blocks (about two weeks for Bitcoin mainnet).
 ///
 /// <https://github.com/bitcoin/bitcoin/blob/89b910711c004c21b7d67baa888073742f7f94f0/src/pow.cpp#L13>
-fn get_next_work_required<Block, Client>(
+fn get_next_work_required(
     last_block_height: u32,
-    last_block: BitcoinHeader,
+    last_block: &BitcoinHeader,
+    block_time: u32,
     params: &Params,
-    client: &Arc<Client>,
-) -> Target
-where
-    Block: BlockT,
-    Client: HeaderBackend<Block> + AuxStore,
-{
+    headers: &impl HeaderProvider,
+) -> Result<Target, Error> {
     if params.no_pow_retargeting {
-        return last_block.target();
+        return Ok(last_block.target());
     }
 
     let height = last_block_height + 1;
@@ -197,31 +398,50 @@ where
     let difficulty_adjustment_interval = params.difficulty_adjustment_interval() as u32;
 
     // Only change once per difficulty adjustment interval.
-    if height >= difficulty_adjustment_interval && height % difficulty_adjustment_interval == 0 {
-        let last_retarget_height = height - difficulty_adjustment_interval;
+    if height % difficulty_adjustment_interval != 0 {
+        if params.allow_min_difficulty_blocks {
+            // Special difficulty rule for testnet: allow mining a min-difficulty block if the
+            // new block's timestamp is more than 2 * 10 minutes after the last block.
+            if u64::from(block_time) > u64::from(last_block.time) + params.pow_target_spacing * 2 {
+                return Ok(params.max_attainable_target);
+            }
 
-        let retarget_header_hash = client
-            .block_hash(last_retarget_height)
-            .expect("Retarget block must be available; qed");
+            // Otherwise, return the target of the last block not mined under the special rule.
+            let pow_limit_bits = params.max_attainable_target.to_compact_lossy();
 
-        let retarget_header = client
-            .block_header(retarget_header_hash)
-            .expect("Retarget block must be available; qed");
+            let mut block_height = last_block_height;
+            let mut block_header = *last_block;
 
-        let first_block_time = retarget_header.time;
+            while block_height % difficulty_adjustment_interval != 0
+                && block_header.bits == pow_limit_bits
+            {
+                block_header = ancestor_header(headers, block_header.prev_blockhash)?;
+                block_height -= 1;
+            }
 
-        // timestamp of last block
-        let last_block_time = last_block.time;
+            return Ok(block_header.target());
+        }
 
-        calculate_next_work_required(
-            last_block.target().0,
-            first_block_time.into(),
-            last_block_time.into(),
-            params,
-        )
-    } else {
-        last_block.target()
+        return Ok(last_block.target());
+    }
+
+    // Go back to the first block of this retarget period.
+    let mut retarget_header = *last_block;
+    for _ in 0..difficulty_adjustment_interval - 1 {
+        retarget_header = ancestor_header(headers, retarget_header.prev_blockhash)?;
     }
+
+    let first_block_time = retarget_header.time;
+
+    // timestamp of last block
+    let last_block_time = last_block.time;
+
+    Ok(calculate_next_work_required(
+        last_block.target().0,
+        first_block_time.into(),
+        last_block_time.into(),
+        params,
+    ))
 }
 
 // <https://github.com/bitcoin/bitcoin/blob/89b910711c004c21b7d67baa888073742f7f94f0/src/pow.cpp#L49-L72>
@@ -260,7 +480,10 @@ fn calculate_next_work_required(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitcoin::block::Version;
     use bitcoin::consensus::encode::deserialize_hex;
+    use bitcoin::{CompactTarget, TxMerkleNode};
+    use std::collections::HashMap;
 
     #[test]
     fn test_calculate_next_work_required() {
@@ -289,4 +512,150 @@ mod tests {
             "Difficulty bits must match"
         );
     }
+
+    struct Headers(HashMap<BlockHash, BitcoinHeader>);
+
+    impl HeaderProvider for Headers {
+        fn header(&self, block_hash: BlockHash) -> Option<BitcoinHeader> {
+            self.0.get(&block_hash).copied()
+        }
+    }
+
+    fn build_chain(blocks: &[(u32, CompactTarget)]) -> (Headers, Vec<BitcoinHeader>) {
+        let mut prev_blockhash = BlockHash::all_zeros();
+        let chain = blocks
+            .iter()
+            .enumerate()
+            .map(|(index, (time, bits))| {
+                let header = BitcoinHeader {
+                    version: Version::from_consensus(4),
+                    prev_blockhash,
+                    merkle_root: TxMerkleNode::all_zeros(),
+                    time: *time,
+                    bits: *bits,
+                    nonce: index as u32,
+                };
+                prev_blockhash = header.block_hash();
+                header
+            })
+            .collect::<Vec<_>>();
+        let headers = chain
+            .iter()
+            .map(|header| (header.block_hash(), *header))
+            .collect();
+        (Headers(headers), chain)
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let bits = CompactTarget::from_consensus(0x1d00ffff);
+        let blocks = [50, 10, 20, 40, 30, 60, 70, 90, 80, 100, 110, 120]
+            .into_iter()
+            .map(|time| (time, bits))
+            .collect::<Vec<_>>();
+        let (headers, chain) = build_chain(&blocks);
+
+        // Only the last 11 blocks are taken into account.
+        assert_eq!(median_time_past(&chain[11], &headers).unwrap(), 70);
+        assert_eq!(median_time_past(&chain[2], &headers).unwrap(), 20);
+        assert_eq!(median_time_past(&chain[0], &headers).unwrap(), 50);
+    }
+
+    #[test]
+    fn test_split_into_windows() {
+        assert!(split_into_windows(0, 1, 2016, 128).is_empty());
+        assert_eq!(split_into_windows(100, 1, 2016, 128), vec![0..100]);
+        assert_eq!(
+            split_into_windows(300, 1, 2016, 128),
+            vec![0..128, 128..256, 256..300]
+        );
+        // A new window is started at the retarget boundary.
+        assert_eq!(
+            split_into_windows(300, 2000, 2016, 128),
+            vec![0..16, 16..144, 144..272, 272..300]
+        );
+    }
+
+    #[test]
+    fn test_verify_headers_parallel() {
+        let verifier = HeaderVerifier::<(), ()>::new(
+            Arc::new(()),
+            ChainParams::new(bitcoin::Network::Regtest),
+        );
+
+        let bits = CompactTarget::from_consensus(0x207fffff);
+
+        // Headers at the `too_old` indices are timestamped before the median time past.
+        let build_chain = |too_old: &[u32]| {
+            let mut prev_blockhash = BlockHash::all_zeros();
+            (0..600u32)
+                .map(|index| {
+                    let mut header = BitcoinHeader {
+                        version: Version::from_consensus(4),
+                        prev_blockhash,
+                        merkle_root: TxMerkleNode::all_zeros(),
+                        time: if too_old.contains(&index) {
+                            1
+                        } else {
+                            1000 + index * 600
+                        },
+                        bits,
+                        nonce: 0,
+                    };
+                    while header.validate_pow(header.target()).is_err() {
+                        header.nonce += 1;
+                    }
+                    prev_blockhash = header.block_hash();
+                    header
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let chain = build_chain(&[]);
+        let genesis = Headers(HashMap::from([(chain[0].block_hash(), chain[0])]));
+        assert!(verifier
+            .verify_headers_parallel(&chain[1..], 0, &genesis)
+            .is_ok());
+
+        // The first invalid header in the chain is reported.
+        let chain = build_chain(&[450, 200]);
+        let genesis = Headers(HashMap::from([(chain[0].block_hash(), chain[0])]));
+        let (index, err) = verifier
+            .verify_headers_parallel(&chain[1..], 0, &genesis)
+            .unwrap_err();
+        assert_eq!(index, 199);
+        assert!(matches!(err, Error::TimeTooOld));
+    }
+
+    #[test]
+    fn test_testnet_min_difficulty_rule() {
+        let params = Params::new(bitcoin::Network::Testnet);
+        let bits = CompactTarget::from_consensus(0x1b0404cb);
+        let pow_limit_bits = params.max_attainable_target.to_compact_lossy();
+
+        let (headers, chain) = build_chain(&[(1000, bits), (1600, bits), (4000, pow_limit_bits)]);
+        let last_block = chain[2];
+
+        let mut header = last_block;
+        header.prev_blockhash = last_block.block_hash();
+
+        // More than 20 minutes after the last block, min-difficulty block is allowed.
+        header.time = last_block.time + 20 * 60 + 1;
+        let target =
+            get_next_work_required(2, &last_block, header.time, &params, &headers).unwrap();
+        assert_eq!(target, params.max_attainable_target);
+
+        // Otherwise, the target of the last block not mined under the special rule is required.
+        header.time = last_block.time + 20 * 60;
+        let target =
+            get_next_work_required(2, &last_block, header.time, &params, &headers).unwrap();
+        assert_eq!(target.to_compact_lossy(), bits);
+
+        // The special rule is not available on mainnet.
+        let params = Params::new(bitcoin::Network::Bitcoin);
+        header.time = last_block.time + 20 * 60 + 1;
+        let target =
+            get_next_work_required(2, &last_block, header.time, &params, &headers).unwrap();
+        assert_eq!(target.to_compact_lossy(), pow_limit_bits);
+    }
 }
@@ -0,0 +1,538 @@
+//! Acceptance of the child-with-parents packages into the unconfirmed transactions.
+//!
+//! A parent whose fee rate is below the minimum relay fee rate is accepted if the package fee
+//! rate together with the child is high enough (CPFP), similar to `submitpackage` in Bitcoin
+//! Core.
+//!
+//! <https://github.com/bitcoin/bitcoin/blob/6f9db1ebcab4064065ccd787161bf2b87e03cc1f/doc/policy/packages.md>
+
+use super::{BlockVerifier, Error};
+use bitcoin::{Amount, OutPoint, Transaction, Txid, Wtxid};
+use sc_client_api::{AuxStore, Backend, StorageProvider};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::collections::{HashMap, HashSet};
+use subcoin_primitives::runtime::Coin;
+
+/// Maximum number of transactions in a package.
+pub const MAX_PACKAGE_COUNT: usize = 25;
+
+/// Maximum total weight of the transactions in a package.
+pub const MAX_PACKAGE_WEIGHT: u64 = 404_000;
+
+/// Maximum number of the unconfirmed ancestors of a transaction, including itself.
+pub const MAX_ANCESTOR_COUNT: usize = 25;
+
+/// Maximum total virtual size of the unconfirmed ancestors of a transaction, including itself.
+pub const MAX_ANCESTOR_VSIZE: u64 = 101_000;
+
+/// Maximum number of the unconfirmed descendants of a transaction, including itself.
+pub const MAX_DESCENDANT_COUNT: usize = 25;
+
+/// Maximum total virtual size of the unconfirmed descendants of a transaction, including itself.
+pub const MAX_DESCENDANT_VSIZE: u64 = 101_000;
+
+/// Minimum fee rate in sat/kvB for a transaction to be relayed.
+pub const MIN_RELAY_FEE_RATE: u64 = 1000;
+
+/// Reason of rejecting the whole package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PackageError {
+    #[error("package-too-many-transactions")]
+    TooManyTransactions,
+    #[error("package-too-large")]
+    TooLarge,
+    #[error("package-contains-duplicates")]
+    ContainsDuplicates,
+    #[error("package-not-sorted")]
+    NotSorted,
+    #[error("conflict-in-package")]
+    ConflictInPackage,
+    #[error("package-not-child-with-parents")]
+    NotChildWithParents,
+    #[error("package-not-child-with-parents-tree")]
+    NotChildWithParentsTree,
+    #[error("package-mempool-limits")]
+    MempoolLimits,
+    #[error("package-fee-too-low")]
+    FeeTooLow,
+    #[error("transaction failed")]
+    TransactionFailed,
+}
+
+/// Result of evaluating a transaction in the package.
+#[derive(Debug)]
+pub enum PackageTransactionStatus {
+    /// The transaction is already unconfirmed.
+    AlreadyInMempool,
+    /// The transaction is accepted.
+    Accepted {
+        /// Fee rate in sat/kvB the transaction is accepted at, i.e., the package fee rate if
+        /// the transaction was accepted together with others.
+        effective_fee_rate: u64,
+        /// Transactions whose fees and sizes are included in the effective fee rate.
+        effective_includes: Vec<Wtxid>,
+    },
+    /// The transaction is invalid.
+    Invalid(Error),
+    /// The transaction spends an output already spent by an unconfirmed transaction.
+    MempoolConflict,
+    /// The transaction is valid but its fee rate, as well as the package fee rate, is too low.
+    FeeTooLow,
+    /// The transaction is not evaluated as the package was rejected earlier.
+    NotValidated,
+}
+
+/// Transaction in the package.
+#[derive(Debug)]
+pub struct PackageTransaction {
+    pub txid: Txid,
+    pub wtxid: Wtxid,
+    pub vsize: u64,
+    /// Transaction fee, `None` if the transaction was not verified.
+    pub fee: Option<Amount>,
+    pub status: PackageTransactionStatus,
+}
+
+/// Result of evaluating a package.
+#[derive(Debug)]
+pub struct PackageAcceptance {
+    /// `None` if all the transactions are accepted or already in the mempool.
+    pub error: Option<PackageError>,
+    /// Transactions in the package order.
+    pub transactions: Vec<PackageTransaction>,
+}
+
+impl PackageAcceptance {
+    /// Returns the transactions newly accepted from the package.
+    pub fn accepted_transactions<'a>(
+        &'a self,
+        package: &'a [Transaction],
+    ) -> impl Iterator<Item = &'a Transaction> + 'a {
+        package
+            .iter()
+            .zip(&self.transactions)
+            .filter(|(_, tx)| matches!(tx.status, PackageTransactionStatus::Accepted { .. }))
+            .map(|(transaction, _)| transaction)
+    }
+}
+
+/// Checks the package is sorted and consists of a child and its parents only, where the
+/// parents do not depend on each other.
+pub fn check_package(package: &[Transaction]) -> Result<(), PackageError> {
+    if package.len() > MAX_PACKAGE_COUNT {
+        return Err(PackageError::TooManyTransactions);
+    }
+
+    let total_weight: u64 = package.iter().map(|tx| tx.weight().to_wu()).sum();
+
+    if total_weight > MAX_PACKAGE_WEIGHT {
+        return Err(PackageError::TooLarge);
+    }
+
+    let txids = package
+        .iter()
+        .map(|tx| tx.compute_txid())
+        .collect::<Vec<_>>();
+
+    let mut later_txids = txids.iter().copied().collect::<HashSet<_>>();
+
+    if later_txids.len() != package.len() {
+        return Err(PackageError::ContainsDuplicates);
+    }
+
+    for (tx, txid) in package.iter().zip(&txids) {
+        later_txids.remove(txid);
+
+        if tx
+            .input
+            .iter()
+            .any(|input| later_txids.contains(&input.previous_output.txid))
+        {
+            return Err(PackageError::NotSorted);
+        }
+    }
+
+    let mut spent_outputs = HashSet::new();
+
+    if !package
+        .iter()
+        .flat_map(|tx| tx.input.iter())
+        .all(|input| spent_outputs.insert(input.previous_output))
+    {
+        return Err(PackageError::ConflictInPackage);
+    }
+
+    let Some((child, parents)) = package.split_last() else {
+        return Ok(());
+    };
+
+    let child_parents = child
+        .input
+        .iter()
+        .map(|input| input.previous_output.txid)
+        .collect::<HashSet<_>>();
+
+    let parent_txids = &txids[..parents.len()];
+
+    if !parent_txids.iter().all(|txid| child_parents.contains(txid)) {
+        return Err(PackageError::NotChildWithParents);
+    }
+
+    if parents.iter().any(|parent| {
+        parent
+            .input
+            .iter()
+            .any(|input| parent_txids.contains(&input.previous_output.txid))
+    }) {
+        return Err(PackageError::NotChildWithParentsTree);
+    }
+
+    Ok(())
+}
+
+/// Fee rate in sat/kvB.
+fn fee_rate(fee: Amount, vsize: u64) -> u64 {
+    fee.to_sat() * 1000 / vsize.max(1)
+}
+
+/// Unconfirmed transaction graph made of the mempool and the package.
+struct TransactionGraph {
+    vsizes: HashMap<Txid, u64>,
+    parents: HashMap<Txid, HashSet<Txid>>,
+    children: HashMap<Txid, HashSet<Txid>>,
+}
+
+impl TransactionGraph {
+    fn new<'a>(transactions: impl Iterator<Item = (Txid, &'a Transaction)> + Clone) -> Self {
+        let vsizes = transactions
+            .clone()
+            .map(|(txid, tx)| (txid, tx.vsize() as u64))
+            .collect::<HashMap<_, _>>();
+
+        let mut parents = HashMap::<Txid, HashSet<Txid>>::new();
+        let mut children = HashMap::<Txid, HashSet<Txid>>::new();
+
+        for (txid, tx) in transactions {
+            for input in &tx.input {
+                let parent = input.previous_output.txid;
+                if vsizes.contains_key(&parent) {
+                    parents.entry(txid).or_default().insert(parent);
+                    children.entry(parent).or_default().insert(txid);
+                }
+            }
+        }
+
+        Self {
+            vsizes,
+            parents,
+            children,
+        }
+    }
+
+    /// Returns the transactions reachable through `edges`, including `txid` itself.
+    fn reachable(edges: &HashMap<Txid, HashSet<Txid>>, txid: Txid) -> HashSet<Txid> {
+        let mut visited = HashSet::from([txid]);
+        let mut stack = vec![txid];
+
+        while let Some(txid) = stack.pop() {
+            for next in edges.get(&txid).into_iter().flatten() {
+                if visited.insert(*next) {
+                    stack.push(*next);
+                }
+            }
+        }
+
+        visited
+    }
+
+    /// Returns `true` if the count and total size of `transactions` are within the limits.
+    fn within_limits(
+        &self,
+        transactions: &HashSet<Txid>,
+        max_count: usize,
+        max_vsize: u64,
+    ) -> bool {
+        let vsize: u64 = transactions.iter().map(|txid| self.vsizes[txid]).sum();
+        transactions.len() <= max_count && vsize <= max_vsize
+    }
+
+    /// Returns `true` if the ancestors of `txid` and the descendants of each of them are within
+    /// the limits.
+    fn check_limits(&self, txid: Txid) -> bool {
+        let ancestors = Self::reachable(&self.parents, txid);
+
+        self.within_limits(&ancestors, MAX_ANCESTOR_COUNT, MAX_ANCESTOR_VSIZE)
+            && ancestors.iter().all(|ancestor| {
+                let descendants = Self::reachable(&self.children, *ancestor);
+                self.within_limits(&descendants, MAX_DESCENDANT_COUNT, MAX_DESCENDANT_VSIZE)
+            })
+    }
+}
+
+impl<Block, Client, BE> BlockVerifier<Block, Client, BE>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block> + StorageProvider<Block, BE> + AuxStore,
+{
+    /// Evaluates the child-with-parents package against the best block and the unconfirmed
+    /// transactions in `mempool`.
+    ///
+    /// Each parent is evaluated on its own first, the ones below the minimum relay fee rate are
+    /// then evaluated together with the child at the package fee rate.
+    pub fn verify_package(
+        &self,
+        package: &[Transaction],
+        mempool: Vec<Transaction>,
+    ) -> Result<PackageAcceptance, Error> {
+        let mut transactions = package
+            .iter()
+            .map(|tx| PackageTransaction {
+                txid: tx.compute_txid(),
+                wtxid: tx.compute_wtxid(),
+                vsize: tx.vsize() as u64,
+                fee: None,
+                status: PackageTransactionStatus::NotValidated,
+            })
+            .collect::<Vec<_>>();
+
+        let rejected = |error, transactions| {
+            Ok(PackageAcceptance {
+                error: Some(error),
+                transactions,
+            })
+        };
+
+        if let Err(err) = check_package(package) {
+            return rejected(err, transactions);
+        }
+
+        let mempool = mempool
+            .into_iter()
+            .map(|tx| (tx.compute_txid(), tx))
+            .collect::<HashMap<_, _>>();
+
+        if let Some(child) = transactions.last() {
+            let graph = TransactionGraph::new(
+                mempool.iter().map(|(txid, tx)| (*txid, tx)).chain(
+                    package
+                        .iter()
+                        .zip(&transactions)
+                        .map(|(tx, entry)| (entry.txid, tx)),
+                ),
+            );
+
+            if !graph.check_limits(child.txid) {
+                return rejected(PackageError::MempoolLimits, transactions);
+            }
+        }
+
+        let (best_hash, deployment_state) = self.next_block_deployment_state()?;
+
+        let mempool_spent = mempool
+            .values()
+            .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+            .collect::<HashSet<_>>();
+
+        let mut unconfirmed_coins = HashMap::new();
+
+        let add_coins =
+            |unconfirmed_coins: &mut HashMap<OutPoint, Coin>, txid, tx: &Transaction| {
+                unconfirmed_coins.extend(tx.output.iter().enumerate().map(|(vout, txout)| {
+                    (
+                        OutPoint::new(txid, vout as u32),
+                        Coin {
+                            is_coinbase: false,
+                            amount: txout.value.to_sat(),
+                            script_pubkey: txout.script_pubkey.to_bytes(),
+                            height: deployment_state.height,
+                        },
+                    )
+                }));
+            };
+
+        for (txid, tx) in &mempool {
+            add_coins(&mut unconfirmed_coins, *txid, tx);
+        }
+
+        // Indices of the valid transactions to be evaluated at the package fee rate.
+        let mut deferred = Vec::new();
+
+        for (index, tx) in package.iter().enumerate() {
+            let entry = &mut transactions[index];
+
+            if mempool.contains_key(&entry.txid) {
+                entry.status = PackageTransactionStatus::AlreadyInMempool;
+                continue;
+            }
+
+            if tx
+                .input
+                .iter()
+                .any(|input| mempool_spent.contains(&input.previous_output))
+            {
+                entry.status = PackageTransactionStatus::MempoolConflict;
+                return rejected(PackageError::TransactionFailed, transactions);
+            }
+
+            let fee = match subcoin_consensus_verification::verify_transaction(
+                tx,
+                &|out_point: &OutPoint| {
+                    unconfirmed_coins
+                        .get(out_point)
+                        .cloned()
+                        .or_else(|| self.find_utxo_in_state(best_hash, *out_point))
+                },
+                &deployment_state,
+                &self.params,
+            ) {
+                Ok(fee) => fee,
+                Err(err) => {
+                    entry.status = PackageTransactionStatus::Invalid(err.into());
+                    return rejected(PackageError::TransactionFailed, transactions);
+                }
+            };
+
+            entry.fee = Some(fee);
+
+            add_coins(&mut unconfirmed_coins, entry.txid, tx);
+
+            let is_child = index + 1 == package.len();
+
+            if !is_child && fee_rate(fee, entry.vsize) >= MIN_RELAY_FEE_RATE {
+                entry.status = PackageTransactionStatus::Accepted {
+                    effective_fee_rate: fee_rate(fee, entry.vsize),
+                    effective_includes: vec![entry.wtxid],
+                };
+            } else {
+                deferred.push(index);
+            }
+        }
+
+        if deferred.is_empty() {
+            return Ok(PackageAcceptance {
+                error: None,
+                transactions,
+            });
+        }
+
+        let (package_fee, package_vsize) =
+            deferred
+                .iter()
+                .fold((Amount::ZERO, 0), |(fee, vsize), index| {
+                    let entry = &transactions[*index];
+                    (fee + entry.fee.unwrap_or(Amount::ZERO), vsize + entry.vsize)
+                });
+
+        let package_fee_rate = fee_rate(package_fee, package_vsize);
+
+        if package_fee_rate < MIN_RELAY_FEE_RATE {
+            for index in deferred {
+                transactions[index].status = PackageTransactionStatus::FeeTooLow;
+            }
+            return rejected(PackageError::FeeTooLow, transactions);
+        }
+
+        let effective_includes = deferred
+            .iter()
+            .map(|index| transactions[*index].wtxid)
+            .collect::<Vec<_>>();
+
+        for index in deferred {
+            transactions[index].status = PackageTransactionStatus::Accepted {
+                effective_fee_rate: package_fee_rate,
+                effective_includes: effective_includes.clone(),
+            };
+        }
+
+        Ok(PackageAcceptance {
+            error: None,
+            transactions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{ScriptBuf, TxIn, TxOut};
+
+    fn transaction(inputs: &[OutPoint], outputs: usize) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|previous_output| TxIn {
+                    previous_output: *previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output: (0..outputs)
+                .map(|_| TxOut {
+                    value: Amount::from_sat(1000),
+                    script_pubkey: ScriptBuf::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_check_package() {
+        let confirmed = |n: u8| OutPoint::new(Txid::from_byte_array([n; 32]), 0);
+
+        let parent1 = transaction(&[confirmed(1)], 2);
+        let parent2 = transaction(&[confirmed(2)], 1);
+        let child = transaction(
+            &[
+                OutPoint::new(parent1.compute_txid(), 0),
+                OutPoint::new(parent2.compute_txid(), 0),
+            ],
+            1,
+        );
+
+        assert_eq!(
+            check_package(&[parent1.clone(), parent2.clone(), child.clone()]),
+            Ok(())
+        );
+        assert_eq!(
+            check_package(&[child.clone(), parent1.clone(), parent2.clone()]),
+            Err(PackageError::NotSorted)
+        );
+        assert_eq!(
+            check_package(&[parent1.clone(), parent1.clone(), child.clone()]),
+            Err(PackageError::ContainsDuplicates)
+        );
+
+        // The first parent is not spent by the child.
+        let unrelated = transaction(&[confirmed(3)], 1);
+        assert_eq!(
+            check_package(&[unrelated, parent2.clone(), child.clone()]),
+            Err(PackageError::NotChildWithParents)
+        );
+
+        // The second parent spends the first one.
+        let middle = transaction(&[OutPoint::new(parent1.compute_txid(), 1)], 1);
+        let bottom = transaction(
+            &[
+                OutPoint::new(parent1.compute_txid(), 0),
+                OutPoint::new(middle.compute_txid(), 0),
+            ],
+            1,
+        );
+        assert_eq!(
+            check_package(&[parent1.clone(), middle, bottom]),
+            Err(PackageError::NotChildWithParentsTree)
+        );
+
+        let conflict = transaction(&[OutPoint::new(parent1.compute_txid(), 0)], 1);
+        assert_eq!(
+            check_package(&[parent1, conflict, child]),
+            Err(PackageError::ConflictInPackage)
+        );
+    }
+}
@@ -34,7 +34,9 @@ use sp_blockchain::{CachedHeaderMetadata, HeaderMetadata};
 use sp_core::offchain::storage::InMemOffchainStorage as OffchainStorage;
 use sp_core::storage::well_known_keys;
 use sp_runtime::generic::BlockId;
-use sp_runtime::traits::{Block as BlockT, HashingFor, Header as HeaderT, NumberFor, Zero};
+use sp_runtime::traits::{
+    Block as BlockT, HashingFor, Header as HeaderT, NumberFor, Saturating, Zero,
+};
 use sp_runtime::{Justification, Justifications, StateVersion, Storage};
 use sp_state_machine::{
     Backend as StateBackend, BackendTransaction, ChildStorageCollection, InMemoryBackend,
@@ -193,6 +195,29 @@ impl<Block: BlockT> Blockchain<Block> {
         self.storage.read().blocks.len()
     }
 
+    /// Removes the canonical blocks older than the last `keep` blocks, the genesis block is kept.
+    ///
+    /// Returns the number of removed blocks.
+    pub fn prune_blocks(&self, keep: NumberFor<Block>) -> usize {
+        let mut storage = self.storage.write();
+
+        let threshold = storage.best_number.saturating_sub(keep);
+
+        let pruned = storage
+            .hashes
+            .iter()
+            .filter(|(number, _)| !number.is_zero() && **number < threshold)
+            .map(|(number, hash)| (*number, *hash))
+            .collect::<Vec<_>>();
+
+        for (number, hash) in &pruned {
+            storage.hashes.remove(number);
+            storage.blocks.remove(hash);
+        }
+
+        pruned.len()
+    }
+
     /// Compare this blockchain with another in-mem blockchain
     pub fn equals_to(&self, other: &Self) -> bool {
         // Check ptr equality first to avoid double read locks.
@@ -994,4 +1019,18 @@ mod tests {
             Err(sp_blockchain::Error::BadJustification(_)),
         ));
     }
+
+    #[test]
+    fn prune_blocks_keeps_genesis_and_recent_blocks() {
+        let blockchain = test_blockchain();
+        assert_eq!(blockchain.blocks_count(), 4);
+
+        assert_eq!(blockchain.prune_blocks(1), 1);
+        assert_eq!(blockchain.blocks_count(), 3);
+        assert!(blockchain.header(header(1).hash()).unwrap().is_none());
+        assert!(blockchain.header(header(0).hash()).unwrap().is_some());
+        assert!(blockchain.header(header(2).hash()).unwrap().is_some());
+
+        assert_eq!(blockchain.prune_blocks(1), 0);
+    }
 }
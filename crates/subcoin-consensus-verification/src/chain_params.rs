@@ -0,0 +1,159 @@
+use bitcoin::consensus::Params;
+use bitcoin::{BlockHash, Network};
+use std::collections::HashMap;
+
+/// bip-0113 defines the median of the last 11 blocks instead of the block's timestamp for lock-time calculations.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Height from which the coinbase of a block may collide with one before BIP34 activation,
+/// BIP30 must be enforced again since then.
+///
+/// <https://github.com/bitcoin/bitcoin/blob/6f9db1ebcab4064065ccd787161bf2b87e03cc1f/src/validation.cpp#L2336>
+const BIP34_IMPLIES_BIP30_LIMIT: u32 = 1_983_702;
+
+/// Extended [`Params`].
+#[derive(Debug, Clone)]
+pub struct ChainParams {
+    /// Chain params defined in rust-bitcoin.
+    pub params: Params,
+    /// Block height at which CSV becomes active.
+    pub csv_height: u32,
+    /// Block height at which Segwit becomes active.
+    pub segwit_height: u32,
+    /// Number of blocks between the subsidy halvings.
+    pub subsidy_halving_interval: u32,
+    /// A map of block hashes to script verification flag exceptions.
+    ///
+    /// This allows for certain blocks to have specific script verification flags, overriding
+    /// the default rules. For example, exceptions may be made for blocks that activated
+    /// BIP16 (P2SH) or Taproot under special conditions.
+    pub script_flag_exceptions: HashMap<BlockHash, u32>,
+    /// Blocks violating BIP30, which recreated the coinbase of a previous block.
+    pub bip30_exceptions: HashMap<u32, BlockHash>,
+}
+
+impl ChainParams {
+    /// Constructs a new instance of [`ChainParams`].
+    // https://github.com/bitcoin/bitcoin/blob/6f9db1ebcab4064065ccd787161bf2b87e03cc1f/src/kernel/chainparams.cpp#L71
+    pub fn new(network: Network) -> Self {
+        let params = Params::new(network);
+        match network {
+            Network::Bitcoin => Self {
+                params,
+                csv_height: 419328, // 000000000000000004a1b34462cb8aeebd5799177f7a29cf28f2d1961716b5b5
+                segwit_height: 481824, // 0000000000000000001c8018d9cb3b742ef25114f27563e3fc4a1902167f9893
+                subsidy_halving_interval: 210_000,
+                script_flag_exceptions: [
+                    // BIP16 exception
+                    (
+                        "00000000000002dc756eebf4f49723ed8d30cc28a5f108eb94b1ba88ac4f9c22",
+                        crate::script::SCRIPT_VERIFY_NONE,
+                    ),
+                    // Taproot exception
+                    (
+                        "0000000000000000000f14c35b2d841e986ab5441de8c585d5ffe55ea1e395ad",
+                        crate::script::SCRIPT_VERIFY_P2SH | crate::script::SCRIPT_VERIFY_WITNESS,
+                    ),
+                ]
+                .into_iter()
+                .map(|(block_hash, flag)| {
+                    (block_hash.parse().expect("Hash must be valid; qed"), flag)
+                })
+                .collect(),
+                bip30_exceptions: [
+                    // Duplicates the coinbase of block 91812.
+                    (
+                        91842,
+                        "00000000000a4d0a398161ffc163c503763b1f4360639393e0e4c8e300e0caec",
+                    ),
+                    // Duplicates the coinbase of block 91722.
+                    (
+                        91880,
+                        "00000000000743f190a18c5577a3c2d2a1f610ae9601ac046a38084ccb7cd721",
+                    ),
+                ]
+                .into_iter()
+                .map(|(height, block_hash)| {
+                    (height, block_hash.parse().expect("Hash must be valid; qed"))
+                })
+                .collect(),
+            },
+            Network::Testnet => Self {
+                params,
+                csv_height: 770112, // 00000000025e930139bac5c6c31a403776da130831ab85be56578f3fa75369bb
+                segwit_height: 834624, // 00000000002b980fcd729daaa248fd9316a5200e9b367f4ff2c42453e84201ca
+                subsidy_halving_interval: 210_000,
+                script_flag_exceptions: HashMap::from_iter([
+                    // BIP16 exception
+                    (
+                        "00000000dd30457c001f4095d208cc1296b0eed002427aa599874af7a432b105"
+                            .parse()
+                            .expect("Hash must be valid; qed"),
+                        crate::script::SCRIPT_VERIFY_NONE,
+                    ),
+                ]),
+                bip30_exceptions: Default::default(),
+            },
+            Network::Signet => Self {
+                params,
+                csv_height: 1,
+                segwit_height: 1,
+                subsidy_halving_interval: 210_000,
+                script_flag_exceptions: Default::default(),
+                bip30_exceptions: Default::default(),
+            },
+            Network::Regtest => Self {
+                params,
+                csv_height: 1,    // Always active unless overridden
+                segwit_height: 0, // Always active unless overridden
+                subsidy_halving_interval: 150,
+                script_flag_exceptions: Default::default(),
+                bip30_exceptions: Default::default(),
+            },
+            _ => unreachable!("Unknown Bitcoin Network"),
+        }
+    }
+
+    /// Returns `true` if BIP30 (no overwriting of the unspent transactions) must be enforced
+    /// for the given block.
+    ///
+    /// BIP34 (block height in coinbase) guarantees the unique coinbase txids, BIP30 is
+    /// therefore skipped between BIP34 activation and [`BIP34_IMPLIES_BIP30_LIMIT`].
+    pub fn enforce_bip30(&self, height: u32, block_hash: BlockHash) -> bool {
+        if self.bip30_exceptions.get(&height) == Some(&block_hash) {
+            return false;
+        }
+
+        height < self.params.bip34_height || height >= BIP34_IMPLIES_BIP30_LIMIT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_enforce_bip30() {
+        let chain_params = ChainParams::new(Network::Bitcoin);
+
+        let block_91842 = "00000000000a4d0a398161ffc163c503763b1f4360639393e0e4c8e300e0caec"
+            .parse()
+            .unwrap();
+        let block_91880 = "00000000000743f190a18c5577a3c2d2a1f610ae9601ac046a38084ccb7cd721"
+            .parse()
+            .unwrap();
+
+        assert!(!chain_params.enforce_bip30(91842, block_91842));
+        assert!(!chain_params.enforce_bip30(91880, block_91880));
+        // Exception is bound to the exact block.
+        assert!(chain_params.enforce_bip30(91842, block_91880));
+        assert!(chain_params.enforce_bip30(91843, BlockHash::all_zeros()));
+
+        // Skipped since BIP34 activation.
+        let bip34_height = chain_params.params.bip34_height;
+        assert!(chain_params.enforce_bip30(bip34_height - 1, BlockHash::all_zeros()));
+        assert!(!chain_params.enforce_bip30(bip34_height, BlockHash::all_zeros()));
+        assert!(chain_params.enforce_bip30(BIP34_IMPLIES_BIP30_LIMIT, BlockHash::all_zeros()));
+    }
+}
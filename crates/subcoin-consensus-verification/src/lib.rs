@@ -0,0 +1,859 @@
+//! # Bitcoin Consensus Verification
+//!
+//! This crate verifies the Bitcoin blocks and transactions against the consensus rules, the
+//! primary code reference for these rules is Bitcoin Core.
+//!
+//! The verification is a set of pure functions without any I/O: the coins spent are read from
+//! a [`UtxoView`] and the context derived from the chain the block builds on, e.g., the median
+//! time past checked by the header verification, is passed in as a [`DeploymentState`]. This
+//! allows the rules to be fuzzed and tested against the vectors of Bitcoin Core in isolation,
+//! and shared by the block import, the mempool checks and the replay tools.
+//!
+//! The main components of this crate are:
+//! - [`verify_block`]: Verifies a block, returning the [`Undo`] data of the coins spent.
+//! - [`verify_transaction`]: Verifies a standalone transaction as if it was in the next block.
+//! - `script`: Verifies the spending conditions with the interpreters configured in
+//!   [`ScriptInterpreters`], the FFI bindings to the interpreter of Bitcoin Core by default.
+//! - `tx_verify`: Context-free checks of the individual transactions.
+
+mod chain_params;
+mod script;
+mod tx_verify;
+
+pub use self::chain_params::{ChainParams, MEDIAN_TIME_SPAN};
+#[cfg(feature = "bitcoinconsensus")]
+pub use self::script::CoreInterpreter;
+pub use self::script::{ScriptError, ScriptFamily, ScriptInterpreter, ScriptInterpreters};
+pub use self::tx_verify::{
+    check_transaction_sanity, get_legacy_sig_op_count, is_final, Error as TxError,
+};
+
+use self::script::{
+    SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY, SCRIPT_VERIFY_CHECKSEQUENCEVERIFY, SCRIPT_VERIFY_DERSIG,
+    SCRIPT_VERIFY_NULLDUMMY, SCRIPT_VERIFY_P2SH, SCRIPT_VERIFY_WITNESS,
+};
+use bitcoin::block::Bip34Error;
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::blockdata::constants::{COINBASE_MATURITY, MAX_BLOCK_SIGOPS_COST};
+use bitcoin::blockdata::weight::WITNESS_SCALE_FACTOR;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    Amount, Block as BitcoinBlock, BlockHash, Network, OutPoint, ScriptBuf, Transaction,
+    TxMerkleNode, TxOut, Txid, VarInt, Weight,
+};
+use std::collections::{HashMap, HashSet};
+use subcoin_runtime_primitives::{block_subsidy, Coin};
+
+/// The maximum allowed weight for a block, see BIP 141 (network rule).
+pub const MAX_BLOCK_WEIGHT: Weight = Weight::MAX_BLOCK;
+
+/// Consensus verification error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The merkle root of the block is invalid.
+    #[error("Invalid merkle root")]
+    BadMerkleRoot,
+    /// Block must contain at least one coinbase transaction.
+    #[error("Transaction list is empty")]
+    EmptyTransactionList,
+    #[error("Block is too large")]
+    BadBlockLength,
+    #[error("First transaction is not coinbase")]
+    FirstTransactionIsNotCoinbase,
+    #[error("Block contains more than one coinbase")]
+    MultipleCoinbase,
+    #[error("Transaction input script contains too many sigops (max: {MAX_BLOCK_SIGOPS_COST})")]
+    TooManySigOps { block_number: u32 },
+    #[error("Invalid witness commitment")]
+    BadWitnessCommitment,
+    #[error("Transaction is not finalized")]
+    TransactionNotFinal,
+    #[error("Block contains duplicate transaction at index {0}")]
+    DuplicateTransaction(usize),
+    #[error("Block height mismatches in coinbase (got: {got}, expected: {expected})")]
+    BadCoinbaseBlockHeight { got: u32, expected: u32 },
+    /// Transaction overwrites an unspent output of a previous transaction, see BIP30.
+    #[error("Transaction overwrites an unspent output (#{block_number}:{utxo:?})")]
+    OverwriteUnspentOutput { block_number: u32, utxo: OutPoint },
+    /// Referenced output does not exist or was spent before.
+    #[error("UTXO not found (#{block_number}:{txid}: {utxo:?})")]
+    UtxoNotFound {
+        block_number: u32,
+        txid: Txid,
+        utxo: OutPoint,
+    },
+    /// Referenced output has already been spent in this block.
+    #[error("UTXO already spent in current block (#{block_number}:{txid}: {utxo:?})")]
+    AlreadySpentInCurrentBlock {
+        block_number: u32,
+        txid: Txid,
+        utxo: OutPoint,
+    },
+    /// Coinbase transaction is only valid as the first transaction of a block.
+    #[error("Coinbase transaction is not allowed outside a block")]
+    UnexpectedCoinbase,
+    #[error("Premature spend of coinbase")]
+    PrematureSpendOfCoinbase,
+    #[error("Total input amount is below total output amount ({value_in} < {value_out})")]
+    InsufficientFunds { value_in: u64, value_out: u64 },
+    // Invalid coinbase value.
+    #[error("Block reward is larger than the sum of block fee and subsidy")]
+    InvalidBlockReward,
+    #[error(transparent)]
+    Transaction(#[from] TxError),
+    #[error(transparent)]
+    Script(#[from] ScriptError),
+    /// Script of an input in the block failed the verification.
+    #[error("Script of input {input_index} of {txid} failed (#{block_number}): {error}")]
+    InvalidInputScript {
+        block_number: u32,
+        txid: Txid,
+        input_index: usize,
+        error: ScriptError,
+    },
+    #[error(transparent)]
+    Bip34(#[from] Bip34Error),
+    #[error("Bitcoin codec: {0:?}")]
+    BitcoinCodec(bitcoin::io::Error),
+}
+
+impl Error {
+    /// Returns `true` if the block with this error is invalid regardless of how it was received.
+    ///
+    /// A block failing the checks on the transaction data may merely be a mutated copy of a
+    /// valid block with the same hash, such blocks must not be marked invalid.
+    pub fn invalidates_block(&self) -> bool {
+        !matches!(
+            self,
+            Self::BadMerkleRoot
+                | Self::BadWitnessCommitment
+                | Self::BadBlockLength
+                | Self::DuplicateTransaction(_)
+                | Self::BitcoinCodec(_)
+        )
+    }
+}
+
+/// Read-only view of the UTXO set the block or the transaction is verified against.
+pub trait UtxoView {
+    /// Returns the unspent coin at `out_point`, if any.
+    fn coin(&self, out_point: &OutPoint) -> Option<Coin>;
+}
+
+impl<F> UtxoView for F
+where
+    F: Fn(&OutPoint) -> Option<Coin>,
+{
+    fn coin(&self, out_point: &OutPoint) -> Option<Coin> {
+        self(out_point)
+    }
+}
+
+impl UtxoView for HashMap<OutPoint, Coin> {
+    fn coin(&self, out_point: &OutPoint) -> Option<Coin> {
+        self.get(out_point).cloned()
+    }
+}
+
+/// Context of the block being verified, derived from the chain it builds on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeploymentState {
+    /// Height of the block.
+    pub height: u32,
+    /// Time the lock time of the transactions is checked against, the median time past of
+    /// the parent block since BIP113, the block time before.
+    pub lock_time_cutoff: u32,
+}
+
+/// Params of the consensus verification.
+#[derive(Clone)]
+pub struct VerificationParams {
+    /// Chain params of the network.
+    pub chain_params: ChainParams,
+    /// Interpreters verifying the scripts.
+    pub script_interpreters: ScriptInterpreters,
+    /// Whether to verify the scripts.
+    pub verify_script: bool,
+    /// Number of threads verifying the input scripts of a block, `1` to verify them in order
+    /// on the calling thread.
+    pub script_verification_threads: usize,
+}
+
+impl VerificationParams {
+    /// Constructs the params of `network`, verifying the scripts with the default interpreters.
+    pub fn new(network: Network) -> Self {
+        Self {
+            chain_params: ChainParams::new(network),
+            script_interpreters: ScriptInterpreters::default(),
+            verify_script: true,
+            script_verification_threads: 1,
+        }
+    }
+}
+
+/// Undo data of a verified block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Undo {
+    /// Coins spent by each transaction except the coinbase, in the order of the inputs.
+    pub spent_coins: Vec<Vec<Coin>>,
+    /// Fee of each transaction except the coinbase.
+    pub tx_fees: Vec<u64>,
+}
+
+/// Verifies the block on top of the UTXO set of its parent block.
+///
+/// The header is expected to be verified against the chain beforehand, which yields the
+/// `lock_time_cutoff` in `deployment_state`.
+///
+/// References:
+/// - <https://en.bitcoin.it/wiki/Protocol_rules#.22block.22_messages>
+pub fn verify_block(
+    block: &BitcoinBlock,
+    view: &dyn UtxoView,
+    deployment_state: &DeploymentState,
+    params: &VerificationParams,
+) -> Result<Undo, Error> {
+    let txids = check_block_sanity(deployment_state.height, block)?;
+
+    contextual_check_block(block, &txids, view, deployment_state, params)
+}
+
+/// Performs preliminary checks, returning the txid of each transaction in the block.
+///
+/// - Transaction list must be non-empty.
+/// - Block size must not exceed [`MAX_BLOCK_WEIGHT`].
+/// - First transaction must be coinbase, the rest must not be.
+/// - No duplicate transactions in the block.
+/// - Check the sum of transaction sig opcounts does not exceed [`MAX_BLOCK_SIGOPS_COST`].
+/// - Check the calculated merkle root of transactions matches the one declared in the header.
+///
+/// <https://github.com/bitcoin/bitcoin/blob/6f9db1ebcab4064065ccdCOIN787161bf2b87e03cc1f/src/validation.cpp#L3986>
+pub fn check_block_sanity(block_number: u32, block: &BitcoinBlock) -> Result<Vec<Txid>, Error> {
+    if block.txdata.is_empty() {
+        return Err(Error::EmptyTransactionList);
+    }
+
+    // Size limits, without tx witness data.
+    if Weight::from_wu((block.txdata.len() * WITNESS_SCALE_FACTOR) as u64) > MAX_BLOCK_WEIGHT
+        || Weight::from_wu((block_base_size(block) * WITNESS_SCALE_FACTOR) as u64)
+            > MAX_BLOCK_WEIGHT
+    {
+        return Err(Error::BadBlockLength);
+    }
+
+    if !block.txdata[0].is_coinbase() {
+        return Err(Error::FirstTransactionIsNotCoinbase);
+    }
+
+    // Check duplicate transactions
+    let tx_count = block.txdata.len();
+
+    let mut seen_transactions = HashSet::with_capacity(tx_count);
+    let mut txids = Vec::with_capacity(tx_count);
+
+    let mut sig_ops = 0;
+
+    for (index, tx) in block.txdata.iter().enumerate() {
+        if index > 0 && tx.is_coinbase() {
+            return Err(Error::MultipleCoinbase);
+        }
+
+        let txid = tx.compute_txid();
+        if !seen_transactions.insert(txid) {
+            // If txid is already in the set, we've found a duplicate.
+            return Err(Error::DuplicateTransaction(index));
+        }
+
+        check_transaction_sanity(tx)?;
+
+        sig_ops += get_legacy_sig_op_count(tx);
+
+        txids.push(txid);
+    }
+
+    if sig_ops * WITNESS_SCALE_FACTOR > MAX_BLOCK_SIGOPS_COST as usize {
+        return Err(Error::TooManySigOps { block_number });
+    }
+
+    // Inline `Block::check_merkle_root()` to avoid redundantly computing txid.
+    let hashes = txids.iter().map(|txid| txid.to_raw_hash());
+
+    let maybe_merkle_root: Option<TxMerkleNode> =
+        bitcoin::merkle_tree::calculate_root(hashes).map(|h| h.into());
+
+    if !maybe_merkle_root
+        .map(|merkle_root| block.header.merkle_root == merkle_root)
+        .unwrap_or(false)
+    {
+        return Err(Error::BadMerkleRoot);
+    }
+
+    Ok(txids)
+}
+
+/// Verifies the block passing [`check_block_sanity`] on top of the UTXO set of its parent.
+///
+/// `txids` must be the ones returned by [`check_block_sanity`].
+pub fn contextual_check_block(
+    block: &BitcoinBlock,
+    txids: &[Txid],
+    view: &dyn UtxoView,
+    deployment_state: &DeploymentState,
+    params: &VerificationParams,
+) -> Result<Undo, Error> {
+    if deployment_state.height >= params.chain_params.segwit_height
+        && !block.check_witness_commitment()
+    {
+        return Err(Error::BadWitnessCommitment);
+    }
+
+    // Check the block weight with witness data.
+    if block.weight() > MAX_BLOCK_WEIGHT {
+        return Err(Error::BadBlockLength);
+    }
+
+    verify_transactions(block, txids, view, deployment_state, params)
+}
+
+fn verify_transactions(
+    block: &BitcoinBlock,
+    txids: &[Txid],
+    view: &dyn UtxoView,
+    deployment_state: &DeploymentState,
+    params: &VerificationParams,
+) -> Result<Undo, Error> {
+    let DeploymentState {
+        height: block_number,
+        lock_time_cutoff,
+    } = *deployment_state;
+
+    let chain_params = &params.chain_params;
+
+    let block_hash = block.block_hash();
+
+    let flags = get_block_script_flags(block_number, block_hash, chain_params);
+
+    // BIP30, checked against the parent state as the duplicate txids within the block
+    // have been rejected in `check_block_sanity()`.
+    if chain_params.enforce_bip30(block_number, block_hash) {
+        for (tx, txid) in block.txdata.iter().zip(txids) {
+            for vout in 0..tx.output.len() as u32 {
+                let out_point = OutPoint { txid: *txid, vout };
+                if view.coin(&out_point).is_some() {
+                    return Err(Error::OverwriteUnspentOutput {
+                        block_number,
+                        utxo: out_point,
+                    });
+                }
+            }
+        }
+    }
+
+    let mut block_fee = 0;
+    let mut undo = Undo {
+        spent_coins: Vec::with_capacity(block.txdata.len() - 1),
+        tx_fees: Vec::with_capacity(block.txdata.len() - 1),
+    };
+    let mut spent_utxos = HashSet::new();
+
+    let mut tx_data = Vec::<u8>::new();
+
+    // The script checks are deferred and run on the verification threads once the other
+    // checks of all transactions have passed, like the check queue in Bitcoin Core.
+    // https://github.com/bitcoin/bitcoin/blob/6f9db1ebcab4064065ccd787161bf2b87e03cc1f/src/validation.cpp#L2611
+    let defer_script_checks = params.verify_script && params.script_verification_threads > 1;
+    let mut encoded_txs = Vec::new();
+    let mut script_checks = Vec::new();
+
+    for (tx_index, tx) in block.txdata.iter().enumerate() {
+        if tx_index == 0 {
+            // Enforce rule that the coinbase starts with serialized block height.
+            if block_number >= chain_params.params.bip34_height {
+                let block_height_in_coinbase = block.bip34_block_height()? as u32;
+                if block_height_in_coinbase != block_number {
+                    return Err(Error::BadCoinbaseBlockHeight {
+                        got: block_height_in_coinbase,
+                        expected: block_number,
+                    });
+                }
+            }
+
+            continue;
+        }
+
+        if !is_final(tx, block_number, lock_time_cutoff) {
+            return Err(Error::TransactionNotFinal);
+        }
+
+        tx_data.clear();
+        tx.consensus_encode(&mut tx_data)
+            .map_err(Error::BitcoinCodec)?;
+
+        let spending_transaction = tx_data.as_slice();
+
+        let access_coin = |out_point: OutPoint| -> Option<Coin> {
+            view.coin(&out_point).or_else(|| {
+                find_utxo_in_current_block(block, out_point, tx_index, txids).map(
+                    |(txout, is_coinbase)| Coin {
+                        is_coinbase,
+                        amount: txout.value.to_sat(),
+                        height: block_number,
+                        script_pubkey: txout.script_pubkey.into_bytes(),
+                    },
+                )
+            })
+        };
+
+        // CheckTxInputs.
+        let mut value_in = 0;
+        let mut sig_ops_cost = 0;
+        let mut spent_coins = Vec::with_capacity(tx.input.len());
+
+        for (input_index, input) in tx.input.iter().enumerate() {
+            let out_point = input.previous_output;
+
+            if spent_utxos.contains(&out_point) {
+                return Err(Error::AlreadySpentInCurrentBlock {
+                    block_number,
+                    txid: txids[tx_index],
+                    utxo: out_point,
+                });
+            }
+
+            // Access coin.
+            let coin = access_coin(out_point).ok_or_else(|| Error::UtxoNotFound {
+                block_number,
+                txid: txids[tx_index],
+                utxo: out_point,
+            })?;
+
+            // If coin is coinbase, check that it's matured.
+            if coin.is_coinbase && block_number - coin.height < COINBASE_MATURITY {
+                return Err(Error::PrematureSpendOfCoinbase);
+            }
+
+            if defer_script_checks {
+                script_checks.push(ScriptCheck {
+                    tx_index,
+                    input_index,
+                    spent_output: coin_output(&coin),
+                });
+            } else if params.verify_script {
+                params
+                    .script_interpreters
+                    .verify(
+                        &coin_output(&coin),
+                        &input.script_sig,
+                        spending_transaction,
+                        input_index,
+                        flags,
+                    )
+                    .or_else(ignore_script_failure)
+                    .map_err(|error| Error::InvalidInputScript {
+                        block_number,
+                        txid: txids[tx_index],
+                        input_index,
+                        error,
+                    })?;
+            }
+
+            spent_utxos.insert(out_point);
+            value_in += coin.amount;
+            spent_coins.push(coin);
+        }
+
+        // > GetTransactionSigOpCost counts 3 types of sigops:
+        // > * legacy (always)
+        // > * p2sh (when P2SH enabled in flags and excludes coinbase)
+        // > * witness (when witness enabled in flags and excludes coinbase)
+        sig_ops_cost += tx.total_sigop_cost(|out_point: &OutPoint| {
+            access_coin(*out_point).map(|coin| coin_output(&coin))
+        });
+
+        if sig_ops_cost > MAX_BLOCK_SIGOPS_COST as usize {
+            return Err(Error::TooManySigOps { block_number });
+        }
+
+        let value_out = tx
+            .output
+            .iter()
+            .map(|output| output.value.to_sat())
+            .sum::<u64>();
+
+        // Total input value must be no less than total output value.
+        // Tx fee is the difference between inputs and outputs.
+        let tx_fee = value_in
+            .checked_sub(value_out)
+            .ok_or(Error::InsufficientFunds {
+                value_in,
+                value_out,
+            })?;
+
+        block_fee += tx_fee;
+        undo.tx_fees.push(tx_fee);
+        undo.spent_coins.push(spent_coins);
+
+        if defer_script_checks {
+            encoded_txs.push(tx_data.clone());
+        }
+    }
+
+    run_in_parallel(
+        &script_checks,
+        params.script_verification_threads,
+        |check| {
+            // The coinbase is not encoded.
+            let spending_transaction = encoded_txs[check.tx_index - 1].as_slice();
+            let input = &block.txdata[check.tx_index].input[check.input_index];
+
+            params
+                .script_interpreters
+                .verify(
+                    &check.spent_output,
+                    &input.script_sig,
+                    spending_transaction,
+                    check.input_index,
+                    flags,
+                )
+                .or_else(ignore_script_failure)
+                .map_err(|error| Error::InvalidInputScript {
+                    block_number,
+                    txid: txids[check.tx_index],
+                    input_index: check.input_index,
+                    error,
+                })
+        },
+    )?;
+
+    let coinbase_value = block.txdata[0]
+        .output
+        .iter()
+        .map(|output| output.value.to_sat())
+        .sum::<u64>();
+
+    let subsidy = block_subsidy(block_number, chain_params.subsidy_halving_interval);
+
+    // Ensures no inflation.
+    if coinbase_value > block_fee + subsidy {
+        return Err(Error::InvalidBlockReward);
+    }
+
+    Ok(undo)
+}
+
+/// Verifies a standalone transaction against the UTXO set of the best block, as if the
+/// transaction was included in the next block described by `deployment_state`.
+///
+/// Returns the transaction fee.
+pub fn verify_transaction(
+    tx: &Transaction,
+    view: &dyn UtxoView,
+    deployment_state: &DeploymentState,
+    params: &VerificationParams,
+) -> Result<Amount, Error> {
+    if tx.is_coinbase() {
+        return Err(Error::UnexpectedCoinbase);
+    }
+
+    check_transaction_sanity(tx)?;
+
+    let DeploymentState {
+        height: block_number,
+        lock_time_cutoff,
+    } = *deployment_state;
+
+    if !is_final(tx, block_number, lock_time_cutoff) {
+        return Err(Error::TransactionNotFinal);
+    }
+
+    let txid = tx.compute_txid();
+
+    let mut tx_data = Vec::<u8>::new();
+    tx.consensus_encode(&mut tx_data)
+        .map_err(Error::BitcoinCodec)?;
+
+    // The script flag exceptions are bound to historical blocks, any hash works here.
+    let flags = get_block_script_flags(block_number, BlockHash::all_zeros(), &params.chain_params);
+
+    let mut spent_outputs = HashMap::with_capacity(tx.input.len());
+    let mut value_in = 0;
+
+    for (input_index, input) in tx.input.iter().enumerate() {
+        let out_point = input.previous_output;
+
+        let coin = view.coin(&out_point).ok_or(Error::UtxoNotFound {
+            block_number,
+            txid,
+            utxo: out_point,
+        })?;
+
+        if coin.is_coinbase && block_number - coin.height < COINBASE_MATURITY {
+            return Err(Error::PrematureSpendOfCoinbase);
+        }
+
+        let spent_output = coin_output(&coin);
+
+        if params.verify_script {
+            params.script_interpreters.verify(
+                &spent_output,
+                &input.script_sig,
+                tx_data.as_slice(),
+                input_index,
+                flags,
+            )?;
+        }
+
+        value_in += coin.amount;
+
+        spent_outputs.insert(out_point, spent_output);
+    }
+
+    let sig_ops_cost =
+        tx.total_sigop_cost(|out_point: &OutPoint| spent_outputs.get(out_point).cloned());
+
+    if sig_ops_cost > MAX_BLOCK_SIGOPS_COST as usize {
+        return Err(Error::TooManySigOps { block_number });
+    }
+
+    let value_out = tx
+        .output
+        .iter()
+        .map(|output| output.value.to_sat())
+        .sum::<u64>();
+
+    value_in
+        .checked_sub(value_out)
+        .map(Amount::from_sat)
+        .ok_or(Error::InsufficientFunds {
+            value_in,
+            value_out,
+        })
+}
+
+/// Input script verification deferred to the verification threads.
+struct ScriptCheck {
+    tx_index: usize,
+    input_index: usize,
+    spent_output: TxOut,
+}
+
+/// Minimum number of the checks worth running on a separate thread.
+const MIN_CHECKS_PER_THREAD: usize = 16;
+
+/// Maps [`ScriptError::Failed`] to success, which is tolerated by the block verification.
+fn ignore_script_failure(error: ScriptError) -> Result<(), ScriptError> {
+    match error {
+        ScriptError::Failed => Ok(()),
+        error => Err(error),
+    }
+}
+
+/// Runs `check` over `items` split across up to `threads` threads, returns the error of the
+/// first failed item in order.
+fn run_in_parallel<T, E>(
+    items: &[T],
+    threads: usize,
+    check: impl Fn(&T) -> Result<(), E> + Sync,
+) -> Result<(), E>
+where
+    T: Sync,
+    E: Send,
+{
+    let chunk_size = items
+        .len()
+        .div_ceil(threads.max(1))
+        .max(MIN_CHECKS_PER_THREAD);
+
+    if items.len() <= chunk_size {
+        return items.iter().try_for_each(check);
+    }
+
+    std::thread::scope(|scope| {
+        let check = &check;
+        let handles = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().try_for_each(check)))
+            .collect::<Vec<_>>();
+
+        handles.into_iter().try_for_each(|handle| {
+            handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    })
+}
+
+fn coin_output(coin: &Coin) -> TxOut {
+    TxOut {
+        value: Amount::from_sat(coin.amount),
+        script_pubkey: ScriptBuf::from_bytes(coin.script_pubkey.clone()),
+    }
+}
+
+// Find a UTXO from the previous transactions in current block.
+fn find_utxo_in_current_block(
+    block: &BitcoinBlock,
+    out_point: OutPoint,
+    tx_index: usize,
+    txids: &[Txid],
+) -> Option<(TxOut, bool)> {
+    let OutPoint { txid, vout } = out_point;
+    block
+        .txdata
+        .iter()
+        .take(tx_index)
+        .enumerate()
+        .find_map(|(index, tx)| (txids[index] == txid).then_some((tx, index == 0)))
+        .and_then(|(tx, is_coinbase)| {
+            tx.output
+                .get(vout as usize)
+                .cloned()
+                .map(|txout| (txout, is_coinbase))
+        })
+}
+
+/// Returns the script validation flags for the specified block.
+///
+/// <https://github.com/bitcoin/bitcoin/blob/6f9db1ebcab4064065ccd787161bf2b87e03cc1f/src/validation.cpp#L2360>
+pub fn get_block_script_flags(
+    height: u32,
+    block_hash: BlockHash,
+    chain_params: &ChainParams,
+) -> u32 {
+    if let Some(flag) = chain_params
+        .script_flag_exceptions
+        .get(&block_hash)
+        .copied()
+    {
+        return flag;
+    }
+
+    let mut flags = SCRIPT_VERIFY_P2SH | SCRIPT_VERIFY_WITNESS;
+
+    // Enforce the DERSIG (BIP66) rule
+    if height >= chain_params.params.bip66_height {
+        flags |= SCRIPT_VERIFY_DERSIG;
+    }
+
+    // Enforce CHECKLOCKTIMEVERIFY (BIP65)
+    if height >= chain_params.params.bip65_height {
+        flags |= SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY;
+    }
+
+    // Enforce CHECKSEQUENCEVERIFY (BIP112)
+    if height >= chain_params.csv_height {
+        flags |= SCRIPT_VERIFY_CHECKSEQUENCEVERIFY;
+    }
+
+    // Enforce BIP147 NULLDUMMY (activated simultaneously with segwit)
+    if height >= chain_params.segwit_height {
+        flags |= SCRIPT_VERIFY_NULLDUMMY;
+    }
+
+    flags
+}
+
+/// Returns the base block size.
+///
+/// > Base size is the block size in bytes with the original transaction serialization without
+/// > any witness-related data, as seen by a non-upgraded node.
+// TODO: copied from rust-bitcoin, send a patch upstream to make this API public?
+fn block_base_size(block: &BitcoinBlock) -> usize {
+    let mut size = BitcoinHeader::SIZE;
+
+    size += VarInt::from(block.txdata.len()).size();
+    size += block.txdata.iter().map(|tx| tx.base_size()).sum::<usize>();
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::encode::deserialize_hex;
+
+    fn test_block() -> BitcoinBlock {
+        let test_block = std::env::current_dir()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("test_data")
+            .join("btc_mainnet_385044.data");
+        let raw_block = std::fs::read_to_string(test_block).unwrap();
+        deserialize_hex::<BitcoinBlock>(raw_block.trim()).unwrap()
+    }
+
+    #[test]
+    fn test_find_utxo_in_current_block() {
+        let block = test_block();
+
+        let txids = block
+            .txdata
+            .iter()
+            .map(|tx| tx.compute_txid())
+            .collect::<Vec<_>>();
+
+        // 385044:35:1
+        let out_point = OutPoint {
+            txid: "2b102a19161e5c93f71e16f9e8c9b2438f362c51ecc8f2a62e3c31d7615dd17d"
+                .parse()
+                .unwrap(),
+            vout: 1,
+        };
+
+        // The input of block 385044:36 is from the previous transaction 385044:35:1.
+        // https://www.blockchain.com/explorer/transactions/btc/5645cb0a3953b7766836919566b25321a976d06c958e69ff270358233a8c82d6
+        assert_eq!(
+            find_utxo_in_current_block(&block, out_point, 36, &txids)
+                .map(|(txout, is_coinbase)| (txout.value.to_sat(), is_coinbase))
+                .unwrap(),
+            (295600000, false)
+        );
+    }
+
+    #[test]
+    fn test_verify_block_against_utxo_view() {
+        let block = test_block();
+        let mut params = VerificationParams::new(Network::Bitcoin);
+        params.verify_script = false;
+
+        let deployment_state = DeploymentState {
+            height: 385044,
+            lock_time_cutoff: block.header.time,
+        };
+
+        // The coins spent by the block are missing from an empty view.
+        let empty = HashMap::<OutPoint, Coin>::new();
+        assert!(matches!(
+            verify_block(&block, &empty, &deployment_state, &params),
+            Err(Error::UtxoNotFound {
+                block_number: 385044,
+                ..
+            })
+        ));
+
+        // A mutated block.
+        let mut mutated = block.clone();
+        mutated.txdata.swap(1, 2);
+        let err = verify_block(&mutated, &empty, &deployment_state, &params).unwrap_err();
+        assert!(matches!(err, Error::BadMerkleRoot));
+        assert!(!err.invalidates_block());
+    }
+
+    #[test]
+    fn test_run_in_parallel_reports_first_failure() {
+        let items = (0..1000).collect::<Vec<usize>>();
+        let check = |item: &usize| match item {
+            370 | 800 => Err(*item),
+            _ => Ok(()),
+        };
+
+        for threads in [1, 4, 64] {
+            assert_eq!(run_in_parallel(&items, threads, check), Err(370));
+            assert_eq!(run_in_parallel(&items[..300], threads, check), Ok(()));
+        }
+    }
+}
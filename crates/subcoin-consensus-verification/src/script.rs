@@ -0,0 +1,223 @@
+//! Script verification.
+//!
+//! The spending conditions are verified by a [`ScriptInterpreter`] selected per
+//! [`ScriptFamily`], so that the interpreter of each family, e.g., a tapscript-aware one for
+//! the taproot spends, can be swapped independently of the others. The FFI bindings to the
+//! interpreter of Bitcoin Core are used for all the families by default, available with the
+//! `bitcoinconsensus` feature.
+//!
+//! The flags are the `SCRIPT_VERIFY_*` flags of Bitcoin Core, an interpreter only enforces the
+//! flags reported by [`ScriptInterpreter::supported_flags`].
+
+#[cfg(all(test, feature = "bitcoinconsensus"))]
+mod conformance;
+
+use bitcoin::{Script, TxOut};
+use std::sync::Arc;
+
+/// Evaluate P2SH subscripts (BIP16).
+pub const SCRIPT_VERIFY_P2SH: u32 = 1 << 0;
+/// Enforce strict DER signatures (BIP66).
+pub const SCRIPT_VERIFY_DERSIG: u32 = 1 << 2;
+/// Enforce the dummy stack item of OP_CHECKMULTISIG to be empty (BIP147).
+pub const SCRIPT_VERIFY_NULLDUMMY: u32 = 1 << 4;
+/// Enable OP_CHECKLOCKTIMEVERIFY (BIP65).
+pub const SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9;
+/// Enable OP_CHECKSEQUENCEVERIFY (BIP112).
+pub const SCRIPT_VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10;
+/// Enable the segregated witness (BIP141).
+pub const SCRIPT_VERIFY_WITNESS: u32 = 1 << 11;
+/// Enable taproot and tapscript (BIP341, BIP342).
+pub const SCRIPT_VERIFY_TAPROOT: u32 = 1 << 17;
+
+/// No script verification flags.
+pub const SCRIPT_VERIFY_NONE: u32 = 0;
+
+/// Consensus flags prior to taproot.
+pub const SCRIPT_VERIFY_ALL_PRE_TAPROOT: u32 = SCRIPT_VERIFY_P2SH
+    | SCRIPT_VERIFY_DERSIG
+    | SCRIPT_VERIFY_NULLDUMMY
+    | SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY
+    | SCRIPT_VERIFY_CHECKSEQUENCEVERIFY
+    | SCRIPT_VERIFY_WITNESS;
+
+/// Script verification error.
+#[derive(Debug, thiserror::Error)]
+pub enum ScriptError {
+    /// The script evaluated to false or failed.
+    #[error("Script verification failed")]
+    Failed,
+    /// No interpreter is configured for the script family.
+    #[error("No script interpreter for {0:?} scripts")]
+    NoInterpreter(ScriptFamily),
+    /// The interpreter could not verify the script, e.g., due to the invalid input.
+    #[error("Script interpreter error: {0}")]
+    Interpreter(String),
+}
+
+/// Family of the spending condition, each family is verified by its own interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptFamily {
+    /// Bare scripts and P2SH.
+    Legacy,
+    /// Native and P2SH-wrapped segwit v0.
+    WitnessV0,
+    /// Taproot key path and tapscript spends.
+    Taproot,
+}
+
+impl ScriptFamily {
+    /// Returns the family of the spend of `script_pubkey` with `script_sig`.
+    pub fn of(script_pubkey: &Script, script_sig: &Script) -> Self {
+        if script_pubkey.is_p2tr() {
+            return Self::Taproot;
+        }
+
+        if script_pubkey.is_p2wpkh() || script_pubkey.is_p2wsh() {
+            return Self::WitnessV0;
+        }
+
+        // The redeem script of a P2SH-wrapped witness program is the only push of `script_sig`.
+        if script_pubkey.is_p2sh() {
+            let redeem_script = match script_sig.instructions().next() {
+                Some(Ok(bitcoin::script::Instruction::PushBytes(bytes))) => {
+                    Script::from_bytes(bytes.as_bytes())
+                }
+                _ => return Self::Legacy,
+            };
+
+            if redeem_script.is_p2wpkh() || redeem_script.is_p2wsh() {
+                return Self::WitnessV0;
+            }
+        }
+
+        Self::Legacy
+    }
+}
+
+/// Interpreter verifying the spending conditions.
+pub trait ScriptInterpreter: Send + Sync {
+    /// Returns the script verification flags enforced by this interpreter.
+    fn supported_flags(&self) -> u32;
+
+    /// Verifies the input `input_index` of the consensus-encoded `spending_transaction` spending
+    /// an output of `amount` satoshis locked by `script_pubkey`.
+    fn verify(
+        &self,
+        script_pubkey: &[u8],
+        amount: u64,
+        spending_transaction: &[u8],
+        input_index: usize,
+        flags: u32,
+    ) -> Result<(), ScriptError>;
+}
+
+/// Interpreter of Bitcoin Core through `libbitcoinconsensus`.
+///
+/// The taproot spends are not verified as the library does not support taproot.
+#[cfg(feature = "bitcoinconsensus")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CoreInterpreter;
+
+#[cfg(feature = "bitcoinconsensus")]
+impl ScriptInterpreter for CoreInterpreter {
+    fn supported_flags(&self) -> u32 {
+        SCRIPT_VERIFY_ALL_PRE_TAPROOT
+    }
+
+    fn verify(
+        &self,
+        script_pubkey: &[u8],
+        amount: u64,
+        spending_transaction: &[u8],
+        input_index: usize,
+        flags: u32,
+    ) -> Result<(), ScriptError> {
+        bitcoinconsensus::verify_with_flags(
+            script_pubkey,
+            amount,
+            spending_transaction,
+            input_index,
+            flags,
+        )
+        .map_err(|err| match err {
+            bitcoinconsensus::Error::ERR_SCRIPT => ScriptError::Failed,
+            err => ScriptError::Interpreter(format!("{err:?}")),
+        })
+    }
+}
+
+/// Interpreters of each [`ScriptFamily`].
+#[derive(Clone)]
+pub struct ScriptInterpreters {
+    legacy: Option<Arc<dyn ScriptInterpreter>>,
+    witness_v0: Option<Arc<dyn ScriptInterpreter>>,
+    taproot: Option<Arc<dyn ScriptInterpreter>>,
+}
+
+impl Default for ScriptInterpreters {
+    /// Uses [`CoreInterpreter`] for all the families if available.
+    fn default() -> Self {
+        #[cfg(feature = "bitcoinconsensus")]
+        let interpreter = Some(Arc::new(CoreInterpreter) as Arc<dyn ScriptInterpreter>);
+        #[cfg(not(feature = "bitcoinconsensus"))]
+        let interpreter = None;
+
+        Self {
+            legacy: interpreter.clone(),
+            witness_v0: interpreter.clone(),
+            taproot: interpreter,
+        }
+    }
+}
+
+impl ScriptInterpreters {
+    /// Sets the interpreter of `family`.
+    pub fn with_interpreter(
+        mut self,
+        family: ScriptFamily,
+        interpreter: Arc<dyn ScriptInterpreter>,
+    ) -> Self {
+        match family {
+            ScriptFamily::Legacy => self.legacy.replace(interpreter),
+            ScriptFamily::WitnessV0 => self.witness_v0.replace(interpreter),
+            ScriptFamily::Taproot => self.taproot.replace(interpreter),
+        };
+        self
+    }
+
+    /// Returns the interpreter of `family`.
+    pub fn interpreter(&self, family: ScriptFamily) -> Option<&Arc<dyn ScriptInterpreter>> {
+        match family {
+            ScriptFamily::Legacy => self.legacy.as_ref(),
+            ScriptFamily::WitnessV0 => self.witness_v0.as_ref(),
+            ScriptFamily::Taproot => self.taproot.as_ref(),
+        }
+    }
+
+    /// Verifies the input `input_index` of `spending_transaction` spending `spent_output`.
+    ///
+    /// The flags not supported by the interpreter of the family are ignored.
+    pub fn verify(
+        &self,
+        spent_output: &TxOut,
+        script_sig: &Script,
+        spending_transaction: &[u8],
+        input_index: usize,
+        flags: u32,
+    ) -> Result<(), ScriptError> {
+        let family = ScriptFamily::of(&spent_output.script_pubkey, script_sig);
+
+        let interpreter = self
+            .interpreter(family)
+            .ok_or(ScriptError::NoInterpreter(family))?;
+
+        interpreter.verify(
+            spent_output.script_pubkey.as_bytes(),
+            spent_output.value.to_sat(),
+            spending_transaction,
+            input_index,
+            flags & interpreter.supported_flags(),
+        )
+    }
+}
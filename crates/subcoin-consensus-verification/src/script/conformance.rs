@@ -0,0 +1,473 @@
+//! Conformance of the script interpreters with the test vectors of Bitcoin Core.
+//!
+//! A subset of `script_tests.json` is embedded and always checked. The complete
+//! `script_tests.json`, `tx_valid.json` and `tx_invalid.json` from `src/test/data` of Bitcoin
+//! Core are checked as well when `BITCOIN_CORE_TEST_DATA` points to a directory containing
+//! them. The vectors requiring a flag not enforced by the interpreter are skipped, only the
+//! outcome of the verification is compared, not the exact script error.
+
+use super::{ScriptError, ScriptInterpreters, SCRIPT_VERIFY_ALL_PRE_TAPROOT};
+use crate::tx_verify::check_transaction_sanity;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hex::FromHex;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    absolute, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const BITCOIN_CORE_TEST_DATA: &str = "BITCOIN_CORE_TEST_DATA";
+
+/// Vectors in the format of `script_tests.json`.
+const EMBEDDED_SCRIPT_TESTS: &str = r#"[
+    ["Format: [[wit..., amount]?, scriptSig, scriptPubKey, flags, expected_scripterror, ... comments]"],
+    ["", "DEPTH 0 EQUAL", "P2SH", "OK"],
+    ["0x01 0x0b", "11 EQUAL", "P2SH", "OK"],
+    ["0", "", "P2SH", "EVAL_FALSE"],
+    ["1 2", "2 EQUALVERIFY 1 EQUAL", "P2SH", "OK"],
+    ["'Az'", "0x02 0x417a EQUAL", "P2SH", "OK"],
+    ["1", "NOP", "P2SH", "OK"],
+    ["1", "RETURN", "P2SH", "OP_RETURN"],
+    ["0x4c01 0x01", "1 EQUAL", "P2SH", "OK"],
+    ["0x01 0x51", "HASH160 0x14 0xda1745e9b549bd0bfa1a569971c77eba30cd5a4b EQUAL", "P2SH", "OK"],
+    ["0x01 0x50", "HASH160 0x14 0xece424a6bb6ddf4db592c0faed60685047a361b1 EQUAL", "", "OK", "P2SH not enforced"],
+    ["0x01 0x50", "HASH160 0x14 0xece424a6bb6ddf4db592c0faed60685047a361b1 EQUAL", "P2SH", "BAD_OPCODE"],
+    [["51", 0.00000000], "", "0 0x20 0x4ae81572f06e1b88fd5ced7a1a000945432e83e1551e6f721ee9c00b8cc33260", "P2SH,WITNESS", "OK"]
+]"#;
+
+#[derive(Debug, Default)]
+struct Report {
+    passed: usize,
+    skipped: usize,
+    failures: Vec<String>,
+}
+
+impl Report {
+    fn check(&mut self, passed: bool, vector: &Value) {
+        if passed {
+            self.passed += 1;
+        } else {
+            self.failures.push(vector.to_string());
+        }
+    }
+
+    fn assert_passed(self, name: &str) {
+        println!(
+            "{name}: {} passed, {} skipped, {} failed",
+            self.passed,
+            self.skipped,
+            self.failures.len()
+        );
+        assert!(
+            self.failures.is_empty(),
+            "{name}: failed vectors:\n{}",
+            self.failures.join("\n")
+        );
+    }
+}
+
+/// Returns the script verification flag of Bitcoin Core named `name`.
+fn parse_flag(name: &str) -> Option<u32> {
+    let bit = match name {
+        "NONE" | "" => return Some(0),
+        "P2SH" => 0,
+        "STRICTENC" => 1,
+        "DERSIG" => 2,
+        "LOW_S" => 3,
+        "NULLDUMMY" => 4,
+        "SIGPUSHONLY" => 5,
+        "MINIMALDATA" => 6,
+        "DISCOURAGE_UPGRADABLE_NOPS" => 7,
+        "CLEANSTACK" => 8,
+        "CHECKLOCKTIMEVERIFY" => 9,
+        "CHECKSEQUENCEVERIFY" => 10,
+        "WITNESS" => 11,
+        "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM" => 12,
+        "MINIMALIF" => 13,
+        "NULLFAIL" => 14,
+        "WITNESS_PUBKEYTYPE" => 15,
+        "CONST_SCRIPTCODE" => 16,
+        "TAPROOT" => 17,
+        "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION" => 18,
+        "DISCOURAGE_OP_SUCCESS" => 19,
+        "DISCOURAGE_UPGRADABLE_PUBKEYTYPE" => 20,
+        _ => return None,
+    };
+    Some(1 << bit)
+}
+
+/// Parses the comma-separated flags, `None` if any flag is unknown.
+fn parse_flags(flags: &str) -> Option<u32> {
+    flags
+        .split(',')
+        .try_fold(0, |acc, name| Some(acc | parse_flag(name.trim())?))
+}
+
+/// Names of the opcodes accepted by the script parser of Bitcoin Core.
+fn opcode_names() -> HashMap<String, u8> {
+    let mut names = HashMap::new();
+
+    // The pushes are written as numbers or raw bytes, except OP_RESERVED.
+    for byte in (0x61..=0xff).chain([0x50]) {
+        let name = Opcode::from(byte).to_string();
+        if let Some(short_name) = name.strip_prefix("OP_") {
+            names.insert(short_name.to_string(), byte);
+        }
+        names.insert(name, byte);
+    }
+
+    for (name, byte) in [
+        ("NOP2", 0xb1),
+        ("CHECKLOCKTIMEVERIFY", 0xb1),
+        ("NOP3", 0xb2),
+        ("CHECKSEQUENCEVERIFY", 0xb2),
+    ] {
+        names.insert(name.to_string(), byte);
+        names.insert(format!("OP_{name}"), byte);
+    }
+
+    names
+}
+
+/// Parses a script in the asm format of the test vectors of Bitcoin Core.
+fn parse_script(asm: &str, opcode_names: &HashMap<String, u8>) -> Result<ScriptBuf, String> {
+    let mut script = Vec::new();
+
+    for token in asm.split_whitespace() {
+        let is_number = token
+            .strip_prefix('-')
+            .unwrap_or(token)
+            .chars()
+            .all(|c| c.is_ascii_digit());
+
+        if is_number {
+            let n = token
+                .parse::<i64>()
+                .map_err(|err| format!("Invalid number {token}: {err}"))?;
+            script.extend(Builder::new().push_int(n).into_bytes());
+        } else if let Some(hex) = token.strip_prefix("0x") {
+            let bytes = Vec::<u8>::from_hex(hex).map_err(|err| format!("{token}: {err}"))?;
+            script.extend(bytes);
+        } else if let Some(s) = token
+            .strip_prefix('\'')
+            .and_then(|token| token.strip_suffix('\''))
+        {
+            let data = PushBytesBuf::try_from(s.as_bytes().to_vec())
+                .map_err(|err| format!("{token}: {err}"))?;
+            script.extend(Builder::new().push_slice(data).into_bytes());
+        } else if let Some(byte) = opcode_names.get(token) {
+            script.push(*byte);
+        } else {
+            return Err(format!("Unknown opcode {token}"));
+        }
+    }
+
+    Ok(ScriptBuf::from_bytes(script))
+}
+
+/// Returns the transaction crediting `script_pubkey` and the one spending it, built the same
+/// way as in the script tests of Bitcoin Core.
+fn build_spend(
+    script_pubkey: ScriptBuf,
+    script_sig: ScriptBuf,
+    witness: Witness,
+    amount: Amount,
+) -> (TxOut, Transaction) {
+    let credit = Transaction {
+        version: Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new().push_int(0).push_int(0).into_script(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: amount,
+            script_pubkey,
+        }],
+    };
+
+    let spend = Transaction {
+        version: Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(credit.compute_txid(), 0),
+            script_sig,
+            sequence: Sequence::MAX,
+            witness,
+        }],
+        output: vec![TxOut {
+            value: amount,
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+
+    (credit.output[0].clone(), spend)
+}
+
+fn btc_to_amount(value: &Value) -> Option<Amount> {
+    Some(Amount::from_sat(
+        (value.as_f64()? * 100_000_000.0).round() as u64
+    ))
+}
+
+fn run_script_tests(vectors: &[Value], interpreters: &ScriptInterpreters) -> Report {
+    let opcode_names = opcode_names();
+    let supported_flags = SCRIPT_VERIFY_ALL_PRE_TAPROOT;
+    let mut report = Report::default();
+
+    for vector in vectors {
+        let Some(fields) = vector.as_array() else {
+            continue;
+        };
+
+        // Witness and amount come first if present.
+        let (witness, amount, fields) = match fields.first() {
+            Some(Value::Array(witness)) => {
+                let Some((amount, items)) = witness.split_last() else {
+                    report.failures.push(vector.to_string());
+                    continue;
+                };
+                let items = items
+                    .iter()
+                    .map(|item| item.as_str().unwrap_or_default())
+                    .collect::<Vec<_>>();
+                // Taproot vectors are templates filled in by the test of Bitcoin Core.
+                if items.iter().any(|item| item.contains('#')) {
+                    report.skipped += 1;
+                    continue;
+                }
+                let Some(items) = items
+                    .into_iter()
+                    .map(|item| Vec::<u8>::from_hex(item).ok())
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    report.failures.push(vector.to_string());
+                    continue;
+                };
+                let Some(amount) = btc_to_amount(amount) else {
+                    report.failures.push(vector.to_string());
+                    continue;
+                };
+                (Witness::from_slice(&items), amount, &fields[1..])
+            }
+            _ => (Witness::new(), Amount::ZERO, &fields[..]),
+        };
+
+        // Comments.
+        if fields.len() < 4 {
+            continue;
+        }
+
+        let (Some(script_sig), Some(script_pubkey), Some(flags), Some(expected)) = (
+            fields[0].as_str(),
+            fields[1].as_str(),
+            fields[2].as_str(),
+            fields[3].as_str(),
+        ) else {
+            report.failures.push(vector.to_string());
+            continue;
+        };
+
+        let Some(flags) = parse_flags(flags).filter(|flags| flags & !supported_flags == 0) else {
+            report.skipped += 1;
+            continue;
+        };
+
+        let (Ok(script_sig), Ok(script_pubkey)) = (
+            parse_script(script_sig, &opcode_names),
+            parse_script(script_pubkey, &opcode_names),
+        ) else {
+            report.failures.push(vector.to_string());
+            continue;
+        };
+
+        let (spent_output, spend) = build_spend(script_pubkey, script_sig, witness, amount);
+
+        let result = interpreters.verify(
+            &spent_output,
+            &spend.input[0].script_sig,
+            &serialize(&spend),
+            0,
+            flags,
+        );
+
+        let passed = match result {
+            Ok(()) => expected == "OK",
+            Err(ScriptError::Failed) => expected != "OK",
+            Err(_) => false,
+        };
+
+        report.check(passed, vector);
+    }
+
+    report
+}
+
+/// Outcome of the verification of all the inputs of a transaction from the tx tests.
+enum TxOutcome {
+    Valid,
+    /// Failed the sanity checks.
+    BadTx,
+    /// Failed the script verification.
+    ScriptFailed,
+}
+
+fn verify_tx(
+    prevouts: &[Value],
+    tx_hex: &str,
+    flags: u32,
+    opcode_names: &HashMap<String, u8>,
+    interpreters: &ScriptInterpreters,
+) -> Result<TxOutcome, String> {
+    let mut spent_outputs = HashMap::new();
+
+    for prevout in prevouts {
+        let fields = prevout
+            .as_array()
+            .filter(|fields| fields.len() >= 3)
+            .ok_or_else(|| format!("Invalid prevout {prevout}"))?;
+
+        let txid = fields[0]
+            .as_str()
+            .and_then(|txid| txid.parse::<Txid>().ok())
+            .ok_or_else(|| format!("Invalid prevout txid {}", fields[0]))?;
+        // -1 is the index of the null outpoint.
+        let vout = fields[1]
+            .as_i64()
+            .map(|vout| vout as u32)
+            .ok_or_else(|| format!("Invalid prevout index {}", fields[1]))?;
+        let script_pubkey = parse_script(fields[2].as_str().unwrap_or_default(), opcode_names)?;
+        let value = fields
+            .get(3)
+            .and_then(Value::as_u64)
+            .map(Amount::from_sat)
+            .unwrap_or(Amount::ZERO);
+
+        spent_outputs.insert(
+            OutPoint::new(txid, vout),
+            TxOut {
+                value,
+                script_pubkey,
+            },
+        );
+    }
+
+    let tx_bytes = Vec::<u8>::from_hex(tx_hex).map_err(|err| err.to_string())?;
+    let tx = deserialize::<Transaction>(&tx_bytes).map_err(|err| err.to_string())?;
+
+    if check_transaction_sanity(&tx).is_err() {
+        return Ok(TxOutcome::BadTx);
+    }
+
+    for (input_index, input) in tx.input.iter().enumerate() {
+        let spent_output = spent_outputs
+            .get(&input.previous_output)
+            .ok_or_else(|| format!("Missing prevout {}", input.previous_output))?;
+
+        match interpreters.verify(
+            spent_output,
+            &input.script_sig,
+            &tx_bytes,
+            input_index,
+            flags,
+        ) {
+            Ok(()) => {}
+            Err(ScriptError::Failed) => return Ok(TxOutcome::ScriptFailed),
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+
+    Ok(TxOutcome::Valid)
+}
+
+/// Runs the vectors of `tx_valid.json` if `valid`, otherwise of `tx_invalid.json`.
+fn run_tx_tests(vectors: &[Value], valid: bool, interpreters: &ScriptInterpreters) -> Report {
+    let opcode_names = opcode_names();
+    let supported_flags = SCRIPT_VERIFY_ALL_PRE_TAPROOT;
+    let mut report = Report::default();
+
+    for vector in vectors {
+        // Comments have a string as the first element.
+        let Some([Value::Array(prevouts), Value::String(tx_hex), Value::String(flags), ..]) =
+            vector.as_array().map(Vec::as_slice)
+        else {
+            continue;
+        };
+
+        let is_bad_tx = flags.split(',').any(|flag| flag == "BADTX");
+        let Some(flags) = parse_flags(&flags.replace("BADTX", "")) else {
+            report.skipped += 1;
+            continue;
+        };
+
+        let flags = if valid {
+            // The flags of the valid vectors are the ones excluded.
+            supported_flags & !flags
+        } else if flags & !supported_flags != 0 {
+            report.skipped += 1;
+            continue;
+        } else {
+            flags
+        };
+
+        let passed = match verify_tx(prevouts, tx_hex, flags, &opcode_names, interpreters) {
+            Ok(TxOutcome::Valid) => valid,
+            Ok(TxOutcome::BadTx) => !valid && is_bad_tx,
+            Ok(TxOutcome::ScriptFailed) => !valid && !is_bad_tx,
+            // Undecodable transactions are rejected.
+            Err(_) => !valid && is_bad_tx,
+        };
+
+        report.check(passed, vector);
+    }
+
+    report
+}
+
+/// Loads a vector file of Bitcoin Core, `None` if the test data is not configured.
+fn load_core_vectors(file_name: &str) -> Option<Vec<Value>> {
+    let dir = std::env::var_os(BITCOIN_CORE_TEST_DATA)?;
+    let path = PathBuf::from(dir).join(file_name);
+    let content = std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Failed to read {}: {err}", path.display()));
+    Some(
+        serde_json::from_str(&content)
+            .unwrap_or_else(|err| panic!("Failed to parse {}: {err}", path.display())),
+    )
+}
+
+#[test]
+fn test_embedded_script_vectors() {
+    let vectors: Vec<Value> = serde_json::from_str(EMBEDDED_SCRIPT_TESTS).unwrap();
+    let report = run_script_tests(&vectors, &ScriptInterpreters::default());
+    assert_eq!(report.passed, 12);
+    report.assert_passed("embedded script_tests");
+}
+
+#[test]
+fn test_core_script_vectors() {
+    let Some(vectors) = load_core_vectors("script_tests.json") else {
+        println!("{BITCOIN_CORE_TEST_DATA} not set, skipping script_tests.json");
+        return;
+    };
+    run_script_tests(&vectors, &ScriptInterpreters::default()).assert_passed("script_tests");
+}
+
+#[test]
+fn test_core_tx_vectors() {
+    let interpreters = ScriptInterpreters::default();
+
+    for (file_name, valid) in [("tx_valid.json", true), ("tx_invalid.json", false)] {
+        let Some(vectors) = load_core_vectors(file_name) else {
+            println!("{BITCOIN_CORE_TEST_DATA} not set, skipping {file_name}");
+            continue;
+        };
+        run_tx_tests(&vectors, valid, &interpreters).assert_passed(file_name);
+    }
+}
@@ -1,4 +1,4 @@
-use super::MAX_BLOCK_WEIGHT;
+use crate::MAX_BLOCK_WEIGHT;
 use bitcoin::absolute::{LockTime, LOCK_TIME_THRESHOLD};
 use bitcoin::blockdata::weight::WITNESS_SCALE_FACTOR;
 use bitcoin::{Amount, Transaction, Weight};
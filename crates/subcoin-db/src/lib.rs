@@ -0,0 +1,350 @@
+//! # Subcoin Database
+//!
+//! The data maintained by subcoin is split by kind:
+//!
+//! - The UTXO set lives in the Substrate state, the block bodies in the block body column of
+//!   the Substrate database. The undo data of a block is simply the state of its parent, which
+//!   is kept as long as the state pruning allows.
+//! - The data written along with the block import (the block hash mapping, chain work and
+//!   headers) stays in the aux column of the Substrate database, so that it's committed
+//!   atomically with the block.
+//! - Everything else, whose lifecycle is independent of the block import, is stored in the
+//!   dedicated column database provided by this crate, one column per kind of data. This
+//!   keeps the aux column small and allows the wallet, snapshots, filters and indexes to be
+//!   rebuilt or dropped without touching the chain data.
+//!
+//! Columns are only ever appended, the databases created by an older version are upgraded
+//! by adding the missing columns on open.
+//!
+//! ## Migration
+//!
+//! Nodes created before the introduction of this database kept the wallet state and
+//! snapshots in the aux column. [`SubcoinDb::migrate_from_aux`] moves the entries over on
+//! first access: the entries are written to the new database before being deleted from the
+//! aux column, an interrupted migration is simply resumed on the next startup.
+//!
+//! The formats of the data themselves, e.g., the layout of an index, are versioned
+//! separately per [`Schema`] and upgraded by the [`Migrator`] on startup.
+
+mod migration;
+mod parity_db;
+
+use sc_client_api::AuxStore;
+use sp_database::{ColumnId, Database, MemDb};
+use std::path::Path;
+use std::sync::Arc;
+
+pub use self::migration::{MigrateFn, Migration, Migrator, PendingMigration, Schema};
+pub use self::parity_db::ReadOnlyDb;
+pub use sp_database::Transaction;
+
+/// Columns of [`SubcoinDb`].
+pub mod columns {
+    use sp_database::ColumnId;
+
+    /// Metadata of the database itself.
+    pub const META: ColumnId = 0;
+    /// UTXO set snapshots served to the network.
+    pub const SNAPSHOTS: ColumnId = 1;
+    /// State of the built-in wallet.
+    pub const WALLET: ColumnId = 2;
+    /// Compact block filters (BIP 158).
+    pub const FILTERS: ColumnId = 3;
+    /// Optional indexes of the chain data.
+    pub const INDEXES: ColumnId = 4;
+    /// Known peer addresses of the Bitcoin p2p network.
+    pub const PEERS: ColumnId = 5;
+}
+
+pub(crate) const NUM_COLUMNS: u32 = 6;
+
+/// Hash type of the database, required by [`Database`] but unused.
+pub type DbHash = [u8; 32];
+
+/// Key of the database version in [`columns::META`].
+const VERSION_KEY: &[u8] = b"version";
+
+/// Current version of the database layout.
+///
+/// - 1: initial layout.
+/// - 2: [`columns::PEERS`] added.
+pub const DB_VERSION: u32 = 2;
+
+/// Database error type.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Database version {0} is newer than the supported version {DB_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("Corrupted database version")]
+    CorruptedVersion,
+    #[error("Schema {schema} version {version} is newer than the supported version {supported}")]
+    UnsupportedSchemaVersion {
+        schema: &'static str,
+        version: u32,
+        supported: u32,
+    },
+    #[error("No migration of schema {schema} to version {version}")]
+    MissingMigration { schema: &'static str, version: u32 },
+    #[error("Migration failed: {0}")]
+    Migration(String),
+    #[error("Database not found")]
+    NotFound,
+    #[error("Database is opened read-only")]
+    ReadOnly,
+    #[error("Reference counted changes are not supported")]
+    UnsupportedChange,
+    #[error(transparent)]
+    ParityDb(#[from] ::parity_db::Error),
+    #[error(transparent)]
+    Database(#[from] sp_database::error::DatabaseError),
+    #[error(transparent)]
+    Blockchain(#[from] sp_blockchain::Error),
+}
+
+/// Column database of the subcoin data not tied to the block import.
+#[derive(Clone)]
+pub struct SubcoinDb {
+    db: Arc<dyn Database<DbHash>>,
+}
+
+impl std::fmt::Debug for SubcoinDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubcoinDb").finish_non_exhaustive()
+    }
+}
+
+impl SubcoinDb {
+    /// Opens the database at given path, creating it if it does not exist.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::init(Arc::new(parity_db::DbAdapter::open(path)?))
+    }
+
+    /// Creates an in-memory database.
+    pub fn in_memory() -> Self {
+        Self::init(Arc::new(MemDb::default())).expect("Fresh in-memory database is valid; qed")
+    }
+
+    /// Opens the existing database at given path read-only, any write fails.
+    pub fn open_read_only(path: &Path) -> Result<Self, Error> {
+        let db = Self {
+            db: Arc::new(ReadOnlyDb::open(path)?),
+        };
+        db.version()?;
+        Ok(db)
+    }
+
+    fn init(db: Arc<dyn Database<DbHash>>) -> Result<Self, Error> {
+        let db = Self { db };
+
+        match db.version()? {
+            Some(version) if version < DB_VERSION => {
+                // The new columns have been added when opening the database.
+                db.insert(columns::META, VERSION_KEY, &DB_VERSION.to_le_bytes())?;
+                tracing::info!("Upgraded subcoin database from version {version} to {DB_VERSION}");
+            }
+            Some(_) => {}
+            None => {
+                db.insert(columns::META, VERSION_KEY, &DB_VERSION.to_le_bytes())?;
+            }
+        }
+
+        Ok(db)
+    }
+
+    /// Returns the version of the database layout, `None` if the database is new.
+    fn version(&self) -> Result<Option<u32>, Error> {
+        let Some(encoded) = self.get(columns::META, VERSION_KEY) else {
+            return Ok(None);
+        };
+
+        let version = u32::from_le_bytes(
+            encoded
+                .as_slice()
+                .try_into()
+                .map_err(|_| Error::CorruptedVersion)?,
+        );
+
+        if version > DB_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        Ok(Some(version))
+    }
+
+    /// Returns the value of given key.
+    pub fn get(&self, col: ColumnId, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.get(col, key)
+    }
+
+    /// Inserts a single value.
+    pub fn insert(&self, col: ColumnId, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let mut transaction = Transaction::new();
+        transaction.set(col, key, value);
+        self.commit(transaction)
+    }
+
+    /// Removes a single value.
+    pub fn remove(&self, col: ColumnId, key: &[u8]) -> Result<(), Error> {
+        let mut transaction = Transaction::new();
+        transaction.remove(col, key);
+        self.commit(transaction)
+    }
+
+    /// Commits the changes atomically.
+    pub fn commit(&self, transaction: Transaction<DbHash>) -> Result<(), Error> {
+        self.db.commit(transaction).map_err(Into::into)
+    }
+
+    /// Moves the given entries from the aux column into `col`.
+    ///
+    /// Returns the number of entries migrated.
+    pub fn migrate_from_aux(
+        &self,
+        aux: &impl AuxStore,
+        col: ColumnId,
+        keys: &[&[u8]],
+    ) -> Result<usize, Error> {
+        let mut transaction = Transaction::new();
+        let mut migrated = Vec::new();
+
+        for key in keys {
+            if let Some(value) = aux.get_aux(key)? {
+                transaction.set(col, key, &value);
+                migrated.push(*key);
+            }
+        }
+
+        if migrated.is_empty() {
+            return Ok(0);
+        }
+
+        self.commit(transaction)?;
+        aux.insert_aux([], migrated.iter())?;
+
+        tracing::info!(
+            "Migrated {} entries from aux storage to column {col}",
+            migrated.len()
+        );
+
+        Ok(migrated.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct TestAux(Mutex<HashMap<Vec<u8>, Vec<u8>>>);
+
+    impl AuxStore for TestAux {
+        fn insert_aux<
+            'a,
+            'b: 'a,
+            'c: 'a,
+            I: IntoIterator<Item = &'a (&'c [u8], &'c [u8])>,
+            D: IntoIterator<Item = &'a &'b [u8]>,
+        >(
+            &self,
+            insert: I,
+            delete: D,
+        ) -> sp_blockchain::Result<()> {
+            let mut map = self.0.lock();
+            for (k, v) in insert {
+                map.insert(k.to_vec(), v.to_vec());
+            }
+            for k in delete {
+                map.remove(*k);
+            }
+            Ok(())
+        }
+
+        fn get_aux(&self, key: &[u8]) -> sp_blockchain::Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().get(key).cloned())
+        }
+    }
+
+    #[test]
+    fn test_migrate_from_aux() {
+        let db = SubcoinDb::in_memory();
+        let aux = TestAux::default();
+        aux.insert_aux(&[(&b"a"[..], &b"1"[..]), (&b"b"[..], &b"2"[..])], [])
+            .unwrap();
+
+        let keys: &[&[u8]] = &[b"a", b"b", b"c"];
+        assert_eq!(db.migrate_from_aux(&aux, columns::WALLET, keys).unwrap(), 2);
+        assert_eq!(db.get(columns::WALLET, b"a"), Some(b"1".to_vec()));
+        assert_eq!(db.get(columns::WALLET, b"b"), Some(b"2".to_vec()));
+        assert_eq!(aux.get_aux(b"a").unwrap(), None);
+
+        // Already migrated.
+        assert_eq!(db.migrate_from_aux(&aux, columns::WALLET, keys).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_reopen_db() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let db = SubcoinDb::open(tmp.path()).unwrap();
+        db.insert(columns::INDEXES, b"key", b"value").unwrap();
+        drop(db);
+
+        let db = SubcoinDb::open(tmp.path()).unwrap();
+        assert_eq!(db.get(columns::INDEXES, b"key"), Some(b"value".to_vec()));
+        assert_eq!(
+            db.get(columns::META, VERSION_KEY),
+            Some(DB_VERSION.to_le_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        assert!(matches!(
+            SubcoinDb::open_read_only(tmp.path()),
+            Err(Error::NotFound)
+        ));
+
+        let db = SubcoinDb::open(tmp.path()).unwrap();
+        db.insert(columns::INDEXES, b"key", b"value").unwrap();
+        drop(db);
+
+        let db = SubcoinDb::open_read_only(tmp.path()).unwrap();
+        assert_eq!(db.get(columns::INDEXES, b"key"), Some(b"value".to_vec()));
+        assert!(db.insert(columns::INDEXES, b"key", b"other").is_err());
+    }
+
+    #[test]
+    fn test_upgrade_from_v1() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        // Version 1 layout without the peers column.
+        let options = ::parity_db::Options::with_columns(tmp.path(), 5);
+        let db = ::parity_db::Db::open_or_create(&options).unwrap();
+        db.commit([
+            (
+                columns::META as u8,
+                VERSION_KEY.to_vec(),
+                Some(1u32.to_le_bytes().to_vec()),
+            ),
+            (
+                columns::WALLET as u8,
+                b"key".to_vec(),
+                Some(b"value".to_vec()),
+            ),
+        ])
+        .unwrap();
+        drop(db);
+
+        let db = SubcoinDb::open(tmp.path()).unwrap();
+        assert_eq!(db.get(columns::WALLET, b"key"), Some(b"value".to_vec()));
+        db.insert(columns::PEERS, b"key", b"value").unwrap();
+        assert_eq!(
+            db.get(columns::META, VERSION_KEY),
+            Some(DB_VERSION.to_le_bytes().to_vec())
+        );
+    }
+}
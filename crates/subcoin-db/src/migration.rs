@@ -0,0 +1,260 @@
+//! Versioned schemas of the subcoin data and the migrations between them.
+//!
+//! Each kind of data, e.g., an index or the aux layout, has a [`Schema`] whose version is
+//! stamped in [`columns::META`]. The [`Migrator`] brings the stamped versions up to the
+//! versions supported by the node on startup, one [`Migration`] per version, so that changing
+//! the format of the existing data does not require a resync.
+//!
+//! A schema without a stamp is at version 1, the layout before the versioning was introduced.
+//! The migration and the stamp of its version are not committed atomically, a migration must
+//! therefore be idempotent as it's run again if the node is interrupted in between.
+
+use crate::{columns, Error, SubcoinDb};
+use std::collections::BTreeMap;
+
+/// Prefix of the schema versions in [`columns::META`].
+const SCHEMA_VERSION_PREFIX: &[u8] = b"schema:";
+
+fn schema_version_key(schema: &str) -> Vec<u8> {
+    [SCHEMA_VERSION_PREFIX, schema.as_bytes()].concat()
+}
+
+/// Schema of a kind of the subcoin data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schema {
+    /// Unique name of the schema.
+    pub name: &'static str,
+    /// Version supported by the node.
+    pub version: u32,
+}
+
+impl Schema {
+    /// Constructs a new instance of [`Schema`].
+    pub const fn new(name: &'static str, version: u32) -> Self {
+        Self { name, version }
+    }
+}
+
+/// Function migrating the data of a schema.
+pub type MigrateFn = Box<dyn Fn(&SubcoinDb) -> Result<(), Error> + Send + Sync>;
+
+/// Migration of a schema from `version - 1` to `version`.
+pub struct Migration {
+    /// Name of the migrated schema.
+    pub schema: &'static str,
+    /// Version of the schema after the migration.
+    pub version: u32,
+    /// Human-readable description of the migration.
+    pub description: &'static str,
+    /// Migrates the data.
+    pub migrate: MigrateFn,
+}
+
+/// Migration not yet applied to the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingMigration {
+    pub schema: &'static str,
+    /// Stamped version of the schema.
+    pub from: u32,
+    /// Version of the schema after the migration.
+    pub to: u32,
+    pub description: &'static str,
+}
+
+impl std::fmt::Display for PendingMigration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} v{} -> v{}: {}",
+            self.schema, self.from, self.to, self.description
+        )
+    }
+}
+
+/// Runner of the [`Migration`]s of the registered [`Schema`]s.
+pub struct Migrator {
+    db: SubcoinDb,
+    schemas: Vec<Schema>,
+    migrations: BTreeMap<(&'static str, u32), Migration>,
+}
+
+impl Migrator {
+    /// Constructs a new instance of [`Migrator`].
+    pub fn new(db: SubcoinDb) -> Self {
+        Self {
+            db,
+            schemas: Vec::new(),
+            migrations: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a schema supported by the node.
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schemas.push(schema);
+        self
+    }
+
+    /// Registers a migration of a registered schema.
+    pub fn with_migration(mut self, migration: Migration) -> Self {
+        self.migrations
+            .insert((migration.schema, migration.version), migration);
+        self
+    }
+
+    /// Returns the migrations to be applied, in the order of application.
+    pub fn pending(&self) -> Result<Vec<PendingMigration>, Error> {
+        let mut pending = Vec::new();
+
+        for schema in &self.schemas {
+            let stamped = self.db.schema_version(schema.name)?.unwrap_or(1);
+
+            if stamped > schema.version {
+                return Err(Error::UnsupportedSchemaVersion {
+                    schema: schema.name,
+                    version: stamped,
+                    supported: schema.version,
+                });
+            }
+
+            for version in stamped + 1..=schema.version {
+                let migration = self.migrations.get(&(schema.name, version)).ok_or(
+                    Error::MissingMigration {
+                        schema: schema.name,
+                        version,
+                    },
+                )?;
+
+                pending.push(PendingMigration {
+                    schema: schema.name,
+                    from: version - 1,
+                    to: version,
+                    description: migration.description,
+                });
+            }
+        }
+
+        Ok(pending)
+    }
+
+    /// Applies the pending migrations and stamps the versions of all the registered schemas.
+    ///
+    /// Returns the applied migrations.
+    pub fn run(&self) -> Result<Vec<PendingMigration>, Error> {
+        let pending = self.pending()?;
+
+        for step in &pending {
+            tracing::info!("Migrating subcoin database: {step}");
+
+            let migration = &self.migrations[&(step.schema, step.to)];
+            (migration.migrate)(&self.db)?;
+
+            self.db.set_schema_version(step.schema, step.to)?;
+        }
+
+        for schema in &self.schemas {
+            if self.db.schema_version(schema.name)?.is_none() {
+                self.db.set_schema_version(schema.name, schema.version)?;
+            }
+        }
+
+        Ok(pending)
+    }
+}
+
+impl SubcoinDb {
+    /// Returns the stamped version of `schema`, `None` if not stamped yet.
+    pub fn schema_version(&self, schema: &str) -> Result<Option<u32>, Error> {
+        self.get(columns::META, &schema_version_key(schema))
+            .map(|encoded| {
+                encoded
+                    .as_slice()
+                    .try_into()
+                    .map(u32::from_le_bytes)
+                    .map_err(|_| Error::CorruptedVersion)
+            })
+            .transpose()
+    }
+
+    fn set_schema_version(&self, schema: &str, version: u32) -> Result<(), Error> {
+        self.insert(
+            columns::META,
+            &schema_version_key(schema),
+            &version.to_le_bytes(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn migration(version: u32, runs: Arc<AtomicUsize>) -> Migration {
+        Migration {
+            schema: "index",
+            version,
+            description: "Rewrite the index",
+            migrate: Box::new(move |db| {
+                runs.fetch_add(1, Ordering::SeqCst);
+                db.insert(columns::INDEXES, b"format", &version.to_le_bytes())
+            }),
+        }
+    }
+
+    #[test]
+    fn test_run_migrations() {
+        let db = SubcoinDb::in_memory();
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let migrator = Migrator::new(db.clone())
+            .with_schema(Schema::new("index", 3))
+            .with_schema(Schema::new("aux", 1))
+            .with_migration(migration(2, runs.clone()))
+            .with_migration(migration(3, runs.clone()));
+
+        let pending = migrator.pending().unwrap();
+        assert_eq!(
+            pending.iter().map(|m| (m.from, m.to)).collect::<Vec<_>>(),
+            vec![(1, 2), (2, 3)]
+        );
+
+        assert_eq!(migrator.run().unwrap(), pending);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            db.get(columns::INDEXES, b"format"),
+            Some(3u32.to_le_bytes().to_vec())
+        );
+        assert_eq!(db.schema_version("index").unwrap(), Some(3));
+        assert_eq!(db.schema_version("aux").unwrap(), Some(1));
+
+        // Up to date.
+        assert!(migrator.pending().unwrap().is_empty());
+        assert!(migrator.run().unwrap().is_empty());
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_invalid_migrations() {
+        let db = SubcoinDb::in_memory();
+
+        let migrator = Migrator::new(db.clone()).with_schema(Schema::new("index", 2));
+        assert!(matches!(
+            migrator.pending(),
+            Err(Error::MissingMigration {
+                schema: "index",
+                version: 2
+            })
+        ));
+
+        db.set_schema_version("index", 3).unwrap();
+        assert!(matches!(
+            migrator.pending(),
+            Err(Error::UnsupportedSchemaVersion {
+                schema: "index",
+                version: 3,
+                supported: 2
+            })
+        ));
+    }
+}
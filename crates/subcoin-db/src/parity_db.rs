@@ -0,0 +1,117 @@
+use crate::{DbHash, Error, NUM_COLUMNS};
+use sp_database::error::DatabaseError;
+use sp_database::{Change, ColumnId, Database, Transaction};
+use std::path::Path;
+
+/// [`Database`] backed by parity-db.
+pub(crate) struct DbAdapter(parity_db::Db);
+
+impl DbAdapter {
+    pub(crate) fn open(path: &Path) -> Result<Self, Error> {
+        let mut options = parity_db::Options::with_columns(path, NUM_COLUMNS as u8);
+
+        // Append the columns introduced after the database was created.
+        if let Some(metadata) = options.load_metadata()? {
+            if metadata.columns.len() < options.columns.len() {
+                let new_columns = options.columns.split_off(metadata.columns.len());
+                options.columns = metadata.columns;
+                for column in new_columns {
+                    parity_db::Db::add_column(&mut options, column)?;
+                }
+            }
+        }
+
+        Ok(Self(parity_db::Db::open_or_create(&options)?))
+    }
+}
+
+/// [`Database`] backed by an existing parity-db opened read-only, any change is rejected.
+///
+/// The columns are loaded from the metadata of the database, so that any parity-db database
+/// can be opened, e.g., the Substrate database of another node.
+pub struct ReadOnlyDb(parity_db::Db);
+
+impl ReadOnlyDb {
+    /// Opens the database at given path, which must exist.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut options = parity_db::Options::with_columns(path, 0);
+        let metadata = options.load_metadata()?.ok_or(Error::NotFound)?;
+        options.columns = metadata.columns;
+        options.salt = Some(metadata.salt);
+
+        Ok(Self(parity_db::Db::open_read_only(&options)?))
+    }
+}
+
+fn handle_err<T>(result: parity_db::Result<T>) -> T {
+    match result {
+        Ok(r) => r,
+        Err(err) => panic!("Critical database error: {err:?}"),
+    }
+}
+
+impl Database<DbHash> for DbAdapter {
+    fn commit(&self, transaction: Transaction<DbHash>) -> Result<(), DatabaseError> {
+        let changes = transaction
+            .0
+            .into_iter()
+            .map(|change| match change {
+                Change::Set(col, key, value) => Ok((col as u8, key, Some(value))),
+                Change::Remove(col, key) => Ok((col as u8, key, None)),
+                Change::Store(..) | Change::Reference(..) | Change::Release(..) => {
+                    Err(DatabaseError(Box::new(Error::UnsupportedChange)))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.0
+            .commit(changes)
+            .map_err(|err| DatabaseError(Box::new(err)))
+    }
+
+    fn get(&self, col: ColumnId, key: &[u8]) -> Option<Vec<u8>> {
+        handle_err(self.0.get(col as u8, key))
+    }
+
+    fn contains(&self, col: ColumnId, key: &[u8]) -> bool {
+        handle_err(self.0.get_size(col as u8, key)).is_some()
+    }
+
+    fn value_size(&self, col: ColumnId, key: &[u8]) -> Option<usize> {
+        handle_err(self.0.get_size(col as u8, key)).map(|size| size as usize)
+    }
+}
+
+impl<H: Clone + AsRef<[u8]>> Database<H> for ReadOnlyDb {
+    fn commit(&self, transaction: Transaction<H>) -> Result<(), DatabaseError> {
+        // Empty transactions are committed by the Substrate backend on open.
+        if transaction.0.is_empty() {
+            Ok(())
+        } else {
+            Err(DatabaseError(Box::new(Error::ReadOnly)))
+        }
+    }
+
+    fn get(&self, col: ColumnId, key: &[u8]) -> Option<Vec<u8>> {
+        handle_err(self.0.get(col as u8, key))
+    }
+
+    fn contains(&self, col: ColumnId, key: &[u8]) -> bool {
+        handle_err(self.0.get_size(col as u8, key)).is_some()
+    }
+
+    fn value_size(&self, col: ColumnId, key: &[u8]) -> Option<usize> {
+        handle_err(self.0.get_size(col as u8, key)).map(|size| size as usize)
+    }
+
+    // Same as the parity-db adapter of the Substrate database, the trie nodes are stored
+    // without the key prefix.
+    fn supports_ref_counting(&self) -> bool {
+        true
+    }
+
+    fn sanitize_key(&self, key: &mut Vec<u8>) {
+        const HASH_LEN: usize = 32;
+        let _prefix = key.drain(0..key.len().saturating_sub(HASH_LEN));
+    }
+}
@@ -0,0 +1,345 @@
+//! gRPC interface for the integrators ingesting the chain data at high throughput.
+//!
+//! The blocks are served in the consensus encoding and streamed as the best chain grows,
+//! avoiding the JSON serialization of the RPC. See `proto/subcoin.proto` for the schema.
+//!
+//! The interface is unauthenticated, it's meant to be served on a trusted network only.
+
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::hashes::Hash;
+use bitcoin::{Block as BitcoinBlock, BlockHash, Transaction, Txid};
+use codec::Decode;
+use futures::StreamExt;
+use sc_client_api::{
+    AuxStore, Backend, BlockBackend, BlockchainEvents, HeaderBackend, StorageProvider,
+};
+use sp_runtime::traits::{Block as BlockT, SaturatedConversion};
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use subcoin_network::{NetworkHandle, SendTransactionResult};
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{
+    convert_to_bitcoin_block, BackendExt, BitcoinTransactionAdapter, CoinStorageKey,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+/// Generated protobuf messages and service.
+pub mod proto {
+    tonic::include_proto!("subcoin.v1");
+}
+
+use self::proto::subcoin_server::{Subcoin, SubcoinServer};
+use self::proto::{
+    get_block_request, ChainTip, GetBlockRequest, GetChainTipRequest, GetUtxosRequest,
+    GetUtxosResponse, StreamBlocksRequest, SubmitTransactionRequest, SubmitTransactionResponse,
+    Utxo,
+};
+
+/// Maximum number of outpoints queried by `GetUtxos`.
+const MAX_UTXOS_PER_REQUEST: usize = 1000;
+
+/// Number of the streamed blocks buffered for a slow receiver.
+const STREAM_BUFFER_SIZE: usize = 16;
+
+fn internal(err: impl std::fmt::Display) -> Status {
+    Status::internal(err.to_string())
+}
+
+fn parse_hash<H: Hash>(bytes: &[u8]) -> Result<H, Status> {
+    H::from_slice(bytes).map_err(|_| Status::invalid_argument("Hash must be 32 bytes"))
+}
+
+/// gRPC service.
+pub struct Grpc<Block, Client, BE, TransactionAdapter> {
+    client: Arc<Client>,
+    network_handle: NetworkHandle,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    _phantom: PhantomData<(Block, BE, TransactionAdapter)>,
+}
+
+impl<Block, Client, BE, TransactionAdapter> Clone for Grpc<Block, Client, BE, TransactionAdapter> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            network_handle: self.network_handle.clone(),
+            coin_storage_key: self.coin_storage_key.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<Block, Client, BE, TransactionAdapter> Grpc<Block, Client, BE, TransactionAdapter>
+where
+    Block: BlockT + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore
+        + Send
+        + Sync
+        + 'static,
+    TransactionAdapter: BitcoinTransactionAdapter<Block> + Send + Sync + 'static,
+{
+    /// Constructs a new instance of [`Grpc`].
+    pub fn new(
+        client: Arc<Client>,
+        network_handle: NetworkHandle,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+    ) -> Self {
+        Self {
+            client,
+            network_handle,
+            coin_storage_key,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Serves the gRPC interface on `addr`.
+    pub async fn run(self, addr: SocketAddr) {
+        tracing::info!("Running gRPC server: addr={addr}");
+
+        if let Err(err) = tonic::transport::Server::builder()
+            .add_service(SubcoinServer::new(self))
+            .serve(addr)
+            .await
+        {
+            tracing::error!(?err, "Failed to run the gRPC server on {addr}");
+        }
+    }
+
+    fn chain_tip(&self) -> Result<ChainTip, Status> {
+        let info = self.client.info();
+        let hash = BackendExt::<Block>::bitcoin_block_hash_for(&self.client, info.best_hash)
+            .ok_or_else(|| Status::internal("Best block not found"))?;
+
+        Ok(ChainTip {
+            height: info.best_number.saturated_into(),
+            hash: hash.to_byte_array().to_vec(),
+        })
+    }
+
+    fn bitcoin_block(&self, block_hash: BlockHash) -> Result<BitcoinBlock, Status> {
+        let not_found = || Status::not_found(format!("Block {block_hash} not found"));
+
+        let substrate_block_hash = self
+            .client
+            .substrate_block_hash_for(block_hash)
+            .ok_or_else(not_found)?;
+
+        let block = match self.client.block(substrate_block_hash).map_err(internal)? {
+            Some(signed_block) => signed_block.block,
+            None if self
+                .client
+                .header(substrate_block_hash)
+                .map_err(internal)?
+                .is_some() =>
+            {
+                return Err(Status::not_found(format!(
+                    "Block {block_hash} not available (pruned data)"
+                )));
+            }
+            None => return Err(not_found()),
+        };
+
+        convert_to_bitcoin_block::<Block, TransactionAdapter>(block)
+            .map_err(|err| Status::internal(format!("Invalid block {block_hash}: {err:?}")))
+    }
+
+    fn block_message(&self, block_hash: BlockHash) -> Result<proto::Block, Status> {
+        let height = BackendExt::<Block>::block_number(&self.client, block_hash)
+            .ok_or_else(|| Status::not_found(format!("Block {block_hash} not found")))?;
+
+        let mut raw_block = Vec::new();
+        self.bitcoin_block(block_hash)?
+            .consensus_encode(&mut raw_block)
+            .map_err(internal)?;
+
+        Ok(proto::Block {
+            height,
+            hash: block_hash.to_byte_array().to_vec(),
+            raw_block,
+        })
+    }
+
+    /// Streams the best chain blocks from `start_height` until the receiver is dropped.
+    async fn stream_blocks_from(
+        self,
+        start_height: u32,
+        sender: mpsc::Sender<Result<proto::Block, Status>>,
+    ) {
+        // Subscribed before catching up to not miss any new block.
+        let mut import_notifications = self.client.import_notification_stream();
+
+        let mut next_height = start_height;
+        let mut last_streamed: Option<(u32, BlockHash)> = None;
+
+        loop {
+            // Rewind to the fork point if the streamed blocks have been reorged out.
+            while let Some((height, block_hash)) = last_streamed {
+                if BackendExt::<Block>::block_hash(&self.client, height) == Some(block_hash) {
+                    break;
+                }
+                next_height = height;
+                last_streamed = height.checked_sub(1).zip(
+                    self.client
+                        .block_header(block_hash)
+                        .map(|header| header.prev_blockhash),
+                );
+            }
+
+            let best_number: u32 = self.client.info().best_number.saturated_into();
+
+            while next_height <= best_number {
+                let Some(block_hash) = BackendExt::<Block>::block_hash(&self.client, next_height)
+                else {
+                    break;
+                };
+
+                let block = self.block_message(block_hash);
+                let failed = block.is_err();
+
+                if sender.send(block).await.is_err() || failed {
+                    return;
+                }
+
+                last_streamed.replace((next_height, block_hash));
+                next_height += 1;
+            }
+
+            loop {
+                match import_notifications.next().await {
+                    Some(notification) if notification.is_new_best => break,
+                    Some(_) => continue,
+                    None => return,
+                }
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<Block, Client, BE, TransactionAdapter> Subcoin for Grpc<Block, Client, BE, TransactionAdapter>
+where
+    Block: BlockT + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore
+        + Send
+        + Sync
+        + 'static,
+    TransactionAdapter: BitcoinTransactionAdapter<Block> + Send + Sync + 'static,
+{
+    type StreamBlocksStream = ReceiverStream<Result<proto::Block, Status>>;
+
+    async fn get_chain_tip(
+        &self,
+        _request: Request<GetChainTipRequest>,
+    ) -> Result<Response<ChainTip>, Status> {
+        self.chain_tip().map(Response::new)
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<GetBlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let block_hash = match request.into_inner().block_id {
+            Some(get_block_request::BlockId::Hash(hash)) => parse_hash(&hash)?,
+            Some(get_block_request::BlockId::Height(height)) => {
+                BackendExt::<Block>::block_hash(&self.client, height)
+                    .ok_or_else(|| Status::not_found(format!("Block #{height} not found")))?
+            }
+            None => return Err(Status::invalid_argument("Block hash or height required")),
+        };
+
+        self.block_message(block_hash).map(Response::new)
+    }
+
+    async fn stream_blocks(
+        &self,
+        request: Request<StreamBlocksRequest>,
+    ) -> Result<Response<Self::StreamBlocksStream>, Status> {
+        let start_height = request.into_inner().start_height;
+
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER_SIZE);
+
+        tokio::spawn(self.clone().stream_blocks_from(start_height, sender));
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_utxos(
+        &self,
+        request: Request<GetUtxosRequest>,
+    ) -> Result<Response<GetUtxosResponse>, Status> {
+        let outpoints = request.into_inner().outpoints;
+
+        if outpoints.len() > MAX_UTXOS_PER_REQUEST {
+            return Err(Status::invalid_argument(format!(
+                "Too many outpoints (max: {MAX_UTXOS_PER_REQUEST}, tried: {})",
+                outpoints.len()
+            )));
+        }
+
+        let best_hash = self.client.info().best_hash;
+        let chain_tip = self.chain_tip()?;
+
+        let utxos = outpoints
+            .into_iter()
+            .map(|outpoint| {
+                let txid: Txid = parse_hash(&outpoint.txid)?;
+                let storage_key = self.coin_storage_key.storage_key(txid, outpoint.vout);
+
+                let coin = self
+                    .client
+                    .storage(best_hash, &sc_client_api::StorageKey(storage_key))
+                    .map_err(internal)?
+                    .and_then(|data| Coin::decode(&mut data.0.as_slice()).ok());
+
+                Ok(match coin {
+                    Some(coin) => Utxo {
+                        outpoint: Some(outpoint),
+                        unspent: true,
+                        value: coin.amount,
+                        script_pubkey: coin.script_pubkey,
+                        height: coin.height,
+                        is_coinbase: coin.is_coinbase,
+                    },
+                    None => Utxo {
+                        outpoint: Some(outpoint),
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+
+        Ok(Response::new(GetUtxosResponse {
+            chain_tip: Some(chain_tip),
+            utxos,
+        }))
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<SubmitTransactionRequest>,
+    ) -> Result<Response<SubmitTransactionResponse>, Status> {
+        let raw_transaction = request.into_inner().raw_transaction;
+
+        let transaction = Transaction::consensus_decode(&mut raw_transaction.as_slice())
+            .map_err(|err| Status::invalid_argument(format!("Invalid transaction: {err}")))?;
+
+        match self.network_handle.send_transaction(transaction).await {
+            SendTransactionResult::Success(txid) => Ok(Response::new(SubmitTransactionResponse {
+                txid: txid.to_byte_array().to_vec(),
+            })),
+            SendTransactionResult::Failure(err) => Err(Status::failed_precondition(err)),
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Indexes built from each block independently, e.g., [`TxIndex`] and [`OpReturnIndex`].
+//!
+//! The sync height of each index is tracked in [`columns::META`], an index enabled on an
+//! already synced node is therefore backfilled from the genesis block by walking the historical
+//! blocks, at the pace of [`BackfillConfig`], before following the finalized blocks.
+//!
+//! [`TxIndex`]: crate::TxIndex
+//! [`OpReturnIndex`]: crate::OpReturnIndex
+
+use crate::Error;
+use bitcoin::Block as BitcoinBlock;
+use codec::{Decode, Encode};
+use futures::StreamExt;
+use sc_client_api::{BlockBackend, BlockchainEvents};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::marker::PhantomData;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use subcoin_db::{columns, Migration, SubcoinDb, Transaction};
+use subcoin_primitives::{convert_to_bitcoin_block, BitcoinTransactionAdapter};
+
+/// Prefix of the keys of the index sync heights in [`columns::META`].
+const INDEX_HEIGHT_PREFIX: &[u8] = b"index_height:";
+
+/// Minimum number of blocks behind the finalized block to report as a backfill.
+const BACKFILL_THRESHOLD: u32 = 100;
+
+pub(crate) fn index_height_key(name: &str) -> Vec<u8> {
+    [INDEX_HEIGHT_PREFIX, name.as_bytes()].concat()
+}
+
+/// Returns the height of the last block indexed by the index `name`.
+pub fn index_height(db: &SubcoinDb, name: &str) -> Result<Option<u32>, Error> {
+    db.get(columns::META, &index_height_key(name))
+        .map(|encoded| u32::decode(&mut encoded.as_slice()))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// Returns the migration moving the sync height of the index `name` from `legacy_key` in
+/// [`columns::INDEXES`] to [`columns::META`].
+pub(crate) fn index_height_migration(
+    name: &'static str,
+    version: u32,
+    legacy_key: &'static [u8],
+) -> Migration {
+    Migration {
+        schema: name,
+        version,
+        description: "Move the sync height of the index to the meta column",
+        migrate: Box::new(move |db| {
+            let Some(height) = db.get(columns::INDEXES, legacy_key) else {
+                return Ok(());
+            };
+            let mut transaction = Transaction::new();
+            transaction.set(columns::META, &index_height_key(name), &height);
+            transaction.remove(columns::INDEXES, legacy_key);
+            db.commit(transaction)
+        }),
+    }
+}
+
+/// Index of the data of each block, not depending on the other blocks or the state.
+///
+/// The genesis block is not indexed.
+pub trait BlockIndex: Send + Sync {
+    /// Unique name of the index.
+    const NAME: &'static str;
+
+    /// Adds the entries of the block at `height` to `transaction`.
+    fn index_block(&self, transaction: &mut Transaction, height: u32, block: &BitcoinBlock);
+}
+
+/// Pace of the backfill of a [`BlockIndex`].
+#[derive(Debug, Clone, Copy)]
+pub struct BackfillConfig {
+    /// Maximum number of blocks indexed per second, unlimited if `None`.
+    pub max_blocks_per_second: Option<NonZeroU32>,
+    /// Interval of reporting the backfill progress.
+    pub progress_interval: Duration,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            max_blocks_per_second: None,
+            progress_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Progress of indexing the blocks up to a target height.
+struct Progress {
+    from: u32,
+    target: u32,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl Progress {
+    fn new(from: u32, target: u32) -> Self {
+        let now = Instant::now();
+        Self {
+            from,
+            target,
+            started: now,
+            last_report: now,
+        }
+    }
+
+    /// Returns the time to wait before indexing the next block to not exceed `max_rate`.
+    fn throttle(&self, indexed: u32, max_rate: NonZeroU32) -> Option<Duration> {
+        let expected = Duration::from_secs_f64(f64::from(indexed) / f64::from(max_rate.get()));
+        expected
+            .checked_sub(self.started.elapsed())
+            .filter(|delay| !delay.is_zero())
+    }
+
+    fn rate(&self, height: u32) -> f64 {
+        f64::from(height + 1 - self.from) / self.started.elapsed().as_secs_f64().max(1e-3)
+    }
+}
+
+/// Indexer following the finalized blocks with a [`BlockIndex`].
+pub struct BlockIndexer<Block, Client, Index> {
+    client: Arc<Client>,
+    db: SubcoinDb,
+    index: Index,
+    backfill: BackfillConfig,
+    _phantom: PhantomData<Block>,
+}
+
+impl<Block, Client, Index> BlockIndexer<Block, Client, Index>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + BlockBackend<Block> + BlockchainEvents<Block>,
+    Index: BlockIndex,
+{
+    /// Constructs a new instance of [`BlockIndexer`].
+    pub fn new(client: Arc<Client>, db: SubcoinDb, index: Index) -> Self {
+        Self {
+            client,
+            db,
+            index,
+            backfill: BackfillConfig::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Specifies the pace of the backfill, unlimited by default.
+    pub fn with_backfill(mut self, backfill: BackfillConfig) -> Self {
+        self.backfill = backfill;
+        self
+    }
+
+    /// Returns a future following the finalized blocks.
+    pub async fn run<TransactionAdapter: BitcoinTransactionAdapter<Block>>(self) {
+        let mut finality_stream = self.client.finality_notification_stream();
+
+        loop {
+            let finalized_number = self.client.info().finalized_number;
+            let Ok(finalized_number) = finalized_number.try_into() else {
+                return;
+            };
+
+            if let Err(err) = self.catch_up::<TransactionAdapter>(finalized_number) {
+                tracing::error!(?err, "Indexer {} stopped", Index::NAME);
+                return;
+            }
+
+            if finality_stream.next().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    fn catch_up<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        finalized_number: u32,
+    ) -> Result<(), Error> {
+        let from = index_height(&self.db, Index::NAME)?.unwrap_or(0) + 1;
+
+        if from > finalized_number {
+            return Ok(());
+        }
+
+        let backfill = finalized_number - from >= BACKFILL_THRESHOLD;
+
+        if backfill {
+            tracing::info!(
+                "🗂️ Backfilling index {} from #{from} to #{finalized_number}",
+                Index::NAME
+            );
+        }
+
+        let mut progress = Progress::new(from, finalized_number);
+
+        for height in from..=finalized_number {
+            let block = self.bitcoin_block::<TransactionAdapter>(height)?;
+
+            let mut transaction = Transaction::new();
+            self.index.index_block(&mut transaction, height, &block);
+            transaction.set(
+                columns::META,
+                &index_height_key(Index::NAME),
+                &height.encode(),
+            );
+            self.db.commit(transaction)?;
+
+            if !backfill {
+                continue;
+            }
+
+            if progress.last_report.elapsed() >= self.backfill.progress_interval {
+                progress.last_report = Instant::now();
+                tracing::info!(
+                    "🗂️ Backfilling index {}: #{height}/#{} ({:.2}%), {:.1} blocks/s",
+                    Index::NAME,
+                    progress.target,
+                    f64::from(height) * 100.0 / f64::from(progress.target),
+                    progress.rate(height),
+                );
+            }
+
+            if let Some(delay) = self
+                .backfill
+                .max_blocks_per_second
+                .and_then(|max_rate| progress.throttle(height + 1 - from, max_rate))
+            {
+                std::thread::sleep(delay);
+            }
+        }
+
+        if backfill {
+            tracing::info!(
+                "🗂️ Backfilled index {} up to #{finalized_number}",
+                Index::NAME
+            );
+        }
+
+        Ok(())
+    }
+
+    fn bitcoin_block<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        number: u32,
+    ) -> Result<BitcoinBlock, Error> {
+        let block_hash = self
+            .client
+            .hash(number.into())?
+            .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("#{number}")))?;
+
+        let block = match self.client.block(block_hash)? {
+            Some(signed_block) => signed_block.block,
+            None if self.client.header(block_hash)?.is_some() => {
+                return Err(Error::BlockPruned(number));
+            }
+            None => return Err(sp_blockchain::Error::UnknownBlock(block_hash.to_string()).into()),
+        };
+
+        convert_to_bitcoin_block::<Block, TransactionAdapter>(block)
+            .map_err(|err| Error::InvalidBlock(number, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_height_migration() {
+        let db = SubcoinDb::in_memory();
+        db.insert(columns::INDEXES, b"legacy_tip", &7u32.encode())
+            .unwrap();
+
+        let migration = index_height_migration("test", 2, b"legacy_tip");
+        (migration.migrate)(&db).unwrap();
+        assert_eq!(index_height(&db, "test").unwrap(), Some(7));
+        assert_eq!(db.get(columns::INDEXES, b"legacy_tip"), None);
+
+        // Idempotent.
+        (migration.migrate)(&db).unwrap();
+        assert_eq!(index_height(&db, "test").unwrap(), Some(7));
+    }
+
+    #[test]
+    fn test_backfill_throttle() {
+        let progress = Progress::new(1, 1000);
+        let max_rate = NonZeroU32::new(10).unwrap();
+
+        let delay = progress.throttle(5, max_rate).unwrap();
+        assert!(delay <= Duration::from_millis(500));
+        assert!(delay > Duration::from_millis(300));
+        assert_eq!(progress.throttle(0, max_rate), None);
+    }
+}
@@ -0,0 +1,573 @@
+//! Time series of the UTXO set statistics.
+//!
+//! The cumulative statistics are updated with each finalized block and recorded every
+//! [`CHAIN_STATS_INTERVAL`] blocks, the coins spent by a block are read from the state of
+//! its parent. The indexing stops if the parent state has been pruned, e.g., when the
+//! indexer is enabled later on a node without the archive state.
+//!
+//! The [`UtxoComposition`] by script type is maintained alongside. On a node indexed before
+//! the composition was introduced, it's counted once from the UTXO set at the last indexed
+//! block, which requires the state of that block.
+
+use crate::{Error, ScriptType};
+use bitcoin::{Block as BitcoinBlock, OutPoint, Script, TxOut};
+use codec::{Compact, CompactLen, Decode, Encode};
+use futures::StreamExt;
+use sc_client_api::{Backend, BlockBackend, BlockchainEvents, StorageKey, StorageProvider};
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use subcoin_db::{columns, SubcoinDb, Transaction};
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{convert_to_bitcoin_block, BitcoinTransactionAdapter, CoinStorageKey};
+
+/// Number of blocks between two recorded statistics, roughly one day.
+pub const CHAIN_STATS_INTERVAL: u32 = 144;
+
+/// Key of the statistics at the last indexed block.
+const TIP_KEY: &[u8] = b"chainstats_tip";
+
+/// Prefix of the keys of the recorded statistics.
+const CHAIN_STATS_PREFIX: &[u8] = b"chainstats";
+
+/// Key of the composition at the last indexed block.
+const COMPOSITION_TIP_KEY: &[u8] = b"utxocomposition_tip";
+
+/// Prefix of the keys of the recorded compositions.
+const COMPOSITION_PREFIX: &[u8] = b"utxocomposition";
+
+fn chain_stats_key(height: u32) -> Vec<u8> {
+    let mut key = CHAIN_STATS_PREFIX.to_vec();
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+fn composition_key(height: u32) -> Vec<u8> {
+    let mut key = COMPOSITION_PREFIX.to_vec();
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+/// Changes of the UTXO set made by a block, accumulated by the statistics.
+trait UtxoSetChanges {
+    fn add_coin(&mut self, amount: u64, script_pubkey: &[u8]);
+
+    fn remove_coin(&mut self, amount: u64, script_pubkey: &[u8]);
+
+    fn set_height(&mut self, height: u32);
+}
+
+impl<A: UtxoSetChanges, B: UtxoSetChanges> UtxoSetChanges for (&mut A, &mut B) {
+    fn add_coin(&mut self, amount: u64, script_pubkey: &[u8]) {
+        self.0.add_coin(amount, script_pubkey);
+        self.1.add_coin(amount, script_pubkey);
+    }
+
+    fn remove_coin(&mut self, amount: u64, script_pubkey: &[u8]) {
+        self.0.remove_coin(amount, script_pubkey);
+        self.1.remove_coin(amount, script_pubkey);
+    }
+
+    fn set_height(&mut self, height: u32) {
+        self.0.set_height(height);
+        self.1.set_height(height);
+    }
+}
+
+/// Cumulative statistics of the UTXO set at a block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainStats {
+    pub height: u32,
+    /// Sum of the values of all unspent outputs in satoshis.
+    pub total_supply: u64,
+    pub utxo_count: u64,
+    /// Sum of the encoded size of all unspent outputs in the state.
+    pub utxo_size: u64,
+    /// Number of the unspent `OP_RETURN` outputs.
+    pub op_return_count: u64,
+}
+
+fn coin_size(script_len: usize) -> u64 {
+    // is_coinbase + amount + height + script_pubkey.
+    (1 + 8 + 4 + Compact::<u32>::compact_len(&(script_len as u32)) + script_len) as u64
+}
+
+impl UtxoSetChanges for ChainStats {
+    fn add_coin(&mut self, amount: u64, script_pubkey: &[u8]) {
+        self.total_supply += amount;
+        self.utxo_count += 1;
+        self.utxo_size += coin_size(script_pubkey.len());
+        if Script::from_bytes(script_pubkey).is_op_return() {
+            self.op_return_count += 1;
+        }
+    }
+
+    fn remove_coin(&mut self, amount: u64, script_pubkey: &[u8]) {
+        self.total_supply = self.total_supply.saturating_sub(amount);
+        self.utxo_count = self.utxo_count.saturating_sub(1);
+        self.utxo_size = self
+            .utxo_size
+            .saturating_sub(coin_size(script_pubkey.len()));
+        if Script::from_bytes(script_pubkey).is_op_return() {
+            self.op_return_count = self.op_return_count.saturating_sub(1);
+        }
+    }
+
+    fn set_height(&mut self, height: u32) {
+        self.height = height;
+    }
+}
+
+/// Number and total value of the unspent outputs of a script type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct ScriptTypeStats {
+    pub count: u64,
+    /// Sum of the values in satoshis.
+    pub amount: u64,
+}
+
+/// Composition of the UTXO set by script type at a block.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UtxoComposition {
+    pub height: u32,
+    /// Script types without any unspent output are omitted.
+    pub script_types: BTreeMap<ScriptType, ScriptTypeStats>,
+}
+
+impl UtxoSetChanges for UtxoComposition {
+    fn add_coin(&mut self, amount: u64, script_pubkey: &[u8]) {
+        let stats = self
+            .script_types
+            .entry(ScriptType::classify(Script::from_bytes(script_pubkey)))
+            .or_default();
+        stats.count += 1;
+        stats.amount += amount;
+    }
+
+    fn remove_coin(&mut self, amount: u64, script_pubkey: &[u8]) {
+        let script_type = ScriptType::classify(Script::from_bytes(script_pubkey));
+        if let Some(stats) = self.script_types.get_mut(&script_type) {
+            stats.count = stats.count.saturating_sub(1);
+            stats.amount = stats.amount.saturating_sub(amount);
+            if stats.count == 0 {
+                self.script_types.remove(&script_type);
+            }
+        }
+    }
+
+    fn set_height(&mut self, height: u32) {
+        self.height = height;
+    }
+}
+
+/// Applies the block on top of the statistics of its parent.
+///
+/// `parent_coin` returns the coin in the UTXO set of the parent block.
+fn apply_block(
+    stats: &mut impl UtxoSetChanges,
+    height: u32,
+    block: &BitcoinBlock,
+    parent_coin: impl Fn(&OutPoint) -> Result<Option<Coin>, Error>,
+) -> Result<(), Error> {
+    // Outputs created in this block, the ones spent within the block never reach the
+    // UTXO set.
+    let mut created = HashMap::<OutPoint, &TxOut>::new();
+
+    for tx in &block.txdata {
+        let txid = tx.compute_txid();
+
+        if tx.is_coinbase() {
+            // The historical duplicate coinbases overwrite the unspent outputs.
+            for vout in 0..tx.output.len() as u32 {
+                if let Some(coin) = parent_coin(&OutPoint { txid, vout })? {
+                    stats.remove_coin(coin.amount, &coin.script_pubkey);
+                }
+            }
+        } else {
+            for input in &tx.input {
+                let out_point = input.previous_output;
+                if created.remove(&out_point).is_none() {
+                    let coin =
+                        parent_coin(&out_point)?.ok_or(Error::MissingCoin(out_point, height))?;
+                    stats.remove_coin(coin.amount, &coin.script_pubkey);
+                }
+            }
+        }
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            created.insert(
+                OutPoint {
+                    txid,
+                    vout: vout as u32,
+                },
+                output,
+            );
+        }
+    }
+
+    for output in created.into_values() {
+        stats.add_coin(output.value.to_sat(), output.script_pubkey.as_bytes());
+    }
+
+    stats.set_height(height);
+
+    Ok(())
+}
+
+/// Returns the statistics at the last indexed block.
+pub fn chain_stats_tip(db: &SubcoinDb) -> Result<Option<ChainStats>, Error> {
+    db.get(columns::INDEXES, TIP_KEY)
+        .map(|encoded| ChainStats::decode(&mut encoded.as_slice()))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// Returns the recorded statistics within `[from, to]`, followed by the statistics of the
+/// last indexed block if it's in the range.
+pub fn chain_stats_range(db: &SubcoinDb, from: u32, to: u32) -> Result<Vec<ChainStats>, Error> {
+    let Some(tip) = chain_stats_tip(db)? else {
+        return Ok(Vec::new());
+    };
+
+    let to = to.min(tip.height);
+
+    let mut range = Vec::new();
+
+    let Some(first) = from.checked_next_multiple_of(CHAIN_STATS_INTERVAL) else {
+        return Ok(range);
+    };
+
+    for height in (first..=to).step_by(CHAIN_STATS_INTERVAL as usize) {
+        if let Some(encoded) = db.get(columns::INDEXES, &chain_stats_key(height)) {
+            range.push(ChainStats::decode(&mut encoded.as_slice())?);
+        }
+    }
+
+    if tip.height >= from && tip.height % CHAIN_STATS_INTERVAL != 0 {
+        range.push(tip);
+    }
+
+    Ok(range)
+}
+
+/// Returns the composition at the given recorded height or the last indexed block, the last
+/// indexed block by default.
+pub fn utxo_composition(
+    db: &SubcoinDb,
+    height: Option<u32>,
+) -> Result<Option<UtxoComposition>, Error> {
+    let Some(encoded) = db.get(columns::INDEXES, COMPOSITION_TIP_KEY) else {
+        return Ok(None);
+    };
+    let tip = UtxoComposition::decode(&mut encoded.as_slice())?;
+
+    match height {
+        None => Ok(Some(tip)),
+        Some(height) if height == tip.height => Ok(Some(tip)),
+        Some(height) if height > tip.height || height % CHAIN_STATS_INTERVAL != 0 => Ok(None),
+        Some(height) => db
+            .get(columns::INDEXES, &composition_key(height))
+            .map(|encoded| UtxoComposition::decode(&mut encoded.as_slice()))
+            .transpose()
+            .map_err(Into::into),
+    }
+}
+
+/// Indexer of the [`ChainStats`] and [`UtxoComposition`].
+pub struct ChainStatsIndexer<Block, Client, BE> {
+    client: Arc<Client>,
+    db: SubcoinDb,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    _phantom: PhantomData<(Block, BE)>,
+}
+
+impl<Block, Client, BE> ChainStatsIndexer<Block, Client, BE>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>,
+{
+    /// Constructs a new instance of [`ChainStatsIndexer`].
+    pub fn new(
+        client: Arc<Client>,
+        db: SubcoinDb,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+    ) -> Self {
+        Self {
+            client,
+            db,
+            coin_storage_key,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns a future following the finalized blocks.
+    ///
+    /// The future needs to be spawned in the background, it takes a while to index the
+    /// historical blocks.
+    pub async fn run<TransactionAdapter: BitcoinTransactionAdapter<Block>>(self) {
+        let mut finality_stream = self.client.finality_notification_stream();
+
+        loop {
+            let finalized_number = self.client.info().finalized_number;
+            let Ok(finalized_number) = finalized_number.try_into() else {
+                return;
+            };
+
+            if let Err(err) = self.catch_up::<TransactionAdapter>(finalized_number) {
+                tracing::error!(?err, "Chain stats indexer stopped");
+                return;
+            }
+
+            if finality_stream.next().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    fn catch_up<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        finalized_number: u32,
+    ) -> Result<(), Error> {
+        let (mut stats, mut composition) = match chain_stats_tip(&self.db)? {
+            Some(stats) => match utxo_composition(&self.db, None)? {
+                Some(composition) if composition.height == stats.height => (stats, composition),
+                _ => {
+                    tracing::info!("Counting the UTXO composition at #{}", stats.height);
+                    let (_, composition) = self.utxo_set_stats(stats.height)?;
+                    self.record(&stats, &composition)?;
+                    (stats, composition)
+                }
+            },
+            None => {
+                let (stats, composition) = self.utxo_set_stats(0)?;
+                self.record(&stats, &composition)?;
+                (stats, composition)
+            }
+        };
+
+        for height in stats.height + 1..=finalized_number {
+            let (parent_hash, block) = self.bitcoin_block::<TransactionAdapter>(height)?;
+
+            apply_block(
+                &mut (&mut stats, &mut composition),
+                height,
+                &block,
+                |out_point| {
+                    let key = StorageKey(
+                        self.coin_storage_key
+                            .storage_key(out_point.txid, out_point.vout),
+                    );
+                    self.client
+                        .storage(parent_hash, &key)?
+                        .map(|data| Coin::decode(&mut data.0.as_slice()))
+                        .transpose()
+                        .map_err(Into::into)
+                },
+            )?;
+
+            self.record(&stats, &composition)?;
+        }
+
+        Ok(())
+    }
+
+    fn record(&self, stats: &ChainStats, composition: &UtxoComposition) -> Result<(), Error> {
+        let encoded = stats.encode();
+        let encoded_composition = composition.encode();
+
+        let mut transaction = Transaction::new();
+        transaction.set(columns::INDEXES, TIP_KEY, &encoded);
+        transaction.set(columns::INDEXES, COMPOSITION_TIP_KEY, &encoded_composition);
+        if stats.height % CHAIN_STATS_INTERVAL == 0 {
+            transaction.set(columns::INDEXES, &chain_stats_key(stats.height), &encoded);
+            transaction.set(
+                columns::INDEXES,
+                &composition_key(stats.height),
+                &encoded_composition,
+            );
+            tracing::debug!("Indexed chain stats at #{}", stats.height);
+        }
+
+        self.db.commit(transaction).map_err(Into::into)
+    }
+
+    /// Counts the statistics from the whole UTXO set at the given height.
+    fn utxo_set_stats(&self, height: u32) -> Result<(ChainStats, UtxoComposition), Error> {
+        let block_hash = self
+            .client
+            .hash(height.into())?
+            .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("#{height}")))?;
+        let prefix = StorageKey(self.coin_storage_key.storage_prefix().to_vec());
+
+        let mut stats = ChainStats::default();
+        let mut composition = UtxoComposition::default();
+        let mut changes = (&mut stats, &mut composition);
+        for (_key, value) in self.client.storage_pairs(block_hash, Some(&prefix), None)? {
+            let coin = Coin::decode(&mut value.0.as_slice())?;
+            changes.add_coin(coin.amount, &coin.script_pubkey);
+        }
+        changes.set_height(height);
+
+        Ok((stats, composition))
+    }
+
+    /// Returns the block at given height and the hash of its parent.
+    fn bitcoin_block<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        number: u32,
+    ) -> Result<(Block::Hash, BitcoinBlock), Error> {
+        let block_hash = self
+            .client
+            .hash(number.into())?
+            .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("#{number}")))?;
+
+        let block = match self.client.block(block_hash)? {
+            Some(signed_block) => signed_block.block,
+            None if self.client.header(block_hash)?.is_some() => {
+                return Err(Error::BlockPruned(number));
+            }
+            None => return Err(sp_blockchain::Error::UnknownBlock(block_hash.to_string()).into()),
+        };
+
+        let parent_hash = *block.header().parent_hash();
+
+        let block = convert_to_bitcoin_block::<Block, TransactionAdapter>(block)
+            .map_err(|err| Error::InvalidBlock(number, err))?;
+
+        Ok((parent_hash, block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, ScriptBuf, Sequence, Transaction as BitcoinTransaction, TxIn, Witness};
+
+    fn tx(inputs: Vec<OutPoint>, outputs: Vec<(u64, ScriptBuf)>) -> BitcoinTransaction {
+        BitcoinTransaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: outputs
+                .into_iter()
+                .map(|(value, script_pubkey)| TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_apply_block() {
+        let script = ScriptBuf::from_bytes(vec![0x51]);
+        let op_return = ScriptBuf::new_op_return([1u8; 4]);
+
+        let parent_out_point = OutPoint {
+            txid: bitcoin::Txid::from_byte_array([1u8; 32]),
+            vout: 0,
+        };
+        let parent_coin = |out_point: &OutPoint| {
+            Ok((*out_point == parent_out_point).then(|| Coin {
+                is_coinbase: false,
+                amount: 1000,
+                height: 1,
+                script_pubkey: script.to_bytes(),
+            }))
+        };
+
+        let mut stats = ChainStats {
+            height: 1,
+            total_supply: 1000,
+            utxo_count: 1,
+            utxo_size: coin_size(1),
+            op_return_count: 0,
+        };
+
+        let coinbase = tx(vec![OutPoint::null()], vec![(5000, script.clone())]);
+        let spend_parent = tx(
+            vec![parent_out_point],
+            vec![(600, script.clone()), (0, op_return.clone())],
+        );
+        // Spends an output created in the same block.
+        let spend_in_block = tx(
+            vec![OutPoint {
+                txid: spend_parent.compute_txid(),
+                vout: 0,
+            }],
+            vec![(500, script.clone())],
+        );
+
+        let block = BitcoinBlock {
+            header: bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header,
+            txdata: vec![coinbase, spend_parent, spend_in_block],
+        };
+
+        let mut composition = UtxoComposition {
+            height: 1,
+            script_types: BTreeMap::from([(
+                ScriptType::NonStandard,
+                ScriptTypeStats {
+                    count: 1,
+                    amount: 1000,
+                },
+            )]),
+        };
+
+        apply_block(&mut (&mut stats, &mut composition), 2, &block, parent_coin).unwrap();
+
+        assert_eq!(
+            stats,
+            ChainStats {
+                height: 2,
+                total_supply: 5500,
+                utxo_count: 3,
+                utxo_size: coin_size(1) * 2 + coin_size(op_return.len()),
+                op_return_count: 1,
+            }
+        );
+
+        assert_eq!(
+            composition,
+            UtxoComposition {
+                height: 2,
+                script_types: BTreeMap::from([
+                    (
+                        ScriptType::OpReturn,
+                        ScriptTypeStats {
+                            count: 1,
+                            amount: 0,
+                        },
+                    ),
+                    (
+                        ScriptType::NonStandard,
+                        ScriptTypeStats {
+                            count: 2,
+                            amount: 5500,
+                        },
+                    ),
+                ]),
+            }
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! # Subcoin Indexer
+//!
+//! Optional indexes of the Bitcoin chain, built by the off-runtime tasks following the
+//! finalized blocks and stored in [`columns::INDEXES`] of the subcoin database.
+//!
+//! Only the finalized blocks are indexed so that the indexes never need to be reverted.
+//! The indexes built from each block alone are backfilled when enabled on a synced node, see
+//! [`BlockIndexer`].
+//!
+//! [`columns::INDEXES`]: subcoin_db::columns::INDEXES
+
+mod block_index;
+mod chain_stats;
+mod op_return;
+mod script_type;
+mod silent_payments;
+mod tx_index;
+
+pub use block_index::{index_height, BackfillConfig, BlockIndex, BlockIndexer};
+pub use chain_stats::{
+    chain_stats_range, chain_stats_tip, utxo_composition, ChainStats, ChainStatsIndexer,
+    ScriptTypeStats, UtxoComposition, CHAIN_STATS_INTERVAL,
+};
+pub use op_return::{
+    op_return_tip, search_op_return, OpReturnIndex, OpReturnOutput, OpReturnSearch,
+    MAX_SCANNED_BLOCKS, OP_RETURN_PAYLOAD_LIMIT,
+};
+pub use script_type::ScriptType;
+pub use silent_payments::{
+    silent_payment_tweaks, silent_payments_tip, SilentPaymentTweak, SilentPaymentsIndexer,
+};
+pub use tx_index::{tx_index_tip, tx_location, txid_for_wtxid, TxIndex, TxLocation};
+
+/// Returns the migrations of the schemas of the indexes.
+pub fn migrations() -> Vec<subcoin_db::Migration> {
+    vec![
+        tx_index::tx_index_migration(),
+        op_return::op_return_migration(),
+    ]
+}
+
+/// Indexer error type.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Block #{0} not available (pruned data)")]
+    BlockPruned(u32),
+    #[error("Invalid block #{0}: {1:?}")]
+    InvalidBlock(u32, subcoin_primitives::HeaderError),
+    #[error("UTXO {0:?} spent in block #{1} not found")]
+    MissingCoin(bitcoin::OutPoint, u32),
+    #[error(transparent)]
+    Database(#[from] subcoin_db::Error),
+    #[error(transparent)]
+    Blockchain(#[from] sp_blockchain::Error),
+    #[error(transparent)]
+    Codec(#[from] codec::Error),
+}
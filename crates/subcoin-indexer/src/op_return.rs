@@ -0,0 +1,229 @@
+//! Index of the `OP_RETURN` outputs.
+//!
+//! The `OP_RETURN` outputs of each finalized block are recorded under the block height, the
+//! payload being the concatenation of the data pushed after `OP_RETURN`. The payloads are
+//! searched by prefix by scanning the blocks in ascending order, at most
+//! [`MAX_SCANNED_BLOCKS`] per search.
+
+use crate::block_index::{index_height, index_height_migration, BlockIndex};
+use crate::Error;
+use bitcoin::hashes::Hash;
+use bitcoin::script::Instruction;
+use bitcoin::{Block as BitcoinBlock, Script, Txid};
+use codec::{Decode, Encode};
+use subcoin_db::{columns, Migration, SubcoinDb, Transaction};
+
+/// Maximum bytes of the payload kept in the index, the standard limit of `OP_RETURN` data.
+pub const OP_RETURN_PAYLOAD_LIMIT: usize = 80;
+
+/// Maximum number of blocks scanned by a search.
+pub const MAX_SCANNED_BLOCKS: u32 = 2016;
+
+/// Key of the last indexed block height in [`columns::INDEXES`] before schema version 2.
+const LEGACY_TIP_KEY: &[u8] = b"opreturn_tip";
+
+/// Prefix of the keys of the outputs in a block.
+const OP_RETURN_PREFIX: &[u8] = b"opreturn";
+
+fn op_return_key(height: u32) -> Vec<u8> {
+    let mut key = OP_RETURN_PREFIX.to_vec();
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+/// Indexed `OP_RETURN` output.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct OpReturnOutput {
+    pub txid: [u8; 32],
+    pub vout: u32,
+    pub height: u32,
+    /// Data pushed after `OP_RETURN`, truncated to [`OP_RETURN_PAYLOAD_LIMIT`] bytes.
+    pub payload: Vec<u8>,
+}
+
+impl OpReturnOutput {
+    pub fn txid(&self) -> Txid {
+        Txid::from_byte_array(self.txid)
+    }
+}
+
+/// Result of a search of the `OP_RETURN` outputs.
+#[derive(Debug, Clone, Default)]
+pub struct OpReturnSearch {
+    pub outputs: Vec<OpReturnOutput>,
+    /// Height to resume the search from, `None` once the last indexed block is scanned.
+    pub next_height: Option<u32>,
+}
+
+/// Returns the data pushed after `OP_RETURN`, `None` if the script is not `OP_RETURN`.
+fn op_return_payload(script: &Script) -> Option<Vec<u8>> {
+    if !script.is_op_return() {
+        return None;
+    }
+
+    let mut payload = Vec::new();
+    // The pushes are collected until the first non-push opcode or malformed push.
+    for instruction in script.instructions().skip(1) {
+        match instruction {
+            Ok(Instruction::PushBytes(bytes)) => payload.extend_from_slice(bytes.as_bytes()),
+            _ => break,
+        }
+    }
+    payload.truncate(OP_RETURN_PAYLOAD_LIMIT);
+
+    Some(payload)
+}
+
+fn block_op_returns(height: u32, block: &BitcoinBlock) -> Vec<OpReturnOutput> {
+    block
+        .txdata
+        .iter()
+        .flat_map(|tx| {
+            let txid = tx.compute_txid().to_byte_array();
+            tx.output
+                .iter()
+                .enumerate()
+                .filter_map(move |(vout, output)| {
+                    op_return_payload(&output.script_pubkey).map(|payload| OpReturnOutput {
+                        txid,
+                        vout: vout as u32,
+                        height,
+                        payload,
+                    })
+                })
+        })
+        .collect()
+}
+
+/// Returns the height of the last indexed block.
+pub fn op_return_tip(db: &SubcoinDb) -> Result<Option<u32>, Error> {
+    index_height(db, OpReturnIndex::NAME)
+}
+
+/// Returns the outputs whose payload starts with `prefix` from `from_height`, up to
+/// `max_outputs` outputs and [`MAX_SCANNED_BLOCKS`] blocks.
+pub fn search_op_return(
+    db: &SubcoinDb,
+    prefix: &[u8],
+    from_height: u32,
+    max_outputs: usize,
+) -> Result<OpReturnSearch, Error> {
+    let Some(tip) = op_return_tip(db)? else {
+        return Ok(OpReturnSearch::default());
+    };
+
+    let to = tip.min(from_height.saturating_add(MAX_SCANNED_BLOCKS - 1));
+
+    let mut search = OpReturnSearch::default();
+
+    for height in from_height..=to {
+        if let Some(encoded) = db.get(columns::INDEXES, &op_return_key(height)) {
+            let outputs = Vec::<OpReturnOutput>::decode(&mut encoded.as_slice())?;
+            search.outputs.extend(
+                outputs
+                    .into_iter()
+                    .filter(|output| output.payload.starts_with(prefix)),
+            );
+        }
+
+        if search.outputs.len() >= max_outputs {
+            search.outputs.truncate(max_outputs);
+            search.next_height = (height < tip).then_some(height + 1);
+            return Ok(search);
+        }
+    }
+
+    search.next_height = (to < tip).then_some(to + 1);
+
+    Ok(search)
+}
+
+/// [`BlockIndex`] of the [`OpReturnOutput`]s.
+pub struct OpReturnIndex;
+
+impl BlockIndex for OpReturnIndex {
+    const NAME: &'static str = "op_return";
+
+    fn index_block(&self, transaction: &mut Transaction, height: u32, block: &BitcoinBlock) {
+        let outputs = block_op_returns(height, block);
+        if !outputs.is_empty() {
+            transaction.set(columns::INDEXES, &op_return_key(height), &outputs.encode());
+        }
+    }
+}
+
+/// Returns the migration of the sync height of [`OpReturnIndex`] to the meta column.
+pub(crate) fn op_return_migration() -> Migration {
+    index_height_migration(OpReturnIndex::NAME, 2, LEGACY_TIP_KEY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::opcodes::all::OP_RETURN;
+    use bitcoin::script::{Builder, PushBytesBuf};
+    use bitcoin::ScriptBuf;
+
+    #[test]
+    fn test_op_return_payload() {
+        assert_eq!(op_return_payload(&ScriptBuf::from_bytes(vec![0x51])), None);
+        assert_eq!(
+            op_return_payload(&ScriptBuf::new_op_return([1u8, 2, 3])),
+            Some(vec![1, 2, 3])
+        );
+
+        let multiple_pushes = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice([1u8, 2])
+            .push_slice([3u8])
+            .into_script();
+        assert_eq!(op_return_payload(&multiple_pushes), Some(vec![1, 2, 3]));
+
+        let oversized = ScriptBuf::new_op_return(PushBytesBuf::try_from(vec![7u8; 100]).unwrap());
+        assert_eq!(
+            op_return_payload(&oversized).map(|payload| payload.len()),
+            Some(OP_RETURN_PAYLOAD_LIMIT)
+        );
+    }
+
+    #[test]
+    fn test_search_op_return() {
+        let db = SubcoinDb::in_memory();
+
+        let output = |height: u32, payload: &[u8]| OpReturnOutput {
+            txid: [height as u8; 32],
+            vout: 0,
+            height,
+            payload: payload.to_vec(),
+        };
+
+        let mut transaction = Transaction::new();
+        transaction.set(
+            columns::INDEXES,
+            &op_return_key(1),
+            &vec![output(1, b"ord1"), output(1, b"abc")].encode(),
+        );
+        transaction.set(
+            columns::INDEXES,
+            &op_return_key(3),
+            &vec![output(3, b"ord2")].encode(),
+        );
+        transaction.set(
+            columns::META,
+            &crate::block_index::index_height_key(OpReturnIndex::NAME),
+            &5u32.encode(),
+        );
+        db.commit(transaction).unwrap();
+
+        let search = search_op_return(&db, b"ord", 0, 10).unwrap();
+        assert_eq!(search.outputs, vec![output(1, b"ord1"), output(3, b"ord2")]);
+        assert_eq!(search.next_height, None);
+
+        let search = search_op_return(&db, b"ord", 0, 1).unwrap();
+        assert_eq!(search.outputs, vec![output(1, b"ord1")]);
+        assert_eq!(search.next_height, Some(2));
+
+        let search = search_op_return(&db, b"", 2, 10).unwrap();
+        assert_eq!(search.outputs, vec![output(3, b"ord2")]);
+    }
+}
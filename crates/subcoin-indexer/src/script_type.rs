@@ -0,0 +1,120 @@
+//! Classification of the output scripts.
+
+use bitcoin::Script;
+use codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Standard type of an output script.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode, Serialize, Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum ScriptType {
+    P2pk,
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    /// Witness program of a version or length not defined yet.
+    WitnessUnknown,
+    OpReturn,
+    NonStandard,
+}
+
+impl ScriptType {
+    /// Classifies the output script.
+    pub fn classify(script_pubkey: &Script) -> Self {
+        if script_pubkey.is_p2pkh() {
+            Self::P2pkh
+        } else if script_pubkey.is_p2sh() {
+            Self::P2sh
+        } else if script_pubkey.is_p2wpkh() {
+            Self::P2wpkh
+        } else if script_pubkey.is_p2wsh() {
+            Self::P2wsh
+        } else if script_pubkey.is_p2tr() {
+            Self::P2tr
+        } else if script_pubkey.is_witness_program() {
+            Self::WitnessUnknown
+        } else if script_pubkey.is_op_return() {
+            Self::OpReturn
+        } else if script_pubkey.is_p2pk() {
+            Self::P2pk
+        } else {
+            Self::NonStandard
+        }
+    }
+
+    /// Returns the name of the script type, same as its serialized form.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::P2pk => "p2pk",
+            Self::P2pkh => "p2pkh",
+            Self::P2sh => "p2sh",
+            Self::P2wpkh => "p2wpkh",
+            Self::P2wsh => "p2wsh",
+            Self::P2tr => "p2tr",
+            Self::WitnessUnknown => "witnessUnknown",
+            Self::OpReturn => "opReturn",
+            Self::NonStandard => "nonStandard",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::UntweakedPublicKey;
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{
+        PubkeyHash, ScriptBuf, ScriptHash, WPubkeyHash, WScriptHash, WitnessProgram, WitnessVersion,
+    };
+
+    #[test]
+    fn test_classify() {
+        let secp = Secp256k1::verification_only();
+        let internal_key = UntweakedPublicKey::from_slice(&[
+            0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87,
+            0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b,
+            0x16, 0xf8, 0x17, 0x98,
+        ])
+        .unwrap();
+
+        let cases = [
+            (
+                ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros()),
+                ScriptType::P2pkh,
+            ),
+            (
+                ScriptBuf::new_p2sh(&ScriptHash::all_zeros()),
+                ScriptType::P2sh,
+            ),
+            (
+                ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()),
+                ScriptType::P2wpkh,
+            ),
+            (
+                ScriptBuf::new_p2wsh(&WScriptHash::all_zeros()),
+                ScriptType::P2wsh,
+            ),
+            (
+                ScriptBuf::new_p2tr(&secp, internal_key, None),
+                ScriptType::P2tr,
+            ),
+            (
+                ScriptBuf::new_witness_program(
+                    &WitnessProgram::new(WitnessVersion::V2, &[0u8; 32]).unwrap(),
+                ),
+                ScriptType::WitnessUnknown,
+            ),
+            (ScriptBuf::new_op_return([1u8; 4]), ScriptType::OpReturn),
+            (ScriptBuf::from_bytes(vec![0x51]), ScriptType::NonStandard),
+        ];
+
+        for (script, script_type) in cases {
+            assert_eq!(ScriptType::classify(&script), script_type, "{script}");
+        }
+    }
+}
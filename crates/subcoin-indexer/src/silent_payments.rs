@@ -0,0 +1,496 @@
+//! Index of the BIP-352 silent payments tweaks.
+//!
+//! For each eligible transaction of a finalized block, the tweak `input_hash·A` is recorded,
+//! `A` being the sum of the public keys of the eligible inputs. A light client scans a block by
+//! computing `b_scan·tweak` for each tweak instead of fetching the whole block along with its
+//! prevouts, the same data as served by the other silent payments indexes.
+//!
+//! The prevouts are read from the state of the parent block, the indexer needs to be enabled
+//! from the genesis or with the archive state like [`ChainStatsIndexer`].
+//!
+//! [`ChainStatsIndexer`]: crate::ChainStatsIndexer
+
+use crate::Error;
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::{hash160, sha256, Hash, HashEngine};
+use bitcoin::key::Parity;
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, Verification, VerifyOnly, XOnlyPublicKey};
+use bitcoin::taproot::TAPROOT_ANNEX_PREFIX;
+use bitcoin::{
+    Block as BitcoinBlock, OutPoint, Script, ScriptBuf, Transaction as BitcoinTransaction, TxIn,
+};
+use codec::{Decode, Encode};
+use futures::StreamExt;
+use sc_client_api::{Backend, BlockBackend, BlockchainEvents, StorageKey, StorageProvider};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use subcoin_db::{columns, SubcoinDb, Transaction};
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{convert_to_bitcoin_block, BitcoinTransactionAdapter, CoinStorageKey};
+
+/// Key of the last indexed block height.
+const TIP_KEY: &[u8] = b"sptweaks_tip";
+
+/// Prefix of the keys of the tweaks in a block.
+const TWEAKS_PREFIX: &[u8] = b"sptweaks";
+
+/// The x coordinate of the NUMS point `H` defined in BIP-341, the taproot script path spends
+/// using it as internal key are not eligible.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+fn tweaks_key(height: u32) -> Vec<u8> {
+    let mut key = TWEAKS_PREFIX.to_vec();
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+/// Tweak of an eligible transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct SilentPaymentTweak {
+    /// Compressed public key `input_hash·A`.
+    pub tweak: [u8; 33],
+    /// Largest value of the taproot outputs in satoshis, allowing the clients to skip the
+    /// transactions with dust outputs only.
+    pub max_output_value: u64,
+}
+
+/// Returns the tweaks of the block at `height`.
+///
+/// `None` if the block is not indexed yet, an empty list if the block has no eligible
+/// transaction.
+pub fn silent_payment_tweaks(
+    db: &SubcoinDb,
+    height: u32,
+) -> Result<Option<Vec<SilentPaymentTweak>>, Error> {
+    let Some(tip) = silent_payments_tip(db)? else {
+        return Ok(None);
+    };
+
+    if height > tip {
+        return Ok(None);
+    }
+
+    db.get(columns::INDEXES, &tweaks_key(height))
+        .map(|encoded| Vec::<SilentPaymentTweak>::decode(&mut encoded.as_slice()))
+        .transpose()
+        .map(|tweaks| Some(tweaks.unwrap_or_default()))
+        .map_err(Into::into)
+}
+
+/// Returns the height of the last indexed block.
+pub fn silent_payments_tip(db: &SubcoinDb) -> Result<Option<u32>, Error> {
+    db.get(columns::INDEXES, TIP_KEY)
+        .map(|encoded| u32::decode(&mut encoded.as_slice()))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// Returns the public key of an input spending `prevout`, `None` if the input is not eligible.
+fn input_public_key(input: &TxIn, prevout: &Script) -> Option<PublicKey> {
+    let compressed_key = |bytes: &[u8]| {
+        (bytes.len() == 33)
+            .then(|| PublicKey::from_slice(bytes).ok())
+            .flatten()
+    };
+
+    if prevout.is_p2tr() {
+        let mut witness = input.witness.iter().collect::<Vec<_>>();
+        if witness.len() > 1
+            && witness
+                .last()
+                .is_some_and(|last| last.first() == Some(&TAPROOT_ANNEX_PREFIX))
+        {
+            witness.pop();
+        }
+        // Script path spend, the last element is the control block.
+        if witness.len() > 1 && witness.last()?.get(1..33) == Some(&NUMS_H[..]) {
+            return None;
+        }
+        XOnlyPublicKey::from_slice(&prevout.as_bytes()[2..34])
+            .ok()
+            .map(|key| key.public_key(Parity::Even))
+    } else if prevout.is_p2wpkh() {
+        input.witness.last().and_then(compressed_key)
+    } else if prevout.is_p2sh() {
+        let redeem_script = input.script_sig.as_bytes().get(1..)?;
+        if Script::from_bytes(redeem_script).is_p2wpkh() {
+            input.witness.last().and_then(compressed_key)
+        } else {
+            None
+        }
+    } else if prevout.is_p2pkh() {
+        // The public key is not necessarily in the last push of a malleated script_sig.
+        let key_hash = &prevout.as_bytes()[3..23];
+        let script_sig = input.script_sig.as_bytes();
+        (33..=script_sig.len()).rev().find_map(|end| {
+            let bytes = &script_sig[end - 33..end];
+            (hash160::Hash::hash(bytes).as_byte_array() == key_hash)
+                .then(|| compressed_key(bytes))
+                .flatten()
+        })
+    } else {
+        None
+    }
+}
+
+/// Returns `hash_BIP0352/Inputs(outpoint_L || A)`.
+fn input_hash(smallest_outpoint: &[u8], public_key_sum: &PublicKey) -> [u8; 32] {
+    let tag = sha256::Hash::hash(b"BIP0352/Inputs");
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_byte_array());
+    engine.input(tag.as_byte_array());
+    engine.input(smallest_outpoint);
+    engine.input(&public_key_sum.serialize());
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Returns the tweak of a transaction, `None` if the transaction is not eligible.
+///
+/// `prevouts` are the scripts spent by the inputs of `tx`.
+fn transaction_tweak<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &BitcoinTransaction,
+    prevouts: &[ScriptBuf],
+) -> Option<SilentPaymentTweak> {
+    let max_output_value = tx
+        .output
+        .iter()
+        .filter(|output| output.script_pubkey.is_p2tr())
+        .map(|output| output.value.to_sat())
+        .max()?;
+
+    // The transactions spending the future segwit versions are reserved for upgrades.
+    if prevouts.iter().any(|prevout| {
+        prevout
+            .witness_version()
+            .is_some_and(|version| version.to_num() > 1)
+    }) {
+        return None;
+    }
+
+    let public_keys = tx
+        .input
+        .iter()
+        .zip(prevouts)
+        .filter_map(|(input, prevout)| input_public_key(input, prevout))
+        .collect::<Vec<_>>();
+
+    // Fails if there is no eligible input or the keys sum up to the point at infinity.
+    let public_key_sum = PublicKey::combine_keys(&public_keys.iter().collect::<Vec<_>>()).ok()?;
+
+    let smallest_outpoint = tx
+        .input
+        .iter()
+        .map(|input| serialize(&input.previous_output))
+        .min()?;
+
+    let scalar = Scalar::from_be_bytes(input_hash(&smallest_outpoint, &public_key_sum)).ok()?;
+
+    let tweak = public_key_sum.mul_tweak(secp, &scalar).ok()?;
+
+    Some(SilentPaymentTweak {
+        tweak: tweak.serialize(),
+        max_output_value,
+    })
+}
+
+/// Returns the tweaks of the eligible transactions in the block.
+///
+/// `parent_coin` returns the coin in the UTXO set of the parent block.
+fn block_tweaks<C: Verification>(
+    secp: &Secp256k1<C>,
+    height: u32,
+    block: &BitcoinBlock,
+    parent_coin: impl Fn(&OutPoint) -> Result<Option<Coin>, Error>,
+) -> Result<Vec<SilentPaymentTweak>, Error> {
+    // Outputs created in this block, which may be spent by the later transactions.
+    let mut created = HashMap::<OutPoint, &ScriptBuf>::new();
+    let mut tweaks = Vec::new();
+
+    for tx in &block.txdata {
+        let txid = tx.compute_txid();
+
+        // Only the transactions with a taproot output need their prevouts.
+        if !tx.is_coinbase()
+            && tx
+                .output
+                .iter()
+                .any(|output| output.script_pubkey.is_p2tr())
+        {
+            let prevouts = tx
+                .input
+                .iter()
+                .map(|input| {
+                    let out_point = input.previous_output;
+                    match created.get(&out_point) {
+                        Some(script_pubkey) => Ok((*script_pubkey).clone()),
+                        None => parent_coin(&out_point)?
+                            .map(|coin| ScriptBuf::from_bytes(coin.script_pubkey))
+                            .ok_or(Error::MissingCoin(out_point, height)),
+                    }
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            tweaks.extend(transaction_tweak(secp, tx, &prevouts));
+        }
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            created.insert(
+                OutPoint {
+                    txid,
+                    vout: vout as u32,
+                },
+                &output.script_pubkey,
+            );
+        }
+    }
+
+    Ok(tweaks)
+}
+
+/// Indexer of the [`SilentPaymentTweak`]s.
+pub struct SilentPaymentsIndexer<Block, BE, Client> {
+    client: Arc<Client>,
+    db: SubcoinDb,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    secp: Secp256k1<VerifyOnly>,
+    _phantom: PhantomData<(Block, BE)>,
+}
+
+impl<Block, BE, Client> SilentPaymentsIndexer<Block, BE, Client>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>,
+{
+    /// Constructs a new instance of [`SilentPaymentsIndexer`].
+    pub fn new(
+        client: Arc<Client>,
+        db: SubcoinDb,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+    ) -> Self {
+        Self {
+            client,
+            db,
+            coin_storage_key,
+            secp: Secp256k1::verification_only(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns a future following the finalized blocks.
+    ///
+    /// The future needs to be spawned in the background, it takes a while to index the
+    /// historical blocks.
+    pub async fn run<TransactionAdapter: BitcoinTransactionAdapter<Block>>(self) {
+        let mut finality_stream = self.client.finality_notification_stream();
+
+        loop {
+            let finalized_number = self.client.info().finalized_number;
+            let Ok(finalized_number) = finalized_number.try_into() else {
+                return;
+            };
+
+            if let Err(err) = self.catch_up::<TransactionAdapter>(finalized_number) {
+                tracing::error!(?err, "Silent payments indexer stopped");
+                return;
+            }
+
+            if finality_stream.next().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    fn catch_up<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        finalized_number: u32,
+    ) -> Result<(), Error> {
+        // The genesis block has no eligible transaction.
+        let from = silent_payments_tip(&self.db)?.unwrap_or(0) + 1;
+
+        for height in from..=finalized_number {
+            let (parent_hash, block) = self.bitcoin_block::<TransactionAdapter>(height)?;
+
+            let tweaks = block_tweaks(&self.secp, height, &block, |out_point| {
+                let key = StorageKey(
+                    self.coin_storage_key
+                        .storage_key(out_point.txid, out_point.vout),
+                );
+                self.client
+                    .storage(parent_hash, &key)?
+                    .map(|data| Coin::decode(&mut data.0.as_slice()))
+                    .transpose()
+                    .map_err(Into::into)
+            })?;
+
+            let mut transaction = Transaction::new();
+            if !tweaks.is_empty() {
+                transaction.set(columns::INDEXES, &tweaks_key(height), &tweaks.encode());
+                tracing::debug!(
+                    "Indexed {} silent payments tweaks at #{height}",
+                    tweaks.len()
+                );
+            }
+            transaction.set(columns::INDEXES, TIP_KEY, &height.encode());
+            self.db.commit(transaction)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the block at given height and the hash of its parent.
+    fn bitcoin_block<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        number: u32,
+    ) -> Result<(Block::Hash, BitcoinBlock), Error> {
+        let block_hash = self
+            .client
+            .hash(number.into())?
+            .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("#{number}")))?;
+
+        let block = match self.client.block(block_hash)? {
+            Some(signed_block) => signed_block.block,
+            None if self.client.header(block_hash)?.is_some() => {
+                return Err(Error::BlockPruned(number));
+            }
+            None => return Err(sp_blockchain::Error::UnknownBlock(block_hash.to_string()).into()),
+        };
+
+        let parent_hash = *block.header().parent_hash();
+
+        let block = convert_to_bitcoin_block::<Block, TransactionAdapter>(block)
+            .map_err(|err| Error::InvalidBlock(number, err))?;
+
+        Ok((parent_hash, block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, CompressedPublicKey, Sequence, TxOut, Txid, Witness};
+
+    fn input(txid: u8, witness: Vec<Vec<u8>>) -> TxIn {
+        TxIn {
+            previous_output: OutPoint {
+                txid: Txid::from_byte_array([txid; 32]),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&witness),
+        }
+    }
+
+    fn p2tr_output(value: u64, key: XOnlyPublicKey) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(
+                bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(key),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_transaction_tweak() {
+        let secp = Secp256k1::new();
+        let p2wpkh_key = SecretKey::from_slice(&[1u8; 32]).unwrap().public_key(&secp);
+        let (p2tr_key, _) = SecretKey::from_slice(&[2u8; 32])
+            .unwrap()
+            .x_only_public_key(&secp);
+
+        let p2wpkh_prevout = ScriptBuf::new_p2wpkh(&CompressedPublicKey(p2wpkh_key).wpubkey_hash());
+        let p2tr_prevout = p2tr_output(0, p2tr_key).script_pubkey;
+
+        let tx = BitcoinTransaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![
+                input(2, vec![vec![0u8; 72], p2wpkh_key.serialize().to_vec()]),
+                input(1, vec![vec![0u8; 64]]),
+            ],
+            output: vec![p2tr_output(1000, p2tr_key), p2tr_output(2000, p2tr_key)],
+        };
+
+        let public_key_sum = p2wpkh_key
+            .combine(&p2tr_key.public_key(Parity::Even))
+            .unwrap();
+        let smallest_outpoint = serialize(&tx.input[1].previous_output);
+        let expected = public_key_sum
+            .mul_tweak(
+                &secp,
+                &Scalar::from_be_bytes(input_hash(&smallest_outpoint, &public_key_sum)).unwrap(),
+            )
+            .unwrap();
+
+        let prevouts = vec![p2wpkh_prevout.clone(), p2tr_prevout.clone()];
+        assert_eq!(
+            transaction_tweak(&secp, &tx, &prevouts),
+            Some(SilentPaymentTweak {
+                tweak: expected.serialize(),
+                max_output_value: 2000,
+            })
+        );
+
+        // Script path spend with the NUMS internal key.
+        let mut control_block = vec![0xc0];
+        control_block.extend_from_slice(&NUMS_H);
+        let mut nums_tx = tx.clone();
+        nums_tx.input[1] = input(1, vec![vec![0x51], control_block]);
+        let p2wpkh_only = p2wpkh_key.mul_tweak(
+            &secp,
+            &Scalar::from_be_bytes(input_hash(&smallest_outpoint, &p2wpkh_key)).unwrap(),
+        );
+        assert_eq!(
+            transaction_tweak(&secp, &nums_tx, &prevouts).map(|tweak| tweak.tweak),
+            Some(p2wpkh_only.unwrap().serialize())
+        );
+
+        // Spending a segwit v2 output.
+        let v2_prevout = ScriptBuf::from_bytes([&[0x52, 0x20][..], &[0u8; 32]].concat());
+        assert_eq!(
+            transaction_tweak(&secp, &tx, &[p2wpkh_prevout.clone(), v2_prevout]),
+            None
+        );
+
+        // No taproot output.
+        let mut no_taproot_output = tx.clone();
+        no_taproot_output.output[0].script_pubkey = p2wpkh_prevout.clone();
+        no_taproot_output.output.truncate(1);
+        assert_eq!(
+            transaction_tweak(&secp, &no_taproot_output, &prevouts),
+            None
+        );
+    }
+
+    #[test]
+    fn test_p2pkh_input_public_key() {
+        let secp = Secp256k1::new();
+        let public_key = SecretKey::from_slice(&[3u8; 32]).unwrap().public_key(&secp);
+        let prevout = ScriptBuf::new_p2pkh(&bitcoin::PublicKey::new(public_key).pubkey_hash());
+
+        let mut script_sig = vec![72];
+        script_sig.extend_from_slice(&[0u8; 72]);
+        script_sig.push(33);
+        script_sig.extend_from_slice(&public_key.serialize());
+
+        let mut tx_in = input(1, vec![]);
+        tx_in.script_sig = ScriptBuf::from_bytes(script_sig);
+
+        assert_eq!(input_public_key(&tx_in, &prevout), Some(public_key));
+        assert_eq!(input_public_key(&input(1, vec![]), &prevout), None);
+    }
+}
@@ -0,0 +1,177 @@
+//! Index of the transactions by txid and wtxid.
+//!
+//! Each transaction of the finalized blocks is recorded under its txid with the position in
+//! the chain, the transactions with witness are also recorded under their wtxid pointing to
+//! the txid. The wtxid of a transaction without witness is the same as its txid. Like Bitcoin
+//! Core, the duplicate coinbase txids point to the latest block.
+
+use crate::block_index::{index_height, index_height_migration, BlockIndex};
+use crate::Error;
+use bitcoin::hashes::Hash;
+use bitcoin::{Block as BitcoinBlock, Txid, Wtxid};
+use codec::{Decode, Encode};
+use subcoin_db::{columns, Migration, SubcoinDb, Transaction};
+
+/// Key of the last indexed block height in [`columns::INDEXES`] before schema version 2.
+const LEGACY_TIP_KEY: &[u8] = b"txindex_tip";
+
+/// Prefix of the keys of the transaction locations.
+const TXID_PREFIX: &[u8] = b"txindex_txid";
+
+/// Prefix of the keys of the txids by wtxid.
+const WTXID_PREFIX: &[u8] = b"txindex_wtxid";
+
+fn txid_key(txid: &Txid) -> Vec<u8> {
+    let mut key = TXID_PREFIX.to_vec();
+    key.extend_from_slice(txid.as_byte_array());
+    key
+}
+
+fn wtxid_key(wtxid: &Wtxid) -> Vec<u8> {
+    let mut key = WTXID_PREFIX.to_vec();
+    key.extend_from_slice(wtxid.as_byte_array());
+    key
+}
+
+/// Position of a transaction in the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct TxLocation {
+    /// Height of the block including the transaction.
+    pub height: u32,
+    /// Index of the transaction in the block.
+    pub index: u32,
+}
+
+/// Returns the height of the last indexed block.
+pub fn tx_index_tip(db: &SubcoinDb) -> Result<Option<u32>, Error> {
+    index_height(db, TxIndex::NAME)
+}
+
+/// Returns the location of the confirmed transaction.
+pub fn tx_location(db: &SubcoinDb, txid: &Txid) -> Result<Option<TxLocation>, Error> {
+    db.get(columns::INDEXES, &txid_key(txid))
+        .map(|encoded| TxLocation::decode(&mut encoded.as_slice()))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// Returns the txid of the confirmed transaction with the wtxid.
+pub fn txid_for_wtxid(db: &SubcoinDb, wtxid: &Wtxid) -> Result<Option<Txid>, Error> {
+    if let Some(encoded) = db.get(columns::INDEXES, &wtxid_key(wtxid)) {
+        let txid = <[u8; 32]>::decode(&mut encoded.as_slice())?;
+        return Ok(Some(Txid::from_byte_array(txid)));
+    }
+
+    let txid = Txid::from_raw_hash(wtxid.to_raw_hash());
+    Ok(tx_location(db, &txid)?.map(|_| txid))
+}
+
+fn index_block(transaction: &mut Transaction, height: u32, block: &BitcoinBlock) {
+    for (index, tx) in block.txdata.iter().enumerate() {
+        let txid = tx.compute_txid();
+        let location = TxLocation {
+            height,
+            index: index as u32,
+        };
+        transaction.set(columns::INDEXES, &txid_key(&txid), &location.encode());
+
+        let wtxid = tx.compute_wtxid();
+        if wtxid.as_byte_array() != txid.as_byte_array() {
+            transaction.set(
+                columns::INDEXES,
+                &wtxid_key(&wtxid),
+                &txid.to_byte_array().encode(),
+            );
+        }
+    }
+}
+
+/// [`BlockIndex`] of the transactions.
+pub struct TxIndex;
+
+impl BlockIndex for TxIndex {
+    const NAME: &'static str = "txindex";
+
+    fn index_block(&self, transaction: &mut Transaction, height: u32, block: &BitcoinBlock) {
+        index_block(transaction, height, block);
+    }
+}
+
+/// Returns the migration of the sync height of [`TxIndex`] to the meta column.
+pub(crate) fn tx_index_migration() -> Migration {
+    index_height_migration(TxIndex::NAME, 2, LEGACY_TIP_KEY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{
+        Amount, OutPoint, ScriptBuf, Sequence, Transaction as BitcoinTransaction, TxIn, TxOut,
+        Witness,
+    };
+
+    fn tx(previous_output: OutPoint, witness: Witness) -> BitcoinTransaction {
+        BitcoinTransaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness,
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_tx_index() {
+        let db = SubcoinDb::in_memory();
+
+        let coinbase = tx(OutPoint::null(), Witness::new());
+        let segwit = tx(
+            OutPoint {
+                txid: coinbase.compute_txid(),
+                vout: 0,
+            },
+            Witness::from_slice(&[[1u8; 64]]),
+        );
+        let block = BitcoinBlock {
+            header: bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header,
+            txdata: vec![coinbase.clone(), segwit.clone()],
+        };
+
+        let mut transaction = Transaction::new();
+        index_block(&mut transaction, 7, &block);
+        db.commit(transaction).unwrap();
+
+        assert_eq!(
+            tx_location(&db, &segwit.compute_txid()).unwrap(),
+            Some(TxLocation {
+                height: 7,
+                index: 1
+            })
+        );
+        assert_ne!(
+            segwit.compute_wtxid().as_byte_array(),
+            segwit.compute_txid().as_byte_array()
+        );
+        assert_eq!(
+            txid_for_wtxid(&db, &segwit.compute_wtxid()).unwrap(),
+            Some(segwit.compute_txid())
+        );
+        assert_eq!(
+            txid_for_wtxid(&db, &coinbase.compute_wtxid()).unwrap(),
+            Some(coinbase.compute_txid())
+        );
+        assert_eq!(
+            txid_for_wtxid(&db, &Wtxid::from_byte_array([9u8; 32])).unwrap(),
+            None
+        );
+    }
+}
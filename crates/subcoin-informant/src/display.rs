@@ -116,13 +116,30 @@ impl<B: BlockT> InformantDisplay<B> {
                 format!("Preparing{speed}"),
                 format!(", target=#{target}"),
             ),
+            SyncStatus::SnapshotSyncing {
+                target,
+                downloaded_chunks,
+                total_chunks,
+            } => (
+                "📸",
+                format!("Snapshot syncing, {downloaded_chunks}/{total_chunks} chunks"),
+                target
+                    .map(|target| format!(", target=#{target}"))
+                    .unwrap_or_default(),
+            ),
         };
 
         let finalized_hash = info.chain.finalized_hash;
 
+        let time_offset = if net_status.time_offset != 0 {
+            format!(", clock offset {:+}s", net_status.time_offset)
+        } else {
+            String::new()
+        };
+
         tracing::info!(
             target: "subcoin",
-            "{level} {}{target} ({} peers), best: #{} ({best_bitcoin_hash},{best_hash}), finalized #{} ({finalized_bitcoin_hash},{finalized_hash}), ⬇ {} ⬆ {}",
+            "{level} {}{target} ({} peers), best: #{} ({best_bitcoin_hash},{best_hash}), finalized #{} ({finalized_bitcoin_hash},{finalized_hash}){time_offset}, ⬇ {} ⬆ {}",
             style(status).white().bold(),
             style(num_connected_peers).white().bold(),
             style(best_number).white().bold(),
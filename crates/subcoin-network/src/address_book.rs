@@ -1,19 +1,98 @@
+use crate::i2p::{is_i2p, I2pDestinations};
 use crate::{validate_outbound_services, PeerId};
 use bitcoin::p2p::address::{AddrV2, AddrV2Message, Address};
-use std::collections::HashSet;
+use bitcoin::p2p::ServiceFlags;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
 
+/// Network an address belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum AddressNetwork {
+    Ipv4,
+    Ipv6,
+    /// `fc00::/8`, routed by the local CJDNS node.
+    Cjdns,
+    /// GarliCat address of an I2P destination.
+    I2p,
+}
+
+/// Returns the network of the address.
+pub(crate) fn address_network(addr: &PeerId) -> AddressNetwork {
+    match addr.ip().to_canonical() {
+        IpAddr::V4(_) => AddressNetwork::Ipv4,
+        IpAddr::V6(_) if is_i2p(addr) => AddressNetwork::I2p,
+        IpAddr::V6(ip) if ip.octets()[0] == 0xfc => AddressNetwork::Cjdns,
+        IpAddr::V6(_) => AddressNetwork::Ipv6,
+    }
+}
+
+/// Network group of an address, the outbound peers are picked from the distinct groups.
+///
+/// The groups are the IPv4 /16 and IPv6 /32 prefixes, autonomous systems (asmap) are not
+/// supported. The CJDNS and I2P addresses are grouped by their first 4 bits like in
+/// Bitcoin Core, they are not allocated by the operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum NetGroup {
+    Ipv4([u8; 2]),
+    Ipv6([u8; 4]),
+    Cjdns(u8),
+    I2p(u8),
+}
+
+/// Returns the network group of the address, `None` for the loopback and private addresses
+/// which are exempted from the diversity requirement.
+pub(crate) fn netgroup(addr: &PeerId) -> Option<NetGroup> {
+    match address_network(addr) {
+        AddressNetwork::I2p => {
+            if let IpAddr::V6(ip) = addr.ip() {
+                return Some(NetGroup::I2p(ip.octets()[6] >> 4));
+            }
+        }
+        AddressNetwork::Cjdns => {
+            if let IpAddr::V6(ip) = addr.ip() {
+                return Some(NetGroup::Cjdns(ip.octets()[1] >> 4));
+            }
+        }
+        AddressNetwork::Ipv4 | AddressNetwork::Ipv6 => {}
+    }
+
+    match addr.ip().to_canonical() {
+        IpAddr::V4(ip) => {
+            if ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() {
+                return None;
+            }
+            let [a, b, ..] = ip.octets();
+            Some(NetGroup::Ipv4([a, b]))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // Unique local (fc00::/7) and link-local (fe80::/10) addresses.
+            if ip.is_loopback()
+                || ip.is_unspecified()
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+            {
+                return None;
+            }
+            let [a, b, c, d, ..] = ip.octets();
+            Some(NetGroup::Ipv6([a, b, c, d]))
+        }
+    }
+}
+
 /// Manages the addresses discovered in the network.
 #[derive(Debug)]
 pub struct AddressBook {
-    /// Addresses available for establishing new connections.
-    discovered_addresses: HashSet<PeerId>,
+    /// Addresses available for establishing new connections, with the advertised services.
+    discovered_addresses: HashMap<PeerId, ServiceFlags>,
     /// Peers that currently have an active connection or are being communicated with.
     active_addresses: HashSet<PeerId>,
     /// Addresses that failed to establish a connection.
     failed_addresses: HashSet<PeerId>,
-    /// Indicates whether only IPv4 addresses should be stored.
-    ipv4_only: bool,
+    /// Networks of the addresses stored.
+    reachable: HashSet<AddressNetwork>,
+    /// Destinations of the I2P addresses, `None` if I2P is not reachable.
+    i2p_destinations: Option<I2pDestinations>,
     /// Maximum number of discovered addresses.
     max_addresses: usize,
     /// Random number generator for selecting peers.
@@ -24,15 +103,38 @@ impl AddressBook {
     /// Constructs a new instance of [`AddressBook`].
     pub fn new(ipv4_only: bool, max_addresses: usize) -> Self {
         Self {
-            discovered_addresses: HashSet::new(),
+            discovered_addresses: HashMap::new(),
             active_addresses: HashSet::new(),
             failed_addresses: HashSet::new(),
-            ipv4_only,
+            reachable: if ipv4_only {
+                HashSet::from([AddressNetwork::Ipv4])
+            } else {
+                HashSet::from([AddressNetwork::Ipv4, AddressNetwork::Ipv6])
+            },
+            i2p_destinations: None,
             max_addresses,
             rng: fastrand::Rng::new(),
         }
     }
 
+    /// Stores the CJDNS addresses.
+    pub(crate) fn with_cjdns(mut self) -> Self {
+        self.reachable.insert(AddressNetwork::Cjdns);
+        self
+    }
+
+    /// Stores the I2P addresses, registering their destinations.
+    pub(crate) fn with_i2p(mut self, i2p_destinations: I2pDestinations) -> Self {
+        self.reachable.insert(AddressNetwork::I2p);
+        self.i2p_destinations = Some(i2p_destinations);
+        self
+    }
+
+    /// Returns the networks of the addresses stored.
+    pub(crate) fn reachable_networks(&self) -> &HashSet<AddressNetwork> {
+        &self.reachable
+    }
+
     /// Checks if the address book has reached the maximum number of addresses.
     pub fn has_max_addresses(&self) -> bool {
         self.discovered_addresses.len() >= self.max_addresses
@@ -42,12 +144,57 @@ impl AddressBook {
         self.discovered_addresses.len()
     }
 
-    /// Pops a random address from the discovered addresses and marks it as active.
-    pub fn pop(&mut self) -> Option<PeerId> {
-        if let Some(peer) = self.rng.choice(self.discovered_addresses.iter()).copied() {
-            self.discovered_addresses.remove(&peer);
+    /// Returns the discovered addresses along with their advertised services.
+    pub fn addresses(&self) -> impl Iterator<Item = (PeerId, ServiceFlags)> + '_ {
+        self.discovered_addresses
+            .iter()
+            .map(|(addr, services)| (*addr, *services))
+    }
+
+    /// Restores the addresses saved by a previous run.
+    pub fn restore(&mut self, addresses: Vec<(PeerId, ServiceFlags)>) {
+        for (addr, services) in addresses {
+            if self.has_max_addresses() {
+                break;
+            }
+
+            if !self.reachable.contains(&address_network(&addr)) {
+                continue;
+            }
+
+            self.discovered_addresses.insert(addr, services);
+        }
+    }
+
+    /// Pops a random address outside of the `excluded` network groups from the discovered
+    /// addresses and marks it as active.
+    ///
+    /// The addresses of the `preferred` networks are picked first if any, so that the node
+    /// is connected to each reachable network. Returns the address along with its advertised
+    /// services.
+    pub(crate) fn pop(
+        &mut self,
+        excluded: &HashSet<NetGroup>,
+        preferred: &HashSet<AddressNetwork>,
+    ) -> Option<(PeerId, ServiceFlags)> {
+        let candidates = self
+            .discovered_addresses
+            .keys()
+            .filter(|addr| netgroup(addr).map_or(true, |group| !excluded.contains(&group)));
+
+        let preferred_candidates = candidates
+            .clone()
+            .filter(|addr| preferred.contains(&address_network(addr)));
+
+        let choice = self
+            .rng
+            .choice(preferred_candidates)
+            .or_else(|| self.rng.choice(candidates));
+
+        if let Some(peer) = choice.copied() {
+            let services = self.discovered_addresses.remove(&peer)?;
             self.active_addresses.insert(peer);
-            return Some(peer);
+            return Some((peer, services));
         }
         None
     }
@@ -76,7 +223,7 @@ impl AddressBook {
 
             if let Ok(addr) = address.socket_addr() {
                 if self.should_add_address(from, addr) {
-                    self.discovered_addresses.insert(addr);
+                    self.discovered_addresses.insert(addr, address.services);
                     added += 1;
                 }
             }
@@ -101,13 +248,26 @@ impl AddressBook {
             let addr = match address.addr {
                 AddrV2::Ipv4(addr) => PeerId::new(IpAddr::V4(addr), address.port),
                 AddrV2::Ipv6(addr) => PeerId::new(IpAddr::V6(addr), address.port),
+                AddrV2::Cjdns(addr) if addr.octets()[0] == 0xfc => {
+                    PeerId::new(IpAddr::V6(addr), address.port)
+                }
+                AddrV2::I2p(hash) => {
+                    let Some(addr) = self
+                        .i2p_destinations
+                        .as_ref()
+                        .and_then(|destinations| destinations.insert(hash))
+                    else {
+                        continue;
+                    };
+                    addr
+                }
                 _ => {
                     continue;
                 }
             };
 
             if self.should_add_address(from, addr) {
-                self.discovered_addresses.insert(addr);
+                self.discovered_addresses.insert(addr, address.services);
                 added += 1;
             }
         }
@@ -120,8 +280,7 @@ impl AddressBook {
             return false;
         }
 
-        // Skip IPv6 if in IPv4-only mode.
-        if self.ipv4_only && new_addr.is_ipv6() {
+        if !self.reachable.contains(&address_network(&new_addr)) {
             return false;
         }
 
@@ -132,3 +291,107 @@ impl AddressBook {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netgroup() {
+        let addr = |s: &str| s.parse::<PeerId>().unwrap();
+
+        assert_eq!(
+            netgroup(&addr("1.2.3.4:8333")),
+            netgroup(&addr("1.2.200.100:8333"))
+        );
+        assert_ne!(
+            netgroup(&addr("1.2.3.4:8333")),
+            netgroup(&addr("1.3.3.4:8333"))
+        );
+        assert_eq!(
+            netgroup(&addr("[2001:db8:1::1]:8333")),
+            netgroup(&addr("[2001:db8:ffff::2]:8333"))
+        );
+        assert_eq!(
+            netgroup(&addr("[::ffff:1.2.3.4]:8333")),
+            netgroup(&addr("1.2.3.4:8333"))
+        );
+        assert_eq!(netgroup(&addr("127.0.0.1:8333")), None);
+        assert_eq!(netgroup(&addr("192.168.1.1:8333")), None);
+        assert_eq!(netgroup(&addr("[fd00::1]:8333")), None);
+        assert_eq!(
+            netgroup(&addr("[fc12::1]:8333")),
+            netgroup(&addr("[fc1f::2]:8333"))
+        );
+        assert_ne!(
+            netgroup(&addr("[fc12::1]:8333")),
+            netgroup(&addr("[fc22::1]:8333"))
+        );
+    }
+
+    #[test]
+    fn test_add_i2p_and_cjdns_addresses() {
+        let from = "9.9.9.9:8333".parse().unwrap();
+        let services = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
+        let cjdns = "fc12::1".parse().unwrap();
+        let addresses = || {
+            vec![
+                AddrV2Message {
+                    time: 0,
+                    services,
+                    addr: AddrV2::I2p([7u8; 32]),
+                    port: 0,
+                },
+                AddrV2Message {
+                    time: 0,
+                    services,
+                    addr: AddrV2::Cjdns(cjdns),
+                    port: 8333,
+                },
+            ]
+        };
+
+        let mut address_book = AddressBook::new(true, 10);
+        assert_eq!(address_book.add_many_v2(from, addresses()), 0);
+
+        let i2p_destinations = I2pDestinations::default();
+        let mut address_book = AddressBook::new(true, 10)
+            .with_cjdns()
+            .with_i2p(i2p_destinations.clone());
+        assert_eq!(address_book.add_many_v2(from, addresses()), 2);
+
+        let i2p_addr = crate::i2p::garlicat(&[7u8; 32]);
+        assert_eq!(address_book.addresses().count(), 2);
+        assert_eq!(address_network(&i2p_addr), AddressNetwork::I2p);
+        assert_eq!(i2p_destinations.get(&i2p_addr), Some([7u8; 32]));
+
+        let preferred = HashSet::from([AddressNetwork::Cjdns]);
+        assert_eq!(
+            address_book
+                .pop(&HashSet::new(), &preferred)
+                .map(|(addr, _)| addr),
+            Some(PeerId::new(IpAddr::V6(cjdns), 8333))
+        );
+    }
+
+    #[test]
+    fn test_pop_skips_excluded_netgroups() {
+        let mut address_book = AddressBook::new(false, 10);
+        let from = "9.9.9.9:8333".parse().unwrap();
+        let addresses = ["1.2.3.4:8333", "1.2.5.6:8333"]
+            .into_iter()
+            .map(|addr| {
+                let addr: PeerId = addr.parse().unwrap();
+                (
+                    0,
+                    Address::new(&addr, ServiceFlags::NETWORK | ServiceFlags::WITNESS),
+                )
+            })
+            .collect();
+        assert_eq!(address_book.add_many(from, addresses), 2);
+
+        let excluded = HashSet::from([netgroup(&"1.2.0.1:8333".parse().unwrap()).unwrap()]);
+        assert!(address_book.pop(&excluded, &HashSet::new()).is_none());
+        assert!(address_book.pop(&HashSet::new(), &HashSet::new()).is_some());
+    }
+}
@@ -0,0 +1,636 @@
+//! BIP-324 v2 encrypted transport.
+//!
+//! The handshake is attempted on the outbound TCP connections to the peers advertising
+//! [`NODE_P2P_V2`], a failed attempt is retried over v1 by the connection initiator. On the
+//! inbound connections, the v1 peers are detected from the first bytes they send, which are
+//! the magic and the command of the v1 `version` message.
+//!
+//! [`NODE_P2P_V2`]: crate::NODE_P2P_V2
+
+use crate::connection::{check_message_entries, check_payload_len};
+use crate::transport::PeerStream;
+use crate::Error;
+use bitcoin::consensus::encode;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage, MAX_MSG_SIZE};
+use bitcoin::p2p::Magic;
+use bitcoin::secp256k1::ellswift::{ElligatorSwift, ElligatorSwiftParty};
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce, Tag};
+use hkdf::Hkdf;
+use rand::Rng;
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+const ELLSWIFT_LEN: usize = 64;
+const GARBAGE_TERMINATOR_LEN: usize = 16;
+const MAX_GARBAGE_LEN: usize = 4095;
+const LENGTH_FIELD_LEN: usize = 3;
+const HEADER_LEN: usize = 1;
+const TAG_LEN: usize = 16;
+const IGNORE_BIT: u8 = 1 << 7;
+const REKEY_INTERVAL: u64 = 224;
+
+/// Length of the magic and command of a v1 message header.
+const V1_PREFIX_LEN: usize = 16;
+
+/// Size of the v1 message header.
+const V1_HEADER_LEN: usize = 24;
+
+/// Length of a long message type encoding: a zero byte followed by the 12-byte command.
+const COMMAND_LEN: usize = 12;
+
+/// Messages with a short (one byte) message type encoding, the id is the index plus one.
+const SHORT_IDS: [&str; 28] = [
+    "addr",
+    "block",
+    "blocktxn",
+    "cmpctblock",
+    "feefilter",
+    "filteradd",
+    "filterclear",
+    "filterload",
+    "getblocks",
+    "getblocktxn",
+    "getdata",
+    "getheaders",
+    "headers",
+    "inv",
+    "mempool",
+    "merkleblock",
+    "notfound",
+    "ping",
+    "pong",
+    "sendcmpct",
+    "tx",
+    "getcfilters",
+    "cfilter",
+    "getcfheaders",
+    "cfheaders",
+    "getcfcheckpt",
+    "cfcheckpt",
+    "addrv2",
+];
+
+/// Outcome of the handshake.
+pub(crate) enum Handshake {
+    /// The responder detected a v1 peer, carrying the bytes already received from it.
+    V1(Vec<u8>),
+    /// The v2 session has been established.
+    V2(Session),
+}
+
+/// Established v2 session.
+pub(crate) struct Session {
+    pub(crate) decoder: PacketDecoder,
+    pub(crate) encoder: PacketEncoder,
+    pub(crate) session_id: [u8; 32],
+}
+
+/// Performs the v2 handshake over the stream.
+///
+/// Only the responder falls back to v1, the initiator fails if the peer does not speak v2.
+pub(crate) async fn handshake(
+    stream: &mut PeerStream,
+    magic: Magic,
+    initiator: bool,
+) -> Result<Handshake, Error> {
+    // `ThreadRng` must not be held across the await points.
+    let (secret_key, ours, garbage) = {
+        let mut rng = rand::thread_rng();
+
+        let secret_key = SecretKey::from_slice(&rng.gen::<[u8; 32]>())
+            .expect("The probability of an invalid random key is negligible; qed");
+        let ours =
+            ElligatorSwift::from_seckey(&Secp256k1::signing_only(), secret_key, Some(rng.gen()));
+
+        let mut garbage = vec![0u8; rng.gen_range(0..=MAX_GARBAGE_LEN)];
+        rng.fill(garbage.as_mut_slice());
+
+        (secret_key, ours, garbage)
+    };
+
+    let mut received = Vec::new();
+
+    if !initiator {
+        read_at_least(&mut stream.reader, &mut received, V1_PREFIX_LEN).await?;
+        if received[..V1_PREFIX_LEN] == v1_prefix(magic) {
+            return Ok(Handshake::V1(received));
+        }
+    }
+
+    let mut key_and_garbage = ours.to_array().to_vec();
+    key_and_garbage.extend_from_slice(&garbage);
+    stream.writer.write_all(&key_and_garbage).await?;
+
+    read_at_least(&mut stream.reader, &mut received, ELLSWIFT_LEN).await?;
+    let theirs = ElligatorSwift::from_array(
+        received[..ELLSWIFT_LEN]
+            .try_into()
+            .expect("Slice of ELLSWIFT_LEN bytes; qed"),
+    );
+    let mut received = received.split_off(ELLSWIFT_LEN);
+
+    let (ellswift_a, ellswift_b, party) = if initiator {
+        (ours, theirs, ElligatorSwiftParty::A)
+    } else {
+        (theirs, ours, ElligatorSwiftParty::B)
+    };
+    let shared_secret =
+        ElligatorSwift::shared_secret(ellswift_a, ellswift_b, secret_key, party, None);
+
+    let keys = SessionKeys::derive(shared_secret.as_secret_bytes(), magic, initiator);
+
+    let mut encoder = PacketEncoder {
+        length_cipher: FsChaCha20::new(keys.send_length_key),
+        packet_cipher: FsChaCha20Poly1305::new(keys.send_packet_key),
+    };
+    let mut decoder = PacketDecoder {
+        length_cipher: FsChaCha20::new(keys.recv_length_key),
+        packet_cipher: FsChaCha20Poly1305::new(keys.recv_packet_key),
+        unparsed: Vec::new(),
+        contents_len: None,
+        aad: Vec::new(),
+        magic,
+    };
+
+    // Our garbage terminator, followed by the version packet authenticating our garbage.
+    let mut terminator_and_version = keys.send_garbage_terminator.to_vec();
+    terminator_and_version.extend(encoder.encrypt_packet(&[], &garbage, false));
+    stream.writer.write_all(&terminator_and_version).await?;
+
+    let terminator_pos = loop {
+        if let Some(pos) = received
+            .windows(GARBAGE_TERMINATOR_LEN)
+            .position(|window| window == keys.recv_garbage_terminator)
+        {
+            break pos;
+        }
+        if received.len() >= MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN {
+            return Err(Error::V2GarbageTerminatorNotFound);
+        }
+        read_more(&mut stream.reader, &mut received).await?;
+    };
+
+    let rest = received.split_off(terminator_pos + GARBAGE_TERMINATOR_LEN);
+    received.truncate(terminator_pos);
+    decoder.aad = received;
+    decoder.input(&rest);
+
+    // The contents of the version packet are reserved for the future extensions, the decoy
+    // packets may be sent before it.
+    loop {
+        match decoder.decode_packet()? {
+            Some((true, _decoy)) => continue,
+            Some((false, _version)) => break,
+            None => {
+                let mut bytes = Vec::new();
+                read_more(&mut stream.reader, &mut bytes).await?;
+                decoder.input(&bytes);
+            }
+        }
+    }
+
+    Ok(Handshake::V2(Session {
+        decoder,
+        encoder,
+        session_id: keys.session_id,
+    }))
+}
+
+fn v1_prefix(magic: Magic) -> [u8; V1_PREFIX_LEN] {
+    let mut prefix = [0u8; V1_PREFIX_LEN];
+    prefix[..4].copy_from_slice(&magic.to_bytes());
+    prefix[4..4 + b"version".len()].copy_from_slice(b"version");
+    prefix
+}
+
+async fn read_more(
+    reader: &mut Box<dyn AsyncRead + Send + Unpin>,
+    buffer: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let mut chunk = [0u8; 4096];
+    let n = reader.read(&mut chunk).await?;
+    if n == 0 {
+        return Err(Error::PeerShutdown);
+    }
+    buffer.extend_from_slice(&chunk[..n]);
+    Ok(())
+}
+
+async fn read_at_least(
+    reader: &mut Box<dyn AsyncRead + Send + Unpin>,
+    buffer: &mut Vec<u8>,
+    len: usize,
+) -> Result<(), Error> {
+    while buffer.len() < len {
+        read_more(reader, buffer).await?;
+    }
+    Ok(())
+}
+
+struct SessionKeys {
+    send_length_key: [u8; 32],
+    send_packet_key: [u8; 32],
+    recv_length_key: [u8; 32],
+    recv_packet_key: [u8; 32],
+    send_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    recv_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    session_id: [u8; 32],
+}
+
+impl SessionKeys {
+    fn derive(shared_secret: &[u8; 32], magic: Magic, initiator: bool) -> Self {
+        let mut salt = b"bitcoin_v2_shared_secret".to_vec();
+        salt.extend_from_slice(&magic.to_bytes());
+
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret);
+        let expand = |info: &[u8]| {
+            let mut okm = [0u8; 32];
+            hkdf.expand(info, &mut okm)
+                .expect("32 bytes is a valid output length; qed");
+            okm
+        };
+
+        let initiator_length_key = expand(b"initiator_L");
+        let initiator_packet_key = expand(b"initiator_P");
+        let responder_length_key = expand(b"responder_L");
+        let responder_packet_key = expand(b"responder_P");
+        let garbage_terminators = expand(b"garbage_terminators");
+
+        let (initiator_terminator, responder_terminator) =
+            garbage_terminators.split_at(GARBAGE_TERMINATOR_LEN);
+        let initiator_terminator = initiator_terminator
+            .try_into()
+            .expect("Half of 32 bytes; qed");
+        let responder_terminator = responder_terminator
+            .try_into()
+            .expect("Half of 32 bytes; qed");
+
+        let session_id = expand(b"session_id");
+
+        if initiator {
+            Self {
+                send_length_key: initiator_length_key,
+                send_packet_key: initiator_packet_key,
+                recv_length_key: responder_length_key,
+                recv_packet_key: responder_packet_key,
+                send_garbage_terminator: initiator_terminator,
+                recv_garbage_terminator: responder_terminator,
+                session_id,
+            }
+        } else {
+            Self {
+                send_length_key: responder_length_key,
+                send_packet_key: responder_packet_key,
+                recv_length_key: initiator_length_key,
+                recv_packet_key: initiator_packet_key,
+                send_garbage_terminator: responder_terminator,
+                recv_garbage_terminator: initiator_terminator,
+                session_id,
+            }
+        }
+    }
+}
+
+/// ChaCha20 with the key rotated every [`REKEY_INTERVAL`] chunks, encrypting the lengths.
+struct FsChaCha20 {
+    cipher: ChaCha20,
+    chunk_counter: u64,
+}
+
+impl FsChaCha20 {
+    fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: Self::cipher(&key, 0),
+            chunk_counter: 0,
+        }
+    }
+
+    fn cipher(key: &[u8; 32], rekey_counter: u64) -> ChaCha20 {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&rekey_counter.to_le_bytes());
+        ChaCha20::new(key.into(), &nonce.into())
+    }
+
+    fn crypt(&mut self, chunk: &mut [u8]) {
+        self.cipher.apply_keystream(chunk);
+        self.chunk_counter += 1;
+        if self.chunk_counter % REKEY_INTERVAL == 0 {
+            let mut key = [0u8; 32];
+            self.cipher.apply_keystream(&mut key);
+            self.cipher = Self::cipher(&key, self.chunk_counter / REKEY_INTERVAL);
+        }
+    }
+}
+
+/// ChaCha20Poly1305 with the key rotated every [`REKEY_INTERVAL`] packets.
+struct FsChaCha20Poly1305 {
+    key: [u8; 32],
+    packet_counter: u64,
+}
+
+impl FsChaCha20Poly1305 {
+    fn new(key: [u8; 32]) -> Self {
+        Self {
+            key,
+            packet_counter: 0,
+        }
+    }
+
+    fn nonce(first: u32, second: u64) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[..4].copy_from_slice(&first.to_le_bytes());
+        nonce[4..].copy_from_slice(&second.to_le_bytes());
+        nonce.into()
+    }
+
+    fn packet_nonce(&self) -> Nonce {
+        Self::nonce(
+            (self.packet_counter % REKEY_INTERVAL) as u32,
+            self.packet_counter / REKEY_INTERVAL,
+        )
+    }
+
+    fn encrypt(&mut self, aad: &[u8], buffer: &mut [u8]) -> Tag {
+        let tag = ChaCha20Poly1305::new((&self.key).into())
+            .encrypt_in_place_detached(&self.packet_nonce(), aad, buffer)
+            .expect("Packet size is far below the limit of ChaCha20Poly1305; qed");
+        self.advance();
+        tag
+    }
+
+    fn decrypt(&mut self, aad: &[u8], buffer: &mut [u8], tag: &[u8]) -> Result<(), Error> {
+        let result = ChaCha20Poly1305::new((&self.key).into()).decrypt_in_place_detached(
+            &self.packet_nonce(),
+            aad,
+            buffer,
+            Tag::from_slice(tag),
+        );
+        self.advance();
+        result.map_err(|_| Error::V2PacketAuthentication)
+    }
+
+    fn advance(&mut self) {
+        if (self.packet_counter + 1) % REKEY_INTERVAL == 0 {
+            let mut key = [0u8; 32];
+            ChaCha20Poly1305::new((&self.key).into())
+                .encrypt_in_place_detached(
+                    &Self::nonce(u32::MAX, self.packet_counter / REKEY_INTERVAL),
+                    &[],
+                    &mut key,
+                )
+                .expect("32 bytes is far below the limit of ChaCha20Poly1305; qed");
+            self.key = key;
+        }
+        self.packet_counter += 1;
+    }
+}
+
+/// Encrypts the outgoing messages.
+pub(crate) struct PacketEncoder {
+    length_cipher: FsChaCha20,
+    packet_cipher: FsChaCha20Poly1305,
+}
+
+impl PacketEncoder {
+    fn encrypt_packet(&mut self, contents: &[u8], aad: &[u8], ignore: bool) -> Vec<u8> {
+        let mut packet =
+            Vec::with_capacity(LENGTH_FIELD_LEN + HEADER_LEN + contents.len() + TAG_LEN);
+
+        packet.extend_from_slice(&(contents.len() as u32).to_le_bytes()[..LENGTH_FIELD_LEN]);
+        self.length_cipher.crypt(&mut packet);
+
+        packet.push(if ignore { IGNORE_BIT } else { 0 });
+        packet.extend_from_slice(contents);
+
+        let tag = self
+            .packet_cipher
+            .encrypt(aad, &mut packet[LENGTH_FIELD_LEN..]);
+        packet.extend_from_slice(&tag);
+
+        packet
+    }
+
+    /// Encodes the message into a packet, returning the command and payload size as well.
+    pub(crate) fn encode(
+        &mut self,
+        magic: Magic,
+        network_message: NetworkMessage,
+    ) -> Result<(&'static str, usize, Vec<u8>), Error> {
+        let raw_network_message = RawNetworkMessage::new(magic, network_message);
+        let cmd = raw_network_message.cmd();
+
+        let mut v1 = Vec::new();
+        raw_network_message.consensus_encode(&mut v1)?;
+
+        let command = &v1[4..4 + COMMAND_LEN];
+        let payload = &v1[V1_HEADER_LEN..];
+
+        let mut contents = Vec::with_capacity(1 + COMMAND_LEN + payload.len());
+        match SHORT_IDS.iter().position(|short| *short == cmd) {
+            Some(index) => contents.push(index as u8 + 1),
+            None => {
+                contents.push(0);
+                contents.extend_from_slice(command);
+            }
+        }
+        contents.extend_from_slice(payload);
+
+        Ok((
+            cmd,
+            payload.len(),
+            self.encrypt_packet(&contents, &[], false),
+        ))
+    }
+}
+
+/// Decrypts the incoming messages.
+pub(crate) struct PacketDecoder {
+    length_cipher: FsChaCha20,
+    packet_cipher: FsChaCha20Poly1305,
+    unparsed: Vec<u8>,
+    /// Length of the packet contents being received.
+    contents_len: Option<usize>,
+    /// Associated data of the next packet, which is the garbage for the first packet.
+    aad: Vec<u8>,
+    magic: Magic,
+}
+
+impl PacketDecoder {
+    pub(crate) fn input(&mut self, bytes: &[u8]) {
+        self.unparsed.extend_from_slice(bytes);
+    }
+
+    /// Returns the next packet contents and whether it's a decoy packet.
+    fn decode_packet(&mut self) -> Result<Option<(bool, Vec<u8>)>, Error> {
+        let contents_len = match self.contents_len {
+            Some(contents_len) => contents_len,
+            None => {
+                if self.unparsed.len() < LENGTH_FIELD_LEN {
+                    return Ok(None);
+                }
+
+                let mut length = [0u8; 4];
+                length[..LENGTH_FIELD_LEN].copy_from_slice(&self.unparsed[..LENGTH_FIELD_LEN]);
+                self.length_cipher.crypt(&mut length[..LENGTH_FIELD_LEN]);
+                self.unparsed.drain(..LENGTH_FIELD_LEN);
+
+                let contents_len = u32::from_le_bytes(length) as usize;
+                if contents_len > 1 + COMMAND_LEN + MAX_MSG_SIZE {
+                    return Err(Error::V2PacketTooLarge(contents_len));
+                }
+
+                *self.contents_len.insert(contents_len)
+            }
+        };
+
+        let packet_len = HEADER_LEN + contents_len + TAG_LEN;
+        if self.unparsed.len() < packet_len {
+            return Ok(None);
+        }
+
+        let mut packet = self.unparsed.drain(..packet_len).collect::<Vec<_>>();
+        let tag = packet.split_off(HEADER_LEN + contents_len);
+
+        let aad = std::mem::take(&mut self.aad);
+        self.packet_cipher.decrypt(&aad, &mut packet, &tag)?;
+        self.contents_len = None;
+
+        let ignore = packet[0] & IGNORE_BIT != 0;
+        packet.remove(0);
+
+        Ok(Some((ignore, packet)))
+    }
+
+    /// Decodes the next message, skipping the decoy packets.
+    ///
+    /// Returns [`None`] if nothing was decoded.
+    pub(crate) fn decode_next(&mut self) -> Result<Option<NetworkMessage>, Error> {
+        while let Some((ignore, contents)) = self.decode_packet()? {
+            if !ignore {
+                return self.decode_message(&contents).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_message(&self, contents: &[u8]) -> Result<NetworkMessage, Error> {
+        let (&message_type, rest) = contents.split_first().ok_or(Error::V2EmptyPacket)?;
+
+        let mut command = [0u8; COMMAND_LEN];
+        let payload = if message_type == 0 {
+            if rest.len() < COMMAND_LEN {
+                return Err(Error::V2EmptyPacket);
+            }
+            command.copy_from_slice(&rest[..COMMAND_LEN]);
+            &rest[COMMAND_LEN..]
+        } else {
+            let short = SHORT_IDS
+                .get(message_type as usize - 1)
+                .ok_or(Error::V2UnknownMessageId(message_type))?;
+            command[..short.len()].copy_from_slice(short.as_bytes());
+            rest
+        };
+
+        check_payload_len(&command, payload.len())?;
+
+        // Reuse the v1 decoding.
+        let mut v1 = Vec::with_capacity(V1_HEADER_LEN + payload.len());
+        v1.extend_from_slice(&self.magic.to_bytes());
+        v1.extend_from_slice(&command);
+        v1.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        v1.extend_from_slice(&sha256d::Hash::hash(payload)[..4]);
+        v1.extend_from_slice(payload);
+
+        let message = encode::deserialize::<RawNetworkMessage>(&v1)?.into_payload();
+        check_message_entries(&message)?;
+
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Transport;
+
+    fn duplex_streams() -> (PeerStream, PeerStream) {
+        let (a, b) = tokio::io::duplex(64 * 1024);
+        let to_peer_stream = |stream: tokio::io::DuplexStream| {
+            let (reader, writer) = tokio::io::split(stream);
+            PeerStream {
+                transport: Transport::Tcp,
+                peer_addr: "127.0.0.1:8333".parse().unwrap(),
+                local_addr: "127.0.0.1:8333".parse().unwrap(),
+                reader: Box::new(reader),
+                writer: Box::new(writer),
+            }
+        };
+        (to_peer_stream(a), to_peer_stream(b))
+    }
+
+    #[tokio::test]
+    async fn test_v2_session() {
+        let magic = bitcoin::Network::Bitcoin.magic();
+        let (mut initiator, mut responder) = duplex_streams();
+
+        let (initiated, responded) = tokio::join!(
+            handshake(&mut initiator, magic, true),
+            handshake(&mut responder, magic, false)
+        );
+        let (Handshake::V2(mut initiated), Handshake::V2(mut responded)) =
+            (initiated.unwrap(), responded.unwrap())
+        else {
+            panic!("v2 session must be established");
+        };
+        assert_eq!(initiated.session_id, responded.session_id);
+
+        // Cross the rekey interval in both directions.
+        for nonce in 0..(REKEY_INTERVAL * 2) {
+            let (_, _, packet) = initiated
+                .encoder
+                .encode(magic, NetworkMessage::Ping(nonce))
+                .unwrap();
+            responded.decoder.input(&packet);
+            assert_eq!(
+                responded.decoder.decode_next().unwrap(),
+                Some(NetworkMessage::Ping(nonce))
+            );
+
+            let (_, _, packet) = responded
+                .encoder
+                .encode(
+                    magic,
+                    NetworkMessage::Unknown {
+                        command: bitcoin::p2p::message::CommandString::try_from("sendtxrcncl")
+                            .unwrap(),
+                        payload: nonce.to_le_bytes().to_vec(),
+                    },
+                )
+                .unwrap();
+            initiated.decoder.input(&packet);
+            assert!(matches!(
+                initiated.decoder.decode_next().unwrap(),
+                Some(NetworkMessage::Unknown { payload, .. }) if payload == nonce.to_le_bytes()
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_v1_detection() {
+        let magic = bitcoin::Network::Bitcoin.magic();
+        let (mut initiator, mut responder) = duplex_streams();
+
+        initiator.writer.write_all(&v1_prefix(magic)).await.unwrap();
+
+        match handshake(&mut responder, magic, false).await.unwrap() {
+            Handshake::V1(received) => assert_eq!(received, v1_prefix(magic)),
+            Handshake::V2(_) => panic!("v1 peer must be detected"),
+        }
+    }
+}
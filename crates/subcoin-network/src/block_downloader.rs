@@ -4,10 +4,13 @@ mod headers_first;
 pub use self::blocks_first::BlocksFirstDownloader;
 pub use self::headers_first::HeadersFirstDownloader;
 
-use crate::orphan_blocks_pool::OrphanBlocksPool;
+use crate::orphan_blocks_pool::{OrphanBlocksPool, ORPHAN_BLOCK_EXPIRY};
+use crate::StopAt;
 use bitcoin::{Block as BitcoinBlock, BlockHash};
 use sc_consensus::BlockImportError;
-use sc_consensus_nakamoto::ImportManyBlocksResult;
+use sc_consensus_nakamoto::{
+    block_verification_failure, BlockVerificationError, ImportManyBlocksResult,
+};
 use std::collections::{HashMap, HashSet};
 use std::time::{Duration, Instant};
 
@@ -47,6 +50,52 @@ impl QueuedBlocks {
     }
 }
 
+/// Accounts the memory held by the blocks downloaded but not imported yet.
+///
+/// The blocks are accounted from being queued until their import results are received, which
+/// covers both the blocks waiting to be sent to the import queue and the ones in the queue.
+#[derive(Debug, Clone)]
+struct ImportMemory {
+    /// Maximum bytes before the download is paused.
+    budget: usize,
+    /// Size of each accounted block.
+    block_sizes: HashMap<BlockHash, usize>,
+    /// Total size of the accounted blocks.
+    used: usize,
+}
+
+impl ImportMemory {
+    fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            block_sizes: HashMap::new(),
+            used: 0,
+        }
+    }
+
+    fn add(&mut self, block_hash: BlockHash, size: usize) {
+        if let Some(old_size) = self.block_sizes.insert(block_hash, size) {
+            self.used -= old_size;
+        }
+        self.used += size;
+    }
+
+    fn remove(&mut self, block_hash: &BlockHash) {
+        if let Some(size) = self.block_sizes.remove(block_hash) {
+            self.used -= size;
+        }
+    }
+
+    fn is_exceeded(&self) -> bool {
+        self.used > self.budget
+    }
+
+    fn clear(&mut self) {
+        self.block_sizes.clear();
+        self.used = 0;
+    }
+}
+
 /// Manages the of blocks downloaded from the Bitcoin network.
 ///
 /// This struct keeps track of:
@@ -77,6 +126,11 @@ pub(crate) struct BlockDownloadManager {
     best_queued_number: u32,
     /// Orphan blocks
     orphan_blocks_pool: OrphanBlocksPool,
+    /// Memory held by the queued blocks.
+    ///
+    /// The orphan blocks are bounded by the pool separately, they can't be counted here as
+    /// their parents would never be downloaded once the budget is exceeded.
+    import_memory: ImportMemory,
     /// Last time at which the block was received or imported.
     ///
     /// This is updated whenever a block is received from the network or
@@ -90,7 +144,7 @@ pub(crate) struct BlockDownloadManager {
 }
 
 impl BlockDownloadManager {
-    fn new() -> Self {
+    fn new(import_memory_budget: usize) -> Self {
         Self {
             requested_blocks: HashSet::new(),
             downloaded_blocks: Vec::new(),
@@ -98,6 +152,7 @@ impl BlockDownloadManager {
             queued_blocks: QueuedBlocks::default(),
             best_queued_number: 0u32,
             orphan_blocks_pool: OrphanBlocksPool::new(),
+            import_memory: ImportMemory::new(import_memory_budget),
             last_progress_time: Instant::now(),
             import_queue_is_overloaded: false,
             last_overloaded_queue_log_time: None,
@@ -148,7 +203,8 @@ impl BlockDownloadManager {
 
         let queued_blocks = self.best_queued_number - best_number;
 
-        let import_queue_is_overloaded = queued_blocks > max_queued_blocks;
+        let import_queue_is_overloaded =
+            queued_blocks > max_queued_blocks || self.import_memory.is_exceeded();
 
         if import_queue_is_overloaded
             && self
@@ -159,6 +215,8 @@ impl BlockDownloadManager {
             tracing::debug!(
                 best_number,
                 best_queued_number = self.best_queued_number,
+                import_memory = self.import_memory.used,
+                import_memory_budget = self.import_memory.budget,
                 "⏸️ Pausing download: too many blocks ({queued_blocks}) in the queue",
             );
             self.last_overloaded_queue_log_time.replace(Instant::now());
@@ -176,6 +234,7 @@ impl BlockDownloadManager {
         self.queued_blocks.clear();
         self.best_queued_number = 0u32;
         self.orphan_blocks_pool.clear();
+        self.import_memory.clear();
         self.last_progress_time = Instant::now();
         self.import_queue_is_overloaded = false;
     }
@@ -189,26 +248,55 @@ impl BlockDownloadManager {
         self.blocks_in_queue.len()
     }
 
+    pub(crate) fn requested_blocks_count(&self) -> usize {
+        self.requested_blocks.len()
+    }
+
     /// Handles blocks that have been processed.
-    pub(crate) fn handle_processed_blocks(&mut self, results: ImportManyBlocksResult) {
+    ///
+    /// Returns the block rejected due to failing the verification, if any.
+    pub(crate) fn handle_processed_blocks(
+        &mut self,
+        results: ImportManyBlocksResult,
+    ) -> Option<(BlockHash, BlockVerificationError)> {
         self.last_progress_time = Instant::now();
+        let mut invalid_block = None;
         for (import_result, hash) in &results.results {
             self.blocks_in_queue.remove(hash);
             self.queued_blocks.remove(hash);
+            self.import_memory.remove(hash);
 
             match import_result {
                 Ok(_) => {}
                 Err(BlockImportError::UnknownParent) => panic!("Unknown parent {hash}"),
+                // The chain is switched to another peer once the download is stalled.
+                Err(BlockImportError::BadBlock(_)) => {
+                    tracing::warn!("Rejected block {hash} known to be invalid");
+                }
+                Err(BlockImportError::Cancelled) => {}
+                Err(BlockImportError::Other(err)) => match block_verification_failure(err) {
+                    // The blocks after the invalid one are cancelled.
+                    Some(verification_error) => {
+                        invalid_block = Some((*hash, verification_error.clone()));
+                    }
+                    None => panic!("Failed to import block {hash:?}: {err:?}"),
+                },
                 Err(err) => {
                     // TODO: handle error properly
                     panic!("Failed to import block {hash:?}: {err:?}");
                 }
             }
         }
+        invalid_block
     }
 
     /// Takes downloaded blocks and prepares them for import.
-    pub(crate) fn prepare_blocks_for_import(&mut self) -> (Vec<BlockHash>, Vec<BitcoinBlock>) {
+    ///
+    /// The blocks after `stop_at` are discarded.
+    pub(crate) fn prepare_blocks_for_import(
+        &mut self,
+        stop_at: Option<StopAt>,
+    ) -> (Vec<BlockHash>, Vec<BitcoinBlock>) {
         let blocks = std::mem::take(&mut self.downloaded_blocks);
 
         let mut blocks = blocks
@@ -229,6 +317,21 @@ impl BlockDownloadManager {
         // Ensure the blocks sent to the import queue are ordered.
         blocks.sort_by(|a, b| a.0.cmp(&b.0));
 
+        let stop_number = stop_at.and_then(|stop_at| match stop_at {
+            StopAt::Height(height) => Some(height),
+            StopAt::BlockHash(block_hash) => self.queued_blocks.block_number(block_hash),
+        });
+
+        if let Some(stop_number) = stop_number {
+            let index = blocks.partition_point(|(number, _)| *number <= stop_number);
+            for (_, block) in blocks.drain(index..) {
+                let block_hash = block.block_hash();
+                self.blocks_in_queue.remove(&block_hash);
+                self.queued_blocks.remove(&block_hash);
+                self.import_memory.remove(&block_hash);
+            }
+        }
+
         blocks
             .into_iter()
             .map(|(number, block)| {
@@ -244,24 +347,43 @@ impl BlockDownloadManager {
 
     /// Add the block the queue that is ready to be imported.
     fn add_block(&mut self, block_number: u32, block_hash: BlockHash, block: BitcoinBlock) {
-        let mut insert_block = |block_number, block_hash, block| {
-            self.downloaded_blocks.push(block);
-            self.queued_blocks.insert(block_number, block_hash);
-            if block_number > self.best_queued_number {
-                self.best_queued_number = block_number;
+        self.queue_block(block_number, block_hash, block);
+
+        // The block may have been received earlier as an unknown block.
+        self.orphan_blocks_pool.remove_block(&block_hash);
+
+        // The children come after their parent, the parent's number is known by then.
+        for child_block in self.orphan_blocks_pool.remove_blocks_for_parent(block_hash) {
+            if let Some(parent_number) = self
+                .queued_blocks
+                .block_number(child_block.header.prev_blockhash)
+            {
+                self.queue_block(parent_number + 1, child_block.block_hash(), child_block);
             }
-        };
+        }
+    }
 
-        insert_block(block_number, block_hash, block);
+    fn queue_block(&mut self, block_number: u32, block_hash: BlockHash, block: BitcoinBlock) {
+        self.import_memory.add(block_hash, block.total_size());
+        self.downloaded_blocks.push(block);
+        self.queued_blocks.insert(block_number, block_hash);
+        if block_number > self.best_queued_number {
+            self.best_queued_number = block_number;
+        }
+    }
 
-        let children = self.orphan_blocks_pool.remove_blocks_for_parent(block_hash);
+    /// Discards the orphan blocks whose parent has not arrived in time.
+    fn remove_expired_orphan_blocks(&mut self) {
+        let expired = self
+            .orphan_blocks_pool
+            .remove_expired_blocks(ORPHAN_BLOCK_EXPIRY);
 
-        if !children.is_empty() {
-            for (index, child_block) in children.into_iter().enumerate() {
-                let hash = child_block.block_hash();
-                let number = block_number + index as u32 + 1;
-                insert_block(number, hash, child_block);
-            }
+        if !expired.is_empty() {
+            tracing::debug!(
+                orphan_blocks_count = self.orphan_blocks_pool.len(),
+                "Discarded {} expired orphan blocks",
+                expired.len(),
+            );
         }
     }
 
@@ -281,3 +403,94 @@ impl BlockDownloadManager {
         self.orphan_blocks_pool.insert_unknown_block(unknown_block);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc_consensus::BlockImportStatus;
+    use subcoin_test_service::block_data;
+
+    #[test]
+    fn orphan_blocks_are_queued_once_connectable() {
+        let blocks = block_data();
+        let mut download_manager = BlockDownloadManager::new(crate::DEFAULT_IMPORT_MEMORY_BUDGET);
+
+        // Blocks #3 and #2 arrive before #1.
+        for number in [3, 2] {
+            let block = blocks[number].clone();
+            download_manager.add_orphan_block(block.block_hash(), block);
+        }
+        assert_eq!(download_manager.orphan_blocks_pool.len(), 2);
+
+        download_manager.add_block(1, blocks[1].block_hash(), blocks[1].clone());
+
+        assert_eq!(download_manager.orphan_blocks_pool.len(), 0);
+        assert_eq!(download_manager.best_queued_number, 3);
+        for number in 1..=3 {
+            assert_eq!(
+                download_manager.block_number(blocks[number].block_hash()),
+                Some(number as u32)
+            );
+        }
+
+        let (hashes, _blocks) = download_manager.prepare_blocks_for_import(None);
+        assert_eq!(
+            hashes,
+            (1..=3)
+                .map(|number| blocks[number].block_hash())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn download_is_paused_until_import_frees_memory() {
+        let blocks = block_data();
+        let budget = blocks[1].total_size() + blocks[2].total_size();
+        let mut download_manager = BlockDownloadManager::new(budget);
+
+        for number in 1..=3 {
+            let block = blocks[number].clone();
+            download_manager.add_block(number as u32, block.block_hash(), block);
+        }
+        assert!(download_manager.update_and_check_queue_status(0));
+
+        let (hashes, _blocks) = download_manager.prepare_blocks_for_import(None);
+        // Still accounted while in the import queue.
+        assert!(download_manager.update_and_check_queue_status(0));
+
+        download_manager.handle_processed_blocks(ImportManyBlocksResult {
+            imported: 1,
+            block_count: 1,
+            results: vec![(Ok(BlockImportStatus::ImportedKnown(1, None)), hashes[0])],
+        });
+        assert!(!download_manager.update_and_check_queue_status(1));
+        assert_eq!(
+            download_manager.import_memory.used,
+            blocks[2].total_size() + blocks[3].total_size()
+        );
+    }
+
+    #[test]
+    fn blocks_after_stop_at_are_discarded() {
+        let blocks = block_data();
+
+        for stop_at in [StopAt::Height(2), StopAt::BlockHash(blocks[2].block_hash())] {
+            let mut download_manager =
+                BlockDownloadManager::new(crate::DEFAULT_IMPORT_MEMORY_BUDGET);
+
+            for number in [3, 1, 2] {
+                let block = blocks[number].clone();
+                download_manager.add_block(number as u32, block.block_hash(), block);
+            }
+
+            let (hashes, _blocks) = download_manager.prepare_blocks_for_import(Some(stop_at));
+            assert_eq!(hashes, vec![blocks[1].block_hash(), blocks[2].block_hash()]);
+            assert_eq!(download_manager.blocks_in_queue_count(), 2);
+            assert!(!download_manager.block_exists(blocks[3].block_hash()));
+            assert_eq!(
+                download_manager.import_memory.used,
+                blocks[1].total_size() + blocks[2].total_size()
+            );
+        }
+    }
+}
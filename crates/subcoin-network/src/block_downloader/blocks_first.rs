@@ -1,5 +1,6 @@
 use crate::block_downloader::BlockDownloadManager;
-use crate::sync::{LocatorRequest, SyncAction, SyncRequest};
+use crate::sync::{LocatorRequest, SyncAction, SyncRequest, MAJOR_SYNC_GAP};
+use crate::sync_progress::SyncPhase;
 use crate::{Error, PeerId, SyncStatus};
 use bitcoin::hashes::Hash;
 use bitcoin::p2p::message_blockdata::Inventory;
@@ -56,13 +57,18 @@ where
     Block: BlockT,
     Client: HeaderBackend<Block> + AuxStore,
 {
-    pub(crate) fn new(client: Arc<Client>, peer_id: PeerId, peer_best: u32) -> (Self, SyncRequest) {
+    pub(crate) fn new(
+        client: Arc<Client>,
+        peer_id: PeerId,
+        peer_best: u32,
+        import_memory_budget: usize,
+    ) -> (Self, SyncRequest) {
         let mut blocks_first_sync = Self {
             peer_id,
             client,
             target_block_number: peer_best,
             download_state: DownloadState::Idle,
-            download_manager: BlockDownloadManager::new(),
+            download_manager: BlockDownloadManager::new(import_memory_budget),
             last_locator_start: 0u32,
             _phantom: Default::default(),
         };
@@ -90,6 +96,27 @@ where
         self.peer_id
     }
 
+    pub(crate) fn phase(&self) -> SyncPhase {
+        if self
+            .target_block_number
+            .saturating_sub(self.client.best_number())
+            > MAJOR_SYNC_GAP
+        {
+            SyncPhase::BlockDownload
+        } else {
+            SyncPhase::NearTip
+        }
+    }
+
+    /// Returns the number of blocks requested from the peer and not received yet.
+    pub(crate) fn requests_in_flight(&self, peer_id: PeerId) -> usize {
+        if peer_id == self.peer_id {
+            self.download_manager.requested_blocks_count()
+        } else {
+            0
+        }
+    }
+
     pub(crate) fn update_sync_peer(&mut self, peer_id: PeerId, target_block_number: u32) {
         self.peer_id = peer_id;
         self.target_block_number = target_block_number;
@@ -100,6 +127,8 @@ where
     }
 
     pub(crate) fn on_tick(&mut self) -> SyncAction {
+        self.download_manager.remove_expired_orphan_blocks();
+
         if matches!(self.download_state, DownloadState::Restarting) {
             return SyncAction::Request(self.prepare_blocks_request());
         }
@@ -386,8 +415,12 @@ mod tests {
                 .expect("Create test node");
 
         let peer_id: PeerId = "0.0.0.0:0".parse().unwrap();
-        let (mut downloader, _initial_request) =
-            BlocksFirstDownloader::new(client, peer_id, 800000);
+        let (mut downloader, _initial_request) = BlocksFirstDownloader::new(
+            client,
+            peer_id,
+            800000,
+            crate::DEFAULT_IMPORT_MEMORY_BUDGET,
+        );
 
         let block = block_data()[3].clone();
         let block_hash = block.block_hash();
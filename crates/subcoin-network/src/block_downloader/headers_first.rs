@@ -1,19 +1,20 @@
 use crate::block_downloader::BlockDownloadManager;
 use crate::sync::{LocatorRequest, SyncAction, SyncRequest};
+use crate::sync_progress::{SyncCheckpoint, SyncPhase};
 use crate::{Error, PeerId, SyncStatus};
 use bitcoin::blockdata::block::Header as BitcoinHeader;
 use bitcoin::p2p::message_blockdata::Inventory;
 use bitcoin::{Block as BitcoinBlock, BlockHash};
 use indexmap::IndexMap;
 use sc_client_api::AuxStore;
+use sc_consensus_nakamoto::{HeaderProvider, HeaderVerifier};
 use sp_blockchain::HeaderBackend;
 use sp_runtime::traits::Block as BlockT;
 use std::collections::{HashSet, VecDeque};
 use std::fmt::Display;
-use std::marker::PhantomData;
 use std::net::IpAddr;
 use std::sync::Arc;
-use subcoin_primitives::{BackendExt, BlockLocatorProvider, ClientExt, IndexedBlock};
+use subcoin_primitives::{BackendExt, ClientExt, HeaderChain, IndexedBlock};
 
 // https://developer.bitcoin.org/reference/p2p_networking.html#headers
 const MAX_HEADERS_SIZE: usize = 2000;
@@ -97,19 +98,48 @@ fn block_download_batch_size(height: u32) -> usize {
     }
 }
 
+/// Header downloaded in the headers-first sync.
+#[derive(Debug, Clone, Copy)]
+struct DownloadedHeader {
+    number: u32,
+    header: BitcoinHeader,
+}
+
+/// [`HeaderProvider`] looking up the downloaded headers first, then the database.
+struct DownloadedHeaders<'a, Block, Client> {
+    downloaded_headers: &'a IndexMap<BlockHash, DownloadedHeader>,
+    header_chain: &'a HeaderChain<Block, Client>,
+}
+
+impl<'a, Block, Client> HeaderProvider for DownloadedHeaders<'a, Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    fn header(&self, block_hash: BlockHash) -> Option<BitcoinHeader> {
+        self.downloaded_headers
+            .get(&block_hash)
+            .map(|downloaded| downloaded.header)
+            .or_else(|| self.header_chain.header(block_hash))
+    }
+}
+
 /// Headers-First download strategy.
 pub struct HeadersFirstDownloader<Block, Client> {
     client: Arc<Client>,
+    header_verifier: HeaderVerifier<Block, Client>,
     peer_id: PeerId,
     download_state: DownloadState,
     download_manager: BlockDownloadManager,
     // Keep the headers ordered so that fetching the blocks orderly later is possible.
-    downloaded_headers: IndexMap<BlockHash, u32>,
+    downloaded_headers: IndexMap<BlockHash, DownloadedHeader>,
     last_locator_start: u32,
+    /// Range `(start, end]` of the blocks requested last.
+    last_requested: Option<(u32, u32)>,
     // TODO: Now it's solely used for the purpose of displaying the sync state.
     // refactor it later.
     target_block_number: u32,
-    _phantom: PhantomData<Block>,
+    header_chain: HeaderChain<Block, Client>,
 }
 
 impl<Block, Client> HeadersFirstDownloader<Block, Client>
@@ -119,23 +149,148 @@ where
 {
     pub(crate) fn new(
         client: Arc<Client>,
+        header_verifier: HeaderVerifier<Block, Client>,
         peer_id: PeerId,
         target_block_number: u32,
+        import_memory_budget: usize,
     ) -> (Self, SyncAction) {
         let mut headers_first_sync = Self {
+            header_chain: HeaderChain::new(client.clone()),
             client,
+            header_verifier,
             peer_id,
             download_state: DownloadState::Idle,
             downloaded_headers: IndexMap::new(),
-            download_manager: BlockDownloadManager::new(),
+            download_manager: BlockDownloadManager::new(import_memory_budget),
             last_locator_start: 0u32,
+            last_requested: None,
             target_block_number,
-            _phantom: Default::default(),
         };
         let sync_action = headers_first_sync.prepare_headers_request_action();
         (headers_first_sync, sync_action)
     }
 
+    /// Resumes the sync from the headers downloaded before the restart.
+    ///
+    /// Falls back to a fresh sync if the headers no longer extend the best block.
+    pub(crate) fn resume(
+        client: Arc<Client>,
+        header_verifier: HeaderVerifier<Block, Client>,
+        peer_id: PeerId,
+        target_block_number: u32,
+        import_memory_budget: usize,
+        checkpoint: SyncCheckpoint,
+    ) -> (Self, SyncAction) {
+        let best_number = client.best_number();
+
+        let mut downloaded_headers = IndexMap::new();
+
+        if let Some(first_header) = checkpoint.headers.first() {
+            if let Some(parent_number) = client.block_number(first_header.prev_blockhash) {
+                let mut prev_hash = first_header.prev_blockhash;
+                for (index, header) in checkpoint.headers.iter().enumerate() {
+                    if header.prev_blockhash != prev_hash {
+                        break;
+                    }
+                    prev_hash = header.block_hash();
+                    downloaded_headers.insert(
+                        prev_hash,
+                        DownloadedHeader {
+                            number: parent_number + index as u32 + 1,
+                            header: *header,
+                        },
+                    );
+                }
+            }
+        }
+
+        let header_tip = downloaded_headers
+            .last()
+            .map(|(hash, downloaded)| IndexedBlock {
+                number: downloaded.number,
+                hash: *hash,
+            })
+            .filter(|tip| tip.number > best_number);
+
+        let Some(header_tip) = header_tip else {
+            tracing::debug!("Sync checkpoint is behind the best block, starting a fresh sync");
+            return Self::new(
+                client,
+                header_verifier,
+                peer_id,
+                target_block_number,
+                import_memory_budget,
+            );
+        };
+
+        let start = IndexedBlock {
+            number: best_number,
+            hash: client
+                .block_hash(best_number)
+                .expect("Best block must exist; qed"),
+        };
+
+        tracing::info!(
+            phase = ?checkpoint.phase,
+            last_requested = ?checkpoint.last_requested,
+            "⏯️ Resuming sync from header tip {header_tip}",
+        );
+
+        let mut headers_first_sync = Self {
+            header_chain: HeaderChain::new(client.clone()),
+            client,
+            header_verifier,
+            peer_id,
+            download_state: DownloadState::Idle,
+            downloaded_headers,
+            download_manager: BlockDownloadManager::new(import_memory_budget),
+            last_locator_start: best_number,
+            last_requested: None,
+            target_block_number,
+        };
+
+        let sync_action = match crate::checkpoint::next_checkpoint(best_number + 1) {
+            // Header tip not at the next checkpoint, continue downloading the headers.
+            Some(end) if header_tip.number < end.number => {
+                headers_first_sync.download_state =
+                    DownloadState::DownloadingHeaders { start, end };
+                SyncAction::Request(SyncRequest::Headers(LocatorRequest {
+                    locator_hashes: vec![header_tip.hash],
+                    stop_hash: end.hash,
+                    from: peer_id,
+                }))
+            }
+            _ => headers_first_sync.start_block_download(start, header_tip),
+        };
+
+        (headers_first_sync, sync_action)
+    }
+
+    pub(crate) fn phase(&self) -> SyncPhase {
+        match self.download_state {
+            DownloadState::DownloadingBlocks(_) | DownloadState::Completed => {
+                SyncPhase::BlockDownload
+            }
+            _ => SyncPhase::HeaderSync,
+        }
+    }
+
+    /// Returns the current progress to persist.
+    pub(crate) fn checkpoint(&self) -> SyncCheckpoint {
+        let best_number = self.client.best_number();
+        SyncCheckpoint {
+            phase: self.phase(),
+            headers: self
+                .downloaded_headers
+                .values()
+                .filter(|downloaded| downloaded.number > best_number)
+                .map(|downloaded| downloaded.header)
+                .collect(),
+            last_requested: self.last_requested,
+            sync_peer: Some(self.peer_id),
+        }
+    }
+
     pub(crate) fn sync_status(&self) -> SyncStatus {
         if self.download_manager.import_queue_is_overloaded {
             SyncStatus::Importing {
@@ -154,12 +309,23 @@ where
         self.peer_id
     }
 
+    /// Returns the number of blocks requested from the peer and not received yet.
+    pub(crate) fn requests_in_flight(&self, peer_id: PeerId) -> usize {
+        if peer_id == self.peer_id {
+            self.download_manager.requested_blocks_count()
+        } else {
+            0
+        }
+    }
+
     pub(crate) fn update_sync_peer(&mut self, peer_id: PeerId, target_block_number: u32) {
         self.peer_id = peer_id;
         self.target_block_number = target_block_number;
     }
 
     pub(crate) fn on_tick(&mut self) -> SyncAction {
+        self.download_manager.remove_expired_orphan_blocks();
+
         if matches!(self.download_state, DownloadState::Restarting) {
             return self.prepare_headers_request_action();
         }
@@ -216,6 +382,7 @@ where
         self.downloaded_headers.clear();
         self.download_manager.reset();
         self.last_locator_start = 0u32;
+        self.last_requested = None;
         self.target_block_number = peer_best;
         self.download_state = DownloadState::Restarting;
     }
@@ -251,8 +418,8 @@ where
         self.last_locator_start = our_best;
 
         let locator_hashes = self
-            .client
-            .block_locator(Some(our_best), |_height: u32| None)
+            .header_chain
+            .best_block_locator(our_best, |_height| None)
             .locator_hashes;
 
         self.download_state = DownloadState::DownloadingHeaders { start, end };
@@ -296,8 +463,8 @@ where
 
         let mut prev_number = if prev_hash == start.hash {
             start.number
-        } else if let Some(block_number) = self.downloaded_headers.get(&prev_hash).copied() {
-            block_number
+        } else if let Some(downloaded) = self.downloaded_headers.get(&prev_hash) {
+            downloaded.number
         } else if let Some(block_number) = self.client.block_number(prev_hash) {
             block_number
         } else {
@@ -310,25 +477,51 @@ where
             return SyncAction::Disconnect(self.peer_id, Error::ParentOfFirstHeaderEntryNotFound);
         };
 
-        for header in headers {
+        for header in &headers {
             if header.prev_blockhash != prev_hash {
                 self.download_state = DownloadState::Disconnecting;
                 return SyncAction::Disconnect(self.peer_id, Error::HeadersNotInAscendingOrder);
             }
+            prev_hash = header.block_hash();
+        }
+
+        // Reject the invalid headers before requesting the block bodies.
+        let ancestors = DownloadedHeaders {
+            downloaded_headers: &self.downloaded_headers,
+            header_chain: &self.header_chain,
+        };
 
-            // TODO: Verify header?
+        let verify_result =
+            self.header_verifier
+                .verify_headers_parallel(&headers, prev_number, &ancestors);
 
-            let block_hash = header.block_hash();
+        let (valid_headers, invalid_header) = match verify_result {
+            Ok(()) => (headers.len(), None),
+            Err((index, err)) => (index, Some((headers[index].block_hash(), err))),
+        };
+
+        for header in headers.into_iter().take(valid_headers) {
             let block_number = prev_number + 1;
 
             // We can't import the header directly at this moment since creating a Substrate
             // header requires the full block data.
-            self.downloaded_headers.insert(block_hash, block_number);
+            self.downloaded_headers.insert(
+                header.block_hash(),
+                DownloadedHeader {
+                    number: block_number,
+                    header,
+                },
+            );
 
-            prev_hash = block_hash;
             prev_number = block_number;
         }
 
+        if let Some((block_hash, err)) = invalid_header {
+            tracing::debug!(?block_hash, ?err, "Received invalid header, disconnecting");
+            self.download_state = DownloadState::Disconnecting;
+            return SyncAction::Disconnect(self.peer_id, Error::BadHeader(block_hash, err));
+        }
+
         let final_block_number = prev_number;
         let target_block_number = end.number;
         let target_block_hash = end.hash;
@@ -352,13 +545,15 @@ where
 
         let best_number = self.client.best_number();
 
+        self.last_requested.replace((start.number, end.number));
+
         let missing_blocks =
             self.downloaded_headers
                 .iter()
-                .filter_map(|(block_hash, block_number)| {
+                .filter_map(|(block_hash, downloaded)| {
                     let block_hash = *block_hash;
 
-                    if *block_number > best_number {
+                    if downloaded.number > best_number {
                         return Some(block_hash);
                     }
 
@@ -523,8 +718,14 @@ where
         } else {
             if receive_requested_block {
                 self.download_manager.add_orphan_block(block_hash, block);
-            } else {
-                tracing::debug!("Discard unrequested orphan block {block_hash}");
+            } else if !self
+                .download_manager
+                .orphan_blocks_pool
+                .block_exists(&block_hash)
+            {
+                // Likely a new block announcement, kept until its parents are downloaded by
+                // the ongoing sync.
+                self.download_manager.add_unknown_block(block_hash, block);
             }
 
             SyncAction::None
@@ -587,19 +788,20 @@ where
 
 fn prepare_ordered_block_data_request(
     blocks: HashSet<BlockHash>,
-    downloaded_headers: &IndexMap<BlockHash, u32>,
+    downloaded_headers: &IndexMap<BlockHash, DownloadedHeader>,
 ) -> Vec<Inventory> {
     let mut blocks = blocks
         .into_iter()
         .map(|block_hash| {
             let block_number = downloaded_headers
                 .get(&block_hash)
-                .expect("Header must exist before downloading blocks in headers-first mode; qed");
+                .expect("Header must exist before downloading blocks in headers-first mode; qed")
+                .number;
             (block_number, block_hash)
         })
         .collect::<Vec<_>>();
 
-    blocks.sort_by(|a, b| a.0.cmp(b.0));
+    blocks.sort_by_key(|(block_number, _)| *block_number);
 
     blocks
         .into_iter()
@@ -0,0 +1,426 @@
+//! Broadcast of the locally submitted transactions.
+//!
+//! Each transaction is announced to a few peers at a time, rotating over the connected peers
+//! until any of them requests or announces it back. It is then rebroadcast periodically until
+//! it is confirmed.
+
+use crate::PeerId;
+use bitcoin::{BlockHash, Transaction, Txid, Wtxid};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of peers the transaction is announced to at a time.
+const ANNOUNCE_PEERS: usize = 4;
+
+/// Time to wait for a peer to request the announced transaction before announcing it to
+/// other peers.
+const FEEDBACK_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// Interval of rebroadcasting the transaction already relayed by the peers.
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// How long a confirmed transaction is kept for the status queries.
+const CONFIRMED_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum number of the tracked transactions.
+const MAX_TRACKED_TRANSACTIONS: usize = 1000;
+
+/// Maximum number of the rejections kept per transaction.
+const MAX_REJECTS: usize = 16;
+
+/// State of the transaction broadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BroadcastState {
+    /// Not announced to any peer yet.
+    Pending,
+    /// Announced, but no peer has requested the transaction.
+    Announced,
+    /// Requested or announced back by at least one peer.
+    Relayed,
+    /// Rejected by the peers and not relayed by any of them.
+    Rejected,
+    /// Included in the best chain.
+    Confirmed,
+}
+
+/// Rejection of the transaction by a peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastReject {
+    pub peer_id: PeerId,
+    /// Reject code, e.g., `Nonstandard`.
+    pub code: String,
+    pub reason: String,
+}
+
+/// Block in which the transaction was confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmedIn {
+    pub block_hash: BlockHash,
+    pub height: u32,
+}
+
+/// Broadcast status of a locally submitted transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastStatus {
+    pub txid: Txid,
+    pub state: BroadcastState,
+    /// UNIX timestamp in seconds at which the transaction was submitted.
+    pub submitted: u64,
+    /// UNIX timestamp in seconds of the last announcement.
+    pub last_broadcast: Option<u64>,
+    /// Number of the announcements so far.
+    pub broadcast_count: u32,
+    /// Peers the transaction has been announced to.
+    pub announced_to: Vec<PeerId>,
+    /// Peers which requested or announced the transaction.
+    pub relayed_by: Vec<PeerId>,
+    /// Rejections received from the peers.
+    pub rejects: Vec<BroadcastReject>,
+    /// Block including the transaction, present once confirmed.
+    pub confirmed_in: Option<ConfirmedIn>,
+}
+
+fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+struct BroadcastInfo {
+    transaction: Transaction,
+    wtxid: Wtxid,
+    submitted: SystemTime,
+    last_broadcast: Option<SystemTime>,
+    broadcast_count: u32,
+    /// Peers announced in the current rotation, reset once all the peers are announced.
+    announced_to: HashSet<PeerId>,
+    relayed_by: HashSet<PeerId>,
+    rejects: Vec<BroadcastReject>,
+    confirmed_in: Option<(ConfirmedIn, SystemTime)>,
+}
+
+impl BroadcastInfo {
+    fn state(&self) -> BroadcastState {
+        if self.confirmed_in.is_some() {
+            BroadcastState::Confirmed
+        } else if !self.relayed_by.is_empty() {
+            BroadcastState::Relayed
+        } else if !self.rejects.is_empty() {
+            BroadcastState::Rejected
+        } else if self.last_broadcast.is_some() {
+            BroadcastState::Announced
+        } else {
+            BroadcastState::Pending
+        }
+    }
+
+    /// Returns `true` if the transaction should be announced again at `now`.
+    fn is_due(&self, now: SystemTime) -> bool {
+        if self.confirmed_in.is_some() {
+            return false;
+        }
+
+        let Some(last_broadcast) = self.last_broadcast else {
+            return true;
+        };
+
+        let interval = if self.relayed_by.is_empty() {
+            FEEDBACK_TIMEOUT
+        } else {
+            REBROADCAST_INTERVAL
+        };
+
+        now.duration_since(last_broadcast)
+            .is_ok_and(|elapsed| elapsed >= interval)
+    }
+}
+
+/// This struct tracks the broadcast of the locally submitted transactions.
+#[derive(Debug)]
+pub(crate) struct BroadcastManager {
+    transactions: IndexMap<Txid, BroadcastInfo>,
+    /// Txids of the tracked transactions by wtxid.
+    wtxids: HashMap<Wtxid, Txid>,
+    rng: fastrand::Rng,
+}
+
+impl BroadcastManager {
+    pub fn new() -> Self {
+        Self {
+            transactions: IndexMap::new(),
+            wtxids: HashMap::new(),
+            rng: fastrand::Rng::new(),
+        }
+    }
+
+    /// Starts tracking the broadcast of a transaction submitted locally.
+    pub fn track(&mut self, txid: Txid, transaction: Transaction, now: SystemTime) {
+        if self.transactions.contains_key(&txid) {
+            return;
+        }
+
+        if self.transactions.len() == MAX_TRACKED_TRANSACTIONS {
+            if let Some((_, info)) = self.transactions.shift_remove_index(0) {
+                self.wtxids.remove(&info.wtxid);
+            }
+        }
+
+        let wtxid = transaction.compute_wtxid();
+        self.wtxids.insert(wtxid, txid);
+
+        self.transactions.insert(
+            txid,
+            BroadcastInfo {
+                transaction,
+                wtxid,
+                submitted: now,
+                last_broadcast: None,
+                broadcast_count: 0,
+                announced_to: HashSet::new(),
+                relayed_by: HashSet::new(),
+                rejects: Vec::new(),
+                confirmed_in: None,
+            },
+        );
+    }
+
+    pub fn is_tracked(&self, txid: &Txid) -> bool {
+        self.transactions.contains_key(txid)
+    }
+
+    pub fn get_transaction(&self, txid: &Txid) -> Option<Transaction> {
+        self.transactions
+            .get(txid)
+            .map(|info| info.transaction.clone())
+    }
+
+    /// Returns the wtxid of the tracked transaction.
+    pub fn wtxid(&self, txid: &Txid) -> Option<Wtxid> {
+        self.transactions.get(txid).map(|info| info.wtxid)
+    }
+
+    /// Returns the txid of the tracked transaction with the wtxid.
+    pub fn txid_for_wtxid(&self, wtxid: &Wtxid) -> Option<Txid> {
+        self.wtxids.get(wtxid).copied()
+    }
+
+    /// Returns the transactions to announce to each peer.
+    pub fn on_tick<'a>(
+        &mut self,
+        connected_peers: impl Iterator<Item = &'a PeerId>,
+        now: SystemTime,
+    ) -> Vec<(PeerId, Vec<Txid>)> {
+        let wtxids = &mut self.wtxids;
+        self.transactions
+            .retain(|txid, info| match info.confirmed_in {
+                Some((_, confirmed_at))
+                    if now
+                        .duration_since(confirmed_at)
+                        .is_ok_and(|elapsed| elapsed >= CONFIRMED_RETENTION) =>
+                {
+                    tracing::debug!(
+                        "Stopped tracking the broadcast of confirmed transaction {txid}"
+                    );
+                    wtxids.remove(&info.wtxid);
+                    false
+                }
+                _ => true,
+            });
+
+        let connected_peers = connected_peers.copied().collect::<Vec<_>>();
+
+        if connected_peers.is_empty() {
+            return Vec::new();
+        }
+
+        let mut announcements = IndexMap::<PeerId, Vec<Txid>>::new();
+
+        for (txid, info) in self.transactions.iter_mut() {
+            if !info.is_due(now) {
+                continue;
+            }
+
+            let mut candidates = connected_peers
+                .iter()
+                .filter(|peer| !info.announced_to.contains(peer) && !info.relayed_by.contains(peer))
+                .copied()
+                .collect::<Vec<_>>();
+
+            // All the peers have been tried, start a new rotation.
+            if candidates.is_empty() {
+                info.announced_to.clear();
+                candidates = connected_peers
+                    .iter()
+                    .filter(|peer| !info.relayed_by.contains(peer))
+                    .copied()
+                    .collect();
+            }
+
+            self.rng.shuffle(&mut candidates);
+            candidates.truncate(ANNOUNCE_PEERS);
+
+            if candidates.is_empty() {
+                continue;
+            }
+
+            for peer in candidates {
+                info.announced_to.insert(peer);
+                announcements.entry(peer).or_default().push(*txid);
+            }
+
+            info.last_broadcast = Some(now);
+            info.broadcast_count += 1;
+        }
+
+        announcements.into_iter().collect()
+    }
+
+    /// Notes the transaction was requested or announced by a peer.
+    pub fn on_relayed(&mut self, from: PeerId, txid: &Txid) {
+        if let Some(info) = self.transactions.get_mut(txid) {
+            info.relayed_by.insert(from);
+        }
+    }
+
+    /// Notes the transaction was rejected by a peer.
+    pub fn on_reject(&mut self, from: PeerId, txid: &Txid, code: String, reason: String) {
+        if let Some(info) = self.transactions.get_mut(txid) {
+            tracing::debug!(?from, "Transaction {txid} rejected: {code} {reason}");
+
+            if info.rejects.len() == MAX_REJECTS {
+                info.rejects.remove(0);
+            }
+
+            info.rejects.push(BroadcastReject {
+                peer_id: from,
+                code,
+                reason,
+            });
+        }
+    }
+
+    /// Marks the transactions included in the new best block as confirmed.
+    pub fn on_block_connected(
+        &mut self,
+        block_hash: BlockHash,
+        height: u32,
+        txids: &[Txid],
+        now: SystemTime,
+    ) {
+        for txid in txids {
+            if let Some(info) = self.transactions.get_mut(txid) {
+                info.confirmed_in = Some((ConfirmedIn { block_hash, height }, now));
+            }
+        }
+    }
+
+    /// Resumes the broadcast of the transactions confirmed in the disconnected blocks.
+    pub fn on_blocks_disconnected(&mut self, block_hashes: &[BlockHash]) {
+        for info in self.transactions.values_mut() {
+            if info
+                .confirmed_in
+                .is_some_and(|(confirmed_in, _)| block_hashes.contains(&confirmed_in.block_hash))
+            {
+                info.confirmed_in = None;
+                info.last_broadcast = None;
+            }
+        }
+    }
+
+    pub fn status(&self, txid: &Txid) -> Option<BroadcastStatus> {
+        self.transactions.get(txid).map(|info| BroadcastStatus {
+            txid: *txid,
+            state: info.state(),
+            submitted: unix_timestamp(info.submitted),
+            last_broadcast: info.last_broadcast.map(unix_timestamp),
+            broadcast_count: info.broadcast_count,
+            announced_to: info.announced_to.iter().copied().collect(),
+            relayed_by: info.relayed_by.iter().copied().collect(),
+            rejects: info.rejects.clone(),
+            confirmed_in: info.confirmed_in.map(|(confirmed_in, _)| confirmed_in),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+
+    #[test]
+    fn test_broadcast_rotation() {
+        let transaction = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: Vec::new(),
+        };
+        let txid = transaction.compute_txid();
+        let peers = (1..=6u16)
+            .map(|port| PeerId::from(([127, 0, 0, 1], port)))
+            .collect::<Vec<_>>();
+
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut manager = BroadcastManager::new();
+        manager.track(txid, transaction, now);
+        assert_eq!(
+            manager.status(&txid).unwrap().state,
+            BroadcastState::Pending
+        );
+
+        let announced = manager.on_tick(peers.iter(), now);
+        assert_eq!(announced.len(), ANNOUNCE_PEERS);
+        assert_eq!(
+            manager.status(&txid).unwrap().state,
+            BroadcastState::Announced
+        );
+
+        // Not due again until the feedback timeout.
+        assert!(manager.on_tick(peers.iter(), now).is_empty());
+
+        // Announced to the remaining peers without any feedback.
+        let now = now + FEEDBACK_TIMEOUT;
+        let announced = manager.on_tick(peers.iter(), now);
+        assert_eq!(announced.len(), peers.len() - ANNOUNCE_PEERS);
+
+        manager.on_relayed(peers[0], &txid);
+        assert_eq!(
+            manager.status(&txid).unwrap().state,
+            BroadcastState::Relayed
+        );
+        assert!(manager
+            .on_tick(peers.iter(), now + FEEDBACK_TIMEOUT)
+            .is_empty());
+
+        let block_hash = BlockHash::from_byte_array([1; 32]);
+        manager.on_block_connected(block_hash, 100, &[txid], now);
+        assert_eq!(
+            manager.status(&txid).unwrap().state,
+            BroadcastState::Confirmed
+        );
+        assert!(manager
+            .on_tick(peers.iter(), now + REBROADCAST_INTERVAL)
+            .is_empty());
+
+        // Broadcast again once the block is disconnected.
+        manager.on_blocks_disconnected(&[block_hash]);
+        assert!(!manager.on_tick(peers.iter(), now).is_empty());
+
+        let wtxid = manager.wtxid(&txid).unwrap();
+        assert_eq!(manager.txid_for_wtxid(&wtxid), Some(txid));
+
+        manager.on_block_connected(block_hash, 100, &[txid], now);
+        manager.on_tick(peers.iter(), now + CONFIRMED_RETENTION);
+        assert!(!manager.is_tracked(&txid));
+        assert_eq!(manager.txid_for_wtxid(&wtxid), None);
+    }
+}
@@ -1,19 +1,29 @@
+use crate::address_book::{address_network, AddressNetwork};
+use crate::bip324::{self, Handshake, PacketDecoder, PacketEncoder};
+use crate::i2p::{is_i2p, I2pDestinations, SamBridge};
+use crate::transport::{PeerStream, QuicEndpoint, Transport, TransportInfo, TransportProtocol};
 use crate::worker::Event;
-use crate::{Bandwidth, Error, Latency, PeerId};
-use bitcoin::consensus::{encode, Decodable, Encodable};
-use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage, MAX_MSG_SIZE};
+use crate::{Bandwidth, Error, Latency, PeerId, NODE_P2P_V2, NODE_QUIC};
+use bitcoin::consensus::{encode, Encodable};
+use bitcoin::hex::DisplayHex;
+use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage, MAX_INV_SIZE, MAX_MSG_SIZE};
+use bitcoin::p2p::{Magic, ServiceFlags};
 use futures::FutureExt;
 use sc_service::SpawnTaskHandle;
-use std::collections::VecDeque;
 use std::fmt::Debug;
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 const MSG_HEADER_SIZE: usize = 24;
 
+/// Size of the buffer receiving the bytes from the peer.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
 /// Channel for sending messages to the peer.
 pub type ConnectionWriter = UnboundedSender<NetworkMessage>;
 
@@ -60,6 +70,10 @@ pub struct NewConnection {
     ///
     /// Only make sense for the outbound connection.
     pub connect_latency: Latency,
+    /// Transport of the connection.
+    pub transport: TransportInfo,
+    /// Bytes transferred over this connection.
+    pub bandwidth: Bandwidth,
     /// Channel for sending messages to the peer over this connection.
     ///
     /// This `ConnectionWriter` enables the local node to transmit data
@@ -73,22 +87,99 @@ pub struct NewConnection {
     pub disconnect_signal: Arc<AtomicBool>,
 }
 
+/// Bytes of a `headers` entry: the header followed by the zero transaction count.
+const HEADERS_ENTRY_SIZE: usize = 81;
+
+/// Maximum number of entries in a `headers` message.
+const MAX_HEADERS: usize = 2000;
+
+/// Bytes of an inventory entry: the type followed by the hash.
+const INV_ENTRY_SIZE: usize = 36;
+
+/// Maximum number of entries in an `addr` or `addrv2` message.
+const MAX_ADDR_TO_SEND: usize = 1000;
+
+/// Bytes of an `addr` entry: the timestamp followed by the address.
+const ADDR_ENTRY_SIZE: usize = 30;
+
+/// Upper bound of the bytes of an `addrv2` entry with the largest address (512 bytes).
+const ADDRV2_ENTRY_SIZE: usize = 4 + 9 + 1 + 3 + 512 + 2;
+
+/// Maximum number of the block locator hashes, as in Bitcoin Core.
+const MAX_LOCATOR_SIZE: usize = 101;
+
+/// Maximum size of the vector length prefix.
+const COMPACT_SIZE_LEN: usize = 5;
+
+/// Returns the maximum payload size of the message `command`.
+///
+/// The messages with a bounded number of entries are capped at the size of the largest valid
+/// message, the others at the message size accepted by the codec.
+fn max_payload_len(command: &[u8]) -> usize {
+    let command_len = command
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(command.len());
+
+    match &command[..command_len] {
+        b"headers" => COMPACT_SIZE_LEN + MAX_HEADERS * HEADERS_ENTRY_SIZE,
+        b"inv" | b"getdata" | b"notfound" => COMPACT_SIZE_LEN + MAX_INV_SIZE * INV_ENTRY_SIZE,
+        b"addr" => COMPACT_SIZE_LEN + MAX_ADDR_TO_SEND * ADDR_ENTRY_SIZE,
+        b"addrv2" => COMPACT_SIZE_LEN + MAX_ADDR_TO_SEND * ADDRV2_ENTRY_SIZE,
+        b"getheaders" | b"getblocks" => 4 + COMPACT_SIZE_LEN + (MAX_LOCATOR_SIZE + 1) * 32,
+        _ => MAX_MSG_SIZE - MSG_HEADER_SIZE,
+    }
+}
+
+/// Rejects the message `command` of `payload_len` bytes, if larger than allowed.
+pub(crate) fn check_payload_len(command: &[u8], payload_len: usize) -> Result<(), Error> {
+    if payload_len > max_payload_len(command) {
+        return Err(Error::OversizedMessage(
+            String::from_utf8_lossy(command)
+                .trim_end_matches('\0')
+                .to_string(),
+            payload_len,
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects the decoded message if it has more entries than allowed.
+///
+/// The entries of `addrv2` vary in size, the payload size alone does not bound their number.
+pub(crate) fn check_message_entries(message: &NetworkMessage) -> Result<(), Error> {
+    match message {
+        NetworkMessage::Headers(headers) if headers.len() > MAX_HEADERS => {
+            Err(Error::TooManyHeaders)
+        }
+        NetworkMessage::Inv(inv) | NetworkMessage::GetData(inv) | NetworkMessage::NotFound(inv)
+            if inv.len() > MAX_INV_SIZE =>
+        {
+            Err(Error::TooManyInventoryItems)
+        }
+        NetworkMessage::Addr(addresses) if addresses.len() > MAX_ADDR_TO_SEND => {
+            Err(Error::TooManyAddresses)
+        }
+        NetworkMessage::AddrV2(addresses) if addresses.len() > MAX_ADDR_TO_SEND => {
+            Err(Error::TooManyAddresses)
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Message stream decoder.
 ///
-/// Used to turn a byte stream into network messages.
-#[derive(Debug)]
+/// Used to turn a byte stream into network messages. The header of a message is checked
+/// before its payload is buffered, the payload is decoded once fully received, hence the
+/// buffer never grows beyond the largest allowed message and the bytes of a read.
+#[derive(Debug, Default)]
 struct NetworkMessageDecoder {
     unparsed: Vec<u8>,
+    /// Size of the message being received, known once its header is checked.
+    message_len: Option<usize>,
 }
 
 impl NetworkMessageDecoder {
-    /// Constructs a new [`NetworkMessageDecoder`].
-    fn new(capacity: usize) -> Self {
-        Self {
-            unparsed: Vec::with_capacity(capacity),
-        }
-    }
-
     /// Input bytes into the decoder.
     fn input(&mut self, bytes: &[u8]) {
         self.unparsed.extend_from_slice(bytes);
@@ -97,18 +188,156 @@ impl NetworkMessageDecoder {
     /// Decode and return the next message.
     ///
     /// Returns [`None`] if nothing was decoded.
-    fn decode_next<D: Decodable>(&mut self) -> Result<Option<D>, encode::Error> {
-        match encode::deserialize_partial::<D>(&self.unparsed) {
-            Ok((msg, index)) => {
-                // Drain deserialized bytes only.
-                self.unparsed.drain(..index);
-                Ok(Some(msg))
+    fn decode_next(&mut self) -> Result<Option<NetworkMessage>, Error> {
+        let message_len = match self.message_len {
+            Some(message_len) => message_len,
+            None => {
+                if self.unparsed.len() < MSG_HEADER_SIZE {
+                    return Ok(None);
+                }
+
+                let header = &self.unparsed[..MSG_HEADER_SIZE];
+                let payload_len =
+                    u32::from_le_bytes(header[16..20].try_into().expect("Slice of 4 bytes; qed"))
+                        as usize;
+                check_payload_len(&header[4..16], payload_len)?;
+
+                *self.message_len.insert(MSG_HEADER_SIZE + payload_len)
             }
+        };
 
-            Err(encode::Error::Io(err)) if err.kind() == bitcoin::io::ErrorKind::UnexpectedEof => {
-                Ok(None)
+        if self.unparsed.len() < message_len {
+            return Ok(None);
+        }
+
+        let raw_network_message =
+            encode::deserialize::<RawNetworkMessage>(&self.unparsed[..message_len])?;
+        self.unparsed.drain(..message_len);
+        self.message_len = None;
+
+        let message = raw_network_message.into_payload();
+        check_message_entries(&message)?;
+
+        Ok(Some(message))
+    }
+}
+
+/// Decoder of the incoming messages of a connection.
+enum MessageDecoder {
+    V1(NetworkMessageDecoder),
+    V2(PacketDecoder),
+}
+
+impl MessageDecoder {
+    fn input(&mut self, bytes: &[u8]) {
+        match self {
+            Self::V1(decoder) => decoder.input(bytes),
+            Self::V2(decoder) => decoder.input(bytes),
+        }
+    }
+
+    fn decode_next(&mut self) -> Result<Option<NetworkMessage>, Error> {
+        match self {
+            Self::V1(decoder) => decoder.decode_next(),
+            Self::V2(decoder) => decoder.decode_next(),
+        }
+    }
+}
+
+/// Encoder of the outgoing messages of a connection.
+enum MessageEncoder {
+    V1,
+    V2(PacketEncoder),
+}
+
+impl MessageEncoder {
+    /// Returns the command, payload size and the bytes to send.
+    fn encode(
+        &mut self,
+        magic: Magic,
+        network_message: NetworkMessage,
+    ) -> Result<(&'static str, usize, Vec<u8>), Error> {
+        match self {
+            Self::V1 => {
+                let raw_network_message = RawNetworkMessage::new(magic, network_message);
+                let mut msg = Vec::new();
+                raw_network_message.consensus_encode(&mut msg)?;
+                // Bitcoin Core logs the message size without counting in the header.
+                let payload_len = msg.len().saturating_sub(MSG_HEADER_SIZE);
+                Ok((raw_network_message.cmd(), payload_len, msg))
             }
-            Err(err) => Err(err),
+            Self::V2(encoder) => encoder.encode(magic, network_message),
+        }
+    }
+}
+
+/// Message framing negotiated on a connection.
+struct Framing {
+    decoder: MessageDecoder,
+    encoder: MessageEncoder,
+    protocol: TransportProtocol,
+    session_id: Option<String>,
+}
+
+impl Framing {
+    fn v1(received: &[u8]) -> Self {
+        let mut decoder = NetworkMessageDecoder::default();
+        decoder.input(received);
+        Self {
+            decoder: MessageDecoder::V1(decoder),
+            encoder: MessageEncoder::V1,
+            protocol: TransportProtocol::V1,
+            session_id: None,
+        }
+    }
+}
+
+impl From<Handshake> for Framing {
+    fn from(handshake: Handshake) -> Self {
+        match handshake {
+            Handshake::V1(received) => Self::v1(&received),
+            Handshake::V2(session) => Self {
+                decoder: MessageDecoder::V2(session.decoder),
+                encoder: MessageEncoder::V2(session.encoder),
+                protocol: TransportProtocol::V2,
+                session_id: Some(session.session_id.to_lower_hex_string()),
+            },
+        }
+    }
+}
+
+/// Bandwidth counters of a connection, the bytes are also added to the total.
+#[derive(Clone)]
+struct ConnectionBandwidth {
+    total: Bandwidth,
+    connection: Bandwidth,
+}
+
+impl ConnectionBandwidth {
+    /// Accounts the received bytes, waiting if the download rate limit is exceeded.
+    async fn on_received(&self, bytes: u64) {
+        for bandwidth in [&self.total, &self.connection] {
+            bandwidth
+                .total_bytes_inbound
+                .fetch_add(bytes, Ordering::Relaxed);
+        }
+        if let Some(limiter) = &self.total.download_limiter {
+            limiter.throttle(bytes).await;
+        }
+    }
+
+    /// Waits until the bytes can be sent without exceeding the upload rate limit.
+    async fn before_send(&self, bytes: u64) {
+        if let Some(limiter) = &self.total.upload_limiter {
+            limiter.throttle(bytes).await;
+        }
+    }
+
+    fn on_sent(&self, bytes: u64) {
+        for bandwidth in [&self.total, &self.connection] {
+            bandwidth
+                .total_bytes_outbound
+                .fetch_add(bytes, Ordering::Relaxed);
         }
     }
 }
@@ -116,54 +345,82 @@ impl NetworkMessageDecoder {
 /// Handles the initiation of connections within the Bitcoin P2P network.
 #[derive(Clone)]
 pub struct ConnectionInitiator {
-    network: bitcoin::Network,
+    magic: Magic,
     network_event_sender: UnboundedSender<Event>,
     spawn_handle: SpawnTaskHandle,
     // Tracks the bandwidth usage of the initiated connections.
     bandwidth: Bandwidth,
     ipv4_only: bool,
+    // QUIC endpoint, `None` if QUIC is disabled.
+    quic_endpoint: Option<QuicEndpoint>,
+    // Whether to support the BIP-324 v2 transport over TCP.
+    v2_transport: bool,
+    // SAM bridge making the I2P connections, `None` if I2P is disabled.
+    i2p_sam: Option<Arc<SamBridge>>,
+    i2p_destinations: I2pDestinations,
 }
 
 impl ConnectionInitiator {
     /// Timeout for the stream connection in seconds.
     const CONNECT_TIMEOUT: u64 = 5;
 
+    /// Timeout for the I2P stream connection in seconds.
+    const I2P_CONNECT_TIMEOUT: u64 = 60;
+
     /// Constructs a new instance of [`ConnectionInitiator`].
-    pub fn new(
-        network: bitcoin::Network,
+    pub(crate) fn new(
+        magic: Magic,
         network_event_sender: UnboundedSender<Event>,
         spawn_handle: SpawnTaskHandle,
         bandwidth: Bandwidth,
         ipv4_only: bool,
+        quic_endpoint: Option<QuicEndpoint>,
+        v2_transport: bool,
+        i2p_sam: Option<SocketAddr>,
+        i2p_destinations: I2pDestinations,
     ) -> Self {
         Self {
-            network,
+            magic,
             network_event_sender,
             spawn_handle,
             bandwidth,
             ipv4_only,
+            quic_endpoint,
+            v2_transport,
+            i2p_sam: i2p_sam.map(|proxy| Arc::new(SamBridge::new(proxy))),
+            i2p_destinations,
         }
     }
 
+    /// Returns `true` if the connections over QUIC are supported.
+    pub(crate) fn quic_enabled(&self) -> bool {
+        self.quic_endpoint.is_some()
+    }
+
+    /// Returns `true` if the BIP-324 v2 transport is supported.
+    pub(crate) fn v2_transport_enabled(&self) -> bool {
+        self.v2_transport
+    }
+
     /// Makes a new outbound connection in the background.
-    pub fn initiate_outbound_connection(&self, addr: PeerId) {
+    ///
+    /// The transport is chosen by the services advertised for the address:
+    /// - QUIC for the subcoin nodes with [`NODE_QUIC`], if enabled.
+    /// - TCP with the v2 transport for the nodes with [`NODE_P2P_V2`], if enabled.
+    /// - TCP with the v1 transport otherwise, which is also the fallback of the above.
+    pub fn initiate_outbound_connection(&self, addr: PeerId, services: ServiceFlags) {
         self.spawn_handle.spawn("outbound-connection", None, {
             let connection_initiator = self.clone();
 
             async move {
                 let outbound_connection_fut = async {
                     let start_time = Instant::now();
-                    let stream_result = tokio::time::timeout(
-                        Duration::from_secs(Self::CONNECT_TIMEOUT),
-                        TcpStream::connect(addr),
-                    )
-                    .await
-                    .map_err(|_| Error::ConnectionTimeout)?;
+                    let (stream, framing) = connection_initiator.connect(addr, services).await?;
                     let connection_time = start_time.elapsed();
-                    let stream = stream_result?;
                     connection_initiator.initiate_new_connection(
                         Direction::Outbound,
                         stream,
+                        framing,
                         connection_time,
                     )
                 };
@@ -180,31 +437,162 @@ impl ConnectionInitiator {
         });
     }
 
+    async fn connect(
+        &self,
+        addr: PeerId,
+        services: ServiceFlags,
+    ) -> Result<(PeerStream, Framing), Error> {
+        let timeout = Duration::from_secs(Self::CONNECT_TIMEOUT);
+
+        if let Some(quic_endpoint) = self
+            .quic_endpoint
+            .as_ref()
+            .filter(|_| services.has(ServiceFlags::from(NODE_QUIC)) && !is_i2p(&addr))
+        {
+            match tokio::time::timeout(timeout, quic_endpoint.connect(addr)).await {
+                Ok(Ok(stream)) => return Ok((stream, Framing::v1(&[]))),
+                Ok(Err(err)) => {
+                    tracing::debug!(?err, ?addr, "Failed to connect over QUIC, trying TCP");
+                }
+                Err(_) => {
+                    tracing::debug!(?addr, "Connecting over QUIC timed out, trying TCP");
+                }
+            }
+        }
+
+        if self.v2_transport && services.has(ServiceFlags::from(NODE_P2P_V2)) {
+            let mut stream = self.connect_tcp(addr).await?;
+
+            let handshake = bip324::handshake(&mut stream, self.magic, true);
+            match tokio::time::timeout(timeout, handshake).await {
+                Ok(Ok(handshake)) => return Ok((stream, handshake.into())),
+                Ok(Err(err)) => {
+                    tracing::debug!(?err, ?addr, "v2 handshake failed, reconnecting with v1");
+                }
+                Err(_) => {
+                    tracing::debug!(?addr, "v2 handshake timed out, reconnecting with v1");
+                }
+            }
+        }
+
+        Ok((self.connect_tcp(addr).await?, Framing::v1(&[])))
+    }
+
+    async fn connect_tcp(&self, addr: PeerId) -> Result<PeerStream, Error> {
+        if is_i2p(&addr) {
+            return self.connect_i2p(addr).await;
+        }
+
+        let stream = tokio::time::timeout(
+            Duration::from_secs(Self::CONNECT_TIMEOUT),
+            TcpStream::connect(addr),
+        )
+        .await
+        .map_err(|_| Error::ConnectionTimeout)??;
+
+        Ok(PeerStream::tcp(stream)?)
+    }
+
+    async fn connect_i2p(&self, addr: PeerId) -> Result<PeerStream, Error> {
+        let (sam, hash) = self
+            .i2p_sam
+            .as_ref()
+            .zip(self.i2p_destinations.get(&addr))
+            .ok_or(Error::UnreachableNetwork(addr))?;
+
+        // The I2P tunnels take much longer to build than a TCP connection.
+        let stream = tokio::time::timeout(
+            Duration::from_secs(Self::I2P_CONNECT_TIMEOUT),
+            sam.connect(&hash),
+        )
+        .await
+        .map_err(|_| Error::ConnectionTimeout)??;
+
+        let mut stream = PeerStream::tcp(stream)?;
+        // The stream is connected to the SAM bridge.
+        stream.peer_addr = addr;
+        Ok(stream)
+    }
+
     /// Makes a new inbound connection.
-    pub fn initiate_inbound_connection(&self, stream: TcpStream) -> Result<(), Error> {
-        self.initiate_new_connection(Direction::Inbound, stream, Duration::ZERO)
+    ///
+    /// The v2 handshake, if enabled, is performed in the background.
+    pub(crate) fn initiate_inbound_connection(&self, mut stream: PeerStream) -> Result<(), Error> {
+        if !self.v2_transport || stream.transport != Transport::Tcp {
+            return self.initiate_new_connection(
+                Direction::Inbound,
+                stream,
+                Framing::v1(&[]),
+                Duration::ZERO,
+            );
+        }
+
+        self.spawn_handle.spawn("inbound-handshake", None, {
+            let connection_initiator = self.clone();
+
+            async move {
+                let peer_addr = stream.peer_addr;
+
+                let handshake = tokio::time::timeout(
+                    Duration::from_secs(Self::CONNECT_TIMEOUT),
+                    bip324::handshake(&mut stream, connection_initiator.magic, false),
+                )
+                .await
+                .map_err(|_| Error::HandshakeTimeout)
+                .and_then(|result| result);
+
+                let result = handshake.and_then(|handshake| {
+                    connection_initiator.initiate_new_connection(
+                        Direction::Inbound,
+                        stream,
+                        handshake.into(),
+                        Duration::ZERO,
+                    )
+                });
+
+                if let Err(err) = result {
+                    tracing::debug!(?err, ?peer_addr, "Failed to initiate inbound connection");
+                }
+            }
+        });
+
+        Ok(())
     }
 
     fn initiate_new_connection(
         &self,
         direction: Direction,
-        stream: TcpStream,
+        stream: PeerStream,
+        framing: Framing,
         connection_time: Duration,
     ) -> Result<(), Error> {
-        let peer_addr = stream.peer_addr()?;
-
-        if self.ipv4_only && peer_addr.is_ipv6() {
+        let PeerStream {
+            transport,
+            peer_addr,
+            local_addr,
+            reader,
+            writer,
+        } = stream;
+
+        let Framing {
+            decoder,
+            encoder,
+            protocol,
+            session_id,
+        } = framing;
+
+        if self.ipv4_only && address_network(&peer_addr) == AddressNetwork::Ipv6 {
             return Err(Error::Ipv4Only);
         }
 
-        let local_addr = stream.local_addr()?;
-
         let connect_latency = connection_time.as_millis();
 
         tracing::debug!(
             ?peer_addr,
             ?local_addr,
             ?direction,
+            ?transport,
+            ?protocol,
             connect_latency,
             "New connection"
         );
@@ -213,25 +601,34 @@ impl ConnectionInitiator {
 
         let disconnect_signal = Arc::new(AtomicBool::new(false));
 
+        let bandwidth = ConnectionBandwidth {
+            total: self.bandwidth.clone(),
+            connection: Bandwidth::default(),
+        };
+
         self.network_event_sender
             .send(Event::NewConnection(NewConnection {
                 peer_addr,
                 local_addr,
                 direction,
                 connect_latency,
+                transport: TransportInfo {
+                    transport,
+                    protocol,
+                    session_id,
+                },
+                bandwidth: bandwidth.connection.clone(),
                 writer: network_message_sender,
                 disconnect_signal: disconnect_signal.clone(),
             }))
             .map_err(|_| Error::NetworkEventStreamError)?;
 
-        // Maintain the communication with a remote peer over the given socket endlessly.
-        let (readable, writable) = stream.into_split();
-
+        // Maintain the communication with a remote peer over the given stream endlessly.
         self.spawn_handle.spawn(
             "connection-reader",
             None,
             {
-                let bandwidth = self.bandwidth.clone();
+                let bandwidth = bandwidth.clone();
                 let network_event_sender = self.network_event_sender.clone();
                 let disconnect_signal = disconnect_signal.clone();
 
@@ -239,7 +636,8 @@ impl ConnectionInitiator {
                     if let Err(err) = read_peer_messages(
                         peer_addr,
                         direction,
-                        readable,
+                        reader,
+                        decoder,
                         network_event_sender.clone(),
                         disconnect_signal,
                         bandwidth,
@@ -257,15 +655,15 @@ impl ConnectionInitiator {
         );
 
         self.spawn_handle.spawn("connection-writer", None, {
-            let bandwidth = self.bandwidth.clone();
             let network_event_sender = self.network_event_sender.clone();
-            let network = self.network;
+            let magic = self.magic;
 
             async move {
                 if let Err(err) = send_peer_messages(
                     peer_addr,
-                    network,
-                    writable,
+                    magic,
+                    writer,
+                    encoder,
                     network_message_receiver,
                     disconnect_signal,
                     bandwidth,
@@ -288,141 +686,180 @@ impl ConnectionInitiator {
 async fn read_peer_messages(
     peer: PeerId,
     direction: Direction,
-    readable: tokio::net::tcp::OwnedReadHalf,
+    mut reader: Box<dyn AsyncRead + Send + Unpin>,
+    mut decoder: MessageDecoder,
     network_event_sender: UnboundedSender<Event>,
     disconnect_signal: Arc<AtomicBool>,
-    bandwidth: Bandwidth,
+    bandwidth: ConnectionBandwidth,
 ) -> Result<(), Error> {
-    let mut decoder = NetworkMessageDecoder::new(1024 * 192);
+    let mut read_buffer = vec![0; READ_BUFFER_SIZE];
 
     loop {
+        // The bytes received during the handshake may already contain some messages.
+        while let Some(payload) = decoder.decode_next()? {
+            network_event_sender
+                .send(Event::PeerMessage {
+                    from: peer,
+                    direction,
+                    payload,
+                })
+                .map_err(|_| Error::NetworkEventStreamError)?;
+        }
+
         if disconnect_signal.load(Ordering::SeqCst) {
             tracing::trace!(?peer, "Stopping the reader task");
             return Ok(());
         }
 
-        readable.readable().await?;
+        let n = reader.read(&mut read_buffer).await?;
 
-        // TODO: optimize this, no need to always allocate the maximum buffer.
-        let mut read_buffer = vec![0; MAX_MSG_SIZE];
-
-        // Try to read data, this may still fail with `WouldBlock`
-        // if the readiness event is a false positive.
-        match readable.as_ref().try_read(&mut read_buffer) {
-            Ok(0) => {
-                tracing::trace!(from = ?peer, "<= recv 0 bytes");
-                return Err(Error::PeerShutdown);
-            }
-            Ok(n) => {
-                tracing::trace!(from = ?peer, "<= recv {n} bytes");
-
-                bandwidth
-                    .total_bytes_inbound
-                    .fetch_add(n as u64, Ordering::Relaxed);
-
-                decoder.input(&read_buffer[..n]);
-
-                while let Some(msg) = decoder.decode_next::<RawNetworkMessage>()? {
-                    network_event_sender
-                        .send(Event::PeerMessage {
-                            from: peer,
-                            direction,
-                            payload: msg.into_payload(),
-                        })
-                        .map_err(|_| Error::NetworkEventStreamError)?;
-                }
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                continue;
-            }
-            Err(e) => {
-                return Err(e.into());
-            }
+        if n == 0 {
+            tracing::trace!(from = ?peer, "<= recv 0 bytes");
+            return Err(Error::PeerShutdown);
         }
+
+        tracing::trace!(from = ?peer, "<= recv {n} bytes");
+
+        bandwidth.on_received(n as u64).await;
+
+        decoder.input(&read_buffer[..n]);
     }
 }
 
 async fn send_peer_messages(
     peer: PeerId,
-    network: bitcoin::Network,
-    writable: tokio::net::tcp::OwnedWriteHalf,
+    magic: Magic,
+    mut writer: Box<dyn AsyncWrite + Send + Unpin>,
+    mut encoder: MessageEncoder,
     mut network_message_receiver: UnboundedReceiver<NetworkMessage>,
     disconnect_signal: Arc<AtomicBool>,
-    bandwidth: Bandwidth,
+    bandwidth: ConnectionBandwidth,
 ) -> Result<(), Error> {
-    let magic = network.magic();
-
-    // Cache the messages yet to be processed due to the false positive readiness.
-    let mut msg_buffer: VecDeque<(&'static str, Vec<u8>)> = VecDeque::with_capacity(32);
-
     loop {
         if disconnect_signal.load(Ordering::SeqCst) {
             tracing::trace!(?peer, "Stopping the writer task");
             return Ok(());
         }
 
-        writable.writable().await?;
+        let Some(network_message) = network_message_receiver.recv().await else {
+            tracing::trace!(?peer, "Connection dropped, stopping the writer task");
+            return Ok(());
+        };
 
-        let mut buffered_front_sent = false;
+        tracing::trace!(to = ?peer, "Sending {network_message:?}");
 
-        if let Some((cmd, msg)) = msg_buffer.front() {
-            // Try to write data, this may still fail with `WouldBlock`
-            // if the readiness event is a false positive.
-            match writable.as_ref().try_write(msg) {
-                Ok(n) => {
-                    bandwidth
-                        .total_bytes_outbound
-                        .fetch_add(n as u64, Ordering::Relaxed);
+        let (cmd, msg_len, msg) = encoder.encode(magic, network_message)?;
 
-                    let msg_len = msg.len().saturating_sub(MSG_HEADER_SIZE);
-                    tracing::trace!(to = ?peer, "=> {cmd} ({msg_len} bytes) sent successfully");
-                    buffered_front_sent = true;
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        continue;
-                    } else {
-                        return Err(e.into());
-                    }
-                }
-            };
+        bandwidth.before_send(msg.len() as u64).await;
+
+        writer.write_all(&msg).await?;
+
+        bandwidth.on_sent(msg.len() as u64);
+
+        tracing::trace!(to = ?peer, "=> {cmd} ({msg_len} bytes) sent successfully");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::p2p::message_blockdata::Inventory;
+    use bitcoin::BlockHash;
+
+    fn encode_v1(message: NetworkMessage) -> Vec<u8> {
+        let (_, _, bytes) = MessageEncoder::V1
+            .encode(bitcoin::Network::Bitcoin.magic(), message)
+            .unwrap();
+        bytes
+    }
+
+    fn v1_header(command: &str, payload_len: u32) -> Vec<u8> {
+        let mut header = bitcoin::Network::Bitcoin.magic().to_bytes().to_vec();
+        let mut command_bytes = [0u8; 12];
+        command_bytes[..command.len()].copy_from_slice(command.as_bytes());
+        header.extend_from_slice(&command_bytes);
+        header.extend_from_slice(&payload_len.to_le_bytes());
+        header.extend_from_slice(&[0u8; 4]);
+        header
+    }
+
+    #[test]
+    fn test_decode_messages_in_chunks() {
+        let messages = vec![
+            NetworkMessage::Ping(1),
+            NetworkMessage::Inv(vec![Inventory::Block(BlockHash::all_zeros()); 100]),
+            NetworkMessage::Verack,
+            NetworkMessage::Pong(2),
+        ];
+        let bytes = messages
+            .iter()
+            .cloned()
+            .flat_map(encode_v1)
+            .collect::<Vec<_>>();
+
+        let mut rng = fastrand::Rng::with_seed(7);
+        let mut decoder = NetworkMessageDecoder::default();
+        let mut decoded = Vec::new();
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let (chunk, remaining) = rest.split_at(rng.usize(1..=rest.len().min(64)));
+            decoder.input(chunk);
+            while let Some(message) = decoder.decode_next().unwrap() {
+                decoded.push(message);
+            }
+            rest = remaining;
         }
 
-        if buffered_front_sent {
-            msg_buffer.pop_front();
-            continue;
+        assert_eq!(decoded, messages);
+        assert!(decoder.unparsed.is_empty());
+    }
+
+    #[test]
+    fn test_reject_oversized_messages() {
+        for (command, payload_len) in [
+            ("headers", 2001 * 81 + 3),
+            ("inv", 50_001 * 36 + 5),
+            ("addr", 1001 * 30 + 3),
+            ("block", MAX_MSG_SIZE as u32),
+        ] {
+            let mut decoder = NetworkMessageDecoder::default();
+            // The header is enough to reject the message.
+            decoder.input(&v1_header(command, payload_len));
+            assert!(matches!(
+                decoder.decode_next(),
+                Err(Error::OversizedMessage(cmd, len)) if cmd == command && len == payload_len as usize
+            ));
         }
 
-        if let Some(network_message) = network_message_receiver.recv().await {
-            tracing::trace!(to = ?peer, "Sending {network_message:?}");
+        let mut decoder = NetworkMessageDecoder::default();
+        decoder.input(&v1_header("headers", 161_000));
+        assert!(matches!(decoder.decode_next(), Ok(None)));
+    }
+
+    #[test]
+    fn test_decode_random_bytes() {
+        let mut rng = fastrand::Rng::with_seed(42);
 
-            let raw_network_message = RawNetworkMessage::new(magic, network_message);
+        for _ in 0..1000 {
+            let mut decoder = NetworkMessageDecoder::default();
 
-            let mut msg = Vec::with_capacity(MAX_MSG_SIZE);
-            raw_network_message.consensus_encode(&mut msg)?;
+            // Valid header prefix with the random length and payload.
+            let command =
+                ["headers", "inv", "addr", "addrv2", "tx", "block", "ping"][rng.usize(..7)];
+            let mut bytes = v1_header(command, rng.u32(..1024));
+            bytes.truncate(20);
+            bytes.extend((0..rng.usize(..2048)).map(|_| rng.u8(..)));
+            decoder.input(&bytes);
 
-            let cmd = raw_network_message.cmd();
+            let mut garbage = vec![0u8; rng.usize(..2048)];
+            rng.fill(&mut garbage);
+            decoder.input(&garbage);
 
-            // Try to write data, this may still fail with `WouldBlock` if
-            // the readiness event is a false positive.
-            match writable.as_ref().try_write(&msg) {
-                Ok(n) => {
-                    bandwidth
-                        .total_bytes_outbound
-                        .fetch_add(n as u64, Ordering::Relaxed);
+            while let Ok(Some(_)) = decoder.decode_next() {}
 
-                    // Bitcoin Core logs the message size without counting in the header.
-                    let msg_len = msg.len().saturating_sub(MSG_HEADER_SIZE);
-                    tracing::trace!(to = ?peer, "=> {cmd} ({msg_len} bytes) sent successfully");
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        msg_buffer.push_back((cmd, msg));
-                    } else {
-                        return Err(e.into());
-                    }
-                }
-            }
+            // The buffer holds the received bytes only.
+            assert!(decoder.unparsed.len() <= bytes.len() + garbage.len());
         }
     }
 }
@@ -0,0 +1,147 @@
+//! Selection of the inbound peer to evict when the inbound slots are full.
+//!
+//! Follows the heuristics of Bitcoin Core: the peers which are hard for an attacker to
+//! imitate are protected, i.e., the peers from distinct network groups, with the lowest
+//! latency, having recently relayed new blocks or transactions to us and the longest
+//! connected ones. The youngest peer of the most represented network group among the
+//! remaining candidates is evicted.
+
+use crate::address_book::NetGroup;
+use crate::{Latency, PeerId};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
+
+/// Number of peers protected by the keyed network group.
+const PROTECTED_BY_NETGROUP: usize = 4;
+
+/// Number of peers protected by the lowest ping latency.
+const PROTECTED_BY_LATENCY: usize = 8;
+
+/// Number of peers protected by the most recently relayed transactions.
+const PROTECTED_BY_TX_RELAY: usize = 4;
+
+/// Number of peers protected by the most recently relayed blocks.
+const PROTECTED_BY_BLOCK_RELAY: usize = 4;
+
+/// Inbound peer eligible for eviction.
+#[derive(Debug, Clone)]
+pub(crate) struct EvictionCandidate {
+    pub(crate) peer_id: PeerId,
+    pub(crate) netgroup: Option<NetGroup>,
+    pub(crate) connected_at: Instant,
+    /// Lowest ping latency of the peer.
+    pub(crate) latency: Latency,
+    pub(crate) last_block_at: Option<Instant>,
+    pub(crate) last_tx_at: Option<Instant>,
+}
+
+/// Removes the `count` candidates ranked first by `key`.
+fn protect<K: Ord>(
+    candidates: &mut Vec<EvictionCandidate>,
+    count: usize,
+    key: impl Fn(&EvictionCandidate) -> K,
+) {
+    candidates.sort_by_key(key);
+    candidates.drain(..count.min(candidates.len()));
+}
+
+/// Returns the inbound peer to evict, `None` if all the candidates are protected.
+///
+/// `netgroup_key` is a secret of the local node, it prevents an attacker from predicting
+/// which network groups are protected.
+pub(crate) fn select_peer_to_evict(
+    mut candidates: Vec<EvictionCandidate>,
+    netgroup_key: u64,
+) -> Option<PeerId> {
+    let keyed_netgroup = |candidate: &EvictionCandidate| {
+        let mut hasher = DefaultHasher::new();
+        netgroup_key.hash(&mut hasher);
+        candidate.netgroup.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    protect(&mut candidates, PROTECTED_BY_NETGROUP, keyed_netgroup);
+    protect(&mut candidates, PROTECTED_BY_LATENCY, |c| c.latency);
+    protect(&mut candidates, PROTECTED_BY_TX_RELAY, |c| {
+        std::cmp::Reverse(c.last_tx_at)
+    });
+    protect(&mut candidates, PROTECTED_BY_BLOCK_RELAY, |c| {
+        std::cmp::Reverse(c.last_block_at)
+    });
+    let half = candidates.len() / 2;
+    protect(&mut candidates, half, |c| c.connected_at);
+
+    // The candidates whose netgroup is unknown form a group of their own.
+    let mut groups: HashMap<Option<NetGroup>, Vec<EvictionCandidate>> = HashMap::new();
+    for candidate in candidates {
+        groups
+            .entry(candidate.netgroup)
+            .or_default()
+            .push(candidate);
+    }
+
+    let youngest = |group: &Vec<EvictionCandidate>| {
+        group
+            .iter()
+            .max_by_key(|candidate| candidate.connected_at)
+            .map(|candidate| (candidate.connected_at, candidate.peer_id))
+    };
+
+    // The largest group, the one with the youngest member in case of a tie.
+    groups
+        .values()
+        .max_by_key(|group| (group.len(), youngest(group).map(|(at, _)| at)))
+        .and_then(youngest)
+        .map(|(_, peer_id)| peer_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address_book::netgroup;
+    use std::time::Duration;
+
+    fn candidate(addr: &str, age_secs: u64, latency: Latency) -> EvictionCandidate {
+        let peer_id: PeerId = addr.parse().unwrap();
+        EvictionCandidate {
+            peer_id,
+            netgroup: netgroup(&peer_id),
+            connected_at: Instant::now() - Duration::from_secs(age_secs),
+            latency,
+            last_block_at: None,
+            last_tx_at: None,
+        }
+    }
+
+    #[test]
+    fn test_too_few_candidates_are_protected() {
+        let candidates = (0..4)
+            .map(|i| candidate(&format!("{}.1.1.1:8333", i + 1), 100, 100))
+            .collect();
+        assert_eq!(select_peer_to_evict(candidates, 0), None);
+    }
+
+    #[test]
+    fn test_evict_youngest_of_largest_netgroup() {
+        // Distinct netgroups with low latency and relaying to us, all protected.
+        let mut candidates = (0..16)
+            .map(|i| EvictionCandidate {
+                last_block_at: Some(Instant::now()),
+                last_tx_at: Some(Instant::now()),
+                ..candidate(&format!("{}.1.1.1:8333", i + 10), 1000, 10)
+            })
+            .collect::<Vec<_>>();
+
+        // Attacker's peers sharing a netgroup, the most recent one is evicted.
+        for i in 0..8 {
+            candidates.push(candidate(&format!("99.99.1.{i}:8333"), 100 - i, 1000));
+        }
+
+        assert_eq!(
+            select_peer_to_evict(candidates, 0),
+            Some("99.99.1.7:8333".parse().unwrap())
+        );
+    }
+}
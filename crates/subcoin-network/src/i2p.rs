@@ -0,0 +1,244 @@
+//! I2P connections through the SAM v3.1 bridge of a local I2P router.
+//!
+//! A [`PeerId`] can't hold an I2P destination, each destination is represented by its
+//! GarliCat address: `fd60:db4d:ddb5::/48` followed by the first 10 bytes of the destination
+//! hash, with port 0 as I2P has no ports. The full hashes are kept in [`I2pDestinations`] to
+//! connect to the peers and to persist their addresses.
+//!
+//! The session uses a transient destination, only the outbound I2P connections are made.
+
+use crate::PeerId;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Prefix of the GarliCat addresses.
+const GARLICAT_PREFIX: [u8; 6] = [0xfd, 0x60, 0xdb, 0x4d, 0xdd, 0xb5];
+
+/// Maximum number of the known destinations, the addresses of the new ones are dropped once
+/// reached.
+const MAX_DESTINATIONS: usize = 16 * 1024;
+
+/// Maximum length of a SAM reply line.
+const MAX_REPLY_LEN: usize = 4096;
+
+/// Returns the GarliCat address of the destination hash.
+pub(crate) fn garlicat(hash: &[u8; 32]) -> PeerId {
+    let mut octets = [0u8; 16];
+    octets[..6].copy_from_slice(&GARLICAT_PREFIX);
+    octets[6..].copy_from_slice(&hash[..10]);
+    PeerId::new(IpAddr::V6(Ipv6Addr::from(octets)), 0)
+}
+
+/// Returns `true` if the address is a GarliCat address.
+pub(crate) fn is_i2p(addr: &PeerId) -> bool {
+    match addr.ip() {
+        IpAddr::V6(ip) => ip.octets()[..6] == GARLICAT_PREFIX,
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// Full destination hashes of the known I2P peers, by their GarliCat address.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct I2pDestinations(Arc<RwLock<HashMap<PeerId, [u8; 32]>>>);
+
+impl I2pDestinations {
+    /// Registers the destination, returns its GarliCat address or `None` if too many
+    /// destinations are known.
+    pub(crate) fn insert(&self, hash: [u8; 32]) -> Option<PeerId> {
+        let addr = garlicat(&hash);
+        let mut destinations = self.0.write();
+        if destinations.len() >= MAX_DESTINATIONS && !destinations.contains_key(&addr) {
+            return None;
+        }
+        destinations.insert(addr, hash);
+        Some(addr)
+    }
+
+    /// Returns the destination hash of the GarliCat address.
+    pub(crate) fn get(&self, addr: &PeerId) -> Option<[u8; 32]> {
+        self.0.read().get(addr).copied()
+    }
+}
+
+/// Returns the `.b32.i2p` address of the destination hash.
+fn b32_address(hash: &[u8; 32]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+    let mut encoded = String::with_capacity(60);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+
+    for byte in hash {
+        buffer = (buffer << 8) | u16::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[usize::from((buffer >> bits) & 0x1f)] as char);
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(ALPHABET[usize::from((buffer << (5 - bits)) & 0x1f)] as char);
+    }
+
+    encoded.push_str(".b32.i2p");
+    encoded
+}
+
+/// Reads a reply line byte by byte, the stream data following it must not be consumed.
+async fn read_reply(stream: &mut TcpStream) -> io::Result<String> {
+    let mut line = Vec::new();
+
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == b'\n' {
+            break;
+        }
+        if line.len() >= MAX_REPLY_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "SAM reply too long",
+            ));
+        }
+        line.push(byte);
+    }
+
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Sends the SAM command and checks the reply starts with `expected` and reports
+/// `RESULT=OK`.
+async fn command(stream: &mut TcpStream, command: &str, expected: &str) -> io::Result<String> {
+    stream.write_all(format!("{command}\n").as_bytes()).await?;
+
+    let reply = read_reply(stream).await?;
+
+    if !reply.starts_with(expected) || !reply.split(' ').any(|field| field == "RESULT=OK") {
+        return Err(io::Error::other(format!(
+            "Unexpected SAM reply to `{command}`: {reply}"
+        )));
+    }
+
+    Ok(reply)
+}
+
+async fn hello(proxy: SocketAddr) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy).await?;
+    command(&mut stream, "HELLO VERSION MIN=3.1 MAX=3.1", "HELLO REPLY").await?;
+    Ok(stream)
+}
+
+/// Session on the SAM bridge, kept alive as long as its control connection is open.
+struct Session {
+    id: String,
+    _control: TcpStream,
+}
+
+/// SAM bridge making the I2P connections.
+pub(crate) struct SamBridge {
+    proxy: SocketAddr,
+    session: Mutex<Option<Session>>,
+}
+
+impl SamBridge {
+    pub(crate) fn new(proxy: SocketAddr) -> Self {
+        Self {
+            proxy,
+            session: Mutex::new(None),
+        }
+    }
+
+    /// Opens a stream to the destination, the session is created on the first connection.
+    pub(crate) async fn connect(&self, hash: &[u8; 32]) -> io::Result<TcpStream> {
+        let session_id = {
+            let mut session = self.session.lock().await;
+            match session.as_ref() {
+                Some(session) => session.id.clone(),
+                None => {
+                    let new_session = self.create_session().await?;
+                    let id = new_session.id.clone();
+                    *session = Some(new_session);
+                    id
+                }
+            }
+        };
+
+        let mut stream = hello(self.proxy).await?;
+
+        let connect = format!(
+            "STREAM CONNECT ID={session_id} DESTINATION={} SILENT=false",
+            b32_address(hash)
+        );
+
+        match command(&mut stream, &connect, "STREAM STATUS").await {
+            Ok(_) => Ok(stream),
+            Err(err) => {
+                // The session is recreated next time in case it's gone, e.g., on a router
+                // restart.
+                if err.to_string().contains("INVALID_ID") {
+                    self.session.lock().await.take();
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn create_session(&self) -> io::Result<Session> {
+        let mut control = hello(self.proxy).await?;
+        let id = format!("subcoin-{:016x}", fastrand::u64(..));
+
+        command(
+            &mut control,
+            &format!(
+                "SESSION CREATE STYLE=STREAM ID={id} DESTINATION=TRANSIENT SIGNATURE_TYPE=7 \
+                i2cp.leaseSetEncType=4,0 inbound.quantity=1 outbound.quantity=1"
+            ),
+            "SESSION STATUS",
+        )
+        .await?;
+
+        tracing::info!("🧄 Created I2P session {id} on {}", self.proxy);
+
+        Ok(Session {
+            id,
+            _control: control,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_garlicat() {
+        let hash = [0xabu8; 32];
+        let addr = garlicat(&hash);
+
+        assert!(is_i2p(&addr));
+        assert_eq!(addr.port(), 0);
+        assert!(!is_i2p(&"[fd00::1]:8333".parse().unwrap()));
+
+        let destinations = I2pDestinations::default();
+        assert_eq!(destinations.insert(hash), Some(addr));
+        assert_eq!(destinations.get(&addr), Some(hash));
+    }
+
+    #[test]
+    fn test_b32_address() {
+        assert_eq!(
+            b32_address(&[0u8; 32]),
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.b32.i2p"
+        );
+
+        let mut hash = [0u8; 32];
+        hash[0] = 0xff;
+        assert!(b32_address(&hash).starts_with("74aa"));
+    }
+}
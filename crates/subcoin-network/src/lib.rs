@@ -18,6 +18,15 @@
 //! from the network is not possible, in that Subcoin requires full block data to derive the corresponding
 //! substrate header properly, including generating the `extrinsics_root` and `state_root`.
 //!
+//! ## Snapshot Sync
+//!
+//! Subcoin nodes can serve the snapshots of the state at the finalized blocks to each other
+//! over the Bitcoin p2p connections, see [`NODE_SNAPSHOT`]. A fresh node can download the
+//! state from the peers offering the snapshots and skip the historical blocks.
+//!
+//! The subcoin-specific messages are versioned, the peers negotiate the supported protocol
+//! features right after the Bitcoin handshake, see [`SubcoinVersion`].
+//!
 //! ## Subcoin Bootstrap Node
 //!
 //! Subcoin node runs the Bitcoin networking and Substrate networking in parallel. Initial block download
@@ -27,42 +36,88 @@
 //! to Bitcoin chain's tip by leveraging the advanced state sync provided by the Substrate networking stack.
 
 mod address_book;
+mod bip324;
 mod block_downloader;
+mod broadcast_manager;
 mod checkpoint;
 mod connection;
+mod eviction;
+mod i2p;
+mod light_sync;
+mod local_address;
+mod mempool_file;
 mod metrics;
+mod network_time;
 mod orphan_blocks_pool;
 mod peer_manager;
+mod peer_store;
+mod port_mapping;
+mod rate_limit;
+mod snapshot_sync;
+mod stall_detector;
+mod subcoin_protocol;
 mod sync;
+mod sync_progress;
 #[cfg(test)]
 mod tests;
 mod transaction_manager;
+mod transport;
 mod worker;
 
 use crate::connection::ConnectionInitiator;
+use crate::i2p::I2pDestinations;
+use crate::rate_limit::{RateLimiter, UploadTarget};
+use crate::transport::QuicEndpoint;
 use crate::worker::NetworkWorker;
-use bitcoin::p2p::ServiceFlags;
+use bitcoin::p2p::{Magic, ServiceFlags};
 use bitcoin::{BlockHash, Network as BitcoinNetwork, Transaction, Txid};
 use peer_manager::HandshakeState;
 use sc_client_api::{AuxStore, HeaderBackend};
-use sc_consensus_nakamoto::BlockImportQueue;
+use sc_consensus_nakamoto::{BlockImportQueue, BlockVerificationError, ChainParams, HeaderError};
 use sc_service::SpawnTaskHandle;
 use sc_utils::mpsc::{tracing_unbounded, TracingUnboundedReceiver, TracingUnboundedSender};
 use serde::{Deserialize, Serialize};
 use sp_runtime::traits::Block as BlockT;
 use std::marker::PhantomData;
 use std::net::{AddrParseError, SocketAddr};
-use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use subcoin_db::SubcoinDb;
+use subcoin_primitives::{BackendExt, ClientExt};
+use subcoin_snapshot::SnapshotStore;
 use substrate_prometheus_endpoint::Registry;
 use tokio::net::TcpListener;
 use tokio::sync::oneshot;
 
+pub use crate::broadcast_manager::{BroadcastReject, BroadcastState, BroadcastStatus, ConfirmedIn};
+pub use crate::mempool_file::{load_mempool, MempoolEntry};
+pub use crate::peer_manager::ConnectionType;
+pub use crate::rate_limit::UploadTargetInfo;
+pub use crate::subcoin_protocol::{SubcoinFeatures, SubcoinVersion, SUBCOIN_PROTOCOL_VERSION};
 pub use crate::sync::{PeerLatency, PeerSync, PeerSyncState};
+pub use crate::sync_progress::SyncPhase;
+pub use crate::transaction_manager::UtxoProvider;
+pub use crate::transport::{Transport, TransportInfo, TransportProtocol};
 
 /// Identifies a peer.
 pub type PeerId = SocketAddr;
 
+/// Service bit of the nodes supporting the BIP-324 v2 encrypted transport.
+pub const NODE_P2P_V2: u64 = 1 << 11;
+
+/// Service bit of the subcoin nodes serving the snapshots.
+///
+/// Bits 24-31 are reserved for the temporary experiments by BIP 159.
+pub const NODE_SNAPSHOT: u64 = 1 << 24;
+
+/// Service bit of the subcoin nodes accepting the connections over QUIC, see [`Transport`].
+pub const NODE_QUIC: u64 = 1 << 25;
+
+/// Default memory budget of the blocks downloaded but not imported yet, in bytes.
+pub const DEFAULT_IMPORT_MEMORY_BUDGET: usize = 512 * 1024 * 1024;
+
 /// Peer latency in milliseconds.
 pub type Latency = u128;
 
@@ -86,6 +141,8 @@ pub enum Error {
     HandshakeTimeout,
     #[error("Only IPv4 peers are supported")]
     Ipv4Only,
+    #[error("Network of {0} is not reachable")]
+    UnreachableNetwork(PeerId),
     #[error("Peer is not a full node")]
     NotFullNode,
     #[error("Peer is not a segwit node")]
@@ -100,12 +157,24 @@ pub enum Error {
     HeadersNotInAscendingOrder,
     #[error("Too many inventory items")]
     TooManyInventoryItems,
+    #[error("Too many entries (> 1000) in addr message")]
+    TooManyAddresses,
+    #[error("Oversized {0} message: {1} bytes")]
+    OversizedMessage(String, usize),
     #[error("Ping timeout")]
     PingTimeout,
     #[error("Ping latency exceeds the threshold")]
     PingLatencyTooHigh,
     #[error("Peer's latency ({0} ms) is too high")]
     SlowPeer(Latency),
+    #[error("Replaced by a new block-relay-only peer")]
+    PeerRotation,
+    #[error("Sync stalled")]
+    SyncStalled,
+    #[error("Evicted to make room for a new inbound peer")]
+    InboundEviction,
+    #[error("Misbehaving peer, ban score {0}")]
+    Misbehaving(u32),
     #[error("Unexpected pong message")]
     UnexpectedPong,
     #[error("Invalid pong message: bad nonce")]
@@ -114,6 +183,30 @@ pub enum Error {
     UnrequestedBlock(BlockHash),
     #[error("Cannot find the parent of the first header in headers message")]
     ParentOfFirstHeaderEntryNotFound,
+    #[error("Invalid header {0:?}: {1}")]
+    BadHeader(BlockHash, HeaderError),
+    #[error("Invalid block {0:?}: {1}")]
+    InvalidBlock(BlockHash, BlockVerificationError),
+    #[error("Snapshot manifest does not match the announced snapshot")]
+    InvalidSnapshotManifest,
+    #[error("Snapshot chunk {0} does not match the manifest")]
+    InvalidSnapshotChunk(u32),
+    #[error("Invalid snapshot message: {0}")]
+    InvalidSnapshotMessage(codec::Error),
+    #[error("Invalid subcoin version message: {0}")]
+    InvalidSubcoinVersion(codec::Error),
+    #[error("BIP-324 garbage terminator not found")]
+    V2GarbageTerminatorNotFound,
+    #[error("BIP-324 packet failed the authentication")]
+    V2PacketAuthentication,
+    #[error("BIP-324 packet is too large: {0} bytes")]
+    V2PacketTooLarge(usize),
+    #[error("BIP-324 packet without a valid message type")]
+    V2EmptyPacket,
+    #[error("Unknown BIP-324 short message type id: {0}")]
+    V2UnknownMessageId(u8),
+    #[error("Invalid QUIC config: {0}")]
+    QuicConfig(String),
     #[error("Other: {0}")]
     Other(String),
     #[error(transparent)]
@@ -121,6 +214,10 @@ pub enum Error {
     #[error(transparent)]
     InvalidAddress(#[from] AddrParseError),
     #[error(transparent)]
+    QuicConnect(#[from] quinn::ConnectError),
+    #[error(transparent)]
+    QuicConnection(#[from] quinn::ConnectionError),
+    #[error(transparent)]
     Blockchain(#[from] sp_blockchain::Error),
     #[error(transparent)]
     Consensus(#[from] sp_consensus::Error),
@@ -130,9 +227,41 @@ pub enum Error {
     BitcoinEncoding(#[from] bitcoin::consensus::encode::Error),
 }
 
+impl Error {
+    /// Returns the misbehavior score of the peer causing this error, if it's a protocol
+    /// violation.
+    ///
+    /// The scores follow the ones of Bitcoin Core before the ban score was removed.
+    fn misbehavior_score(&self) -> Option<u32> {
+        match self {
+            Self::TooManyBlockEntries
+            | Self::TooManyHeaders
+            | Self::HeadersNotInAscendingOrder
+            | Self::TooManyInventoryItems
+            | Self::TooManyAddresses => Some(20),
+            // The block too far in the future may become valid later.
+            Self::InvalidBlock(_, BlockVerificationError::TimeTooNew) => None,
+            Self::BadHeader(..)
+            | Self::InvalidBlock(..)
+            | Self::InvalidSnapshotManifest
+            | Self::InvalidSnapshotChunk(_)
+            | Self::InvalidSnapshotMessage(_)
+            | Self::InvalidSubcoinVersion(_)
+            | Self::OversizedMessage(..) => Some(100),
+            _ => None,
+        }
+    }
+}
+
 // Ignore the peer if it is not full with witness enabled as we only want to
 // download from peers that can provide use full witness data for blocks.
+//
+// The subcoin nodes serving the snapshots are accepted for the snapshot sync.
 fn validate_outbound_services(services: ServiceFlags) -> Result<(), Error> {
+    if services.has(ServiceFlags::from(NODE_SNAPSHOT)) {
+        return Ok(());
+    }
+
     if !services.has(ServiceFlags::NETWORK) {
         return Err(Error::NotFullNode);
     }
@@ -153,6 +282,34 @@ pub enum SyncStrategy {
     HeadersFirst,
     /// Download the full blocks (both headers and bodies) in sequence.
     BlocksFirst,
+    /// Download and verify the headers only, used by the light client.
+    #[cfg_attr(feature = "cli", value(skip))]
+    HeadersOnly,
+}
+
+/// Block at which the sync is stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopAt {
+    /// Stop once the best block reaches the height.
+    Height(u32),
+    /// Stop once the block is imported, it must be in the chain being synced.
+    BlockHash(BlockHash),
+}
+
+impl StopAt {
+    /// Returns `true` if the block to stop at has been imported.
+    pub fn is_reached<Block, Client>(&self, client: &Arc<Client>) -> bool
+    where
+        Block: BlockT,
+        Client: HeaderBackend<Block> + AuxStore,
+    {
+        match self {
+            Self::Height(height) => ClientExt::<Block>::best_number(client) >= *height,
+            Self::BlockHash(block_hash) => {
+                BackendExt::<Block>::block_number(client, *block_hash).is_some()
+            }
+        }
+    }
 }
 
 /// Represents the sync status of node.
@@ -168,6 +325,14 @@ pub enum SyncStatus {
     Downloading { target: u32, peers: Vec<PeerId> },
     /// The node is importing downloaded blocks into the local database.
     Importing { target: u32, peers: Vec<PeerId> },
+    /// The node is downloading the state snapshot from peers.
+    ///
+    /// `target` is the snapshot block number, `None` if no snapshot has been chosen yet.
+    SnapshotSyncing {
+        target: Option<u32>,
+        downloaded_chunks: u32,
+        total_chunks: u32,
+    },
 }
 
 /// Represents the status of network.
@@ -182,12 +347,99 @@ pub struct NetworkStatus {
     pub total_bytes_outbound: u64,
     /// Current sync status of the node.
     pub sync_status: SyncStatus,
+    /// Current phase of the block sync, `None` during the snapshot sync.
+    pub sync_phase: Option<SyncPhase>,
+    /// Offset of the network-adjusted time from the local clock, in seconds.
+    pub time_offset: i64,
+}
+
+/// Detailed information of a connected peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerDetails {
+    /// Address of the peer.
+    pub peer_id: PeerId,
+    /// Local address of the connection.
+    pub local_addr: PeerId,
+    pub connection_type: ConnectionType,
+    pub transport: TransportInfo,
+    /// Hex-encoded services advertised by the peer.
+    pub services: String,
+    /// Protocol version negotiated with the peer.
+    pub version: u32,
+    pub user_agent: String,
+    /// Best block number announced by the peer in its version message.
+    pub start_height: u32,
+    /// Whether the peer wants the transactions to be relayed.
+    pub relay: bool,
+    /// Seconds since the connection was established.
+    pub connected_secs: u64,
+    /// Average ping latency in milliseconds, `None` if no pong has been received.
+    pub ping: Option<Latency>,
+    /// Latency of the last ping in milliseconds.
+    pub last_ping: Option<Latency>,
+    /// Lowest ping latency in milliseconds.
+    pub min_ping: Option<Latency>,
+    /// Milliseconds since the ping without pong yet was sent, the peer is disconnected if no
+    /// pong is received within 30 seconds.
+    pub ping_wait: Option<Latency>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Number of the blocks or snapshot data requested from the peer and not received yet.
+    pub requests_in_flight: usize,
+    /// Accumulated misbehavior score, the peer is disconnected once it reaches 100.
+    pub ban_score: u32,
+    /// Number of the messages from the peer dropped for exceeding the message rate limit.
+    pub dropped_messages: u64,
+    /// State of the block sync with the peer, `None` if the peer is not used for the sync.
+    pub sync_state: Option<PeerSyncState>,
+    /// Subcoin protocol version negotiated with the peer, `None` if not a subcoin node.
+    pub subcoin_version: Option<u32>,
+    /// Hex-encoded subcoin protocol features negotiated with the peer.
+    pub subcoin_features: Option<String>,
+}
+
+/// Aggregate information of the p2p networking.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkInfo {
+    /// Protocol version of the local node.
+    pub protocol_version: u32,
+    pub user_agent: String,
+    /// Hex-encoded services advertised by the local node.
+    pub local_services: String,
+    pub connections: usize,
+    pub connections_in: usize,
+    pub connections_out: usize,
+    /// Number of the known addresses available for new outbound connections.
+    pub available_addresses: usize,
+    pub total_bytes_inbound: u64,
+    pub total_bytes_outbound: u64,
+    /// Offset of the network-adjusted time from the local clock, in seconds.
+    pub time_offset: i64,
+    /// Whether the BIP-324 v2 transport is supported.
+    pub v2_transport: bool,
+    /// Whether the QUIC transport is enabled.
+    pub quic: bool,
+    /// External address advertised to the peers, `None` if not discovered yet.
+    pub local_address: Option<PeerId>,
+    /// Status of the upload target, `None` if unlimited.
+    pub upload_target: Option<UploadTargetInfo>,
+}
+
+/// Formats the service flags the way Bitcoin Core does.
+fn services_hex(services: ServiceFlags) -> String {
+    format!("{:016x}", services.to_u64())
 }
 
 #[derive(Debug, Default)]
 struct Bandwidth {
     total_bytes_inbound: Arc<AtomicU64>,
     total_bytes_outbound: Arc<AtomicU64>,
+    /// Limit of the inbound bytes per second.
+    download_limiter: Option<RateLimiter>,
+    /// Limit of the outbound bytes per second.
+    upload_limiter: Option<RateLimiter>,
 }
 
 impl Clone for Bandwidth {
@@ -195,10 +447,22 @@ impl Clone for Bandwidth {
         Self {
             total_bytes_inbound: self.total_bytes_inbound.clone(),
             total_bytes_outbound: self.total_bytes_outbound.clone(),
+            download_limiter: self.download_limiter.clone(),
+            upload_limiter: self.upload_limiter.clone(),
         }
     }
 }
 
+impl Bandwidth {
+    fn inbound(&self) -> u64 {
+        self.total_bytes_inbound.load(Ordering::Relaxed)
+    }
+
+    fn outbound(&self) -> u64 {
+        self.total_bytes_outbound.load(Ordering::Relaxed)
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum SendTransactionResult {
@@ -220,12 +484,34 @@ enum NetworkWorkerMessage {
     NetworkStatus(oneshot::Sender<NetworkStatus>),
     /// Retrieve the sync peers.
     SyncPeers(oneshot::Sender<Vec<PeerSync>>),
-    /// Retrieve the number of inbound connected peers.
-    InboundPeersCount(oneshot::Sender<usize>),
+    /// Retrieve the details of the connected peers.
+    PeerDetails(oneshot::Sender<Vec<PeerDetails>>),
+    /// Retrieve the aggregate network information.
+    NetworkInfo(oneshot::Sender<NetworkInfo>),
+    /// Make room for a new inbound peer, evicting an existing one if the inbound slots are
+    /// full. Returns whether the new peer can be accepted.
+    RequestInboundSlot(oneshot::Sender<bool>),
     /// Retrieve the transaction.
     GetTransaction((Txid, oneshot::Sender<Option<Transaction>>)),
+    /// Retrieve all the transactions in the transaction manager.
+    Transactions(oneshot::Sender<Vec<Transaction>>),
     /// Add transaction to the transaction manager.
     SendTransaction((IncomingTransaction, oneshot::Sender<SendTransactionResult>)),
+    /// Add the transactions loaded from the mempool file to the transaction manager.
+    RestoreTransactions((Vec<MempoolEntry>, oneshot::Sender<usize>)),
+    /// Write the transactions in the transaction manager to the mempool file.
+    SaveMempool(oneshot::Sender<Result<(PathBuf, usize), Error>>),
+    /// Retrieve the broadcast status of a locally submitted transaction.
+    BroadcastStatus((Txid, oneshot::Sender<Option<BroadcastStatus>>)),
+    /// The block including these transactions became part of the best chain.
+    BlockConnected((BlockHash, u32, Vec<Txid>)),
+    /// The blocks were removed from the best chain.
+    BlocksDisconnected(Vec<BlockHash>),
+    /// Maintain the connection with the peer like the ones specified with `--addnode`.
+    AddNode(PeerId),
+    /// The listen port was mapped on the gateway to this address, `None` if the mapping is
+    /// lost.
+    SetMappedAddress(Option<PeerId>),
 }
 
 /// A handle for interacting with the network worker.
@@ -268,6 +554,36 @@ impl NetworkHandle {
         receiver.await.unwrap_or_default()
     }
 
+    /// Returns the details of the connected peers.
+    pub async fn peer_details(&self) -> Vec<PeerDetails> {
+        let (sender, receiver) = oneshot::channel();
+
+        if self
+            .worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::PeerDetails(sender))
+            .is_err()
+        {
+            return Vec::new();
+        }
+
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Returns the aggregate network information.
+    ///
+    /// Returns None if the `NetworkWorker` is no longer running.
+    pub async fn network_info(&self) -> Option<NetworkInfo> {
+        let (sender, receiver) = oneshot::channel();
+
+        self.worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::NetworkInfo(sender))
+            .ok()?;
+
+        receiver.await.ok()
+    }
+
+    /// Returns the unconfirmed transaction by txid, or by wtxid if no transaction with the
+    /// txid is known.
     pub async fn get_transaction(&self, txid: Txid) -> Option<Transaction> {
         let (sender, receiver) = oneshot::channel();
 
@@ -282,6 +598,24 @@ impl NetworkHandle {
         receiver.await.ok().flatten()
     }
 
+    /// Returns the transactions received from the network or submitted locally and not yet
+    /// expired, in the order they were received.
+    pub async fn transactions(&self) -> Vec<Transaction> {
+        let (sender, receiver) = oneshot::channel();
+
+        if self
+            .worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::Transactions(sender))
+            .is_err()
+        {
+            return Vec::new();
+        }
+
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Adds the transaction to the transaction manager and broadcasts it until confirmed,
+    /// see [`Self::broadcast_status`].
     pub async fn send_transaction(&self, transaction: Transaction) -> SendTransactionResult {
         let (sender, receiver) = oneshot::channel();
 
@@ -306,6 +640,75 @@ impl NetworkHandle {
             .unwrap_or(SendTransactionResult::Failure("Internal error".to_string()))
     }
 
+    /// Adds the transactions loaded by [`load_mempool`], keeping the time they were received.
+    ///
+    /// The transactions must have been revalidated. Returns the number of the transactions
+    /// added, the expired ones are dropped.
+    pub async fn restore_mempool(&self, entries: Vec<MempoolEntry>) -> usize {
+        let (sender, receiver) = oneshot::channel();
+
+        if self
+            .worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::RestoreTransactions((entries, sender)))
+            .is_err()
+        {
+            return 0;
+        }
+
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Writes the transactions to the mempool file, which is also done on shutdown.
+    ///
+    /// Returns the path of the file and the number of the saved transactions.
+    pub async fn save_mempool(&self) -> Result<(PathBuf, usize), Error> {
+        let (sender, receiver) = oneshot::channel();
+
+        self.worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::SaveMempool(sender))
+            .map_err(|_| Error::NetworkEventStreamError)?;
+
+        receiver.await.map_err(|_| Error::NetworkEventStreamError)?
+    }
+
+    /// Returns the broadcast status of a transaction submitted via
+    /// [`Self::send_transaction`] by txid or wtxid, `None` if it is not tracked.
+    pub async fn broadcast_status(&self, txid: Txid) -> Option<BroadcastStatus> {
+        let (sender, receiver) = oneshot::channel();
+
+        self.worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::BroadcastStatus((txid, sender)))
+            .ok()?;
+
+        receiver.await.ok().flatten()
+    }
+
+    /// Notifies the transactions included in the new best block, which are no longer
+    /// rebroadcast.
+    pub fn note_block_connected(&self, block_hash: BlockHash, height: u32, txids: Vec<Txid>) {
+        let _ = self
+            .worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::BlockConnected((
+                block_hash, height, txids,
+            )));
+    }
+
+    /// Notifies the blocks removed from the best chain by a reorg, the transactions
+    /// confirmed in them are rebroadcast.
+    pub fn note_blocks_disconnected(&self, block_hashes: Vec<BlockHash>) {
+        let _ = self
+            .worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::BlocksDisconnected(block_hashes));
+    }
+
+    /// Connects to the peer and keeps reconnecting to it, similar to `addnode add` in
+    /// Bitcoin Core.
+    pub fn add_node(&self, addr: PeerId) {
+        let _ = self
+            .worker_msg_sender
+            .unbounded_send(NetworkWorkerMessage::AddNode(addr));
+    }
+
     /// Returns a flag indicating whether the node is actively performing a major sync.
     pub fn is_major_syncing(&self) -> Arc<AtomicBool> {
         self.is_major_syncing.clone()
@@ -316,20 +719,106 @@ impl NetworkHandle {
 pub struct Params {
     /// Bitcoin network type.
     pub network: BitcoinNetwork,
+    /// Custom network overriding the P2P and consensus params of `network`.
+    pub custom_network: Option<CustomNetworkParams>,
     /// Specify the local listen address.
     pub listen_on: PeerId,
+    /// Transports accepting the inbound connections on `listen_on`.
+    ///
+    /// The outbound connections to the peers advertising [`NODE_QUIC`] are made over QUIC
+    /// if it's enabled here, falling back to TCP.
+    pub listen_transports: Vec<Transport>,
+    /// Whether to support the BIP-324 v2 encrypted transport over TCP.
+    pub v2_transport: bool,
+    /// Whether to map the TCP listen port on the gateway with NAT-PMP or UPnP.
+    pub port_mapping: bool,
+    /// SAM bridge of the I2P router, the I2P peers are not connected if `None`.
+    pub i2p_sam: Option<SocketAddr>,
+    /// Whether the CJDNS peers are reachable through the local CJDNS node.
+    pub cjdns_reachable: bool,
     /// List of seednodes.
     pub seednodes: Vec<String>,
     /// Whether to connect to the seednode only.
     pub seednode_only: bool,
     /// Whether to accept the peer in ipv4 only.
     pub ipv4_only: bool,
-    /// Maximum number of outbound peer connections.
+    /// Number of automatic outbound full-relay connections.
     pub max_outbound_peers: usize,
+    /// Number of automatic outbound block-relay-only connections.
+    pub max_block_relay_only_peers: usize,
     /// Maximum number of inbound peer connections.
     pub max_inbound_peers: usize,
+    /// Connect to these peers only, the automatic outbound connections and the seednodes
+    /// are disabled if not empty.
+    pub connect: Vec<String>,
+    /// Peers to maintain the connections with, in addition to the automatic ones.
+    pub addnode: Vec<String>,
     /// Major sync strategy.
     pub sync_strategy: SyncStrategy,
+    /// Snapshot serving and syncing, disabled if `None`.
+    pub snapshot: Option<SnapshotParams>,
+    /// Database persisting the known peer addresses and the anchors across restarts.
+    pub db: Option<SubcoinDb>,
+    /// Maximum outbound bytes per 24 hours, the snapshots are no longer served once reached.
+    pub max_upload_target: Option<u64>,
+    /// Maximum outbound bytes per second, over all the connections.
+    pub max_upload_rate: Option<u64>,
+    /// Maximum inbound bytes per second, over all the connections.
+    pub max_download_rate: Option<u64>,
+    /// Maximum messages per second accepted from a peer, the excess ones are dropped.
+    pub max_peer_message_rate: Option<u32>,
+    /// Maximum bytes of the blocks downloaded but not imported yet, the block download is
+    /// paused once exceeded.
+    pub import_memory_budget: usize,
+    /// Updated with the best block number announced by the peers.
+    pub network_tip: Option<Arc<AtomicU32>>,
+    /// File the unconfirmed transactions are saved to on shutdown, see [`load_mempool`].
+    pub mempool_path: Option<PathBuf>,
+    /// Whether to replace the unconfirmed transactions not signaling the BIP125
+    /// replaceability.
+    pub full_rbf: bool,
+    /// Confirmed outputs used to compute the fees of the unconfirmed transactions, the
+    /// transactions with unknown fees can be neither replaced nor used as replacements.
+    pub utxo_provider: Option<Arc<dyn UtxoProvider>>,
+    /// Stop the sync once the block is imported, `None` to keep syncing.
+    pub stop_at: Option<StopAt>,
+    /// Number of threads verifying the downloaded headers, all the cores if `None`.
+    pub verification_threads: Option<usize>,
+    /// Period without the best block advancing while the peers are ahead after which the
+    /// sync is reported as stalled, never if `None`.
+    pub sync_stall_timeout: Option<Duration>,
+    /// Whether to switch to another sync peer and disconnect the current one once stalled.
+    pub rotate_stalled_sync_peer: bool,
+    /// Whether to disable the transaction relay, the peers are asked not to announce the
+    /// transactions and the relayed ones are ignored.
+    ///
+    /// The local transactions are still broadcast.
+    pub blocks_only: bool,
+}
+
+/// Params of a custom Bitcoin-like network.
+#[derive(Debug, Clone)]
+pub struct CustomNetworkParams {
+    /// Message start of the P2P messages.
+    pub magic: Magic,
+    /// Consensus params verifying the downloaded headers.
+    pub chain_params: ChainParams,
+    /// Seednodes of the network, used instead of the built-in ones.
+    pub seednodes: Vec<String>,
+}
+
+/// Snapshot params.
+pub struct SnapshotParams {
+    /// Snapshot storage.
+    pub store: Arc<dyn SnapshotStore>,
+    /// Whether to serve the snapshots to the peers.
+    pub serve: bool,
+    /// Start the snapshot sync on a fresh node once the same snapshot is announced by
+    /// this number of peers.
+    pub sync_quorum: Option<usize>,
+    /// zstd compression level of the chunks served to the peers supporting the compressed
+    /// chunks, uncompressed if `None`.
+    pub chunk_compression_level: Option<i32>,
 }
 
 fn builtin_seednodes(network: BitcoinNetwork) -> &'static [&'static str] {
@@ -359,6 +848,49 @@ fn builtin_seednodes(network: BitcoinNetwork) -> &'static [&'static str] {
     }
 }
 
+/// Resolves the `host:port` addresses, the failed ones are logged and skipped.
+async fn resolve_addresses(hosts: Vec<String>) -> Vec<PeerId> {
+    // Create a vector of futures for DNS lookups
+    let lookup_futures = hosts.into_iter().map(|host| async move {
+        tokio::net::lookup_host(&host).await.map(|mut addrs| {
+            addrs
+                .next()
+                .ok_or_else(|| Error::InvalidBootnode(host.to_string()))
+        })
+    });
+
+    // Await all futures concurrently
+    let lookup_results = futures::future::join_all(lookup_futures).await;
+
+    lookup_results
+        .into_iter()
+        .filter_map(|result| match result {
+            Ok(Ok(addr)) => Some(addr),
+            Ok(Err(e)) => {
+                tracing::error!("Failed to resolve peer address: {e}");
+                None
+            }
+            Err(e) => {
+                tracing::error!("Failed to perform peer DNS lookup: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns whether another inbound peer can be accepted, `None` if the worker has stopped.
+async fn request_inbound_slot(
+    worker_msg_sender: &TracingUnboundedSender<NetworkWorkerMessage>,
+) -> Option<bool> {
+    let (sender, receiver) = oneshot::channel();
+
+    worker_msg_sender
+        .unbounded_send(NetworkWorkerMessage::RequestInboundSlot(sender))
+        .ok()?;
+
+    receiver.await.ok()
+}
+
 /// Represents the network component.
 pub struct Network<Block, Client> {
     client: Arc<Client>,
@@ -426,78 +958,217 @@ where
             _phantom,
         } = self;
 
+        let mut params = params;
+
         let mut listen_on = params.listen_on;
-        let listener = match TcpListener::bind(&listen_on).await {
-            Ok(listener) => listener,
-            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
-                tracing::warn!("{listen_on} is occupied, trying any available port.");
-                listen_on.set_port(0);
-                TcpListener::bind(listen_on).await?
-            }
-            Err(err) => return Err(err.into()),
+
+        let tcp_listener = if params.listen_transports.contains(&Transport::Tcp) {
+            let listener = match TcpListener::bind(&listen_on).await {
+                Ok(listener) => listener,
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                    tracing::warn!("{listen_on} is occupied, trying any available port.");
+                    listen_on.set_port(0);
+                    TcpListener::bind(listen_on).await?
+                }
+                Err(err) => return Err(err.into()),
+            };
+            // QUIC shares the port with TCP.
+            listen_on = listener.local_addr()?;
+            Some(listener)
+        } else {
+            None
+        };
+
+        let quic_endpoint = if params.listen_transports.contains(&Transport::Quic) {
+            let endpoint = match QuicEndpoint::bind(listen_on) {
+                Ok(endpoint) => endpoint,
+                Err(Error::IO(e)) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                    tracing::warn!("{listen_on} (UDP) is occupied, trying any available port.");
+                    listen_on.set_port(0);
+                    QuicEndpoint::bind(listen_on)?
+                }
+                Err(err) => return Err(err),
+            };
+            Some(endpoint)
+        } else {
+            None
         };
 
         let (network_event_sender, network_event_receiver) = tokio::sync::mpsc::unbounded_channel();
 
-        let bandwidth = Bandwidth::default();
+        let bandwidth = Bandwidth {
+            download_limiter: params.max_download_rate.map(RateLimiter::new),
+            upload_limiter: params.max_upload_rate.map(RateLimiter::new),
+            ..Default::default()
+        };
+
+        let i2p_destinations = I2pDestinations::default();
+
+        let (magic, chain_params, custom_seednodes) = match params.custom_network.take() {
+            Some(CustomNetworkParams {
+                magic,
+                chain_params,
+                seednodes,
+            }) => (magic, chain_params, Some(seednodes)),
+            None => (
+                params.network.magic(),
+                ChainParams::new(params.network),
+                None,
+            ),
+        };
 
         let connection_initiator = ConnectionInitiator::new(
-            params.network,
+            magic,
             network_event_sender,
             spawn_handle.clone(),
             bandwidth.clone(),
             params.ipv4_only,
+            quic_endpoint.clone(),
+            params.v2_transport,
+            params.i2p_sam,
+            i2p_destinations.clone(),
         );
 
+        // `--connect` disables the automatic outbound connections like in Bitcoin Core.
+        let connect_only = !params.connect.is_empty();
+
+        let (max_outbound_peers, max_block_relay_only_peers) = if connect_only {
+            (0, 0)
+        } else {
+            (params.max_outbound_peers, params.max_block_relay_only_peers)
+        };
+
+        let manual_peers = resolve_addresses(
+            std::mem::take(&mut params.connect)
+                .into_iter()
+                .chain(std::mem::take(&mut params.addnode))
+                .collect(),
+        )
+        .await;
+
         let network_worker = NetworkWorker::new(
             worker::Params {
                 client: client.clone(),
+                chain_params,
                 network_event_receiver,
                 import_queue,
                 sync_strategy: params.sync_strategy,
                 is_major_syncing,
                 connection_initiator: connection_initiator.clone(),
-                max_outbound_peers: params.max_outbound_peers,
+                max_outbound_peers,
+                max_block_relay_only_peers,
+                max_inbound_peers: params.max_inbound_peers,
+                manual_peers,
+                db: params.db.take(),
+                upload_target: params.max_upload_target.map(|target| {
+                    UploadTarget::new(target, bandwidth.total_bytes_outbound.clone())
+                }),
+                max_peer_message_rate: params.max_peer_message_rate,
+                import_memory_budget: params.import_memory_budget,
+                snapshot: params.snapshot.take(),
+                network_tip: params.network_tip.take(),
+                mempool_path: params.mempool_path.take(),
+                full_rbf: params.full_rbf,
+                utxo_provider: params.utxo_provider.take(),
+                stop_at: params.stop_at,
+                verification_threads: params.verification_threads,
+                listen_port: tcp_listener
+                    .as_ref()
+                    .filter(|_| params.max_inbound_peers > 0)
+                    .map(|_| listen_on.port()),
+                cjdns_reachable: params.cjdns_reachable,
+                i2p_destinations: params.i2p_sam.map(|_| i2p_destinations),
+                sync_stall_timeout: params.sync_stall_timeout,
+                rotate_stalled_sync_peer: params.rotate_stalled_sync_peer,
+                blocks_only: params.blocks_only,
             },
             registry.as_ref(),
         );
 
-        spawn_handle.spawn("inbound-connection", None, {
-            let local_addr = listener.local_addr()?;
-            let connection_initiator = connection_initiator.clone();
-            let max_inbound_peers = params.max_inbound_peers;
+        if params.port_mapping && tcp_listener.is_some() {
+            spawn_handle.spawn(
+                "port-mapping",
+                None,
+                port_mapping::run(listen_on.port(), worker_msg_sender.clone()),
+            );
+        }
+
+        if let Some(listener) = tcp_listener {
+            spawn_handle.spawn("inbound-connection", None, {
+                let local_addr = listener.local_addr()?;
+                let connection_initiator = connection_initiator.clone();
+                let worker_msg_sender = worker_msg_sender.clone();
+
+                async move {
+                    tracing::info!("🔊 Listening on {local_addr:?}",);
 
-            async move {
-                tracing::info!("🔊 Listening on {local_addr:?}",);
+                    while let Ok((socket, peer_addr)) = listener.accept().await {
+                        let Some(accept) = request_inbound_slot(&worker_msg_sender).await else {
+                            return;
+                        };
 
-                while let Ok((socket, peer_addr)) = listener.accept().await {
-                    let (sender, receiver) = oneshot::channel();
+                        if accept {
+                            tracing::debug!(?peer_addr, "New peer accepted");
 
-                    if worker_msg_sender
-                        .unbounded_send(NetworkWorkerMessage::InboundPeersCount(sender))
-                        .is_err()
-                    {
-                        return;
+                            let result = transport::PeerStream::tcp(socket)
+                                .map_err(Error::from)
+                                .and_then(|stream| {
+                                    connection_initiator.initiate_inbound_connection(stream)
+                                });
+
+                            if let Err(err) = result {
+                                tracing::debug!(
+                                    ?err,
+                                    ?peer_addr,
+                                    "Failed to initiate inbound connection"
+                                );
+                            }
+                        }
                     }
+                }
+            });
+        }
+
+        if let Some(endpoint) = quic_endpoint {
+            spawn_handle.spawn("inbound-quic-connection", None, {
+                let local_addr = endpoint.local_addr()?;
+                let connection_initiator = connection_initiator.clone();
 
-                    let Ok(inbound_peers_count) = receiver.await else {
-                        return;
-                    };
+                async move {
+                    tracing::info!("🔊 Listening on {local_addr:?} (QUIC)");
 
-                    if inbound_peers_count < max_inbound_peers {
-                        tracing::debug!(?peer_addr, "New peer accepted");
+                    while let Some(result) = endpoint.accept().await {
+                        let stream = match result {
+                            Ok(stream) => stream,
+                            Err(err) => {
+                                tracing::debug!(?err, "Failed to accept QUIC connection");
+                                continue;
+                            }
+                        };
 
-                        if let Err(err) = connection_initiator.initiate_inbound_connection(socket) {
-                            tracing::debug!(
-                                ?err,
-                                ?peer_addr,
-                                "Failed to initiate inbound connection"
-                            );
+                        let Some(accept) = request_inbound_slot(&worker_msg_sender).await else {
+                            return;
+                        };
+
+                        if accept {
+                            let peer_addr = stream.peer_addr;
+
+                            tracing::debug!(?peer_addr, "New peer accepted over QUIC");
+
+                            if let Err(err) =
+                                connection_initiator.initiate_inbound_connection(stream)
+                            {
+                                tracing::debug!(
+                                    ?err,
+                                    ?peer_addr,
+                                    "Failed to initiate inbound connection"
+                                );
+                            }
                         }
                     }
                 }
-            }
-        });
+            });
+        }
 
         let Params {
             seednode_only,
@@ -506,36 +1177,18 @@ where
             ..
         } = params;
 
-        let mut bootnodes = seednodes;
+        let mut bootnodes = if connect_only { Vec::new() } else { seednodes };
 
-        if !seednode_only {
-            bootnodes.extend(builtin_seednodes(network).iter().map(|s| s.to_string()));
+        if !seednode_only && !connect_only {
+            match custom_seednodes {
+                Some(custom_seednodes) => bootnodes.extend(custom_seednodes),
+                None => bootnodes.extend(builtin_seednodes(network).iter().map(|s| s.to_string())),
+            }
         }
 
-        // Create a vector of futures for DNS lookups
-        let lookup_futures = bootnodes.into_iter().map(|bootnode| async move {
-            tokio::net::lookup_host(&bootnode).await.map(|mut addrs| {
-                addrs
-                    .next()
-                    .ok_or_else(|| Error::InvalidBootnode(bootnode.to_string()))
-            })
-        });
-
-        // Await all futures concurrently
-        let lookup_results = futures::future::join_all(lookup_futures).await;
-
-        for result in lookup_results {
-            match result {
-                Ok(Ok(addr)) => {
-                    connection_initiator.initiate_outbound_connection(addr);
-                }
-                Ok(Err(e)) => {
-                    tracing::error!("Failed to resolve bootnode address: {e}");
-                }
-                Err(e) => {
-                    tracing::error!("Failed to perform bootnode DNS lookup: {e}");
-                }
-            }
+        for addr in resolve_addresses(bootnodes).await {
+            // The services of the seednodes are unknown.
+            connection_initiator.initiate_outbound_connection(addr, ServiceFlags::NONE);
         }
 
         network_worker.run(worker_msg_receiver, bandwidth).await;
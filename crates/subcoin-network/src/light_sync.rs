@@ -0,0 +1,246 @@
+//! Headers-only sync of the light client.
+//!
+//! The headers are downloaded from a single peer with `getheaders`, verified (proof of work,
+//! difficulty retargets, median time past) and inserted into the [`LightChain`]. No block is
+//! downloaded, the sync is completed once the peer returns less than a full batch.
+
+use crate::sync::{LocatorRequest, SyncAction, SyncRequest};
+use crate::{Error, PeerId, SyncStatus};
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use sc_client_api::AuxStore;
+use sc_consensus_nakamoto::HeaderVerifier;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use subcoin_primitives::LightChain;
+
+// https://developer.bitcoin.org/reference/p2p_networking.html#headers
+const MAX_HEADERS_SIZE: usize = 2000;
+
+/// Time to wait for the `headers` response before restarting with another peer.
+const HEADERS_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Headers-only sync from a peer.
+pub(crate) struct LightSync<Block, Client> {
+    light_chain: LightChain<Block, Client>,
+    header_verifier: HeaderVerifier<Block, Client>,
+    peer_id: PeerId,
+    target_block_number: u32,
+    /// Time of the pending headers request, `None` if the next request is yet to be sent.
+    requested_at: Option<Instant>,
+    completed: bool,
+}
+
+impl<Block, Client> LightSync<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    /// Constructs a new instance of [`LightSync`].
+    pub(crate) fn new(
+        client: Arc<Client>,
+        header_verifier: HeaderVerifier<Block, Client>,
+        peer_id: PeerId,
+        target_block_number: u32,
+    ) -> Self {
+        Self {
+            light_chain: LightChain::new(client),
+            header_verifier,
+            peer_id,
+            target_block_number,
+            requested_at: None,
+            completed: false,
+        }
+    }
+
+    pub(crate) fn sync_status(&self) -> SyncStatus {
+        SyncStatus::Downloading {
+            target: self.target_block_number,
+            peers: vec![self.peer_id],
+        }
+    }
+
+    pub(crate) fn sync_peer(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Returns `true` if the peer has no more headers to serve.
+    pub(crate) fn is_completed(&self) -> bool {
+        self.completed
+    }
+
+    pub(crate) fn requests_in_flight(&self, peer_id: PeerId) -> usize {
+        usize::from(peer_id == self.peer_id && self.requested_at.is_some())
+    }
+
+    pub(crate) fn update_sync_peer(&mut self, peer_id: PeerId, target_block_number: u32) {
+        self.peer_id = peer_id;
+        self.target_block_number = target_block_number;
+        self.requested_at = None;
+    }
+
+    pub(crate) fn restart(&mut self, new_peer: PeerId, peer_best: u32) {
+        self.update_sync_peer(new_peer, peer_best);
+        self.completed = false;
+    }
+
+    pub(crate) fn on_tick(&mut self) -> SyncAction {
+        if self.completed {
+            return SyncAction::None;
+        }
+
+        match self.requested_at {
+            None => self.headers_request_action(),
+            Some(requested_at) if requested_at.elapsed() > HEADERS_REQUEST_TIMEOUT => {
+                SyncAction::RestartSyncWithStalledPeer(self.peer_id)
+            }
+            Some(_) => SyncAction::None,
+        }
+    }
+
+    /// Requests the headers following the tip of the light chain.
+    pub(crate) fn headers_request_action(&mut self) -> SyncAction {
+        self.requested_at.replace(Instant::now());
+
+        SyncAction::Request(SyncRequest::Headers(LocatorRequest {
+            locator_hashes: self.light_chain.block_locator().locator_hashes,
+            stop_hash: BlockHash::all_zeros(),
+            from: self.peer_id,
+        }))
+    }
+
+    /// Verifies and inserts the headers, either solicited or announced by the sync peer.
+    pub(crate) fn on_headers(&mut self, headers: Vec<BitcoinHeader>, from: PeerId) -> SyncAction {
+        if from != self.peer_id {
+            return SyncAction::None;
+        }
+
+        if headers.len() > MAX_HEADERS_SIZE {
+            return SyncAction::Disconnect(from, Error::TooManyHeaders);
+        }
+
+        let is_full_batch = headers.len() == MAX_HEADERS_SIZE;
+
+        self.requested_at.take();
+
+        let Some(first_header) = headers.first() else {
+            self.completed = true;
+            return SyncAction::None;
+        };
+
+        // The announced headers may not connect to our tip, fetch the missing ones.
+        if self
+            .light_chain
+            .entry(first_header.prev_blockhash)
+            .is_none()
+        {
+            return self.headers_request_action();
+        }
+
+        let mut prev_hash = first_header.prev_blockhash;
+        let mut tip_updated = false;
+
+        for header in headers {
+            let block_hash = header.block_hash();
+
+            if header.prev_blockhash != prev_hash {
+                return SyncAction::Disconnect(from, Error::HeadersNotInAscendingOrder);
+            }
+            prev_hash = block_hash;
+
+            if self.light_chain.entry(block_hash).is_some() {
+                continue;
+            }
+
+            if let Err(err) = self.header_verifier.verify_header(&header) {
+                tracing::debug!(?block_hash, ?err, "Received invalid header, disconnecting");
+                return SyncAction::Disconnect(from, Error::BadHeader(block_hash, err));
+            }
+
+            match self.light_chain.insert(header) {
+                Ok(is_new_tip) => tip_updated |= is_new_tip,
+                Err(err) => {
+                    tracing::error!(?block_hash, ?err, "Failed to insert header");
+                    return SyncAction::None;
+                }
+            }
+        }
+
+        if tip_updated {
+            let tip = self.light_chain.tip();
+            tracing::debug!(
+                "📄 Synced headers ({}/{}), tip: {}",
+                tip.height,
+                self.target_block_number.max(tip.height),
+                tip.header.block_hash()
+            );
+        }
+
+        if is_full_batch {
+            self.headers_request_action()
+        } else {
+            self.completed = true;
+            SyncAction::None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::merkle_tree::MerkleBlock;
+    use sc_consensus_nakamoto::ChainParams;
+    use subcoin_test_service::block_data;
+
+    #[test]
+    fn test_light_sync() {
+        let runtime = tokio::runtime::Runtime::new().expect("Create tokio runtime");
+        let subcoin_service::NodeComponents { client, .. } =
+            subcoin_test_service::new_test_node(runtime.handle().clone())
+                .expect("Create test node");
+
+        let peer_id: PeerId = "1.2.3.4:8333".parse().unwrap();
+        let header_verifier =
+            HeaderVerifier::new(client.clone(), ChainParams::new(bitcoin::Network::Bitcoin));
+        let mut light_sync = LightSync::new(client.clone(), header_verifier, peer_id, 3);
+
+        let blocks = block_data();
+        let headers = blocks[1..]
+            .iter()
+            .map(|block| block.header)
+            .collect::<Vec<_>>();
+
+        let mut bad_header = headers[0];
+        bad_header.nonce += 1;
+        assert!(matches!(
+            light_sync.on_headers(vec![bad_header], peer_id),
+            SyncAction::Disconnect(_, Error::BadHeader(..))
+        ));
+
+        assert!(matches!(
+            light_sync.on_headers(headers.clone(), peer_id),
+            SyncAction::None
+        ));
+        assert!(light_sync.is_completed());
+        // No block is imported.
+        assert_eq!(client.info().best_number, 0);
+
+        let light_chain = LightChain::new(client);
+        assert_eq!(light_chain.tip().height, 3);
+        assert_eq!(light_chain.best_hash(2), Some(blocks[2].block_hash()));
+        assert_eq!(light_chain.best_hash(4), None);
+
+        let coinbase = blocks[2].txdata[0].compute_txid();
+        let merkle_block =
+            MerkleBlock::from_header_txids_with_predicate(&blocks[2].header, &[coinbase], |txid| {
+                *txid == coinbase
+            });
+        let inclusion = light_chain.verify_tx_out_proof(&merkle_block).unwrap();
+        assert_eq!(inclusion.height, 2);
+        assert_eq!(inclusion.confirmations, 2);
+        assert_eq!(inclusion.txids, vec![coinbase]);
+    }
+}
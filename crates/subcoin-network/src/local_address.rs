@@ -0,0 +1,106 @@
+//! Discovery of the external address of the local node.
+//!
+//! Each outbound peer reports the address it sees us connecting from in its `version`
+//! message. The address mapped on the gateway takes precedence, otherwise the routable IP
+//! reported by the most peers is used with the local listen port.
+
+use crate::address_book::netgroup;
+use crate::PeerId;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Minimum number of the outbound peers which have to report the same IP before it's
+/// advertised, a single peer can't make us advertise an arbitrary address.
+const MIN_REPORTING_PEERS: usize = 2;
+
+/// External addresses of the local node.
+#[derive(Debug)]
+pub(crate) struct LocalAddresses {
+    /// Port accepting the inbound TCP connections, nothing is advertised if `None`.
+    listen_port: Option<u16>,
+    /// Address mapped on the gateway.
+    mapped: Option<PeerId>,
+    /// Our IP reported by the connected outbound peers.
+    reported: HashMap<PeerId, IpAddr>,
+}
+
+impl LocalAddresses {
+    pub(crate) fn new(listen_port: Option<u16>) -> Self {
+        Self {
+            listen_port,
+            mapped: None,
+            reported: HashMap::new(),
+        }
+    }
+
+    /// Records our address as seen by the outbound peer.
+    pub(crate) fn on_reported(&mut self, peer_id: PeerId, addr: PeerId) {
+        if netgroup(&addr).is_some() {
+            self.reported.insert(peer_id, addr.ip().to_canonical());
+        }
+    }
+
+    pub(crate) fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.reported.remove(peer_id);
+    }
+
+    pub(crate) fn set_mapped(&mut self, mapped: Option<PeerId>) {
+        self.mapped = mapped;
+    }
+
+    /// Returns the address to advertise to the peers.
+    pub(crate) fn best(&self) -> Option<PeerId> {
+        let listen_port = self.listen_port?;
+
+        if let Some(mapped) = self.mapped.filter(|mapped| netgroup(mapped).is_some()) {
+            return Some(mapped);
+        }
+
+        let mut counts = HashMap::<IpAddr, usize>::new();
+        for ip in self.reported.values() {
+            *counts.entry(*ip).or_default() += 1;
+        }
+
+        counts
+            .into_iter()
+            .filter(|(_, count)| *count >= MIN_REPORTING_PEERS)
+            .max_by_key(|(ip, count)| (*count, *ip))
+            .map(|(ip, _)| PeerId::new(ip, listen_port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_best_local_address() {
+        let peer = |i: u8| PeerId::from(([8, 8, 8, i], 8333));
+        let reported = |ip: [u8; 4]| PeerId::from((ip, 51234));
+
+        let mut local_addresses = LocalAddresses::new(Some(8333));
+        local_addresses.on_reported(peer(1), reported([1, 2, 3, 4]));
+        assert_eq!(local_addresses.best(), None);
+
+        // Private addresses are ignored.
+        local_addresses.on_reported(peer(2), reported([192, 168, 1, 2]));
+        assert_eq!(local_addresses.best(), None);
+
+        local_addresses.on_reported(peer(3), reported([1, 2, 3, 4]));
+        assert_eq!(
+            local_addresses.best(),
+            Some("1.2.3.4:8333".parse().unwrap())
+        );
+
+        local_addresses.remove_peer(&peer(3));
+        assert_eq!(local_addresses.best(), None);
+
+        local_addresses.set_mapped(Some("5.6.7.8:18333".parse().unwrap()));
+        assert_eq!(
+            local_addresses.best(),
+            Some("5.6.7.8:18333".parse().unwrap())
+        );
+
+        assert_eq!(LocalAddresses::new(None).best(), None);
+    }
+}
@@ -0,0 +1,126 @@
+//! Persistence of the unconfirmed transactions across restarts.
+//!
+//! The transactions tracked by the transaction manager are written on shutdown in the format
+//! of `mempool.dat` version 1 in Bitcoin Core, without any fee delta or unbroadcast
+//! transaction. They are revalidated against the best block before being loaded again.
+
+use crate::Error;
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::Transaction;
+use std::path::Path;
+
+/// Version of the file format, same as the one without the XOR obfuscation in Bitcoin Core.
+const MEMPOOL_DUMP_VERSION: u64 = 1;
+
+/// Persisted transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolEntry {
+    pub transaction: Transaction,
+    /// UNIX timestamp in seconds at which the transaction was received.
+    pub time: i64,
+}
+
+fn encode_mempool(entries: &[MempoolEntry]) -> Vec<u8> {
+    fn encode(data: &mut Vec<u8>, value: &impl Encodable) {
+        value
+            .consensus_encode(data)
+            .expect("Writing to a vec never fails; qed");
+    }
+
+    let mut data = Vec::new();
+
+    encode(&mut data, &MEMPOOL_DUMP_VERSION);
+    encode(&mut data, &(entries.len() as u64));
+
+    for entry in entries {
+        encode(&mut data, &entry.transaction);
+        encode(&mut data, &entry.time);
+        // Fee delta.
+        encode(&mut data, &0i64);
+    }
+
+    // Fee deltas of the transactions not in the mempool and the unbroadcast transactions.
+    encode(&mut data, &VarInt(0));
+    encode(&mut data, &VarInt(0));
+
+    data
+}
+
+fn decode_mempool(mut data: &[u8]) -> Result<Vec<MempoolEntry>, Error> {
+    let version = u64::consensus_decode(&mut data)?;
+
+    if version != MEMPOOL_DUMP_VERSION {
+        return Err(Error::Other(format!(
+            "Unsupported mempool.dat version {version}"
+        )));
+    }
+
+    let count = u64::consensus_decode(&mut data)?;
+
+    // The rest of the file is not used.
+    (0..count)
+        .map(|_| {
+            let transaction = Transaction::consensus_decode(&mut data)?;
+            let time = i64::consensus_decode(&mut data)?;
+            let _fee_delta = i64::consensus_decode(&mut data)?;
+            Ok(MempoolEntry { transaction, time })
+        })
+        .collect()
+}
+
+/// Writes the transactions to `path`, replacing the existing file.
+pub(crate) fn save_mempool(path: &Path, entries: &[MempoolEntry]) -> std::io::Result<()> {
+    // Written to a temporary file first so that the old file is intact if the node crashes
+    // in the middle of writing.
+    let tmp_path = path.with_extension("dat.new");
+    std::fs::write(&tmp_path, encode_mempool(entries))?;
+    std::fs::rename(tmp_path, path)
+}
+
+/// Reads the transactions from `path`, returns an empty list if the file does not exist.
+pub fn load_mempool(path: &Path) -> Result<Vec<MempoolEntry>, Error> {
+    match std::fs::read(path) {
+        Ok(data) => decode_mempool(&data),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, TxIn, TxOut};
+
+    #[test]
+    fn test_mempool_roundtrip() {
+        let transaction = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(bitcoin::Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey: ScriptBuf::new_op_return([0u8; 4]),
+            }],
+        };
+
+        let entries = vec![MempoolEntry {
+            transaction,
+            time: 1_700_000_000,
+        }];
+
+        let data = encode_mempool(&entries);
+        assert_eq!(decode_mempool(&data).unwrap(), entries);
+        assert!(decode_mempool(&encode_mempool(&[])).unwrap().is_empty());
+
+        let mut data = data;
+        data[0] = 2;
+        assert!(decode_mempool(&data).is_err());
+    }
+}
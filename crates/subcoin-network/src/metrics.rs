@@ -1,5 +1,7 @@
-use substrate_prometheus_endpoint::prometheus::IntCounterVec;
-use substrate_prometheus_endpoint::{register, GaugeVec, Opts, PrometheusError, Registry, U64};
+use substrate_prometheus_endpoint::prometheus::{IntCounter, IntCounterVec};
+use substrate_prometheus_endpoint::{
+    register, Gauge, GaugeVec, Opts, PrometheusError, Registry, U64,
+};
 
 #[derive(Clone)]
 pub struct Metrics {
@@ -8,6 +10,9 @@ pub struct Metrics {
     pub(crate) connected_peers: GaugeVec<U64>,
     pub(crate) messages_received: IntCounterVec,
     pub(crate) messages_sent: IntCounterVec,
+    pub(crate) sync_stalled: Gauge<U64>,
+    pub(crate) sync_stalls: IntCounter,
+    pub(crate) sync_seconds_since_progress: Gauge<U64>,
 }
 
 impl Metrics {
@@ -55,6 +60,27 @@ impl Metrics {
                 )?,
                 registry,
             )?,
+            sync_stalled: register(
+                Gauge::new(
+                    "subcoin_sync_stalled",
+                    "Whether the sync is stalled while the peers report higher tips",
+                )?,
+                registry,
+            )?,
+            sync_stalls: register(
+                IntCounter::new(
+                    "subcoin_sync_stalls_total",
+                    "Number of the sync stalls detected",
+                )?,
+                registry,
+            )?,
+            sync_seconds_since_progress: register(
+                Gauge::new(
+                    "subcoin_sync_seconds_since_progress",
+                    "Seconds since the best block last advanced while behind the peers",
+                )?,
+                registry,
+            )?,
         })
     }
 }
@@ -0,0 +1,135 @@
+//! Network time offset sampled from the timestamps in the `version` messages of the outbound
+//! peers, following Bitcoin Core.
+//!
+//! <https://github.com/bitcoin/bitcoin/blob/v27.0/src/timedata.cpp>
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+/// Maximum number of time samples.
+const MAX_TIME_SAMPLES: usize = 200;
+
+/// Minimum number of time samples before the offset is adjusted.
+const MIN_TIME_SAMPLES: usize = 5;
+
+/// Maximum allowed network time adjustment in seconds (70 minutes).
+const MAX_TIME_ADJUSTMENT: i64 = 70 * 60;
+
+/// Peers within 5 minutes of our local clock are considered to agree with us.
+const LOCAL_CLOCK_TOLERANCE: i64 = 5 * 60;
+
+/// Tracks the median time offset of the peers.
+#[derive(Debug)]
+pub(crate) struct NetworkTime {
+    /// Peers already sampled, one sample per IP.
+    sampled: HashSet<IpAddr>,
+    /// Time offsets of the peers, including our own zero offset.
+    offsets: Vec<i64>,
+    /// Current network time offset in seconds.
+    offset: i64,
+    /// Whether the warning about the local clock has been emitted.
+    warned: bool,
+}
+
+impl NetworkTime {
+    pub(crate) fn new() -> Self {
+        Self {
+            sampled: HashSet::new(),
+            offsets: vec![0],
+            offset: 0,
+            warned: false,
+        }
+    }
+
+    /// Adds the time offset of a peer.
+    ///
+    /// Returns the new network time offset if it's recalculated.
+    pub(crate) fn add_sample(&mut self, ip: IpAddr, offset: i64) -> Option<i64> {
+        if self.offsets.len() >= MAX_TIME_SAMPLES || !self.sampled.insert(ip) {
+            return None;
+        }
+
+        self.offsets.push(offset);
+
+        // Only recalculate with an odd number of samples so that the median is well-defined.
+        if self.offsets.len() < MIN_TIME_SAMPLES || self.offsets.len() % 2 == 0 {
+            return None;
+        }
+
+        let mut sorted = self.offsets.clone();
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2];
+
+        if median.abs() <= MAX_TIME_ADJUSTMENT {
+            self.offset = median;
+        } else {
+            self.offset = 0;
+
+            let any_peer_agrees = sorted
+                .iter()
+                .any(|offset| *offset != 0 && offset.abs() < LOCAL_CLOCK_TOLERANCE);
+
+            if !any_peer_agrees && !self.warned {
+                self.warned = true;
+                tracing::warn!(
+                    "⚠️ Please check that your computer's date and time are correct! \
+                    The median time of the peers differs from the local clock by {median} seconds"
+                );
+            }
+        }
+
+        tracing::debug!(
+            samples = self.offsets.len(),
+            median,
+            "Network time offset: {}s",
+            self.offset
+        );
+
+        Some(self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(i: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, i])
+    }
+
+    #[test]
+    fn test_network_time_offset() {
+        let mut network_time = NetworkTime::new();
+
+        for (i, offset) in [10, 20, 30].into_iter().enumerate() {
+            assert_eq!(network_time.add_sample(ip(i as u8), offset), None);
+        }
+
+        // Duplicate peer is ignored.
+        assert_eq!(network_time.add_sample(ip(0), 1000), None);
+
+        // Samples: [0, 10, 20, 30, 40].
+        assert_eq!(network_time.add_sample(ip(3), 40), Some(20));
+
+        // Even number of samples does not trigger the recalculation.
+        assert_eq!(network_time.add_sample(ip(4), 50), None);
+
+        // Samples: [0, 10, 20, 30, 40, 50, 60].
+        assert_eq!(network_time.add_sample(ip(5), 60), Some(30));
+    }
+
+    #[test]
+    fn test_network_time_offset_too_large() {
+        let mut network_time = NetworkTime::new();
+
+        for i in 0..3 {
+            network_time.add_sample(ip(i), 2 * MAX_TIME_ADJUSTMENT);
+        }
+
+        assert_eq!(
+            network_time.add_sample(ip(3), 2 * MAX_TIME_ADJUSTMENT),
+            Some(0)
+        );
+        assert!(network_time.warned);
+    }
+}
@@ -2,14 +2,30 @@
 
 use bitcoin::{Block as BitcoinBlock, BlockHash};
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Maximum total size of the orphan blocks in bytes.
+const MAX_ORPHAN_BLOCKS_SIZE: usize = 256 * 1024 * 1024;
+
+/// Orphan blocks whose parent has not been received in this period are discarded.
+pub(crate) const ORPHAN_BLOCK_EXPIRY: Duration = Duration::from_secs(20 * 60);
+
+#[derive(Debug, Clone)]
+struct OrphanBlock {
+    block: BitcoinBlock,
+    received_at: Instant,
+}
 
 /// Storage for the blocks without parent yet.
 ///
 /// Blocks from this storage are either moved to verification queue, or removed at all.
+///
+/// The pool is bounded by [`MAX_ORPHAN_BLOCKS_SIZE`], the unknown blocks are evicted first
+/// as the requested ones are expected to be connected soon.
 #[derive(Debug, Clone)]
 pub struct OrphanBlocksPool {
     /// Mapping of the block hash to the full block data.
-    blocks: HashMap<BlockHash, BitcoinBlock>,
+    blocks: HashMap<BlockHash, OrphanBlock>,
     /// Blocks with unknown parents. They may be block announcement or received out-of-order.
     ///
     /// block_hash => Vec<child_block_hash>
@@ -18,15 +34,25 @@ pub struct OrphanBlocksPool {
     orphan_blocks: HashMap<BlockHash, HashSet<BlockHash>>,
     /// Blocks that we have received but we didn't ask for.
     unknown_blocks: HashSet<BlockHash>,
+    /// Total size of the blocks in bytes.
+    total_size: usize,
+    /// Maximum total size of the blocks in bytes.
+    max_size: usize,
 }
 
 impl OrphanBlocksPool {
     /// Constructs a new [`OrphanBlocksPool`].
     pub(crate) fn new() -> Self {
+        Self::with_max_size(MAX_ORPHAN_BLOCKS_SIZE)
+    }
+
+    fn with_max_size(max_size: usize) -> Self {
         Self {
             blocks: HashMap::new(),
             orphan_blocks: HashMap::new(),
             unknown_blocks: HashSet::new(),
+            total_size: 0,
+            max_size,
         }
     }
 
@@ -35,6 +61,11 @@ impl OrphanBlocksPool {
         self.blocks.len()
     }
 
+    /// Returns the total size of the orphan blocks in bytes.
+    pub(crate) fn total_size(&self) -> usize {
+        self.total_size
+    }
+
     /// Returns `true` if the block specified by `hash` already exists in the pool.
     pub(crate) fn block_exists(&self, hash: &BlockHash) -> bool {
         self.blocks.contains_key(hash)
@@ -59,16 +90,28 @@ impl OrphanBlocksPool {
         self.blocks.clear();
         self.orphan_blocks.clear();
         self.unknown_blocks.clear();
+        self.total_size = 0;
     }
 
     /// Insert orphaned block, for which we have already requested its parent block
     pub(crate) fn insert_orphan_block(&mut self, block: BitcoinBlock) {
         let block_hash = block.block_hash();
+        if self.blocks.contains_key(&block_hash) {
+            return;
+        }
         self.orphan_blocks
             .entry(block.header.prev_blockhash)
             .or_default()
             .insert(block_hash);
-        self.blocks.insert(block_hash, block);
+        self.total_size += block.total_size();
+        self.blocks.insert(
+            block_hash,
+            OrphanBlock {
+                block,
+                received_at: Instant::now(),
+            },
+        );
+        self.enforce_size_limit();
     }
 
     /// Insert unknown block, for which we know nothing about its parent block
@@ -80,6 +123,64 @@ impl OrphanBlocksPool {
         self.insert_orphan_block(block);
     }
 
+    /// Evicts the oldest blocks until the pool fits in the size limit, the unknown ones first.
+    fn enforce_size_limit(&mut self) {
+        while self.total_size > self.max_size {
+            let oldest = |hashes: &mut dyn Iterator<Item = &BlockHash>| {
+                hashes
+                    .min_by_key(|hash| self.blocks[*hash].received_at)
+                    .copied()
+            };
+
+            let Some(to_evict) =
+                oldest(&mut self.unknown_blocks.iter()).or_else(|| oldest(&mut self.blocks.keys()))
+            else {
+                return;
+            };
+
+            let evicted = self.remove_blocks(&HashSet::from([to_evict]));
+
+            tracing::debug!(
+                total_size = self.total_size,
+                "Orphan blocks pool is full, evicted {} blocks: {evicted:?}",
+                evicted.len(),
+            );
+        }
+    }
+
+    /// Removes the block from the pool, its children are kept.
+    pub(crate) fn remove_block(&mut self, hash: &BlockHash) -> Option<BitcoinBlock> {
+        let OrphanBlock { block, .. } = self.blocks.remove(hash)?;
+
+        let parent_hash = block.header.prev_blockhash;
+        if let Some(siblings) = self.orphan_blocks.get_mut(&parent_hash) {
+            siblings.remove(hash);
+            if siblings.is_empty() {
+                self.orphan_blocks.remove(&parent_hash);
+            }
+        }
+
+        self.unknown_blocks.remove(hash);
+        self.total_size -= block.total_size();
+
+        Some(block)
+    }
+
+    /// Removes the blocks received more than `max_age` ago and their descendants.
+    pub(crate) fn remove_expired_blocks(&mut self, max_age: Duration) -> Vec<BlockHash> {
+        let expired = self
+            .blocks
+            .iter()
+            .filter_map(|(hash, orphan)| (orphan.received_at.elapsed() > max_age).then_some(*hash))
+            .collect::<HashSet<_>>();
+
+        if expired.is_empty() {
+            return Vec::new();
+        }
+
+        self.remove_blocks(&expired)
+    }
+
     /// Remove all blocks, which are not-unknown
     pub(crate) fn remove_known_blocks(&mut self) -> Vec<BlockHash> {
         let orphans_to_remove: HashSet<_> = self
@@ -94,6 +195,9 @@ impl OrphanBlocksPool {
     }
 
     /// Remove all blocks whose ancestor is `hash`.
+    ///
+    /// The blocks are returned in the breadth-first order, i.e., a block always comes after
+    /// its parent.
     pub(crate) fn remove_blocks_for_parent(&mut self, hash: BlockHash) -> VecDeque<BitcoinBlock> {
         let mut queue: VecDeque<BlockHash> = VecDeque::new();
         queue.push_back(hash);
@@ -101,13 +205,11 @@ impl OrphanBlocksPool {
         let mut removed: VecDeque<BitcoinBlock> = VecDeque::new();
 
         while let Some(parent_hash) = queue.pop_front() {
-            if let Some(children) = self.orphan_blocks.remove(&parent_hash) {
+            if let Some(children) = self.orphan_blocks.get(&parent_hash).cloned() {
                 for child_hash in children {
-                    self.unknown_blocks.remove(&child_hash);
-
                     queue.push_back(child_hash);
 
-                    if let Some(block) = self.blocks.remove(&child_hash) {
+                    if let Some(block) = self.remove_block(&child_hash) {
                         removed.push_back(block);
                     }
                 }
@@ -121,19 +223,11 @@ impl OrphanBlocksPool {
     pub(crate) fn remove_blocks(&mut self, hashes: &HashSet<BlockHash>) -> Vec<BlockHash> {
         let mut removed = Vec::new();
 
-        self.orphan_blocks.retain(|_, orphans| {
-            for hash in hashes {
-                if orphans.remove(hash) {
-                    removed.push(*hash);
-                }
+        for hash in hashes {
+            if self.remove_block(hash).is_some() {
+                removed.push(*hash);
             }
-            !orphans.is_empty()
-        });
-
-        removed.iter().for_each(|block_hash| {
-            self.unknown_blocks.remove(block_hash);
-            self.blocks.remove(block_hash);
-        });
+        }
 
         // also delete all children
         for hash in hashes.iter() {
@@ -289,4 +383,48 @@ mod tests {
 
         assert_eq!(pool.len(), 1);
     }
+
+    #[test]
+    fn orphan_block_pool_evicts_unknown_blocks_first() {
+        let b1 = block1();
+        let b2 = block2();
+        let b3 = block169();
+        let b3_hash = b3.block_hash();
+        let max_size = b1.total_size() + b2.total_size() + b3.total_size() - 1;
+        let mut pool = OrphanBlocksPool::with_max_size(max_size);
+
+        pool.insert_orphan_block(b1.clone());
+        pool.insert_unknown_block(b3);
+        pool.insert_orphan_block(b2.clone());
+
+        assert_eq!(pool.len(), 2);
+        assert!(!pool.block_exists(&b3_hash));
+        assert_eq!(pool.unknown_blocks().len(), 0);
+        assert_eq!(pool.total_size(), b1.total_size() + b2.total_size());
+    }
+
+    #[test]
+    fn orphan_block_pool_remove_expired_blocks() {
+        let mut pool = OrphanBlocksPool::new();
+        let b1 = block1();
+        let b1_hash = b1.block_hash();
+        let b2 = block2();
+        let b2_hash = b2.block_hash();
+        let b3 = block169();
+
+        pool.insert_orphan_block(b1);
+        pool.insert_orphan_block(b2);
+        pool.insert_orphan_block(b3);
+
+        pool.blocks.get_mut(&b1_hash).unwrap().received_at =
+            Instant::now() - ORPHAN_BLOCK_EXPIRY - Duration::from_secs(1);
+
+        // The child of the expired block is removed as well.
+        let removed = pool.remove_expired_blocks(ORPHAN_BLOCK_EXPIRY);
+        assert_eq!(
+            removed.into_iter().collect::<HashSet<_>>(),
+            HashSet::from_iter([b1_hash, b2_hash])
+        );
+        assert_eq!(pool.len(), 1);
+    }
 }
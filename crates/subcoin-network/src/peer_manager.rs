@@ -1,15 +1,25 @@
-use crate::address_book::AddressBook;
+use crate::address_book::{address_network, netgroup, AddressBook, AddressNetwork, NetGroup};
 use crate::connection::{ConnectionInitiator, ConnectionWriter, Direction, NewConnection};
+use crate::eviction::{select_peer_to_evict, EvictionCandidate};
+use crate::i2p::I2pDestinations;
+use crate::local_address::LocalAddresses;
 use crate::metrics::Metrics;
-use crate::{validate_outbound_services, Error, Latency, PeerId};
+use crate::network_time::NetworkTime;
+use crate::peer_store::{PeerStore, MAX_ANCHORS};
+use crate::rate_limit::RateLimiter;
+use crate::{
+    services_hex, validate_outbound_services, Bandwidth, Error, Latency, PeerDetails, PeerId,
+    SubcoinVersion, TransportInfo, NODE_SNAPSHOT,
+};
 use bitcoin::p2p::address::AddrV2Message;
 use bitcoin::p2p::message::NetworkMessage;
 use bitcoin::p2p::message_network::VersionMessage;
 use bitcoin::p2p::{Address, ServiceFlags};
 use chrono::prelude::{DateTime, Local};
 use sc_client_api::HeaderBackend;
+use serde::{Deserialize, Serialize};
 use sp_runtime::traits::Block as BlockT;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -21,6 +31,9 @@ type LocalTime = DateTime<Local>;
 /// "wtxidrelay" command for wtxid-based relay starts with this version.
 const WTXID_RELAY_VERSION: u32 = 70016;
 
+/// "sendaddrv2" command of BIP155 starts with this version.
+const ADDRV2_VERSION: u32 = 70016;
+
 /// Maximum number of available addresses in the address book.
 const MAX_AVAILABLE_ADDRESSES: usize = 2000;
 
@@ -40,9 +53,61 @@ const SLOW_PEER_LATENCY: Latency = 5_000;
 /// when the peer set is full. This creates opportunities to connect with potentially better peers.
 const EVICTION_INTERVAL: Duration = Duration::from_secs(600);
 
+/// Interval for replacing the oldest block-relay-only peer, 30 minutes.
+///
+/// Rotating the peers periodically limits the time an attacker has to capture all the
+/// outbound slots with the connections it controls.
+const PEER_ROTATION_INTERVAL: Duration = Duration::from_secs(1800);
+
+/// Interval for saving the address book.
+const ADDRESSES_SAVE_INTERVAL: Duration = Duration::from_secs(900);
+
+/// Interval for reconnecting to the manual peers.
+const MANUAL_RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Misbehavior score at which the peer is disconnected.
+const MISBEHAVIOR_THRESHOLD: u32 = 100;
+
 /// Timeout for the outbound peer to send their version, in seconds.
 const HANDSHAKE_TIMEOUT: i64 = 1;
 
+/// Type of a connection, which determines the messages relayed over it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnectionType {
+    /// Connection initiated by the remote peer.
+    Inbound,
+    /// Automatic outbound connection relaying the blocks, transactions and addresses.
+    OutboundFullRelay,
+    /// Automatic outbound connection relaying the blocks only.
+    ///
+    /// Not taking part in the transaction and address relay makes these connections hard
+    /// to discover by observing the network, which protects against the eclipse attacks.
+    BlockRelayOnly,
+    /// Connection to a peer specified with `--connect` or `--addnode`.
+    Manual,
+}
+
+impl ConnectionType {
+    /// Whether the transactions and addresses are relayed over the connection.
+    pub fn is_full_relay(&self) -> bool {
+        !matches!(self, Self::BlockRelayOnly)
+    }
+}
+
+/// Numbers of the automatic outbound connections to maintain.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OutboundTargets {
+    pub(crate) full_relay: usize,
+    pub(crate) block_relay_only: usize,
+}
+
+impl OutboundTargets {
+    fn total(&self) -> usize {
+        self.full_relay + self.block_relay_only
+    }
+}
+
 /// Peer configuration.
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -50,10 +115,20 @@ pub struct Config {
     pub protocol_version: u32,
     /// Services offered by this implementation.
     pub services: ServiceFlags,
-    /// Peer addresses to persist connections with.
+    /// Peer addresses to persist connections with, i.e., the manual peers.
     pub persistent: Vec<PeerId>,
     /// Our user agent.
     pub user_agent: String,
+    /// Maximum messages per second accepted from a peer.
+    pub max_message_rate: Option<u32>,
+    /// Port accepting the inbound TCP connections, our address is not advertised if `None`.
+    pub listen_port: Option<u16>,
+    /// Whether the CJDNS addresses are reachable.
+    pub cjdns_reachable: bool,
+    /// Destinations of the I2P peers, `None` if I2P is not reachable.
+    pub i2p_destinations: Option<I2pDestinations>,
+    /// Whether to ask the peers not to relay the transactions to us, i.e., `-blocksonly`.
+    pub blocks_only: bool,
 }
 
 impl Config {
@@ -64,6 +139,11 @@ impl Config {
             services: ServiceFlags::NONE,
             persistent: Vec::new(),
             user_agent,
+            max_message_rate: None,
+            listen_port: None,
+            cjdns_reachable: false,
+            i2p_destinations: None,
+            blocks_only: false,
         }
     }
 }
@@ -71,6 +151,14 @@ impl Config {
 /// Channel for communication with the remote peer.
 struct Connection {
     local_addr: PeerId,
+    transport: TransportInfo,
+    connection_type: ConnectionType,
+    established_at: Instant,
+    bandwidth: Bandwidth,
+    /// Limit of the messages accepted from the peer.
+    message_limiter: Option<RateLimiter>,
+    /// Number of the messages dropped by `message_limiter`.
+    dropped_messages: u64,
     writer: ConnectionWriter,
     disconnect_signal: Arc<AtomicBool>,
 }
@@ -93,6 +181,9 @@ pub struct NewPeer {
     pub peer_id: PeerId,
     pub best_number: u32,
     pub connect_latency: Latency,
+    pub services: ServiceFlags,
+    pub transport: TransportInfo,
+    pub connection_type: ConnectionType,
 }
 
 /// Handshake state.
@@ -139,12 +230,18 @@ impl HandshakeState {
 pub struct PingLatency {
     pub received_pongs: u64,
     pub total_latency: Latency,
+    /// Latency of the last ping.
+    pub last: Option<Latency>,
+    /// Lowest latency of the pings.
+    pub min: Option<Latency>,
 }
 
 impl PingLatency {
     fn on_pong(&mut self, latency: Latency) {
         self.received_pongs = self.received_pongs.saturating_add(1);
         self.total_latency = self.total_latency.saturating_add(latency);
+        self.last = Some(latency);
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
     }
 
     fn average(&self) -> Latency {
@@ -185,6 +282,14 @@ impl PingState {
             Self::AwaitingPong { last_ping_at, .. } => last_ping_at.elapsed() >= Self::PING_TIMEOUT,
         }
     }
+
+    /// Returns the time elapsed since the outstanding ping was sent.
+    fn ping_wait(&self) -> Option<Latency> {
+        match self {
+            Self::Idle { .. } => None,
+            Self::AwaitingPong { last_ping_at, .. } => Some(last_ping_at.elapsed().as_millis()),
+        }
+    }
 }
 
 /// A peer with protocol information.
@@ -221,8 +326,18 @@ pub struct PeerInfo {
     pub ping_state: PingState,
     /// Whether the ping has ever sent to the peer.
     pub has_sent_ping: bool,
+    /// Time at which the peer last sent us a block.
+    pub last_block_at: Option<Instant>,
+    /// Time at which the peer last sent us a new transaction.
+    pub last_tx_at: Option<Instant>,
+    /// Accumulated misbehavior score.
+    pub misbehavior: u32,
     /// Inbound or outbound peer?
     pub direction: Direction,
+    /// Subcoin protocol announced by the peer in `subcoinver`.
+    pub subcoin_version: Option<SubcoinVersion>,
+    /// Whether our `subcoinver` has been sent to the peer.
+    pub subcoin_version_sent: bool,
 }
 
 impl PeerInfo {
@@ -243,7 +358,12 @@ impl PeerInfo {
                 last_pong_at: Instant::now(),
             },
             has_sent_ping: false,
+            last_block_at: None,
+            last_tx_at: None,
+            misbehavior: 0,
             direction,
+            subcoin_version: None,
+            subcoin_version_sent: false,
         }
     }
 }
@@ -260,13 +380,33 @@ pub struct PeerManager<Block, Client> {
     client: Arc<Client>,
     address_book: AddressBook,
     handshaking_peers: HashMap<PeerId, HandshakeState>,
+    /// Handshaking peers which sent `wtxidrelay`.
+    wtxid_relay_requested: HashSet<PeerId>,
     connections: HashMap<PeerId, Connection>,
     connection_latencies: HashMap<PeerId, Latency>,
     connected_peers: HashMap<PeerId, PeerInfo>,
-    max_outbound_peers: usize,
+    network_time: NetworkTime,
+    local_addresses: LocalAddresses,
+    outbound_targets: OutboundTargets,
+    max_inbound_peers: usize,
+    /// Outbound connections being established, by the connection type.
+    pending_outbound: HashMap<PeerId, ConnectionType>,
     connection_initiator: ConnectionInitiator,
     /// Time at which the slowest peer was evicted.
     last_eviction: Instant,
+    /// Time at which a block-relay-only peer was last rotated.
+    last_rotation: Instant,
+    /// Time at which the manual peers were last attempted.
+    last_manual_attempt: Option<Instant>,
+    peer_store: Option<PeerStore>,
+    /// Last block-relay-only peers, reconnected first after a restart.
+    anchors: Vec<(PeerId, ServiceFlags)>,
+    /// Anchors loaded on startup, not connected yet.
+    pending_anchors: Vec<(PeerId, ServiceFlags)>,
+    /// Time at which the address book was last saved.
+    last_addresses_save: Instant,
+    /// Secret for the eviction protection by network group.
+    netgroup_key: u64,
     rng: fastrand::Rng,
     metrics: Option<Metrics>,
     _phantom: PhantomData<Block>,
@@ -282,21 +422,60 @@ where
         client: Arc<Client>,
         config: Config,
         connection_initiator: ConnectionInitiator,
-        max_outbound_peers: usize,
+        outbound_targets: OutboundTargets,
+        max_inbound_peers: usize,
+        peer_store: Option<PeerStore>,
         metrics: Option<Metrics>,
     ) -> Self {
+        let mut address_book = AddressBook::new(true, MAX_AVAILABLE_ADDRESSES);
+        if config.cjdns_reachable {
+            address_book = address_book.with_cjdns();
+        }
+        if let Some(i2p_destinations) = &config.i2p_destinations {
+            address_book = address_book.with_i2p(i2p_destinations.clone());
+        }
+
+        let anchors = match &peer_store {
+            Some(peer_store) => {
+                address_book.restore(peer_store.load_addresses());
+                peer_store.load_anchors()
+            }
+            None => Vec::new(),
+        };
+
+        // The anchors are not used with `--connect`.
+        let pending_anchors = if outbound_targets.block_relay_only > 0 {
+            anchors.clone()
+        } else {
+            Vec::new()
+        };
+
+        let mut rng = fastrand::Rng::new();
+
         Self {
+            local_addresses: LocalAddresses::new(config.listen_port),
             config,
             client,
-            address_book: AddressBook::new(true, MAX_AVAILABLE_ADDRESSES),
+            address_book,
             handshaking_peers: HashMap::new(),
+            wtxid_relay_requested: HashSet::new(),
             connections: HashMap::new(),
             connection_latencies: HashMap::new(),
             connected_peers: HashMap::new(),
-            max_outbound_peers,
+            network_time: NetworkTime::new(),
+            outbound_targets,
+            max_inbound_peers,
+            pending_outbound: HashMap::new(),
             connection_initiator,
             last_eviction: Instant::now(),
-            rng: fastrand::Rng::new(),
+            last_rotation: Instant::now(),
+            last_manual_attempt: None,
+            peer_store,
+            anchors,
+            pending_anchors,
+            last_addresses_save: Instant::now(),
+            netgroup_key: rng.u64(..),
+            rng,
             metrics,
             _phantom: Default::default(),
         }
@@ -347,23 +526,57 @@ where
                 .set(self.address_book.available_addresses_count() as u64);
         }
 
-        if outbound_peers_count < self.max_outbound_peers {
-            if let Some(addr) = self.address_book.pop() {
-                if !self.connections.contains_key(&addr) {
-                    self.connection_initiator.initiate_outbound_connection(addr);
+        if self.last_addresses_save.elapsed() > ADDRESSES_SAVE_INTERVAL {
+            self.last_addresses_save = Instant::now();
+            if let Some(peer_store) = &self.peer_store {
+                peer_store.save_addresses(self.address_book.addresses());
+            }
+        }
+
+        self.connect_to_anchors();
+
+        self.maintain_manual_connections();
+
+        let (full_relay_count, block_relay_only_count) = self.outbound_counts();
+
+        let connection_type = if full_relay_count < self.outbound_targets.full_relay {
+            Some(ConnectionType::OutboundFullRelay)
+        } else if block_relay_only_count < self.outbound_targets.block_relay_only {
+            Some(ConnectionType::BlockRelayOnly)
+        } else {
+            None
+        };
+
+        if let Some(connection_type) = connection_type {
+            // Pick the addresses from the network groups not connected yet, so that the
+            // outbound peers can not be easily taken over by the nodes of a single operator.
+            let netgroups = self.outbound_netgroups();
+            let missing_networks = self
+                .address_book
+                .reachable_networks()
+                .difference(&self.outbound_networks())
+                .copied()
+                .collect();
+            if let Some((addr, services)) = self.address_book.pop(&netgroups, &missing_networks) {
+                if !self.connections.contains_key(&addr)
+                    && !self.pending_outbound.contains_key(&addr)
+                {
+                    self.pending_outbound.insert(addr, connection_type);
+                    self.connection_initiator
+                        .initiate_outbound_connection(addr, services);
                 }
             }
             None
         } else {
             // It's possible for the number of connected peers to temporarily exceed the
-            // `max_outbound_peers` limit if multiple connection attempts are in progress
+            // outbound targets if multiple connection attempts are in progress
             // and succeed simultaneously. This isn't a significant issue, as the slowest
             // peer can be evicted to enforce the limit.
             //
             // When the `max_inbound_peers` limit is reached, we still attempt to discover
             // potentially better peers by evicting the slowest peer after the eviction
             // interval has elapsed.
-            if self.max_outbound_peers > outbound_peers_count
+            if self.outbound_targets.total() > outbound_peers_count
                 || self.last_eviction.elapsed() > EVICTION_INTERVAL
             {
                 // Find the slowest peer.
@@ -389,6 +602,164 @@ where
         }
     }
 
+    /// Returns the numbers of the automatic outbound full-relay and block-relay-only
+    /// connections, including the ones being established.
+    fn outbound_counts(&self) -> (usize, usize) {
+        let connection_types = self
+            .connections
+            .values()
+            .map(|connection| connection.connection_type)
+            .chain(self.pending_outbound.values().copied());
+
+        let mut counts = (0, 0);
+        for connection_type in connection_types {
+            match connection_type {
+                ConnectionType::OutboundFullRelay => counts.0 += 1,
+                ConnectionType::BlockRelayOnly => counts.1 += 1,
+                ConnectionType::Inbound | ConnectionType::Manual => {}
+            }
+        }
+        counts
+    }
+
+    /// Returns the network groups of the automatic outbound connections.
+    fn outbound_netgroups(&self) -> HashSet<NetGroup> {
+        self.connections
+            .iter()
+            .filter(|(_, connection)| {
+                matches!(
+                    connection.connection_type,
+                    ConnectionType::OutboundFullRelay | ConnectionType::BlockRelayOnly
+                )
+            })
+            .map(|(peer_id, _)| peer_id)
+            .chain(self.pending_outbound.keys())
+            .filter_map(netgroup)
+            .collect()
+    }
+
+    /// Returns the networks of the outbound peers, including the pending ones.
+    fn outbound_networks(&self) -> HashSet<AddressNetwork> {
+        self.connections
+            .iter()
+            .filter(|(_, connection)| {
+                matches!(
+                    connection.connection_type,
+                    ConnectionType::OutboundFullRelay | ConnectionType::BlockRelayOnly
+                )
+            })
+            .map(|(peer_id, _)| peer_id)
+            .chain(self.pending_outbound.keys())
+            .map(address_network)
+            .collect()
+    }
+
+    /// Connects to the anchors from the previous run as the block-relay-only peers.
+    fn connect_to_anchors(&mut self) {
+        for (addr, services) in std::mem::take(&mut self.pending_anchors) {
+            if !self.connections.contains_key(&addr) && !self.pending_outbound.contains_key(&addr) {
+                tracing::debug!(?addr, "Connecting to anchor");
+                self.pending_outbound
+                    .insert(addr, ConnectionType::BlockRelayOnly);
+                self.connection_initiator
+                    .initiate_outbound_connection(addr, services);
+            }
+        }
+    }
+
+    /// Records the block-relay-only peer as an anchor, only the latest [`MAX_ANCHORS`] are kept.
+    fn add_anchor(&mut self, peer_id: PeerId, services: ServiceFlags) {
+        self.anchors.retain(|(addr, _)| *addr != peer_id);
+        self.anchors.push((peer_id, services));
+        if self.anchors.len() > MAX_ANCHORS {
+            self.anchors.remove(0);
+        }
+        if let Some(peer_store) = &self.peer_store {
+            peer_store.save_anchors(&self.anchors);
+        }
+    }
+
+    /// Adds a manual peer, connected to on the next tick.
+    pub(crate) fn add_manual_peer(&mut self, addr: PeerId) {
+        if !self.config.persistent.contains(&addr) {
+            self.config.persistent.push(addr);
+        }
+        self.last_manual_attempt.take();
+    }
+
+    /// Connects to the manual peers which are not connected.
+    fn maintain_manual_connections(&mut self) {
+        if self
+            .last_manual_attempt
+            .is_some_and(|at| at.elapsed() < MANUAL_RECONNECT_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_manual_attempt.replace(Instant::now());
+
+        for addr in &self.config.persistent {
+            if !self.connections.contains_key(addr) && !self.pending_outbound.contains_key(addr) {
+                self.pending_outbound.insert(*addr, ConnectionType::Manual);
+                // The services of the manual peers are unknown.
+                self.connection_initiator
+                    .initiate_outbound_connection(*addr, ServiceFlags::NONE);
+            }
+        }
+    }
+
+    /// Returns the peer to be replaced by a new block-relay-only peer, if it's time.
+    ///
+    /// The oldest block-relay-only peer is picked once at least every
+    /// [`PEER_ROTATION_INTERVAL`], when all the block-relay-only slots are in use.
+    pub(crate) fn peer_to_rotate(&mut self) -> Option<PeerId> {
+        if self.last_rotation.elapsed() < PEER_ROTATION_INTERVAL {
+            return None;
+        }
+
+        let (_, block_relay_only_count) = self.outbound_counts();
+        if block_relay_only_count < self.outbound_targets.block_relay_only {
+            return None;
+        }
+
+        self.last_rotation = Instant::now();
+
+        self.connections
+            .iter()
+            .filter(|(peer_id, connection)| {
+                connection.connection_type == ConnectionType::BlockRelayOnly
+                    && self.connected_peers.contains_key(peer_id)
+            })
+            .min_by_key(|(_, connection)| connection.established_at)
+            .map(|(peer_id, _)| *peer_id)
+    }
+
+    /// Returns `true` if the transactions and addresses are relayed with the peer.
+    pub(crate) fn is_full_relay(&self, peer_id: &PeerId) -> bool {
+        self.connections
+            .get(peer_id)
+            .is_some_and(|connection| connection.connection_type.is_full_relay())
+    }
+
+    /// Returns `true` if we accept the transactions relayed by the peer.
+    pub(crate) fn accepts_transactions(&self, peer_id: &PeerId) -> bool {
+        !self.config.blocks_only && self.is_full_relay(peer_id)
+    }
+
+    /// Returns the connected peers the transactions are announced to, excluding the ones
+    /// asking not to receive the transactions in their `version`.
+    pub(crate) fn transaction_relay_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.connected_peers
+            .iter()
+            .filter(|(peer_id, peer_info)| peer_info.relay && self.is_full_relay(peer_id))
+            .map(|(peer_id, _)| peer_id)
+    }
+
+    /// Returns `true` if the automatic outbound connections are enabled, i.e., not `--connect`.
+    fn discovers_addresses(&self) -> bool {
+        self.outbound_targets.total() > 0
+    }
+
     fn send_pings(&mut self, should_pings: Vec<PeerId>) {
         for peer_id in should_pings {
             if let Some(peer_info) = self.connected_peers.get_mut(&peer_id) {
@@ -416,9 +787,26 @@ where
             .send(network_message)?)
     }
 
+    /// Returns the writer of the connection to the peer, for sending messages off the worker.
+    pub(crate) fn connection_writer(&self, peer_id: PeerId) -> Option<ConnectionWriter> {
+        self.connections
+            .get(&peer_id)
+            .map(|connection| connection.writer.clone())
+    }
+
     pub(crate) fn on_outbound_connection_failure(&mut self, addr: PeerId, err: Error) {
         tracing::trace!(?err, ?addr, "Failed to initiate outbound connection");
 
+        self.pending_outbound.remove(&addr);
+
+        // An unreachable anchor is not retried on the next startup.
+        if self.anchors.iter().any(|(anchor, _)| *anchor == addr) {
+            self.anchors.retain(|(anchor, _)| *anchor != addr);
+            if let Some(peer_store) = &self.peer_store {
+                peer_store.save_anchors(&self.anchors);
+            }
+        }
+
         self.address_book.note_failed_address(addr);
 
         if let Some(metrics) = &self.metrics {
@@ -435,6 +823,24 @@ where
             return;
         }
 
+        self.remove_connection(peer_id, reason);
+    }
+
+    /// Removes the connection closed by the remote, including the persistent one which will
+    /// be reconnected later.
+    pub(crate) fn on_connection_closed(&mut self, peer_id: PeerId, reason: Error) {
+        // The connection reader closes the connection once the peer violates the protocol,
+        // e.g., sending an oversized message, the address is not retried.
+        let misbehaving = reason.misbehavior_score().is_some();
+
+        self.remove_connection(peer_id, reason);
+
+        if misbehaving {
+            self.address_book.note_failed_address(peer_id);
+        }
+    }
+
+    fn remove_connection(&mut self, peer_id: PeerId, reason: Error) {
         if let Some(Connection {
             disconnect_signal, ..
         }) = self.connections.remove(&peer_id)
@@ -449,10 +855,96 @@ where
 
         self.address_book.mark_disconnected(&peer_id);
         self.handshaking_peers.remove(&peer_id);
+        self.wtxid_relay_requested.remove(&peer_id);
+        self.local_addresses.remove_peer(&peer_id);
         self.connected_peers.remove(&peer_id);
         self.connection_latencies.remove(&peer_id);
     }
 
+    /// Sets the address mapped on the gateway for the listen port.
+    pub(crate) fn set_mapped_address(&mut self, mapped: Option<PeerId>) {
+        self.local_addresses.set_mapped(mapped);
+    }
+
+    /// Returns the address advertised to the peers.
+    pub(crate) fn local_address(&self) -> Option<PeerId> {
+        self.local_addresses.best()
+    }
+
+    /// Handles receiving a `wtxidrelay` message, only valid between `version` and `verack`.
+    pub(crate) fn on_wtxid_relay(&mut self, peer_id: PeerId) {
+        if matches!(
+            self.handshaking_peers.get(&peer_id),
+            Some(HandshakeState::VersionReceived(_))
+        ) {
+            self.wtxid_relay_requested.insert(peer_id);
+        } else {
+            tracing::debug!(
+                ?peer_id,
+                "Ignoring wtxidrelay received out of the handshake"
+            );
+        }
+    }
+
+    /// Handles receiving a `subcoinver` message, replying with ours if not sent yet.
+    pub(crate) fn on_subcoin_version(
+        &mut self,
+        peer_id: PeerId,
+        version: SubcoinVersion,
+    ) -> Result<(), Error> {
+        let Some(peer_info) = self.connected_peers.get_mut(&peer_id) else {
+            tracing::debug!(?peer_id, "Ignoring subcoinver received before verack");
+            return Ok(());
+        };
+
+        if peer_info.subcoin_version.is_some() {
+            tracing::debug!(?peer_id, "Ignoring redundant subcoinver");
+            return Ok(());
+        }
+
+        peer_info.subcoin_version.replace(version);
+
+        tracing::debug!(
+            ?peer_id,
+            "Negotiated subcoin protocol: {:?}",
+            SubcoinVersion::LOCAL.negotiate(version)
+        );
+
+        if !peer_info.subcoin_version_sent {
+            self.send_subcoin_version(peer_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_subcoin_version(&mut self, peer_id: PeerId) -> Result<(), Error> {
+        if let Some(peer_info) = self.connected_peers.get_mut(&peer_id) {
+            peer_info.subcoin_version_sent = true;
+        }
+        self.send(peer_id, SubcoinVersion::LOCAL.into_network_message())
+    }
+
+    /// Returns the subcoin protocol negotiated with the peer, `None` if the peer is not a
+    /// subcoin node.
+    pub(crate) fn subcoin_version(&self, peer_id: &PeerId) -> Option<SubcoinVersion> {
+        let peer_info = self.connected_peers.get(peer_id)?;
+
+        match peer_info.subcoin_version {
+            Some(version) => Some(SubcoinVersion::LOCAL.negotiate(version)),
+            None if peer_info.services.has(ServiceFlags::from(NODE_SNAPSHOT)) => {
+                Some(SubcoinVersion::LEGACY)
+            }
+            None => None,
+        }
+    }
+
+    /// Returns `true` if the transactions are announced to the peer by wtxid.
+    pub(crate) fn is_wtxid_relay(&self, peer_id: &PeerId) -> bool {
+        self.connected_peers
+            .get(peer_id)
+            .is_some_and(|info| info.wtxidrelay)
+    }
+
     /// Sets the prefer addrv2 flag for a peer.
     pub(crate) fn set_want_addrv2(&mut self, peer_id: PeerId) {
         self.connected_peers.entry(peer_id).and_modify(|info| {
@@ -476,11 +968,6 @@ where
         self.connected_peers.contains_key(&peer_id)
     }
 
-    /// Returns the list of connected peers.
-    pub(crate) fn connected_peers(&self) -> impl Iterator<Item = &PeerId> {
-        self.connected_peers.keys()
-    }
-
     /// Returns the number of connected peers.
     pub(crate) fn connected_peers_count(&self) -> usize {
         self.connected_peers.len()
@@ -494,6 +981,129 @@ where
             .count()
     }
 
+    /// Returns `true` if another inbound peer can be accepted without evicting one.
+    pub(crate) fn has_inbound_slot(&self) -> bool {
+        self.inbound_peers_count() < self.max_inbound_peers
+    }
+
+    /// Selects the inbound peer to evict for accepting a new inbound connection.
+    ///
+    /// Returns `None` if all the inbound peers are protected, the new connection must be
+    /// rejected in that case.
+    pub(crate) fn select_inbound_peer_to_evict(&self) -> Option<PeerId> {
+        let candidates = self
+            .connected_peers
+            .iter()
+            .filter(|(peer_id, peer_info)| {
+                peer_info.direction.is_inbound() && !self.config.persistent.contains(peer_id)
+            })
+            .filter_map(|(peer_id, peer_info)| {
+                let connection = self.connections.get(peer_id)?;
+                Some(EvictionCandidate {
+                    peer_id: *peer_id,
+                    netgroup: netgroup(peer_id),
+                    connected_at: connection.established_at,
+                    // The minimum is not inflated by the transient congestion, like Bitcoin Core.
+                    latency: peer_info.ping_latency.min.unwrap_or(Latency::MAX),
+                    last_block_at: peer_info.last_block_at,
+                    last_tx_at: peer_info.last_tx_at,
+                })
+            })
+            .collect();
+
+        select_peer_to_evict(candidates, self.netgroup_key)
+    }
+
+    /// Returns `false` if the message from the peer exceeds the message rate limit and must be
+    /// dropped.
+    pub(crate) fn allow_message(&mut self, peer_id: PeerId) -> bool {
+        let Some(connection) = self.connections.get_mut(&peer_id) else {
+            return true;
+        };
+
+        let allowed = connection
+            .message_limiter
+            .as_ref()
+            .map_or(true, |limiter| limiter.try_acquire(1));
+
+        if !allowed {
+            connection.dropped_messages += 1;
+        }
+
+        allowed
+    }
+
+    /// Increases the misbehavior score of the peer.
+    ///
+    /// Returns the new score if it reaches [`MISBEHAVIOR_THRESHOLD`], the peer should be
+    /// disconnected then.
+    pub(crate) fn misbehaving(&mut self, peer_id: PeerId, score: u32) -> Option<u32> {
+        let peer_info = self.connected_peers.get_mut(&peer_id)?;
+        peer_info.misbehavior = peer_info.misbehavior.saturating_add(score);
+        tracing::debug!(
+            ?peer_id,
+            "Misbehaving peer, ban score {}",
+            peer_info.misbehavior
+        );
+        (peer_info.misbehavior >= MISBEHAVIOR_THRESHOLD).then_some(peer_info.misbehavior)
+    }
+
+    /// Returns the details of the connected peers, without the sync related information.
+    pub(crate) fn peer_details(&self) -> Vec<PeerDetails> {
+        self.connected_peers
+            .iter()
+            .filter_map(|(peer_id, peer_info)| {
+                let connection = self.connections.get(peer_id)?;
+                let average_latency = peer_info.ping_latency.average();
+                let subcoin_version = self.subcoin_version(peer_id);
+                Some(PeerDetails {
+                    peer_id: *peer_id,
+                    local_addr: connection.local_addr,
+                    connection_type: connection.connection_type,
+                    transport: connection.transport.clone(),
+                    services: services_hex(peer_info.services),
+                    version: peer_info.version,
+                    user_agent: peer_info.user_agent.clone(),
+                    start_height: peer_info.best_height,
+                    relay: peer_info.relay,
+                    connected_secs: connection.established_at.elapsed().as_secs(),
+                    ping: (average_latency != Latency::MAX).then_some(average_latency),
+                    last_ping: peer_info.ping_latency.last,
+                    min_ping: peer_info.ping_latency.min,
+                    ping_wait: peer_info.ping_state.ping_wait(),
+                    bytes_sent: connection.bandwidth.outbound(),
+                    bytes_received: connection.bandwidth.inbound(),
+                    requests_in_flight: 0,
+                    ban_score: peer_info.misbehavior,
+                    dropped_messages: connection.dropped_messages,
+                    sync_state: None,
+                    subcoin_version: subcoin_version.map(|version| version.version),
+                    subcoin_features: subcoin_version
+                        .map(|version| format!("{:016x}", version.features.bits())),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the number of the known addresses available for new connections.
+    pub(crate) fn available_addresses_count(&self) -> usize {
+        self.address_book.available_addresses_count()
+    }
+
+    /// Records a block received from the peer, for the eviction protection.
+    pub(crate) fn note_block_received(&mut self, peer_id: PeerId) {
+        if let Some(peer_info) = self.connected_peers.get_mut(&peer_id) {
+            peer_info.last_block_at.replace(Instant::now());
+        }
+    }
+
+    /// Records a new transaction received from the peer, for the eviction protection.
+    pub(crate) fn note_tx_received(&mut self, peer_id: PeerId) {
+        if let Some(peer_info) = self.connected_peers.get_mut(&peer_id) {
+            peer_info.last_tx_at.replace(Instant::now());
+        }
+    }
+
     /// Handles a new connection.
     pub(crate) fn on_new_connection(&mut self, new_connection: NewConnection) {
         let NewConnection {
@@ -501,12 +1111,32 @@ where
             local_addr,
             direction,
             connect_latency,
+            transport,
+            bandwidth,
             writer,
             disconnect_signal,
         } = new_connection;
 
+        let connection_type = match direction {
+            Direction::Inbound => ConnectionType::Inbound,
+            // The outbound connections to the seednodes are not initiated by the peer manager.
+            Direction::Outbound => self
+                .pending_outbound
+                .remove(&peer_addr)
+                .unwrap_or(ConnectionType::OutboundFullRelay),
+        };
+
         let connection = Connection {
             local_addr,
+            transport,
+            connection_type,
+            established_at: Instant::now(),
+            bandwidth,
+            message_limiter: self
+                .config
+                .max_message_rate
+                .map(|rate| RateLimiter::new(rate.into())),
+            dropped_messages: 0,
             writer,
             disconnect_signal,
         };
@@ -539,7 +1169,7 @@ where
                     // Our best height.
                     start_height: best_number as i32,
                     // Whether we want to receive transaction `inv` messages.
-                    relay: false,
+                    relay: !self.config.blocks_only && connection_type.is_full_relay(),
                 };
 
                 if connection
@@ -607,18 +1237,18 @@ where
                     nonce,
                     user_agent: self.config.user_agent.to_owned(),
                     start_height: best_number as i32,
-                    relay: false,
+                    relay: self.accepts_transactions(&peer_id),
                 };
                 self.send(peer_id, NetworkMessage::Version(our_version))?;
 
                 if greatest_common_version >= WTXID_RELAY_VERSION {
-                    // TODO: support wtxidrelay
-                    // self.send(peer_id, NetworkMessage::WtxidRelay)?;
+                    self.send(peer_id, NetworkMessage::WtxidRelay)?;
                 }
 
-                // if greatest_common_version >= 70016 {
-                // self.send(peer_id, NetworkMessage::SendAddrV2)?;
-                // }
+                // Without `addrv2`, the CJDNS and I2P addresses can't be relayed to us.
+                if greatest_common_version >= ADDRV2_VERSION {
+                    self.send(peer_id, NetworkMessage::SendAddrV2)?;
+                }
 
                 self.send(peer_id, NetworkMessage::Verack)?;
             }
@@ -630,6 +1260,14 @@ where
                 // Ensure the peer has required services.
                 validate_outbound_services(version_message.services)?;
 
+                // Only the outbound peers are sampled as they are not chosen by the remote.
+                let time_offset = version_message.timestamp - Local::now().timestamp();
+                if let Some(network_time_offset) =
+                    self.network_time.add_sample(peer_id.ip(), time_offset)
+                {
+                    sc_consensus_nakamoto::set_time_offset(network_time_offset);
+                }
+
                 match self
                     .handshaking_peers
                     .insert(peer_id, HandshakeState::VersionReceived(version_message))
@@ -669,17 +1307,42 @@ where
             return Err(Error::UnexpectedHandshakeState(Box::new(handshake_state)));
         };
 
-        let peer_info = PeerInfo::new(version, direction);
+        let supports_wtxid_relay =
+            self.config.protocol_version.min(version.version) >= WTXID_RELAY_VERSION;
+        let supports_addrv2 = self.config.protocol_version.min(version.version) >= ADDRV2_VERSION;
+
+        // The inbound peers could report any address without making a connection from it.
+        if direction.is_outbound() {
+            if let Ok(our_addr) = version.receiver.socket_addr() {
+                self.local_addresses.on_reported(peer_id, our_addr);
+            }
+        }
+
+        let mut peer_info = PeerInfo::new(version, direction);
+        peer_info.wtxidrelay = self.wtxid_relay_requested.remove(&peer_id) && supports_wtxid_relay;
+
+        let (transport, connection_type) = self
+            .connections
+            .get(&peer_id)
+            .map(|connection| (connection.transport.clone(), connection.connection_type))
+            .ok_or(Error::ConnectionNotFound(peer_id))?;
 
         let new_peer = NewPeer {
             peer_id,
             best_number: peer_info.best_height,
+            services: peer_info.services,
             connect_latency: self
                 .connection_latencies
                 .remove(&peer_id)
                 .ok_or(Error::PeerNotFound(peer_id))?,
+            transport,
+            connection_type,
         };
 
+        if connection_type == ConnectionType::BlockRelayOnly {
+            self.add_anchor(peer_id, peer_info.services);
+        }
+
         self.connected_peers.insert(peer_id, peer_info);
 
         match direction {
@@ -688,19 +1351,48 @@ where
                 // tracing::debug!(peer = ?peer_id, ?direction, "🤝 Completed handshake");
             }
             Direction::Outbound => {
+                // `wtxidrelay` and `sendaddrv2` must be sent before `verack`.
+                if supports_wtxid_relay {
+                    self.send(peer_id, NetworkMessage::WtxidRelay)?;
+                }
+                if supports_addrv2 {
+                    self.send(peer_id, NetworkMessage::SendAddrV2)?;
+                }
                 self.send(peer_id, NetworkMessage::Verack)?;
                 tracing::debug!(peer = ?peer_id, ?direction, "🤝 Completed handshake");
             }
         }
 
-        if !self.address_book.has_max_addresses() {
+        // The other Bitcoin nodes never receive the subcoin messages.
+        if new_peer.services.has(ServiceFlags::from(NODE_SNAPSHOT)) {
+            self.send_subcoin_version(peer_id)?;
+        }
+
+        if self.is_full_relay(&peer_id)
+            && self.discovers_addresses()
+            && !self.address_book.has_max_addresses()
+        {
             self.send(peer_id, NetworkMessage::GetAddr)?;
         }
 
+        if self.is_full_relay(&peer_id) {
+            if let Some(local_addr) = self.local_addresses.best() {
+                let addr = Address::new(&local_addr, self.config.services);
+                self.send(
+                    peer_id,
+                    NetworkMessage::Addr(vec![(Local::now().timestamp() as u32, addr)]),
+                )?;
+            }
+        }
+
         Ok(new_peer)
     }
 
     pub(crate) fn on_addr(&mut self, peer_id: PeerId, addresses: Vec<(u32, Address)>) {
+        if !self.is_full_relay(&peer_id) || !self.discovers_addresses() {
+            return;
+        }
+
         let added = self.address_book.add_many(peer_id, addresses);
         if added > 0 {
             tracing::debug!("Added {added} addresses from {peer_id:?}");
@@ -715,6 +1407,10 @@ where
     }
 
     pub(crate) fn on_addr_v2(&mut self, peer_id: PeerId, addresses: Vec<AddrV2Message>) {
+        if !self.is_full_relay(&peer_id) || !self.discovers_addresses() {
+            return;
+        }
+
         let added = self.address_book.add_many_v2(peer_id, addresses);
         if added > 0 {
             tracing::debug!("Added {added} addresses from {peer_id:?}");
@@ -0,0 +1,146 @@
+//! Persistence of the peer addresses across restarts.
+//!
+//! Two lists are kept in [`columns::PEERS`] of the subcoin database:
+//!
+//! - The addresses of the address book, saved periodically, so that a restarted node does not
+//!   depend on the seednodes to find its peers again.
+//! - The anchors, i.e., the last block-relay-only peers, reconnected first on startup. An
+//!   attacker filling the address book with its own nodes while we are offline can not
+//!   eclipse us, as long as one of the anchors is honest.
+//!
+//! [`columns::PEERS`]: subcoin_db::columns::PEERS
+
+use crate::address_book::{address_network, AddressNetwork};
+use crate::i2p::I2pDestinations;
+use crate::PeerId;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::p2p::address::{AddrV2, AddrV2Message};
+use bitcoin::p2p::ServiceFlags;
+use std::net::IpAddr;
+use subcoin_db::{columns, SubcoinDb};
+
+/// Key of the anchors in [`columns::PEERS`].
+const ANCHORS_KEY: &[u8] = b"anchors";
+
+/// Key of the address book in [`columns::PEERS`].
+const ADDRESSES_KEY: &[u8] = b"addresses";
+
+/// Maximum number of anchors, same as Bitcoin Core.
+pub(crate) const MAX_ANCHORS: usize = 2;
+
+/// Store of the peer addresses.
+#[derive(Debug, Clone)]
+pub(crate) struct PeerStore {
+    db: SubcoinDb,
+    /// Destinations of the I2P addresses, which are dropped if `None`.
+    i2p_destinations: Option<I2pDestinations>,
+}
+
+impl PeerStore {
+    pub(crate) fn new(db: SubcoinDb, i2p_destinations: Option<I2pDestinations>) -> Self {
+        Self {
+            db,
+            i2p_destinations,
+        }
+    }
+
+    pub(crate) fn load_anchors(&self) -> Vec<(PeerId, ServiceFlags)> {
+        let mut anchors = self.load(ANCHORS_KEY);
+        anchors.truncate(MAX_ANCHORS);
+        anchors
+    }
+
+    pub(crate) fn save_anchors(&self, anchors: &[(PeerId, ServiceFlags)]) {
+        self.save(ANCHORS_KEY, anchors.iter().copied());
+    }
+
+    pub(crate) fn load_addresses(&self) -> Vec<(PeerId, ServiceFlags)> {
+        self.load(ADDRESSES_KEY)
+    }
+
+    pub(crate) fn save_addresses(&self, addresses: impl Iterator<Item = (PeerId, ServiceFlags)>) {
+        self.save(ADDRESSES_KEY, addresses);
+    }
+
+    fn load(&self, key: &[u8]) -> Vec<(PeerId, ServiceFlags)> {
+        let Some(encoded) = self.db.get(columns::PEERS, key) else {
+            return Vec::new();
+        };
+
+        match deserialize::<Vec<AddrV2Message>>(&encoded) {
+            Ok(addresses) => addresses
+                .into_iter()
+                .filter_map(|address| {
+                    let addr = match address.addr {
+                        AddrV2::Ipv4(ip) => PeerId::new(IpAddr::V4(ip), address.port),
+                        AddrV2::Ipv6(ip) | AddrV2::Cjdns(ip) => {
+                            PeerId::new(IpAddr::V6(ip), address.port)
+                        }
+                        AddrV2::I2p(hash) => self.i2p_destinations.as_ref()?.insert(hash)?,
+                        _ => return None,
+                    };
+                    Some((addr, address.services))
+                })
+                .collect(),
+            Err(err) => {
+                tracing::warn!("Discarding corrupted peer addresses: {err}");
+                Vec::new()
+            }
+        }
+    }
+
+    fn save(&self, key: &[u8], addresses: impl Iterator<Item = (PeerId, ServiceFlags)>) {
+        let addresses = addresses
+            .filter_map(|(addr, services)| {
+                let addr_v2 = match (address_network(&addr), addr.ip()) {
+                    (AddressNetwork::I2p, _) => {
+                        AddrV2::I2p(self.i2p_destinations.as_ref()?.get(&addr)?)
+                    }
+                    (AddressNetwork::Cjdns, IpAddr::V6(ip)) => AddrV2::Cjdns(ip),
+                    (_, IpAddr::V4(ip)) => AddrV2::Ipv4(ip),
+                    (_, IpAddr::V6(ip)) => AddrV2::Ipv6(ip),
+                };
+                Some(AddrV2Message {
+                    time: 0,
+                    services,
+                    addr: addr_v2,
+                    port: addr.port(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if let Err(err) = self.db.insert(columns::PEERS, key, &serialize(&addresses)) {
+            tracing::error!("Failed to save peer addresses: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_store() {
+        let store = PeerStore::new(SubcoinDb::in_memory(), Some(I2pDestinations::default()));
+        assert!(store.load_anchors().is_empty());
+
+        let services = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
+        let anchors: Vec<(PeerId, ServiceFlags)> = vec![
+            ("1.2.3.4:8333".parse().unwrap(), services),
+            ("[2001:db8::1]:8333".parse().unwrap(), ServiceFlags::NONE),
+            ("5.6.7.8:8333".parse().unwrap(), services),
+        ];
+        store.save_anchors(&anchors);
+        assert_eq!(store.load_anchors(), anchors[..MAX_ANCHORS]);
+
+        let i2p_addr = crate::i2p::garlicat(&[3u8; 32]);
+        store.i2p_destinations.as_ref().unwrap().insert([3u8; 32]);
+        let addresses = vec![
+            anchors[0],
+            ("[fc00::1]:8333".parse().unwrap(), services),
+            (i2p_addr, services),
+        ];
+        store.save_addresses(addresses.clone().into_iter());
+        assert_eq!(store.load_addresses(), addresses);
+    }
+}
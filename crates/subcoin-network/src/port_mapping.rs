@@ -0,0 +1,380 @@
+//! Automatic port forwarding on the home routers.
+//!
+//! The listen port is mapped with NAT-PMP (RFC 6886) on the default gateway, falling back to
+//! UPnP IGD discovered over SSDP. The mapping is renewed before its lease expires and the
+//! mapped external address is advertised to the peers.
+
+use crate::{NetworkWorkerMessage, PeerId};
+use sc_utils::mpsc::TracingUnboundedSender;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Lease of the port mapping, renewed at half of it.
+const MAPPING_LIFETIME: Duration = Duration::from_secs(20 * 60);
+
+/// Interval of retrying the port mapping after a failure.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Time to wait for the response of the gateway.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+const NATPMP_PORT: u16 = 5351;
+
+/// Number of the NAT-PMP requests sent before giving up, the timeout doubles on each retry.
+const NATPMP_ATTEMPTS: u32 = 4;
+
+const SSDP_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900);
+
+const IGD_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+
+/// Services of the IGD able to map the ports, in the order of preference.
+const WAN_CONNECTION_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// Maps the TCP `port` on the gateway and keeps the mapping alive, reporting the mapped
+/// external address to the network worker.
+pub(crate) async fn run(
+    port: u16,
+    worker_msg_sender: TracingUnboundedSender<NetworkWorkerMessage>,
+) {
+    let mut current = None;
+
+    loop {
+        let mapped = match map_port(port).await {
+            Ok(external_addr) => Some(external_addr),
+            Err(err) => {
+                tracing::debug!(?err, "Failed to map the port {port}");
+                None
+            }
+        };
+
+        if mapped != current {
+            match mapped {
+                Some(external_addr) => tracing::info!("🔌 Mapped port {port} to {external_addr}"),
+                None => tracing::info!("🔌 Port mapping of {port} is unavailable"),
+            }
+
+            if worker_msg_sender
+                .unbounded_send(NetworkWorkerMessage::SetMappedAddress(mapped))
+                .is_err()
+            {
+                return;
+            }
+
+            current = mapped;
+        }
+
+        let next_attempt = if mapped.is_some() {
+            MAPPING_LIFETIME / 2
+        } else {
+            RETRY_INTERVAL
+        };
+
+        tokio::time::sleep(next_attempt).await;
+    }
+}
+
+async fn map_port(port: u16) -> io::Result<PeerId> {
+    if let Some(gateway) = default_gateway() {
+        match natpmp_map_port(gateway, port).await {
+            Ok(external_addr) => return Ok(external_addr),
+            Err(err) => tracing::debug!(?err, "NAT-PMP is unavailable on {gateway}"),
+        }
+    }
+
+    upnp_map_port(port).await
+}
+
+fn protocol_error(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Returns the IPv4 default gateway, only available on Linux.
+fn default_gateway() -> Option<Ipv4Addr> {
+    if cfg!(target_os = "linux") {
+        parse_default_gateway(&std::fs::read_to_string("/proc/net/route").ok()?)
+    } else {
+        None
+    }
+}
+
+/// Parses the default gateway from the content of `/proc/net/route`.
+fn parse_default_gateway(route_table: &str) -> Option<Ipv4Addr> {
+    /// The route uses a gateway.
+    const RTF_GATEWAY: u16 = 0x2;
+
+    route_table.lines().skip(1).find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (destination, gateway, flags) = (fields.get(1)?, fields.get(2)?, fields.get(3)?);
+
+        let flags = u16::from_str_radix(flags, 16).ok()?;
+        if *destination != "00000000" || flags & RTF_GATEWAY == 0 {
+            return None;
+        }
+
+        // The address is printed as an integer in the host byte order.
+        let gateway = u32::from_str_radix(gateway, 16).ok()?;
+        Some(Ipv4Addr::from(gateway.to_ne_bytes()))
+    })
+}
+
+/// Sends the NAT-PMP request and returns the successful response of at least `len` bytes.
+async fn natpmp_request(socket: &UdpSocket, request: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut timeout = Duration::from_millis(250);
+    let mut buf = [0u8; 16];
+
+    for _ in 0..NATPMP_ATTEMPTS {
+        socket.send(request).await?;
+
+        if let Ok(received) = tokio::time::timeout(timeout, socket.recv(&mut buf)).await {
+            let response = &buf[..received?];
+
+            if response.len() < len || response[0] != 0 || response[1] != request[1] | 0x80 {
+                return Err(protocol_error("Malformed NAT-PMP response"));
+            }
+
+            let result_code = u16::from_be_bytes([response[2], response[3]]);
+            if result_code != 0 {
+                return Err(protocol_error(format!(
+                    "NAT-PMP request failed with result code {result_code}"
+                )));
+            }
+
+            return Ok(response.to_vec());
+        }
+
+        timeout *= 2;
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "No NAT-PMP response",
+    ))
+}
+
+async fn natpmp_map_port(gateway: Ipv4Addr, port: u16) -> io::Result<PeerId> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect((gateway, NATPMP_PORT)).await?;
+
+    let response = natpmp_request(&socket, &[0, 0], 12).await?;
+    let external_ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let mut request = vec![0, 2, 0, 0];
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&(MAPPING_LIFETIME.as_secs() as u32).to_be_bytes());
+
+    let response = natpmp_request(&socket, &request, 16).await?;
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+
+    Ok(PeerId::new(IpAddr::V4(external_ip), external_port))
+}
+
+/// Finds the URL of the IGD description over SSDP.
+async fn discover_igd() -> io::Result<String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {SSDP_ADDR}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\n\
+        ST: {IGD_SEARCH_TARGET}\r\n\r\n"
+    );
+    socket.send_to(search.as_bytes(), SSDP_ADDR).await?;
+
+    let mut buf = [0u8; 2048];
+    let (received, _) = tokio::time::timeout(RESPONSE_TIMEOUT, socket.recv_from(&mut buf))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No UPnP gateway found"))??;
+
+    String::from_utf8_lossy(&buf[..received])
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("location")
+                .then(|| value.trim().to_string())
+        })
+        .ok_or_else(|| protocol_error("SSDP response without location"))
+}
+
+/// Splits the `http://` URL into the address of the host and the path.
+fn parse_http_url(url: &str) -> Option<(SocketAddr, String)> {
+    let rest = url.strip_prefix("http://")?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+
+    let host = host
+        .parse()
+        .or_else(|_| format!("{host}:80").parse())
+        .ok()?;
+
+    Some((host, path.to_string()))
+}
+
+/// Sends the HTTP request, returns the body of the response and the local IP of the
+/// connection.
+async fn http_request(host: SocketAddr, request: String) -> io::Result<(String, IpAddr)> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(host).await?;
+        let local_ip = stream.local_addr()?.ip();
+
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+
+        Ok::<_, io::Error>((String::from_utf8_lossy(&response).into_owned(), local_ip))
+    };
+
+    let (response, local_ip) = tokio::time::timeout(RESPONSE_TIMEOUT, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "UPnP gateway not responding"))??;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| protocol_error("Malformed HTTP response"))?;
+
+    if head.split(' ').nth(1) != Some("200") {
+        let status = head.lines().next().unwrap_or_default();
+        return Err(protocol_error(format!("UPnP request failed: {status}")));
+    }
+
+    Ok((body.to_string(), local_ip))
+}
+
+/// Returns the text of the first `tag` element.
+fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..end].trim())
+}
+
+/// Returns the type and the control path of the WAN connection service in the IGD
+/// description.
+fn find_wan_connection(description: &str) -> Option<(&'static str, String)> {
+    WAN_CONNECTION_SERVICES.into_iter().find_map(|service| {
+        let service_start = description.find(&format!("<serviceType>{service}</serviceType>"))?;
+        let control_url = xml_value(&description[service_start..], "controlURL")?;
+
+        let control_path = match control_url.strip_prefix("http://") {
+            Some(url) => url.find('/').map_or("/", |index| &url[index..]).to_string(),
+            None if control_url.starts_with('/') => control_url.to_string(),
+            None => format!("/{control_url}"),
+        };
+
+        Some((service, control_path))
+    })
+}
+
+async fn soap_request(
+    host: SocketAddr,
+    control_path: &str,
+    service: &str,
+    action: &str,
+    arguments: &str,
+) -> io::Result<String> {
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+        s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+        <s:Body><u:{action} xmlns:u=\"{service}\">{arguments}</u:{action}></s:Body>\
+        </s:Envelope>"
+    );
+
+    // HTTP/1.0 so that the response is not chunked.
+    let request = format!(
+        "POST {control_path} HTTP/1.0\r\nHost: {host}\r\n\
+        Content-Type: text/xml; charset=\"utf-8\"\r\n\
+        SOAPAction: \"{service}#{action}\"\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+
+    http_request(host, request).await.map(|(body, _)| body)
+}
+
+async fn upnp_map_port(port: u16) -> io::Result<PeerId> {
+    let location = discover_igd().await?;
+
+    let (host, path) = parse_http_url(&location)
+        .ok_or_else(|| protocol_error(format!("Unsupported IGD location {location}")))?;
+
+    let (description, local_ip) =
+        http_request(host, format!("GET {path} HTTP/1.0\r\nHost: {host}\r\n\r\n")).await?;
+
+    let (service, control_path) = find_wan_connection(&description)
+        .ok_or_else(|| protocol_error("IGD without WAN connection service"))?;
+
+    let arguments = format!(
+        "<NewRemoteHost></NewRemoteHost>\
+        <NewExternalPort>{port}</NewExternalPort>\
+        <NewProtocol>TCP</NewProtocol>\
+        <NewInternalPort>{port}</NewInternalPort>\
+        <NewInternalClient>{local_ip}</NewInternalClient>\
+        <NewEnabled>1</NewEnabled>\
+        <NewPortMappingDescription>subcoin</NewPortMappingDescription>\
+        <NewLeaseDuration>{}</NewLeaseDuration>",
+        MAPPING_LIFETIME.as_secs()
+    );
+    soap_request(host, &control_path, service, "AddPortMapping", &arguments).await?;
+
+    let response = soap_request(host, &control_path, service, "GetExternalIPAddress", "").await?;
+    let external_ip = xml_value(&response, "NewExternalIPAddress")
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+        .ok_or_else(|| protocol_error("Invalid external IP address from IGD"))?;
+
+    Ok(PeerId::new(external_ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_default_gateway() {
+        let gateway = u32::from_ne_bytes([192, 168, 1, 1]);
+        let route_table = format!(
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+            eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\n\
+            eth0\t00000000\t{gateway:08X}\t0003\t0\t0\t0\t00000000\n"
+        );
+
+        assert_eq!(
+            parse_default_gateway(&route_table),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+        assert_eq!(parse_default_gateway("Iface\tDestination\n"), None);
+    }
+
+    #[test]
+    fn test_find_wan_connection() {
+        let description = "<root><device><serviceList>\
+            <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+            <controlURL>/l3f</controlURL></service>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+            <controlURL>ctl/IPConn</controlURL></service>\
+            </serviceList></device></root>";
+
+        assert_eq!(
+            find_wan_connection(description),
+            Some((
+                "urn:schemas-upnp-org:service:WANIPConnection:1",
+                "/ctl/IPConn".to_string()
+            ))
+        );
+
+        assert_eq!(
+            parse_http_url("http://192.168.1.1:5000/rootDesc.xml"),
+            Some(("192.168.1.1:5000".parse().unwrap(), "/rootDesc.xml".into()))
+        );
+        assert_eq!(
+            parse_http_url("http://192.168.1.1"),
+            Some(("192.168.1.1:80".parse().unwrap(), "/".into()))
+        );
+    }
+}
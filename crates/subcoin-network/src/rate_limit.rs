@@ -0,0 +1,189 @@
+//! Traffic limits of the p2p networking.
+//!
+//! - [`RateLimiter`] caps the rate of the bytes or messages, the connection tasks wait for the
+//!   tokens before reading from or writing to the stream, which propagates the backpressure to
+//!   the remote through the transport flow control.
+//! - [`UploadTarget`] keeps the outbound traffic under a target per 24 hours like the
+//!   `-maxuploadtarget` option of Bitcoin Core, the bulk data (snapshots, historical blocks)
+//!   is no longer served once the target is reached.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Timeframe of the upload target.
+const UPLOAD_TARGET_TIMEFRAME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Token bucket refilled at a constant rate.
+#[derive(Debug)]
+struct TokenBucket {
+    /// Tokens added per second.
+    rate: f64,
+    /// Maximum number of tokens.
+    capacity: f64,
+    /// Available tokens, negative if tokens have been reserved in advance.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        // Allow bursts of one second worth of tokens.
+        let capacity = rate as f64;
+        Self {
+            rate: rate as f64,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Takes the tokens if available.
+    fn try_consume(&mut self, amount: u64) -> bool {
+        self.refill();
+        if self.tokens >= amount as f64 {
+            self.tokens -= amount as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes the tokens unconditionally, returns how long to wait until the debt is repaid.
+    fn reserve(&mut self, amount: u64) -> Duration {
+        self.refill();
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Rate limiter shared by the connections.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter(Arc<Mutex<TokenBucket>>);
+
+impl RateLimiter {
+    /// Constructs a new limiter of `rate` units per second.
+    pub(crate) fn new(rate: u64) -> Self {
+        Self(Arc::new(Mutex::new(TokenBucket::new(rate.max(1)))))
+    }
+
+    /// Waits until `amount` units can be transferred without exceeding the rate.
+    pub(crate) async fn throttle(&self, amount: u64) {
+        let delay = self.0.lock().reserve(amount);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Returns `true` if `amount` units are allowed now.
+    pub(crate) fn try_acquire(&self, amount: u64) -> bool {
+        self.0.lock().try_consume(amount)
+    }
+}
+
+/// Status of the upload target.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadTargetInfo {
+    /// Target in bytes per 24 hours.
+    pub target: u64,
+    /// Whether the target has been reached in the current cycle.
+    pub target_reached: bool,
+    /// Bytes left before the target is reached.
+    pub bytes_left_in_cycle: u64,
+    /// Seconds left in the current cycle.
+    pub time_left_in_cycle: u64,
+}
+
+/// Outbound traffic target per 24 hours.
+#[derive(Debug)]
+pub(crate) struct UploadTarget {
+    target: u64,
+    /// Total outbound bytes of the node.
+    total_bytes_outbound: Arc<AtomicU64>,
+    cycle_start: Instant,
+    /// Total outbound bytes at the start of the cycle.
+    cycle_start_bytes: u64,
+}
+
+impl UploadTarget {
+    pub(crate) fn new(target: u64, total_bytes_outbound: Arc<AtomicU64>) -> Self {
+        Self {
+            target,
+            cycle_start_bytes: total_bytes_outbound.load(Ordering::Relaxed),
+            total_bytes_outbound,
+            cycle_start: Instant::now(),
+        }
+    }
+
+    /// Returns the bytes sent in the current cycle, starting a new cycle if it's due.
+    fn bytes_sent_in_cycle(&mut self) -> u64 {
+        let total_bytes_outbound = self.total_bytes_outbound.load(Ordering::Relaxed);
+        if self.cycle_start.elapsed() >= UPLOAD_TARGET_TIMEFRAME {
+            self.cycle_start = Instant::now();
+            self.cycle_start_bytes = total_bytes_outbound;
+        }
+        total_bytes_outbound.saturating_sub(self.cycle_start_bytes)
+    }
+
+    /// Returns `true` if the bulk data should not be served anymore in the current cycle.
+    pub(crate) fn is_reached(&mut self) -> bool {
+        self.bytes_sent_in_cycle() >= self.target
+    }
+
+    pub(crate) fn info(&mut self) -> UploadTargetInfo {
+        let sent = self.bytes_sent_in_cycle();
+        UploadTargetInfo {
+            target: self.target,
+            target_reached: sent >= self.target,
+            bytes_left_in_cycle: self.target.saturating_sub(sent),
+            time_left_in_cycle: UPLOAD_TARGET_TIMEFRAME
+                .saturating_sub(self.cycle_start.elapsed())
+                .as_secs(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(100);
+        assert!(bucket.try_consume(60));
+        assert!(!bucket.try_consume(60));
+        assert_eq!(bucket.reserve(40), Duration::ZERO);
+        let delay = bucket.reserve(50);
+        assert!(delay > Duration::from_millis(400) && delay <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_upload_target() {
+        let total_bytes_outbound = Arc::new(AtomicU64::new(500));
+        let mut upload_target = UploadTarget::new(1000, total_bytes_outbound.clone());
+
+        total_bytes_outbound.store(1200, Ordering::Relaxed);
+        let info = upload_target.info();
+        assert!(!info.target_reached);
+        assert_eq!(info.bytes_left_in_cycle, 300);
+
+        total_bytes_outbound.store(1499, Ordering::Relaxed);
+        assert!(!upload_target.is_reached());
+        total_bytes_outbound.store(1500, Ordering::Relaxed);
+        assert!(upload_target.is_reached());
+    }
+}
@@ -0,0 +1,790 @@
+//! Snapshot sync.
+//!
+//! A fresh node can download the state at a recent finalized block from the other subcoin
+//! nodes serving the snapshots, instead of downloading and executing the entire history:
+//!
+//! 1. Discover the snapshots announced by the peers offering [`NODE_SNAPSHOT`], the snapshot
+//!    announced by at least `quorum` peers is chosen.
+//! 2. Download and verify the headers up to the snapshot block from the Bitcoin network.
+//! 3. Download the manifest of the snapshot, which must match the announced root.
+//! 4. Download the chunks from all the peers announcing the snapshot, each chunk is verified
+//!    against the manifest on arrival.
+//! 5. Import the snapshot block along with the state, then continue with the block sync.
+//!
+//! The verified chunks are staged on disk by the [`SnapshotStore`] until the import.
+//!
+//! [`NODE_SNAPSHOT`]: crate::NODE_SNAPSHOT
+
+mod message;
+
+pub(crate) use self::message::SnapshotMessage;
+
+use crate::sync::{LocatorRequest, PeerSync, SyncAction, SyncRequest};
+use crate::{Error, PeerId, SyncStatus};
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::{BlockHash, Work};
+use sc_client_api::AuxStore;
+use sc_consensus_nakamoto::{HeaderProvider, HeaderVerifier};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use subcoin_primitives::{BackendExt, IndexedBlock};
+use subcoin_snapshot::{
+    CompressedChunk, DownloadedSnapshot, SnapshotChunk, SnapshotInfo, SnapshotManifest,
+    SnapshotStore, ANCESTOR_HEADERS, TOTAL_CHUNKS,
+};
+use tokio::sync::oneshot;
+
+// https://developer.bitcoin.org/reference/p2p_networking.html#headers
+const MAX_HEADERS_SIZE: usize = 2000;
+
+/// Time to wait for the snapshot announcements before falling back to the block sync.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Timeout of the headers, manifest and chunk requests.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum number of the chunks requested from a peer at the same time.
+const MAX_CHUNK_REQUESTS_PER_PEER: usize = 16;
+
+/// Maximum decompressed size of a chunk, no uncompressed chunk can exceed the message size.
+const MAX_DECOMPRESSED_CHUNK_SIZE: usize = 4 * bitcoin::p2p::message::MAX_MSG_SIZE;
+
+/// Recent headers leading to the snapshot block.
+///
+/// Only the last [`ANCESTOR_HEADERS`] headers are kept, which are enough for verifying the
+/// next header.
+struct HeaderChain {
+    tip: IndexedBlock,
+    chain_work: Work,
+    recent: VecDeque<BitcoinHeader>,
+    headers: HashMap<BlockHash, BitcoinHeader>,
+}
+
+impl HeaderChain {
+    fn new(genesis: BitcoinHeader, genesis_work: Work) -> Self {
+        let genesis_hash = genesis.block_hash();
+        Self {
+            tip: IndexedBlock {
+                number: 0,
+                hash: genesis_hash,
+            },
+            chain_work: genesis_work,
+            recent: VecDeque::from([genesis]),
+            headers: HashMap::from([(genesis_hash, genesis)]),
+        }
+    }
+
+    fn push(&mut self, header: BitcoinHeader) {
+        let block_hash = header.block_hash();
+
+        self.tip = IndexedBlock {
+            number: self.tip.number + 1,
+            hash: block_hash,
+        };
+        self.chain_work = self.chain_work + header.work();
+        self.recent.push_back(header);
+        self.headers.insert(block_hash, header);
+
+        // Keep the tip and its ancestors.
+        if self.recent.len() > ANCESTOR_HEADERS + 1 {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.headers.remove(&oldest.block_hash());
+            }
+        }
+    }
+
+    /// Returns the headers prior to the tip.
+    fn ancestors(&self) -> Vec<BitcoinHeader> {
+        self.recent
+            .iter()
+            .take(self.recent.len().saturating_sub(1))
+            .copied()
+            .collect()
+    }
+}
+
+impl HeaderProvider for HeaderChain {
+    fn header(&self, block_hash: BlockHash) -> Option<BitcoinHeader> {
+        self.headers.get(&block_hash).copied()
+    }
+}
+
+/// Chunks being downloaded.
+struct ChunkDownload {
+    manifest: SnapshotManifest,
+    pending: VecDeque<u32>,
+    in_flight: HashMap<u32, (PeerId, Instant)>,
+    downloaded: u32,
+}
+
+impl ChunkDownload {
+    fn new(manifest: SnapshotManifest) -> Self {
+        Self {
+            manifest,
+            pending: (0..TOTAL_CHUNKS).collect(),
+            in_flight: HashMap::new(),
+            downloaded: 0,
+        }
+    }
+
+    fn in_flight_count(&self, peer_id: PeerId) -> usize {
+        self.in_flight
+            .values()
+            .filter(|(from, _)| *from == peer_id)
+            .count()
+    }
+
+    /// Reschedules the chunks requested from the peer.
+    fn reschedule(&mut self, peer_id: PeerId) {
+        let indexes = self
+            .in_flight
+            .iter()
+            .filter_map(|(index, (from, _))| (*from == peer_id).then_some(*index))
+            .collect::<Vec<_>>();
+        for index in indexes {
+            self.in_flight.remove(&index);
+            self.pending.push_front(index);
+        }
+    }
+}
+
+enum State {
+    /// Waiting for enough peers announcing the same snapshot.
+    Discovering { started_at: Instant },
+    /// Downloading the headers up to the snapshot block.
+    DownloadingHeaders {
+        target: SnapshotInfo,
+        peer: Option<(PeerId, Instant)>,
+    },
+    /// Downloading the manifest of the snapshot.
+    DownloadingManifest {
+        target: SnapshotInfo,
+        peer: Option<(PeerId, Instant)>,
+    },
+    /// Downloading the chunks of the snapshot.
+    DownloadingChunks {
+        target: SnapshotInfo,
+        download: Box<ChunkDownload>,
+    },
+    /// Importing the downloaded snapshot in the background.
+    Importing {
+        target: SnapshotInfo,
+        result_receiver: oneshot::Receiver<Result<(), subcoin_snapshot::Error>>,
+    },
+    /// The snapshot sync is over, either imported or given up.
+    Finished,
+}
+
+/// Snapshot sync.
+pub(crate) struct SnapshotSync<Block, Client> {
+    header_verifier: HeaderVerifier<Block, Client>,
+    store: Arc<dyn SnapshotStore>,
+    /// Minimum number of the peers announcing the same snapshot.
+    quorum: usize,
+    /// Snapshots announced by the peers.
+    announcements: HashMap<PeerId, Option<SnapshotInfo>>,
+    /// Peers failed to provide the snapshot data.
+    rejected: HashSet<PeerId>,
+    header_chain: HeaderChain,
+    state: State,
+}
+
+impl<Block, Client> SnapshotSync<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    /// Constructs a new instance of [`SnapshotSync`].
+    pub(crate) fn new(
+        client: &Arc<Client>,
+        header_verifier: HeaderVerifier<Block, Client>,
+        store: Arc<dyn SnapshotStore>,
+        quorum: usize,
+    ) -> Self {
+        let genesis_hash = client.block_hash(0).expect("Genesis block must exist; qed");
+        let genesis = client
+            .block_header(genesis_hash)
+            .expect("Genesis header must exist; qed");
+        let genesis_work = client
+            .chain_work(genesis_hash)
+            .unwrap_or_else(|| genesis.work());
+
+        Self {
+            header_verifier,
+            store,
+            quorum: quorum.max(1),
+            announcements: HashMap::new(),
+            rejected: HashSet::new(),
+            header_chain: HeaderChain::new(genesis, genesis_work),
+            state: State::Discovering {
+                started_at: Instant::now(),
+            },
+        }
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        matches!(self.state, State::Finished)
+    }
+
+    pub(crate) fn sync_status(&self) -> SyncStatus {
+        let (target, downloaded_chunks) = match &self.state {
+            State::Discovering { .. } | State::Finished => (None, 0),
+            State::DownloadingHeaders { target, .. }
+            | State::DownloadingManifest { target, .. } => (Some(target.height), 0),
+            State::DownloadingChunks { target, download } => {
+                (Some(target.height), download.downloaded)
+            }
+            State::Importing { target, .. } => (Some(target.height), TOTAL_CHUNKS),
+        };
+
+        SyncStatus::SnapshotSyncing {
+            target,
+            downloaded_chunks,
+            total_chunks: TOTAL_CHUNKS,
+        }
+    }
+
+    /// Queries the snapshot of a new peer offering the snapshots.
+    pub(crate) fn add_snapshot_peer(&mut self, peer_id: PeerId) -> SyncAction {
+        self.announcements.insert(peer_id, None);
+        SyncAction::Request(SyncRequest::Snapshot(vec![(
+            peer_id,
+            SnapshotMessage::GetSnapshotInfo,
+        )]))
+    }
+
+    /// Returns the number of the snapshot requests to the peer not answered yet.
+    pub(crate) fn requests_in_flight(&self, peer_id: PeerId) -> usize {
+        match &self.state {
+            State::DownloadingHeaders { peer, .. } | State::DownloadingManifest { peer, .. } => {
+                usize::from(peer.is_some_and(|(from, _)| from == peer_id))
+            }
+            State::DownloadingChunks { download, .. } => download.in_flight_count(peer_id),
+            State::Discovering { .. } | State::Importing { .. } | State::Finished => 0,
+        }
+    }
+
+    pub(crate) fn remove_peer(&mut self, peer_id: PeerId) {
+        self.announcements.remove(&peer_id);
+
+        match &mut self.state {
+            State::DownloadingHeaders { peer, .. } | State::DownloadingManifest { peer, .. } => {
+                if peer.is_some_and(|(from, _)| from == peer_id) {
+                    peer.take();
+                }
+            }
+            State::DownloadingChunks { download, .. } => download.reschedule(peer_id),
+            State::Discovering { .. } | State::Importing { .. } | State::Finished => {}
+        }
+    }
+
+    pub(crate) fn on_tick(&mut self, peers: &HashMap<PeerId, PeerSync>) -> SyncAction {
+        match &mut self.state {
+            State::Discovering { started_at } => {
+                if started_at.elapsed() > DISCOVERY_TIMEOUT {
+                    tracing::info!(
+                        "No snapshot announced by {} peers, falling back to block sync",
+                        self.quorum
+                    );
+                    self.state = State::Finished;
+                }
+                SyncAction::None
+            }
+            State::DownloadingHeaders { target, peer } => {
+                if peer.is_some_and(|(_, requested_at)| requested_at.elapsed() > REQUEST_TIMEOUT) {
+                    if let Some((stalled, _)) = peer.take() {
+                        self.rejected.insert(stalled);
+                    }
+                }
+
+                if peer.is_some() {
+                    return SyncAction::None;
+                }
+
+                let target = *target;
+                self.request_headers(target, peers)
+            }
+            State::DownloadingManifest { target, peer } => {
+                if peer.is_some_and(|(_, requested_at)| requested_at.elapsed() > REQUEST_TIMEOUT) {
+                    if let Some((stalled, _)) = peer.take() {
+                        self.rejected.insert(stalled);
+                    }
+                }
+
+                if peer.is_some() {
+                    return SyncAction::None;
+                }
+
+                let target = *target;
+                self.request_manifest(target)
+            }
+            State::DownloadingChunks { download, .. } => {
+                let timed_out = download
+                    .in_flight
+                    .iter()
+                    .filter_map(|(index, (_, requested_at))| {
+                        (requested_at.elapsed() > REQUEST_TIMEOUT).then_some(*index)
+                    })
+                    .collect::<Vec<_>>();
+
+                for index in timed_out {
+                    download.in_flight.remove(&index);
+                    download.pending.push_front(index);
+                }
+
+                self.request_chunks()
+            }
+            State::Importing {
+                target,
+                result_receiver,
+            } => {
+                match result_receiver.try_recv() {
+                    Ok(Ok(())) => {
+                        tracing::info!(
+                            "📦 Imported snapshot at #{},{}, continuing with block sync",
+                            target.height,
+                            target.block_hash
+                        );
+                        self.state = State::Finished;
+                    }
+                    Ok(Err(err)) => {
+                        tracing::error!(
+                            ?err,
+                            "Failed to import snapshot, falling back to block sync"
+                        );
+                        self.state = State::Finished;
+                    }
+                    Err(oneshot::error::TryRecvError::Empty) => {}
+                    Err(oneshot::error::TryRecvError::Closed) => {
+                        tracing::error!(
+                            "Snapshot import was interrupted, falling back to block sync"
+                        );
+                        self.state = State::Finished;
+                    }
+                }
+                SyncAction::None
+            }
+            State::Finished => SyncAction::None,
+        }
+    }
+
+    pub(crate) fn on_snapshot_message(
+        &mut self,
+        from: PeerId,
+        message: SnapshotMessage,
+        peers: &HashMap<PeerId, PeerSync>,
+    ) -> SyncAction {
+        match message {
+            SnapshotMessage::SnapshotInfo(info) => {
+                if !self.announcements.contains_key(&from) {
+                    return SyncAction::None;
+                }
+                self.announcements.insert(from, info);
+
+                if !matches!(self.state, State::Discovering { .. }) {
+                    return SyncAction::None;
+                }
+
+                match self.select_snapshot() {
+                    Some(target) => {
+                        tracing::info!(
+                            "📦 Starting snapshot sync at #{},{}",
+                            target.height,
+                            target.block_hash
+                        );
+                        self.state = State::DownloadingHeaders { target, peer: None };
+                        self.request_headers(target, peers)
+                    }
+                    None => SyncAction::None,
+                }
+            }
+            SnapshotMessage::Manifest(manifest) => self.on_manifest(from, manifest),
+            SnapshotMessage::Chunk(chunk) => self.on_chunk(from, chunk),
+            SnapshotMessage::CompressedChunk(chunk) => self.on_compressed_chunk(from, chunk),
+            SnapshotMessage::GetSnapshotInfo
+            | SnapshotMessage::GetManifest(_)
+            | SnapshotMessage::GetChunk { .. } => SyncAction::None,
+        }
+    }
+
+    pub(crate) fn on_headers(&mut self, headers: Vec<BitcoinHeader>, from: PeerId) -> SyncAction {
+        let State::DownloadingHeaders { target, peer } = &mut self.state else {
+            return SyncAction::None;
+        };
+
+        if !peer.is_some_and(|(sync_peer, _)| sync_peer == from) {
+            return SyncAction::None;
+        }
+
+        if headers.len() > MAX_HEADERS_SIZE {
+            return SyncAction::Disconnect(from, Error::TooManyHeaders);
+        }
+
+        let target = *target;
+
+        if headers.is_empty() {
+            // The peer does not have the snapshot block, try another one.
+            peer.take();
+            self.rejected.insert(from);
+            return SyncAction::None;
+        }
+
+        let mut headers = headers;
+        headers.truncate(target.height.saturating_sub(self.header_chain.tip.number) as usize);
+
+        let mut prev_hash = self.header_chain.tip.hash;
+        for header in &headers {
+            if header.prev_blockhash != prev_hash {
+                return SyncAction::Disconnect(from, Error::HeadersNotInAscendingOrder);
+            }
+            prev_hash = header.block_hash();
+        }
+
+        if let Err((index, err)) = self.header_verifier.verify_headers_parallel(
+            &headers,
+            self.header_chain.tip.number,
+            &self.header_chain,
+        ) {
+            return SyncAction::Disconnect(
+                from,
+                Error::BadHeader(headers[index].block_hash(), err),
+            );
+        }
+
+        for header in headers {
+            self.header_chain.push(header);
+        }
+
+        if self.header_chain.tip.number < target.height {
+            peer.replace((from, Instant::now()));
+            return self.headers_request(from, target);
+        }
+
+        if self.header_chain.tip.hash != target.block_hash {
+            tracing::warn!(
+                "Snapshot block #{},{} is not in the best chain, falling back to block sync",
+                target.height,
+                target.block_hash
+            );
+            self.state = State::Finished;
+            return SyncAction::None;
+        }
+
+        self.state = State::DownloadingManifest { target, peer: None };
+        self.request_manifest(target)
+    }
+
+    /// Returns the most recent snapshot announced by enough peers.
+    fn select_snapshot(&self) -> Option<SnapshotInfo> {
+        let mut votes = HashMap::<SnapshotInfo, usize>::new();
+        for info in self.announcements.values().flatten() {
+            *votes.entry(*info).or_default() += 1;
+        }
+
+        votes
+            .into_iter()
+            .filter(|(_, count)| *count >= self.quorum)
+            .map(|(info, _)| info)
+            .max_by_key(|info| info.height)
+    }
+
+    /// Returns the peers announcing the target snapshot.
+    fn providers(&self, target: SnapshotInfo) -> Vec<PeerId> {
+        self.announcements
+            .iter()
+            .filter(|(peer_id, info)| {
+                info.as_ref() == Some(&target) && !self.rejected.contains(peer_id)
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    fn fall_back_if_no_providers(&mut self, target: SnapshotInfo) -> bool {
+        if self.providers(target).is_empty() {
+            tracing::info!(
+                "No peer is able to provide snapshot #{}, falling back to block sync",
+                target.height
+            );
+            self.state = State::Finished;
+            return true;
+        }
+        false
+    }
+
+    /// Requests the headers from a new peer chosen from `peers`.
+    fn request_headers(
+        &mut self,
+        target: SnapshotInfo,
+        peers: &HashMap<PeerId, PeerSync>,
+    ) -> SyncAction {
+        let State::DownloadingHeaders { peer, .. } = &mut self.state else {
+            return SyncAction::None;
+        };
+
+        let Some(new_peer) = peers
+            .values()
+            .filter(|peer| {
+                peer.best_number >= target.height && !self.rejected.contains(&peer.peer_id)
+            })
+            .min_by_key(|peer| peer.latency)
+        else {
+            tracing::debug!("Waiting for a peer to download the headers of the snapshot");
+            return SyncAction::None;
+        };
+
+        let peer_id = new_peer.peer_id;
+        peer.replace((peer_id, Instant::now()));
+
+        self.headers_request(peer_id, target)
+    }
+
+    fn headers_request(&self, peer_id: PeerId, target: SnapshotInfo) -> SyncAction {
+        SyncAction::Request(SyncRequest::Headers(LocatorRequest {
+            locator_hashes: vec![self.header_chain.tip.hash],
+            stop_hash: target.block_hash,
+            from: peer_id,
+        }))
+    }
+
+    fn request_manifest(&mut self, target: SnapshotInfo) -> SyncAction {
+        if self.fall_back_if_no_providers(target) {
+            return SyncAction::None;
+        }
+
+        let providers = self.providers(target);
+
+        let State::DownloadingManifest { peer, .. } = &mut self.state else {
+            return SyncAction::None;
+        };
+
+        let peer_id = providers[fastrand::usize(..providers.len())];
+        peer.replace((peer_id, Instant::now()));
+
+        SyncAction::Request(SyncRequest::Snapshot(vec![(
+            peer_id,
+            SnapshotMessage::GetManifest(target.block_hash),
+        )]))
+    }
+
+    fn on_manifest(&mut self, from: PeerId, manifest: Option<SnapshotManifest>) -> SyncAction {
+        let State::DownloadingManifest { target, peer } = &mut self.state else {
+            return SyncAction::None;
+        };
+
+        if !peer.is_some_and(|(manifest_peer, _)| manifest_peer == from) {
+            return SyncAction::None;
+        }
+
+        let target = *target;
+        peer.take();
+
+        let Some(manifest) = manifest else {
+            self.rejected.insert(from);
+            return self.request_manifest(target);
+        };
+
+        if manifest.info() != target || !manifest.is_complete() {
+            self.rejected.insert(from);
+            return SyncAction::Disconnect(from, Error::InvalidSnapshotManifest);
+        }
+
+        self.state = State::DownloadingChunks {
+            target,
+            download: Box::new(ChunkDownload::new(manifest)),
+        };
+
+        self.request_chunks()
+    }
+
+    fn request_chunks(&mut self) -> SyncAction {
+        let State::DownloadingChunks { target, .. } = &self.state else {
+            return SyncAction::None;
+        };
+
+        let target = *target;
+
+        if self.fall_back_if_no_providers(target) {
+            return SyncAction::None;
+        }
+
+        let providers = self.providers(target);
+
+        let State::DownloadingChunks { download, .. } = &mut self.state else {
+            return SyncAction::None;
+        };
+
+        let mut requests = Vec::new();
+
+        for peer_id in providers {
+            let available =
+                MAX_CHUNK_REQUESTS_PER_PEER.saturating_sub(download.in_flight_count(peer_id));
+
+            for _ in 0..available {
+                let Some(index) = download.pending.pop_front() else {
+                    break;
+                };
+                download.in_flight.insert(index, (peer_id, Instant::now()));
+                requests.push((
+                    peer_id,
+                    SnapshotMessage::GetChunk {
+                        block_hash: target.block_hash,
+                        index,
+                    },
+                ));
+            }
+        }
+
+        if requests.is_empty() {
+            SyncAction::None
+        } else {
+            SyncAction::Request(SyncRequest::Snapshot(requests))
+        }
+    }
+
+    /// Returns the chunk download if the chunk at `index` is requested from the peer.
+    fn requested_chunk(&mut self, from: PeerId, index: u32) -> Option<&mut ChunkDownload> {
+        let State::DownloadingChunks { download, .. } = &mut self.state else {
+            return None;
+        };
+
+        if !download
+            .in_flight
+            .get(&index)
+            .is_some_and(|(peer_id, _)| *peer_id == from)
+        {
+            tracing::debug!(?from, "Ignoring unrequested snapshot chunk {index}");
+            return None;
+        }
+
+        Some(download)
+    }
+
+    fn reject_chunk(&mut self, from: PeerId, index: u32) -> SyncAction {
+        if let State::DownloadingChunks { download, .. } = &mut self.state {
+            download.in_flight.remove(&index);
+            download.pending.push_front(index);
+            download.reschedule(from);
+        }
+        self.rejected.insert(from);
+        SyncAction::Disconnect(from, Error::InvalidSnapshotChunk(index))
+    }
+
+    fn on_compressed_chunk(&mut self, from: PeerId, chunk: CompressedChunk) -> SyncAction {
+        let index = chunk.index;
+
+        // Check the request before paying for the decompression.
+        if self.requested_chunk(from, index).is_none() {
+            return SyncAction::None;
+        }
+
+        match chunk.decompress(MAX_DECOMPRESSED_CHUNK_SIZE) {
+            Ok(chunk) => self.on_chunk(from, chunk),
+            Err(err) => {
+                tracing::debug!(?from, ?err, "Invalid compressed snapshot chunk {index}");
+                self.reject_chunk(from, index)
+            }
+        }
+    }
+
+    fn on_chunk(&mut self, from: PeerId, chunk: SnapshotChunk) -> SyncAction {
+        let index = chunk.index;
+
+        let Some(download) = self.requested_chunk(from, index) else {
+            return SyncAction::None;
+        };
+
+        if !download.manifest.verify_chunk(&chunk) {
+            return self.reject_chunk(from, index);
+        }
+
+        download.in_flight.remove(&index);
+        download.downloaded += 1;
+        let downloaded = download.downloaded;
+
+        if let Err(err) = self.store.stage_chunk(&chunk) {
+            tracing::error!(
+                ?err,
+                "Failed to stage snapshot chunk {index}, falling back to block sync"
+            );
+            self.state = State::Finished;
+            return SyncAction::None;
+        }
+
+        if downloaded < TOTAL_CHUNKS {
+            return self.request_chunks();
+        }
+
+        self.start_import();
+
+        SyncAction::None
+    }
+
+    fn start_import(&mut self) {
+        let State::DownloadingChunks { target, download } =
+            std::mem::replace(&mut self.state, State::Finished)
+        else {
+            return;
+        };
+
+        let snapshot = DownloadedSnapshot {
+            manifest: download.manifest,
+            chain_work: self.header_chain.chain_work,
+            ancestors: self.header_chain.ancestors(),
+        };
+
+        tracing::info!(
+            "📦 Downloaded all {TOTAL_CHUNKS} chunks of snapshot #{}, importing",
+            target.height
+        );
+
+        let (result_sender, result_receiver) = oneshot::channel();
+        let store = self.store.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let _ = result_sender.send(store.import_snapshot(snapshot));
+        });
+
+        self.state = State::Importing {
+            target,
+            result_receiver,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_chain_keeps_recent_headers() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header;
+        let mut header_chain = HeaderChain::new(genesis, genesis.work());
+
+        let mut prev_blockhash = genesis.block_hash();
+        for nonce in 0..ANCESTOR_HEADERS as u32 + 10 {
+            let header = BitcoinHeader {
+                prev_blockhash,
+                nonce,
+                ..genesis
+            };
+            prev_blockhash = header.block_hash();
+            header_chain.push(header);
+        }
+
+        assert_eq!(header_chain.tip.number, ANCESTOR_HEADERS as u32 + 10);
+        assert_eq!(header_chain.tip.hash, prev_blockhash);
+        assert_eq!(header_chain.recent.len(), ANCESTOR_HEADERS + 1);
+        assert_eq!(header_chain.headers.len(), ANCESTOR_HEADERS + 1);
+        assert!(header_chain.header(genesis.block_hash()).is_none());
+
+        let ancestors = header_chain.ancestors();
+        assert_eq!(ancestors.len(), ANCESTOR_HEADERS);
+        assert_eq!(
+            ancestors.last().unwrap().block_hash(),
+            header_chain.recent[ANCESTOR_HEADERS - 1].block_hash()
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::message::{CommandString, NetworkMessage};
+use bitcoin::BlockHash;
+use codec::{DecodeAll, Encode};
+use subcoin_snapshot::{CompressedChunk, SnapshotChunk, SnapshotInfo, SnapshotManifest};
+
+const GET_SNAPSHOT_INFO: &str = "getsnapinfo";
+const SNAPSHOT_INFO: &str = "snapinfo";
+const GET_MANIFEST: &str = "getsnapmfst";
+const MANIFEST: &str = "snapmfst";
+const GET_CHUNK: &str = "getsnapchunk";
+const CHUNK: &str = "snapchunk";
+const COMPRESSED_CHUNK: &str = "snapzchunk";
+
+/// Messages of the snapshot protocol.
+///
+/// The messages are transferred as the Bitcoin p2p messages of the custom commands, which
+/// are ignored by the other Bitcoin nodes. The payloads are SCALE-encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SnapshotMessage {
+    /// Request the latest snapshot of the peer.
+    GetSnapshotInfo,
+    /// The latest snapshot, `None` if the peer has no snapshot.
+    SnapshotInfo(Option<SnapshotInfo>),
+    /// Request the manifest of the snapshot at given block.
+    GetManifest(BlockHash),
+    /// The requested manifest, `None` if not found.
+    Manifest(Option<SnapshotManifest>),
+    /// Request a chunk of the snapshot at given block.
+    GetChunk { block_hash: BlockHash, index: u32 },
+    /// The requested chunk.
+    Chunk(SnapshotChunk),
+    /// The requested chunk, compressed for the peers supporting
+    /// [`SubcoinFeatures::COMPRESSED_CHUNKS`].
+    ///
+    /// [`SubcoinFeatures::COMPRESSED_CHUNKS`]: crate::SubcoinFeatures::COMPRESSED_CHUNKS
+    CompressedChunk(CompressedChunk),
+}
+
+impl SnapshotMessage {
+    fn command(&self) -> &'static str {
+        match self {
+            Self::GetSnapshotInfo => GET_SNAPSHOT_INFO,
+            Self::SnapshotInfo(_) => SNAPSHOT_INFO,
+            Self::GetManifest(_) => GET_MANIFEST,
+            Self::Manifest(_) => MANIFEST,
+            Self::GetChunk { .. } => GET_CHUNK,
+            Self::Chunk(_) => CHUNK,
+            Self::CompressedChunk(_) => COMPRESSED_CHUNK,
+        }
+    }
+
+    /// Returns `true` if the message is a request served by the snapshot providers.
+    pub(crate) fn is_request(&self) -> bool {
+        matches!(
+            self,
+            Self::GetSnapshotInfo | Self::GetManifest(_) | Self::GetChunk { .. }
+        )
+    }
+
+    pub(crate) fn into_network_message(self) -> NetworkMessage {
+        let command = CommandString::try_from_static(self.command())
+            .expect("Snapshot commands are valid; qed");
+
+        let payload = match self {
+            Self::GetSnapshotInfo => Vec::new(),
+            Self::SnapshotInfo(info) => info.encode(),
+            Self::GetManifest(block_hash) => block_hash.to_byte_array().encode(),
+            Self::Manifest(manifest) => manifest.encode(),
+            Self::GetChunk { block_hash, index } => (block_hash.to_byte_array(), index).encode(),
+            Self::Chunk(chunk) => chunk.encode(),
+            Self::CompressedChunk(chunk) => chunk.encode(),
+        };
+
+        NetworkMessage::Unknown { command, payload }
+    }
+
+    /// Decodes the snapshot message from the payload of an unknown command.
+    ///
+    /// Returns `None` if the command is not part of the snapshot protocol.
+    pub(crate) fn decode(
+        command: &CommandString,
+        mut payload: &[u8],
+    ) -> Option<Result<Self, codec::Error>> {
+        let payload = &mut payload;
+
+        let message = match command.as_ref() {
+            GET_SNAPSHOT_INFO => {
+                if payload.is_empty() {
+                    Ok(Self::GetSnapshotInfo)
+                } else {
+                    Err("Unexpected payload".into())
+                }
+            }
+            SNAPSHOT_INFO => DecodeAll::decode_all(payload).map(Self::SnapshotInfo),
+            GET_MANIFEST => <[u8; 32]>::decode_all(payload)
+                .map(|hash| Self::GetManifest(BlockHash::from_byte_array(hash))),
+            MANIFEST => DecodeAll::decode_all(payload).map(Self::Manifest),
+            GET_CHUNK => {
+                <([u8; 32], u32)>::decode_all(payload).map(|(hash, index)| Self::GetChunk {
+                    block_hash: BlockHash::from_byte_array(hash),
+                    index,
+                })
+            }
+            CHUNK => DecodeAll::decode_all(payload).map(Self::Chunk),
+            COMPRESSED_CHUNK => DecodeAll::decode_all(payload).map(Self::CompressedChunk),
+            _ => return None,
+        };
+
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_message_roundtrip() {
+        let block_hash = BlockHash::from_byte_array([1u8; 32]);
+
+        let messages = vec![
+            SnapshotMessage::GetSnapshotInfo,
+            SnapshotMessage::SnapshotInfo(None),
+            SnapshotMessage::SnapshotInfo(Some(SnapshotInfo {
+                block_hash,
+                height: 2016,
+                root: [2u8; 32],
+            })),
+            SnapshotMessage::GetManifest(block_hash),
+            SnapshotMessage::Manifest(Some(SnapshotManifest {
+                block_hash,
+                height: 2016,
+                header: vec![3u8; 10],
+                chunk_hashes: vec![[4u8; 32]; 3],
+            })),
+            SnapshotMessage::GetChunk {
+                block_hash,
+                index: 5,
+            },
+            SnapshotMessage::Chunk(SnapshotChunk {
+                index: 5,
+                entries: vec![(vec![6u8], vec![7u8])],
+            }),
+            SnapshotMessage::CompressedChunk(
+                SnapshotChunk {
+                    index: 5,
+                    entries: vec![(vec![6u8], vec![7u8])],
+                }
+                .compress(subcoin_snapshot::DEFAULT_CHUNK_COMPRESSION_LEVEL)
+                .unwrap(),
+            ),
+        ];
+
+        for message in messages {
+            let NetworkMessage::Unknown { command, payload } =
+                message.clone().into_network_message()
+            else {
+                panic!("Snapshot message must be sent as unknown message");
+            };
+            assert_eq!(
+                SnapshotMessage::decode(&command, &payload)
+                    .unwrap()
+                    .unwrap(),
+                message
+            );
+        }
+
+        let unknown = CommandString::try_from_static("foo").unwrap();
+        assert!(SnapshotMessage::decode(&unknown, &[]).is_none());
+
+        // Trailing bytes.
+        let get_chunk = CommandString::try_from_static(GET_CHUNK).unwrap();
+        assert!(SnapshotMessage::decode(&get_chunk, &[0u8; 37])
+            .unwrap()
+            .is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Detection of the wedged sync.
+//!
+//! The sync is considered stalled once the best block hasn't advanced for the configured
+//! period while the peers report higher tips. The state is exported as the
+//! `subcoin_sync_stalled` gauge, which the alerting rules can fire on directly.
+
+use crate::metrics::Metrics;
+use std::time::{Duration, Instant};
+
+/// Outcome of a stall check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StallCheck {
+    /// The sync is progressing or there is nothing to sync.
+    Progressing,
+    /// The sync has been stalled for another timeout period.
+    Stalled { best_number: u32, peer_best: u32 },
+    /// The sync was stalled and is still, the timeout period since the last report is not
+    /// over yet.
+    StillStalled,
+}
+
+/// Watchdog of the sync progress.
+#[derive(Debug)]
+pub(crate) struct StallDetector {
+    timeout: Duration,
+    best_number: u32,
+    last_progress_at: Instant,
+    /// Time of the last stall report, `None` if not stalled.
+    last_report_at: Option<Instant>,
+}
+
+impl StallDetector {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            best_number: 0,
+            last_progress_at: Instant::now(),
+            last_report_at: None,
+        }
+    }
+
+    /// Checks the sync progress with the best block number and the highest tip of the peers.
+    pub(crate) fn check(
+        &mut self,
+        best_number: u32,
+        peer_best: Option<u32>,
+        now: Instant,
+    ) -> StallCheck {
+        let behind = peer_best.is_some_and(|peer_best| peer_best > best_number);
+
+        if best_number > self.best_number || !behind {
+            if self.is_stalled() && best_number > self.best_number {
+                tracing::info!("✅ Sync resumed at #{best_number}");
+            }
+            self.best_number = best_number;
+            self.last_progress_at = now;
+            self.last_report_at = None;
+            return StallCheck::Progressing;
+        }
+
+        // Reported again after another timeout period if the sync is still stalled.
+        let since = self.last_report_at.unwrap_or(self.last_progress_at);
+        if now.duration_since(since) < self.timeout {
+            return if self.is_stalled() {
+                StallCheck::StillStalled
+            } else {
+                StallCheck::Progressing
+            };
+        }
+
+        self.last_report_at = Some(now);
+
+        StallCheck::Stalled {
+            best_number,
+            peer_best: peer_best.unwrap_or_default(),
+        }
+    }
+
+    /// Returns whether the sync is stalled.
+    pub(crate) fn is_stalled(&self) -> bool {
+        self.last_report_at.is_some()
+    }
+
+    pub(crate) fn report_metrics(&self, metrics: &Metrics, now: Instant) {
+        metrics.sync_stalled.set(u64::from(self.is_stalled()));
+        metrics
+            .sync_seconds_since_progress
+            .set(now.duration_since(self.last_progress_at).as_secs());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall_detection() {
+        let timeout = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut detector = StallDetector::new(timeout);
+
+        assert_eq!(
+            detector.check(10, Some(100), start),
+            StallCheck::Progressing
+        );
+        assert_eq!(
+            detector.check(10, Some(100), start + timeout / 2),
+            StallCheck::Progressing
+        );
+        assert_eq!(
+            detector.check(10, Some(100), start + timeout),
+            StallCheck::Stalled {
+                best_number: 10,
+                peer_best: 100
+            }
+        );
+        assert!(detector.is_stalled());
+        assert_eq!(
+            detector.check(10, Some(100), start + timeout + timeout / 2),
+            StallCheck::StillStalled
+        );
+        assert!(matches!(
+            detector.check(10, Some(100), start + timeout * 2),
+            StallCheck::Stalled { .. }
+        ));
+
+        assert_eq!(
+            detector.check(11, Some(100), start + timeout * 3),
+            StallCheck::Progressing
+        );
+        assert!(!detector.is_stalled());
+
+        // Not stalled at the network tip.
+        assert_eq!(
+            detector.check(11, Some(11), start + timeout * 5),
+            StallCheck::Progressing
+        );
+        assert_eq!(
+            detector.check(11, None, start + timeout * 7),
+            StallCheck::Progressing
+        );
+    }
+}
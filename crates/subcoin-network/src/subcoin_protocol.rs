@@ -0,0 +1,170 @@
+//! Versioning of the subcoin-specific p2p protocol, e.g., the snapshot sync.
+//!
+//! Right after the Bitcoin handshake, a node sends a `subcoinver` message carrying its
+//! [`SubcoinVersion`] to the peers offering [`NODE_SNAPSHOT`], which reply with theirs. Both
+//! sides then use the lowest version and the features supported by both of them, so that a new
+//! protocol feature is only used with the peers understanding it.
+//!
+//! A peer offering [`NODE_SNAPSHOT`] without ever sending `subcoinver` predates the versioning
+//! and is assumed to speak [`SubcoinVersion::LEGACY`].
+//!
+//! [`NODE_SNAPSHOT`]: crate::NODE_SNAPSHOT
+
+use bitcoin::p2p::message::{CommandString, NetworkMessage};
+use codec::{Decode, Encode};
+
+const SUBCOIN_VERSION: &str = "subcoinver";
+
+/// Version of the subcoin p2p protocol implemented by this node.
+pub const SUBCOIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Features of the subcoin p2p protocol.
+///
+/// Unknown bits are kept as is, they are dropped by the negotiation since the local node does
+/// not support them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct SubcoinFeatures(u64);
+
+impl SubcoinFeatures {
+    /// No features.
+    pub const NONE: Self = Self(0);
+
+    /// The snapshot protocol: `getsnapinfo`, `getsnapmfst`, `getsnapchunk` and the responses.
+    pub const SNAPSHOT: Self = Self(1 << 0);
+
+    /// The snapshot chunks compressed with zstd, `snapzchunk` in response to `getsnapchunk`.
+    pub const COMPRESSED_CHUNKS: Self = Self(1 << 1);
+
+    /// Constructs the features from the raw bits.
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw bits.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns `true` if all the features of `other` are included.
+    pub const fn has(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the features included in both.
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Returns the features included in either.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Version and features of the subcoin p2p protocol, the payload of `subcoinver`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct SubcoinVersion {
+    pub version: u32,
+    pub features: SubcoinFeatures,
+}
+
+impl SubcoinVersion {
+    /// Protocol of the subcoin nodes before the versioning was introduced.
+    pub const LEGACY: Self = Self {
+        version: 0,
+        features: SubcoinFeatures::SNAPSHOT,
+    };
+
+    /// Protocol implemented by this node.
+    pub const LOCAL: Self = Self {
+        version: SUBCOIN_PROTOCOL_VERSION,
+        features: SubcoinFeatures::SNAPSHOT.union(SubcoinFeatures::COMPRESSED_CHUNKS),
+    };
+
+    /// Returns the protocol used with a peer speaking `remote`.
+    pub fn negotiate(self, remote: Self) -> Self {
+        Self {
+            version: self.version.min(remote.version),
+            features: self.features.intersection(remote.features),
+        }
+    }
+
+    pub(crate) fn into_network_message(self) -> NetworkMessage {
+        NetworkMessage::Unknown {
+            command: CommandString::try_from_static(SUBCOIN_VERSION)
+                .expect("Subcoin version command is valid; qed"),
+            payload: self.encode(),
+        }
+    }
+
+    /// Decodes the `subcoinver` message from the payload of an unknown command.
+    ///
+    /// Returns `None` if the command is not `subcoinver`.
+    ///
+    /// The trailing bytes are ignored so that the future versions can extend the payload.
+    pub(crate) fn decode(
+        command: &CommandString,
+        mut payload: &[u8],
+    ) -> Option<Result<Self, codec::Error>> {
+        (command.as_ref() == SUBCOIN_VERSION).then(|| Decode::decode(&mut payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subcoin_version_roundtrip() {
+        let NetworkMessage::Unknown {
+            command,
+            mut payload,
+        } = SubcoinVersion::LOCAL.into_network_message()
+        else {
+            panic!("Subcoin version must be sent as unknown message");
+        };
+        assert_eq!(
+            SubcoinVersion::decode(&command, &payload).unwrap().unwrap(),
+            SubcoinVersion::LOCAL
+        );
+
+        // Extended by a future version.
+        payload.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(
+            SubcoinVersion::decode(&command, &payload).unwrap().unwrap(),
+            SubcoinVersion::LOCAL
+        );
+
+        assert!(SubcoinVersion::decode(&command, &[0u8; 3])
+            .unwrap()
+            .is_err());
+
+        let unknown = CommandString::try_from_static("snapinfo").unwrap();
+        assert!(SubcoinVersion::decode(&unknown, &payload).is_none());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let future = SubcoinVersion {
+            version: SUBCOIN_PROTOCOL_VERSION + 1,
+            features: SubcoinVersion::LOCAL
+                .features
+                .union(SubcoinFeatures::from_bits(1 << 63)),
+        };
+        assert_eq!(
+            SubcoinVersion::LOCAL.negotiate(future),
+            SubcoinVersion::LOCAL
+        );
+
+        let no_snapshot = SubcoinVersion {
+            version: SUBCOIN_PROTOCOL_VERSION,
+            features: SubcoinFeatures::NONE,
+        };
+        let negotiated = SubcoinVersion::LOCAL.negotiate(no_snapshot);
+        assert!(!negotiated.features.has(SubcoinFeatures::SNAPSHOT));
+
+        let legacy = SubcoinVersion::LOCAL.negotiate(SubcoinVersion::LEGACY);
+        assert_eq!(legacy, SubcoinVersion::LEGACY);
+        assert!(!legacy.features.has(SubcoinFeatures::COMPRESSED_CHUNKS));
+    }
+}
@@ -1,23 +1,36 @@
 use crate::block_downloader::{BlocksFirstDownloader, HeadersFirstDownloader};
-use crate::peer_manager::NewPeer;
-use crate::{Error, Latency, PeerId, SyncStatus, SyncStrategy};
+use crate::light_sync::LightSync;
+use crate::peer_manager::{ConnectionType, NewPeer};
+use crate::snapshot_sync::{SnapshotMessage, SnapshotSync};
+use crate::sync_progress::{SyncCheckpoint, SyncPhase};
+use crate::{
+    Error, Latency, PeerId, StopAt, SyncStatus, SyncStrategy, TransportInfo, NODE_SNAPSHOT,
+};
 use bitcoin::blockdata::block::Header as BitcoinHeader;
 use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::ServiceFlags;
 use bitcoin::{Block as BitcoinBlock, BlockHash};
 use sc_client_api::{AuxStore, HeaderBackend};
-use sc_consensus_nakamoto::{BlockImportQueue, ImportBlocks, ImportManyBlocksResult};
+use sc_consensus_nakamoto::{
+    BlockImportQueue, ChainParams, HeaderVerifier, ImportBlocks, ImportManyBlocksResult,
+};
 use serde::{Deserialize, Serialize};
 use sp_consensus::BlockOrigin;
 use sp_runtime::traits::Block as BlockT;
 use std::cmp::Ordering as CmpOrdering;
 use std::collections::HashMap;
 use std::marker::PhantomData;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
-use subcoin_primitives::ClientExt;
+use std::time::{Duration, Instant};
+use subcoin_primitives::{ClientExt, LightChain};
+use subcoin_snapshot::SnapshotStore;
 
 // Do major sync when the current tip falls behind the network by 144 blocks (roughly one day).
-const MAJOR_SYNC_GAP: u32 = 144;
+pub(crate) const MAJOR_SYNC_GAP: u32 = 144;
+
+/// Interval of persisting the sync progress.
+const SYNC_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
 
 const LATENCY_IMPROVEMENT_THRESHOLD: f64 = 1.2;
 
@@ -85,6 +98,10 @@ pub struct PeerSync {
     /// The state of syncing this peer is in for us, generally categories
     /// into `Available` or "busy" with something as defined by `PeerSyncState`.
     pub state: PeerSyncState,
+    /// Transport of the connection to this peer.
+    pub transport: TransportInfo,
+    /// Type of the connection to this peer.
+    pub connection_type: ConnectionType,
 }
 
 /// Locator based sync request, for requesting either Headers or Blocks.
@@ -104,6 +121,8 @@ pub(crate) enum SyncRequest {
     Blocks(LocatorRequest),
     /// Get data request.
     Data(Vec<Inventory>, PeerId),
+    /// Snapshot requests.
+    Snapshot(Vec<(PeerId, SnapshotMessage)>),
 }
 
 /// Represents actions that can be taken during the syncing.
@@ -130,6 +149,10 @@ enum Syncing<Block, Client> {
     BlocksFirstSync(BlocksFirstDownloader<Block, Client>),
     /// Headers-First sync.
     HeadersFirstSync(HeadersFirstDownloader<Block, Client>),
+    /// Snapshot sync, followed by the block sync.
+    SnapshotSync(Box<SnapshotSync<Block, Client>>),
+    /// Headers-only sync of the light client.
+    LightSync(LightSync<Block, Client>),
     /// Not syncing.
     ///
     /// This could indicate that the node is either fully synced
@@ -139,7 +162,30 @@ enum Syncing<Block, Client> {
 
 impl<Block, Client> Syncing<Block, Client> {
     fn is_major_syncing(&self) -> bool {
-        matches!(self, Self::BlocksFirstSync(_) | Self::HeadersFirstSync(_))
+        matches!(
+            self,
+            Self::BlocksFirstSync(_)
+                | Self::HeadersFirstSync(_)
+                | Self::SnapshotSync(_)
+                | Self::LightSync(_)
+        )
+    }
+}
+
+impl<Block, Client> Syncing<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    /// Returns the phase of the block sync, `None` for the snapshot sync.
+    fn phase(&self) -> Option<SyncPhase> {
+        match self {
+            Self::BlocksFirstSync(downloader) => Some(downloader.phase()),
+            Self::HeadersFirstSync(downloader) => Some(downloader.phase()),
+            Self::SnapshotSync(_) => None,
+            Self::LightSync(_) => Some(SyncPhase::HeaderSync),
+            Self::Idle => Some(SyncPhase::NearTip),
+        }
     }
 }
 
@@ -147,13 +193,28 @@ impl<Block, Client> Syncing<Block, Client> {
 pub(crate) struct ChainSync<Block, Client> {
     /// Chain client.
     client: Arc<Client>,
+    /// Verifier of the headers downloaded in the headers-first sync.
+    header_verifier: HeaderVerifier<Block, Client>,
     /// The active peers that we are using to sync and their PeerSync status
     pub(crate) peers: HashMap<PeerId, PeerSync>,
     syncing: Syncing<Block, Client>,
     /// Handle of the import queue.
     import_queue: BlockImportQueue,
     sync_strategy: SyncStrategy,
+    /// Snapshot store and quorum of the snapshot sync, taken once the first sync starts.
+    pending_snapshot_sync: Option<(Arc<dyn SnapshotStore>, usize)>,
     is_major_syncing: Arc<AtomicBool>,
+    network_tip: Option<Arc<AtomicU32>>,
+    /// Memory budget of the blocks downloaded but not imported yet.
+    import_memory_budget: usize,
+    /// Checkpoint of the previous run, taken once the first sync starts.
+    resume_checkpoint: Option<SyncCheckpoint>,
+    last_saved_checkpoint: Option<SyncCheckpoint>,
+    last_checkpoint_at: Instant,
+    /// Block at which the sync is stopped.
+    stop_at: Option<StopAt>,
+    /// Whether the block to stop at has been imported.
+    stopped: bool,
     rng: fastrand::Rng,
     _phantom: PhantomData<Block>,
 }
@@ -164,19 +225,40 @@ where
     Client: HeaderBackend<Block> + AuxStore,
 {
     /// Constructs a new instance of [`ChainSync`].
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         client: Arc<Client>,
+        chain_params: ChainParams,
         import_queue: BlockImportQueue,
         sync_strategy: SyncStrategy,
+        snapshot_sync: Option<(Arc<dyn SnapshotStore>, usize)>,
         is_major_syncing: Arc<AtomicBool>,
+        network_tip: Option<Arc<AtomicU32>>,
+        import_memory_budget: usize,
+        stop_at: Option<StopAt>,
+        verification_threads: Option<usize>,
     ) -> Self {
+        let resume_checkpoint = SyncCheckpoint::load(&*client);
+        let mut header_verifier = HeaderVerifier::new(client.clone(), chain_params);
+        if let Some(threads) = verification_threads {
+            header_verifier = header_verifier.with_threads(threads);
+        }
         Self {
+            header_verifier,
             client,
             peers: HashMap::new(),
             import_queue,
             syncing: Syncing::Idle,
             sync_strategy,
+            pending_snapshot_sync: snapshot_sync,
             is_major_syncing,
+            network_tip,
+            import_memory_budget,
+            last_saved_checkpoint: resume_checkpoint.clone(),
+            resume_checkpoint,
+            last_checkpoint_at: Instant::now(),
+            stop_at,
+            stopped: false,
             rng: fastrand::Rng::new(),
             _phantom: Default::default(),
         }
@@ -187,14 +269,97 @@ where
             Syncing::Idle => SyncStatus::Idle,
             Syncing::BlocksFirstSync(downloader) => downloader.sync_status(),
             Syncing::HeadersFirstSync(downloader) => downloader.sync_status(),
+            Syncing::SnapshotSync(snapshot_sync) => snapshot_sync.sync_status(),
+            Syncing::LightSync(light_sync) => light_sync.sync_status(),
+        }
+    }
+
+    pub(super) fn sync_phase(&self) -> Option<SyncPhase> {
+        self.syncing.phase()
+    }
+
+    /// Returns our best block number and the highest one reported by the peers.
+    pub(super) fn sync_heights(&self) -> (u32, Option<u32>) {
+        let peer_best = self.peers.values().map(|peer| peer.best_number).max();
+        (self.best_number(), peer_best)
+    }
+
+    /// Returns the height of our best block, the light chain tip if syncing the headers only.
+    fn best_number(&self) -> u32 {
+        match self.sync_strategy {
+            SyncStrategy::HeadersOnly => {
+                LightChain::<Block, _>::new(self.client.clone())
+                    .tip()
+                    .height
+            }
+            SyncStrategy::HeadersFirst | SyncStrategy::BlocksFirst => self.client.best_number(),
+        }
+    }
+
+    /// Returns the peer the blocks are downloaded from, if any.
+    pub(super) fn sync_peer(&self) -> Option<PeerId> {
+        match &self.syncing {
+            Syncing::BlocksFirstSync(downloader) => Some(downloader.sync_peer()),
+            Syncing::HeadersFirstSync(downloader) => Some(downloader.sync_peer()),
+            Syncing::LightSync(light_sync) => Some(light_sync.sync_peer()),
+            Syncing::SnapshotSync(_) | Syncing::Idle => None,
         }
     }
 
     pub(super) fn on_tick(&mut self) -> SyncAction {
+        if self.last_checkpoint_at.elapsed() >= SYNC_CHECKPOINT_INTERVAL {
+            self.save_checkpoint();
+        }
+
+        if let Some(network_tip) = &self.network_tip {
+            let best_number = self.peers.values().map(|peer| peer.best_number).max();
+            network_tip.store(best_number.unwrap_or(0), Ordering::Relaxed);
+        }
+
         match &mut self.syncing {
             Syncing::Idle => SyncAction::None,
             Syncing::BlocksFirstSync(downloader) => downloader.on_tick(),
             Syncing::HeadersFirstSync(downloader) => downloader.on_tick(),
+            Syncing::SnapshotSync(snapshot_sync) => {
+                let sync_action = snapshot_sync.on_tick(&self.peers);
+
+                if snapshot_sync.is_finished() {
+                    self.update_syncing_state(Syncing::Idle);
+                    return self.attempt_sync_start();
+                }
+
+                sync_action
+            }
+            Syncing::LightSync(light_sync) => light_sync.on_tick(),
+        }
+    }
+
+    /// Persists the sync progress if it has changed since the last save.
+    fn save_checkpoint(&mut self) {
+        self.last_checkpoint_at = Instant::now();
+
+        let checkpoint = match &self.syncing {
+            Syncing::HeadersFirstSync(downloader) => downloader.checkpoint(),
+            Syncing::BlocksFirstSync(downloader) => SyncCheckpoint {
+                phase: downloader.phase(),
+                headers: Vec::new(),
+                last_requested: None,
+                sync_peer: Some(downloader.sync_peer()),
+            },
+            Syncing::Idle => SyncCheckpoint {
+                phase: SyncPhase::NearTip,
+                headers: Vec::new(),
+                last_requested: None,
+                sync_peer: None,
+            },
+            // The snapshot sync restarts from scratch anyway, the light sync resumes from the
+            // headers in the database.
+            Syncing::SnapshotSync(_) | Syncing::LightSync(_) => return,
+        };
+
+        if self.last_saved_checkpoint.as_ref() != Some(&checkpoint) {
+            checkpoint.save(&*self.client);
+            self.last_saved_checkpoint.replace(checkpoint);
         }
     }
 
@@ -206,7 +371,7 @@ where
     ///
     /// Returns `true` if the sync is restarted with a new peer.
     pub(super) fn restart_sync(&mut self, stalled_peer: PeerId) -> bool {
-        let our_best = self.client.best_number();
+        let our_best = self.best_number();
 
         // First, try to find the best available peer for syncing.
         let new_available_peer = self
@@ -254,7 +419,22 @@ where
                 downloader.restart(new_peer.peer_id, new_peer.best_number);
                 true
             }
-            Syncing::Idle => false,
+            Syncing::LightSync(light_sync) => {
+                light_sync.restart(new_peer.peer_id, new_peer.best_number);
+                true
+            }
+            Syncing::SnapshotSync(_) | Syncing::Idle => false,
+        }
+    }
+
+    /// Returns the number of the requests to the peer not answered yet.
+    pub(super) fn requests_in_flight(&self, peer_id: PeerId) -> usize {
+        match &self.syncing {
+            Syncing::BlocksFirstSync(downloader) => downloader.requests_in_flight(peer_id),
+            Syncing::HeadersFirstSync(downloader) => downloader.requests_in_flight(peer_id),
+            Syncing::SnapshotSync(snapshot_sync) => snapshot_sync.requests_in_flight(peer_id),
+            Syncing::LightSync(light_sync) => light_sync.requests_in_flight(peer_id),
+            Syncing::Idle => 0,
         }
     }
 
@@ -267,6 +447,10 @@ where
     pub(super) fn remove_peer(&mut self, peer_id: PeerId) {
         // TODO: handle the situation that the peer is being involved in the downloader.
         self.peers.remove(&peer_id);
+
+        if let Syncing::SnapshotSync(snapshot_sync) = &mut self.syncing {
+            snapshot_sync.remove_peer(peer_id);
+        }
     }
 
     pub(super) fn set_peer_latency(&mut self, peer_id: PeerId, avg_latency: Latency) {
@@ -279,10 +463,11 @@ where
         let current_sync_peer_id = match &self.syncing {
             Syncing::BlocksFirstSync(downloader) => downloader.sync_peer(),
             Syncing::HeadersFirstSync(downloader) => downloader.sync_peer(),
-            Syncing::Idle => return,
+            Syncing::LightSync(light_sync) => light_sync.sync_peer(),
+            Syncing::SnapshotSync(_) | Syncing::Idle => return,
         };
 
-        let our_best = self.client.best_number();
+        let our_best = self.best_number();
 
         // Find the peer with lowest latency.
         let Some(best_sync_peer) = self
@@ -314,7 +499,12 @@ where
                         Syncing::HeadersFirstSync(downloader) => {
                             downloader.update_sync_peer(peer_id, target_block_number);
                         }
-                        Syncing::Idle => unreachable!("Must not be Idle as checked; qed"),
+                        Syncing::LightSync(light_sync) => {
+                            light_sync.update_sync_peer(peer_id, target_block_number);
+                        }
+                        Syncing::SnapshotSync(_) | Syncing::Idle => {
+                            unreachable!("Must not be Idle or SnapshotSync as checked; qed")
+                        }
                     }
 
                     tracing::debug!(
@@ -333,33 +523,75 @@ where
             peer_id,
             best_number,
             connect_latency,
+            services,
+            transport,
+            connection_type,
         } = new_peer;
 
-        let new_peer = PeerSync {
-            peer_id,
-            best_number,
-            latency: PeerLatency::Connect(connect_latency),
-            state: PeerSyncState::Available,
-        };
+        // The peers not serving blocks, e.g., the subcoin nodes serving the snapshots only,
+        // are not used for the block sync.
+        if services.has(ServiceFlags::NETWORK) {
+            let new_peer = PeerSync {
+                peer_id,
+                best_number,
+                latency: PeerLatency::Connect(connect_latency),
+                state: PeerSyncState::Available,
+                transport,
+                connection_type,
+            };
+
+            self.peers.insert(peer_id, new_peer);
+        }
 
-        self.peers.insert(peer_id, new_peer);
+        let sync_action = self.attempt_sync_start();
 
-        self.attempt_sync_start()
+        if services.has(ServiceFlags::from(NODE_SNAPSHOT)) {
+            if let Syncing::SnapshotSync(snapshot_sync) = &mut self.syncing {
+                return snapshot_sync.add_snapshot_peer(peer_id);
+            }
+        }
+
+        sync_action
     }
 
     fn attempt_sync_start(&mut self) -> SyncAction {
-        if self.syncing.is_major_syncing() {
+        if self.syncing.is_major_syncing() || self.check_stopped() {
             return SyncAction::None;
         }
 
-        let our_best = self.client.best_number();
+        if let Some((store, quorum)) = self.pending_snapshot_sync.take() {
+            // Only a fresh node can start from the snapshot.
+            if self.client.best_number() == 0 {
+                tracing::info!("📦 Looking for the snapshots announced by {quorum} peers");
+                let snapshot_sync =
+                    SnapshotSync::new(&self.client, self.header_verifier.clone(), store, quorum);
+                self.update_syncing_state(Syncing::SnapshotSync(Box::new(snapshot_sync)));
+                return SyncAction::None;
+            }
+        }
 
-        let Some(best_peer) = self
-            .peers
-            .values_mut()
-            .filter(|peer| peer.best_number > our_best && peer.state.is_available())
-            .min_by_key(|peer| peer.latency)
-        else {
+        let our_best = self.best_number();
+
+        // Prefer the sync peer of the previous run so that the download continues from where
+        // it was stopped.
+        let checkpoint_peer = self
+            .resume_checkpoint
+            .as_ref()
+            .and_then(|checkpoint| checkpoint.sync_peer)
+            .filter(|peer_id| {
+                self.peers
+                    .get(peer_id)
+                    .is_some_and(|peer| peer.best_number > our_best && peer.state.is_available())
+            });
+
+        let Some(best_peer) = (match checkpoint_peer {
+            Some(peer_id) => self.peers.get_mut(&peer_id),
+            None => self
+                .peers
+                .values_mut()
+                .filter(|peer| peer.best_number > our_best && peer.state.is_available())
+                .min_by_key(|peer| peer.latency),
+        }) else {
             return SyncAction::None;
         };
 
@@ -370,8 +602,18 @@ where
 
             best_peer.state = PeerSyncState::DownloadingNew { start: our_best };
 
+            // The light client follows the headers only, whatever the gap is.
+            if let SyncStrategy::HeadersOnly = self.sync_strategy {
+                return self.start_light_sync(sync_peer, peer_best);
+            }
+
             let require_major_sync = peer_best - our_best > MAJOR_SYNC_GAP;
 
+            let resume_checkpoint = self
+                .resume_checkpoint
+                .take()
+                .filter(|checkpoint| !checkpoint.headers.is_empty());
+
             // Start major syncing if the gap is significant.
             let (new_syncing, sync_action) = if require_major_sync {
                 tracing::debug!(
@@ -384,24 +626,51 @@ where
                 match self.sync_strategy {
                     SyncStrategy::BlocksFirst => {
                         let (blocks_first_downloader, blocks_sync_request) =
-                            BlocksFirstDownloader::new(self.client.clone(), sync_peer, peer_best);
+                            BlocksFirstDownloader::new(
+                                self.client.clone(),
+                                sync_peer,
+                                peer_best,
+                                self.import_memory_budget,
+                            );
                         (
                             Syncing::BlocksFirstSync(blocks_first_downloader),
                             SyncAction::Request(blocks_sync_request),
                         )
                     }
                     SyncStrategy::HeadersFirst => {
-                        let (headers_first_downloader, sync_action) =
-                            HeadersFirstDownloader::new(self.client.clone(), sync_peer, peer_best);
+                        let (headers_first_downloader, sync_action) = match resume_checkpoint {
+                            Some(checkpoint) => HeadersFirstDownloader::resume(
+                                self.client.clone(),
+                                self.header_verifier.clone(),
+                                sync_peer,
+                                peer_best,
+                                self.import_memory_budget,
+                                checkpoint,
+                            ),
+                            None => HeadersFirstDownloader::new(
+                                self.client.clone(),
+                                self.header_verifier.clone(),
+                                sync_peer,
+                                peer_best,
+                                self.import_memory_budget,
+                            ),
+                        };
                         (
                             Syncing::HeadersFirstSync(headers_first_downloader),
                             sync_action,
                         )
                     }
+                    SyncStrategy::HeadersOnly => {
+                        unreachable!("Light sync is started regardless of the gap; qed")
+                    }
                 }
             } else {
-                let (blocks_first_downloader, blocks_sync_request) =
-                    BlocksFirstDownloader::new(self.client.clone(), sync_peer, peer_best);
+                let (blocks_first_downloader, blocks_sync_request) = BlocksFirstDownloader::new(
+                    self.client.clone(),
+                    sync_peer,
+                    peer_best,
+                    self.import_memory_budget,
+                );
                 (
                     Syncing::BlocksFirstSync(blocks_first_downloader),
                     SyncAction::Request(blocks_sync_request),
@@ -416,8 +685,52 @@ where
         SyncAction::None
     }
 
+    fn start_light_sync(&mut self, sync_peer: PeerId, peer_best: u32) -> SyncAction {
+        tracing::debug!(from = ?sync_peer, target = peer_best, "⏩ Starting light sync");
+
+        let mut light_sync = LightSync::new(
+            self.client.clone(),
+            self.header_verifier.clone(),
+            sync_peer,
+            peer_best,
+        );
+        let sync_action = light_sync.headers_request_action();
+        self.update_syncing_state(Syncing::LightSync(light_sync));
+
+        sync_action
+    }
+
+    /// Returns `true` if the sync has been stopped at [`Self::stop_at`], the sync is switched
+    /// to idle once the block is imported.
+    fn check_stopped(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+
+        let Some(stop_at) = self.stop_at else {
+            return false;
+        };
+
+        if stop_at.is_reached::<Block, _>(&self.client) {
+            tracing::info!(
+                best_number = self.client.best_number(),
+                "🏁 Reached {stop_at:?}, stopping the sync",
+            );
+            self.stopped = true;
+            self.update_syncing_state(Syncing::Idle);
+        }
+
+        self.stopped
+    }
+
     fn update_syncing_state(&mut self, new: Syncing<Block, Client>) {
         let is_major_syncing = new.is_major_syncing();
+
+        let (old_phase, new_phase) = (self.syncing.phase(), new.phase());
+        if old_phase != new_phase {
+            tracing::debug!("Sync phase changed from {old_phase:?} to {new_phase:?}");
+        }
+
         self.syncing = new;
         self.is_major_syncing
             .store(is_major_syncing, Ordering::Relaxed);
@@ -427,6 +740,10 @@ where
         // Import the potential remaining blocks downloaded by Headers-First sync.
         self.import_pending_blocks();
 
+        if self.check_stopped() {
+            return None;
+        }
+
         let our_best = self.client.best_number();
 
         let Some(best_peer) = self
@@ -443,6 +760,7 @@ where
             self.client.clone(),
             best_peer.peer_id,
             best_peer.best_number,
+            self.import_memory_budget,
         );
 
         tracing::debug!("Headers-first sync is complete, continuing with blocks-first sync");
@@ -456,7 +774,19 @@ where
     pub(super) fn on_inv(&mut self, inventories: Vec<Inventory>, from: PeerId) -> SyncAction {
         match &mut self.syncing {
             Syncing::BlocksFirstSync(downloader) => downloader.on_inv(inventories, from),
-            Syncing::HeadersFirstSync(_) => SyncAction::None,
+            Syncing::HeadersFirstSync(_) | Syncing::SnapshotSync(_) | Syncing::LightSync(_) => {
+                SyncAction::None
+            }
+            Syncing::Idle if matches!(self.sync_strategy, SyncStrategy::HeadersOnly) => {
+                // Fetch the headers of the announced blocks.
+                let announced = inventories
+                    .iter()
+                    .any(|inv| matches!(inv, Inventory::Block(_) | Inventory::WitnessBlock(_)));
+                match self.peers.get(&from) {
+                    Some(peer) if announced => self.start_light_sync(from, peer.best_number),
+                    _ => SyncAction::None,
+                }
+            }
             Syncing::Idle => {
                 // TODO: A new block maybe broadcasted via `inv` message.
                 SyncAction::None
@@ -466,7 +796,7 @@ where
 
     pub(super) fn on_block(&mut self, block: BitcoinBlock, from: PeerId) -> SyncAction {
         match &mut self.syncing {
-            Syncing::Idle => SyncAction::None,
+            Syncing::Idle | Syncing::SnapshotSync(_) | Syncing::LightSync(_) => SyncAction::None,
             Syncing::BlocksFirstSync(downloader) => downloader.on_block(block, from),
             Syncing::HeadersFirstSync(downloader) => downloader.on_block(block, from),
         }
@@ -475,7 +805,39 @@ where
     pub(super) fn on_headers(&mut self, headers: Vec<BitcoinHeader>, from: PeerId) -> SyncAction {
         match &mut self.syncing {
             Syncing::HeadersFirstSync(downloader) => downloader.on_headers(headers, from),
+            Syncing::SnapshotSync(snapshot_sync) => snapshot_sync.on_headers(headers, from),
+            Syncing::LightSync(light_sync) => {
+                let sync_action = light_sync.on_headers(headers, from);
+
+                if light_sync.is_completed() {
+                    tracing::debug!("Light sync is complete");
+                    self.update_syncing_state(Syncing::Idle);
+                    // Continue with the peers further ahead if any.
+                    if let SyncAction::None = sync_action {
+                        return self.attempt_sync_start();
+                    }
+                }
+
+                sync_action
+            }
             Syncing::BlocksFirstSync(_) => SyncAction::None,
+            Syncing::Idle if matches!(self.sync_strategy, SyncStrategy::HeadersOnly) => {
+                // The new blocks announced via `headers`.
+                let Some(peer_best) = self.peers.get(&from).map(|peer| peer.best_number) else {
+                    return SyncAction::None;
+                };
+                let mut light_sync = LightSync::new(
+                    self.client.clone(),
+                    self.header_verifier.clone(),
+                    from,
+                    peer_best,
+                );
+                let sync_action = light_sync.on_headers(headers, from);
+                if !light_sync.is_completed() {
+                    self.update_syncing_state(Syncing::LightSync(light_sync));
+                }
+                sync_action
+            }
             Syncing::Idle => {
                 // TODO: A new block maybe broadcasted via `headers` message.
                 SyncAction::None
@@ -483,18 +845,45 @@ where
         }
     }
 
-    pub(super) fn on_blocks_processed(&mut self, results: ImportManyBlocksResult) {
-        let download_manager = match &mut self.syncing {
-            Syncing::Idle => return,
-            Syncing::BlocksFirstSync(downloader) => downloader.download_manager(),
-            Syncing::HeadersFirstSync(downloader) => downloader.download_manager(),
+    pub(super) fn on_snapshot_message(
+        &mut self,
+        message: SnapshotMessage,
+        from: PeerId,
+    ) -> SyncAction {
+        match &mut self.syncing {
+            Syncing::SnapshotSync(snapshot_sync) => {
+                snapshot_sync.on_snapshot_message(from, message, &self.peers)
+            }
+            _ => SyncAction::None,
+        }
+    }
+
+    /// Returns the sync peer and the error if it provided an invalid block.
+    pub(super) fn on_blocks_processed(
+        &mut self,
+        results: ImportManyBlocksResult,
+    ) -> Option<(PeerId, Error)> {
+        let (sync_peer, download_manager) = match &mut self.syncing {
+            Syncing::Idle | Syncing::SnapshotSync(_) | Syncing::LightSync(_) => return None,
+            Syncing::BlocksFirstSync(downloader) => {
+                (downloader.sync_peer(), downloader.download_manager())
+            }
+            Syncing::HeadersFirstSync(downloader) => {
+                (downloader.sync_peer(), downloader.download_manager())
+            }
         };
-        download_manager.handle_processed_blocks(results);
+        let invalid_block = download_manager
+            .handle_processed_blocks(results)
+            .map(|(block_hash, err)| (sync_peer, Error::InvalidBlock(block_hash, err)));
+
+        self.check_stopped();
+
+        invalid_block
     }
 
     pub(super) fn import_pending_blocks(&mut self) {
         let download_manager = match &mut self.syncing {
-            Syncing::Idle => return,
+            Syncing::Idle | Syncing::SnapshotSync(_) | Syncing::LightSync(_) => return,
             Syncing::BlocksFirstSync(downloader) => downloader.download_manager(),
             Syncing::HeadersFirstSync(downloader) => downloader.download_manager(),
         };
@@ -503,7 +892,11 @@ where
             return;
         }
 
-        let (hashes, blocks) = download_manager.prepare_blocks_for_import();
+        let (hashes, blocks) = download_manager.prepare_blocks_for_import(self.stop_at);
+
+        if blocks.is_empty() {
+            return;
+        }
 
         tracing::trace!(
             blocks = ?hashes,
@@ -0,0 +1,121 @@
+//! Phases of the chain sync and the persisted sync progress.
+//!
+//! The sync goes through [`SyncPhase::HeaderSync`], [`SyncPhase::BlockDownload`] and then stays
+//! in [`SyncPhase::NearTip`] once caught up with the network. The progress of the sync is
+//! checkpointed to the aux-db periodically, the headers downloaded ahead of the best block are
+//! thus not downloaded again after a restart and the block download continues right away.
+
+use crate::PeerId;
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::consensus::{deserialize, serialize};
+use codec::{Decode, Encode};
+use sc_client_api::AuxStore;
+use serde::{Deserialize, Serialize};
+
+/// Key of the sync checkpoint in the aux-db.
+const SYNC_CHECKPOINT_KEY: &[u8] = b"sync_checkpoint";
+
+/// Phase of the chain sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncPhase {
+    /// Downloading the headers up to the next checkpoint.
+    HeaderSync,
+    /// Downloading the blocks far behind the network tip.
+    BlockDownload,
+    /// Following the network tip.
+    NearTip,
+}
+
+/// Sync progress persisted in the aux-db.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SyncCheckpoint {
+    pub(crate) phase: SyncPhase,
+    /// Headers downloaded ahead of the best block in ascending order, the last one is the
+    /// header tip.
+    pub(crate) headers: Vec<BitcoinHeader>,
+    /// Range `(start, end]` of the blocks requested last.
+    pub(crate) last_requested: Option<(u32, u32)>,
+    /// Peer the sync was assigned to.
+    pub(crate) sync_peer: Option<PeerId>,
+}
+
+#[derive(Encode, Decode)]
+struct EncodedSyncCheckpoint {
+    phase: SyncPhase,
+    /// Consensus-encoded headers.
+    headers: Vec<u8>,
+    last_requested: Option<(u32, u32)>,
+    sync_peer: Option<String>,
+}
+
+impl SyncCheckpoint {
+    /// Loads the checkpoint saved by the previous run, if any.
+    pub(crate) fn load<Client: AuxStore>(client: &Client) -> Option<Self> {
+        let encoded = client.get_aux(SYNC_CHECKPOINT_KEY).ok().flatten()?;
+
+        let decoded = EncodedSyncCheckpoint::decode(&mut encoded.as_slice())
+            .map_err(|err| err.to_string())
+            .and_then(|checkpoint| {
+                let headers = deserialize::<Vec<BitcoinHeader>>(&checkpoint.headers)
+                    .map_err(|err| err.to_string())?;
+                Ok(Self {
+                    phase: checkpoint.phase,
+                    headers,
+                    last_requested: checkpoint.last_requested,
+                    sync_peer: checkpoint.sync_peer.and_then(|peer| peer.parse().ok()),
+                })
+            });
+
+        match decoded {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(err) => {
+                tracing::warn!("Discarding corrupted sync checkpoint: {err}");
+                None
+            }
+        }
+    }
+
+    pub(crate) fn save<Client: AuxStore>(&self, client: &Client) {
+        let encoded = EncodedSyncCheckpoint {
+            phase: self.phase,
+            headers: serialize(&self.headers),
+            last_requested: self.last_requested,
+            sync_peer: self.sync_peer.map(|peer| peer.to_string()),
+        }
+        .encode();
+
+        if let Err(err) = client.insert_aux(&[(SYNC_CHECKPOINT_KEY, encoded.as_slice())], &[]) {
+            tracing::error!("Failed to save sync checkpoint: {err:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use subcoin_test_service::block_data;
+
+    #[test]
+    fn test_sync_checkpoint_roundtrip() {
+        let runtime = tokio::runtime::Runtime::new().expect("Create tokio runtime");
+        let subcoin_service::NodeComponents { client, .. } =
+            subcoin_test_service::new_test_node(runtime.handle().clone())
+                .expect("Create test node");
+
+        assert_eq!(SyncCheckpoint::load(&*client), None);
+
+        let checkpoint = SyncCheckpoint {
+            phase: SyncPhase::BlockDownload,
+            headers: block_data()[1..4]
+                .iter()
+                .map(|block| block.header)
+                .collect(),
+            last_requested: Some((0, 3)),
+            sync_peer: Some("1.2.3.4:8333".parse().unwrap()),
+        };
+        checkpoint.save(&*client);
+
+        assert_eq!(SyncCheckpoint::load(&*client), Some(checkpoint));
+    }
+}
@@ -1,16 +1,35 @@
-use crate::{IncomingTransaction, PeerId};
-use bitcoin::{Transaction, Txid};
-use indexmap::map::Entry;
+use crate::{IncomingTransaction, MempoolEntry, PeerId};
+use bitcoin::{Amount, OutPoint, Transaction, TxOut, Txid, Wtxid};
 use indexmap::IndexMap;
-use std::collections::HashSet;
-use std::time::{Duration, SystemTime};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-const TRANSACTION_TIMEOUT_DURATION_SECS: u64 = 10 * 60;
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Maximum number of the transactions evicted by a replacement, BIP125 rule 5.
+const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+/// Fee rate in sat/kvB the replacement must pay for its own size in addition to the fees of
+/// the replaced transactions, BIP125 rule 4.
+const INCREMENTAL_RELAY_FEE_RATE: u64 = 1000;
+
+/// Provides the confirmed outputs spent by the unconfirmed transactions.
+pub trait UtxoProvider: Send + Sync {
+    /// Returns the unspent output at the best block.
+    fn get_utxo(&self, out_point: &OutPoint) -> Option<TxOut>;
+}
 
 #[derive(Debug)]
 struct TransactionInfo {
     /// The actual transaction to be sent to the network.
     transaction: Transaction,
+    wtxid: Wtxid,
+    /// Transaction fee, `None` if any of the spent outputs is unknown.
+    fee: Option<Amount>,
+    /// Virtual size of the transaction.
+    vsize: u64,
     /// Set of peers to which we advertised this transaction.
     ///
     /// Note that having a peer in this set doesn't guarantee the the peer actually
@@ -21,42 +40,76 @@ struct TransactionInfo {
 }
 
 impl TransactionInfo {
-    fn new(transaction: Transaction) -> Self {
+    fn new(transaction: Transaction, fee: Option<Amount>, received: SystemTime) -> Self {
         Self {
+            vsize: transaction.vsize() as u64,
+            wtxid: transaction.compute_wtxid(),
             transaction,
+            fee,
             advertised: HashSet::new(),
-            ttl: SystemTime::now() + Duration::from_secs(TRANSACTION_TIMEOUT_DURATION_SECS),
+            ttl: received + TRANSACTION_TIMEOUT,
+        }
+    }
+
+    fn received(&self) -> SystemTime {
+        self.ttl - TRANSACTION_TIMEOUT
+    }
+
+    /// Fee rate in sat/kvB, `None` if the fee is unknown.
+    fn fee_rate(&self) -> Option<u64> {
+        self.fee.map(|fee| fee.to_sat() * 1000 / self.vsize.max(1))
+    }
+}
+
+/// Removes the outputs spent by `transaction` from `spenders`.
+fn remove_spenders(spenders: &mut HashMap<OutPoint, Txid>, txid: &Txid, transaction: &Transaction) {
+    for input in &transaction.input {
+        if spenders.get(&input.previous_output) == Some(txid) {
+            spenders.remove(&input.previous_output);
         }
     }
 }
 
 /// This struct manages the transactions received from the network.
-#[derive(Debug)]
+///
+/// A transaction spending the same output as the existing ones replaces them and their
+/// descendants if it pays more fees, following the BIP125 rules.
 pub(crate) struct TransactionManager {
     /// List of transactions tracked by this manager, in the FIFO order.
     transactions: IndexMap<Txid, TransactionInfo>,
+    /// Transaction spending each output.
+    spenders: HashMap<OutPoint, Txid>,
+    /// Whether the transactions not signaling the replaceability can be replaced.
+    full_rbf: bool,
+    /// Used to compute the fees of the transactions spending the confirmed outputs.
+    utxo_provider: Option<Arc<dyn UtxoProvider>>,
 }
 
 impl TransactionManager {
     /// Maximum number of transactions the manager holds.
     const MAX_TRANSACTIONS: usize = 256;
 
-    pub fn new() -> Self {
+    pub fn new(full_rbf: bool, utxo_provider: Option<Arc<dyn UtxoProvider>>) -> Self {
         Self {
             transactions: IndexMap::new(),
+            spenders: HashMap::new(),
+            full_rbf,
+            utxo_provider,
         }
     }
 
-    /// Broadcast known transaction IDs to the connected peers.
+    /// Broadcast known transaction IDs to the connected peers, except the ones `is_local`.
     pub fn on_tick<'a>(
         &mut self,
         connected_peers: impl Iterator<Item = &'a PeerId>,
+        is_local: impl Fn(&Txid) -> bool,
     ) -> Vec<(PeerId, Vec<Txid>)> {
         // Remove timeout transactions.
         let now = SystemTime::now();
         self.transactions.retain(|txid, info| {
             if info.ttl < now {
                 tracing::debug!("Removing timeout transaction {txid}");
+                remove_spenders(&mut self.spenders, txid, &info.transaction);
                 false
             } else {
                 true
@@ -68,7 +121,7 @@ impl TransactionManager {
                 let mut to_advertise = vec![];
 
                 for (txid, info) in self.transactions.iter_mut() {
-                    if !info.advertised.contains(address) {
+                    if !info.advertised.contains(address) && !is_local(txid) {
                         to_advertise.push(*txid);
                         info.advertised.insert(*address);
                     }
@@ -89,22 +142,414 @@ impl TransactionManager {
             .map(|tx_info| tx_info.transaction.clone())
     }
 
+    /// Returns the wtxid of the transaction.
+    pub fn wtxid(&self, txid: &Txid) -> Option<Wtxid> {
+        self.transactions.get(txid).map(|tx_info| tx_info.wtxid)
+    }
+
+    /// Returns the txid of the transaction with the wtxid.
+    pub fn txid_for_wtxid(&self, wtxid: &Wtxid) -> Option<Txid> {
+        // At most `MAX_TRANSACTIONS` are scanned.
+        self.transactions
+            .iter()
+            .find_map(|(txid, tx_info)| (tx_info.wtxid == *wtxid).then_some(*txid))
+    }
+
+    /// Returns all the transactions tracked by this manager, in the FIFO order.
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.transactions
+            .values()
+            .map(|tx_info| tx_info.transaction.clone())
+            .collect()
+    }
+
+    /// Returns all the transactions tracked by this manager with the time they were received,
+    /// in the FIFO order.
+    pub fn mempool_entries(&self) -> Vec<MempoolEntry> {
+        self.transactions
+            .values()
+            .map(|tx_info| MempoolEntry {
+                transaction: tx_info.transaction.clone(),
+                time: tx_info
+                    .received()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_secs() as i64)
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Writes the transactions to the mempool file at `path`.
+    ///
+    /// Returns the number of the saved transactions.
+    pub fn save_mempool(&self, path: &Path) -> std::io::Result<usize> {
+        let entries = self.mempool_entries();
+        crate::mempool_file::save_mempool(path, &entries)?;
+        Ok(entries.len())
+    }
+
     pub fn add_transaction(
         &mut self,
         incoming_transaction: IncomingTransaction,
     ) -> Result<Txid, String> {
         let IncomingTransaction { txid, transaction } = incoming_transaction;
+        let fee = self.fee(&transaction);
+        self.insert(
+            txid,
+            TransactionInfo::new(transaction, fee, SystemTime::now()),
+        )
+    }
+
+    /// Adds the transaction loaded from the disk, keeping the time it was received.
+    ///
+    /// The transaction is rejected if it has already expired.
+    pub fn restore_transaction(&mut self, entry: MempoolEntry) -> Result<Txid, String> {
+        let MempoolEntry { transaction, time } = entry;
+
+        let txid = transaction.compute_txid();
+        let received = UNIX_EPOCH + Duration::from_secs(time.max(0) as u64);
+        let fee = self.fee(&transaction);
+        let tx_info = TransactionInfo::new(transaction, fee, received);
+
+        if tx_info.ttl < SystemTime::now() {
+            return Err(format!("Transaction {txid} has expired"));
+        }
+
+        self.insert(txid, tx_info)
+    }
+
+    fn insert(&mut self, txid: Txid, tx_info: TransactionInfo) -> Result<Txid, String> {
+        if self.transactions.contains_key(&txid) {
+            return Err(format!("Already have transaction {txid}"));
+        }
+
+        for replaced in self.check_replacement(&txid, &tx_info)? {
+            tracing::debug!("Replacing transaction {replaced} by {txid}");
+            self.remove(&replaced);
+        }
 
         if self.transactions.len() == Self::MAX_TRANSACTIONS {
-            self.transactions.shift_remove_index(0);
+            if let Some((evicted, info)) = self.transactions.shift_remove_index(0) {
+                remove_spenders(&mut self.spenders, &evicted, &info.transaction);
+            }
+        }
+
+        for input in &tx_info.transaction.input {
+            self.spenders.insert(input.previous_output, txid);
+        }
+
+        self.transactions.insert(txid, tx_info);
+
+        Ok(txid)
+    }
+
+    fn remove(&mut self, txid: &Txid) {
+        if let Some(info) = self.transactions.shift_remove(txid) {
+            remove_spenders(&mut self.spenders, txid, &info.transaction);
+        }
+    }
+
+    /// Returns the fee of the transaction, `None` if any of the spent outputs is unknown.
+    fn fee(&self, transaction: &Transaction) -> Option<Amount> {
+        let input_value = transaction
+            .input
+            .iter()
+            .map(|input| {
+                let OutPoint { txid, vout } = input.previous_output;
+                match self.transactions.get(&txid) {
+                    Some(parent) => parent
+                        .transaction
+                        .output
+                        .get(vout as usize)
+                        .map(|txout| txout.value),
+                    None => self
+                        .utxo_provider
+                        .as_ref()?
+                        .get_utxo(&input.previous_output)
+                        .map(|txout| txout.value),
+                }
+            })
+            .try_fold(Amount::ZERO, |total, value| total.checked_add(value?))?;
+
+        let output_value = transaction
+            .output
+            .iter()
+            .try_fold(Amount::ZERO, |total, txout| total.checked_add(txout.value))?;
+
+        input_value.checked_sub(output_value)
+    }
+
+    /// Returns `true` if the transaction or any of its unconfirmed ancestors signals the
+    /// replaceability.
+    fn signals_rbf(&self, txid: Txid) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![txid];
+
+        while let Some(txid) = stack.pop() {
+            if !visited.insert(txid) {
+                continue;
+            }
+
+            let Some(info) = self.transactions.get(&txid) else {
+                continue;
+            };
+
+            if info.transaction.is_explicitly_rbf() {
+                return true;
+            }
+
+            stack.extend(
+                info.transaction
+                    .input
+                    .iter()
+                    .map(|input| input.previous_output.txid),
+            );
         }
 
-        match self.transactions.entry(txid) {
-            Entry::Occupied(_) => Err(format!("Already have transaction {txid}")),
-            Entry::Vacant(entry) => {
-                entry.insert(TransactionInfo::new(transaction));
-                Ok(txid)
+        false
+    }
+
+    /// Returns the transaction and all its unconfirmed descendants.
+    fn descendants(&self, txid: Txid) -> HashSet<Txid> {
+        let mut descendants = HashSet::from([txid]);
+        let mut stack = vec![txid];
+
+        while let Some(txid) = stack.pop() {
+            let Some(info) = self.transactions.get(&txid) else {
+                continue;
+            };
+
+            for vout in 0..info.transaction.output.len() {
+                if let Some(spender) = self.spenders.get(&OutPoint::new(txid, vout as u32)) {
+                    if descendants.insert(*spender) {
+                        stack.push(*spender);
+                    }
+                }
             }
         }
+
+        descendants
+    }
+
+    /// Checks whether the transaction can replace the ones spending the same outputs.
+    ///
+    /// Returns the transactions to be evicted, i.e., the conflicting transactions and their
+    /// descendants.
+    fn check_replacement(
+        &self,
+        txid: &Txid,
+        tx_info: &TransactionInfo,
+    ) -> Result<Vec<Txid>, String> {
+        let conflicts = tx_info
+            .transaction
+            .input
+            .iter()
+            .filter_map(|input| self.spenders.get(&input.previous_output).copied())
+            .collect::<HashSet<_>>();
+
+        if conflicts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Rule 1, the replaced transactions must signal the replaceability.
+        if !self.full_rbf {
+            if let Some(conflict) = conflicts
+                .iter()
+                .find(|conflict| !self.signals_rbf(**conflict))
+            {
+                return Err(format!(
+                    "txn-mempool-conflict, non-replaceable transaction {conflict}"
+                ));
+            }
+        }
+
+        let evicted = conflicts
+            .iter()
+            .flat_map(|conflict| self.descendants(*conflict))
+            .collect::<HashSet<_>>();
+
+        // Rule 5.
+        if evicted.len() > MAX_REPLACEMENT_CANDIDATES {
+            return Err(format!(
+                "too many potential replacements, rejecting replacement {txid}; \
+                too many potential replacements ({} > {MAX_REPLACEMENT_CANDIDATES})",
+                evicted.len()
+            ));
+        }
+
+        let spent_txids = tx_info
+            .transaction
+            .input
+            .iter()
+            .map(|input| input.previous_output.txid)
+            .collect::<HashSet<_>>();
+
+        if let Some(spent) = spent_txids.intersection(&evicted).next() {
+            return Err(format!(
+                "bad-txns-spends-conflicting-tx, {txid} spends conflicting transaction {spent}"
+            ));
+        }
+
+        // Rule 2, no new unconfirmed inputs than the ones of the replaced transactions.
+        let conflict_parents = conflicts
+            .iter()
+            .flat_map(|conflict| &self.transactions[conflict].transaction.input)
+            .map(|input| input.previous_output.txid)
+            .collect::<HashSet<_>>();
+
+        if spent_txids.iter().any(|parent| {
+            self.transactions.contains_key(parent) && !conflict_parents.contains(parent)
+        }) {
+            return Err(format!(
+                "replacement-adds-unconfirmed, replacement {txid} adds unconfirmed inputs"
+            ));
+        }
+
+        let (Some(fee), Some(fee_rate)) = (tx_info.fee, tx_info.fee_rate()) else {
+            return Err(format!(
+                "txn-mempool-conflict, unknown fee of replacement {txid}"
+            ));
+        };
+
+        // Rule 6, the fee rate must be higher than the directly conflicting transactions.
+        for conflict in &conflicts {
+            let Some(old_fee_rate) = self.transactions[conflict].fee_rate() else {
+                return Err(format!(
+                    "txn-mempool-conflict, unknown fee of conflicting transaction {conflict}"
+                ));
+            };
+
+            if fee_rate <= old_fee_rate {
+                return Err(format!(
+                    "insufficient fee, rejecting replacement {txid}; \
+                    new feerate {fee_rate} <= old feerate {old_fee_rate} sat/kvB"
+                ));
+            }
+        }
+
+        // Rule 3, the fee must be no less than the fees of all the replaced transactions.
+        let Some(evicted_fee) = evicted
+            .iter()
+            .map(|evicted| self.transactions[evicted].fee)
+            .sum::<Option<Amount>>()
+        else {
+            return Err(format!(
+                "txn-mempool-conflict, unknown fees of the transactions replaced by {txid}"
+            ));
+        };
+
+        if fee < evicted_fee {
+            return Err(format!(
+                "insufficient fee, rejecting replacement {txid}, less fees than conflicting txs; \
+                {fee} < {evicted_fee}"
+            ));
+        }
+
+        // Rule 4, the additional fee must pay for the relay of the replacement.
+        let additional_fee = fee - evicted_fee;
+        let required_fee = Amount::from_sat(INCREMENTAL_RELAY_FEE_RATE * tx_info.vsize / 1000);
+
+        if additional_fee < required_fee {
+            return Err(format!(
+                "insufficient fee, rejecting replacement {txid}, not enough additional fees to \
+                relay; {additional_fee} < {required_fee}"
+            ));
+        }
+
+        Ok(evicted.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{ScriptBuf, Sequence, TxIn};
+
+    struct Utxos(HashMap<OutPoint, TxOut>);
+
+    impl UtxoProvider for Utxos {
+        fn get_utxo(&self, out_point: &OutPoint) -> Option<TxOut> {
+            self.0.get(out_point).cloned()
+        }
+    }
+
+    fn transaction(input: OutPoint, sequence: Sequence, value: u64) -> IncomingTransaction {
+        let transaction = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: input,
+                sequence,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new_op_return([0u8; 4]),
+            }],
+        };
+
+        IncomingTransaction {
+            txid: transaction.compute_txid(),
+            transaction,
+        }
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let utxo = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        let utxo_provider = Arc::new(Utxos(HashMap::from([(
+            utxo,
+            TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new(),
+            },
+        )])));
+
+        let mut manager = TransactionManager::new(false, Some(utxo_provider.clone()));
+        manager
+            .add_transaction(transaction(utxo, Sequence::MAX, 99_000))
+            .unwrap();
+
+        // The original transaction does not signal the replaceability.
+        let replacement = transaction(utxo, Sequence::MAX, 90_000);
+        assert!(manager
+            .add_transaction(replacement)
+            .unwrap_err()
+            .starts_with("txn-mempool-conflict"));
+
+        let original = transaction(utxo, Sequence::ENABLE_RBF_NO_LOCKTIME, 99_000);
+        let original_txid = original.txid;
+        let child = transaction(OutPoint::new(original_txid, 0), Sequence::MAX, 98_000);
+        let child_txid = child.txid;
+        let mut manager = TransactionManager::new(false, Some(utxo_provider.clone()));
+        manager.add_transaction(original).unwrap();
+        manager.add_transaction(child).unwrap();
+
+        // Less fees than the original transaction and its child.
+        assert!(manager
+            .add_transaction(transaction(utxo, Sequence::MAX, 98_500))
+            .unwrap_err()
+            .starts_with("insufficient fee"));
+
+        let replacement = transaction(utxo, Sequence::MAX, 90_000);
+        let replacement_txid = replacement.txid;
+        manager.add_transaction(replacement).unwrap();
+
+        assert!(manager.get_transaction(&original_txid).is_none());
+        assert!(manager.get_transaction(&child_txid).is_none());
+        assert!(manager.get_transaction(&replacement_txid).is_some());
+        assert_eq!(manager.spenders.get(&utxo), Some(&replacement_txid));
+
+        // Any transaction is replaceable with full RBF.
+        let mut manager = TransactionManager::new(true, Some(utxo_provider));
+        manager
+            .add_transaction(transaction(utxo, Sequence::MAX, 99_000))
+            .unwrap();
+        assert!(manager
+            .add_transaction(transaction(utxo, Sequence::MAX, 90_000))
+            .is_ok());
     }
 }
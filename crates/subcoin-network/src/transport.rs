@@ -0,0 +1,267 @@
+//! Transports of the subcoin p2p connections.
+//!
+//! TCP is always used to talk to the regular Bitcoin nodes. The subcoin nodes can additionally
+//! listen on QUIC (UDP, same port as TCP) and advertise [`NODE_QUIC`], the large transfers like
+//! the snapshot sync then benefit from the QUIC congestion control and the lack of head-of-line
+//! blocking between the connections sharing an endpoint.
+//!
+//! The Bitcoin messages are carried over a single bidirectional stream per connection, QUIC is
+//! a drop-in replacement of the TCP stream. As the Bitcoin p2p protocol has no notion of peer
+//! identity, the TLS certificates are self-signed and not verified, QUIC merely provides the
+//! encryption of the traffic.
+//!
+//! [`NODE_QUIC`]: crate::NODE_QUIC
+
+use crate::Error;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// ALPN identifier of the subcoin p2p protocol over QUIC.
+const ALPN: &[u8] = b"subcoin/1";
+
+/// Server name of the self-signed certificates.
+const SERVER_NAME: &str = "subcoin";
+
+/// Transport of a p2p connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "camelCase")]
+pub enum Transport {
+    /// Plain TCP, understood by every Bitcoin node.
+    Tcp,
+    /// QUIC over UDP, subcoin nodes only.
+    Quic,
+}
+
+/// Framing of the Bitcoin messages on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransportProtocol {
+    /// Plaintext messages.
+    V1,
+    /// BIP-324 encrypted packets.
+    V2,
+}
+
+/// Transport details of a connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportInfo {
+    pub transport: Transport,
+    pub protocol: TransportProtocol,
+    /// Hex-encoded BIP-324 session id, for comparing with the one reported by the peer.
+    pub session_id: Option<String>,
+}
+
+/// Byte stream of an established connection, regardless of the transport.
+pub(crate) struct PeerStream {
+    pub(crate) transport: Transport,
+    pub(crate) peer_addr: SocketAddr,
+    pub(crate) local_addr: SocketAddr,
+    pub(crate) reader: Box<dyn AsyncRead + Send + Unpin>,
+    pub(crate) writer: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+impl PeerStream {
+    pub(crate) fn tcp(stream: TcpStream) -> std::io::Result<Self> {
+        let peer_addr = stream.peer_addr()?;
+        let local_addr = stream.local_addr()?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            transport: Transport::Tcp,
+            peer_addr,
+            local_addr,
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+        })
+    }
+
+    fn quic(
+        connection: &quinn::Connection,
+        local_addr: SocketAddr,
+        (send, recv): (quinn::SendStream, quinn::RecvStream),
+    ) -> Self {
+        // The streams keep the underlying connection alive.
+        Self {
+            transport: Transport::Quic,
+            peer_addr: connection.remote_address(),
+            local_addr,
+            reader: Box::new(recv),
+            writer: Box::new(send),
+        }
+    }
+}
+
+/// QUIC endpoint used for both the inbound and outbound connections.
+#[derive(Clone)]
+pub(crate) struct QuicEndpoint {
+    endpoint: quinn::Endpoint,
+}
+
+impl QuicEndpoint {
+    /// Binds a new endpoint to the given UDP address.
+    pub(crate) fn bind(addr: SocketAddr) -> Result<Self, Error> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+        let mut endpoint = quinn::Endpoint::server(server_config(provider.clone())?, addr)?;
+        endpoint.set_default_client_config(client_config(provider)?);
+
+        Ok(Self { endpoint })
+    }
+
+    pub(crate) fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Connects to the peer and opens the stream of the Bitcoin messages.
+    pub(crate) async fn connect(&self, addr: SocketAddr) -> Result<PeerStream, Error> {
+        let connection = self.endpoint.connect(addr, SERVER_NAME)?.await?;
+        // The stream is announced to the peer along with our version message.
+        let streams = connection.open_bi().await?;
+        Ok(PeerStream::quic(&connection, self.local_addr()?, streams))
+    }
+
+    /// Waits for the next inbound connection and its stream of the Bitcoin messages.
+    ///
+    /// Returns `None` once the endpoint is closed.
+    pub(crate) async fn accept(&self) -> Option<Result<PeerStream, Error>> {
+        let incoming = self.endpoint.accept().await?;
+
+        let accept = async {
+            let connection = incoming.await?;
+            let streams = connection.accept_bi().await?;
+            Ok::<_, Error>(PeerStream::quic(&connection, self.local_addr()?, streams))
+        };
+
+        Some(accept.await)
+    }
+}
+
+fn server_config(provider: Arc<CryptoProvider>) -> Result<quinn::ServerConfig, Error> {
+    let certified_key = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+        .map_err(|err| Error::QuicConfig(err.to_string()))?;
+    let cert = certified_key.cert.der().clone();
+    let key = PrivatePkcs8KeyDer::from(certified_key.key_pair.serialize_der());
+
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .and_then(|builder| {
+            builder
+                .with_no_client_auth()
+                .with_single_cert(vec![cert], key.into())
+        })
+        .map_err(|err| Error::QuicConfig(err.to_string()))?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(crypto)
+        .map_err(|err| Error::QuicConfig(err.to_string()))?;
+
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+fn client_config(provider: Arc<CryptoProvider>) -> Result<quinn::ClientConfig, Error> {
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|err| Error::QuicConfig(err.to_string()))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)
+        .map_err(|err| Error::QuicConfig(err.to_string()))?;
+
+    Ok(quinn::ClientConfig::new(Arc::new(crypto)))
+}
+
+/// Accepts any server certificate, only the handshake signatures are checked.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_quic_stream() {
+        let server = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let client = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let mut stream = server.accept().await.unwrap().unwrap();
+            let mut buf = [0u8; 7];
+            stream.reader.read_exact(&mut buf).await.unwrap();
+            stream.writer.write_all(b"verack").await.unwrap();
+            stream.writer.flush().await.unwrap();
+            // Keep the connection until the client has read the response.
+            let _ = stream.reader.read(&mut [0u8; 1]).await;
+            buf
+        });
+
+        let mut stream = client.connect(server_addr).await.unwrap();
+        assert_eq!(stream.transport, Transport::Quic);
+        assert_eq!(stream.peer_addr, server_addr);
+
+        stream.writer.write_all(b"version").await.unwrap();
+        let mut buf = [0u8; 6];
+        stream.reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"verack");
+        drop(stream);
+
+        assert_eq!(&accept.await.unwrap(), b"version");
+    }
+}
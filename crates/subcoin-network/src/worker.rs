@@ -1,23 +1,36 @@
+use crate::broadcast_manager::BroadcastManager;
 use crate::connection::{ConnectionInitiator, Direction, NewConnection};
+use crate::i2p::I2pDestinations;
 use crate::metrics::Metrics;
-use crate::peer_manager::{Config, PeerManager, SlowPeer};
+use crate::peer_manager::{Config, OutboundTargets, PeerManager, SlowPeer};
+use crate::peer_store::PeerStore;
+use crate::rate_limit::UploadTarget;
+use crate::snapshot_sync::SnapshotMessage;
+use crate::stall_detector::{StallCheck, StallDetector};
+use crate::subcoin_protocol::{SubcoinFeatures, SubcoinVersion};
 use crate::sync::{ChainSync, LocatorRequest, SyncAction, SyncRequest};
-use crate::transaction_manager::TransactionManager;
+use crate::transaction_manager::{TransactionManager, UtxoProvider};
 use crate::{
-    Bandwidth, Error, IncomingTransaction, Latency, NetworkStatus, NetworkWorkerMessage, PeerId,
-    SendTransactionResult, SyncStrategy,
+    services_hex, Bandwidth, Error, IncomingTransaction, Latency, NetworkInfo, NetworkStatus,
+    NetworkWorkerMessage, PeerId, SendTransactionResult, SnapshotParams, StopAt, SyncStrategy,
+    NODE_P2P_V2, NODE_QUIC, NODE_SNAPSHOT,
 };
-use bitcoin::p2p::message::{NetworkMessage, MAX_INV_SIZE};
+use bitcoin::p2p::message::{CommandString, NetworkMessage, MAX_INV_SIZE, MAX_MSG_SIZE};
 use bitcoin::p2p::message_blockdata::{GetBlocksMessage, GetHeadersMessage, Inventory};
+use bitcoin::p2p::ServiceFlags;
+use bitcoin::{Txid, Wtxid};
 use futures::stream::FusedStream;
 use futures::StreamExt;
 use sc_client_api::{AuxStore, HeaderBackend};
-use sc_consensus_nakamoto::BlockImportQueue;
+use sc_consensus_nakamoto::{BlockImportQueue, ChainParams};
 use sc_utils::mpsc::TracingUnboundedReceiver;
 use sp_runtime::traits::Block as BlockT;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+use subcoin_db::SubcoinDb;
+use subcoin_snapshot::SnapshotStore;
 use substrate_prometheus_endpoint::Registry;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::time::MissedTickBehavior;
@@ -29,6 +42,9 @@ const TICK_TIMEOUT: Duration = Duration::from_millis(1100);
 /// If a peer's latency exceeds this value, it will be considered a slow peer and may be evicted.
 const LATENCY_THRESHOLD: Latency = 10_000;
 
+/// Size of the header of a Bitcoin p2p message.
+const MESSAGE_HEADER_SIZE: usize = 24;
+
 /// Network event.
 #[derive(Debug)]
 pub enum Event {
@@ -49,12 +65,40 @@ pub enum Event {
 /// Parameters for creating a [`NetworkWorker`].
 pub struct Params<Client> {
     pub client: Arc<Client>,
+    pub chain_params: ChainParams,
     pub network_event_receiver: UnboundedReceiver<Event>,
     pub import_queue: BlockImportQueue,
     pub sync_strategy: SyncStrategy,
     pub is_major_syncing: Arc<AtomicBool>,
     pub connection_initiator: ConnectionInitiator,
     pub max_outbound_peers: usize,
+    pub max_block_relay_only_peers: usize,
+    pub max_inbound_peers: usize,
+    /// Resolved addresses of the manual peers.
+    pub manual_peers: Vec<PeerId>,
+    pub db: Option<SubcoinDb>,
+    pub upload_target: Option<UploadTarget>,
+    pub max_peer_message_rate: Option<u32>,
+    pub import_memory_budget: usize,
+    pub snapshot: Option<SnapshotParams>,
+    pub network_tip: Option<Arc<AtomicU32>>,
+    pub mempool_path: Option<PathBuf>,
+    pub full_rbf: bool,
+    pub utxo_provider: Option<Arc<dyn UtxoProvider>>,
+    pub stop_at: Option<StopAt>,
+    pub verification_threads: Option<usize>,
+    /// Port accepting the inbound TCP connections.
+    pub listen_port: Option<u16>,
+    pub cjdns_reachable: bool,
+    /// Destinations of the I2P peers, `None` if I2P is disabled.
+    pub i2p_destinations: Option<I2pDestinations>,
+    /// Period without the best block advancing while behind the peers after which the sync
+    /// is considered stalled, never if `None`.
+    pub sync_stall_timeout: Option<Duration>,
+    /// Whether to switch to another sync peer and disconnect the current one once stalled.
+    pub rotate_stalled_sync_peer: bool,
+    /// Whether to ignore the transactions relayed by the peers.
+    pub blocks_only: bool,
 }
 
 /// [`NetworkWorker`] is responsible for processing the network events.
@@ -63,7 +107,17 @@ pub struct NetworkWorker<Block, Client> {
     network_event_receiver: UnboundedReceiver<Event>,
     peer_manager: PeerManager<Block, Client>,
     transaction_manager: TransactionManager,
+    broadcast_manager: BroadcastManager,
     chain_sync: ChainSync<Block, Client>,
+    /// Snapshot store, if serving the snapshots.
+    snapshot_store: Option<Arc<dyn SnapshotStore>>,
+    /// zstd compression level of the served chunks, uncompressed if `None`.
+    chunk_compression_level: Option<i32>,
+    upload_target: Option<UploadTarget>,
+    /// File the transactions are saved to on shutdown.
+    mempool_path: Option<PathBuf>,
+    stall_detector: Option<StallDetector>,
+    rotate_stalled_sync_peer: bool,
     metrics: Option<Metrics>,
 }
 
@@ -76,15 +130,70 @@ where
     pub fn new(params: Params<Client>, registry: Option<&Registry>) -> Self {
         let Params {
             client,
+            chain_params,
             network_event_receiver,
             import_queue,
             sync_strategy,
             is_major_syncing,
             connection_initiator,
             max_outbound_peers,
+            max_block_relay_only_peers,
+            max_inbound_peers,
+            manual_peers,
+            db,
+            upload_target,
+            max_peer_message_rate,
+            import_memory_budget,
+            snapshot,
+            network_tip,
+            mempool_path,
+            full_rbf,
+            utxo_provider,
+            stop_at,
+            verification_threads,
+            listen_port,
+            cjdns_reachable,
+            i2p_destinations,
+            sync_stall_timeout,
+            rotate_stalled_sync_peer,
+            blocks_only,
         } = params;
 
-        let config = Config::new();
+        let mut config = Config::new();
+
+        config.persistent = manual_peers;
+        config.max_message_rate = max_peer_message_rate;
+        config.listen_port = listen_port;
+        config.cjdns_reachable = cjdns_reachable;
+        config.i2p_destinations = i2p_destinations;
+        config.blocks_only = blocks_only;
+
+        if connection_initiator.quic_enabled() {
+            config.services |= ServiceFlags::from(NODE_QUIC);
+        }
+
+        if connection_initiator.v2_transport_enabled() {
+            config.services |= ServiceFlags::from(NODE_P2P_V2);
+        }
+
+        let (snapshot_store, snapshot_sync, chunk_compression_level) = match snapshot {
+            Some(SnapshotParams {
+                store,
+                serve,
+                sync_quorum,
+                chunk_compression_level,
+            }) => {
+                if serve {
+                    config.services |= ServiceFlags::from(NODE_SNAPSHOT);
+                }
+                (
+                    serve.then(|| store.clone()),
+                    sync_quorum.map(|quorum| (store, quorum)),
+                    chunk_compression_level,
+                )
+            }
+            None => (None, None, None),
+        };
 
         let metrics = match registry {
             Some(registry) => Metrics::register(registry)
@@ -97,15 +206,38 @@ where
             client.clone(),
             config.clone(),
             connection_initiator,
-            max_outbound_peers,
+            OutboundTargets {
+                full_relay: max_outbound_peers,
+                block_relay_only: max_block_relay_only_peers,
+            },
+            max_inbound_peers,
+            db.map(|db| PeerStore::new(db, config.i2p_destinations.clone())),
             metrics.clone(),
         );
 
         Self {
             network_event_receiver,
             peer_manager,
-            transaction_manager: TransactionManager::new(),
-            chain_sync: ChainSync::new(client, import_queue, sync_strategy, is_major_syncing),
+            transaction_manager: TransactionManager::new(full_rbf, utxo_provider),
+            broadcast_manager: BroadcastManager::new(),
+            chain_sync: ChainSync::new(
+                client,
+                chain_params,
+                import_queue,
+                sync_strategy,
+                snapshot_sync,
+                is_major_syncing,
+                network_tip,
+                import_memory_budget,
+                stop_at,
+                verification_threads,
+            ),
+            snapshot_store,
+            chunk_compression_level,
+            upload_target,
+            mempool_path,
+            stall_detector: sync_stall_timeout.map(StallDetector::new),
+            rotate_stalled_sync_peer,
             metrics,
             config,
         }
@@ -132,7 +264,9 @@ where
         loop {
             tokio::select! {
                 results = self.chain_sync.wait_for_block_import_results() => {
-                    self.chain_sync.on_blocks_processed(results);
+                    if let Some((peer_id, err)) = self.chain_sync.on_blocks_processed(results) {
+                        self.on_invalid_block(peer_id, err);
+                    }
                 }
                 maybe_event = self.network_event_receiver.recv() => {
                     let Some(event) = maybe_event else {
@@ -175,7 +309,7 @@ where
                     .on_outbound_connection_failure(peer_addr, reason);
             }
             Event::Disconnect { peer_addr, reason } => {
-                self.peer_manager.disconnect(peer_addr, reason);
+                self.peer_manager.on_connection_closed(peer_addr, reason);
                 self.chain_sync.remove_peer(peer_addr);
             }
             Event::PeerMessage {
@@ -187,6 +321,16 @@ where
 
                 tracing::trace!(?from, "Recv {msg_cmd}");
 
+                // The blocks and headers are only received in response to our own requests.
+                let requested = matches!(
+                    payload,
+                    NetworkMessage::Block(_) | NetworkMessage::Headers(_)
+                );
+                if !requested && !self.peer_manager.allow_message(from) {
+                    tracing::trace!(?from, "Dropping {msg_cmd}, message rate limit exceeded");
+                    return;
+                }
+
                 if let Some(metrics) = &self.metrics {
                     metrics
                         .messages_received
@@ -198,6 +342,15 @@ where
                     Ok(action) => self.do_sync_action(action),
                     Err(err) => {
                         tracing::error!(?from, ?err, "Failed to process peer message: {msg_cmd}");
+
+                        if let Some(ban_score) = err
+                            .misbehavior_score()
+                            .and_then(|score| self.peer_manager.misbehaving(from, score))
+                        {
+                            self.peer_manager
+                                .disconnect(from, Error::Misbehaving(ban_score));
+                            self.chain_sync.remove_peer(from);
+                        }
                     }
                 }
             }
@@ -208,6 +361,8 @@ where
         let sync_action = self.chain_sync.on_tick();
         self.do_sync_action(sync_action);
 
+        self.check_sync_stall();
+
         if let Some(SlowPeer {
             peer_id,
             peer_latency,
@@ -219,18 +374,80 @@ where
             self.chain_sync.remove_peer(peer_id);
         }
 
-        for (peer, txids) in self
+        if let Some(peer_id) = self.peer_manager.peer_to_rotate() {
+            self.peer_manager.disconnect(peer_id, Error::PeerRotation);
+            self.chain_sync.remove_peer(peer_id);
+        }
+
+        // The local transactions are announced by the broadcast manager only.
+        let mut announcements = self
             .transaction_manager
-            .on_tick(self.peer_manager.connected_peers())
-        {
+            .on_tick(self.peer_manager.transaction_relay_peers(), |txid| {
+                self.broadcast_manager.is_tracked(txid)
+            });
+
+        announcements.extend(self.broadcast_manager.on_tick(
+            self.peer_manager.transaction_relay_peers(),
+            SystemTime::now(),
+        ));
+
+        for (peer, txids) in announcements {
             tracing::debug!("Broadcasting transaction IDs {txids:?} to {peer:?}");
-            let msg = NetworkMessage::Inv(txids.into_iter().map(Inventory::Transaction).collect());
+            let wtxid_relay = self.peer_manager.is_wtxid_relay(&peer);
+            let msg = NetworkMessage::Inv(
+                txids
+                    .into_iter()
+                    .map(|txid| match self.wtxid(&txid) {
+                        Some(wtxid) if wtxid_relay => Inventory::WTx(wtxid),
+                        _ => Inventory::Transaction(txid),
+                    })
+                    .collect(),
+            );
             if let Err(err) = self.send(peer, msg) {
                 self.peer_manager.disconnect(peer, err);
             }
         }
     }
 
+    fn check_sync_stall(&mut self) {
+        let Some(stall_detector) = &mut self.stall_detector else {
+            return;
+        };
+
+        let now = Instant::now();
+        let (best_number, peer_best) = self.chain_sync.sync_heights();
+        let check = stall_detector.check(best_number, peer_best, now);
+
+        if let Some(metrics) = &self.metrics {
+            stall_detector.report_metrics(metrics, now);
+        }
+
+        let StallCheck::Stalled {
+            best_number,
+            peer_best,
+        } = check
+        else {
+            return;
+        };
+
+        let sync_peer = self.chain_sync.sync_peer();
+
+        tracing::warn!(
+            ?sync_peer,
+            "⚠️ Sync stalled at #{best_number}, the peers are at #{peer_best}"
+        );
+
+        if let Some(metrics) = &self.metrics {
+            metrics.sync_stalls.inc();
+        }
+
+        if let Some(sync_peer) = sync_peer.filter(|_| self.rotate_stalled_sync_peer) {
+            self.chain_sync.restart_sync(sync_peer);
+            self.peer_manager.disconnect(sync_peer, Error::SyncStalled);
+            self.chain_sync.remove_peer(sync_peer);
+        }
+    }
+
     fn process_worker_message(&mut self, worker_msg: NetworkWorkerMessage, bandwidth: &Bandwidth) {
         match worker_msg {
             NetworkWorkerMessage::NetworkStatus(result_sender) => {
@@ -239,6 +456,8 @@ where
                     total_bytes_inbound: bandwidth.total_bytes_inbound.load(Ordering::Relaxed),
                     total_bytes_outbound: bandwidth.total_bytes_outbound.load(Ordering::Relaxed),
                     sync_status: self.chain_sync.sync_status(),
+                    sync_phase: self.chain_sync.sync_phase(),
+                    time_offset: sc_consensus_nakamoto::time_offset(),
                 };
                 let _ = result_sender.send(net_status);
             }
@@ -246,22 +465,119 @@ where
                 let sync_peers = self.chain_sync.peers.values().cloned().collect::<Vec<_>>();
                 let _ = result_sender.send(sync_peers);
             }
-            NetworkWorkerMessage::InboundPeersCount(result_sender) => {
-                let _ = result_sender.send(self.peer_manager.inbound_peers_count());
+            NetworkWorkerMessage::PeerDetails(result_sender) => {
+                let mut peer_details = self.peer_manager.peer_details();
+                for details in peer_details.iter_mut() {
+                    details.requests_in_flight =
+                        self.chain_sync.requests_in_flight(details.peer_id);
+                    details.sync_state = self
+                        .chain_sync
+                        .peers
+                        .get(&details.peer_id)
+                        .map(|peer| peer.state);
+                }
+                let _ = result_sender.send(peer_details);
+            }
+            NetworkWorkerMessage::NetworkInfo(result_sender) => {
+                let connections = self.peer_manager.connected_peers_count();
+                let connections_in = self.peer_manager.inbound_peers_count();
+                let network_info = NetworkInfo {
+                    protocol_version: self.config.protocol_version,
+                    user_agent: self.config.user_agent.clone(),
+                    local_services: services_hex(self.config.services),
+                    connections,
+                    connections_in,
+                    connections_out: connections - connections_in,
+                    available_addresses: self.peer_manager.available_addresses_count(),
+                    total_bytes_inbound: bandwidth.inbound(),
+                    total_bytes_outbound: bandwidth.outbound(),
+                    time_offset: sc_consensus_nakamoto::time_offset(),
+                    v2_transport: self.config.services.has(ServiceFlags::from(NODE_P2P_V2)),
+                    quic: self.config.services.has(ServiceFlags::from(NODE_QUIC)),
+                    local_address: self.peer_manager.local_address(),
+                    upload_target: self.upload_target.as_mut().map(UploadTarget::info),
+                };
+                let _ = result_sender.send(network_info);
+            }
+            NetworkWorkerMessage::RequestInboundSlot(result_sender) => {
+                let accept = if self.peer_manager.has_inbound_slot() {
+                    true
+                } else if let Some(peer_id) = self.peer_manager.select_inbound_peer_to_evict() {
+                    self.peer_manager
+                        .disconnect(peer_id, Error::InboundEviction);
+                    self.chain_sync.remove_peer(peer_id);
+                    true
+                } else {
+                    false
+                };
+                let _ = result_sender.send(accept);
             }
             NetworkWorkerMessage::GetTransaction((txid, result_sender)) => {
+                let txid = self.resolve_txid(txid);
                 let _ = result_sender.send(self.transaction_manager.get_transaction(&txid));
             }
+            NetworkWorkerMessage::Transactions(result_sender) => {
+                let _ = result_sender.send(self.transaction_manager.transactions());
+            }
+            NetworkWorkerMessage::RestoreTransactions((entries, result_sender)) => {
+                let restored = entries
+                    .into_iter()
+                    .filter_map(|entry| {
+                        self.transaction_manager
+                            .restore_transaction(entry)
+                            .inspect_err(|err| tracing::debug!("Failed to restore: {err}"))
+                            .ok()
+                    })
+                    .count();
+                let _ = result_sender.send(restored);
+            }
+            NetworkWorkerMessage::SaveMempool(result_sender) => {
+                let result = match &self.mempool_path {
+                    Some(path) => self
+                        .transaction_manager
+                        .save_mempool(path)
+                        .map(|saved| (path.clone(), saved))
+                        .map_err(Error::from),
+                    None => Err(Error::Other("Mempool persistence is disabled".to_string())),
+                };
+                let _ = result_sender.send(result);
+            }
             NetworkWorkerMessage::SendTransaction((incoming_transaction, result_sender)) => {
+                let transaction = incoming_transaction.transaction.clone();
                 let send_transaction_result = match self
                     .transaction_manager
                     .add_transaction(incoming_transaction)
                 {
-                    Ok(txid) => SendTransactionResult::Success(txid),
+                    Ok(txid) => {
+                        self.broadcast_manager
+                            .track(txid, transaction, SystemTime::now());
+                        SendTransactionResult::Success(txid)
+                    }
                     Err(error_msg) => SendTransactionResult::Failure(error_msg),
                 };
                 let _ = result_sender.send(send_transaction_result);
             }
+            NetworkWorkerMessage::BroadcastStatus((txid, result_sender)) => {
+                let txid = self.resolve_txid(txid);
+                let _ = result_sender.send(self.broadcast_manager.status(&txid));
+            }
+            NetworkWorkerMessage::BlockConnected((block_hash, height, txids)) => {
+                self.broadcast_manager.on_block_connected(
+                    block_hash,
+                    height,
+                    &txids,
+                    SystemTime::now(),
+                );
+            }
+            NetworkWorkerMessage::BlocksDisconnected(block_hashes) => {
+                self.broadcast_manager.on_blocks_disconnected(&block_hashes);
+            }
+            NetworkWorkerMessage::AddNode(addr) => {
+                self.peer_manager.add_manual_peer(addr);
+            }
+            NetworkWorkerMessage::SetMappedAddress(mapped) => {
+                self.peer_manager.set_mapped_address(mapped);
+            }
         }
     }
 
@@ -303,16 +619,22 @@ where
                 Ok(SyncAction::None)
             }
             NetworkMessage::Tx(tx) => {
-                // TODO: Check has relay permission.
+                if !self.peer_manager.accepts_transactions(&from) {
+                    tracing::debug!(?from, "Ignoring transaction not relayed to us");
+                    return Ok(SyncAction::None);
+                }
                 let incoming_transaction = IncomingTransaction {
                     txid: tx.compute_txid(),
                     transaction: tx,
                 };
-                if let Err(err_msg) = self
+                match self
                     .transaction_manager
                     .add_transaction(incoming_transaction)
                 {
-                    tracing::debug!(?from, "Failed to add transaction: {err_msg}");
+                    Ok(_) => self.peer_manager.note_tx_received(from),
+                    Err(err_msg) => {
+                        tracing::debug!(?from, "Failed to add transaction: {err_msg}");
+                    }
                 }
                 Ok(SyncAction::None)
             }
@@ -364,6 +686,10 @@ where
                 self.peer_manager.set_want_addrv2(from);
                 Ok(SyncAction::None)
             }
+            NetworkMessage::WtxidRelay => {
+                self.peer_manager.on_wtxid_relay(from);
+                Ok(SyncAction::None)
+            }
             NetworkMessage::SendHeaders => {
                 self.peer_manager.set_prefer_headers(from);
                 Ok(SyncAction::None)
@@ -373,11 +699,27 @@ where
                 Ok(SyncAction::None)
             }
             NetworkMessage::Inv(inv) => self.process_inv(from, inv),
-            NetworkMessage::Block(block) => Ok(self.chain_sync.on_block(block, from)),
+            NetworkMessage::Reject(reject) => {
+                if reject.message.as_ref() == "tx" {
+                    self.broadcast_manager.on_reject(
+                        from,
+                        &Txid::from_raw_hash(reject.hash),
+                        format!("{:?}", reject.ccode),
+                        reject.reason.into_owned(),
+                    );
+                }
+                Ok(SyncAction::None)
+            }
+            NetworkMessage::Block(block) => {
+                self.peer_manager.note_block_received(from);
+                Ok(self.chain_sync.on_block(block, from))
+            }
             NetworkMessage::Headers(headers) => Ok(self.chain_sync.on_headers(headers, from)),
             NetworkMessage::MerkleBlock(_) => Ok(SyncAction::None),
-            NetworkMessage::Unknown { .. }
-            | NetworkMessage::NotFound(_)
+            NetworkMessage::Unknown { command, payload } => {
+                Ok(self.process_unknown_message(from, command, payload))
+            }
+            NetworkMessage::NotFound(_)
             | NetworkMessage::MemPool
             | NetworkMessage::FilterLoad(_)
             | NetworkMessage::FilterAdd(_)
@@ -392,9 +734,7 @@ where
             | NetworkMessage::CmpctBlock(_)
             | NetworkMessage::GetBlockTxn(_)
             | NetworkMessage::BlockTxn(_)
-            | NetworkMessage::Alert(_)
-            | NetworkMessage::Reject(_)
-            | NetworkMessage::WtxidRelay => Ok(SyncAction::None),
+            | NetworkMessage::Alert(_) => Ok(SyncAction::None),
         }
     }
 
@@ -425,6 +765,11 @@ where
                         let _ = self.send(from, NetworkMessage::GetData(invs));
                     }
                 }
+                SyncRequest::Snapshot(requests) => {
+                    for (peer_id, request) in requests {
+                        let _ = self.send(peer_id, request.into_network_message());
+                    }
+                }
             },
             SyncAction::SwitchToBlocksFirstSync => {
                 if let Some(SyncRequest::Blocks(request)) =
@@ -446,6 +791,19 @@ where
         }
     }
 
+    fn on_invalid_block(&mut self, peer_id: PeerId, err: Error) {
+        tracing::warn!(?peer_id, "Peer provided an invalid block: {err}");
+
+        if let Some(ban_score) = err
+            .misbehavior_score()
+            .and_then(|score| self.peer_manager.misbehaving(peer_id, score))
+        {
+            self.peer_manager
+                .disconnect(peer_id, Error::Misbehaving(ban_score));
+            self.chain_sync.remove_peer(peer_id);
+        }
+    }
+
     fn send_get_blocks_request(&self, request: LocatorRequest) {
         let LocatorRequest {
             locator_hashes,
@@ -468,10 +826,31 @@ where
             return Ok(SyncAction::Disconnect(from, Error::TooManyInventoryItems));
         }
 
+        if !self.peer_manager.accepts_transactions(&from) {
+            // The transactions were not asked for, only the blocks are of interest.
+            let inv = inv
+                .into_iter()
+                .filter(|item| !matches!(item, Inventory::Transaction(_) | Inventory::WTx(_)))
+                .collect();
+            return Ok(self.chain_sync.on_inv(inv, from));
+        }
+
+        for item in &inv {
+            match item {
+                Inventory::Transaction(txid) => self.broadcast_manager.on_relayed(from, txid),
+                Inventory::WTx(wtxid) => {
+                    if let Some(txid) = self.broadcast_manager.txid_for_wtxid(wtxid) {
+                        self.broadcast_manager.on_relayed(from, &txid);
+                    }
+                }
+                _ => {}
+            }
+        }
+
         Ok(self.chain_sync.on_inv(inv, from))
     }
 
-    fn process_get_data(&self, from: PeerId, get_data_requests: Vec<Inventory>) {
+    fn process_get_data(&mut self, from: PeerId, get_data_requests: Vec<Inventory>) {
         // TODO: process tx as many as possible.
         for inv in get_data_requests {
             match inv {
@@ -481,24 +860,191 @@ where
                 }
                 Inventory::Transaction(txid) => {
                     tracing::debug!("Recv transaction request: {txid:?} from {from:?}");
-                    if let Some(transaction) = self.transaction_manager.get_transaction(&txid) {
-                        if let Err(err) = self.send(from, NetworkMessage::Tx(transaction)) {
-                            tracing::error!(?err, "Failed to send transaction {txid} to {from:?}");
-                        }
+                    self.send_transaction_to(from, txid);
+                }
+                Inventory::WTx(wtxid) => {
+                    tracing::debug!("Recv transaction request: {wtxid:?} from {from:?}");
+                    if let Some(txid) = self
+                        .transaction_manager
+                        .txid_for_wtxid(&wtxid)
+                        .or_else(|| self.broadcast_manager.txid_for_wtxid(&wtxid))
+                    {
+                        self.send_transaction_to(from, txid);
                     }
                 }
-                Inventory::WTx(_)
-                | Inventory::WitnessTransaction(_)
-                | Inventory::Unknown { .. }
-                | Inventory::Error => {}
+                Inventory::WitnessTransaction(_) | Inventory::Unknown { .. } | Inventory::Error => {
+                }
             }
         }
     }
 
+    fn send_transaction_to(&mut self, from: PeerId, txid: Txid) {
+        self.broadcast_manager.on_relayed(from, &txid);
+        if let Some(transaction) = self
+            .transaction_manager
+            .get_transaction(&txid)
+            .or_else(|| self.broadcast_manager.get_transaction(&txid))
+        {
+            if let Err(err) = self.send(from, NetworkMessage::Tx(transaction)) {
+                tracing::error!(?err, "Failed to send transaction {txid} to {from:?}");
+            }
+        }
+    }
+
+    /// Returns the wtxid of a known transaction.
+    fn wtxid(&self, txid: &Txid) -> Option<Wtxid> {
+        self.transaction_manager
+            .wtxid(txid)
+            .or_else(|| self.broadcast_manager.wtxid(txid))
+    }
+
+    /// Interprets the hash as a wtxid if no transaction with the txid is known.
+    fn resolve_txid(&self, txid: Txid) -> Txid {
+        if self.wtxid(&txid).is_some() {
+            return txid;
+        }
+
+        let wtxid = Wtxid::from_raw_hash(txid.to_raw_hash());
+        self.transaction_manager
+            .txid_for_wtxid(&wtxid)
+            .or_else(|| self.broadcast_manager.txid_for_wtxid(&wtxid))
+            .unwrap_or(txid)
+    }
+
     fn process_get_block_data(&self, _inv: &Inventory) {
         // TODO: load the requested block and send them back.
     }
 
+    fn process_unknown_message(
+        &mut self,
+        from: PeerId,
+        command: CommandString,
+        payload: Vec<u8>,
+    ) -> SyncAction {
+        match SubcoinVersion::decode(&command, &payload) {
+            Some(Ok(version)) => {
+                if let Err(err) = self.peer_manager.on_subcoin_version(from, version) {
+                    return SyncAction::Disconnect(from, err);
+                }
+                return SyncAction::None;
+            }
+            Some(Err(err)) => {
+                return SyncAction::Disconnect(from, Error::InvalidSubcoinVersion(err));
+            }
+            None => {}
+        }
+
+        let message = match SnapshotMessage::decode(&command, &payload) {
+            Some(Ok(message)) => message,
+            Some(Err(err)) => {
+                return SyncAction::Disconnect(from, Error::InvalidSnapshotMessage(err));
+            }
+            None => {
+                tracing::trace!(?from, "Ignoring unknown message: {command}");
+                return SyncAction::None;
+            }
+        };
+
+        if message.is_request() {
+            self.serve_snapshot_request(from, message);
+            SyncAction::None
+        } else {
+            self.chain_sync.on_snapshot_message(message, from)
+        }
+    }
+
+    fn serve_snapshot_request(&mut self, from: PeerId, request: SnapshotMessage) {
+        let Some(store) = self.snapshot_store.clone() else {
+            tracing::trace!(
+                ?from,
+                "Ignoring snapshot request as snapshots are not served"
+            );
+            return;
+        };
+
+        let is_bulk_data = matches!(
+            request,
+            SnapshotMessage::GetManifest(_) | SnapshotMessage::GetChunk { .. }
+        );
+        if is_bulk_data
+            && self
+                .upload_target
+                .as_mut()
+                .is_some_and(UploadTarget::is_reached)
+        {
+            tracing::debug!(?from, "Ignoring snapshot request, upload target reached");
+            return;
+        }
+
+        let response = match request {
+            SnapshotMessage::GetSnapshotInfo => {
+                SnapshotMessage::SnapshotInfo(store.latest_snapshot())
+            }
+            SnapshotMessage::GetManifest(block_hash) => {
+                SnapshotMessage::Manifest(store.manifest(block_hash))
+            }
+            SnapshotMessage::GetChunk { block_hash, index } => {
+                let Some(writer) = self.peer_manager.connection_writer(from) else {
+                    return;
+                };
+
+                let compression_level = self.chunk_compression_level.filter(|_| {
+                    self.peer_manager
+                        .subcoin_version(&from)
+                        .is_some_and(|version| {
+                            version.features.has(SubcoinFeatures::COMPRESSED_CHUNKS)
+                        })
+                });
+
+                // Reading a chunk from the state takes a while, do not block the worker.
+                tokio::task::spawn_blocking(move || match store.chunk(block_hash, index) {
+                    Ok(chunk) => {
+                        let message = match compression_level {
+                            Some(level) => match chunk.compress(level) {
+                                Ok(compressed) => SnapshotMessage::CompressedChunk(compressed),
+                                Err(err) => {
+                                    tracing::debug!(
+                                        ?err,
+                                        "Failed to compress snapshot chunk {index}"
+                                    );
+                                    SnapshotMessage::Chunk(chunk)
+                                }
+                            },
+                            None => SnapshotMessage::Chunk(chunk),
+                        };
+                        let network_message = message.into_network_message();
+                        if exceeds_max_message_size(&network_message) {
+                            tracing::warn!("Snapshot chunk {index} is too large to send");
+                            return;
+                        }
+                        let _ = writer.send(network_message);
+                    }
+                    Err(err) => {
+                        tracing::debug!(?from, ?err, "Failed to load snapshot chunk {index}");
+                    }
+                });
+
+                return;
+            }
+            SnapshotMessage::SnapshotInfo(_)
+            | SnapshotMessage::Manifest(_)
+            | SnapshotMessage::Chunk(_)
+            | SnapshotMessage::CompressedChunk(_) => return,
+        };
+
+        let network_message = response.into_network_message();
+
+        if exceeds_max_message_size(&network_message) {
+            tracing::warn!(
+                "Snapshot response {} is too large to send",
+                network_message.cmd()
+            );
+            return;
+        }
+
+        let _ = self.send(from, network_message);
+    }
+
     /// Send a network message to given peer.
     #[inline]
     fn send(&self, peer_id: PeerId, network_message: NetworkMessage) -> Result<(), Error> {
@@ -510,3 +1056,23 @@ where
         Ok(())
     }
 }
+
+impl<Block, Client> Drop for NetworkWorker<Block, Client> {
+    fn drop(&mut self) {
+        let Some(path) = &self.mempool_path else {
+            return;
+        };
+
+        match self.transaction_manager.save_mempool(path) {
+            Ok(saved) => tracing::info!("Saved {saved} transactions to {}", path.display()),
+            Err(err) => tracing::warn!("Failed to save transactions to {}: {err}", path.display()),
+        }
+    }
+}
+
+fn exceeds_max_message_size(network_message: &NetworkMessage) -> bool {
+    matches!(
+        network_message,
+        NetworkMessage::Unknown { payload, .. } if payload.len() + MESSAGE_HEADER_SIZE > MAX_MSG_SIZE
+    )
+}
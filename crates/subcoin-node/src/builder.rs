@@ -0,0 +1,1205 @@
+//! Library API for embedding a subcoin node into a custom binary.
+//!
+//! [`SubcoinNodeBuilder`] wires up the same components as the `run` command, with each
+//! subsystem being optional:
+//!
+//! ```ignore
+//! let node = SubcoinNodeBuilder::new(config)
+//!     .with_bitcoin_network(network_params)
+//!     .with_rpc(true)
+//!     .with_finalizer(None)
+//!     .build()?;
+//! ```
+
+use crate::commands::export_analytics::ExportAnalyticsJob;
+use crate::telemetry::DEFAULT_BITCOIN_TELEMETRY_INTERVAL;
+use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut};
+use codec::Decode;
+use futures::StreamExt;
+use jsonrpsee::server::BatchRequestConfig;
+use sc_client_api::{BlockBackend, BlockchainEvents, StorageKey, StorageProvider, UsageProvider};
+use sc_consensus_nakamoto::{
+    BitcoinBlockImporter, BlockExecutionStrategy, BlockVerification, BlockVerifier, ChainReorg,
+    ClientContext, ImportConfig, LocalBlockImport, ReferenceNode, RuntimeBlockExecutor,
+    StateRootAuditor,
+};
+use sc_network_sync::SyncingService;
+use sc_service::config::{BlocksPruning, PruningMode, RpcBatchRequestConfig, RpcMethods};
+use sc_service::error::Error as ServiceError;
+use sc_service::{Configuration, TaskManager};
+use sc_telemetry::Telemetry;
+use sc_utils::mpsc::TracingUnboundedSender;
+use sp_blockchain::HeaderBackend;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use subcoin_indexer::{BackfillConfig, BlockIndexer, OpReturnIndex, TxIndex};
+use subcoin_network::{NetworkHandle, SnapshotParams, StopAt, SyncStrategy, UtxoProvider};
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{BackendExt, BitcoinTransactionAdapter, BlockPruning, CoinStorageKey};
+use subcoin_rpc::auth::RpcAuth;
+use subcoin_rpc::fee_estimation::FeeEstimator;
+use subcoin_rpc::server::ServerConfig;
+use subcoin_runtime::interface::OpaqueBlock as Block;
+use subcoin_service::{
+    BlockSource, ExecutorKind, FinalityPolicyConfig, FullBackend, FullClient, InMemoryBackendConfig,
+};
+use subcoin_snapshot::{
+    ClientSnapshotStore, HttpBootstrap, SnapshotStore, DEFAULT_CHUNK_COMPRESSION_LEVEL,
+};
+
+/// Default confirmation depth used by the finalizer during the major sync.
+const DEFAULT_MAJOR_SYNC_CONFIRMATION_DEPTH: u32 = 100;
+
+/// A running subcoin node.
+pub struct SubcoinNode {
+    /// Client.
+    pub client: Arc<FullClient>,
+    /// Task manager, must be kept alive for as long as the node is running.
+    pub task_manager: TaskManager,
+    /// Handle of the Bitcoin networking.
+    ///
+    /// Always available, even when the Bitcoin networking is not running.
+    pub network_handle: NetworkHandle,
+    /// Sync service of the Substrate networking.
+    pub substrate_sync_service: Arc<SyncingService<Block>>,
+    /// Sender of the Substrate system RPC requests.
+    pub system_rpc_tx: TracingUnboundedSender<sc_rpc::system::Request<Block>>,
+    /// Handle for importing the blocks produced locally, e.g., the mined blocks.
+    pub local_block_import: LocalBlockImport,
+}
+
+/// Builder of [`SubcoinNode`].
+pub struct SubcoinNodeBuilder {
+    config: Configuration,
+    network_params: subcoin_network::Params,
+    block_execution_strategy: BlockExecutionStrategy,
+    executor: ExecutorKind,
+    import_config: Option<ImportConfig>,
+    bitcoin_networking: bool,
+    block_source: Option<Arc<dyn BlockSource>>,
+    persist_mempool: bool,
+    rpc: bool,
+    finalizer: Option<u32>,
+    finality_policy: FinalityPolicyConfig,
+    informant: bool,
+    bitcoin_telemetry_interval: Option<Duration>,
+    wallet: bool,
+    chain_stats: bool,
+    op_return_index: bool,
+    silent_payments_index: bool,
+    tx_index: bool,
+    index_backfill: BackfillConfig,
+    rpc_auth: Option<RpcAuth>,
+    rpc_cookie: bool,
+    rest: Option<SocketAddr>,
+    grpc: Option<SocketAddr>,
+    hardware_benchmarks: bool,
+    storage_monitor: sc_storage_monitor::StorageMonitorParams,
+    serve_snapshots: bool,
+    snapshot_compression_level: Option<i32>,
+    snapshot_sync_quorum: Option<usize>,
+    snapshot_bootstrap: Option<HttpBootstrap>,
+    block_pruning: Option<BlockPruning>,
+    verify_against: Option<ReferenceNode>,
+    state_root_diagnostics: bool,
+    state_root_audit: Option<u32>,
+    in_memory_backend: InMemoryBackendConfig,
+    exit_on_stop: Option<Arc<AtomicBool>>,
+}
+
+impl SubcoinNodeBuilder {
+    /// Constructs a new instance of [`SubcoinNodeBuilder`].
+    ///
+    /// By default, the node follows the Bitcoin mainnet with all the subsystems enabled.
+    pub fn new(config: Configuration) -> Self {
+        Self {
+            config,
+            network_params: default_network_params(bitcoin::Network::Bitcoin),
+            block_execution_strategy: BlockExecutionStrategy::runtime_disk(),
+            executor: ExecutorKind::default(),
+            import_config: None,
+            bitcoin_networking: true,
+            block_source: None,
+            persist_mempool: true,
+            rpc: true,
+            finalizer: Some(DEFAULT_MAJOR_SYNC_CONFIRMATION_DEPTH),
+            finality_policy: FinalityPolicyConfig::default(),
+            informant: true,
+            bitcoin_telemetry_interval: Some(DEFAULT_BITCOIN_TELEMETRY_INTERVAL),
+            wallet: false,
+            chain_stats: false,
+            op_return_index: false,
+            silent_payments_index: false,
+            tx_index: false,
+            index_backfill: BackfillConfig::default(),
+            rpc_auth: None,
+            rpc_cookie: false,
+            rest: None,
+            grpc: None,
+            hardware_benchmarks: true,
+            storage_monitor: Default::default(),
+            serve_snapshots: false,
+            snapshot_compression_level: Some(DEFAULT_CHUNK_COMPRESSION_LEVEL),
+            snapshot_sync_quorum: None,
+            snapshot_bootstrap: None,
+            block_pruning: None,
+            verify_against: None,
+            state_root_diagnostics: false,
+            state_root_audit: None,
+            in_memory_backend: Default::default(),
+            exit_on_stop: None,
+        }
+    }
+
+    /// Specifies the Bitcoin networking params, including the Bitcoin network type.
+    pub fn with_bitcoin_network(mut self, network_params: subcoin_network::Params) -> Self {
+        self.network_params = network_params;
+        self
+    }
+
+    /// Specifies the block execution strategy.
+    pub fn with_block_execution_strategy(
+        mut self,
+        block_execution_strategy: BlockExecutionStrategy,
+    ) -> Self {
+        self.block_execution_strategy = block_execution_strategy;
+        self
+    }
+
+    /// Specifies the executor running the runtime, native else wasm by default.
+    pub fn with_executor(mut self, executor: ExecutorKind) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Specifies the block import config.
+    ///
+    /// Full verification with script checks is used if not specified.
+    pub fn with_import_config(mut self, import_config: ImportConfig) -> Self {
+        self.import_config.replace(import_config);
+        self
+    }
+
+    /// Whether to run the Bitcoin networking.
+    pub fn with_bitcoin_networking(mut self, enabled: bool) -> Self {
+        self.bitcoin_networking = enabled;
+        self
+    }
+
+    /// Imports the blocks from `block_source` instead of syncing from the Bitcoin network.
+    ///
+    /// The Bitcoin networking is not run then, its handle remains available.
+    pub fn with_block_source(mut self, block_source: Option<Arc<dyn BlockSource>>) -> Self {
+        self.block_source = block_source;
+        self
+    }
+
+    /// Whether to save the unconfirmed transactions to `mempool.dat` in the chain data
+    /// directory on shutdown and load them on startup, enabled by default. The fee rates of the
+    /// fee estimator are persisted next to it.
+    ///
+    /// Only applies when the Bitcoin networking is running.
+    pub fn with_persist_mempool(mut self, enabled: bool) -> Self {
+        self.persist_mempool = enabled;
+        self
+    }
+
+    /// Whether to start the RPC servers.
+    pub fn with_rpc(mut self, enabled: bool) -> Self {
+        self.rpc = enabled;
+        self
+    }
+
+    /// Specifies the confirmation depth of the finalizer during the major sync.
+    ///
+    /// `None` disables the finalizer.
+    pub fn with_finalizer(mut self, major_sync_confirmation_depth: Option<u32>) -> Self {
+        self.finalizer = major_sync_confirmation_depth;
+        self
+    }
+
+    /// Specifies the policy deciding the blocks finalized by the finalizer, by the standard
+    /// confirmation depth by default.
+    pub fn with_finality_policy(mut self, finality_policy: FinalityPolicyConfig) -> Self {
+        self.finality_policy = finality_policy;
+        self
+    }
+
+    /// Whether to run the subcoin informant.
+    pub fn with_informant(mut self, enabled: bool) -> Self {
+        self.informant = enabled;
+        self
+    }
+
+    /// Specifies the interval of sending the Bitcoin stats to the telemetry server, every 30
+    /// seconds by default.
+    ///
+    /// `None` disables the Bitcoin stats, it has no effect if the telemetry is disabled.
+    pub fn with_bitcoin_telemetry(mut self, interval: Option<Duration>) -> Self {
+        self.bitcoin_telemetry_interval = interval;
+        self
+    }
+
+    /// Whether to run the watch-only wallet, disabled by default.
+    ///
+    /// The wallet RPCs are only available when the RPC servers are enabled.
+    pub fn with_wallet(mut self, enabled: bool) -> Self {
+        self.wallet = enabled;
+        self
+    }
+
+    /// Whether to index the UTXO set statistics of the finalized blocks, disabled by default.
+    pub fn with_chain_stats(mut self, enabled: bool) -> Self {
+        self.chain_stats = enabled;
+        self
+    }
+
+    /// Whether to index the `OP_RETURN` outputs of the finalized blocks, disabled by default.
+    pub fn with_op_return_index(mut self, enabled: bool) -> Self {
+        self.op_return_index = enabled;
+        self
+    }
+
+    /// Whether to index the silent payments tweaks of the finalized blocks, disabled by
+    /// default.
+    pub fn with_silent_payments_index(mut self, enabled: bool) -> Self {
+        self.silent_payments_index = enabled;
+        self
+    }
+
+    /// Whether to index the transactions of the finalized blocks by txid and wtxid, disabled
+    /// by default.
+    pub fn with_tx_index(mut self, enabled: bool) -> Self {
+        self.tx_index = enabled;
+        self
+    }
+
+    /// Specifies the pace of backfilling the `OP_RETURN` and transaction indexes enabled on an
+    /// already synced node, unlimited by default.
+    pub fn with_index_backfill(mut self, index_backfill: BackfillConfig) -> Self {
+        self.index_backfill = index_backfill;
+        self
+    }
+
+    /// Specifies the RPC authentication, disabled by default.
+    ///
+    /// Once enabled, the RPCs are served by the authenticated RPC server instead of the
+    /// Substrate one.
+    pub fn with_rpc_auth(mut self, rpc_auth: Option<RpcAuth>) -> Self {
+        self.rpc_auth = rpc_auth;
+        self
+    }
+
+    /// Whether to generate the `.cookie` file in the base path for the RPC authentication.
+    ///
+    /// Enables the RPC authentication if not yet enabled by [`Self::with_rpc_auth`].
+    pub fn with_rpc_cookie(mut self, enabled: bool) -> Self {
+        self.rpc_cookie = enabled;
+        self
+    }
+
+    /// Specifies the address of the REST interface, disabled by default.
+    pub fn with_rest(mut self, addr: Option<SocketAddr>) -> Self {
+        self.rest = addr;
+        self
+    }
+
+    /// Specifies the address of the gRPC interface, disabled by default.
+    pub fn with_grpc(mut self, addr: Option<SocketAddr>) -> Self {
+        self.grpc = addr;
+        self
+    }
+
+    /// Whether to run the hardware benchmarks on startup.
+    pub fn with_hardware_benchmarks(mut self, enabled: bool) -> Self {
+        self.hardware_benchmarks = enabled;
+        self
+    }
+
+    /// Specifies the storage monitor params.
+    pub fn with_storage_monitor(
+        mut self,
+        storage_monitor: sc_storage_monitor::StorageMonitorParams,
+    ) -> Self {
+        self.storage_monitor = storage_monitor;
+        self
+    }
+
+    /// Whether to generate the snapshots and serve them to the peers, disabled by default.
+    ///
+    /// The state of the snapshot blocks must be kept, use the archive state pruning.
+    pub fn with_serve_snapshots(mut self, enabled: bool) -> Self {
+        self.serve_snapshots = enabled;
+        self
+    }
+
+    /// Specifies the zstd compression level of the snapshot chunks served to the peers,
+    /// [`DEFAULT_CHUNK_COMPRESSION_LEVEL`] by default.
+    ///
+    /// `None` serves the chunks uncompressed.
+    pub fn with_snapshot_compression(mut self, level: Option<i32>) -> Self {
+        self.snapshot_compression_level = level;
+        self
+    }
+
+    /// Specifies the number of peers that must announce the same snapshot before the
+    /// snapshot sync starts on a fresh node.
+    ///
+    /// `None` disables the snapshot sync.
+    pub fn with_snapshot_sync(mut self, quorum: Option<usize>) -> Self {
+        self.snapshot_sync_quorum = quorum;
+        self
+    }
+
+    /// Specifies the snapshot artifact to bootstrap the state from on a fresh node before the
+    /// Bitcoin networking starts, disabled by default.
+    pub fn with_snapshot_bootstrap(mut self, bootstrap: Option<HttpBootstrap>) -> Self {
+        self.snapshot_bootstrap = bootstrap;
+        self
+    }
+
+    /// Specifies the block body pruning, disabled by default.
+    ///
+    /// Overrides the blocks pruning in the configuration. The bodies are only pruned once
+    /// finalized, the finalizer needs to be running.
+    pub fn with_block_pruning(mut self, block_pruning: Option<BlockPruning>) -> Self {
+        self.block_pruning = block_pruning;
+        self
+    }
+
+    /// Specifies the trusted bitcoind to validate the imported blocks against, disabled by
+    /// default.
+    pub fn with_differential_validation(mut self, reference: Option<ReferenceNode>) -> Self {
+        self.verify_against = reference;
+        self
+    }
+
+    /// Specifies the memory limits of the in-memory backend.
+    pub fn with_in_memory_backend_config(mut self, config: InMemoryBackendConfig) -> Self {
+        self.in_memory_backend = config;
+        self
+    }
+
+    /// Cross-checks the state root of each imported block with the other execution strategy,
+    /// disabled by default.
+    pub fn with_state_root_diagnostics(mut self, enable: bool) -> Self {
+        self.state_root_diagnostics = enable;
+        self
+    }
+
+    /// Audits the state root of every `sample_rate`-th block with the runtime in the background,
+    /// disabled by default.
+    ///
+    /// Only applies to the execution strategies bypassing the runtime.
+    pub fn with_state_root_audit(mut self, sample_rate: Option<u32>) -> Self {
+        self.state_root_audit = sample_rate;
+        self
+    }
+
+    /// Shuts down the node once the sync reaches [`subcoin_network::Params::stop_at`],
+    /// disabled by default.
+    ///
+    /// `stop_reached` is set right before the shutdown, to tell it apart from a failure.
+    pub fn with_exit_on_stop(mut self, stop_reached: Option<Arc<AtomicBool>>) -> Self {
+        self.exit_on_stop = stop_reached;
+        self
+    }
+
+    /// Builds the node and spawns the enabled subsystems.
+    pub fn build(self) -> Result<SubcoinNode, ServiceError> {
+        let Self {
+            mut config,
+            mut network_params,
+            block_execution_strategy,
+            executor,
+            import_config,
+            mut bitcoin_networking,
+            block_source,
+            persist_mempool,
+            rpc,
+            finalizer,
+            finality_policy,
+            informant,
+            bitcoin_telemetry_interval,
+            wallet,
+            chain_stats,
+            op_return_index,
+            silent_payments_index,
+            tx_index,
+            index_backfill,
+            rpc_auth,
+            rpc_cookie,
+            rest,
+            grpc,
+            hardware_benchmarks,
+            storage_monitor,
+            serve_snapshots,
+            snapshot_compression_level,
+            snapshot_sync_quorum,
+            snapshot_bootstrap,
+            block_pruning,
+            verify_against,
+            state_root_diagnostics,
+            state_root_audit,
+            in_memory_backend,
+            exit_on_stop,
+        } = self;
+
+        if let Some(block_pruning) = block_pruning {
+            config.blocks_pruning = BlocksPruning::Some(block_pruning.blocks_to_keep());
+
+            if finalizer.is_none() {
+                tracing::warn!("No block bodies will be pruned as the finalizer is disabled");
+            }
+        }
+
+        let network = network_params.network;
+        let mut import_config = import_config.unwrap_or(ImportConfig {
+            network,
+            chain_params: None,
+            block_verification: BlockVerification::Full,
+            execute_block: true,
+            verify_script: true,
+            verification_threads: None,
+        });
+
+        let subcoin_service::NodeComponents {
+            client,
+            backend,
+            mut task_manager,
+            block_executor,
+            network_tip,
+            keystore_container,
+            telemetry,
+            subcoin_db,
+            verification_threads,
+            ..
+        } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
+            network,
+            config: &config,
+            block_execution_strategy,
+            no_hardware_benchmarks: !hardware_benchmarks,
+            storage_monitor,
+            in_memory_backend,
+            executor,
+        })?;
+
+        crate::db_migration::migrator(subcoin_db.clone())
+            .run()
+            .map_err(|err| ServiceError::Application(Box::new(err)))?;
+
+        import_config
+            .verification_threads
+            .get_or_insert(verification_threads);
+        network_params
+            .verification_threads
+            .get_or_insert(verification_threads);
+
+        let chain_info = client.usage_info().chain;
+
+        tracing::info!("📦 Highest known block at #{}", chain_info.best_number);
+
+        let spawn_handle = task_manager.spawn_handle();
+
+        let mut bitcoin_block_import =
+            BitcoinBlockImporter::<_, _, _, _, subcoin_service::TransactionAdapter>::new(
+                client.clone(),
+                client.clone(),
+                import_config,
+                Arc::new(subcoin_service::CoinStorageKey),
+                block_executor,
+                config.prometheus_registry(),
+            );
+
+        if let Some(reference) = verify_against {
+            bitcoin_block_import = bitcoin_block_import.with_differential_validation(reference);
+        }
+
+        if state_root_diagnostics {
+            bitcoin_block_import = bitcoin_block_import.with_state_root_diagnostics(
+                subcoin_service::new_reference_block_executor(
+                    client.clone(),
+                    block_execution_strategy,
+                ),
+            );
+        }
+
+        let state_root_audit = state_root_audit.and_then(|sample_rate| {
+            if matches!(
+                block_execution_strategy,
+                BlockExecutionStrategy::RuntimeExecution(_)
+                    | BlockExecutionStrategy::BenchmarkRuntimeExecution
+            ) {
+                tracing::warn!(
+                    "State root audit disabled as the blocks are executed by the runtime"
+                );
+                return None;
+            }
+
+            let auditor = StateRootAuditor::new(
+                client.clone(),
+                Box::new(RuntimeBlockExecutor::new(
+                    client.clone(),
+                    ClientContext::<FullClient>::Disk,
+                )),
+                sample_rate,
+                config.prometheus_registry(),
+            );
+            let audit = auditor.audit();
+            spawn_handle.spawn_blocking("state-root-audit", None, auditor.run());
+            Some(audit)
+        });
+
+        let import_queue = sc_consensus_nakamoto::bitcoin_import_queue(
+            &task_manager.spawn_essential_handle(),
+            bitcoin_block_import,
+        );
+        let local_block_import = import_queue.local_block_import();
+
+        let import_queue = match block_source {
+            Some(block_source) => {
+                bitcoin_networking = false;
+                task_manager.spawn_essential_handle().spawn(
+                    "block-source",
+                    None,
+                    subcoin_service::follow_block_source::<Block, _>(
+                        client.clone(),
+                        block_source,
+                        import_queue,
+                    ),
+                );
+                sc_consensus_nakamoto::disabled_import_queue(&task_manager.spawn_essential_handle())
+            }
+            None => import_queue,
+        };
+
+        if let (Some(stop_at), Some(stop_reached)) = (network_params.stop_at, exit_on_stop) {
+            // The shutdown is triggered by the essential task exiting.
+            task_manager.spawn_essential_handle().spawn(
+                "sync-stop",
+                None,
+                wait_for_stop(client.clone(), stop_at, stop_reached),
+            );
+        }
+
+        let snapshot_network = serve_snapshots || snapshot_sync_quorum.is_some();
+
+        let snapshot_store = (snapshot_network || snapshot_bootstrap.is_some())
+            .then(|| {
+                ClientSnapshotStore::new(
+                    client.clone(),
+                    subcoin_db.clone(),
+                    client.clone(),
+                    Arc::new(subcoin_service::CoinStorageKey),
+                )
+                .map(Arc::new)
+                .map_err(|err| ServiceError::Application(Box::new(err)))
+            })
+            .transpose()?;
+
+        if let Some(store) = snapshot_store.as_ref().filter(|_| snapshot_network) {
+            if serve_snapshots
+                && !matches!(
+                    config.state_pruning,
+                    Some(PruningMode::ArchiveAll | PruningMode::ArchiveCanonical)
+                )
+            {
+                tracing::warn!(
+                    "Serving snapshots requires the archive state pruning, \
+                     the snapshots will be unavailable once the state is pruned"
+                );
+            }
+
+            network_params.snapshot.replace(SnapshotParams {
+                store: store.clone(),
+                serve: serve_snapshots,
+                sync_quorum: snapshot_sync_quorum,
+                chunk_compression_level: snapshot_compression_level,
+            });
+        }
+
+        network_params.db.replace(subcoin_db.clone());
+        network_params.network_tip.replace(network_tip);
+
+        let mempool_path =
+            (bitcoin_networking && persist_mempool).then(|| config.data_path.join("mempool.dat"));
+        network_params.mempool_path = mempool_path.clone();
+        let fee_estimates_path = mempool_path
+            .as_ref()
+            .map(|path| path.with_file_name(subcoin_rpc::fee_estimation::FEE_ESTIMATES_FILE_NAME));
+        network_params
+            .utxo_provider
+            .replace(Arc::new(StateUtxoProvider {
+                client: client.clone(),
+            }));
+
+        let (subcoin_networking, network_handle) = subcoin_network::Network::new(
+            client.clone(),
+            network_params,
+            import_queue,
+            spawn_handle.clone(),
+            config.prometheus_registry().cloned(),
+        );
+
+        let snapshot_bootstrap = snapshot_bootstrap.and_then(|bootstrap| {
+            if chain_info.best_number > 0 {
+                tracing::info!("Skipping the snapshot bootstrap as the node is not fresh");
+                return None;
+            }
+
+            snapshot_store.clone().map(|store| async move {
+                bootstrap
+                    .run(store.as_ref())
+                    .await
+                    .inspect_err(|err| tracing::error!(?err, "Failed to bootstrap from snapshot"))
+                    .is_ok()
+            })
+        });
+
+        // TODO: handle Substrate networking and Bitcoin networking properly.
+        if bitcoin_networking {
+            task_manager.spawn_essential_handle().spawn_blocking(
+                "subcoin-networking",
+                None,
+                async move {
+                    // The sync resumes from the snapshot block once bootstrapped.
+                    if let Some(bootstrap) = snapshot_bootstrap {
+                        if !bootstrap.await {
+                            return;
+                        }
+                    }
+
+                    if let Err(err) = subcoin_networking.run().await {
+                        tracing::error!(?err, "Error occurred in subcoin networking");
+                    }
+                },
+            );
+        } else {
+            task_manager.keep_alive(subcoin_networking);
+
+            if let Some(bootstrap) = snapshot_bootstrap {
+                spawn_handle.spawn_blocking("snapshot-bootstrap", None, async move {
+                    bootstrap.await;
+                });
+            }
+        }
+
+        if let Some(path) = mempool_path {
+            let verifier = BlockVerifier::new(
+                client.clone(),
+                network,
+                BlockVerification::Full,
+                Arc::new(subcoin_service::CoinStorageKey),
+                true,
+            );
+            spawn_handle.spawn_blocking(
+                "mempool-loader",
+                None,
+                load_mempool(path, verifier, network_handle.clone()),
+            );
+        }
+
+        if bitcoin_networking {
+            spawn_handle.spawn(
+                "transaction-confirmations",
+                None,
+                note_confirmations(client.clone(), network_handle.clone()),
+            );
+        }
+
+        let rpc_backend = backend.clone();
+
+        let bitcoin_telemetry = telemetry
+            .as_ref()
+            .map(Telemetry::handle)
+            .zip(bitcoin_telemetry_interval);
+
+        let (system_rpc_tx, substrate_sync_service) = match config.network.network_backend {
+            sc_network::config::NetworkBackendType::Libp2p => {
+                subcoin_service::start_substrate_network::<
+                    sc_network::NetworkWorker<Block, <Block as sp_runtime::traits::Block>::Hash>,
+                >(
+                    &mut config,
+                    client.clone(),
+                    backend,
+                    &mut task_manager,
+                    keystore_container.keystore(),
+                    telemetry,
+                )?
+            }
+            sc_network::config::NetworkBackendType::Litep2p => {
+                subcoin_service::start_substrate_network::<sc_network::Litep2pNetworkBackend>(
+                    &mut config,
+                    client.clone(),
+                    backend,
+                    &mut task_manager,
+                    keystore_container.keystore(),
+                    telemetry,
+                )?
+            }
+        };
+
+        if chain_stats {
+            let indexer = subcoin_indexer::ChainStatsIndexer::new(
+                client.clone(),
+                subcoin_db.clone(),
+                Arc::new(subcoin_service::CoinStorageKey),
+            );
+            spawn_handle.spawn_blocking(
+                "chain-stats-indexer",
+                None,
+                indexer.run::<subcoin_service::TransactionAdapter>(),
+            );
+        }
+
+        if op_return_index {
+            let indexer = BlockIndexer::new(client.clone(), subcoin_db.clone(), OpReturnIndex)
+                .with_backfill(index_backfill);
+            spawn_handle.spawn_blocking(
+                "op-return-indexer",
+                None,
+                indexer.run::<subcoin_service::TransactionAdapter>(),
+            );
+        }
+
+        if silent_payments_index {
+            let indexer = subcoin_indexer::SilentPaymentsIndexer::new(
+                client.clone(),
+                subcoin_db.clone(),
+                Arc::new(subcoin_service::CoinStorageKey),
+            );
+            spawn_handle.spawn_blocking(
+                "silent-payments-indexer",
+                None,
+                indexer.run::<subcoin_service::TransactionAdapter>(),
+            );
+        }
+
+        if tx_index {
+            let indexer = BlockIndexer::new(client.clone(), subcoin_db.clone(), TxIndex)
+                .with_backfill(index_backfill);
+            spawn_handle.spawn_blocking(
+                "tx-indexer",
+                None,
+                indexer.run::<subcoin_service::TransactionAdapter>(),
+            );
+        }
+
+        if rpc {
+            let fee_estimator = match fee_estimates_path {
+                Some(path) => {
+                    let fee_estimator = FeeEstimator::load(path);
+                    task_manager.keep_alive(fee_estimator.save_on_drop());
+                    fee_estimator
+                }
+                None => FeeEstimator::new(),
+            };
+
+            spawn_handle.spawn(
+                "fee-estimator",
+                None,
+                fee_estimator
+                    .clone()
+                    .run::<_, _, _, subcoin_service::TransactionAdapter>(
+                        client.clone(),
+                        Arc::new(subcoin_service::CoinStorageKey),
+                        network_handle.is_major_syncing(),
+                    ),
+            );
+
+            let wallet = if wallet {
+                let wallet = subcoin_wallet::Wallet::new(
+                    client.clone(),
+                    subcoin_db.clone(),
+                    network,
+                    Arc::new(subcoin_service::CoinStorageKey),
+                )
+                .map_err(|err| ServiceError::Application(Box::new(err)))?;
+
+                spawn_handle.spawn(
+                    "subcoin-wallet",
+                    None,
+                    wallet.clone().run::<subcoin_service::TransactionAdapter>(),
+                );
+
+                Some(wallet)
+            } else {
+                None
+            };
+
+            let job_manager = subcoin_service::JobManager::new(client.clone());
+            job_manager.register(
+                ExportAnalyticsJob::KIND,
+                Arc::new(ExportAnalyticsJob::new(client.clone(), network)),
+            );
+            if let Err(err) = job_manager.resume_interrupted() {
+                tracing::error!("Failed to resume the interrupted jobs: {err}");
+            }
+
+            let rpc_auth = if rpc_cookie {
+                let (cookie, credential) =
+                    subcoin_rpc::auth::Cookie::generate(config.base_path.path())
+                        .map_err(|err| ServiceError::Application(Box::new(err)))?;
+                task_manager.keep_alive(cookie);
+                let mut rpc_auth = rpc_auth.unwrap_or_default();
+                rpc_auth.add_credential(credential);
+                Some(rpc_auth)
+            } else {
+                rpc_auth
+            };
+
+            // TODO: Bitcoin-compatible RPC
+            let gen_rpc_module = |deny_unsafe: sc_rpc::DenyUnsafe| {
+                let system_info = sc_rpc::system::SystemInfo {
+                    chain_name: config.chain_spec.name().into(),
+                    impl_name: config.impl_name.clone(),
+                    impl_version: config.impl_version.clone(),
+                    properties: config.chain_spec.properties(),
+                    chain_type: config.chain_spec.chain_type(),
+                };
+
+                crate::rpc::gen_rpc_module(
+                    system_info,
+                    client.clone(),
+                    rpc_backend.clone(),
+                    task_manager.spawn_handle(),
+                    system_rpc_tx.clone(),
+                    deny_unsafe,
+                    network_handle.clone(),
+                    local_block_import.clone(),
+                    network,
+                    fee_estimator.clone(),
+                    wallet.clone(),
+                    block_pruning,
+                    chain_stats.then(|| subcoin_db.clone()),
+                    op_return_index.then(|| subcoin_db.clone()),
+                    silent_payments_index.then(|| subcoin_db.clone()),
+                    tx_index.then(|| subcoin_db.clone()),
+                    finalizer.and(finality_policy.confirmation_depth()),
+                    match &finality_policy {
+                        FinalityPolicyConfig::External(oracle) if finalizer.is_some() => {
+                            Some(oracle.clone())
+                        }
+                        _ => None,
+                    },
+                    state_root_audit.clone(),
+                    job_manager.clone(),
+                )
+            };
+
+            if let Some(rpc_auth) = rpc_auth {
+                let rpc = start_authenticated_rpc_server(&config, gen_rpc_module, rpc_auth)?;
+                task_manager.keep_alive((config.base_path.clone(), rpc));
+            } else {
+                let rpc = sc_service::start_rpc_servers(&config, gen_rpc_module, None)?;
+                task_manager.keep_alive((config.base_path.clone(), rpc));
+            }
+        }
+
+        if let Some(addr) = rest {
+            let rest = subcoin_rest::Rest::<
+                Block,
+                FullClient,
+                FullBackend,
+                subcoin_service::TransactionAdapter,
+            >::new(
+                client.clone(),
+                network,
+                Arc::new(subcoin_service::CoinStorageKey),
+            );
+            spawn_handle.spawn("subcoin-rest", None, rest.run(addr));
+        }
+
+        if let Some(addr) = grpc {
+            let grpc = subcoin_grpc::Grpc::<
+                Block,
+                FullClient,
+                FullBackend,
+                subcoin_service::TransactionAdapter,
+            >::new(
+                client.clone(),
+                network_handle.clone(),
+                Arc::new(subcoin_service::CoinStorageKey),
+            );
+            spawn_handle.spawn("subcoin-grpc", None, grpc.run(addr));
+        }
+
+        if let Some(major_sync_confirmation_depth) = finalizer {
+            spawn_handle.spawn(
+                "finalizer",
+                None,
+                subcoin_service::finalize_confirmed_blocks(
+                    client.clone(),
+                    spawn_handle.clone(),
+                    finality_policy.into_policy(client.clone()),
+                    major_sync_confirmation_depth,
+                    network_handle.is_major_syncing(),
+                    Some(substrate_sync_service.clone()),
+                ),
+            );
+        }
+
+        if let Some((telemetry, interval)) = bitcoin_telemetry {
+            spawn_handle.spawn(
+                "bitcoin-telemetry",
+                None,
+                crate::telemetry::report_bitcoin_stats(
+                    telemetry,
+                    client.clone(),
+                    network_handle.clone(),
+                    chain_stats.then(|| subcoin_db.clone()),
+                    snapshot_store
+                        .clone()
+                        .filter(|_| serve_snapshots)
+                        .map(|store| store as Arc<dyn SnapshotStore>),
+                    interval,
+                ),
+            );
+        }
+
+        if let Some(store) = snapshot_store.filter(|_| serve_snapshots) {
+            spawn_handle.spawn_blocking(
+                "snapshot-generator",
+                None,
+                subcoin_snapshot::snapshot_generator(
+                    client.clone(),
+                    store,
+                    network_handle.is_major_syncing(),
+                ),
+            );
+        }
+
+        if informant {
+            spawn_handle.spawn(
+                "subcoin-informant",
+                None,
+                subcoin_informant::build(client.clone(), network_handle.clone()),
+            );
+        }
+
+        Ok(SubcoinNode {
+            client,
+            task_manager,
+            network_handle,
+            substrate_sync_service,
+            system_rpc_tx,
+            local_block_import,
+        })
+    }
+}
+
+/// Returns once the block to stop at is imported.
+async fn wait_for_stop(client: Arc<FullClient>, stop_at: StopAt, stop_reached: Arc<AtomicBool>) {
+    let mut notifications = client.import_notification_stream();
+
+    while !stop_at.is_reached::<Block, _>(&client) {
+        if notifications.next().await.is_none() {
+            return;
+        }
+    }
+
+    tracing::info!("🏁 Reached {stop_at:?}, shutting down the node");
+
+    stop_reached.store(true, Ordering::SeqCst);
+}
+
+/// Reports the blocks connected to and disconnected from the best chain to the broadcast
+/// manager, so that the confirmed transactions are no longer rebroadcast.
+async fn note_confirmations(client: Arc<FullClient>, network_handle: NetworkHandle) {
+    let mut notifications = client.import_notification_stream();
+
+    while let Some(notification) = notifications.next().await {
+        if !notification.is_new_best {
+            continue;
+        }
+
+        if let Some(reorg) = ChainReorg::from_import_notification(&client, &notification) {
+            network_handle.note_blocks_disconnected(reorg.disconnected);
+        }
+
+        let connected = notification
+            .tree_route
+            .as_ref()
+            .map(|tree_route| tree_route.enacted().to_vec())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|block| block.hash)
+            .chain(std::iter::once(notification.hash));
+
+        for substrate_block_hash in connected {
+            let (Some(block_hash), Ok(Some(number)), Ok(Some(extrinsics))) = (
+                client.bitcoin_block_hash_for(substrate_block_hash),
+                client.number(substrate_block_hash),
+                client.block_body(substrate_block_hash),
+            ) else {
+                continue;
+            };
+
+            let txids = extrinsics
+                .iter()
+                .map(|extrinsic| {
+                    subcoin_service::TransactionAdapter::extrinsic_to_bitcoin_transaction(extrinsic)
+                        .compute_txid()
+                })
+                .collect();
+
+            network_handle.note_block_connected(block_hash, number, txids);
+        }
+    }
+}
+
+/// Provides the unspent outputs from the state of the best block.
+struct StateUtxoProvider {
+    client: Arc<FullClient>,
+}
+
+impl UtxoProvider for StateUtxoProvider {
+    fn get_utxo(&self, out_point: &OutPoint) -> Option<TxOut> {
+        let storage_key =
+            subcoin_service::CoinStorageKey.storage_key(out_point.txid, out_point.vout);
+
+        let data = self
+            .client
+            .storage(self.client.info().best_hash, &StorageKey(storage_key))
+            .ok()
+            .flatten()?;
+
+        let coin = Coin::decode(&mut data.0.as_slice()).ok()?;
+
+        Some(TxOut {
+            value: Amount::from_sat(coin.amount),
+            script_pubkey: ScriptBuf::from_bytes(coin.script_pubkey),
+        })
+    }
+}
+
+/// Loads the transactions saved on the last shutdown, the ones no longer valid at the best
+/// block are dropped.
+async fn load_mempool(
+    path: PathBuf,
+    verifier: BlockVerifier<Block, FullClient, FullBackend>,
+    network_handle: NetworkHandle,
+) {
+    let entries = match subcoin_network::load_mempool(&path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            tracing::warn!("Failed to load transactions from {}: {err}", path.display());
+            return;
+        }
+    };
+
+    if entries.is_empty() {
+        return;
+    }
+
+    let transactions = entries
+        .iter()
+        .map(|entry| entry.transaction.clone())
+        .collect::<Vec<_>>();
+
+    let fees = match verifier.verify_transaction_chain(&transactions) {
+        Ok(fees) => fees,
+        Err(err) => {
+            tracing::warn!(
+                "Failed to revalidate the transactions from {}: {err}",
+                path.display()
+            );
+            return;
+        }
+    };
+
+    let total = entries.len();
+    let valid_entries = entries
+        .into_iter()
+        .zip(fees)
+        .filter_map(|(entry, fee)| fee.map(|_| entry))
+        .collect();
+
+    let restored = network_handle.restore_mempool(valid_entries).await;
+
+    tracing::info!(
+        "Loaded {restored} of {total} transactions from {}",
+        path.display()
+    );
+}
+
+/// Starts the RPC server with the authentication and per-method permissions enforced.
+///
+/// The unsafe RPCs are only denied if `--rpc-methods safe` is specified, as they are
+/// protected by the `admin` permission group.
+pub(crate) fn start_authenticated_rpc_server(
+    config: &Configuration,
+    gen_rpc_module: impl FnOnce(sc_rpc::DenyUnsafe) -> Result<jsonrpsee::RpcModule<()>, ServiceError>,
+    rpc_auth: RpcAuth,
+) -> Result<jsonrpsee::server::ServerHandle, ServiceError> {
+    let deny_unsafe = match config.rpc_methods {
+        RpcMethods::Safe => sc_rpc::DenyUnsafe::Yes,
+        RpcMethods::Auto | RpcMethods::Unsafe => sc_rpc::DenyUnsafe::No,
+    };
+
+    let server_config = ServerConfig {
+        addr: config
+            .rpc_addr
+            .unwrap_or_else(|| ([127, 0, 0, 1], config.rpc_port).into()),
+        max_connections: config.rpc_max_connections,
+        max_subs_per_conn: config.rpc_max_subs_per_conn,
+        max_payload_in_mb: config.rpc_max_request_size,
+        max_payload_out_mb: config.rpc_max_response_size,
+        message_buffer_capacity: config.rpc_message_buffer_capacity,
+        batch_config: match config.rpc_batch_config {
+            RpcBatchRequestConfig::Disabled => BatchRequestConfig::Disabled,
+            RpcBatchRequestConfig::Unlimited => BatchRequestConfig::Unlimited,
+            RpcBatchRequestConfig::Limit(limit) => BatchRequestConfig::Limit(limit),
+        },
+    };
+
+    subcoin_rpc::server::start_server(
+        server_config,
+        gen_rpc_module(deny_unsafe)?,
+        rpc_auth,
+        config.tokio_handle.clone(),
+    )
+    .map_err(|err| ServiceError::Application(Box::new(err)))
+}
+
+/// Returns the networking params matching the defaults of the `run` command.
+fn default_network_params(network: bitcoin::Network) -> subcoin_network::Params {
+    subcoin_network::Params {
+        network,
+        custom_network: None,
+        listen_on: subcoin_network::PeerId::from(([127, 0, 0, 1], 8333)),
+        listen_transports: vec![subcoin_network::Transport::Tcp],
+        v2_transport: true,
+        port_mapping: false,
+        i2p_sam: None,
+        cjdns_reachable: false,
+        seednodes: Vec::new(),
+        seednode_only: false,
+        ipv4_only: false,
+        max_outbound_peers: 20,
+        max_block_relay_only_peers: 2,
+        max_inbound_peers: 103,
+        connect: Vec::new(),
+        addnode: Vec::new(),
+        max_upload_target: None,
+        max_upload_rate: None,
+        max_download_rate: None,
+        max_peer_message_rate: None,
+        import_memory_budget: subcoin_network::DEFAULT_IMPORT_MEMORY_BUDGET,
+        sync_strategy: SyncStrategy::HeadersFirst,
+        snapshot: None,
+        db: None,
+        network_tip: None,
+        mempool_path: None,
+        full_rbf: true,
+        utxo_provider: None,
+        stop_at: None,
+        verification_threads: None,
+        sync_stall_timeout: None,
+        rotate_stalled_sync_peer: false,
+        blocks_only: false,
+    }
+}
@@ -1,9 +1,19 @@
 pub mod params;
 
+use crate::cli::params::Executor;
 use crate::commands::blockchain::{Blockchain, BlockchainCmd};
+use crate::commands::build_spec::BuildSpec;
+use crate::commands::chain_ops::{ChainOps, ChainOpsCmd};
+use crate::commands::export_analytics::{ExportAnalytics, ExportAnalyticsCmd};
 use crate::commands::import_blocks::{ImportBlocks, ImportBlocksCmd};
+use crate::commands::replay_block::{ReplayBlock, ReplayBlockCmd};
+use crate::commands::revert_finalized::RevertFinalized;
 use crate::commands::run::{Run, RunCmd};
+use crate::commands::snapshot::{Snapshot, SnapshotCreateCmd};
 use crate::commands::tools::Tools;
+use crate::commands::verify_utxo::VerifyUtxo;
+use crate::commands::wallet::{Wallet, WalletCmd};
+use crate::db_migration::DbMigrate;
 use crate::substrate_cli::SubstrateCli;
 use clap::Parser;
 use frame_benchmarking_cli::{BenchmarkCmd, SUBSTRATE_REFERENCE_HARDWARE};
@@ -11,8 +21,10 @@ use sc_cli::SubstrateCli as SubstrateCliT;
 use sc_client_api::UsageProvider;
 use sc_consensus_nakamoto::ImportConfig;
 use sc_service::PartialComponents;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use subcoin_primitives::CONFIRMATION_DEPTH;
+use subcoin_service::ExecutorKind;
 
 #[derive(Debug, clap::Subcommand)]
 pub enum Command {
@@ -22,16 +34,34 @@ pub enum Command {
     /// Import blocks from bitcoind database.
     ImportBlocks(ImportBlocks),
 
+    /// Re-execute an imported block against the state of its parent.
+    ReplayBlock(Box<ReplayBlock>),
+
     /// Utility tools.
     #[command(subcommand)]
     Tools(Tools),
 
+    /// Verify the coins in a UTXO proof without a node.
+    VerifyUtxo(VerifyUtxo),
+
     /// Blockchain.
     #[command(subcommand)]
     Blockchain(Blockchain),
 
+    /// Chain operations.
+    #[command(subcommand)]
+    ChainOps(ChainOps),
+
+    /// Watch-only wallet.
+    #[command(subcommand)]
+    Wallet(Wallet),
+
+    /// Snapshot artifacts.
+    #[command(subcommand)]
+    Snapshot(Snapshot),
+
     /// Build a chain specification.
-    BuildSpec(sc_cli::BuildSpecCmd),
+    BuildSpec(BuildSpec),
 
     /// Validate blocks.
     CheckBlock(Box<sc_cli::CheckBlockCmd>),
@@ -39,6 +69,9 @@ pub enum Command {
     /// Export blocks.
     ExportBlocks(sc_cli::ExportBlocksCmd),
 
+    /// Export the transactions, inputs and outputs of a block range to Parquet or CSV files.
+    ExportAnalytics(Box<ExportAnalytics>),
+
     /// Export the state of a given block into a chain spec.
     ExportState(sc_cli::ExportStateCmd),
 
@@ -48,6 +81,9 @@ pub enum Command {
     /// Revert the chain to a previous state.
     Revert(sc_cli::RevertCmd),
 
+    /// Revert the finalized blocks to recover from a Bitcoin reorg beyond the finalized block.
+    RevertFinalized(RevertFinalized),
+
     /// Sub-commands concerned with benchmarking.
     #[command(subcommand)]
     Benchmark(Box<frame_benchmarking_cli::BenchmarkCmd>),
@@ -61,6 +97,10 @@ pub struct Cli {
     #[command(subcommand)]
     pub command: Command,
 
+    /// Specify the executor running the runtime.
+    #[arg(long, value_enum, default_value_t = Executor::NativeElseWasm)]
+    pub executor: Executor,
+
     /// Disable automatic hardware benchmarks.
     ///
     /// By default these benchmarks are automatically ran at startup and measure
@@ -80,24 +120,47 @@ pub struct Cli {
 pub fn run() -> sc_cli::Result<()> {
     let Cli {
         command,
+        executor,
         no_hardware_benchmarks,
         storage_monitor,
     } = Cli::parse();
 
+    let executor: ExecutorKind = executor.into();
+
     match command {
         Command::Run(run) => {
             let run_cmd = RunCmd::new(&run);
+            let stop_reached = run_cmd.stop_reached();
             let runner = SubstrateCli.create_runner(&run_cmd)?;
-            runner.run_node_until_exit(|config| async move {
-                run_cmd
-                    .start(config, *run, no_hardware_benchmarks, storage_monitor)
-                    .await
-            })
+            if run.db_migrate == DbMigrate::DryRun {
+                return crate::db_migration::dry_run(runner.config());
+            }
+            runner
+                .run_node_until_exit(|config| async move {
+                    run_cmd
+                        .start(
+                            config,
+                            *run,
+                            executor,
+                            no_hardware_benchmarks,
+                            storage_monitor,
+                        )
+                        .await
+                })
+                .or_else(|err| {
+                    // `--exit-on-stop` shuts down the node by exiting an essential task.
+                    if stop_reached.load(Ordering::SeqCst) {
+                        Ok(())
+                    } else {
+                        Err(err)
+                    }
+                })
         }
         Command::ImportBlocks(cmd) => {
             let block_execution_strategy = cmd.common_params.block_execution_strategy();
+            let in_memory_backend = cmd.common_params.in_memory_backend_config();
             let bitcoin_network = cmd.common_params.bitcoin_network();
-            let import_config = ImportConfig {
+            let mut import_config = ImportConfig {
                 execute_block: cmd.execute_transactions,
                 ..cmd.common_params.import_config()
             };
@@ -113,6 +176,7 @@ pub fn run() -> sc_cli::Result<()> {
                     client,
                     task_manager,
                     block_executor,
+                    verification_threads,
                     ..
                 } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
                     network: bitcoin_network,
@@ -120,7 +184,12 @@ pub fn run() -> sc_cli::Result<()> {
                     block_execution_strategy,
                     no_hardware_benchmarks,
                     storage_monitor,
+                    in_memory_backend,
+                    executor,
                 })?;
+                import_config
+                    .verification_threads
+                    .get_or_insert(verification_threads);
                 let spawn_handle = task_manager.spawn_handle();
                 spawn_handle.spawn("finalizer", None, {
                     let client = client.clone();
@@ -130,7 +199,7 @@ pub fn run() -> sc_cli::Result<()> {
                     subcoin_service::finalize_confirmed_blocks(
                         client,
                         spawn_handle,
-                        CONFIRMATION_DEPTH,
+                        Arc::new(subcoin_service::DepthFinality::new(CONFIRMATION_DEPTH)),
                         100,
                         is_major_syncing,
                         None,
@@ -149,7 +218,53 @@ pub fn run() -> sc_cli::Result<()> {
                 ))
             })
         }
+        Command::ReplayBlock(replay_block) => {
+            let block_execution_strategy = replay_block.common_params.block_execution_strategy();
+            let bitcoin_network = replay_block.common_params.bitcoin_network();
+            let cmd = ReplayBlockCmd::new(&replay_block);
+            let runner = SubstrateCli.create_runner(&cmd)?;
+            runner.async_run(|config| {
+                let subcoin_service::NodeComponents {
+                    client,
+                    task_manager,
+                    ..
+                } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
+                    network: bitcoin_network,
+                    config: &config,
+                    block_execution_strategy,
+                    no_hardware_benchmarks: true,
+                    storage_monitor,
+                    in_memory_backend: Default::default(),
+                    executor,
+                })?;
+                Ok((cmd.run(client), task_manager))
+            })
+        }
+        Command::ExportAnalytics(export_analytics) => {
+            let block_execution_strategy =
+                export_analytics.common_params.block_execution_strategy();
+            let bitcoin_network = export_analytics.common_params.bitcoin_network();
+            let cmd = ExportAnalyticsCmd::new(&export_analytics);
+            let runner = SubstrateCli.create_runner(&cmd)?;
+            runner.async_run(|config| {
+                let subcoin_service::NodeComponents {
+                    client,
+                    task_manager,
+                    ..
+                } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
+                    network: bitcoin_network,
+                    config: &config,
+                    block_execution_strategy,
+                    no_hardware_benchmarks: true,
+                    storage_monitor,
+                    in_memory_backend: Default::default(),
+                    executor,
+                })?;
+                Ok((cmd.run(client), task_manager))
+            })
+        }
         Command::Tools(tools) => tools.run(),
+        Command::VerifyUtxo(verify_utxo) => verify_utxo.run(),
         Command::Blockchain(blockchain) => {
             let block_execution_strategy = blockchain.block_execution_strategy();
             let cmd = BlockchainCmd::new(blockchain);
@@ -165,12 +280,84 @@ pub fn run() -> sc_cli::Result<()> {
                     block_execution_strategy,
                     no_hardware_benchmarks: true,
                     storage_monitor,
+                    in_memory_backend: Default::default(),
+                    executor,
                 })?;
                 Ok((cmd.run(client), task_manager))
             })
         }
-        Command::BuildSpec(cmd) => {
+        Command::ChainOps(chain_ops) => {
+            let block_execution_strategy = chain_ops.block_execution_strategy();
+            let bitcoin_network = chain_ops.bitcoin_network();
+            let cmd = ChainOpsCmd::new(chain_ops);
+            let runner = SubstrateCli.create_runner(&cmd)?;
+            runner.async_run(|config| {
+                let subcoin_service::NodeComponents {
+                    client,
+                    task_manager,
+                    ..
+                } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
+                    network: bitcoin_network,
+                    config: &config,
+                    block_execution_strategy,
+                    no_hardware_benchmarks: true,
+                    storage_monitor,
+                    in_memory_backend: Default::default(),
+                    executor,
+                })?;
+                Ok((cmd.run(client), task_manager))
+            })
+        }
+        Command::Wallet(wallet) => {
+            let block_execution_strategy = wallet.block_execution_strategy();
+            let bitcoin_network = wallet.bitcoin_network();
+            let cmd = WalletCmd::new(wallet);
+            let runner = SubstrateCli.create_runner(&cmd)?;
+            runner.async_run(|config| {
+                let subcoin_service::NodeComponents {
+                    client,
+                    task_manager,
+                    subcoin_db,
+                    ..
+                } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
+                    network: bitcoin_network,
+                    config: &config,
+                    block_execution_strategy,
+                    no_hardware_benchmarks: true,
+                    storage_monitor,
+                    in_memory_backend: Default::default(),
+                    executor,
+                })?;
+                Ok((cmd.run(client, subcoin_db), task_manager))
+            })
+        }
+        Command::Snapshot(Snapshot::Create(create)) => {
+            let block_execution_strategy = create.common_params.block_execution_strategy();
+            let bitcoin_network = create.common_params.bitcoin_network();
+            let cmd = SnapshotCreateCmd::new(*create);
             let runner = SubstrateCli.create_runner(&cmd)?;
+            runner.async_run(|config| {
+                let subcoin_service::NodeComponents {
+                    client,
+                    task_manager,
+                    ..
+                } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
+                    network: bitcoin_network,
+                    config: &config,
+                    block_execution_strategy,
+                    no_hardware_benchmarks: true,
+                    storage_monitor,
+                    in_memory_backend: Default::default(),
+                    executor,
+                })?;
+                Ok((cmd.run(client), task_manager))
+            })
+        }
+        Command::Snapshot(Snapshot::Verify { artifact, signer }) => {
+            crate::commands::snapshot::verify(&artifact, signer)
+        }
+        Command::BuildSpec(cmd) => {
+            let runner = SubstrateCli.create_runner(&cmd.inner)?;
             runner.sync_run(|config| cmd.run(config.chain_spec, config.network))
         }
         Command::CheckBlock(cmd) => {
@@ -181,7 +368,7 @@ pub fn run() -> sc_cli::Result<()> {
                     task_manager,
                     import_queue,
                     ..
-                } = subcoin_service::new_partial(&config)?;
+                } = subcoin_service::new_partial(&config, executor)?;
                 Ok((cmd.run(client, import_queue), task_manager))
             })
         }
@@ -192,7 +379,7 @@ pub fn run() -> sc_cli::Result<()> {
                     client,
                     task_manager,
                     ..
-                } = subcoin_service::new_partial(&config)?;
+                } = subcoin_service::new_partial(&config, executor)?;
                 Ok((cmd.run(client, config.database), task_manager))
             })
         }
@@ -203,7 +390,7 @@ pub fn run() -> sc_cli::Result<()> {
                     client,
                     task_manager,
                     ..
-                } = subcoin_service::new_partial(&config)?;
+                } = subcoin_service::new_partial(&config, executor)?;
 
                 let run_cmd = async move {
                     tracing::info!("Exporting raw state...");
@@ -235,10 +422,19 @@ pub fn run() -> sc_cli::Result<()> {
                     task_manager,
                     backend,
                     ..
-                } = subcoin_service::new_partial(&config)?;
+                } = subcoin_service::new_partial(&config, executor)?;
                 Ok((cmd.run(client, backend, None), task_manager))
             })
         }
+        Command::RevertFinalized(cmd) => {
+            let runner = SubstrateCli.create_runner(&cmd)?;
+            runner.sync_run(|config| {
+                let PartialComponents {
+                    client, backend, ..
+                } = subcoin_service::new_partial(&config, executor)?;
+                cmd.run(client, backend)
+            })
+        }
         Command::Benchmark(cmd) => {
             let runner = SubstrateCli.create_runner(&*cmd)?;
 
@@ -251,7 +447,7 @@ pub fn run() -> sc_cli::Result<()> {
                     }
                     BenchmarkCmd::Block(cmd) => {
                         let PartialComponents { client, .. } =
-                            subcoin_service::new_partial(&config)?;
+                            subcoin_service::new_partial(&config, executor)?;
                         cmd.run(client)
                     }
                     #[cfg(not(feature = "runtime-benchmarks"))]
@@ -263,7 +459,7 @@ pub fn run() -> sc_cli::Result<()> {
                     BenchmarkCmd::Storage(cmd) => {
                         let PartialComponents {
                             client, backend, ..
-                        } = subcoin_service::new_partial(&config)?;
+                        } = subcoin_service::new_partial(&config, executor)?;
                         let db = backend.expose_db();
                         let storage = backend.expose_storage();
 
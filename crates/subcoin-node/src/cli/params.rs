@@ -3,7 +3,9 @@ use sc_consensus_nakamoto::{
     BlockExecutionStrategy, BlockVerification, ExecutionBackend, ImportConfig,
 };
 use std::path::PathBuf;
-use subcoin_network::PeerId;
+use subcoin_network::{PeerId, Transport};
+use subcoin_rpc::auth::{Credential, MethodPermission, MethodPermissions, RpcAuth};
+use subcoin_service::{ChainSpec, CustomNetwork, ExecutorKind, InMemoryBackendConfig};
 
 /// Chain.
 ///
@@ -31,6 +33,89 @@ impl Chain {
             Self::BitcoinSignet => "bitcoin-signet",
         }
     }
+
+    /// Returns the Bitcoin network of the chain.
+    pub fn bitcoin_network(&self) -> bitcoin::Network {
+        match self {
+            Self::BitcoinMainnet => bitcoin::Network::Bitcoin,
+            Self::BitcoinTestnet => bitcoin::Network::Testnet,
+            Self::BitcoinSignet => bitcoin::Network::Signet,
+        }
+    }
+}
+
+/// Chain spec of a custom network specified by `--chain-spec`.
+#[derive(Debug, Clone)]
+pub struct CustomChainSpec {
+    /// Path of the chain spec file.
+    pub path: PathBuf,
+    /// Custom network defined in the chain spec.
+    pub network: CustomNetwork,
+}
+
+impl CustomChainSpec {
+    /// Returns the params of the custom network used in the Bitcoin P2P networking.
+    pub fn network_params(&self) -> subcoin_network::CustomNetworkParams {
+        subcoin_network::CustomNetworkParams {
+            magic: self.network.magic,
+            chain_params: self.network.chain_params.clone(),
+            seednodes: self.network.seednodes.clone(),
+        }
+    }
+}
+
+fn parse_custom_chain_spec(path: &str) -> Result<CustomChainSpec, String> {
+    let chain_spec = ChainSpec::from_json_file(PathBuf::from(path))?;
+    let network = CustomNetwork::from_properties(&sc_service::ChainSpec::properties(&chain_spec))?
+        .ok_or_else(|| format!("{path} is not a chain spec of a custom network"))?;
+    Ok(CustomChainSpec {
+        path: path.into(),
+        network,
+    })
+}
+
+/// RPC authentication params.
+///
+/// The RPC authentication is enabled if either `--rpc-cookie` or `--rpc-auth` is specified.
+#[derive(Debug, Clone, Parser)]
+pub struct RpcAuthParams {
+    /// Generate the `.cookie` file in the base path for authenticating the local RPC clients.
+    ///
+    /// The cookie user is granted all the permission groups.
+    #[clap(long)]
+    pub rpc_cookie: bool,
+
+    /// Specify the static RPC credentials in the form of `user:password[:group,...]`.
+    ///
+    /// The available groups are `public`, `wallet` and `admin`, all the groups are granted
+    /// if not specified.
+    #[clap(long, value_name = "USER:PASSWORD[:GROUPS]")]
+    pub rpc_auth: Vec<Credential>,
+
+    /// Override the permission group of a RPC method in the form of `method=group`.
+    #[clap(long, value_name = "METHOD=GROUP")]
+    pub rpc_method_group: Vec<MethodPermission>,
+
+    /// Allow the unauthenticated clients to call the methods in the `public` group.
+    #[clap(long)]
+    pub rpc_anonymous_public: bool,
+}
+
+impl RpcAuthParams {
+    /// Returns the RPC authentication config, `None` if the RPC authentication is disabled.
+    ///
+    /// The cookie credentials are not included as the cookie is generated on the node startup.
+    pub fn rpc_auth(&self) -> Option<RpcAuth> {
+        if !self.rpc_cookie && self.rpc_auth.is_empty() {
+            return None;
+        }
+
+        Some(RpcAuth::new(
+            self.rpc_auth.clone(),
+            self.rpc_anonymous_public,
+            MethodPermissions::new(self.rpc_method_group.clone()),
+        ))
+    }
 }
 
 /// Subcoin networking params.
@@ -50,17 +135,154 @@ pub struct NetworkParams {
     #[clap(long, default_value = "127.0.0.1:8333")]
     pub listen: PeerId,
 
+    /// Specify the transports accepting the inbound connections.
+    ///
+    /// With `quic`, the node also listens on UDP at the same port and connects to the
+    /// subcoin peers advertising QUIC over QUIC, falling back to TCP.
+    #[clap(long, value_delimiter = ',', default_value = "tcp")]
+    pub listen_transports: Vec<Transport>,
+
+    /// Do not support the BIP-324 v2 encrypted transport.
+    ///
+    /// By default, the v2 transport is advertised and attempted with the peers supporting it,
+    /// falling back to the plaintext v1 transport.
+    #[clap(long)]
+    pub no_v2_transport: bool,
+
+    /// Map the listen port on the router with NAT-PMP or UPnP to accept the inbound
+    /// connections behind a NAT.
+    ///
+    /// The mapped external address is advertised to the peers.
+    #[clap(long)]
+    pub port_mapping: bool,
+
+    /// Connect to the I2P peers through the SAM v3.1 bridge of an I2P router at this
+    /// address, e.g., `127.0.0.1:7656`.
+    ///
+    /// Only the outbound I2P connections are made, the local node has no persistent I2P
+    /// address.
+    #[clap(long, value_name = "ADDR")]
+    pub i2psam: Option<std::net::SocketAddr>,
+
+    /// Connect to the CJDNS peers (`fc00::/8`), routed by a CJDNS node running locally.
+    #[clap(long)]
+    pub cjdnsreachable: bool,
+
     /// Whether to connect to the nodes using IPv6 address.
     #[clap(long)]
     pub ipv4_only: bool,
 
-    /// Specify the maximum number of inbound subcoin networking peers.
-    #[clap(long, default_value = "100")]
-    pub max_inbound_peers: usize,
+    /// Specify the maximum number of subcoin networking peers, inbound and outbound.
+    ///
+    /// The slots left by the automatic outbound connections are available to the inbound peers.
+    #[clap(long, default_value = "125")]
+    pub max_connections: usize,
 
-    /// Specify the maximum number of outbound subcoin networking peers.
+    /// Specify the number of outbound full-relay subcoin networking peers.
     #[clap(long, default_value = "20")]
     pub max_outbound_peers: usize,
+
+    /// Specify the number of outbound block-relay-only subcoin networking peers.
+    ///
+    /// These peers relay no transactions and addresses, which makes them harder to
+    /// discover and eclipse.
+    #[clap(long, default_value = "2")]
+    pub max_block_relay_only_peers: usize,
+
+    /// Connect to the specified peers only, can be repeated.
+    ///
+    /// Disables the automatic outbound connections, the seednodes and the address discovery.
+    #[clap(long, value_name = "ADDR")]
+    pub connect: Vec<String>,
+
+    /// Maintain a connection to the specified peer, can be repeated.
+    ///
+    /// The peer is reconnected when disconnected and never evicted, in addition to the
+    /// automatic outbound connections.
+    #[clap(long, value_name = "ADDR")]
+    pub addnode: Vec<String>,
+
+    /// Keep the outbound traffic under the given target in MiB per 24 hours.
+    ///
+    /// The snapshots are no longer served once the target is reached, the blocks are still
+    /// relayed. Unlimited by default.
+    #[clap(long, value_name = "MiB")]
+    pub max_upload_target: Option<u64>,
+
+    /// Limit the outbound traffic of the subcoin networking to the given rate in KiB/s.
+    #[clap(long, value_name = "KiB/s")]
+    pub max_upload_rate: Option<u64>,
+
+    /// Limit the inbound traffic of the subcoin networking to the given rate in KiB/s.
+    #[clap(long, value_name = "KiB/s")]
+    pub max_download_rate: Option<u64>,
+
+    /// Drop the messages of a peer exceeding the given rate per second.
+    ///
+    /// The blocks and headers are exempted as they are only received upon our requests.
+    #[clap(long, value_name = "COUNT")]
+    pub max_peer_message_rate: Option<u32>,
+
+    /// Maximum memory in MiB held by the blocks downloaded but not imported yet.
+    ///
+    /// The block download is paused once exceeded until the import catches up, lower it on
+    /// the machines with limited memory.
+    #[clap(long, value_name = "MiB", default_value_t = 512)]
+    pub max_import_memory: usize,
+
+    /// Only replace the unconfirmed transactions signaling the BIP125 replaceability.
+    #[clap(long)]
+    pub no_full_rbf: bool,
+
+    /// Report the sync as stalled once the best block hasn't advanced for this many seconds
+    /// while the peers are ahead, 0 to disable.
+    ///
+    /// The stall is logged and exported as the `subcoin_sync_stalled` metric.
+    #[clap(long, value_name = "SECS", default_value_t = 900)]
+    pub sync_stall_timeout: u64,
+
+    /// Switch to another sync peer and disconnect the current one once the sync is stalled.
+    #[clap(long)]
+    pub rotate_stalled_sync_peer: bool,
+
+    /// Disable the transaction relay, only the blocks are downloaded from the peers.
+    ///
+    /// The peers are asked not to announce their transactions, which saves the bandwidth
+    /// during the initial sync or on the constrained machines. The transactions submitted
+    /// locally are still broadcast.
+    #[clap(long)]
+    pub blocksonly: bool,
+}
+
+impl NetworkParams {
+    /// Returns the maximum number of inbound peers, i.e., the connections not taken by the
+    /// automatic outbound peers.
+    pub fn max_inbound_peers(&self) -> usize {
+        let outbound = if self.connect.is_empty() {
+            self.max_outbound_peers + self.max_block_relay_only_peers
+        } else {
+            0
+        };
+        self.max_connections.saturating_sub(outbound)
+    }
+}
+
+/// Executor running the runtime.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Executor {
+    /// Use the native runtime if it matches the on-chain runtime version, wasm otherwise.
+    NativeElseWasm,
+    /// Always use the wasm runtime.
+    Wasm,
+}
+
+impl From<Executor> for ExecutorKind {
+    fn from(executor: Executor) -> Self {
+        match executor {
+            Executor::NativeElseWasm => Self::NativeElseWasm,
+            Executor::Wasm => Self::Wasm,
+        }
+    }
 }
 
 #[derive(Debug, Clone, clap::ValueEnum)]
@@ -77,6 +299,8 @@ pub enum BlockExecution {
     BenchRuntime,
     /// Benchmark all supported strategies.
     BenchAll,
+    /// Use `OffRuntimeInMemory` during the major sync and `RuntimeDisk` near the network tip.
+    Adaptive,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -85,10 +309,34 @@ pub struct CommonParams {
     #[arg(long, value_name = "CHAIN", default_value = "bitcoin-mainnet")]
     pub chain: Chain,
 
+    /// Specify the chain spec of a custom Bitcoin-like network, overriding `--chain`.
+    ///
+    /// The chain spec must be generated by `build-spec --bitcoin-network`.
+    #[arg(long, value_name = "PATH", value_parser = parse_custom_chain_spec)]
+    pub chain_spec: Option<CustomChainSpec>,
+
     /// Specify the block execution strategy.
     #[clap(long, value_enum, default_value_t = BlockExecution::RuntimeDisk)]
     pub block_execution: BlockExecution,
 
+    /// Distance to the network tip in blocks below which the adaptive block execution switches
+    /// to the runtime execution on disk.
+    #[clap(long, value_name = "BLOCKS", default_value_t = 144)]
+    pub adaptive_switch_depth: u32,
+
+    /// Number of imported blocks between two flushes of the in-memory backend.
+    ///
+    /// Only applies to the block execution strategies using the in-memory backend.
+    #[clap(long, value_name = "BLOCKS", default_value_t = 1000)]
+    pub in_memory_flush_interval: u32,
+
+    /// Resident memory of the node in MiB beyond which the in-memory backend is released and
+    /// the blocks are executed on disk instead.
+    ///
+    /// Only applies to the block execution strategies using the in-memory backend.
+    #[clap(long, value_name = "MiB")]
+    pub in_memory_max_memory: Option<u64>,
+
     /// Specify the block verification level.
     #[clap(long, default_value = "full")]
     pub block_verification: BlockVerification,
@@ -97,6 +345,13 @@ pub struct CommonParams {
     #[clap(long, default_value_t = true)]
     pub verify_script: bool,
 
+    /// Number of threads verifying the scripts of a block and the downloaded headers.
+    ///
+    /// Defaults to all the cores, or half of them if the hardware benchmarks fall short of
+    /// the reference hardware. Lower it to leave room for the other workloads on the machine.
+    #[clap(long, value_name = "N")]
+    pub verification_threads: Option<usize>,
+
     /// Specify custom base path.
     #[arg(long, short = 'd', value_name = "PATH")]
     pub base_path: Option<PathBuf>,
@@ -126,7 +381,10 @@ impl CommonParams {
     pub fn as_shared_params(&self) -> sc_cli::SharedParams {
         // TODO: expose more flags?
         sc_cli::SharedParams {
-            chain: Some(self.chain.chain_spec_id().to_string()),
+            chain: Some(match &self.chain_spec {
+                Some(chain_spec) => chain_spec.path.display().to_string(),
+                None => self.chain.chain_spec_id().to_string(),
+            }),
             dev: false,
             base_path: self.base_path.clone(),
             log: self.log.clone(),
@@ -139,20 +397,36 @@ impl CommonParams {
     }
 
     /// Determines the Bitcoin network type based on the current chain setting.
+    ///
+    /// Returns the base network of the custom network if `--chain-spec` is specified.
     pub fn bitcoin_network(&self) -> bitcoin::Network {
-        match self.chain {
-            Chain::BitcoinMainnet => bitcoin::Network::Bitcoin,
-            Chain::BitcoinTestnet => bitcoin::Network::Testnet,
-            Chain::BitcoinSignet => bitcoin::Network::Signet,
+        if let Some(chain_spec) = &self.chain_spec {
+            return chain_spec.network.base;
         }
+
+        self.chain.bitcoin_network()
     }
 
     pub fn import_config(&self) -> ImportConfig {
         ImportConfig {
             network: self.bitcoin_network(),
+            chain_params: self
+                .chain_spec
+                .as_ref()
+                .map(|chain_spec| chain_spec.network.chain_params.clone()),
             block_verification: self.block_verification,
             execute_block: true,
             verify_script: self.verify_script,
+            verification_threads: self.verification_threads,
+        }
+    }
+
+    pub fn in_memory_backend_config(&self) -> InMemoryBackendConfig {
+        InMemoryBackendConfig {
+            flush_interval: self.in_memory_flush_interval.max(1),
+            max_memory: self
+                .in_memory_max_memory
+                .map(|mebibytes| mebibytes * 1024 * 1024),
         }
     }
 
@@ -172,6 +446,9 @@ impl CommonParams {
             }
             BlockExecution::BenchRuntime => BlockExecutionStrategy::BenchmarkRuntimeExecution,
             BlockExecution::BenchAll => BlockExecutionStrategy::BenchmarkAll,
+            BlockExecution::Adaptive => BlockExecutionStrategy::Adaptive {
+                switch_depth: self.adaptive_switch_depth,
+            },
         }
     }
 }
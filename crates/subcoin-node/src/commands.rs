@@ -1,4 +1,12 @@
 pub mod blockchain;
+pub mod build_spec;
+pub mod chain_ops;
+pub mod export_analytics;
 pub mod import_blocks;
+pub mod replay_block;
+pub mod revert_finalized;
 pub mod run;
+pub mod snapshot;
 pub mod tools;
+pub mod verify_utxo;
+pub mod wallet;
@@ -0,0 +1,40 @@
+use sc_service::config::NetworkConfiguration;
+use std::path::PathBuf;
+use subcoin_service::CustomNetwork;
+
+/// Build a chain specification.
+#[derive(Debug, clap::Args)]
+pub struct BuildSpec {
+    /// Build the chain spec of a custom Bitcoin-like network defined in the JSON file.
+    ///
+    /// The definition specifies the magic, the genesis block, the halving interval and the
+    /// proof of work params of the network, the unspecified ones are inherited from the base
+    /// network. Run the node with `--chain-spec` to sync the network.
+    #[clap(long, value_name = "PATH")]
+    pub bitcoin_network: Option<PathBuf>,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub inner: sc_cli::BuildSpecCmd,
+}
+
+impl BuildSpec {
+    pub fn run(
+        &self,
+        chain_spec: Box<dyn sc_service::ChainSpec>,
+        network_config: NetworkConfiguration,
+    ) -> sc_cli::Result<()> {
+        let chain_spec = match &self.bitcoin_network {
+            Some(path) => {
+                let custom_network =
+                    CustomNetwork::from_json_file(path).map_err(sc_cli::Error::Input)?;
+                let chain_spec = subcoin_service::chain_spec::custom_config(&custom_network)
+                    .map_err(sc_cli::Error::Input)?;
+                Box::new(chain_spec) as Box<dyn sc_service::ChainSpec>
+            }
+            None => chain_spec,
+        };
+
+        self.inner.run(chain_spec, network_config)
+    }
+}
@@ -0,0 +1,189 @@
+use crate::cli::params::CommonParams;
+use bitcoin::hex::DisplayHex;
+use bitcoin::{BlockHash, OutPoint};
+use sc_cli::{ImportParams, NodeKeyParams, SharedParams};
+use sc_consensus_nakamoto::{BlockExecutionStrategy, UtxoSetDiff};
+use std::path::PathBuf;
+use std::sync::Arc;
+use subcoin_primitives::runtime::Coin;
+use subcoin_service::{CoinStorageKey, FullClient, TransactionAdapter};
+
+/// Chain operations.
+#[derive(Debug, clap::Subcommand)]
+pub enum ChainOps {
+    /// Print the coins created and spent between two blocks.
+    ///
+    /// The blocks do not have to be on the same chain, the diff goes through their common
+    /// ancestor. The parent states of the blocks in between must not have been pruned.
+    UtxoDiff {
+        /// Hash of the Bitcoin block to start from.
+        #[clap(long)]
+        from: BlockHash,
+
+        /// Hash of the Bitcoin block to end at.
+        #[clap(long)]
+        to: BlockHash,
+
+        /// Write the diff in JSON to the file instead of stdout.
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+
+        #[allow(missing_docs)]
+        #[clap(flatten)]
+        common_params: CommonParams,
+
+        #[allow(missing_docs)]
+        #[clap(flatten)]
+        import_params: ImportParams,
+    },
+}
+
+impl ChainOps {
+    pub fn block_execution_strategy(&self) -> BlockExecutionStrategy {
+        match self {
+            Self::UtxoDiff { common_params, .. } => common_params.block_execution_strategy(),
+        }
+    }
+
+    pub fn bitcoin_network(&self) -> bitcoin::Network {
+        match self {
+            Self::UtxoDiff { common_params, .. } => common_params.bitcoin_network(),
+        }
+    }
+}
+
+pub enum ChainOpsCmd {
+    UtxoDiff {
+        from: BlockHash,
+        to: BlockHash,
+        output: Option<PathBuf>,
+        shared_params: SharedParams,
+        import_params: ImportParams,
+    },
+}
+
+impl ChainOpsCmd {
+    /// Constructs a new instance of [`ChainOpsCmd`].
+    pub fn new(chain_ops: ChainOps) -> Self {
+        match chain_ops {
+            ChainOps::UtxoDiff {
+                from,
+                to,
+                output,
+                common_params,
+                import_params,
+            } => Self::UtxoDiff {
+                from,
+                to,
+                output,
+                shared_params: common_params.as_shared_params(),
+                import_params,
+            },
+        }
+    }
+
+    fn shared_params(&self) -> &SharedParams {
+        match self {
+            Self::UtxoDiff { shared_params, .. } => shared_params,
+        }
+    }
+
+    pub async fn run(self, client: Arc<FullClient>) -> sc_cli::Result<()> {
+        match self {
+            Self::UtxoDiff {
+                from, to, output, ..
+            } => utxo_diff(&client, from, to, output),
+        }
+    }
+}
+
+impl sc_cli::CliConfiguration for ChainOpsCmd {
+    fn shared_params(&self) -> &SharedParams {
+        ChainOpsCmd::shared_params(self)
+    }
+
+    fn import_params(&self) -> Option<&ImportParams> {
+        match self {
+            Self::UtxoDiff { import_params, .. } => Some(import_params),
+        }
+    }
+
+    fn node_key_params(&self) -> Option<&NodeKeyParams> {
+        None
+    }
+}
+
+fn utxo_diff(
+    client: &Arc<FullClient>,
+    from: BlockHash,
+    to: BlockHash,
+    output: Option<PathBuf>,
+) -> sc_cli::Result<()> {
+    let diff = sc_consensus_nakamoto::utxo_set_diff::<_, _, _, TransactionAdapter>(
+        client,
+        &CoinStorageKey,
+        from,
+        to,
+    )
+    .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+
+    let json = serde_json::to_string_pretty(&diff_to_json(from, to, &diff))
+        .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, json)?;
+            println!(
+                "Common ancestor: {}, disconnected: {}, connected: {}",
+                diff.common_ancestor,
+                diff.disconnected.len(),
+                diff.connected.len()
+            );
+            println!(
+                "Created coins: {} ({} sats), spent coins: {} ({} sats)",
+                diff.created.len(),
+                total_amount(&diff.created),
+                diff.spent.len(),
+                total_amount(&diff.spent)
+            );
+            println!("UTXO diff written to {}", path.display());
+        }
+        None => println!("{json}"),
+    }
+
+    Ok(())
+}
+
+fn total_amount(coins: &[(OutPoint, Coin)]) -> u64 {
+    coins.iter().map(|(_, coin)| coin.amount).sum()
+}
+
+fn diff_to_json(from: BlockHash, to: BlockHash, diff: &UtxoSetDiff) -> serde_json::Value {
+    let coins_to_json = |coins: &[(OutPoint, Coin)]| {
+        coins
+            .iter()
+            .map(|(out_point, coin)| {
+                serde_json::json!({
+                    "txid": out_point.txid,
+                    "vout": out_point.vout,
+                    "value": coin.amount,
+                    "scriptPubKey": coin.script_pubkey.as_hex().to_string(),
+                    "height": coin.height,
+                    "coinbase": coin.is_coinbase,
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    serde_json::json!({
+        "from": from,
+        "to": to,
+        "commonAncestor": diff.common_ancestor,
+        "disconnected": diff.disconnected,
+        "connected": diff.connected,
+        "created": coins_to_json(&diff.created),
+        "spent": coins_to_json(&diff.spent),
+        "createdAmount": total_amount(&diff.created),
+        "spentAmount": total_amount(&diff.spent),
+    })
+}
@@ -0,0 +1,777 @@
+use crate::cli::params::CommonParams;
+use arrow::array::{ArrayRef, BooleanArray, Int32Array, StringArray, UInt32Array, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use bitcoin::hex::DisplayHex;
+use bitcoin::{Address, Block as BitcoinBlock, OutPoint, ScriptBuf};
+use codec::Decode;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use sc_cli::{ImportParams, NodeKeyParams, SharedParams};
+use sc_client_api::{BlockBackend, HeaderBackend, StorageProvider};
+use serde::Deserialize;
+use serde_json::Value;
+use sp_core::storage::StorageKey;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use subcoin_indexer::ScriptType;
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{convert_to_bitcoin_block, CoinStorageKey as _};
+use subcoin_runtime::interface::OpaqueBlock;
+use subcoin_service::{
+    CoinStorageKey, FullClient, JobContext, JobError, JobHandler, TransactionAdapter,
+};
+
+/// Rows buffered per table before being written out as a row group, bounding the memory
+/// usage of the export regardless of the block range.
+const ROWS_PER_BATCH: usize = 65_536;
+
+/// Interval of the progress logs in blocks.
+const PROGRESS_INTERVAL: u32 = 1000;
+
+/// Number of blocks exported into each file of a background export.
+const BLOCKS_PER_PART: u32 = 10_000;
+
+fn application_error(err: impl std::error::Error + Send + Sync + 'static) -> sc_cli::Error {
+    sc_cli::Error::Application(Box::new(err))
+}
+
+/// Format of the exported tables.
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Apache Parquet, compressed with zstd.
+    Parquet,
+    /// CSV with a header row.
+    Csv,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Parquet => "parquet",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+/// Export the transactions, inputs and outputs of a block range into columnar files.
+///
+/// The prevouts of the inputs are resolved from the state of the parent blocks, the node
+/// must keep the state of the exported range, e.g., with `--state-pruning archive`.
+///
+/// A running node exports in the background with the `exportAnalytics` job instead, see
+/// `subcoin_startJob`.
+#[derive(clap::Parser, Debug, Clone)]
+pub struct ExportAnalytics {
+    /// Height of the first exported block.
+    #[clap(long, value_name = "HEIGHT", default_value_t = 0)]
+    pub from: u32,
+
+    /// Height of the last exported block, defaults to the best block.
+    #[clap(long, value_name = "HEIGHT")]
+    pub to: Option<u32>,
+
+    /// Format of the exported files.
+    #[clap(long, value_enum, default_value_t = ExportFormat::Parquet)]
+    pub format: ExportFormat,
+
+    /// Directory of the exported `transactions`, `inputs` and `outputs` files.
+    #[clap(long, short, value_name = "DIR")]
+    pub output: PathBuf,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub common_params: CommonParams,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub import_params: ImportParams,
+}
+
+pub struct ExportAnalyticsCmd {
+    from: u32,
+    to: Option<u32>,
+    format: ExportFormat,
+    output: PathBuf,
+    network: bitcoin::Network,
+    shared_params: SharedParams,
+    import_params: ImportParams,
+}
+
+impl ExportAnalyticsCmd {
+    /// Constructs a new instance of [`ExportAnalyticsCmd`].
+    pub fn new(cmd: &ExportAnalytics) -> Self {
+        Self {
+            from: cmd.from,
+            to: cmd.to,
+            format: cmd.format,
+            output: cmd.output.clone(),
+            network: cmd.common_params.bitcoin_network(),
+            shared_params: cmd.common_params.as_shared_params(),
+            import_params: cmd.import_params.clone(),
+        }
+    }
+
+    pub async fn run(self, client: Arc<FullClient>) -> sc_cli::Result<()> {
+        let to = check_range(&client, self.from, self.to)?;
+
+        let now = Instant::now();
+
+        let summary = export_blocks(
+            &client,
+            self.network,
+            self.from..=to,
+            &self.output,
+            self.format,
+            "",
+            || false,
+        )?
+        .expect("Export is never cancelled; qed");
+
+        println!(
+            "Exported blocks #{}..=#{to} to {} in {}s: {} transactions, {} inputs, {} outputs",
+            self.from,
+            self.output.display(),
+            now.elapsed().as_secs(),
+            summary.transactions,
+            summary.inputs,
+            summary.outputs,
+        );
+
+        Ok(())
+    }
+}
+
+/// Returns the last block of the range, the best block if unspecified.
+fn check_range(client: &FullClient, from: u32, to: Option<u32>) -> sc_cli::Result<u32> {
+    let best_number = client.info().best_number;
+    let to = to.unwrap_or(best_number);
+
+    if from > to || to > best_number {
+        return Err(sc_cli::Error::Input(format!(
+            "Invalid block range #{from}..=#{to}, best block: #{best_number}"
+        )));
+    }
+
+    Ok(to)
+}
+
+/// Number of the exported rows.
+struct ExportSummary {
+    transactions: usize,
+    inputs: usize,
+    outputs: usize,
+}
+
+/// Exports the blocks into `{table}{suffix}.{extension}` files in `dir`.
+///
+/// Returns `None` if `is_cancelled` returns `true` before the export is complete, the files
+/// are left incomplete then.
+fn export_blocks(
+    client: &FullClient,
+    network: bitcoin::Network,
+    range: RangeInclusive<u32>,
+    dir: &Path,
+    format: ExportFormat,
+    suffix: &str,
+    is_cancelled: impl Fn() -> bool,
+) -> sc_cli::Result<Option<ExportSummary>> {
+    std::fs::create_dir_all(dir)?;
+
+    let mut tables = Tables {
+        transactions: Table::create(dir, format, suffix)?,
+        inputs: Table::create(dir, format, suffix)?,
+        outputs: Table::create(dir, format, suffix)?,
+    };
+
+    let to = *range.end();
+
+    for height in range {
+        if is_cancelled() {
+            return Ok(None);
+        }
+
+        let (parent_hash, block) = bitcoin_block(client, height)?;
+
+        tables.push_block(height, &block, network, |out_point| {
+            let key = StorageKey(CoinStorageKey.storage_key(out_point.txid, out_point.vout));
+            let coin = client.storage(parent_hash, &key).map_err(|err| {
+                sc_cli::Error::Input(format!(
+                    "State of block #{} unavailable, the prevouts are resolved from the \
+                    archive state: {err}",
+                    height - 1
+                ))
+            })?;
+            coin.map(|data| Coin::decode(&mut data.0.as_slice()))
+                .transpose()
+                .map_err(application_error)
+        })?;
+
+        if height % PROGRESS_INTERVAL == 0 {
+            tracing::info!(
+                "Exported #{height}/#{to}, transactions: {}, inputs: {}, outputs: {}",
+                tables.transactions.total_rows,
+                tables.inputs.total_rows,
+                tables.outputs.total_rows
+            );
+        }
+    }
+
+    let Tables {
+        transactions,
+        inputs,
+        outputs,
+    } = tables;
+
+    let summary = ExportSummary {
+        transactions: transactions.total_rows,
+        inputs: inputs.total_rows,
+        outputs: outputs.total_rows,
+    };
+
+    transactions.finish()?;
+    inputs.finish()?;
+    outputs.finish()?;
+
+    Ok(Some(summary))
+}
+
+/// Params of an `exportAnalytics` job.
+#[derive(Debug, Deserialize)]
+struct ExportJobParams {
+    from: u32,
+    to: Option<u32>,
+    #[serde(default = "default_export_format")]
+    format: ExportFormat,
+    output: PathBuf,
+}
+
+fn default_export_format() -> ExportFormat {
+    ExportFormat::Parquet
+}
+
+/// Analytics export running as a background job.
+///
+/// The range is exported in parts of [`BLOCKS_PER_PART`] blocks, e.g.,
+/// `transactions-0000000-0009999.parquet`, a resumed job redoes the last incomplete part.
+pub struct ExportAnalyticsJob {
+    client: Arc<FullClient>,
+    network: bitcoin::Network,
+}
+
+impl ExportAnalyticsJob {
+    /// Kind of the job.
+    pub const KIND: &'static str = "exportAnalytics";
+
+    /// Constructs a new instance of [`ExportAnalyticsJob`].
+    pub fn new(client: Arc<FullClient>, network: bitcoin::Network) -> Self {
+        Self { client, network }
+    }
+
+    fn params(&self, params: &Value) -> Result<(ExportJobParams, u32), JobError> {
+        let params = ExportJobParams::deserialize(params)
+            .map_err(|err| JobError::InvalidParams(err.to_string()))?;
+        let to = check_range(&self.client, params.from, params.to)
+            .map_err(|err| JobError::InvalidParams(err.to_string()))?;
+        Ok((params, to))
+    }
+}
+
+impl JobHandler for ExportAnalyticsJob {
+    fn validate(&self, params: &Value) -> Result<u64, JobError> {
+        let (params, to) = self.params(params)?;
+        Ok(u64::from(to - params.from) + 1)
+    }
+
+    fn run(&self, params: &Value, ctx: &JobContext) -> Result<(), JobError> {
+        let (params, to) = self.params(params)?;
+        let total = u64::from(to - params.from) + 1;
+
+        let mut start = ctx
+            .checkpoint()
+            .and_then(Value::as_u64)
+            .map_or(params.from, |next_height| next_height as u32);
+
+        while start <= to {
+            let end = to.min(start.saturating_add(BLOCKS_PER_PART - 1));
+
+            export_blocks(
+                &self.client,
+                self.network,
+                start..=end,
+                &params.output,
+                params.format,
+                &format!("-{start:07}-{end:07}"),
+                || ctx.is_cancelled(),
+            )
+            .map_err(|err| JobError::Failed(err.to_string()))?
+            .ok_or(JobError::Cancelled)?;
+
+            start = end + 1;
+            ctx.report(u64::from(start - params.from), total, Value::from(start))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl sc_cli::CliConfiguration for ExportAnalyticsCmd {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn import_params(&self) -> Option<&ImportParams> {
+        Some(&self.import_params)
+    }
+
+    fn node_key_params(&self) -> Option<&NodeKeyParams> {
+        None
+    }
+}
+
+/// Returns the block at given height and the hash of its parent.
+fn bitcoin_block(
+    client: &FullClient,
+    height: u32,
+) -> sc_cli::Result<(<OpaqueBlock as BlockT>::Hash, BitcoinBlock)> {
+    let block_hash = client
+        .hash(height)?
+        .ok_or_else(|| sc_cli::Error::Input(format!("Block #{height} not found")))?;
+
+    let block = client
+        .block(block_hash)?
+        .ok_or_else(|| sc_cli::Error::Input(format!("Block #{height} not available (pruned)")))?
+        .block;
+
+    let parent_hash = *block.header().parent_hash();
+
+    let block =
+        convert_to_bitcoin_block::<OpaqueBlock, TransactionAdapter>(block).map_err(|err| {
+            sc_cli::Error::Application(format!("Invalid block #{height}: {err:?}").into())
+        })?;
+
+    Ok((parent_hash, block))
+}
+
+/// Output spent by an input.
+struct Prevout {
+    value: u64,
+    script_pubkey: ScriptBuf,
+    height: u32,
+}
+
+struct Tables {
+    transactions: Table<TransactionRow>,
+    inputs: Table<InputRow>,
+    outputs: Table<OutputRow>,
+}
+
+impl Tables {
+    /// Appends the rows of the block.
+    ///
+    /// `parent_coin` returns the coin in the UTXO set of the parent block.
+    fn push_block(
+        &mut self,
+        height: u32,
+        block: &BitcoinBlock,
+        network: bitcoin::Network,
+        parent_coin: impl Fn(&OutPoint) -> sc_cli::Result<Option<Coin>>,
+    ) -> sc_cli::Result<()> {
+        let block_hash = block.block_hash().to_string();
+
+        // Outputs created in this block, which may be spent by the later transactions.
+        let mut created = HashMap::<OutPoint, Prevout>::new();
+
+        for (tx_index, tx) in block.txdata.iter().enumerate() {
+            let txid = tx.compute_txid();
+            let txid_str = txid.to_string();
+            let is_coinbase = tx.is_coinbase();
+
+            let mut input_value = 0u64;
+
+            for (input_index, input) in tx.input.iter().enumerate() {
+                let out_point = input.previous_output;
+
+                let prevout = if is_coinbase {
+                    None
+                } else {
+                    let prevout = match created.remove(&out_point) {
+                        Some(prevout) => prevout,
+                        None => parent_coin(&out_point)?
+                            .map(|coin| Prevout {
+                                value: coin.amount,
+                                script_pubkey: ScriptBuf::from_bytes(coin.script_pubkey),
+                                height: coin.height,
+                            })
+                            .ok_or_else(|| {
+                                sc_cli::Error::Input(format!(
+                                    "UTXO {out_point} spent in block #{height} not found"
+                                ))
+                            })?,
+                    };
+                    input_value += prevout.value;
+                    Some(prevout)
+                };
+
+                self.inputs.push(InputRow {
+                    height,
+                    txid: txid_str.clone(),
+                    input_index: input_index as u32,
+                    prev_txid: out_point.txid.to_string(),
+                    prev_vout: out_point.vout,
+                    sequence: input.sequence.0,
+                    witness_items: input.witness.len() as u32,
+                    value: prevout.as_ref().map(|prevout| prevout.value),
+                    script_type: prevout.as_ref().map(|prevout| {
+                        ScriptType::classify(&prevout.script_pubkey)
+                            .as_str()
+                            .to_string()
+                    }),
+                    script_pubkey: prevout
+                        .as_ref()
+                        .map(|prevout| prevout.script_pubkey.as_bytes().to_lower_hex_string()),
+                    prevout_height: prevout.as_ref().map(|prevout| prevout.height),
+                })?;
+            }
+
+            let mut output_value = 0u64;
+
+            for (vout, output) in tx.output.iter().enumerate() {
+                let value = output.value.to_sat();
+                output_value += value;
+
+                self.outputs.push(OutputRow {
+                    height,
+                    txid: txid_str.clone(),
+                    vout: vout as u32,
+                    value,
+                    script_type: ScriptType::classify(&output.script_pubkey)
+                        .as_str()
+                        .to_string(),
+                    script_pubkey: output.script_pubkey.as_bytes().to_lower_hex_string(),
+                    address: Address::from_script(&output.script_pubkey, network)
+                        .ok()
+                        .map(|address| address.to_string()),
+                })?;
+
+                created.insert(
+                    OutPoint {
+                        txid,
+                        vout: vout as u32,
+                    },
+                    Prevout {
+                        value,
+                        script_pubkey: output.script_pubkey.clone(),
+                        height,
+                    },
+                );
+            }
+
+            self.transactions.push(TransactionRow {
+                height,
+                block_hash: block_hash.clone(),
+                txid: txid_str,
+                tx_index: tx_index as u32,
+                version: tx.version.0,
+                lock_time: tx.lock_time.to_consensus_u32(),
+                size: tx.total_size() as u32,
+                vsize: tx.vsize() as u32,
+                weight: tx.weight().to_wu(),
+                input_count: tx.input.len() as u32,
+                output_count: tx.output.len() as u32,
+                output_value,
+                fee: (!is_coinbase).then(|| input_value.saturating_sub(output_value)),
+                is_coinbase,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Row of an exported table.
+trait Row: Sized {
+    /// Name of the table, also the name of its file.
+    const TABLE: &'static str;
+
+    fn schema() -> Schema;
+
+    fn columns(rows: &[Self]) -> Vec<ArrayRef>;
+}
+
+struct TransactionRow {
+    height: u32,
+    block_hash: String,
+    txid: String,
+    tx_index: u32,
+    version: i32,
+    lock_time: u32,
+    size: u32,
+    vsize: u32,
+    weight: u64,
+    input_count: u32,
+    output_count: u32,
+    output_value: u64,
+    fee: Option<u64>,
+    is_coinbase: bool,
+}
+
+impl Row for TransactionRow {
+    const TABLE: &'static str = "transactions";
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("height", DataType::UInt32, false),
+            Field::new("block_hash", DataType::Utf8, false),
+            Field::new("txid", DataType::Utf8, false),
+            Field::new("tx_index", DataType::UInt32, false),
+            Field::new("version", DataType::Int32, false),
+            Field::new("lock_time", DataType::UInt32, false),
+            Field::new("size", DataType::UInt32, false),
+            Field::new("vsize", DataType::UInt32, false),
+            Field::new("weight", DataType::UInt64, false),
+            Field::new("input_count", DataType::UInt32, false),
+            Field::new("output_count", DataType::UInt32, false),
+            Field::new("output_value", DataType::UInt64, false),
+            Field::new("fee", DataType::UInt64, true),
+            Field::new("is_coinbase", DataType::Boolean, false),
+        ])
+    }
+
+    fn columns(rows: &[Self]) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.height))),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|r| &r.block_hash),
+            )),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.txid))),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.tx_index),
+            )),
+            Arc::new(Int32Array::from_iter_values(rows.iter().map(|r| r.version))),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.lock_time),
+            )),
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.size))),
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.vsize))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.weight))),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.input_count),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.output_count),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                rows.iter().map(|r| r.output_value),
+            )),
+            Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.fee))),
+            Arc::new(BooleanArray::from_iter(
+                rows.iter().map(|r| Some(r.is_coinbase)),
+            )),
+        ]
+    }
+}
+
+/// Input along with its resolved prevout, the prevout columns are null for the coinbase.
+struct InputRow {
+    height: u32,
+    txid: String,
+    input_index: u32,
+    prev_txid: String,
+    prev_vout: u32,
+    sequence: u32,
+    witness_items: u32,
+    value: Option<u64>,
+    script_type: Option<String>,
+    script_pubkey: Option<String>,
+    prevout_height: Option<u32>,
+}
+
+impl Row for InputRow {
+    const TABLE: &'static str = "inputs";
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("height", DataType::UInt32, false),
+            Field::new("txid", DataType::Utf8, false),
+            Field::new("input_index", DataType::UInt32, false),
+            Field::new("prev_txid", DataType::Utf8, false),
+            Field::new("prev_vout", DataType::UInt32, false),
+            Field::new("sequence", DataType::UInt32, false),
+            Field::new("witness_items", DataType::UInt32, false),
+            Field::new("value", DataType::UInt64, true),
+            Field::new("script_type", DataType::Utf8, true),
+            Field::new("script_pubkey", DataType::Utf8, true),
+            Field::new("prevout_height", DataType::UInt32, true),
+        ])
+    }
+
+    fn columns(rows: &[Self]) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.height))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.txid))),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.input_index),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|r| &r.prev_txid),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.prev_vout),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.sequence),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.witness_items),
+            )),
+            Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.value))),
+            Arc::new(StringArray::from_iter(
+                rows.iter().map(|r| r.script_type.as_deref()),
+            )),
+            Arc::new(StringArray::from_iter(
+                rows.iter().map(|r| r.script_pubkey.as_deref()),
+            )),
+            Arc::new(UInt32Array::from_iter(
+                rows.iter().map(|r| r.prevout_height),
+            )),
+        ]
+    }
+}
+
+struct OutputRow {
+    height: u32,
+    txid: String,
+    vout: u32,
+    value: u64,
+    script_type: String,
+    script_pubkey: String,
+    address: Option<String>,
+}
+
+impl Row for OutputRow {
+    const TABLE: &'static str = "outputs";
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("height", DataType::UInt32, false),
+            Field::new("txid", DataType::Utf8, false),
+            Field::new("vout", DataType::UInt32, false),
+            Field::new("value", DataType::UInt64, false),
+            Field::new("script_type", DataType::Utf8, false),
+            Field::new("script_pubkey", DataType::Utf8, false),
+            Field::new("address", DataType::Utf8, true),
+        ])
+    }
+
+    fn columns(rows: &[Self]) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.height))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.txid))),
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.vout))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.value))),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|r| &r.script_type),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|r| &r.script_pubkey),
+            )),
+            Arc::new(StringArray::from_iter(
+                rows.iter().map(|r| r.address.as_deref()),
+            )),
+        ]
+    }
+}
+
+enum TableWriter {
+    Parquet(ArrowWriter<File>),
+    Csv(arrow::csv::Writer<BufWriter<File>>),
+}
+
+/// Exported table, written out in batches of [`ROWS_PER_BATCH`] rows.
+struct Table<R> {
+    schema: SchemaRef,
+    rows: Vec<R>,
+    total_rows: usize,
+    writer: TableWriter,
+}
+
+impl<R: Row> Table<R> {
+    fn create(dir: &Path, format: ExportFormat, suffix: &str) -> sc_cli::Result<Self> {
+        let schema = Arc::new(R::schema());
+        let file = File::create(dir.join(format!("{}{suffix}.{}", R::TABLE, format.extension())))?;
+
+        let writer = match format {
+            ExportFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                    .set_max_row_group_size(ROWS_PER_BATCH)
+                    .build();
+                TableWriter::Parquet(
+                    ArrowWriter::try_new(file, schema.clone(), Some(properties))
+                        .map_err(application_error)?,
+                )
+            }
+            ExportFormat::Csv => TableWriter::Csv(
+                arrow::csv::WriterBuilder::new()
+                    .with_header(true)
+                    .build(BufWriter::new(file)),
+            ),
+        };
+
+        Ok(Self {
+            schema,
+            rows: Vec::with_capacity(ROWS_PER_BATCH),
+            total_rows: 0,
+            writer,
+        })
+    }
+
+    fn push(&mut self, row: R) -> sc_cli::Result<()> {
+        self.rows.push(row);
+        self.total_rows += 1;
+        if self.rows.len() >= ROWS_PER_BATCH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> sc_cli::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let batch = RecordBatch::try_new(self.schema.clone(), R::columns(&self.rows))
+            .map_err(application_error)?;
+
+        match &mut self.writer {
+            TableWriter::Parquet(writer) => writer.write(&batch).map_err(application_error)?,
+            TableWriter::Csv(writer) => writer.write(&batch).map_err(application_error)?,
+        }
+
+        self.rows.clear();
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> sc_cli::Result<()> {
+        self.flush()?;
+
+        match self.writer {
+            TableWriter::Parquet(writer) => {
+                writer.close().map_err(application_error)?;
+            }
+            TableWriter::Csv(writer) => writer.into_inner().flush()?,
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::cli::params::CommonParams;
+use crate::logging::LogFormat;
 use crate::utils::Yield;
 use bitcoin_explorer::BitcoinDB;
 use futures::FutureExt;
@@ -41,6 +42,10 @@ pub struct ImportBlocks {
     #[clap(long, default_value_t = true)]
     pub execute_transactions: bool,
 
+    /// Specify the format of the log output.
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
     #[allow(missing_docs)]
     #[clap(flatten)]
     pub prometheus_params: PrometheusParams,
@@ -60,6 +65,7 @@ pub struct ImportBlocksCmd {
     import_params: ImportParams,
     block_count: Option<usize>,
     to: Option<usize>,
+    log_format: LogFormat,
 }
 
 impl ImportBlocksCmd {
@@ -72,6 +78,7 @@ impl ImportBlocksCmd {
             import_params,
             block_count: cmd.block_count,
             to: cmd.end_block,
+            log_format: cmd.log_format,
         }
     }
 
@@ -242,6 +249,24 @@ impl sc_cli::CliConfiguration for ImportBlocksCmd {
     fn node_key_params(&self) -> Option<&NodeKeyParams> {
         None
     }
+
+    fn init<F>(
+        &self,
+        support_url: &String,
+        impl_version: &String,
+        logger_hook: F,
+    ) -> sc_cli::Result<()>
+    where
+        F: FnOnce(&mut sc_cli::LoggerBuilder),
+    {
+        crate::logging::init_logger(
+            &self.shared_params,
+            self.log_format,
+            support_url,
+            impl_version,
+            logger_hook,
+        )
+    }
 }
 
 struct BitcoinBackend {
@@ -0,0 +1,200 @@
+use crate::cli::params::CommonParams;
+use bitcoin::hex::DisplayHex;
+use bitcoin::BlockHash;
+use sc_cli::{ImportParams, NodeKeyParams, SharedParams};
+use sc_consensus_nakamoto::{
+    BlockExecutor, BlockReplay, BlockReplayer, ClientContext, CoinSource, OffRuntimeBlockExecutor,
+    RuntimeBlockExecutor,
+};
+use std::sync::Arc;
+use subcoin_runtime::interface::OpaqueBlock;
+use subcoin_service::{CoinStorageKey, FullClient, TransactionAdapter};
+
+/// Re-execute an imported block against the state of its parent.
+#[derive(clap::Parser, Debug, Clone)]
+pub struct ReplayBlock {
+    /// Hash of the Bitcoin block to replay.
+    #[clap(index = 1)]
+    pub block_hash: BlockHash,
+
+    /// Dump the storage reads/writes and the script verification of each transaction.
+    #[clap(long)]
+    pub trace: bool,
+
+    /// Execute the block with both the runtime and off-runtime executors and compare the
+    /// outputs.
+    #[clap(long)]
+    pub compare_executors: bool,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub common_params: CommonParams,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub import_params: ImportParams,
+}
+
+pub struct ReplayBlockCmd {
+    block_hash: BlockHash,
+    trace: bool,
+    compare_executors: bool,
+    network: bitcoin::Network,
+    shared_params: SharedParams,
+    import_params: ImportParams,
+}
+
+impl ReplayBlockCmd {
+    /// Constructs a new instance of [`ReplayBlockCmd`].
+    pub fn new(cmd: &ReplayBlock) -> Self {
+        Self {
+            block_hash: cmd.block_hash,
+            trace: cmd.trace,
+            compare_executors: cmd.compare_executors,
+            network: cmd.common_params.bitcoin_network(),
+            shared_params: cmd.common_params.as_shared_params(),
+            import_params: cmd.import_params.clone(),
+        }
+    }
+
+    pub async fn run(self, client: Arc<FullClient>) -> sc_cli::Result<()> {
+        // The in memory backend only has the latest state, the block is always replayed from
+        // the disk.
+        let runtime_executor = RuntimeBlockExecutor::new(client.clone(), ClientContext::Disk);
+        let off_runtime_executor = OffRuntimeBlockExecutor::<_, _, _, TransactionAdapter, _>::new(
+            client.clone(),
+            ClientContext::Disk,
+            Arc::new(CoinStorageKey),
+        );
+
+        let mut executors: Vec<&dyn BlockExecutor<OpaqueBlock>> = vec![&runtime_executor];
+        if self.compare_executors {
+            executors.push(&off_runtime_executor);
+        }
+
+        let replayer = BlockReplayer::<_, _, _, TransactionAdapter>::new(
+            client,
+            self.network,
+            Arc::new(CoinStorageKey),
+        );
+
+        let replay = replayer
+            .replay(self.block_hash, &executors, self.trace)
+            .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+
+        print_replay(&replay);
+
+        let diverging_keys = replay.diverging_keys();
+
+        if self.compare_executors && !diverging_keys.is_empty() {
+            let mismatch = replayer
+                .diagnose_state_root_mismatch(
+                    self.block_hash,
+                    &runtime_executor,
+                    &off_runtime_executor,
+                )
+                .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+            if let Some(mismatch) = mismatch {
+                println!("{mismatch}");
+            }
+        }
+
+        if replay.state_root_mismatches().next().is_some() || !diverging_keys.is_empty() {
+            return Err(sc_cli::Error::Application(
+                format!("Replay of block #{} diverged", replay.block_number).into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn print_replay(replay: &BlockReplay<OpaqueBlock>) {
+    println!("Block #{} {}", replay.block_number, replay.block_hash);
+    println!("Imported state root: {}", replay.imported_state_root);
+
+    for execution in &replay.executions {
+        let status = if execution.state_root == replay.imported_state_root {
+            "ok"
+        } else {
+            "MISMATCH"
+        };
+        println!(
+            "{:?}: state_root: {} ({status}), storage changes: {}, total: {}ns, \
+            fetch_state: {}ns, execute_block: {}ns, into_storage_changes: {}ns",
+            execution.strategy,
+            execution.state_root,
+            execution.main_storage_changes.len(),
+            execution.exec_info.total(),
+            execution.exec_info.fetch_state_time,
+            execution.exec_info.execute_block_time,
+            execution.exec_info.into_storage_changes_time,
+        );
+    }
+
+    if replay.executions.len() > 1 {
+        let diverging_keys = replay.diverging_keys();
+        println!("Diverging storage keys: {}", diverging_keys.len());
+        for key in diverging_keys {
+            println!("  0x{}", key.as_hex());
+        }
+    }
+
+    for tx in &replay.transactions {
+        println!("Transaction #{} {} ({:?})", tx.index, tx.txid, tx.elapsed);
+
+        for read in &tx.reads {
+            let source = match read.source {
+                CoinSource::ParentState => "parent state".to_string(),
+                CoinSource::Block { tx_index } => format!("transaction #{tx_index}"),
+                CoinSource::Missing => "MISSING".to_string(),
+            };
+            match &read.coin {
+                Some(coin) => println!(
+                    "  read  {} 0x{} from {source}: {} {}",
+                    read.out_point,
+                    read.storage_key.as_hex(),
+                    coin.value,
+                    coin.script_pubkey.as_bytes().as_hex(),
+                ),
+                None => println!(
+                    "  read  {} 0x{} from {source}",
+                    read.out_point,
+                    read.storage_key.as_hex(),
+                ),
+            }
+        }
+
+        for (key, value) in &tx.writes {
+            match value {
+                Some(value) => println!("  write 0x{} = 0x{}", key.as_hex(), value.as_hex()),
+                None => println!("  write 0x{} deleted", key.as_hex()),
+            }
+        }
+
+        for script in &tx.scripts {
+            let result = match &script.result {
+                Ok(()) => "ok".to_string(),
+                Err(err) => format!("FAILED: {err}"),
+            };
+            println!(
+                "  script input #{} {:?}: {result} ({:?})",
+                script.input_index, script.family, script.elapsed
+            );
+        }
+    }
+}
+
+impl sc_cli::CliConfiguration for ReplayBlockCmd {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn import_params(&self) -> Option<&ImportParams> {
+        Some(&self.import_params)
+    }
+
+    fn node_key_params(&self) -> Option<&NodeKeyParams> {
+        None
+    }
+}
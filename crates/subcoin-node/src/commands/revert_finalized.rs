@@ -0,0 +1,78 @@
+use sc_cli::SharedParams;
+use sc_client_api::Backend;
+use sc_consensus_nakamoto::FinalityConflict;
+use sp_blockchain::HeaderBackend;
+use std::sync::Arc;
+use subcoin_service::{FullBackend, FullClient};
+
+/// Revert the finalized blocks, e.g., to follow a Bitcoin reorg deeper than the finalized
+/// block.
+///
+/// The blocks after the fork point of the detected finality conflict are reverted by default,
+/// the node resumes the sync from there on the next start.
+#[derive(Debug, Clone, clap::Parser)]
+pub struct RevertFinalized {
+    /// Height of the block to revert to, overriding the fork point of the detected conflict.
+    #[clap(long, value_name = "HEIGHT")]
+    pub to: Option<u32>,
+
+    /// Confirm reverting the finalized blocks, which is irreversible.
+    #[clap(long)]
+    pub force: bool,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub shared_params: SharedParams,
+}
+
+impl RevertFinalized {
+    pub fn run(&self, client: Arc<FullClient>, backend: Arc<FullBackend>) -> sc_cli::Result<()> {
+        let conflict = FinalityConflict::load(&*client);
+
+        let target = match (self.to, &conflict) {
+            (Some(height), _) => height,
+            (None, Some(conflict)) => {
+                println!("Detected finality conflict: {conflict}");
+                conflict.fork_height
+            }
+            (None, None) => {
+                return Err(sc_cli::Error::Input(
+                    "No finality conflict detected, specify the height with `--to`".to_string(),
+                ))
+            }
+        };
+
+        let info = client.info();
+
+        if target >= info.best_number {
+            return Err(sc_cli::Error::Input(format!(
+                "Block #{target} is not below the best block #{}",
+                info.best_number
+            )));
+        }
+
+        if !self.force {
+            return Err(sc_cli::Error::Input(format!(
+                "Reverting to #{target} may revert the finalized blocks, pass `--force` to confirm"
+            )));
+        }
+
+        let (reverted, _) = backend.revert(info.best_number - target, true)?;
+
+        FinalityConflict::clear(&*client)?;
+
+        let info = client.info();
+        println!(
+            "Reverted {reverted} blocks, best: #{}, finalized: #{}",
+            info.best_number, info.finalized_number
+        );
+
+        Ok(())
+    }
+}
+
+impl sc_cli::CliConfiguration for RevertFinalized {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+}
@@ -1,15 +1,30 @@
-use crate::cli::params::{CommonParams, NetworkParams};
+use crate::builder::SubcoinNodeBuilder;
+use crate::cli::params::{CommonParams, NetworkParams, RpcAuthParams};
+use crate::db_migration::DbMigrate;
+use crate::logging::LogFormat;
+use bitcoin::hex::FromHex;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::{BlockHash, Work};
 use clap::Parser;
 use sc_cli::{
     ImportParams, NetworkParams as SubstrateNetworkParams, NodeKeyParams, PrometheusParams, Role,
     SharedParams,
 };
-use sc_client_api::UsageProvider;
-use sc_consensus_nakamoto::BitcoinBlockImporter;
+use sc_consensus_nakamoto::ReferenceNode;
 use sc_service::{Configuration, TaskManager};
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use subcoin_network::SyncStrategy;
-use subcoin_primitives::CONFIRMATION_DEPTH;
+use std::time::Duration;
+use subcoin_indexer::BackfillConfig;
+use subcoin_network::{StopAt, SyncStrategy};
+use subcoin_primitives::{BlockPruning, CoinStorageKey, FinalityOracle, MIN_PRUNE_TARGET};
+use subcoin_service::{
+    BitcoindRpc, BitcoindRpcAuth, BlockSource, ExecutorKind, FinalityPolicyConfig,
+};
+use subcoin_snapshot::{HttpBootstrap, SnapshotUrl};
 
 /// The `run` command used to run a Bitcoin node.
 #[derive(Debug, Clone, Parser)]
@@ -29,10 +44,249 @@ pub struct Run {
     #[clap(long)]
     pub no_finalizer: bool,
 
+    /// Specify the policy deciding the blocks finalized by the finalizer.
+    ///
+    /// `depth` finalizes the blocks with 6 confirmations, `work` the blocks buried under
+    /// `--finality-min-work` and `external` the checkpoints signed by `--finality-signer`
+    /// and submitted with the `subcoin_submitFinalityCheckpoint` RPC, e.g., by a sidechain
+    /// federation.
+    #[clap(long, value_enum, default_value_t = FinalityPolicy::Depth)]
+    pub finality_policy: FinalityPolicy,
+
+    /// Chain work in hex on top of a block required to finalize it with
+    /// `--finality-policy work`.
+    #[clap(
+        long,
+        value_name = "WORK",
+        value_parser = parse_work,
+        required_if_eq("finality_policy", "work")
+    )]
+    pub finality_min_work: Option<Work>,
+
+    /// Hex-encoded x-only public key of the signer of the finality checkpoints with
+    /// `--finality-policy external`.
+    #[clap(
+        long,
+        value_name = "PUBKEY",
+        value_parser = parse_x_only_public_key,
+        required_if_eq("finality_policy", "external")
+    )]
+    pub finality_signer: Option<XOnlyPublicKey>,
+
     /// Disable the Bitcoin networking.
     #[clap(long)]
     pub disable_subcoin_networking: bool,
 
+    /// Do not save the unconfirmed transactions to `mempool.dat` on shutdown and load them
+    /// on startup.
+    #[clap(long)]
+    pub no_persist_mempool: bool,
+
+    /// Enable the watch-only wallet and its RPCs.
+    #[clap(long)]
+    pub wallet: bool,
+
+    /// Index the UTXO set statistics of the finalized blocks and enable
+    /// `subcoin_getStatsRange` and `subcoin_getUtxoComposition`.
+    ///
+    /// The parent states of the blocks being indexed must be available, i.e., the indexer
+    /// needs to be enabled from the genesis or with `--state-pruning archive`.
+    #[clap(long)]
+    pub chain_stats: bool,
+
+    /// Index the `OP_RETURN` outputs of the finalized blocks and enable
+    /// `subcoin_searchOpReturn`.
+    #[clap(long)]
+    pub op_return_index: bool,
+
+    /// Index the BIP-352 silent payments tweaks of the finalized blocks and enable
+    /// `subcoin_getSilentPaymentTweaks`.
+    ///
+    /// The parent states of the blocks being indexed must be available, like `--chain-stats`.
+    #[clap(long)]
+    pub silent_payments_index: bool,
+
+    /// Index the transactions of the finalized blocks by txid and wtxid, the confirmed
+    /// transactions are then returned by `subcoin_getRawTransaction`.
+    #[clap(long)]
+    pub txindex: bool,
+
+    /// Interval in seconds of sending the Bitcoin height, UTXO count, verification progress
+    /// and snapshot availability to the telemetry server, `0` disables them.
+    ///
+    /// The UTXO count requires `--chain-stats`, the snapshot `--serve-snapshots`.
+    #[clap(long, value_name = "SECS", default_value_t = 30)]
+    pub bitcoin_telemetry_interval: u64,
+
+    /// Maximum number of blocks indexed per second when backfilling `--op-return-index` and
+    /// `--txindex` enabled on an already synced node, unlimited by default.
+    #[clap(long, value_name = "BLOCKS")]
+    pub index_backfill_rate: Option<NonZeroU32>,
+
+    /// Serve the REST interface compatible with Bitcoin Core on the specified address.
+    ///
+    /// The REST interface is unauthenticated, do not expose it publicly.
+    #[clap(long, value_name = "ADDR")]
+    pub rest: Option<SocketAddr>,
+
+    /// Serve the gRPC interface for streaming the blocks, querying the UTXOs and submitting
+    /// the transactions on the specified address.
+    ///
+    /// The gRPC interface is unauthenticated, do not expose it publicly.
+    #[clap(long, value_name = "ADDR")]
+    pub grpc: Option<SocketAddr>,
+
+    /// Prune the bodies of the finalized blocks to keep their disk usage below the target
+    /// in MiB, the headers are always kept.
+    ///
+    /// At least the bodies of the last 288 blocks are kept. Overrides `--blocks-pruning`.
+    #[clap(long, value_name = "MiB", conflicts_with = "blocks_pruning")]
+    pub prune: Option<u64>,
+
+    /// Generate the state snapshots at the finalized blocks and serve them to the peers.
+    ///
+    /// Requires `--state-pruning archive`.
+    #[clap(long)]
+    pub serve_snapshots: bool,
+
+    /// zstd compression level of the snapshot chunks served to the peers supporting it,
+    /// `0` serves them uncompressed.
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        default_value_t = subcoin_snapshot::DEFAULT_CHUNK_COMPRESSION_LEVEL,
+        value_parser = clap::value_parser!(i32).range(0..=22)
+    )]
+    pub snapshot_compression_level: i32,
+
+    /// Sync the state from the snapshot announced by at least this number of peers
+    /// instead of downloading all historical blocks.
+    ///
+    /// Only applies to a fresh node.
+    #[clap(long, value_name = "PEERS")]
+    pub snapshot_sync: Option<usize>,
+
+    /// Bootstrap the state from the snapshot artifact served at the URL, created by
+    /// `snapshot create`, then resume the sync from the snapshot block.
+    ///
+    /// The snapshot block must be an embedded checkpoint unless `--snapshot-block-hash` is
+    /// specified. Only applies to a fresh node.
+    #[clap(long, value_name = "URL", conflicts_with = "snapshot_sync")]
+    pub sync_from_snapshot_url: Option<SnapshotUrl>,
+
+    /// Trust the snapshot artifact at this block hash for `--sync-from-snapshot-url`.
+    #[clap(long, value_name = "HASH", requires = "sync_from_snapshot_url")]
+    pub snapshot_block_hash: Option<BlockHash>,
+
+    /// Compare the UTXO set changes and the verdict of each imported block with a trusted
+    /// bitcoind, e.g., `bitcoind://127.0.0.1:8332`, and halt on divergence.
+    ///
+    /// The REST interface of bitcoind must be enabled with `-rest`.
+    #[clap(long, value_name = "URL")]
+    pub verify_against: Option<ReferenceNode>,
+
+    /// Import the blocks of a trusted bitcoind, e.g., `bitcoind://127.0.0.1:8332`, instead of
+    /// syncing from the Bitcoin P2P network.
+    ///
+    /// The REST interface of bitcoind must be enabled with `-rest`.
+    #[clap(long, value_name = "URL", conflicts_with_all = ["snapshot_sync", "light"])]
+    pub follow_bitcoind_rest: Option<ReferenceNode>,
+
+    /// Import the blocks of a trusted bitcoind through its JSON-RPC interface, e.g.,
+    /// `127.0.0.1:8332`, instead of syncing from the Bitcoin P2P network.
+    ///
+    /// Authenticated with `--bitcoind-rpc-user` and `--bitcoind-rpc-password`, or the cookie
+    /// file of bitcoind otherwise.
+    #[clap(
+        long,
+        value_name = "HOST:PORT",
+        conflicts_with_all = ["snapshot_sync", "light", "follow_bitcoind_rest"]
+    )]
+    pub follow_bitcoind_rpc: Option<String>,
+
+    /// RPC user of the bitcoind followed by `--follow-bitcoind-rpc`.
+    #[clap(long, requires_all = ["follow_bitcoind_rpc", "bitcoind_rpc_password"])]
+    pub bitcoind_rpc_user: Option<String>,
+
+    /// RPC password of the bitcoind followed by `--follow-bitcoind-rpc`.
+    #[clap(long, requires = "bitcoind_rpc_user")]
+    pub bitcoind_rpc_password: Option<String>,
+
+    /// Cookie file of the bitcoind followed by `--follow-bitcoind-rpc`, `.cookie` in the data
+    /// directory of bitcoind.
+    #[clap(
+        long,
+        value_name = "PATH",
+        requires = "follow_bitcoind_rpc",
+        conflicts_with = "bitcoind_rpc_user"
+    )]
+    pub bitcoind_rpc_cookie: Option<PathBuf>,
+
+    /// Execute each block with both the runtime and off-runtime executors, a state root
+    /// mismatch is bisected to the first divergent transaction and the block is rejected.
+    ///
+    /// Doubles the block execution time.
+    #[clap(long)]
+    pub diagnose_state_root: bool,
+
+    /// Re-execute every N-th finalized block with the runtime in the background and verify
+    /// its state root, reported by the `subcoin_getStateRootAudit` RPC.
+    ///
+    /// Only applies to the off-runtime block execution. The blocks whose parent state has been
+    /// pruned are skipped, use `--state-pruning archive` to audit the whole chain.
+    #[clap(long, value_name = "N")]
+    pub state_root_audit: Option<u32>,
+
+    /// Stop the sync once the best block reaches the height.
+    #[clap(long, value_name = "HEIGHT", group = "stop_at")]
+    pub stop_at_height: Option<u32>,
+
+    /// Stop the sync once the block is imported.
+    ///
+    /// The block must be in the chain being synced, the sync continues otherwise.
+    #[clap(long, value_name = "HASH", group = "stop_at")]
+    pub stop_at_block_hash: Option<BlockHash>,
+
+    /// Migrate the subcoin database created by an older version on startup, `dry-run` prints
+    /// the pending migrations and exits instead.
+    #[clap(long, value_enum, default_value_t = DbMigrate::Auto)]
+    pub db_migrate: DbMigrate,
+
+    /// Exit the node once the sync is stopped by `--stop-at-height` or
+    /// `--stop-at-block-hash`.
+    #[clap(long, requires = "stop_at")]
+    pub exit_on_stop: bool,
+
+    /// Only serve the query RPCs (blocks, coins and stats) from the database opened read-only,
+    /// without the networking and block import.
+    ///
+    /// The chain is served as it was in the database at startup, e.g., a snapshot of the
+    /// database of another node. The networking and import options are ignored, `--rpc-cookie`
+    /// is not supported as the cookie of the node owning the database would be overwritten.
+    #[clap(long, conflicts_with = "rpc_cookie")]
+    pub read_only: bool,
+
+    /// Run as a light client, which syncs and verifies the Bitcoin headers only.
+    ///
+    /// No block is downloaded and no UTXO set is maintained. The header chain and the
+    /// verification of the merkle inclusion proofs are served by the `light_*` RPCs, the
+    /// import, index and snapshot options are ignored.
+    #[clap(long, conflicts_with_all = ["read_only", "rpc_cookie"])]
+    pub light: bool,
+
+    /// Specify the format of the log output.
+    ///
+    /// The `json` format enables the structured records of the imported blocks with the time
+    /// spent on the verification, execution and database flush, emitted at most once per
+    /// second unless the block is slow to import. Use `-l subcoin::import=debug` to have them
+    /// in the text format.
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub rpc_auth_params: RpcAuthParams,
+
     #[allow(missing_docs)]
     #[clap(flatten)]
     pub prometheus_params: PrometheusParams,
@@ -54,17 +308,161 @@ pub struct Run {
     pub import_params: ImportParams,
 }
 
+/// Policy of the finalizer.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum FinalityPolicy {
+    /// Finalize the blocks with enough confirmations.
+    #[default]
+    Depth,
+    /// Finalize the blocks buried under enough chain work.
+    Work,
+    /// Finalize the checkpoints submitted by an external oracle.
+    External,
+}
+
+fn parse_work(s: &str) -> Result<Work, String> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if s.len() > 64 {
+        return Err("Chain work must be at most 32 bytes".to_string());
+    }
+    <[u8; 32]>::from_hex(&format!("{s:0>64}"))
+        .map(Work::from_be_bytes)
+        .map_err(|err| format!("Invalid chain work: {err}"))
+}
+
+fn parse_x_only_public_key(s: &str) -> Result<XOnlyPublicKey, String> {
+    s.parse()
+        .map_err(|err| format!("Invalid x-only public key: {err}"))
+}
+
 impl Run {
+    /// Returns the finality policy specified by `--finality-policy`.
+    pub fn finality_policy(&self) -> FinalityPolicyConfig {
+        match self.finality_policy {
+            FinalityPolicy::Depth => FinalityPolicyConfig::default(),
+            FinalityPolicy::Work => FinalityPolicyConfig::Work(
+                self.finality_min_work
+                    .expect("`--finality-min-work` is required by the work policy; qed"),
+            ),
+            FinalityPolicy::External => FinalityPolicyConfig::External(FinalityOracle::new(
+                self.finality_signer
+                    .expect("`--finality-signer` is required by the external policy; qed"),
+            )),
+        }
+    }
+
+    /// Returns the block to stop the sync at.
+    pub fn stop_at(&self) -> Option<StopAt> {
+        self.stop_at_height
+            .map(StopAt::Height)
+            .or(self.stop_at_block_hash.map(StopAt::BlockHash))
+    }
+
+    /// Returns the bitcoind to import the blocks from instead of the Bitcoin P2P network.
+    pub fn block_source(&self) -> sc_cli::Result<Option<Arc<dyn BlockSource>>> {
+        if let Some(bitcoind) = &self.follow_bitcoind_rest {
+            return Ok(Some(Arc::new(bitcoind.clone())));
+        }
+
+        let Some(addr) = &self.follow_bitcoind_rpc else {
+            return Ok(None);
+        };
+
+        let auth = match (
+            &self.bitcoind_rpc_user,
+            &self.bitcoind_rpc_password,
+            &self.bitcoind_rpc_cookie,
+        ) {
+            (Some(user), Some(password), _) => {
+                BitcoindRpcAuth::UserPass(user.clone(), password.clone())
+            }
+            (_, _, Some(cookie)) => BitcoindRpcAuth::CookieFile(cookie.clone()),
+            _ => {
+                return Err(sc_cli::Error::Input(
+                    "`--follow-bitcoind-rpc` requires `--bitcoind-rpc-user` or \
+                    `--bitcoind-rpc-cookie`"
+                        .to_string(),
+                ))
+            }
+        };
+
+        let bitcoind =
+            BitcoindRpc::new(addr, auth).map_err(|err| sc_cli::Error::Input(err.to_string()))?;
+
+        Ok(Some(Arc::new(bitcoind)))
+    }
+
+    /// Returns the block pruning specified by `--prune`.
+    pub fn block_pruning(&self) -> sc_cli::Result<Option<BlockPruning>> {
+        self.prune
+            .map(|target| {
+                BlockPruning::new(target).ok_or_else(|| {
+                    sc_cli::Error::Input(format!(
+                        "Prune target {target} MiB is below the minimum of {MIN_PRUNE_TARGET} MiB"
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the snapshot bootstrap specified by `--sync-from-snapshot-url`.
+    pub fn snapshot_bootstrap(&self, network: bitcoin::Network) -> Option<HttpBootstrap> {
+        self.sync_from_snapshot_url.clone().map(|url| {
+            let bootstrap = HttpBootstrap::new(
+                url,
+                network,
+                subcoin_service::CoinStorageKey.storage_prefix(),
+            );
+            match self.snapshot_block_hash {
+                Some(block_hash) => bootstrap.with_trusted_block_hash(block_hash),
+                None => bootstrap,
+            }
+        })
+    }
+
     pub fn subcoin_network_params(&self, network: bitcoin::Network) -> subcoin_network::Params {
         subcoin_network::Params {
             network,
+            custom_network: self
+                .common_params
+                .chain_spec
+                .as_ref()
+                .map(|chain_spec| chain_spec.network_params()),
             listen_on: self.network_params.listen,
+            listen_transports: self.network_params.listen_transports.clone(),
+            v2_transport: !self.network_params.no_v2_transport,
+            port_mapping: self.network_params.port_mapping,
+            i2p_sam: self.network_params.i2psam,
+            cjdns_reachable: self.network_params.cjdnsreachable,
             seednodes: self.network_params.seednodes.clone(),
             seednode_only: self.network_params.seednode_only,
             ipv4_only: self.network_params.ipv4_only,
             max_outbound_peers: self.network_params.max_outbound_peers,
-            max_inbound_peers: self.network_params.max_inbound_peers,
+            max_block_relay_only_peers: self.network_params.max_block_relay_only_peers,
+            max_inbound_peers: self.network_params.max_inbound_peers(),
+            connect: self.network_params.connect.clone(),
+            addnode: self.network_params.addnode.clone(),
+            max_upload_target: self
+                .network_params
+                .max_upload_target
+                .map(|mib| mib * 1024 * 1024),
+            max_upload_rate: self.network_params.max_upload_rate.map(|kib| kib * 1024),
+            max_download_rate: self.network_params.max_download_rate.map(|kib| kib * 1024),
+            max_peer_message_rate: self.network_params.max_peer_message_rate,
+            import_memory_budget: self.network_params.max_import_memory * 1024 * 1024,
             sync_strategy: self.sync_strategy,
+            snapshot: None,
+            db: None,
+            network_tip: None,
+            mempool_path: None,
+            full_rbf: !self.network_params.no_full_rbf,
+            utxo_provider: None,
+            stop_at: self.stop_at(),
+            verification_threads: self.common_params.verification_threads,
+            sync_stall_timeout: (self.network_params.sync_stall_timeout > 0)
+                .then(|| Duration::from_secs(self.network_params.sync_stall_timeout)),
+            rotate_stalled_sync_peer: self.network_params.rotate_stalled_sync_peer,
+            blocks_only: self.network_params.blocksonly,
         }
     }
 }
@@ -75,6 +473,8 @@ pub struct RunCmd {
     import_params: ImportParams,
     prometheus_params: PrometheusParams,
     substrate_network_params: SubstrateNetworkParams,
+    log_format: LogFormat,
+    stop_reached: Arc<AtomicBool>,
 }
 
 impl RunCmd {
@@ -85,157 +485,93 @@ impl RunCmd {
             prometheus_params: run.prometheus_params.clone(),
             import_params: run.import_params.clone(),
             substrate_network_params: run.substrate_network_params.clone(),
+            log_format: run.log_format,
+            stop_reached: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Returns the flag set once the node is shut down by `--exit-on-stop`.
+    pub fn stop_reached(&self) -> Arc<AtomicBool> {
+        self.stop_reached.clone()
+    }
+
     /// Start subcoin node.
     pub async fn start(
         self,
-        mut config: Configuration,
+        config: Configuration,
         run: Run,
+        executor: ExecutorKind,
         no_hardware_benchmarks: bool,
         storage_monitor: sc_storage_monitor::StorageMonitorParams,
     ) -> sc_cli::Result<TaskManager> {
-        let block_execution_strategy = run.common_params.block_execution_strategy();
         let network = run.common_params.bitcoin_network();
-        let import_config = run.common_params.import_config();
-        let no_finalizer = run.no_finalizer;
-        let major_sync_confirmation_depth = run.major_sync_confirmation_depth;
-
-        let subcoin_service::NodeComponents {
-            client,
-            backend,
-            mut task_manager,
-            block_executor,
-            keystore_container,
-            telemetry,
-            ..
-        } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
-            network,
-            config: &config,
-            block_execution_strategy,
-            no_hardware_benchmarks,
-            storage_monitor,
-        })?;
-
-        let chain_info = client.usage_info().chain;
-
-        tracing::info!("📦 Highest known block at #{}", chain_info.best_number);
-
-        let spawn_handle = task_manager.spawn_handle();
-
-        let bitcoin_block_import =
-            BitcoinBlockImporter::<_, _, _, _, subcoin_service::TransactionAdapter>::new(
-                client.clone(),
-                client.clone(),
-                import_config,
-                Arc::new(subcoin_service::CoinStorageKey),
-                block_executor,
-                config.prometheus_registry(),
-            );
 
-        let import_queue = sc_consensus_nakamoto::bitcoin_import_queue(
-            &task_manager.spawn_essential_handle(),
-            bitcoin_block_import,
-        );
-
-        let (subcoin_networking, subcoin_network_handle) = subcoin_network::Network::new(
-            client.clone(),
-            run.subcoin_network_params(network),
-            import_queue,
-            spawn_handle.clone(),
-            config.prometheus_registry().cloned(),
-        );
-
-        // TODO: handle Substrate networking and Bitcoin networking properly.
-        if !run.disable_subcoin_networking {
-            task_manager.spawn_essential_handle().spawn_blocking(
-                "subcoin-networking",
-                None,
-                async move {
-                    if let Err(err) = subcoin_networking.run().await {
-                        tracing::error!(?err, "Error occurred in subcoin networking");
-                    }
-                },
-            );
-        } else {
-            task_manager.keep_alive(subcoin_networking);
+        if run.read_only {
+            return crate::read_only::start_read_only_node(
+                config,
+                executor,
+                network,
+                run.rpc_auth_params.rpc_auth(),
+            )
+            .map_err(Into::into);
         }
 
-        let (system_rpc_tx, substrate_sync_service) = match config.network.network_backend {
-            sc_network::config::NetworkBackendType::Libp2p => {
-                subcoin_service::start_substrate_network::<
-                    sc_network::NetworkWorker<
-                        subcoin_runtime::interface::OpaqueBlock,
-                        <subcoin_runtime::interface::OpaqueBlock as sp_runtime::traits::Block>::Hash,
-                    >,
-                >(
-                    &mut config,
-                    client.clone(),
-                    backend,
-                    &mut task_manager,
-                    keystore_container.keystore(),
-                    telemetry,
-                )?
-            }
-            sc_network::config::NetworkBackendType::Litep2p => {
-                subcoin_service::start_substrate_network::<sc_network::Litep2pNetworkBackend>(
-                    &mut config,
-                    client.clone(),
-                    backend,
-                    &mut task_manager,
-                    keystore_container.keystore(),
-                    telemetry,
-                )?
-            }
-        };
-
-        // TODO: Bitcoin-compatible RPC
-        // Start JSON-RPC server.
-        let gen_rpc_module = |deny_unsafe: sc_rpc::DenyUnsafe| {
-            let system_info = sc_rpc::system::SystemInfo {
-                chain_name: config.chain_spec.name().into(),
-                impl_name: config.impl_name.clone(),
-                impl_version: config.impl_version.clone(),
-                properties: config.chain_spec.properties(),
-                chain_type: config.chain_spec.chain_type(),
-            };
-            let system_rpc_tx = system_rpc_tx.clone();
-
-            crate::rpc::gen_rpc_module(
-                system_info,
-                client.clone(),
-                task_manager.spawn_handle(),
-                system_rpc_tx,
-                deny_unsafe,
-                subcoin_network_handle.clone(),
+        if run.light {
+            return crate::light::start_light_node(
+                config,
+                executor,
+                run.subcoin_network_params(network),
+                no_hardware_benchmarks,
+                storage_monitor,
+                run.rpc_auth_params.rpc_auth(),
             )
-        };
-
-        let rpc = sc_service::start_rpc_servers(&config, gen_rpc_module, None)?;
-        task_manager.keep_alive((config.base_path.clone(), rpc));
-
-        if !no_finalizer {
-            spawn_handle.spawn("finalizer", None, {
-                subcoin_service::finalize_confirmed_blocks(
-                    client.clone(),
-                    spawn_handle.clone(),
-                    CONFIRMATION_DEPTH,
-                    major_sync_confirmation_depth,
-                    subcoin_network_handle.is_major_syncing(),
-                    Some(substrate_sync_service),
-                )
-            });
+            .map_err(Into::into);
         }
 
-        // Spawn subcoin informant task.
-        spawn_handle.spawn(
-            "subcoin-informant",
-            None,
-            subcoin_informant::build(client.clone(), subcoin_network_handle),
-        );
-
-        Ok(task_manager)
+        let node = SubcoinNodeBuilder::new(config)
+            .with_bitcoin_network(run.subcoin_network_params(network))
+            .with_block_execution_strategy(run.common_params.block_execution_strategy())
+            .with_executor(executor)
+            .with_in_memory_backend_config(run.common_params.in_memory_backend_config())
+            .with_import_config(run.common_params.import_config())
+            .with_bitcoin_networking(!run.disable_subcoin_networking)
+            .with_block_source(run.block_source()?)
+            .with_persist_mempool(!run.no_persist_mempool)
+            .with_finalizer((!run.no_finalizer).then_some(run.major_sync_confirmation_depth))
+            .with_finality_policy(run.finality_policy())
+            .with_bitcoin_telemetry(
+                (run.bitcoin_telemetry_interval > 0)
+                    .then(|| Duration::from_secs(run.bitcoin_telemetry_interval)),
+            )
+            .with_wallet(run.wallet)
+            .with_chain_stats(run.chain_stats)
+            .with_op_return_index(run.op_return_index)
+            .with_silent_payments_index(run.silent_payments_index)
+            .with_tx_index(run.txindex)
+            .with_index_backfill(BackfillConfig {
+                max_blocks_per_second: run.index_backfill_rate,
+                ..Default::default()
+            })
+            .with_rest(run.rest)
+            .with_grpc(run.grpc)
+            .with_block_pruning(run.block_pruning()?)
+            .with_serve_snapshots(run.serve_snapshots)
+            .with_snapshot_compression(
+                (run.snapshot_compression_level > 0).then_some(run.snapshot_compression_level),
+            )
+            .with_snapshot_sync(run.snapshot_sync)
+            .with_snapshot_bootstrap(run.snapshot_bootstrap(network))
+            .with_differential_validation(run.verify_against.clone())
+            .with_state_root_diagnostics(run.diagnose_state_root)
+            .with_state_root_audit(run.state_root_audit)
+            .with_rpc_auth(run.rpc_auth_params.rpc_auth())
+            .with_rpc_cookie(run.rpc_auth_params.rpc_cookie)
+            .with_hardware_benchmarks(!no_hardware_benchmarks)
+            .with_storage_monitor(storage_monitor)
+            .with_exit_on_stop(run.exit_on_stop.then(|| self.stop_reached.clone()))
+            .build()?;
+
+        Ok(node.task_manager)
     }
 }
 
@@ -252,6 +588,24 @@ impl sc_cli::CliConfiguration for RunCmd {
         Some(&self.substrate_network_params.node_key_params)
     }
 
+    fn init<F>(
+        &self,
+        support_url: &String,
+        impl_version: &String,
+        logger_hook: F,
+    ) -> sc_cli::Result<()>
+    where
+        F: FnOnce(&mut sc_cli::LoggerBuilder),
+    {
+        crate::logging::init_logger(
+            &self.shared_params,
+            self.log_format,
+            support_url,
+            impl_version,
+            logger_hook,
+        )
+    }
+
     fn role(&self, _is_dev: bool) -> sc_cli::Result<Role> {
         Ok(Role::Full)
     }
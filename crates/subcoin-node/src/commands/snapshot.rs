@@ -0,0 +1,192 @@
+use crate::cli::params::CommonParams;
+use sc_cli::{ImportParams, NodeKeyParams, SharedParams};
+use sp_core::{ed25519, Pair};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use subcoin_primitives::CoinStorageKey;
+use subcoin_runtime::interface::OpaqueBlock as Block;
+use subcoin_service::FullClient;
+
+/// Snapshot artifacts for the out of band distribution.
+#[derive(Debug, clap::Subcommand)]
+pub enum Snapshot {
+    /// Create the snapshot artifact of the state at the given height.
+    ///
+    /// The state at the height must not be pruned.
+    Create(Box<SnapshotCreate>),
+
+    /// Verify a snapshot artifact offline.
+    Verify {
+        /// Directory of the artifact.
+        #[arg(index = 1, value_name = "DIR")]
+        artifact: PathBuf,
+
+        /// Require the manifest to be signed by the hex-encoded ed25519 public key.
+        #[clap(long, value_name = "PUBLIC_KEY")]
+        signer: Option<String>,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SnapshotCreate {
+    /// Height of the snapshot block.
+    #[clap(long)]
+    at: u32,
+
+    /// Directory of the created artifact.
+    #[clap(long, short, value_name = "DIR")]
+    output: PathBuf,
+
+    /// Sign the manifest with the ed25519 secret key in the file, raw or hex-encoded.
+    ///
+    /// The node key, e.g., `<base-path>/chains/<chain>/network/secret_ed25519`, can be
+    /// used to attribute the artifact to this node.
+    #[clap(long, value_name = "PATH")]
+    signing_key: Option<PathBuf>,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub common_params: CommonParams,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    import_params: ImportParams,
+}
+
+pub struct SnapshotCreateCmd {
+    at: u32,
+    output: PathBuf,
+    signing_key: Option<PathBuf>,
+    shared_params: SharedParams,
+    import_params: ImportParams,
+}
+
+impl SnapshotCreateCmd {
+    /// Constructs a new instance of [`SnapshotCreateCmd`].
+    pub fn new(create: SnapshotCreate) -> Self {
+        let SnapshotCreate {
+            at,
+            output,
+            signing_key,
+            common_params,
+            import_params,
+        } = create;
+
+        Self {
+            at,
+            output,
+            signing_key,
+            shared_params: common_params.as_shared_params(),
+            import_params,
+        }
+    }
+
+    pub async fn run(self, client: Arc<FullClient>) -> sc_cli::Result<()> {
+        let Self {
+            at,
+            output,
+            signing_key,
+            ..
+        } = self;
+
+        let signer = signing_key.as_deref().map(load_signing_key).transpose()?;
+
+        let now = Instant::now();
+
+        let manifest = subcoin_snapshot::create_artifact(
+            client.as_ref(),
+            &subcoin_service::CoinStorageKey.storage_prefix(),
+            at,
+            &output,
+            signer.as_ref(),
+        )
+        .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+
+        println!(
+            "Created snapshot artifact at #{at},{} in {}s",
+            manifest.block_hash,
+            now.elapsed().as_secs()
+        );
+        print_manifest(&manifest);
+
+        Ok(())
+    }
+}
+
+impl sc_cli::CliConfiguration for SnapshotCreateCmd {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn import_params(&self) -> Option<&ImportParams> {
+        Some(&self.import_params)
+    }
+
+    fn node_key_params(&self) -> Option<&NodeKeyParams> {
+        None
+    }
+}
+
+/// Verifies the artifact in the directory, optionally signed by the expected signer.
+pub fn verify(artifact: &Path, signer: Option<String>) -> sc_cli::Result<()> {
+    let expected_signer = signer
+        .map(|signer| {
+            let public = hex::decode(signer.trim_start_matches("0x"))
+                .ok()
+                .and_then(|public| <[u8; 32]>::try_from(public).ok())
+                .ok_or_else(|| sc_cli::Error::Input(format!("Invalid public key: {signer}")))?;
+            Ok::<_, sc_cli::Error>(ed25519::Public::from_raw(public))
+        })
+        .transpose()?;
+
+    let manifest = subcoin_snapshot::verify_artifact::<Block>(
+        artifact,
+        &subcoin_service::CoinStorageKey.storage_prefix(),
+    )
+    .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+
+    let signer = manifest
+        .signature
+        .as_ref()
+        .map(|signature| signature.public);
+
+    if let Some(expected_signer) = expected_signer {
+        if signer != Some(expected_signer) {
+            return Err(sc_cli::Error::Input(
+                "Manifest is not signed by the expected key".to_string(),
+            ));
+        }
+    }
+
+    println!("Snapshot artifact is valid");
+    print_manifest(&manifest);
+
+    Ok(())
+}
+
+fn print_manifest(manifest: &subcoin_snapshot::ArtifactManifest) {
+    println!("id: {}", hex::encode(manifest.id()));
+    println!("height: {}", manifest.height);
+    println!("block_hash: {}", manifest.block_hash);
+    println!("state_root: 0x{}", hex::encode(manifest.state_root));
+    println!("snapshot_root: {}", hex::encode(manifest.snapshot_root));
+    println!("files: {}", manifest.files.len());
+    match &manifest.signature {
+        Some(signature) => println!("signer: {}", hex::encode(signature.public)),
+        None => println!("signer: none"),
+    }
+}
+
+fn load_signing_key(path: &Path) -> sc_cli::Result<ed25519::Pair> {
+    let content = std::fs::read(path)?;
+
+    // The node key file may contain the raw secret or the hex-encoded one.
+    let secret = std::str::from_utf8(&content)
+        .ok()
+        .and_then(|content| hex::decode(content.trim()).ok())
+        .unwrap_or(content);
+
+    ed25519::Pair::from_seed_slice(&secret)
+        .map_err(|err| sc_cli::Error::Input(format!("Invalid signing key: {err:?}")))
+}
@@ -0,0 +1,102 @@
+use crate::cli::params::Chain;
+use bitcoin::hex::DisplayHex;
+use bitcoin::BlockHash;
+use serde_json::json;
+use std::path::PathBuf;
+use subcoin_utxo_proof::{verify_utxo_proof, UtxoProof, VerifyOptions};
+
+/// Verify the coins in a UTXO proof against the proof of work and the state root, no node is
+/// required.
+#[derive(Debug, clap::Parser)]
+pub struct VerifyUtxo {
+    /// Path of the JSON proof returned by `subcoin_getUtxoProof`.
+    #[clap(long)]
+    proof: PathBuf,
+
+    /// Chain of the proof.
+    #[clap(long, value_enum, default_value_t = Chain::BitcoinMainnet)]
+    chain: Chain,
+
+    /// Block known to be in the best chain, e.g., the current tip from a trusted source.
+    ///
+    /// It must be the proven block or one of the headers on top of it in the proof.
+    #[clap(long)]
+    trusted_block: Option<BlockHash>,
+
+    /// Minimum confirmations of the proven block, including itself.
+    #[clap(long, default_value_t = 6)]
+    min_confirmations: u32,
+}
+
+impl VerifyUtxo {
+    pub fn run(self) -> sc_cli::Result<()> {
+        let file = std::fs::File::open(&self.proof)?;
+
+        let proof: UtxoProof = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|err| sc_cli::Error::Input(format!("Invalid proof file: {err}")))?;
+
+        let verified = verify_utxo_proof(
+            &proof,
+            &VerifyOptions {
+                network: self.chain.bitcoin_network(),
+                trusted_block: self.trusted_block,
+                min_confirmations: self.min_confirmations,
+            },
+        )
+        .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+
+        let coins = verified
+            .coins
+            .iter()
+            .map(|(outpoint, coin)| match coin {
+                Some(coin) => json!({
+                    "outpoint": outpoint,
+                    "unspent": true,
+                    "value": coin.amount,
+                    "height": coin.height,
+                    "isCoinbase": coin.is_coinbase,
+                    "scriptPubKey": coin.script_pubkey.to_lower_hex_string(),
+                }),
+                None => json!({
+                    "outpoint": outpoint,
+                    "unspent": false,
+                }),
+            })
+            .collect::<Vec<_>>();
+
+        let summary = json!({
+            "blockHash": verified.block_hash,
+            "height": verified.height,
+            "substrateHash": verified.substrate_hash,
+            "confirmations": verified.confirmations,
+            "work": verified.work.to_be_bytes().to_lower_hex_string(),
+            "coins": coins,
+        });
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&summary)
+                .map_err(|err| sc_cli::Error::Application(Box::new(err)))?
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use subcoin_primitives::CoinStorageKey;
+
+    #[test]
+    fn test_coin_storage_key_matches_runtime() {
+        let outpoint = bitcoin::OutPoint {
+            txid: bitcoin::Txid::from_byte_array([7; 32]),
+            vout: 3,
+        };
+        assert_eq!(
+            subcoin_utxo_proof::coin_storage_key(&outpoint),
+            subcoin_service::CoinStorageKey.storage_key(outpoint.txid, outpoint.vout)
+        );
+    }
+}
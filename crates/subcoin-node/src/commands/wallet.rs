@@ -0,0 +1,163 @@
+use crate::cli::params::CommonParams;
+use sc_cli::{ImportParams, NodeKeyParams, SharedParams};
+use sc_consensus_nakamoto::BlockExecutionStrategy;
+use std::sync::Arc;
+use subcoin_db::SubcoinDb;
+use subcoin_service::FullClient;
+use subcoin_wallet::RescanOutcome;
+
+/// Watch-only wallet.
+#[derive(Debug, clap::Subcommand)]
+pub enum Wallet {
+    /// Rescan the stored blocks for the transactions affecting the imported descriptors.
+    Rescan {
+        /// Height of the first block to rescan.
+        #[clap(long, conflicts_with = "resume")]
+        from_height: Option<u32>,
+
+        /// Height of the last block to rescan, defaults to the best block of the wallet.
+        #[clap(long, conflicts_with = "resume")]
+        to_height: Option<u32>,
+
+        /// Resume the previously interrupted rescan.
+        #[clap(long)]
+        resume: bool,
+
+        #[allow(missing_docs)]
+        #[clap(flatten)]
+        common_params: CommonParams,
+
+        #[allow(missing_docs)]
+        #[clap(flatten)]
+        import_params: ImportParams,
+    },
+}
+
+impl Wallet {
+    pub fn block_execution_strategy(&self) -> BlockExecutionStrategy {
+        match self {
+            Self::Rescan { common_params, .. } => common_params.block_execution_strategy(),
+        }
+    }
+
+    pub fn bitcoin_network(&self) -> bitcoin::Network {
+        match self {
+            Self::Rescan { common_params, .. } => common_params.bitcoin_network(),
+        }
+    }
+}
+
+pub enum WalletCmd {
+    Rescan {
+        from_height: Option<u32>,
+        to_height: Option<u32>,
+        resume: bool,
+        network: bitcoin::Network,
+        shared_params: SharedParams,
+        import_params: ImportParams,
+    },
+}
+
+impl WalletCmd {
+    /// Constructs a new instance of [`WalletCmd`].
+    pub fn new(wallet: Wallet) -> Self {
+        let network = wallet.bitcoin_network();
+        match wallet {
+            Wallet::Rescan {
+                from_height,
+                to_height,
+                resume,
+                common_params,
+                import_params,
+            } => Self::Rescan {
+                from_height,
+                to_height,
+                resume,
+                network,
+                shared_params: common_params.as_shared_params(),
+                import_params,
+            },
+        }
+    }
+
+    fn shared_params(&self) -> &SharedParams {
+        match self {
+            Self::Rescan { shared_params, .. } => shared_params,
+        }
+    }
+
+    pub async fn run(self, client: Arc<FullClient>, db: SubcoinDb) -> sc_cli::Result<()> {
+        match self {
+            Self::Rescan {
+                from_height,
+                to_height,
+                resume,
+                network,
+                ..
+            } => rescan(client, db, network, from_height, to_height, resume),
+        }
+    }
+}
+
+impl sc_cli::CliConfiguration for WalletCmd {
+    fn shared_params(&self) -> &SharedParams {
+        WalletCmd::shared_params(self)
+    }
+
+    fn import_params(&self) -> Option<&ImportParams> {
+        match self {
+            Self::Rescan { import_params, .. } => Some(import_params),
+        }
+    }
+
+    fn node_key_params(&self) -> Option<&NodeKeyParams> {
+        None
+    }
+}
+
+fn rescan(
+    client: Arc<FullClient>,
+    db: SubcoinDb,
+    network: bitcoin::Network,
+    from_height: Option<u32>,
+    to_height: Option<u32>,
+    resume: bool,
+) -> sc_cli::Result<()> {
+    let wallet = subcoin_wallet::Wallet::new(
+        client,
+        db,
+        network,
+        Arc::new(subcoin_service::CoinStorageKey),
+    )
+    .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+
+    let outcome = if resume {
+        wallet
+            .resume_rescan::<subcoin_service::TransactionAdapter>()
+            .map_err(|err| sc_cli::Error::Application(Box::new(err)))?
+            .ok_or_else(|| sc_cli::Error::Input("No interrupted rescan to resume".to_string()))?
+    } else {
+        wallet
+            .rescan::<subcoin_service::TransactionAdapter>(from_height.unwrap_or(0), to_height)
+            .map_err(|err| sc_cli::Error::Application(Box::new(err)))?
+    };
+
+    match outcome {
+        RescanOutcome::Completed(progress) => {
+            println!(
+                "Rescanned blocks #{}..=#{}, {} wallet transactions in total",
+                progress.start_height,
+                progress.stop_height,
+                wallet.history().len()
+            );
+        }
+        RescanOutcome::Aborted(progress) => {
+            println!(
+                "Rescan aborted at #{}, run with --resume to continue",
+                progress.current_height
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Schemas of the data maintained by the node and their migrations run on startup.
+
+use sc_service::Configuration;
+use subcoin_db::{Migrator, Schema, SubcoinDb};
+
+/// Mode of the database migrations on startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DbMigrate {
+    /// Apply the pending migrations before starting the node.
+    #[default]
+    Auto,
+    /// Print the pending migrations and exit without changing the database.
+    DryRun,
+}
+
+/// Schemas of the data maintained by the node.
+///
+/// The version of a schema must be bumped along with a migration registered in [`migrator`]
+/// whenever the layout of its existing data changes.
+const SCHEMAS: &[Schema] = &[
+    // Block hash mapping, chain work, headers and sync checkpoint in the aux column.
+    Schema::new("aux", 1),
+    Schema::new("wallet", 1),
+    Schema::new("snapshots", 1),
+    Schema::new("filters", 1),
+    Schema::new("peers", 1),
+    Schema::new("chain_stats", 1),
+    // 2: sync height moved to the meta column.
+    Schema::new("op_return", 2),
+    Schema::new("silent_payments", 1),
+    // 2: sync height moved to the meta column.
+    Schema::new("txindex", 2),
+];
+
+/// Returns the [`Migrator`] of all the schemas of the node.
+pub(crate) fn migrator(db: SubcoinDb) -> Migrator {
+    let migrator = SCHEMAS.iter().fold(Migrator::new(db), |migrator, schema| {
+        migrator.with_schema(*schema)
+    });
+
+    subcoin_indexer::migrations()
+        .into_iter()
+        .fold(migrator, Migrator::with_migration)
+}
+
+/// Prints the migrations pending in the database of `config`, `--db-migrate dry-run`.
+pub(crate) fn dry_run(config: &Configuration) -> sc_cli::Result<()> {
+    let Some(database_path) = config.database.path() else {
+        println!("In-memory database, nothing to migrate");
+        return Ok(());
+    };
+
+    let db = match SubcoinDb::open_read_only(&subcoin_service::subcoin_db_path(database_path)) {
+        Ok(db) => db,
+        Err(subcoin_db::Error::NotFound) => {
+            println!("No subcoin database yet, nothing to migrate");
+            return Ok(());
+        }
+        Err(err) => return Err(sc_cli::Error::Application(Box::new(err))),
+    };
+
+    let pending = migrator(db)
+        .pending()
+        .map_err(|err| sc_cli::Error::Application(Box::new(err)))?;
+
+    if pending.is_empty() {
+        println!("Subcoin database is up to date");
+    } else {
+        println!("{} pending migration(s):", pending.len());
+        for migration in pending {
+            println!("  {migration}");
+        }
+    }
+
+    Ok(())
+}
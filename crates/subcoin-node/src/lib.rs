@@ -1,14 +1,22 @@
 //! Subcoin Node Library.
 //!
 //! The main feature of this library is to start and run the node as a CLI application.
+//! [`SubcoinNodeBuilder`] can be used to embed a subcoin node in another binary.
 
+mod builder;
 mod cli;
 mod commands;
+mod db_migration;
+mod light;
+mod logging;
+mod read_only;
 mod rpc;
 mod substrate_cli;
+mod telemetry;
 mod transaction_pool;
 mod utils;
 
+pub use self::builder::{SubcoinNode, SubcoinNodeBuilder};
 pub use self::cli::run;
 
 #[cfg(test)]
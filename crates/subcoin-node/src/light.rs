@@ -0,0 +1,89 @@
+//! Light client syncing and verifying the Bitcoin headers only.
+//!
+//! No block is downloaded or imported, hence no UTXO set is maintained. The header chain is
+//! served over RPC along with the verification of the merkle inclusion proofs, e.g., for a
+//! bridge checking the Bitcoin transactions it relays.
+
+use sc_consensus_nakamoto::BlockExecutionStrategy;
+use sc_service::error::Error as ServiceError;
+use sc_service::{Configuration, TaskManager};
+use subcoin_network::SyncStrategy;
+use subcoin_primitives::LightChain;
+use subcoin_rpc::auth::RpcAuth;
+use subcoin_runtime::interface::OpaqueBlock as Block;
+use subcoin_service::ExecutorKind;
+
+/// Starts the light client, returns the task manager to keep it running.
+pub(crate) fn start_light_node(
+    config: Configuration,
+    executor: ExecutorKind,
+    mut network_params: subcoin_network::Params,
+    no_hardware_benchmarks: bool,
+    storage_monitor: sc_storage_monitor::StorageMonitorParams,
+    rpc_auth: Option<RpcAuth>,
+) -> Result<TaskManager, ServiceError> {
+    let subcoin_service::NodeComponents {
+        client,
+        mut task_manager,
+        subcoin_db,
+        verification_threads,
+        ..
+    } = subcoin_service::new_node(subcoin_service::SubcoinConfiguration {
+        network: network_params.network,
+        config: &config,
+        block_execution_strategy: BlockExecutionStrategy::runtime_disk(),
+        no_hardware_benchmarks,
+        storage_monitor,
+        in_memory_backend: Default::default(),
+        executor,
+    })?;
+
+    let tip = LightChain::<Block, _>::new(client.clone()).tip();
+
+    tracing::info!(
+        "🪶 Light client at #{},{}",
+        tip.height,
+        tip.header.block_hash()
+    );
+
+    network_params.sync_strategy = SyncStrategy::HeadersOnly;
+    network_params.snapshot = None;
+    network_params.stop_at = None;
+    network_params.db.replace(subcoin_db);
+    network_params
+        .verification_threads
+        .get_or_insert(verification_threads);
+
+    let import_queue =
+        sc_consensus_nakamoto::disabled_import_queue(&task_manager.spawn_essential_handle());
+
+    let (subcoin_networking, _network_handle) = subcoin_network::Network::new(
+        client.clone(),
+        network_params,
+        import_queue,
+        task_manager.spawn_handle(),
+        config.prometheus_registry().cloned(),
+    );
+
+    task_manager
+        .spawn_essential_handle()
+        .spawn_blocking("subcoin-networking", None, async move {
+            if let Err(err) = subcoin_networking.run().await {
+                tracing::error!(?err, "Error occurred in subcoin networking");
+            }
+        });
+
+    let gen_rpc_module =
+        |_deny_unsafe: sc_rpc::DenyUnsafe| crate::rpc::gen_light_rpc_module(client.clone());
+
+    if let Some(rpc_auth) = rpc_auth {
+        let rpc =
+            crate::builder::start_authenticated_rpc_server(&config, gen_rpc_module, rpc_auth)?;
+        task_manager.keep_alive((config.base_path.clone(), rpc));
+    } else {
+        let rpc = sc_service::start_rpc_servers(&config, gen_rpc_module, None)?;
+        task_manager.keep_alive((config.base_path.clone(), rpc));
+    }
+
+    Ok(task_manager)
+}
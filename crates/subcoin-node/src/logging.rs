@@ -0,0 +1,74 @@
+use sc_cli::{CliConfiguration, LoggerBuilder, SharedParams};
+use tracing_subscriber::EnvFilter;
+
+const IMPORT_RECORD_TARGET: &str = "subcoin::import";
+
+/// Format of the log output.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One JSON object per line, including the fields of the structured records.
+    ///
+    /// The block import records under the `subcoin::import` target are enabled by default.
+    Json,
+}
+
+/// Only provides the logging options, for reusing the default logger initialization of
+/// [`CliConfiguration`].
+struct LoggingConfiguration<'a>(&'a SharedParams);
+
+impl CliConfiguration for LoggingConfiguration<'_> {
+    fn shared_params(&self) -> &SharedParams {
+        self.0
+    }
+}
+
+/// Initializes the logger in the specified format, used in place of
+/// [`CliConfiguration::init`].
+pub(crate) fn init_logger<F>(
+    shared_params: &SharedParams,
+    log_format: LogFormat,
+    support_url: &str,
+    impl_version: &str,
+    logger_hook: F,
+) -> sc_cli::Result<()>
+where
+    F: FnOnce(&mut LoggerBuilder),
+{
+    match log_format {
+        LogFormat::Text => LoggingConfiguration(shared_params).init(
+            &support_url.to_owned(),
+            &impl_version.to_owned(),
+            logger_hook,
+        ),
+        LogFormat::Json => {
+            sp_panic_handler::set(support_url, impl_version);
+
+            // All targets log `info` by default like the text logger, except that the block
+            // import records are enabled.
+            let directives = format!(
+                "info,{IMPORT_RECORD_TARGET}=debug,{}",
+                shared_params.log_filters()?
+            );
+            let env_filter = EnvFilter::try_new(directives)
+                .map_err(|err| sc_cli::Error::Input(format!("Invalid log filter: {err}")))?;
+
+            tracing_subscriber::fmt()
+                .json()
+                .flatten_event(true)
+                .with_current_span(false)
+                .with_env_filter(env_filter)
+                .with_writer(std::io::stderr)
+                .try_init()
+                .map_err(sc_cli::Error::Application)?;
+
+            if let Err(err) = fdlimit::raise_fd_limit() {
+                tracing::warn!("Failed to raise the file descriptor limit: {err}");
+            }
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Read-only instance serving the query RPCs from the database of another node.
+//!
+//! Neither the networking nor the block import is running, the chain is served as it was in
+//! the database at startup. Multiple instances can be pointed at the snapshots of the same
+//! database to scale out the query load.
+
+use sc_client_api::UsageProvider;
+use sc_service::error::Error as ServiceError;
+use sc_service::{Configuration, PartialComponents, TaskManager};
+use std::path::Path;
+use subcoin_db::SubcoinDb;
+use subcoin_rpc::auth::RpcAuth;
+use subcoin_service::ExecutorKind;
+
+/// Starts the read-only instance, returns the task manager to keep it running.
+///
+/// The index RPCs are only served if the subcoin database of the node exists.
+pub(crate) fn start_read_only_node(
+    mut config: Configuration,
+    executor: ExecutorKind,
+    network: bitcoin::Network,
+    rpc_auth: Option<RpcAuth>,
+) -> Result<TaskManager, ServiceError> {
+    let database_path = config.database.path().map(Path::to_path_buf);
+
+    subcoin_service::use_read_only_database(&mut config)?;
+
+    let PartialComponents {
+        client,
+        mut task_manager,
+        ..
+    } = subcoin_service::new_partial(&config, executor)?;
+
+    let chain_info = client.usage_info().chain;
+
+    tracing::info!(
+        "📖 Serving the chain read-only at #{},{}",
+        chain_info.best_number,
+        chain_info.best_hash
+    );
+
+    let subcoin_db = database_path
+        .map(|db_path| subcoin_service::subcoin_db_path(&db_path))
+        .filter(|db_path| db_path.exists())
+        .map(|db_path| SubcoinDb::open_read_only(&db_path))
+        .transpose()
+        .map_err(|err| ServiceError::Application(err.into()))?;
+
+    let gen_rpc_module = |deny_unsafe: sc_rpc::DenyUnsafe| {
+        crate::rpc::gen_read_only_rpc_module(
+            client.clone(),
+            task_manager.spawn_handle(),
+            deny_unsafe,
+            network,
+            subcoin_db.clone(),
+        )
+    };
+
+    if let Some(rpc_auth) = rpc_auth {
+        let rpc =
+            crate::builder::start_authenticated_rpc_server(&config, gen_rpc_module, rpc_auth)?;
+        task_manager.keep_alive((config.base_path.clone(), rpc));
+    } else {
+        let rpc = sc_service::start_rpc_servers(&config, gen_rpc_module, None)?;
+        task_manager.keep_alive((config.base_path.clone(), rpc));
+    }
+
+    Ok(task_manager)
+}
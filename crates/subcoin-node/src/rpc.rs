@@ -1,26 +1,57 @@
 use jsonrpsee::RpcModule;
+use sc_consensus_nakamoto::{LocalBlockImport, StateRootAudit};
 use sc_service::SpawnTaskHandle;
 use sc_utils::mpsc::TracingUnboundedSender;
+use sp_runtime::traits::Block as BlockT;
 use std::sync::Arc;
+use subcoin_db::SubcoinDb;
 use subcoin_network::NetworkHandle;
+use subcoin_primitives::{BlockPruning, FinalityOracle};
+use subcoin_rpc::fee_estimation::FeeEstimator;
 use subcoin_runtime::interface::OpaqueBlock;
-use subcoin_service::FullClient;
+use subcoin_service::{FullBackend, FullClient, JobManager};
 use substrate_frame_rpc_system::{System as FrameSystem, SystemApiServer as _};
 
 /// Instantiate all full RPC extensions.
+#[allow(clippy::too_many_arguments)]
 pub fn gen_rpc_module(
     system_info: sc_rpc::system::SystemInfo,
     client: Arc<FullClient>,
+    backend: Arc<FullBackend>,
     spawn_handle: SpawnTaskHandle,
     system_rpc_tx: TracingUnboundedSender<sc_rpc::system::Request<OpaqueBlock>>,
     deny_unsafe: sc_rpc::DenyUnsafe,
     network_handle: NetworkHandle,
+    local_block_import: LocalBlockImport,
+    network: bitcoin::Network,
+    fee_estimator: FeeEstimator,
+    wallet: Option<subcoin_wallet::Wallet<OpaqueBlock, FullClient, FullBackend>>,
+    block_pruning: Option<BlockPruning>,
+    chain_stats_db: Option<SubcoinDb>,
+    op_return_db: Option<SubcoinDb>,
+    silent_payments_db: Option<SubcoinDb>,
+    tx_index_db: Option<SubcoinDb>,
+    confirmation_depth: Option<u32>,
+    finality_oracle: Option<FinalityOracle>,
+    state_root_audit: Option<StateRootAudit<<OpaqueBlock as BlockT>::Hash>>,
+    job_manager: JobManager<FullClient>,
 ) -> Result<RpcModule<()>, sc_service::Error> {
     use sc_rpc::chain::ChainApiServer;
     use sc_rpc::state::{ChildStateApiServer, StateApiServer};
     use sc_rpc::system::SystemApiServer;
     use subcoin_rpc::blockchain::{Blockchain, BlockchainApiServer};
+    use subcoin_rpc::fee_estimation::{FeeEstimation, FeeEstimationApiServer};
+    use subcoin_rpc::jobs::{Jobs, JobsApiServer};
+    use subcoin_rpc::mining::{Mining, MiningApiServer};
+    use subcoin_rpc::op_return::{OpReturn, OpReturnApiServer};
+    use subcoin_rpc::raw_transactions::{RawTransactions, RawTransactionsApiServer};
+    use subcoin_rpc::scan::{Scan, ScanApiServer};
+    use subcoin_rpc::silent_payments::{SilentPayments, SilentPaymentsApiServer};
+    use subcoin_rpc::state_root_audit::{StateRootAuditApiServer, StateRootAuditRpc};
+    use subcoin_rpc::stats::{Stats, StatsApiServer};
     use subcoin_rpc::subcoin::{Subcoin, SubcoinApiServer};
+    use subcoin_rpc::utxo_proof::{UtxoProofApiServer, UtxoProofs};
+    use subcoin_rpc::wallet::{Wallet, WalletApiServer};
 
     let mut module = RpcModule::new(());
 
@@ -48,12 +79,163 @@ pub fn gen_rpc_module(
     // module.merge(frame_system).map_err(into_service_error)?;
 
     // Subcoin RPCs.
-    let blockchain =
-        Blockchain::<_, _, subcoin_service::TransactionAdapter>::new(client.clone()).into_rpc();
-    let subcoin = Subcoin::new(client.clone(), network_handle).into_rpc();
+    let blockchain = Blockchain::<_, _, FullBackend, subcoin_service::TransactionAdapter>::new(
+        client.clone(),
+        network,
+        Arc::new(subcoin_service::CoinStorageKey),
+        block_pruning,
+    )
+    .into_rpc();
+    let subcoin = Subcoin::<_, _, _, subcoin_service::TransactionAdapter>::new(
+        client.clone(),
+        backend,
+        network_handle.clone(),
+        tx_index_db,
+    )
+    .with_confirmation_depth(confirmation_depth)
+    .with_finality_oracle(finality_oracle)
+    .with_fee_estimator(fee_estimator.clone())
+    .into_rpc();
+    let raw_transactions = RawTransactions::<_, _, FullBackend>::new(
+        client.clone(),
+        network,
+        Arc::new(subcoin_service::CoinStorageKey),
+        network_handle.clone(),
+    )
+    .into_rpc();
+    let mining = Mining::<_, _, FullBackend>::new(
+        client.clone(),
+        network,
+        Arc::new(subcoin_service::CoinStorageKey),
+        network_handle.clone(),
+        local_block_import,
+    )
+    .into_rpc();
+    let scan = Scan::<_, _, FullBackend>::new(
+        client.clone(),
+        network,
+        Arc::new(subcoin_service::CoinStorageKey),
+    )
+    .into_rpc();
+    let fee_estimation = FeeEstimation::<OpaqueBlock>::new(fee_estimator.clone()).into_rpc();
+    let utxo_proofs = UtxoProofs::<OpaqueBlock, _>::new(client.clone()).into_rpc();
 
     module.merge(blockchain).map_err(into_service_error)?;
     module.merge(subcoin).map_err(into_service_error)?;
+    module.merge(raw_transactions).map_err(into_service_error)?;
+    module.merge(mining).map_err(into_service_error)?;
+    module.merge(scan).map_err(into_service_error)?;
+    module.merge(fee_estimation).map_err(into_service_error)?;
+    module.merge(utxo_proofs).map_err(into_service_error)?;
+    module
+        .merge(Jobs::new(job_manager).into_rpc())
+        .map_err(into_service_error)?;
+
+    if let Some(db) = chain_stats_db {
+        module
+            .merge(Stats::new(db).into_rpc())
+            .map_err(into_service_error)?;
+    }
+
+    if let Some(db) = op_return_db {
+        module
+            .merge(OpReturn::new(db).into_rpc())
+            .map_err(into_service_error)?;
+    }
+
+    if let Some(db) = silent_payments_db {
+        module
+            .merge(SilentPayments::<OpaqueBlock, _>::new(client.clone(), db).into_rpc())
+            .map_err(into_service_error)?;
+    }
+
+    if let Some(audit) = state_root_audit {
+        module
+            .merge(StateRootAuditRpc::new(audit).into_rpc())
+            .map_err(into_service_error)?;
+    }
+
+    if let Some(wallet) = wallet {
+        let wallet = Wallet::<_, _, _, subcoin_service::TransactionAdapter>::new(
+            wallet,
+            client,
+            network,
+            Arc::new(subcoin_service::CoinStorageKey),
+            network_handle,
+            fee_estimator,
+        )
+        .into_rpc();
+        module.merge(wallet).map_err(into_service_error)?;
+    }
+
+    Ok(module)
+}
+
+/// Instantiate the query RPCs served by the read-only instance.
+///
+/// Only the RPCs reading the chain are available, the ones depending on the networking, the
+/// block import or the mempool are not.
+pub fn gen_read_only_rpc_module(
+    client: Arc<FullClient>,
+    spawn_handle: SpawnTaskHandle,
+    deny_unsafe: sc_rpc::DenyUnsafe,
+    network: bitcoin::Network,
+    chain_stats_db: Option<SubcoinDb>,
+) -> Result<RpcModule<()>, sc_service::Error> {
+    use sc_rpc::chain::ChainApiServer;
+    use sc_rpc::state::StateApiServer;
+    use subcoin_rpc::blockchain::{Blockchain, BlockchainApiServer};
+    use subcoin_rpc::scan::{Scan, ScanApiServer};
+    use subcoin_rpc::stats::{Stats, StatsApiServer};
+    use subcoin_rpc::utxo_proof::{UtxoProofApiServer, UtxoProofs};
+
+    let mut module = RpcModule::new(());
+
+    let task_executor = Arc::new(spawn_handle);
+
+    let chain = sc_rpc::chain::new_full(client.clone(), task_executor.clone()).into_rpc();
+    let (state, _child_state) = sc_rpc::state::new_full(client.clone(), task_executor, deny_unsafe);
+
+    let into_service_error =
+        |e: jsonrpsee::core::error::RegisterMethodError| sc_service::Error::Application(e.into());
+
+    module.merge(chain).map_err(into_service_error)?;
+    module.merge(state.into_rpc()).map_err(into_service_error)?;
+
+    let blockchain = Blockchain::<_, _, FullBackend, subcoin_service::TransactionAdapter>::new(
+        client.clone(),
+        network,
+        Arc::new(subcoin_service::CoinStorageKey),
+        None,
+    )
+    .into_rpc();
+    let utxo_proofs = UtxoProofs::<OpaqueBlock, _>::new(client.clone()).into_rpc();
+    let scan =
+        Scan::<_, _, FullBackend>::new(client, network, Arc::new(subcoin_service::CoinStorageKey))
+            .into_rpc();
+
+    module.merge(blockchain).map_err(into_service_error)?;
+    module.merge(scan).map_err(into_service_error)?;
+    module.merge(utxo_proofs).map_err(into_service_error)?;
+
+    if let Some(db) = chain_stats_db {
+        module
+            .merge(Stats::new(db).into_rpc())
+            .map_err(into_service_error)?;
+    }
+
+    Ok(module)
+}
+
+/// Instantiate the RPCs served by the light client.
+pub fn gen_light_rpc_module(client: Arc<FullClient>) -> Result<RpcModule<()>, sc_service::Error> {
+    use subcoin_rpc::light::{Light, LightApiServer};
+
+    let mut module = RpcModule::new(());
+
+    module
+        .merge(Light::<OpaqueBlock, _>::new(client).into_rpc())
+        .map_err(|e| sc_service::Error::Application(e.into()))?;
 
     Ok(module)
 }
@@ -0,0 +1,128 @@
+//! Bitcoin stats reported to the Substrate telemetry server.
+//!
+//! The stats are sent periodically as the custom `subcoin.bitcoin_stats` message alongside the
+//! standard Substrate telemetry, so that the dashboards can display the health of the subcoin
+//! network.
+
+use futures_timer::Delay;
+use sc_telemetry::{telemetry, TelemetryHandle, SUBSTRATE_INFO};
+use sp_blockchain::HeaderBackend;
+use std::sync::Arc;
+use std::time::Duration;
+use subcoin_db::SubcoinDb;
+use subcoin_network::{NetworkHandle, SyncStatus};
+use subcoin_primitives::BackendExt;
+use subcoin_runtime::interface::OpaqueBlock as Block;
+use subcoin_service::FullClient;
+use subcoin_snapshot::SnapshotStore;
+
+/// Default interval of reporting the Bitcoin stats.
+pub(crate) const DEFAULT_BITCOIN_TELEMETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Returns the progress of verifying the blocks up to the sync target, from 0 to 1.
+fn verification_progress(best_number: u32, sync_status: &SyncStatus) -> f64 {
+    match sync_status {
+        SyncStatus::Idle => 1.0,
+        SyncStatus::Downloading { target, .. } | SyncStatus::Importing { target, .. } => {
+            if *target == 0 {
+                1.0
+            } else {
+                (f64::from(best_number) / f64::from(*target)).min(1.0)
+            }
+        }
+        SyncStatus::SnapshotSyncing { .. } => 0.0,
+    }
+}
+
+/// Sends the Bitcoin stats every `interval` until the Bitcoin networking is stopped.
+///
+/// The UTXO count is reported only with the chain stats index, i.e., `chain_stats_db` is
+/// `Some`, the snapshot only when the node serves the snapshots.
+pub(crate) async fn report_bitcoin_stats(
+    telemetry: TelemetryHandle,
+    client: Arc<FullClient>,
+    network_handle: NetworkHandle,
+    chain_stats_db: Option<SubcoinDb>,
+    snapshot_store: Option<Arc<dyn SnapshotStore>>,
+    interval: Duration,
+) {
+    let telemetry = Some(telemetry);
+
+    loop {
+        Delay::new(interval).await;
+
+        let Some(status) = network_handle.status().await else {
+            return;
+        };
+
+        let info = client.info();
+
+        let best_hash = BackendExt::<Block>::bitcoin_block_hash_for(&*client, info.best_hash);
+
+        let utxo_count = chain_stats_db.as_ref().and_then(|db| {
+            subcoin_indexer::chain_stats_tip(db)
+                .inspect_err(|err| tracing::debug!(?err, "Failed to read the chain stats"))
+                .ok()
+                .flatten()
+                .map(|stats| stats.utxo_count)
+        });
+
+        let snapshot = snapshot_store
+            .as_ref()
+            .and_then(|store| store.latest_snapshot());
+
+        telemetry!(
+            telemetry;
+            SUBSTRATE_INFO;
+            "subcoin.bitcoin_stats";
+            "height" => info.best_number,
+            "best_hash" => best_hash.map(|hash| hash.to_string()),
+            "peers" => status.num_connected_peers,
+            "utxo_count" => utxo_count,
+            "verification_progress" => verification_progress(info.best_number, &status.sync_status),
+            "snapshot_height" => snapshot.as_ref().map(|snapshot| snapshot.height),
+            "snapshot_hash" => snapshot.map(|snapshot| snapshot.block_hash.to_string()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verification_progress() {
+        assert_eq!(verification_progress(100, &SyncStatus::Idle), 1.0);
+        assert_eq!(
+            verification_progress(
+                100,
+                &SyncStatus::Downloading {
+                    target: 400,
+                    peers: vec![]
+                }
+            ),
+            0.25
+        );
+        assert_eq!(
+            verification_progress(
+                500,
+                &SyncStatus::Importing {
+                    target: 400,
+                    peers: vec![]
+                }
+            ),
+            1.0
+        );
+        assert_eq!(
+            verification_progress(
+                0,
+                &SyncStatus::SnapshotSyncing {
+                    target: None,
+                    downloaded_chunks: 0,
+                    total_chunks: 0
+                }
+            ),
+            0.0
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use bitcoin::{Block as BitcoinBlock, BlockHash};
+use codec::{Decode, Encode};
+
+/// Prefix of the aux-db key of the statistics of a block.
+const BLOCK_STATS_PREFIX: &[u8] = b"blockstats";
+
+/// Returns the aux-db key of the statistics of the Bitcoin block.
+pub fn block_stats_key(bitcoin_block_hash: BlockHash) -> Vec<u8> {
+    let mut key = BLOCK_STATS_PREFIX.to_vec();
+    key.extend_from_slice(bitcoin_block_hash.as_ref());
+    key
+}
+
+const WITNESS_SCALE_FACTOR: u64 = 4;
+
+/// Weights of the fee rate percentiles, in percent.
+const FEERATE_PERCENTILES: [u64; 5] = [10, 25, 50, 75, 90];
+
+/// Statistics of a block, same semantic with `getblockstats` in Bitcoin Core.
+///
+/// The coinbase transaction is excluded from the statistics except for `txs`, `outs` and
+/// `utxo_increase`. Amounts are in satoshis, fee rates in sat/vB and sizes in bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct BlockStats {
+    pub avg_fee: u64,
+    pub avg_fee_rate: u64,
+    pub avg_tx_size: u64,
+    /// Fee rates at the 10th, 25th, 50th, 75th and 90th percentile weight unit.
+    pub fee_rate_percentiles: [u64; 5],
+    pub ins: u64,
+    pub max_fee: u64,
+    pub max_fee_rate: u64,
+    pub max_tx_size: u64,
+    pub median_fee: u64,
+    pub median_tx_size: u64,
+    pub min_fee: u64,
+    pub min_fee_rate: u64,
+    pub min_tx_size: u64,
+    pub outs: u64,
+    pub subsidy: u64,
+    pub sw_total_size: u64,
+    pub sw_total_weight: u64,
+    pub sw_txs: u64,
+    pub total_out: u64,
+    pub total_size: u64,
+    pub total_weight: u64,
+    pub total_fee: u64,
+    pub txs: u64,
+    pub utxo_increase: i64,
+}
+
+impl BlockStats {
+    /// Computes the statistics of a block.
+    ///
+    /// `tx_fees` contains the fee of each transaction in the block except the coinbase.
+    pub fn compute(block: &BitcoinBlock, subsidy: u64, tx_fees: &[u64]) -> Self {
+        assert_eq!(
+            tx_fees.len() + 1,
+            block.txdata.len(),
+            "Fee of every non-coinbase transaction must be provided"
+        );
+
+        let mut stats = Self {
+            subsidy,
+            txs: block.txdata.len() as u64,
+            min_fee: u64::MAX,
+            min_fee_rate: u64::MAX,
+            min_tx_size: u64::MAX,
+            ..Default::default()
+        };
+
+        let mut fees = Vec::with_capacity(tx_fees.len());
+        let mut tx_sizes = Vec::with_capacity(tx_fees.len());
+        let mut fee_rates = Vec::with_capacity(tx_fees.len());
+
+        for (index, tx) in block.txdata.iter().enumerate() {
+            stats.outs += tx.output.len() as u64;
+
+            if index == 0 {
+                continue;
+            }
+
+            let fee = tx_fees[index - 1];
+            let size = tx.total_size() as u64;
+            let weight = tx.weight().to_wu();
+
+            stats.ins += tx.input.len() as u64;
+            stats.total_out += tx
+                .output
+                .iter()
+                .map(|output| output.value.to_sat())
+                .sum::<u64>();
+
+            stats.total_size += size;
+            stats.total_weight += weight;
+            stats.max_tx_size = stats.max_tx_size.max(size);
+            stats.min_tx_size = stats.min_tx_size.min(size);
+            tx_sizes.push(size);
+
+            if tx.input.iter().any(|input| !input.witness.is_empty()) {
+                stats.sw_txs += 1;
+                stats.sw_total_size += size;
+                stats.sw_total_weight += weight;
+            }
+
+            let fee_rate = if weight > 0 {
+                fee * WITNESS_SCALE_FACTOR / weight
+            } else {
+                0
+            };
+
+            stats.total_fee += fee;
+            stats.max_fee = stats.max_fee.max(fee);
+            stats.min_fee = stats.min_fee.min(fee);
+            stats.max_fee_rate = stats.max_fee_rate.max(fee_rate);
+            stats.min_fee_rate = stats.min_fee_rate.min(fee_rate);
+            fees.push(fee);
+            fee_rates.push((fee_rate, weight));
+        }
+
+        let non_coinbase_txs = tx_fees.len() as u64;
+
+        if non_coinbase_txs == 0 {
+            stats.min_fee = 0;
+            stats.min_fee_rate = 0;
+            stats.min_tx_size = 0;
+        } else {
+            stats.avg_fee = stats.total_fee / non_coinbase_txs;
+            stats.avg_tx_size = stats.total_size / non_coinbase_txs;
+        }
+
+        if stats.total_weight > 0 {
+            stats.avg_fee_rate = stats.total_fee * WITNESS_SCALE_FACTOR / stats.total_weight;
+        }
+
+        stats.median_fee = truncated_median(fees);
+        stats.median_tx_size = truncated_median(tx_sizes);
+        stats.fee_rate_percentiles = percentiles_by_weight(fee_rates, stats.total_weight);
+
+        stats.utxo_increase = stats.outs as i64 - stats.ins as i64;
+
+        stats
+    }
+}
+
+fn truncated_median(mut values: Vec<u64>) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+
+    values.sort_unstable();
+
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2
+    } else {
+        values[mid]
+    }
+}
+
+/// Returns the fee rates at [`FEERATE_PERCENTILES`] of the total weight.
+fn percentiles_by_weight(mut fee_rates: Vec<(u64, u64)>, total_weight: u64) -> [u64; 5] {
+    let mut result = [0u64; 5];
+
+    let Some(highest) = fee_rates.iter().map(|(fee_rate, _)| *fee_rate).max() else {
+        return result;
+    };
+
+    fee_rates.sort_unstable_by_key(|(fee_rate, _)| *fee_rate);
+
+    let mut next = 0;
+    let mut cumulative_weight = 0;
+
+    for (fee_rate, weight) in fee_rates {
+        cumulative_weight += weight;
+        // `cumulative_weight >= total_weight * percentile / 100`.
+        while next < FEERATE_PERCENTILES.len()
+            && cumulative_weight * 100 >= total_weight * FEERATE_PERCENTILES[next]
+        {
+            result[next] = fee_rate;
+            next += 1;
+        }
+    }
+
+    for value in result.iter_mut().skip(next) {
+        *value = highest;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncated_median() {
+        assert_eq!(truncated_median(vec![]), 0);
+        assert_eq!(truncated_median(vec![3, 1, 2]), 2);
+        assert_eq!(truncated_median(vec![4, 1, 3, 2]), 2);
+    }
+
+    #[test]
+    fn test_percentiles_by_weight() {
+        assert_eq!(percentiles_by_weight(vec![], 0), [0; 5]);
+
+        // A single heavy transaction dominates all the percentiles up to 75%.
+        let fee_rates = vec![(10, 100), (1, 800), (5, 100)];
+        assert_eq!(percentiles_by_weight(fee_rates, 1000), [1, 1, 1, 1, 5]);
+    }
+}
@@ -0,0 +1,1203 @@
+//! Output script descriptors (BIP-380) and output script classification.
+//!
+//! Shared by the wallet and the scanning RPCs, only the subset of the descriptor language
+//! needed by a watch-only wallet is supported:
+//!
+//! - `pkh(KEY)`, `wpkh(KEY)`, `sh(wpkh(KEY))`
+//! - `tr(KEY)`, key path spending only.
+//! - `multi(k,KEY,...)` and `sortedmulti(k,KEY,...)`, bare or wrapped in `sh()`, `wsh()` or
+//!   `sh(wsh())`.
+//! - `wsh(MINISCRIPT)` and `sh(wsh(MINISCRIPT))`, see [`Miniscript`].
+//!
+//! `KEY` is either a hex-encoded public key or an extended public key with an optional key
+//! origin and an unhardened derivation path, e.g. `[d34db33f/84'/0'/0']xpub.../0/*`.
+
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource, Xpub};
+use bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_16};
+use bitcoin::script::{Builder, Instruction};
+use bitcoin::secp256k1::{Secp256k1, VerifyOnly};
+use bitcoin::{Address, Network, PublicKey, Script, ScriptBuf, Weight, XOnlyPublicKey};
+use once_cell::sync::Lazy;
+use std::fmt;
+use std::str::FromStr;
+
+mod miniscript;
+
+pub use self::miniscript::{Miniscript, Policy};
+
+static SECP256K1: Lazy<Secp256k1<VerifyOnly>> = Lazy::new(Secp256k1::verification_only);
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_LENGTH: usize = 8;
+
+/// Maximum number of keys in a `multi()` descriptor, same as `OP_CHECKMULTISIG`.
+const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// Default end of the derivation range of a ranged descriptor, same as Bitcoin Core.
+pub const DEFAULT_RANGE_END: u32 = 999;
+
+/// Maximum number of scripts derived from a ranged descriptor.
+pub const MAX_RANGE_SIZE: u32 = 100_000;
+
+/// Descriptor error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid character in descriptor: {0:?}")]
+    InvalidCharacter(char),
+    #[error("Invalid checksum, expected {expected}, got {got}")]
+    InvalidChecksum { expected: String, got: String },
+    #[error("Unsupported descriptor: {0}")]
+    Unsupported(String),
+    #[error("Malformed descriptor: {0}")]
+    Malformed(String),
+    #[error("Invalid key {0}")]
+    InvalidKey(String),
+    #[error("Hardened derivation is not possible without private keys")]
+    HardenedDerivation,
+    #[error("Uncompressed keys are not allowed in segwit descriptors")]
+    UncompressedKey,
+    #[error("Invalid multisig threshold {threshold} for {keys} keys")]
+    InvalidThreshold { threshold: usize, keys: usize },
+    #[error("Invalid derivation range [{0}, {1}]")]
+    InvalidRange(u32, u32),
+    #[error("Invalid miniscript: {0}")]
+    InvalidMiniscript(String),
+    #[error(transparent)]
+    Bip32(#[from] bitcoin::bip32::Error),
+}
+
+/// Key expression in a descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescriptorKey {
+    /// Hex-encoded public key.
+    Single(PublicKey),
+    /// Hex-encoded x-only public key, only valid in `tr()`.
+    XOnly(XOnlyPublicKey),
+    /// Extended public key with the derivation path following it.
+    Extended {
+        /// Key origin `[fingerprint/path]`.
+        origin: Option<KeySource>,
+        xpub: Xpub,
+        path: Vec<ChildNumber>,
+        /// Whether the path ends with `/*`.
+        wildcard: bool,
+    },
+}
+
+impl DescriptorKey {
+    fn is_ranged(&self) -> bool {
+        matches!(self, Self::Extended { wildcard: true, .. })
+    }
+
+    /// Returns the public key at `index`, the index is ignored if the key is not ranged.
+    fn derive(&self, index: u32) -> Result<PublicKey, Error> {
+        match self {
+            Self::Single(pk) => Ok(*pk),
+            Self::XOnly(_) => Err(Error::InvalidKey(
+                "x-only keys are only allowed in tr()".to_string(),
+            )),
+            Self::Extended {
+                xpub,
+                path,
+                wildcard,
+                ..
+            } => {
+                let mut path = path.clone();
+                if *wildcard {
+                    path.push(ChildNumber::from_normal_idx(index)?);
+                }
+                let derived = xpub.derive_pub(&SECP256K1, &path)?;
+                Ok(PublicKey::new(derived.public_key))
+            }
+        }
+    }
+
+    fn derive_x_only(&self, index: u32) -> Result<XOnlyPublicKey, Error> {
+        match self {
+            Self::XOnly(key) => Ok(*key),
+            key => Ok(key.derive(index)?.inner.x_only_public_key().0),
+        }
+    }
+
+    /// Returns the full derivation path of the key at `index` from the master key.
+    ///
+    /// `None` for the non-extended keys as their origins are unknown.
+    fn key_source(&self, index: u32) -> Result<Option<KeySource>, Error> {
+        let Self::Extended {
+            origin,
+            xpub,
+            path,
+            wildcard,
+        } = self
+        else {
+            return Ok(None);
+        };
+
+        let (fingerprint, mut full_path) = match origin {
+            Some((fingerprint, origin_path)) => (*fingerprint, origin_path.to_u32_vec()),
+            None => (xpub.fingerprint(), Vec::new()),
+        };
+        full_path.extend(path.iter().map(|child| u32::from(*child)));
+        if *wildcard {
+            full_path.push(index);
+        }
+
+        let full_path = full_path
+            .into_iter()
+            .map(ChildNumber::from)
+            .collect::<DerivationPath>();
+
+        Ok(Some((fingerprint, full_path)))
+    }
+}
+
+impl fmt::Display for DescriptorKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Single(pk) => write!(f, "{pk}"),
+            Self::XOnly(key) => write!(f, "{key}"),
+            Self::Extended {
+                origin,
+                xpub,
+                path,
+                wildcard,
+            } => {
+                if let Some((fingerprint, origin_path)) = origin {
+                    write!(f, "[{fingerprint}")?;
+                    for child in origin_path {
+                        write!(f, "/{child}")?;
+                    }
+                    f.write_str("]")?;
+                }
+                write!(f, "{xpub}")?;
+                for child in path {
+                    write!(f, "/{child}")?;
+                }
+                if *wildcard {
+                    f.write_str("/*")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Fields of a PSBT input or output spending to a descriptor.
+#[derive(Debug, Clone, Default)]
+pub struct PsbtFields {
+    pub redeem_script: Option<ScriptBuf>,
+    pub witness_script: Option<ScriptBuf>,
+    pub bip32_derivation: Vec<(PublicKey, KeySource)>,
+    pub tap_internal_key: Option<(XOnlyPublicKey, Option<KeySource>)>,
+}
+
+/// Script wrapping a `multi()` expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiWrapper {
+    /// Bare multisig.
+    Bare,
+    /// `sh(multi())`
+    Sh,
+    /// `wsh(multi())`
+    Wsh,
+    /// `sh(wsh(multi()))`
+    ShWsh,
+}
+
+/// Parsed output descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Descriptor {
+    /// `pkh(KEY)`
+    Pkh(DescriptorKey),
+    /// `wpkh(KEY)`
+    Wpkh(DescriptorKey),
+    /// `sh(wpkh(KEY))`
+    ShWpkh(DescriptorKey),
+    /// `tr(KEY)`
+    Tr(DescriptorKey),
+    /// `multi(k,KEY,...)` or `sortedmulti(k,KEY,...)`.
+    Multi {
+        wrapper: MultiWrapper,
+        threshold: usize,
+        keys: Vec<DescriptorKey>,
+        sorted: bool,
+    },
+    /// `wsh(MINISCRIPT)`, or `sh(wsh(MINISCRIPT))` if nested.
+    Wsh {
+        nested: bool,
+        miniscript: Miniscript,
+    },
+}
+
+impl Descriptor {
+    /// Returns `true` if the descriptor contains a key with a `/*` derivation step.
+    pub fn is_ranged(&self) -> bool {
+        match self {
+            Self::Pkh(key) | Self::Wpkh(key) | Self::ShWpkh(key) | Self::Tr(key) => key.is_ranged(),
+            Self::Multi { keys, .. } => keys.iter().any(DescriptorKey::is_ranged),
+            Self::Wsh { miniscript, .. } => {
+                miniscript.keys().into_iter().any(DescriptorKey::is_ranged)
+            }
+        }
+    }
+
+    /// Returns the output script at the derivation `index`.
+    ///
+    /// The index is ignored if the descriptor is not ranged.
+    pub fn script_pubkey(&self, index: u32) -> Result<ScriptBuf, Error> {
+        let script = match self {
+            Self::Pkh(key) => ScriptBuf::new_p2pkh(&key.derive(index)?.pubkey_hash()),
+            Self::Wpkh(key) => ScriptBuf::new_p2wpkh(&wpubkey_hash(key, index)?),
+            Self::ShWpkh(key) => ScriptBuf::new_p2wpkh(&wpubkey_hash(key, index)?).to_p2sh(),
+            Self::Tr(key) => ScriptBuf::new_p2tr(&SECP256K1, key.derive_x_only(index)?, None),
+            Self::Multi { wrapper, .. } => {
+                let redeem_script = self.multisig_script(index)?;
+                match wrapper {
+                    MultiWrapper::Bare => redeem_script,
+                    MultiWrapper::Sh => redeem_script.to_p2sh(),
+                    MultiWrapper::Wsh => redeem_script.to_p2wsh(),
+                    MultiWrapper::ShWsh => redeem_script.to_p2wsh().to_p2sh(),
+                }
+            }
+            Self::Wsh { nested, miniscript } => {
+                let script_pubkey = miniscript.witness_script(index)?.to_p2wsh();
+                if *nested {
+                    script_pubkey.to_p2sh()
+                } else {
+                    script_pubkey
+                }
+            }
+        };
+
+        Ok(script)
+    }
+
+    /// Returns the inclusive derivation range, `None` if the descriptor is not ranged.
+    ///
+    /// `range` defaults to `[0, DEFAULT_RANGE_END]` and is ignored if the descriptor is not
+    /// ranged.
+    pub fn derivation_range(&self, range: Option<(u32, u32)>) -> Result<Option<(u32, u32)>, Error> {
+        if !self.is_ranged() {
+            return Ok(None);
+        }
+
+        let (start, end) = range.unwrap_or((0, DEFAULT_RANGE_END));
+        if start > end || end - start >= MAX_RANGE_SIZE {
+            return Err(Error::InvalidRange(start, end));
+        }
+
+        Ok(Some((start, end)))
+    }
+
+    /// Returns the output scripts within the derivation range along with their indices.
+    ///
+    /// See [`Self::derivation_range`] for `range`.
+    pub fn script_pubkeys(
+        &self,
+        range: Option<(u32, u32)>,
+    ) -> Result<Vec<(u32, ScriptBuf)>, Error> {
+        let (start, end) = self.derivation_range(range)?.unwrap_or((0, 0));
+
+        (start..=end)
+            .map(|index| Ok((index, self.script_pubkey(index)?)))
+            .collect()
+    }
+
+    /// Returns the addresses within the derivation range along with their indices.
+    ///
+    /// See [`Self::derivation_range`] for `range`, fails for the bare multisig as it has no
+    /// address.
+    pub fn addresses(
+        &self,
+        range: Option<(u32, u32)>,
+        network: Network,
+    ) -> Result<Vec<(u32, Address)>, Error> {
+        if matches!(
+            self,
+            Self::Multi {
+                wrapper: MultiWrapper::Bare,
+                ..
+            }
+        ) {
+            return Err(Error::Unsupported("address of bare multisig".to_string()));
+        }
+
+        Ok(self
+            .script_pubkeys(range)?
+            .into_iter()
+            .filter_map(|(index, script_pubkey)| {
+                Address::from_script(&script_pubkey, network)
+                    .ok()
+                    .map(|address| (index, address))
+            })
+            .collect())
+    }
+
+    /// Returns the multisig script at `index`.
+    fn multisig_script(&self, index: u32) -> Result<ScriptBuf, Error> {
+        let Self::Multi {
+            wrapper,
+            threshold,
+            keys,
+            sorted,
+        } = self
+        else {
+            return Err(Error::Unsupported("not a multisig descriptor".to_string()));
+        };
+
+        let mut pubkeys = keys
+            .iter()
+            .map(|key| key.derive(index))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if matches!(wrapper, MultiWrapper::Wsh | MultiWrapper::ShWsh)
+            && pubkeys.iter().any(|pk| !pk.compressed)
+        {
+            return Err(Error::UncompressedKey);
+        }
+
+        // BIP-67, lexicographical order of the serialized keys.
+        if *sorted {
+            pubkeys.sort_by_key(|pk| pk.to_bytes());
+        }
+
+        Ok(multisig_script(*threshold, &pubkeys))
+    }
+
+    /// Returns the PSBT fields needed by the signers to spend the output at `index`.
+    pub fn psbt_fields(&self, index: u32) -> Result<PsbtFields, Error> {
+        let mut fields = PsbtFields::default();
+
+        let mut key_sources = |keys: &[DescriptorKey]| -> Result<(), Error> {
+            for key in keys {
+                if let Some(key_source) = key.key_source(index)? {
+                    fields
+                        .bip32_derivation
+                        .push((key.derive(index)?, key_source));
+                }
+            }
+            Ok(())
+        };
+
+        match self {
+            Self::Pkh(key) | Self::Wpkh(key) => key_sources(std::slice::from_ref(key))?,
+            Self::ShWpkh(key) => {
+                key_sources(std::slice::from_ref(key))?;
+                fields.redeem_script = Some(ScriptBuf::new_p2wpkh(&wpubkey_hash(key, index)?));
+            }
+            Self::Tr(key) => {
+                fields.tap_internal_key = Some((key.derive_x_only(index)?, key.key_source(index)?));
+            }
+            Self::Multi { wrapper, keys, .. } => {
+                key_sources(keys)?;
+                let multisig_script = self.multisig_script(index)?;
+                match wrapper {
+                    MultiWrapper::Bare => {}
+                    MultiWrapper::Sh => fields.redeem_script = Some(multisig_script),
+                    MultiWrapper::Wsh => fields.witness_script = Some(multisig_script),
+                    MultiWrapper::ShWsh => {
+                        fields.redeem_script = Some(multisig_script.to_p2wsh());
+                        fields.witness_script = Some(multisig_script);
+                    }
+                }
+            }
+            Self::Wsh { nested, miniscript } => {
+                let keys = miniscript.keys().into_iter().cloned().collect::<Vec<_>>();
+                key_sources(&keys)?;
+                let witness_script = miniscript.witness_script(index)?;
+                if *nested {
+                    fields.redeem_script = Some(witness_script.to_p2wsh());
+                }
+                fields.witness_script = Some(witness_script);
+            }
+        }
+
+        Ok(fields)
+    }
+
+    /// Returns whether spending the outputs of this descriptor requires the witness data.
+    pub fn is_segwit(&self) -> bool {
+        match self {
+            Self::Pkh(_) => false,
+            Self::Wpkh(_) | Self::ShWpkh(_) | Self::Tr(_) | Self::Wsh { .. } => true,
+            Self::Multi { wrapper, .. } => {
+                matches!(wrapper, MultiWrapper::Wsh | MultiWrapper::ShWsh)
+            }
+        }
+    }
+
+    /// Returns the maximum weight of a transaction input spending the output of this
+    /// descriptor, including the outpoint, sequence and the satisfaction.
+    ///
+    /// Compressed keys and 72-byte ECDSA signatures are assumed.
+    pub fn max_satisfaction_weight(&self) -> Weight {
+        // Outpoint (36) + sequence (4).
+        const INPUT_BASE_SIZE: usize = 40;
+        const ECDSA_SIG_PUSH_SIZE: usize = 1 + 72;
+        const PUBKEY_PUSH_SIZE: usize = 1 + 33;
+
+        let weight = |script_sig_size: usize, witness_size: usize| {
+            let base_size = INPUT_BASE_SIZE + varint_size(script_sig_size) + script_sig_size;
+            Weight::from_wu((base_size * 4 + witness_size) as u64)
+        };
+
+        let input_weight = |script_sig_size: usize, witness_items: &[usize]| {
+            let witness_size = if witness_items.is_empty() {
+                0
+            } else {
+                varint_size(witness_items.len())
+                    + witness_items
+                        .iter()
+                        .map(|item| varint_size(*item) + item)
+                        .sum::<usize>()
+            };
+            weight(script_sig_size, witness_size)
+        };
+
+        match self {
+            Self::Pkh(_) => input_weight(ECDSA_SIG_PUSH_SIZE + PUBKEY_PUSH_SIZE, &[]),
+            Self::Wpkh(_) => input_weight(0, &[72, 33]),
+            // Push of the 22-byte P2WPKH redeem script.
+            Self::ShWpkh(_) => input_weight(1 + 22, &[72, 33]),
+            // Schnorr signature with a non-default sighash type.
+            Self::Tr(_) => input_weight(0, &[65]),
+            Self::Multi {
+                wrapper,
+                threshold,
+                keys,
+                ..
+            } => {
+                let script_size = 3 + keys.len() * PUBKEY_PUSH_SIZE;
+                let sigs_size = threshold * ECDSA_SIG_PUSH_SIZE;
+                let mut witness = vec![0; threshold + 2];
+                witness[1..=*threshold].fill(72);
+                witness[threshold + 1] = script_size;
+
+                match wrapper {
+                    // OP_0 due to the off-by-one bug of OP_CHECKMULTISIG.
+                    MultiWrapper::Bare => input_weight(1 + sigs_size, &[]),
+                    MultiWrapper::Sh => {
+                        input_weight(1 + sigs_size + push_size(script_size) + script_size, &[])
+                    }
+                    MultiWrapper::Wsh => input_weight(0, &witness),
+                    // Push of the 34-byte P2WSH redeem script.
+                    MultiWrapper::ShWsh => input_weight(1 + 34, &witness),
+                }
+            }
+            Self::Wsh { nested, miniscript } => {
+                let (items, satisfaction_size) = miniscript.max_satisfaction_size();
+                let script_size = miniscript.script_size();
+                let witness_size = varint_size(items + 1)
+                    + satisfaction_size
+                    + varint_size(script_size)
+                    + script_size;
+                weight(if *nested { 1 + 34 } else { 0 }, witness_size)
+            }
+        }
+    }
+
+    /// Returns the spending policy.
+    pub fn policy(&self) -> Policy {
+        let key = |key: &DescriptorKey| Policy::Key(key.to_string());
+
+        match self {
+            Self::Pkh(k) | Self::Wpkh(k) | Self::ShWpkh(k) | Self::Tr(k) => key(k),
+            Self::Multi {
+                threshold, keys, ..
+            } => Policy::Thresh(*threshold, keys.iter().map(key).collect()).normalized(),
+            Self::Wsh { miniscript, .. } => miniscript.policy(),
+        }
+    }
+}
+
+/// Type of an output script, named as in Bitcoin Core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    PubKey,
+    PubKeyHash,
+    ScriptHash,
+    Multisig,
+    NullData,
+    WitnessV0KeyHash,
+    WitnessV0ScriptHash,
+    WitnessV1Taproot,
+    /// Witness program of an unknown version or length.
+    WitnessUnknown,
+    NonStandard,
+}
+
+impl ScriptType {
+    /// Classifies the output script.
+    pub fn from_script(script: &Script) -> Self {
+        if script.is_p2pkh() {
+            Self::PubKeyHash
+        } else if script.is_p2sh() {
+            Self::ScriptHash
+        } else if script.is_p2wpkh() {
+            Self::WitnessV0KeyHash
+        } else if script.is_p2wsh() {
+            Self::WitnessV0ScriptHash
+        } else if script.is_p2tr() {
+            Self::WitnessV1Taproot
+        } else if script.is_witness_program() {
+            Self::WitnessUnknown
+        } else if script.is_p2pk() {
+            Self::PubKey
+        } else if script.is_op_return() {
+            Self::NullData
+        } else if parse_multisig(script).is_some() {
+            Self::Multisig
+        } else {
+            Self::NonStandard
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PubKey => "pubkey",
+            Self::PubKeyHash => "pubkeyhash",
+            Self::ScriptHash => "scripthash",
+            Self::Multisig => "multisig",
+            Self::NullData => "nulldata",
+            Self::WitnessV0KeyHash => "witness_v0_keyhash",
+            Self::WitnessV0ScriptHash => "witness_v0_scripthash",
+            Self::WitnessV1Taproot => "witness_v1_taproot",
+            Self::WitnessUnknown => "witness_unknown",
+            Self::NonStandard => "nonstandard",
+        }
+    }
+}
+
+impl fmt::Display for ScriptType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parses a bare multisig script, returns the threshold and the keys.
+pub fn parse_multisig(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+
+    let pushnum = |instruction: &Instruction| match instruction {
+        Instruction::Op(op)
+            if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) =>
+        {
+            Some((op.to_u8() - OP_PUSHNUM_1.to_u8() + 1) as usize)
+        }
+        _ => None,
+    };
+
+    let (first, rest) = instructions.split_first()?;
+    let (last, rest) = rest.split_last()?;
+    let (n, key_pushes) = rest.split_last()?;
+
+    if !matches!(last, Instruction::Op(op) if *op == OP_CHECKMULTISIG) {
+        return None;
+    }
+
+    let threshold = pushnum(first)?;
+
+    let keys = key_pushes
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::PushBytes(bytes) => PublicKey::from_slice(bytes.as_bytes()).ok(),
+            Instruction::Op(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    if pushnum(n)? != keys.len() || threshold > keys.len() {
+        return None;
+    }
+
+    Some((threshold, keys))
+}
+
+/// Size of the compact size encoding of `n`.
+fn varint_size(n: usize) -> usize {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffffffff => 5,
+        _ => 9,
+    }
+}
+
+/// Size of the push opcodes in front of the data of `len` bytes.
+fn push_size(len: usize) -> usize {
+    match len {
+        0..=75 => 1,
+        76..=0xff => 2,
+        _ => 3,
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = Error;
+
+    /// Parses a descriptor, the checksum is verified if present.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let desc = match s.split_once('#') {
+            Some((desc, checksum)) => {
+                let expected = descriptor_checksum(desc)?;
+                if expected != checksum {
+                    return Err(Error::InvalidChecksum {
+                        expected,
+                        got: checksum.to_string(),
+                    });
+                }
+                desc
+            }
+            None => {
+                // Validate the character set.
+                descriptor_checksum(s)?;
+                s
+            }
+        };
+
+        let (name, args) = split_function(desc)?;
+
+        match name {
+            "pkh" => Ok(Self::Pkh(parse_key(args)?)),
+            "wpkh" => Ok(Self::Wpkh(parse_key(args)?)),
+            "tr" => {
+                if args.contains(',') {
+                    return Err(Error::Unsupported("tr() with script tree".to_string()));
+                }
+                Ok(Self::Tr(parse_tr_key(args)?))
+            }
+            "multi" | "sortedmulti" => parse_multi(MultiWrapper::Bare, name, args),
+            "sh" => {
+                let (inner, inner_args) = split_function(args)?;
+                match inner {
+                    "wpkh" => Ok(Self::ShWpkh(parse_key(inner_args)?)),
+                    "multi" | "sortedmulti" => parse_multi(MultiWrapper::Sh, inner, inner_args),
+                    "wsh" => parse_wsh(true, inner_args),
+                    other => Err(Error::Unsupported(format!("sh({other}())"))),
+                }
+            }
+            "wsh" => parse_wsh(false, args),
+            other => Err(Error::Unsupported(format!("{other}()"))),
+        }
+    }
+}
+
+/// Computes the checksum of a descriptor without checksum.
+pub fn descriptor_checksum(desc: &str) -> Result<String, Error> {
+    fn polymod(c: u64, value: u64) -> u64 {
+        let top = c >> 35;
+        let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+        if top & 1 != 0 {
+            c ^= 0xf5dee51989;
+        }
+        if top & 2 != 0 {
+            c ^= 0xa9fdca3312;
+        }
+        if top & 4 != 0 {
+            c ^= 0x1bab10e32d;
+        }
+        if top & 8 != 0 {
+            c ^= 0x3706b1677a;
+        }
+        if top & 16 != 0 {
+            c ^= 0x644d626ffd;
+        }
+        c
+    }
+
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET.find(ch).ok_or(Error::InvalidCharacter(ch))? as u64;
+        c = polymod(c, pos & 31);
+        class = class * 3 + (pos >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+
+    for _ in 0..CHECKSUM_LENGTH {
+        c = polymod(c, 0);
+    }
+
+    c ^= 1;
+
+    let checksum = (0..CHECKSUM_LENGTH)
+        .map(|i| CHECKSUM_CHARSET[((c >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect();
+
+    Ok(checksum)
+}
+
+/// Returns the descriptor with the checksum appended, stripping the existing one if any.
+pub fn with_checksum(desc: &str) -> Result<String, Error> {
+    let desc = desc.split_once('#').map_or(desc, |(desc, _)| desc);
+    Ok(format!("{desc}#{}", descriptor_checksum(desc)?))
+}
+
+fn wpubkey_hash(key: &DescriptorKey, index: u32) -> Result<bitcoin::WPubkeyHash, Error> {
+    key.derive(index)?
+        .wpubkey_hash()
+        .map_err(|_| Error::UncompressedKey)
+}
+
+fn multisig_script(threshold: usize, pubkeys: &[PublicKey]) -> ScriptBuf {
+    let builder = pubkeys
+        .iter()
+        .fold(Builder::new().push_int(threshold as i64), |builder, pk| {
+            builder.push_key(pk)
+        });
+
+    builder
+        .push_int(pubkeys.len() as i64)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script()
+}
+
+/// Splits `name(args)` into `name` and `args`.
+fn split_function(s: &str) -> Result<(&str, &str), Error> {
+    let open = s
+        .find('(')
+        .ok_or_else(|| Error::Malformed(format!("missing '(' in {s}")))?;
+    let args = s[open + 1..]
+        .strip_suffix(')')
+        .ok_or_else(|| Error::Malformed(format!("missing ')' in {s}")))?;
+    Ok((&s[..open], args))
+}
+
+fn parse_multi(wrapper: MultiWrapper, name: &str, args: &str) -> Result<Descriptor, Error> {
+    let sorted = match name {
+        "multi" => false,
+        "sortedmulti" => true,
+        other => return Err(Error::Unsupported(format!("{other}() in multisig context"))),
+    };
+
+    let mut args = args.split(',');
+
+    let threshold = args
+        .next()
+        .and_then(|k| k.parse::<usize>().ok())
+        .ok_or_else(|| Error::Malformed("invalid multisig threshold".to_string()))?;
+
+    let keys = args.map(parse_key).collect::<Result<Vec<_>, _>>()?;
+
+    if threshold == 0 || threshold > keys.len() || keys.len() > MAX_PUBKEYS_PER_MULTISIG {
+        return Err(Error::InvalidThreshold {
+            threshold,
+            keys: keys.len(),
+        });
+    }
+
+    Ok(Descriptor::Multi {
+        wrapper,
+        threshold,
+        keys,
+        sorted,
+    })
+}
+
+/// Parses the script inside `wsh()`, `multi()` and `sortedmulti()` are parsed as the multisig
+/// descriptors rather than miniscript.
+fn parse_wsh(nested: bool, args: &str) -> Result<Descriptor, Error> {
+    let wrapper = if nested {
+        MultiWrapper::ShWsh
+    } else {
+        MultiWrapper::Wsh
+    };
+
+    match split_function(args) {
+        Ok((name @ ("multi" | "sortedmulti"), multi_args)) => {
+            parse_multi(wrapper, name, multi_args)
+        }
+        _ => Ok(Descriptor::Wsh {
+            nested,
+            miniscript: args.parse()?,
+        }),
+    }
+}
+
+fn parse_tr_key(s: &str) -> Result<DescriptorKey, Error> {
+    let (_origin, key) = parse_origin(s)?;
+    if key.len() == 64 {
+        XOnlyPublicKey::from_str(key)
+            .map(DescriptorKey::XOnly)
+            .map_err(|_| Error::InvalidKey(key.to_string()))
+    } else {
+        parse_key(s)
+    }
+}
+
+fn parse_key(s: &str) -> Result<DescriptorKey, Error> {
+    let (origin, key) = parse_origin(s)?;
+
+    if key.len() == 66 || key.len() == 130 {
+        return PublicKey::from_str(key)
+            .map(DescriptorKey::Single)
+            .map_err(|_| Error::InvalidKey(key.to_string()));
+    }
+
+    let mut steps = key.split('/');
+
+    let xpub = steps
+        .next()
+        .and_then(|xpub| Xpub::from_str(xpub).ok())
+        .ok_or_else(|| Error::InvalidKey(key.to_string()))?;
+
+    let mut path = Vec::new();
+    let mut wildcard = false;
+
+    for step in steps {
+        if wildcard {
+            return Err(Error::Malformed(
+                "wildcard must be the last derivation step".to_string(),
+            ));
+        }
+
+        if step.ends_with(['\'', 'h', 'H']) {
+            return Err(Error::HardenedDerivation);
+        }
+
+        if step == "*" {
+            wildcard = true;
+        } else {
+            let index = step
+                .parse::<u32>()
+                .map_err(|_| Error::Malformed(format!("invalid derivation step {step}")))?;
+            path.push(ChildNumber::from_normal_idx(index)?);
+        }
+    }
+
+    Ok(DescriptorKey::Extended {
+        origin,
+        xpub,
+        path,
+        wildcard,
+    })
+}
+
+/// Parses the optional key origin `[fingerprint/path]`, returns the origin and the rest.
+fn parse_origin(s: &str) -> Result<(Option<KeySource>, &str), Error> {
+    let Some(rest) = s.strip_prefix('[') else {
+        return Ok((None, s));
+    };
+
+    let (origin, key) = rest
+        .split_once(']')
+        .ok_or_else(|| Error::Malformed(format!("unclosed key origin in {s}")))?;
+
+    let (fingerprint, path) = match origin.split_once('/') {
+        Some((fingerprint, path)) => (fingerprint, DerivationPath::from_str(&format!("m/{path}"))?),
+        None => (origin, DerivationPath::master()),
+    };
+
+    let fingerprint = Fingerprint::from_str(fingerprint)
+        .map_err(|_| Error::Malformed(format!("invalid key origin fingerprint in {s}")))?;
+
+    Ok((Some((fingerprint, path)), key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY1: &str = "022f8bde4d1a07209355b4a7250a5c5128e88b84bddc619ab7cba8d569b240efe4";
+    const KEY2: &str = "025cbdf0646e5db4eaa398f365f2ea7a0e3d419b7e0330e39ce92bddedcac4f9bc";
+
+    #[test]
+    fn test_descriptor_checksum() {
+        let cases = [
+            ("raw(deadbeef)", "89f8spxm"),
+            (
+                "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)",
+                "8zl0zxma",
+            ),
+            (
+                "pkh([d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*)",
+                "ml40v0wf",
+            ),
+            (
+                "tr(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+                "gxjkeue2",
+            ),
+        ];
+
+        for (desc, checksum) in cases {
+            assert_eq!(descriptor_checksum(desc).unwrap(), checksum);
+        }
+
+        assert_eq!(
+            with_checksum(&format!("sh(multi(2,{KEY1},{KEY2}))#xxxxxxxx")).unwrap(),
+            format!("sh(multi(2,{KEY1},{KEY2}))#0zm2ysl2")
+        );
+    }
+
+    #[test]
+    fn test_parse_descriptor() {
+        assert!(Descriptor::from_str(&format!("sh(multi(2,{KEY1},{KEY2}))#0zm2ysl2")).is_ok());
+        assert!(matches!(
+            Descriptor::from_str(&format!("sh(multi(2,{KEY1},{KEY2}))#0zm2ysl3")),
+            Err(Error::InvalidChecksum { .. })
+        ));
+        assert!(matches!(
+            Descriptor::from_str(&format!("multi(3,{KEY1},{KEY2})")),
+            Err(Error::InvalidThreshold { .. })
+        ));
+        assert!(matches!(
+            Descriptor::from_str("raw(deadbeef)"),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            Descriptor::from_str("wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1'/*)"),
+            Err(Error::HardenedDerivation)
+        ));
+    }
+
+    #[test]
+    fn test_script_pubkey() {
+        let wpkh = Descriptor::from_str(
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma",
+        )
+        .unwrap();
+        assert!(!wpkh.is_ranged());
+        assert_eq!(
+            wpkh.script_pubkey(0).unwrap().to_hex_string(),
+            "00147dd65592d0ab2fe0d0257d571abf032cd9db93dc"
+        );
+
+        let sh_multi = Descriptor::from_str(&format!("sh(multi(2,{KEY1},{KEY2}))")).unwrap();
+        assert_eq!(
+            sh_multi.script_pubkey(0).unwrap().to_hex_string(),
+            "a91421ed952d4b024761e9b61cbedbff0a0fd231024587"
+        );
+
+        // Reversed keys result in the same script once sorted.
+        let sorted = Descriptor::from_str(&format!("sh(sortedmulti(2,{KEY2},{KEY1}))")).unwrap();
+        assert_eq!(
+            sorted.script_pubkey(0).unwrap(),
+            sh_multi.script_pubkey(0).unwrap()
+        );
+        let unsorted = Descriptor::from_str(&format!("sh(multi(2,{KEY2},{KEY1}))")).unwrap();
+        assert_eq!(
+            unsorted.script_pubkey(0).unwrap().to_hex_string(),
+            "a91489a814c7942d32670f9e1ab117be59f6e35c65a987"
+        );
+
+        assert_eq!(wpkh.max_satisfaction_weight(), Weight::from_wu(272));
+        assert_eq!(
+            Descriptor::from_str(&format!("wsh(multi(2,{KEY1},{KEY2}))"))
+                .unwrap()
+                .max_satisfaction_weight(),
+            // 41 * 4 + witness of [<>, <sig>, <sig>, <2 KEY1 KEY2 2 OP_CHECKMULTISIG>].
+            Weight::from_wu(164 + 1 + 1 + 73 * 2 + 1 + 71)
+        );
+
+        let tr = Descriptor::from_str(
+            "tr(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        )
+        .unwrap();
+        assert!(tr.script_pubkey(0).unwrap().is_p2tr());
+    }
+
+    #[test]
+    fn test_ranged_derivation() {
+        // BIP-32 test vector 1, chain m/0H/1/2H/2 and its child m/0H/1/2H/2/1000000000.
+        let parent = "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV";
+        let child = "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy";
+
+        let ranged = Descriptor::from_str(&format!("pkh([d34db33f/0h/1/2h/2]{parent}/*)")).unwrap();
+        assert!(ranged.is_ranged());
+
+        let expected = ScriptBuf::new_p2pkh(
+            &PublicKey::new(Xpub::from_str(child).unwrap().public_key).pubkey_hash(),
+        );
+        assert_eq!(ranged.script_pubkey(1_000_000_000).unwrap(), expected);
+        assert_ne!(ranged.script_pubkey(0).unwrap(), expected);
+
+        let (_, key_source) = ranged.psbt_fields(5).unwrap().bip32_derivation[0].clone();
+        assert_eq!(
+            key_source,
+            (
+                Fingerprint::from_str("d34db33f").unwrap(),
+                DerivationPath::from_str("m/0h/1/2h/2/5").unwrap()
+            )
+        );
+
+        let fixed = Descriptor::from_str(&format!("pkh({parent}/1000000000)")).unwrap();
+        assert!(!fixed.is_ranged());
+        assert_eq!(fixed.script_pubkey(0).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_script_pubkeys() {
+        let parent = "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV";
+        let ranged = Descriptor::from_str(&format!("wpkh({parent}/*)")).unwrap();
+
+        assert_eq!(
+            ranged.derivation_range(None).unwrap(),
+            Some((0, DEFAULT_RANGE_END))
+        );
+        assert!(matches!(
+            ranged.derivation_range(Some((5, 4))),
+            Err(Error::InvalidRange(5, 4))
+        ));
+        assert!(matches!(
+            ranged.derivation_range(Some((0, MAX_RANGE_SIZE))),
+            Err(Error::InvalidRange(..))
+        ));
+
+        let scripts = ranged.script_pubkeys(Some((3, 5))).unwrap();
+        assert_eq!(
+            scripts.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(scripts[1].1, ranged.script_pubkey(4).unwrap());
+
+        let addresses = ranged.addresses(Some((3, 5)), Network::Bitcoin).unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[1].1.script_pubkey(), scripts[1].1);
+        assert!(Descriptor::from_str(&format!("multi(1,{KEY1})"))
+            .unwrap()
+            .addresses(None, Network::Bitcoin)
+            .is_err());
+
+        // The range is ignored for the non-ranged descriptors.
+        let single = Descriptor::from_str(&format!("wpkh({KEY1})")).unwrap();
+        assert_eq!(single.derivation_range(Some((5, 4))).unwrap(), None);
+        assert_eq!(single.script_pubkeys(Some((3, 5))).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_script_type() {
+        let key = PublicKey::from_str(KEY1).unwrap();
+        let multisig = multisig_script(1, &[key, PublicKey::from_str(KEY2).unwrap()]);
+
+        let cases = [
+            (ScriptBuf::new_p2pk(&key), ScriptType::PubKey),
+            (
+                ScriptBuf::new_p2pkh(&key.pubkey_hash()),
+                ScriptType::PubKeyHash,
+            ),
+            (multisig.to_p2sh(), ScriptType::ScriptHash),
+            (multisig.clone(), ScriptType::Multisig),
+            (ScriptBuf::new_op_return([1u8, 2]), ScriptType::NullData),
+            (
+                ScriptBuf::new_p2wpkh(&key.wpubkey_hash().unwrap()),
+                ScriptType::WitnessV0KeyHash,
+            ),
+            (multisig.to_p2wsh(), ScriptType::WitnessV0ScriptHash),
+            (
+                ScriptBuf::new_p2tr(&SECP256K1, key.inner.x_only_public_key().0, None),
+                ScriptType::WitnessV1Taproot,
+            ),
+            (
+                ScriptBuf::from_hex("52020001").unwrap(),
+                ScriptType::WitnessUnknown,
+            ),
+            (ScriptBuf::from_hex("51").unwrap(), ScriptType::NonStandard),
+        ];
+
+        for (script, script_type) in cases {
+            assert_eq!(ScriptType::from_script(&script), script_type, "{script}");
+        }
+    }
+
+    #[test]
+    fn test_parse_multisig() {
+        let descriptor = Descriptor::from_str(&format!("wsh(multi(1,{KEY1},{KEY2}))")).unwrap();
+        let witness_script = descriptor.psbt_fields(0).unwrap().witness_script.unwrap();
+
+        let (threshold, keys) = parse_multisig(&witness_script).unwrap();
+        assert_eq!(threshold, 1);
+        assert_eq!(
+            keys,
+            vec![
+                PublicKey::from_str(KEY1).unwrap(),
+                PublicKey::from_str(KEY2).unwrap()
+            ]
+        );
+
+        assert!(parse_multisig(&descriptor.script_pubkey(0).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_miniscript() {
+        use bitcoin::opcodes::all::{OP_CHECKSIGVERIFY, OP_CSV};
+
+        let descriptor =
+            Descriptor::from_str(&format!("wsh(and_v(v:pk({KEY1}),older(144)))")).unwrap();
+        let witness_script = Builder::new()
+            .push_key(&PublicKey::from_str(KEY1).unwrap())
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_int(144)
+            .push_opcode(OP_CSV)
+            .into_script();
+        assert_eq!(
+            descriptor.psbt_fields(0).unwrap().witness_script,
+            Some(witness_script.clone())
+        );
+        assert_eq!(
+            descriptor.script_pubkey(0).unwrap(),
+            witness_script.to_p2wsh()
+        );
+        assert_eq!(
+            descriptor.policy().to_string(),
+            format!("and(pk({KEY1}),older(144))")
+        );
+
+        // Key or the other key after the timelock.
+        let descriptor = Descriptor::from_str(&format!(
+            "sh(wsh(or_d(pk({KEY1}),and_v(v:pkh({KEY2}),older(144)))))"
+        ))
+        .unwrap();
+        assert!(descriptor.is_segwit());
+        assert!(descriptor.script_pubkey(0).unwrap().is_p2sh());
+        let policy = descriptor.policy();
+        assert_eq!(
+            policy.to_string(),
+            format!("or(pk({KEY1}),and(pk({KEY2}),older(144)))")
+        );
+        assert_eq!(policy.keys(), vec![KEY1, KEY2]);
+        assert_eq!(policy.relative_timelocks(), vec![144]);
+        assert!(policy.absolute_timelocks().is_empty());
+        assert_eq!(
+            descriptor.max_satisfaction_weight(),
+            // (41 + 35) * 4 + witness of [<sig>, <key>, <>, <67-byte script>].
+            Weight::from_wu(76 * 4 + 1 + 73 + 34 + 1 + 1 + 67)
+        );
+
+        let descriptor = Descriptor::from_str(&format!(
+            "wsh(thresh(2,pk({KEY1}),s:pk({KEY2}),sln:older(144)))"
+        ))
+        .unwrap();
+        assert_eq!(
+            descriptor.policy().to_string(),
+            format!("thresh(2,pk({KEY1}),pk({KEY2}),older(144))")
+        );
+
+        // Type errors.
+        for desc in [
+            format!("wsh(pk_k({KEY1}))"),
+            format!("wsh(and_v(pk({KEY1}),pk({KEY2})))"),
+            format!("wsh(thresh(1,pk({KEY1}),pk({KEY2})))"),
+        ] {
+            assert!(
+                matches!(
+                    Descriptor::from_str(&desc),
+                    Err(Error::InvalidMiniscript(_))
+                ),
+                "{desc}"
+            );
+        }
+        assert!(Descriptor::from_str("wsh(older(0))").is_err());
+    }
+}
@@ -0,0 +1,946 @@
+//! Miniscript (BIP-379) in `wsh()`.
+//!
+//! All the fragments and wrappers of segwit v0 are supported. The expressions are type checked
+//! for correctness, the malleability analysis and the resource limits other than the script
+//! size are left to the signers.
+
+use super::{parse_key, split_function, DescriptorKey, Error};
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::Builder;
+use bitcoin::{PublicKey, ScriptBuf};
+use std::fmt;
+use std::str::FromStr;
+
+/// Maximum size of a standard P2WSH witness script.
+const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+
+/// Maximum number of keys in `multi()`.
+const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// Timelocks are encoded as positive script numbers.
+const MAX_TIMELOCK: u32 = 0x7fffffff;
+
+/// Witness stack item sizes, see [`super::Descriptor::max_satisfaction_weight`].
+const SIGNATURE_SIZE: usize = 72;
+const PUBKEY_SIZE: usize = 33;
+const PREIMAGE_SIZE: usize = 32;
+
+/// Miniscript fragment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    False,
+    True,
+    PkK(DescriptorKey),
+    PkH(DescriptorKey),
+    Older(u32),
+    After(u32),
+    Sha256([u8; 32]),
+    Hash256([u8; 32]),
+    Ripemd160([u8; 20]),
+    Hash160([u8; 20]),
+    AndV(Box<Node>, Box<Node>),
+    AndB(Box<Node>, Box<Node>),
+    AndOr(Box<Node>, Box<Node>, Box<Node>),
+    OrB(Box<Node>, Box<Node>),
+    OrC(Box<Node>, Box<Node>),
+    OrD(Box<Node>, Box<Node>),
+    OrI(Box<Node>, Box<Node>),
+    Thresh(usize, Vec<Node>),
+    Multi(usize, Vec<DescriptorKey>),
+    /// `a:`
+    Alt(Box<Node>),
+    /// `s:`
+    Swap(Box<Node>),
+    /// `c:`
+    Check(Box<Node>),
+    /// `d:`
+    DupIf(Box<Node>),
+    /// `v:`
+    Verify(Box<Node>),
+    /// `j:`
+    NonZero(Box<Node>),
+    /// `n:`
+    ZeroNotEqual(Box<Node>),
+}
+
+/// Basic type of a fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Base {
+    B,
+    V,
+    K,
+    W,
+}
+
+/// Correctness type of a fragment, the basic type along with the properties `z`, `o`, `n`,
+/// `d` and `u` as defined in the spec.
+#[derive(Debug, Clone, Copy)]
+struct Type {
+    base: Base,
+    z: bool,
+    o: bool,
+    n: bool,
+    d: bool,
+    u: bool,
+}
+
+/// Size of the witness stack items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stack {
+    items: usize,
+    /// Total size of the items, including the length prefixes.
+    size: usize,
+}
+
+impl Stack {
+    const EMPTY: Self = Self { items: 0, size: 0 };
+
+    fn item(len: usize) -> Self {
+        Self {
+            items: 1,
+            size: super::varint_size(len) + len,
+        }
+    }
+
+    fn concat(self, other: Self) -> Self {
+        Self {
+            items: self.items + other.items,
+            size: self.size + other.size,
+        }
+    }
+}
+
+fn concat(a: Option<Stack>, b: Option<Stack>) -> Option<Stack> {
+    Some(a?.concat(b?))
+}
+
+fn larger(a: Option<Stack>, b: Option<Stack>) -> Option<Stack> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.size > a.size { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+impl Node {
+    fn ty(&self) -> Result<Type, Error> {
+        use Base::{B, K, V, W};
+
+        let ty = |base, z, o, n, d, u| Type {
+            base,
+            z,
+            o,
+            n,
+            d,
+            u,
+        };
+
+        let check = |valid: bool, fragment: &str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(Error::InvalidMiniscript(format!(
+                    "{fragment} has invalid argument types"
+                )))
+            }
+        };
+
+        Ok(match self {
+            Self::False => ty(B, true, false, false, true, true),
+            Self::True => ty(B, true, false, false, false, true),
+            Self::PkK(_) => ty(K, false, true, true, true, true),
+            Self::PkH(_) => ty(K, false, false, true, true, true),
+            Self::Older(_) | Self::After(_) => ty(B, true, false, false, false, false),
+            Self::Sha256(_) | Self::Hash256(_) | Self::Ripemd160(_) | Self::Hash160(_) => {
+                ty(B, false, true, true, true, true)
+            }
+            Self::Multi(..) => ty(B, false, false, true, true, true),
+            Self::Alt(x) => {
+                let x = x.ty()?;
+                check(x.base == B, "a:")?;
+                ty(W, false, false, false, x.d, x.u)
+            }
+            Self::Swap(x) => {
+                let x = x.ty()?;
+                check(x.base == B && x.o, "s:")?;
+                ty(W, false, false, false, x.d, x.u)
+            }
+            Self::Check(x) => {
+                let x = x.ty()?;
+                check(x.base == K, "c:")?;
+                ty(B, false, x.o, x.n, x.d, true)
+            }
+            Self::DupIf(x) => {
+                let x = x.ty()?;
+                check(x.base == V && x.z, "d:")?;
+                ty(B, false, true, true, true, false)
+            }
+            Self::Verify(x) => {
+                let x = x.ty()?;
+                check(x.base == B, "v:")?;
+                ty(V, x.z, x.o, x.n, false, false)
+            }
+            Self::NonZero(x) => {
+                let x = x.ty()?;
+                check(x.base == B && x.n, "j:")?;
+                ty(B, false, x.o, true, true, x.u)
+            }
+            Self::ZeroNotEqual(x) => {
+                let x = x.ty()?;
+                check(x.base == B, "n:")?;
+                ty(B, x.z, x.o, x.n, x.d, true)
+            }
+            Self::AndV(x, y) => {
+                let (x, y) = (x.ty()?, y.ty()?);
+                check(x.base == V && y.base != W, "and_v")?;
+                ty(
+                    y.base,
+                    x.z && y.z,
+                    (x.z && y.o) || (x.o && y.z),
+                    x.n || (x.z && y.n),
+                    false,
+                    y.u,
+                )
+            }
+            Self::AndB(x, y) => {
+                let (x, y) = (x.ty()?, y.ty()?);
+                check(x.base == B && y.base == W, "and_b")?;
+                ty(
+                    B,
+                    x.z && y.z,
+                    (x.z && y.o) || (x.o && y.z),
+                    x.n || (x.z && y.n),
+                    x.d && y.d,
+                    true,
+                )
+            }
+            Self::AndOr(x, y, z) => {
+                let (x, y, z) = (x.ty()?, y.ty()?, z.ty()?);
+                check(
+                    x.base == B && x.d && x.u && y.base == z.base && y.base != W,
+                    "andor",
+                )?;
+                ty(
+                    y.base,
+                    x.z && y.z && z.z,
+                    (x.z && y.o && z.o) || (x.o && y.z && z.z),
+                    false,
+                    z.d,
+                    y.u && z.u,
+                )
+            }
+            Self::OrB(x, z) => {
+                let (x, z) = (x.ty()?, z.ty()?);
+                check(x.base == B && x.d && z.base == W && z.d, "or_b")?;
+                ty(
+                    B,
+                    x.z && z.z,
+                    (x.z && z.o) || (x.o && z.z),
+                    false,
+                    true,
+                    true,
+                )
+            }
+            Self::OrC(x, z) => {
+                let (x, z) = (x.ty()?, z.ty()?);
+                check(x.base == B && x.d && x.u && z.base == V, "or_c")?;
+                ty(V, x.z && z.z, x.o && z.z, false, false, false)
+            }
+            Self::OrD(x, z) => {
+                let (x, z) = (x.ty()?, z.ty()?);
+                check(x.base == B && x.d && x.u && z.base == B, "or_d")?;
+                ty(B, x.z && z.z, x.o && z.z, false, z.d, z.u)
+            }
+            Self::OrI(x, z) => {
+                let (x, z) = (x.ty()?, z.ty()?);
+                check(x.base == z.base && x.base != W, "or_i")?;
+                ty(x.base, false, x.z && z.z, false, x.d || z.d, x.u && z.u)
+            }
+            Self::Thresh(k, subs) => {
+                let types = subs.iter().map(Node::ty).collect::<Result<Vec<_>, _>>()?;
+                let valid = types
+                    .iter()
+                    .enumerate()
+                    .all(|(i, ty)| ty.base == if i == 0 { B } else { W } && ty.d && ty.u);
+                check(valid && *k >= 1 && *k <= subs.len(), "thresh")?;
+                let non_zero = types.iter().filter(|ty| !ty.z).collect::<Vec<_>>();
+                ty(
+                    B,
+                    non_zero.is_empty(),
+                    non_zero.len() == 1 && non_zero[0].o,
+                    false,
+                    true,
+                    true,
+                )
+            }
+        })
+    }
+
+    fn encode(&self, builder: Builder, index: u32) -> Result<Builder, Error> {
+        let key = |key: &DescriptorKey| -> Result<PublicKey, Error> {
+            let pk = key.derive(index)?;
+            if !pk.compressed {
+                return Err(Error::UncompressedKey);
+            }
+            Ok(pk)
+        };
+
+        let hash_check = |builder: Builder, opcode: Opcode| {
+            builder
+                .push_opcode(OP_SIZE)
+                .push_int(PREIMAGE_SIZE as i64)
+                .push_opcode(OP_EQUALVERIFY)
+                .push_opcode(opcode)
+        };
+
+        let builder = match self {
+            Self::False => builder.push_int(0),
+            Self::True => builder.push_int(1),
+            Self::PkK(pk) => builder.push_key(&key(pk)?),
+            Self::PkH(pk) => builder
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_HASH160)
+                .push_slice(key(pk)?.pubkey_hash())
+                .push_opcode(OP_EQUALVERIFY),
+            Self::Older(n) => builder.push_int(*n as i64).push_opcode(OP_CSV),
+            Self::After(n) => builder.push_int(*n as i64).push_opcode(OP_CLTV),
+            Self::Sha256(hash) | Self::Hash256(hash) => {
+                let opcode = if matches!(self, Self::Sha256(_)) {
+                    OP_SHA256
+                } else {
+                    OP_HASH256
+                };
+                hash_check(builder, opcode)
+                    .push_slice(*hash)
+                    .push_opcode(OP_EQUAL)
+            }
+            Self::Ripemd160(hash) | Self::Hash160(hash) => {
+                let opcode = if matches!(self, Self::Ripemd160(_)) {
+                    OP_RIPEMD160
+                } else {
+                    OP_HASH160
+                };
+                hash_check(builder, opcode)
+                    .push_slice(*hash)
+                    .push_opcode(OP_EQUAL)
+            }
+            Self::AndV(x, y) => y.encode(x.encode(builder, index)?, index)?,
+            Self::AndB(x, y) => y
+                .encode(x.encode(builder, index)?, index)?
+                .push_opcode(OP_BOOLAND),
+            Self::AndOr(x, y, z) => {
+                let builder = z.encode(x.encode(builder, index)?.push_opcode(OP_NOTIF), index)?;
+                y.encode(builder.push_opcode(OP_ELSE), index)?
+                    .push_opcode(OP_ENDIF)
+            }
+            Self::OrB(x, z) => z
+                .encode(x.encode(builder, index)?, index)?
+                .push_opcode(OP_BOOLOR),
+            Self::OrC(x, z) => z
+                .encode(x.encode(builder, index)?.push_opcode(OP_NOTIF), index)?
+                .push_opcode(OP_ENDIF),
+            Self::OrD(x, z) => {
+                let builder = x
+                    .encode(builder, index)?
+                    .push_opcode(OP_IFDUP)
+                    .push_opcode(OP_NOTIF);
+                z.encode(builder, index)?.push_opcode(OP_ENDIF)
+            }
+            Self::OrI(x, z) => {
+                let builder = x.encode(builder.push_opcode(OP_IF), index)?;
+                z.encode(builder.push_opcode(OP_ELSE), index)?
+                    .push_opcode(OP_ENDIF)
+            }
+            Self::Thresh(k, subs) => {
+                let mut builder = builder;
+                for (i, sub) in subs.iter().enumerate() {
+                    builder = sub.encode(builder, index)?;
+                    if i > 0 {
+                        builder = builder.push_opcode(OP_ADD);
+                    }
+                }
+                builder.push_int(*k as i64).push_opcode(OP_EQUAL)
+            }
+            Self::Multi(k, keys) => {
+                let mut builder = builder.push_int(*k as i64);
+                for pk in keys {
+                    builder = builder.push_key(&key(pk)?);
+                }
+                builder
+                    .push_int(keys.len() as i64)
+                    .push_opcode(OP_CHECKMULTISIG)
+            }
+            Self::Alt(x) => x
+                .encode(builder.push_opcode(OP_TOALTSTACK), index)?
+                .push_opcode(OP_FROMALTSTACK),
+            Self::Swap(x) => x.encode(builder.push_opcode(OP_SWAP), index)?,
+            Self::Check(x) => x.encode(builder, index)?.push_opcode(OP_CHECKSIG),
+            Self::DupIf(x) => {
+                let builder = builder.push_opcode(OP_DUP).push_opcode(OP_IF);
+                x.encode(builder, index)?.push_opcode(OP_ENDIF)
+            }
+            // The last opcode is replaced with its VERIFY version if possible.
+            Self::Verify(x) => x.encode(builder, index)?.push_verify(),
+            Self::NonZero(x) => {
+                let builder = builder
+                    .push_opcode(OP_SIZE)
+                    .push_opcode(OP_0NOTEQUAL)
+                    .push_opcode(OP_IF);
+                x.encode(builder, index)?.push_opcode(OP_ENDIF)
+            }
+            Self::ZeroNotEqual(x) => x.encode(builder, index)?.push_opcode(OP_0NOTEQUAL),
+        };
+
+        Ok(builder)
+    }
+
+    /// Returns the largest satisfaction and dissatisfaction, `None` if impossible.
+    fn max_stacks(&self) -> (Option<Stack>, Option<Stack>) {
+        let empty = Some(Stack::item(0));
+        let one = Some(Stack::item(1));
+        let signature = Some(Stack::item(SIGNATURE_SIZE));
+        let pubkey = Some(Stack::item(PUBKEY_SIZE));
+        let preimage = Some(Stack::item(PREIMAGE_SIZE));
+
+        match self {
+            Self::False => (None, Some(Stack::EMPTY)),
+            Self::True | Self::Older(_) | Self::After(_) => (Some(Stack::EMPTY), None),
+            Self::PkK(_) => (signature, empty),
+            Self::PkH(_) => (concat(signature, pubkey), concat(empty, pubkey)),
+            // Any 32-byte value other than the preimage dissatisfies.
+            Self::Sha256(_) | Self::Hash256(_) | Self::Ripemd160(_) | Self::Hash160(_) => {
+                (preimage, preimage)
+            }
+            Self::AndV(x, y) => {
+                let ((sat_x, _), (sat_y, _)) = (x.max_stacks(), y.max_stacks());
+                (concat(sat_y, sat_x), None)
+            }
+            Self::AndB(x, y) => {
+                let ((sat_x, dsat_x), (sat_y, dsat_y)) = (x.max_stacks(), y.max_stacks());
+                (concat(sat_y, sat_x), concat(dsat_y, dsat_x))
+            }
+            Self::AndOr(x, y, z) => {
+                let ((sat_x, dsat_x), (sat_y, _), (sat_z, dsat_z)) =
+                    (x.max_stacks(), y.max_stacks(), z.max_stacks());
+                (
+                    larger(concat(sat_y, sat_x), concat(sat_z, dsat_x)),
+                    concat(dsat_z, dsat_x),
+                )
+            }
+            Self::OrB(x, z) => {
+                let ((sat_x, dsat_x), (sat_z, dsat_z)) = (x.max_stacks(), z.max_stacks());
+                (
+                    larger(concat(dsat_z, sat_x), concat(sat_z, dsat_x)),
+                    concat(dsat_z, dsat_x),
+                )
+            }
+            Self::OrC(x, z) => {
+                let ((sat_x, dsat_x), (sat_z, _)) = (x.max_stacks(), z.max_stacks());
+                (larger(sat_x, concat(sat_z, dsat_x)), None)
+            }
+            Self::OrD(x, z) => {
+                let ((sat_x, dsat_x), (sat_z, dsat_z)) = (x.max_stacks(), z.max_stacks());
+                (larger(sat_x, concat(sat_z, dsat_x)), concat(dsat_z, dsat_x))
+            }
+            Self::OrI(x, z) => {
+                let ((sat_x, dsat_x), (sat_z, dsat_z)) = (x.max_stacks(), z.max_stacks());
+                (
+                    larger(concat(sat_x, one), concat(sat_z, empty)),
+                    larger(concat(dsat_x, one), concat(dsat_z, empty)),
+                )
+            }
+            Self::Thresh(k, subs) => {
+                let stacks = subs.iter().map(Node::max_stacks).collect::<Vec<_>>();
+                let dsat = stacks
+                    .iter()
+                    .try_fold(Stack::EMPTY, |acc, (_, dsat)| Some(acc.concat((*dsat)?)));
+
+                // Satisfy the `k` subexpressions growing the witness the most, dissatisfy the
+                // others.
+                let mut growth = stacks
+                    .iter()
+                    .filter_map(|(sat, dsat)| {
+                        let (sat, dsat) = (sat.as_ref()?, dsat.as_ref()?);
+                        Some((
+                            sat.items as isize - dsat.items as isize,
+                            sat.size as isize - dsat.size as isize,
+                        ))
+                    })
+                    .collect::<Vec<_>>();
+                growth.sort_by(|a, b| b.1.cmp(&a.1));
+
+                let sat = dsat.filter(|_| growth.len() >= *k).map(|dsat| {
+                    growth
+                        .iter()
+                        .take(*k)
+                        .fold(dsat, |acc, (items, size)| Stack {
+                            items: acc.items.wrapping_add_signed(*items),
+                            size: acc.size.wrapping_add_signed(*size),
+                        })
+                });
+
+                (sat, dsat)
+            }
+            // The extra empty item is consumed by OP_CHECKMULTISIG.
+            Self::Multi(k, _) => (
+                (0..*k).fold(empty, |acc, _| concat(acc, signature)),
+                (0..*k).fold(empty, |acc, _| concat(acc, empty)),
+            ),
+            Self::Alt(x) | Self::Swap(x) | Self::Check(x) | Self::ZeroNotEqual(x) => x.max_stacks(),
+            Self::DupIf(x) => (concat(x.max_stacks().0, one), empty),
+            Self::Verify(x) => (x.max_stacks().0, None),
+            Self::NonZero(x) => (x.max_stacks().0, empty),
+        }
+    }
+
+    fn keys<'a>(&'a self, keys: &mut Vec<&'a DescriptorKey>) {
+        match self {
+            Self::PkK(key) | Self::PkH(key) => keys.push(key),
+            Self::Multi(_, multi_keys) => keys.extend(multi_keys),
+            Self::False
+            | Self::True
+            | Self::Older(_)
+            | Self::After(_)
+            | Self::Sha256(_)
+            | Self::Hash256(_)
+            | Self::Ripemd160(_)
+            | Self::Hash160(_) => {}
+            Self::AndOr(x, y, z) => {
+                x.keys(keys);
+                y.keys(keys);
+                z.keys(keys);
+            }
+            Self::AndV(x, y)
+            | Self::AndB(x, y)
+            | Self::OrB(x, y)
+            | Self::OrC(x, y)
+            | Self::OrD(x, y)
+            | Self::OrI(x, y) => {
+                x.keys(keys);
+                y.keys(keys);
+            }
+            Self::Thresh(_, subs) => subs.iter().for_each(|sub| sub.keys(keys)),
+            Self::Alt(x)
+            | Self::Swap(x)
+            | Self::Check(x)
+            | Self::DupIf(x)
+            | Self::Verify(x)
+            | Self::NonZero(x)
+            | Self::ZeroNotEqual(x) => x.keys(keys),
+        }
+    }
+
+    fn lift(&self) -> Policy {
+        let and = |x: &Node, y: &Node| Policy::Thresh(2, vec![x.lift(), y.lift()]);
+        let or = |x: &Node, y: &Node| Policy::Thresh(1, vec![x.lift(), y.lift()]);
+
+        match self {
+            Self::False => Policy::Unsatisfiable,
+            Self::True => Policy::Trivial,
+            Self::PkK(key) | Self::PkH(key) => Policy::Key(key.to_string()),
+            Self::Older(n) => Policy::Older(*n),
+            Self::After(n) => Policy::After(*n),
+            Self::Sha256(hash) => Policy::Hash("sha256", hash.as_slice().to_lower_hex_string()),
+            Self::Hash256(hash) => Policy::Hash("hash256", hash.as_slice().to_lower_hex_string()),
+            Self::Ripemd160(hash) => {
+                Policy::Hash("ripemd160", hash.as_slice().to_lower_hex_string())
+            }
+            Self::Hash160(hash) => Policy::Hash("hash160", hash.as_slice().to_lower_hex_string()),
+            Self::AndV(x, y) | Self::AndB(x, y) => and(x, y),
+            Self::AndOr(x, y, z) => Policy::Thresh(1, vec![and(x, y), z.lift()]),
+            Self::OrB(x, z) | Self::OrC(x, z) | Self::OrD(x, z) | Self::OrI(x, z) => or(x, z),
+            Self::Thresh(k, subs) => Policy::Thresh(*k, subs.iter().map(Node::lift).collect()),
+            Self::Multi(k, keys) => Policy::Thresh(
+                *k,
+                keys.iter()
+                    .map(|key| Policy::Key(key.to_string()))
+                    .collect(),
+            ),
+            Self::Alt(x)
+            | Self::Swap(x)
+            | Self::Check(x)
+            | Self::DupIf(x)
+            | Self::Verify(x)
+            | Self::NonZero(x)
+            | Self::ZeroNotEqual(x) => x.lift(),
+        }
+    }
+}
+
+/// Splits the arguments at the top-level commas.
+fn split_args(args: &str) -> Vec<&str> {
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut parts = Vec::new();
+
+    for (i, ch) in args.char_indices() {
+        match ch {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&args[start..]);
+    parts
+}
+
+fn parse_hash<const N: usize>(s: &str) -> Result<[u8; N], Error> {
+    Vec::<u8>::from_hex(s)
+        .ok()
+        .and_then(|bytes| <[u8; N]>::try_from(bytes.as_slice()).ok())
+        .ok_or_else(|| Error::InvalidMiniscript(format!("invalid hash {s}")))
+}
+
+fn parse_timelock(s: &str) -> Result<u32, Error> {
+    s.parse::<u32>()
+        .ok()
+        .filter(|n| (1..=MAX_TIMELOCK).contains(n))
+        .ok_or_else(|| Error::InvalidMiniscript(format!("invalid timelock {s}")))
+}
+
+fn parse_node(s: &str) -> Result<Node, Error> {
+    // Wrappers are the letters in front of the colon, e.g. `sv:` in `sv:older(144)`.
+    let (wrappers, expr) = match s.find(['(', ':']) {
+        Some(pos) if s.as_bytes()[pos] == b':' => (&s[..pos], &s[pos + 1..]),
+        _ => ("", s),
+    };
+
+    let wrong_args = |name: &str| Error::InvalidMiniscript(format!("wrong arguments of {name}"));
+
+    let node = match expr {
+        "0" => Node::False,
+        "1" => Node::True,
+        expr => {
+            let (name, args) = split_function(expr)?;
+            let args = split_args(args);
+
+            let sub = |i: usize| parse_node(args[i]).map(Box::new);
+            let arity = |n: usize| {
+                if args.len() == n {
+                    Ok(())
+                } else {
+                    Err(wrong_args(name))
+                }
+            };
+
+            match name {
+                "pk_k" | "pk_h" | "pk" | "pkh" => {
+                    arity(1)?;
+                    let key = parse_key(args[0])?;
+                    match name {
+                        "pk_k" => Node::PkK(key),
+                        "pk_h" => Node::PkH(key),
+                        "pk" => Node::Check(Box::new(Node::PkK(key))),
+                        _ => Node::Check(Box::new(Node::PkH(key))),
+                    }
+                }
+                "older" | "after" => {
+                    arity(1)?;
+                    let n = parse_timelock(args[0])?;
+                    if name == "older" {
+                        Node::Older(n)
+                    } else {
+                        Node::After(n)
+                    }
+                }
+                "sha256" | "hash256" => {
+                    arity(1)?;
+                    let hash = parse_hash::<32>(args[0])?;
+                    if name == "sha256" {
+                        Node::Sha256(hash)
+                    } else {
+                        Node::Hash256(hash)
+                    }
+                }
+                "ripemd160" | "hash160" => {
+                    arity(1)?;
+                    let hash = parse_hash::<20>(args[0])?;
+                    if name == "ripemd160" {
+                        Node::Ripemd160(hash)
+                    } else {
+                        Node::Hash160(hash)
+                    }
+                }
+                "and_v" | "and_b" | "and_n" | "or_b" | "or_c" | "or_d" | "or_i" => {
+                    arity(2)?;
+                    let (x, y) = (sub(0)?, sub(1)?);
+                    match name {
+                        "and_v" => Node::AndV(x, y),
+                        "and_b" => Node::AndB(x, y),
+                        "and_n" => Node::AndOr(x, y, Box::new(Node::False)),
+                        "or_b" => Node::OrB(x, y),
+                        "or_c" => Node::OrC(x, y),
+                        "or_d" => Node::OrD(x, y),
+                        _ => Node::OrI(x, y),
+                    }
+                }
+                "andor" => {
+                    arity(3)?;
+                    Node::AndOr(sub(0)?, sub(1)?, sub(2)?)
+                }
+                "thresh" | "multi" => {
+                    let k = args[0].parse::<usize>().map_err(|_| wrong_args(name))?;
+                    let n = args.len() - 1;
+                    if k == 0 || k > n {
+                        return Err(Error::InvalidThreshold {
+                            threshold: k,
+                            keys: n,
+                        });
+                    }
+
+                    if name == "thresh" {
+                        Node::Thresh(
+                            k,
+                            args[1..]
+                                .iter()
+                                .map(|arg| parse_node(arg))
+                                .collect::<Result<_, _>>()?,
+                        )
+                    } else {
+                        if n > MAX_PUBKEYS_PER_MULTISIG {
+                            return Err(Error::InvalidThreshold {
+                                threshold: k,
+                                keys: n,
+                            });
+                        }
+                        Node::Multi(
+                            k,
+                            args[1..]
+                                .iter()
+                                .map(|arg| parse_key(arg))
+                                .collect::<Result<_, _>>()?,
+                        )
+                    }
+                }
+                other => return Err(Error::Unsupported(format!("{other}() in miniscript"))),
+            }
+        }
+    };
+
+    wrappers.chars().rev().try_fold(node, |node, wrapper| {
+        let node = Box::new(node);
+        let wrapped = match wrapper {
+            'a' => Node::Alt(node),
+            's' => Node::Swap(node),
+            'c' => Node::Check(node),
+            'd' => Node::DupIf(node),
+            'v' => Node::Verify(node),
+            'j' => Node::NonZero(node),
+            'n' => Node::ZeroNotEqual(node),
+            't' => Node::AndV(node, Box::new(Node::True)),
+            'l' => Node::OrI(Box::new(Node::False), node),
+            'u' => Node::OrI(node, Box::new(Node::False)),
+            other => {
+                return Err(Error::InvalidMiniscript(format!(
+                    "unknown wrapper {other}:"
+                )))
+            }
+        };
+        Ok(wrapped)
+    })
+}
+
+/// Type checked miniscript expression of a witness script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Miniscript {
+    node: Node,
+    script_size: usize,
+}
+
+impl FromStr for Miniscript {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let node = parse_node(s)?;
+
+        if node.ty()?.base != Base::B {
+            return Err(Error::InvalidMiniscript(
+                "top-level expression is not of type B".to_string(),
+            ));
+        }
+
+        if node.max_stacks().0.is_none() {
+            return Err(Error::InvalidMiniscript("unsatisfiable".to_string()));
+        }
+
+        // The script size only depends on the structure as the keys are always compressed.
+        let script_size = node.encode(Builder::new(), 0)?.into_script().len();
+        if script_size > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+            return Err(Error::InvalidMiniscript(format!(
+                "script size {script_size} exceeds {MAX_STANDARD_P2WSH_SCRIPT_SIZE}"
+            )));
+        }
+
+        Ok(Self { node, script_size })
+    }
+}
+
+impl Miniscript {
+    /// Returns the witness script at the derivation `index`.
+    pub fn witness_script(&self, index: u32) -> Result<ScriptBuf, Error> {
+        Ok(self.node.encode(Builder::new(), index)?.into_script())
+    }
+
+    /// Returns the size of the witness script.
+    pub fn script_size(&self) -> usize {
+        self.script_size
+    }
+
+    /// Returns the keys in the order of appearance.
+    pub fn keys(&self) -> Vec<&DescriptorKey> {
+        let mut keys = Vec::new();
+        self.node.keys(&mut keys);
+        keys
+    }
+
+    /// Returns the number of items and the total size in bytes of the largest satisfaction
+    /// witness, excluding the witness script.
+    pub fn max_satisfaction_size(&self) -> (usize, usize) {
+        let stack = self
+            .node
+            .max_stacks()
+            .0
+            .expect("Unsatisfiable miniscript is rejected on parsing; qed");
+        (stack.items, stack.size)
+    }
+
+    /// Returns the spending policy.
+    pub fn policy(&self) -> Policy {
+        self.node.lift().normalized()
+    }
+}
+
+/// Spending policy of a descriptor, i.e., the conditions to satisfy regardless of the script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Policy {
+    Unsatisfiable,
+    Trivial,
+    /// Signature of the key.
+    Key(String),
+    /// Relative timelock.
+    Older(u32),
+    /// Absolute timelock.
+    After(u32),
+    /// Preimage of the hash, the hash function and the hex-encoded digest.
+    Hash(&'static str, String),
+    /// `k` of the subpolicies.
+    Thresh(usize, Vec<Policy>),
+}
+
+impl Policy {
+    /// Simplifies the trivial conditions and flattens the nested `and`s and `or`s.
+    pub(super) fn normalized(self) -> Self {
+        let Self::Thresh(mut k, subs) = self else {
+            return self;
+        };
+
+        let n = subs.len();
+        let mut flattened = Vec::new();
+
+        for sub in subs.into_iter().map(Self::normalized) {
+            match sub {
+                Self::Trivial => k = k.saturating_sub(1),
+                Self::Unsatisfiable => {}
+                // `and` of `and`s or `or` of `or`s.
+                Self::Thresh(sub_k, sub_subs)
+                    if (k == n && sub_k == sub_subs.len()) || (k == 1 && sub_k == 1) =>
+                {
+                    if k == n {
+                        k += sub_k - 1;
+                    }
+                    flattened.extend(sub_subs);
+                }
+                sub => flattened.push(sub),
+            }
+        }
+
+        if k == 0 {
+            Self::Trivial
+        } else if flattened.len() < k {
+            Self::Unsatisfiable
+        } else if flattened.len() == 1 {
+            flattened.remove(0)
+        } else {
+            Self::Thresh(k, flattened)
+        }
+    }
+
+    /// Returns the distinct keys.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys = Vec::new();
+        self.visit(&mut |policy| {
+            if let Self::Key(key) = policy {
+                if !keys.contains(&key.as_str()) {
+                    keys.push(key.as_str());
+                }
+            }
+        });
+        keys
+    }
+
+    /// Returns the distinct relative timelocks in ascending order.
+    pub fn relative_timelocks(&self) -> Vec<u32> {
+        self.timelocks(|policy| match policy {
+            Self::Older(n) => Some(*n),
+            _ => None,
+        })
+    }
+
+    /// Returns the distinct absolute timelocks in ascending order.
+    pub fn absolute_timelocks(&self) -> Vec<u32> {
+        self.timelocks(|policy| match policy {
+            Self::After(n) => Some(*n),
+            _ => None,
+        })
+    }
+
+    fn timelocks(&self, f: impl Fn(&Self) -> Option<u32>) -> Vec<u32> {
+        let mut timelocks = Vec::new();
+        self.visit(&mut |policy| timelocks.extend(f(policy)));
+        timelocks.sort_unstable();
+        timelocks.dedup();
+        timelocks
+    }
+
+    fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Self)) {
+        f(self);
+        if let Self::Thresh(_, subs) = self {
+            subs.iter().for_each(|sub| sub.visit(f));
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsatisfiable => f.write_str("UNSATISFIABLE"),
+            Self::Trivial => f.write_str("TRIVIAL"),
+            Self::Key(key) => write!(f, "pk({key})"),
+            Self::Older(n) => write!(f, "older({n})"),
+            Self::After(n) => write!(f, "after({n})"),
+            Self::Hash(function, digest) => write!(f, "{function}({digest})"),
+            Self::Thresh(k, subs) => {
+                if *k == subs.len() {
+                    f.write_str("and(")?;
+                } else if *k == 1 {
+                    f.write_str("or(")?;
+                } else {
+                    write!(f, "thresh({k},")?;
+                }
+                for (i, sub) in subs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{sub}")?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Database of the Bitcoin headers.
+//!
+//! Each header is stored in the aux-db along with its height and cumulative chainwork, keyed
+//! by the Bitcoin block hash, so that neither the Substrate header needs to be loaded and
+//! decoded nor the chainwork to be recalculated on lookup. The headers of the blocks imported
+//! before the entries were introduced are still served from the Substrate headers.
+
+use crate::{locator_indexes, BackendExt, BlockLocator, Height};
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::{BlockHash, Work};
+use sc_client_api::AuxStore;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Prefix of the aux-db key of a header chain entry.
+const HEADER_ENTRY_PREFIX: &[u8] = b"headerentry";
+
+/// Size of an encoded header.
+const HEADER_SIZE: usize = 80;
+
+/// Returns the aux-db key of the header chain entry of the Bitcoin block.
+pub fn header_entry_key(bitcoin_block_hash: BlockHash) -> Vec<u8> {
+    let mut key = HEADER_ENTRY_PREFIX.to_vec();
+    key.extend_from_slice(bitcoin_block_hash.as_ref());
+    key
+}
+
+/// Bitcoin header with its position in the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderEntry {
+    pub header: BitcoinHeader,
+    pub height: Height,
+    /// Cumulative chainwork up to this block (inclusive).
+    pub chain_work: Work,
+}
+
+impl HeaderEntry {
+    /// Encodes the entry as the header, the height in little endian and the chainwork in big
+    /// endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_SIZE + 4 + 32);
+        self.header
+            .consensus_encode(&mut data)
+            .expect("Writing to a vec never fails; qed");
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.extend_from_slice(&self.chain_work.to_be_bytes());
+        data
+    }
+
+    /// Decodes the entry encoded by [`Self::encode`].
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != HEADER_SIZE + 4 + 32 {
+            return None;
+        }
+
+        let header = BitcoinHeader::consensus_decode(&mut &data[..HEADER_SIZE]).ok()?;
+        let height = u32::from_le_bytes(data[HEADER_SIZE..HEADER_SIZE + 4].try_into().ok()?);
+        let chain_work = Work::from_be_bytes(data[HEADER_SIZE + 4..].try_into().ok()?);
+
+        Some(Self {
+            header,
+            height,
+            chain_work,
+        })
+    }
+
+    /// Returns the aux-db key and value of the entry.
+    pub fn aux_item(&self) -> (Vec<u8>, Vec<u8>) {
+        (header_entry_key(self.header.block_hash()), self.encode())
+    }
+}
+
+/// Bitcoin header chain backed by the aux-db of the client.
+pub struct HeaderChain<Block, Client> {
+    client: Arc<Client>,
+    _phantom: PhantomData<Block>,
+}
+
+impl<Block, Client> Clone for HeaderChain<Block, Client> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<Block, Client> HeaderChain<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    /// Constructs a new instance of [`HeaderChain`].
+    pub fn new(client: Arc<Client>) -> Self {
+        Self {
+            client,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the entry of the Bitcoin block.
+    pub fn entry(&self, block_hash: BlockHash) -> Option<HeaderEntry> {
+        if let Some(entry) = self.stored_entry(block_hash) {
+            return Some(entry);
+        }
+
+        // Block imported before the header chain entries were introduced.
+        Some(HeaderEntry {
+            header: BackendExt::<Block>::block_header(&self.client, block_hash)?,
+            height: BackendExt::<Block>::block_number(&self.client, block_hash)?,
+            chain_work: BackendExt::<Block>::chain_work(&self.client, block_hash)?,
+        })
+    }
+
+    fn stored_entry(&self, block_hash: BlockHash) -> Option<HeaderEntry> {
+        self.client
+            .get_aux(&header_entry_key(block_hash))
+            .ok()
+            .flatten()
+            .and_then(|data| HeaderEntry::decode(&data))
+    }
+
+    /// Returns the header of the Bitcoin block.
+    pub fn header(&self, block_hash: BlockHash) -> Option<BitcoinHeader> {
+        self.stored_entry(block_hash)
+            .map(|entry| entry.header)
+            .or_else(|| BackendExt::<Block>::block_header(&self.client, block_hash))
+    }
+
+    /// Returns the height of the Bitcoin block.
+    pub fn height(&self, block_hash: BlockHash) -> Option<Height> {
+        self.entry(block_hash).map(|entry| entry.height)
+    }
+
+    /// Returns the cumulative chainwork up to the Bitcoin block (inclusive).
+    pub fn chain_work(&self, block_hash: BlockHash) -> Option<Work> {
+        self.entry(block_hash).map(|entry| entry.chain_work)
+    }
+
+    /// Returns the hash of the block at `height` in the best chain.
+    pub fn best_hash(&self, height: Height) -> Option<BlockHash> {
+        BackendExt::<Block>::block_hash(&self.client, height)
+    }
+
+    /// Inserts the header on top of its parent and returns the new entry.
+    pub fn insert(&self, header: BitcoinHeader) -> sp_blockchain::Result<HeaderEntry> {
+        let parent = self.entry(header.prev_blockhash).ok_or_else(|| {
+            sp_blockchain::Error::MissingHeader(header.prev_blockhash.to_string())
+        })?;
+
+        let entry = HeaderEntry {
+            header,
+            height: parent.height + 1,
+            chain_work: parent.chain_work + header.work(),
+        };
+
+        let (key, value) = entry.aux_item();
+        self.client
+            .insert_aux(&[(key.as_slice(), value.as_slice())], &[])?;
+
+        Ok(entry)
+    }
+
+    /// Returns the block locator of the chain ending at `tip`, which is not necessarily in
+    /// the best chain.
+    ///
+    /// The blocks are walked back one by one only until the fork joins the best chain, the
+    /// remaining hashes are looked up by height.
+    pub fn block_locator(&self, tip: BlockHash) -> Option<BlockLocator> {
+        let tip_entry = self.entry(tip)?;
+
+        let mut locator_hashes = Vec::new();
+        let mut fork = Some((tip, tip_entry));
+
+        for height in locator_indexes(tip_entry.height) {
+            let hash = match fork.as_mut() {
+                Some((hash, entry)) => {
+                    while entry.height > height {
+                        *hash = entry.header.prev_blockhash;
+                        *entry = self.entry(*hash)?;
+                    }
+                    *hash
+                }
+                None => self.best_hash(height)?,
+            };
+
+            if fork.is_some() && self.best_hash(height) == Some(hash) {
+                fork = None;
+            }
+
+            locator_hashes.push(hash);
+        }
+
+        Some(BlockLocator {
+            latest_block: tip_entry.height,
+            locator_hashes,
+        })
+    }
+
+    /// Returns the block locator of the best chain from `from`, see
+    /// [`crate::BlockLocatorProvider::block_locator`].
+    pub fn best_block_locator(
+        &self,
+        from: Height,
+        search_pending_block: impl Fn(Height) -> Option<BlockHash>,
+    ) -> BlockLocator {
+        let locator_hashes = locator_indexes(from)
+            .into_iter()
+            .filter_map(|height| search_pending_block(height).or_else(|| self.best_hash(height)))
+            .collect();
+
+        BlockLocator {
+            latest_block: from,
+            locator_hashes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_entry_roundtrip() {
+        let header = bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin).header;
+
+        let entry = HeaderEntry {
+            header,
+            height: 123,
+            chain_work: header.work() + header.work(),
+        };
+
+        let encoded = entry.encode();
+        assert_eq!(HeaderEntry::decode(&encoded), Some(entry));
+        assert_eq!(HeaderEntry::decode(&encoded[1..]), None);
+        assert_eq!(entry.aux_item().0, header_entry_key(header.block_hash()));
+    }
+}
@@ -1,25 +1,218 @@
 //! Primitives for the client.
 
+mod block_stats;
+pub mod descriptor;
+mod header_chain;
+mod light_chain;
+
 use bitcoin::blockdata::block::Header as BitcoinHeader;
 use bitcoin::consensus::{Decodable, Encodable};
 use bitcoin::constants::genesis_block;
-use bitcoin::hashes::Hash;
-use bitcoin::{Block as BitcoinBlock, BlockHash, Transaction};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{schnorr, Message, Secp256k1, XOnlyPublicKey};
+use bitcoin::{Block as BitcoinBlock, BlockHash, Transaction, Work};
 use codec::Decode;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use sc_client_api::AuxStore;
 use sp_blockchain::HeaderBackend;
+use sp_inherents::InherentDataProvider;
 use sp_runtime::generic::{Digest, DigestItem};
 use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
 use std::sync::Arc;
 use subcoin_runtime_primitives::{NAKAMOTO_HASH_ENGINE_ID, NAKAMOTO_HEADER_ENGINE_ID};
 
+pub use block_stats::{block_stats_key, BlockStats};
+pub use header_chain::{header_entry_key, HeaderChain, HeaderEntry};
+pub use light_chain::{LightChain, ProofError, TxInclusion};
 pub use subcoin_runtime_primitives as runtime;
 
 type Height = u32;
 
+/// Prefix of the aux-db key of the cumulative chainwork of a block.
+const CHAIN_WORK_PREFIX: &[u8] = b"chainwork";
+
+/// Returns the aux-db key of the cumulative chainwork of the Bitcoin block.
+pub fn chain_work_key(bitcoin_block_hash: BlockHash) -> Vec<u8> {
+    let mut key = CHAIN_WORK_PREFIX.to_vec();
+    key.extend_from_slice(bitcoin_block_hash.as_ref());
+    key
+}
+
+/// Prefix of the aux-db key of a Bitcoin header without the corresponding Substrate block.
+const HEADER_PREFIX: &[u8] = b"header";
+
+/// Returns the aux-db key of the Bitcoin header of a block not stored in the database.
+///
+/// The blocks before a state snapshot are never imported, their headers are still needed,
+/// e.g., for the difficulty adjustment and the median time past.
+pub fn header_key(bitcoin_block_hash: BlockHash) -> Vec<u8> {
+    let mut key = HEADER_PREFIX.to_vec();
+    key.extend_from_slice(bitcoin_block_hash.as_ref());
+    key
+}
+
+/// Prefix of the aux-db key of a block marked invalid.
+const INVALID_BLOCK_PREFIX: &[u8] = b"invalid";
+
+/// Returns the aux-db key marking the Bitcoin block as invalid.
+///
+/// The key exists for the blocks failed the verification on import and the blocks
+/// invalidated manually, including the descendants of the latter.
+pub fn invalid_block_key(bitcoin_block_hash: BlockHash) -> Vec<u8> {
+    let mut key = INVALID_BLOCK_PREFIX.to_vec();
+    key.extend_from_slice(bitcoin_block_hash.as_ref());
+    key
+}
+
 // 6 blocks is the standard confirmation period in the Bitcoin community.
 pub const CONFIRMATION_DEPTH: u32 = 6u32;
 
+/// Aux-db key of the latest checkpoint submitted to the [`FinalityOracle`].
+const FINALITY_CHECKPOINT_KEY: &[u8] = b"finality_checkpoint";
+
+/// Tag of the message signed for a finality checkpoint.
+const FINALITY_CHECKPOINT_TAG: &[u8] = b"subcoin/finality-checkpoint";
+
+/// Finality checkpoint error.
+#[derive(Debug, thiserror::Error)]
+pub enum FinalityCheckpointError {
+    #[error("Invalid checkpoint signature")]
+    InvalidSignature,
+    #[error(transparent)]
+    Client(#[from] sp_blockchain::Error),
+}
+
+/// Handle of an external finality oracle, e.g., the checkpoints signed by a sidechain
+/// federation.
+///
+/// A checkpoint is only accepted with a valid BIP340 signature of the configured signer
+/// over [`FinalityOracle::checkpoint_message`]. The latest checkpoint is persisted in the
+/// aux-db and finalized by the finalizer once the block is in the best chain.
+#[derive(Debug, Clone)]
+pub struct FinalityOracle {
+    signer: XOnlyPublicKey,
+    subscribers: Arc<parking_lot::Mutex<Vec<UnboundedSender<()>>>>,
+}
+
+impl FinalityOracle {
+    /// Constructs a new instance of [`FinalityOracle`] accepting the checkpoints of `signer`.
+    pub fn new(signer: XOnlyPublicKey) -> Self {
+        Self {
+            signer,
+            subscribers: Default::default(),
+        }
+    }
+
+    /// Returns the signer of the checkpoints.
+    pub fn signer(&self) -> XOnlyPublicKey {
+        self.signer
+    }
+
+    /// Returns the message signed for the checkpoint of the block.
+    pub fn checkpoint_message(bitcoin_block_hash: BlockHash) -> Message {
+        let mut engine = sha256::Hash::engine();
+        engine.input(FINALITY_CHECKPOINT_TAG);
+        engine.input(bitcoin_block_hash.as_byte_array());
+        Message::from_digest(sha256::Hash::from_engine(engine).to_byte_array())
+    }
+
+    /// Submits a new checkpoint signed by the signer, which replaces the previous one.
+    pub fn submit_checkpoint<Client: AuxStore>(
+        &self,
+        client: &Client,
+        bitcoin_block_hash: BlockHash,
+        signature: &schnorr::Signature,
+    ) -> Result<(), FinalityCheckpointError> {
+        Secp256k1::verification_only()
+            .verify_schnorr(
+                signature,
+                &Self::checkpoint_message(bitcoin_block_hash),
+                &self.signer,
+            )
+            .map_err(|_| FinalityCheckpointError::InvalidSignature)?;
+
+        client.insert_aux(
+            &[(
+                FINALITY_CHECKPOINT_KEY,
+                bitcoin_block_hash.as_byte_array().as_slice(),
+            )],
+            &[],
+        )?;
+
+        self.subscribers
+            .lock()
+            .retain(|subscriber| subscriber.unbounded_send(()).is_ok());
+
+        Ok(())
+    }
+
+    /// Returns the latest checkpoint.
+    pub fn checkpoint<Client: AuxStore>(client: &Client) -> Option<BlockHash> {
+        client
+            .get_aux(FINALITY_CHECKPOINT_KEY)
+            .ok()
+            .flatten()
+            .and_then(|encoded| BlockHash::from_slice(&encoded).ok())
+    }
+
+    /// Returns a stream notified on every new checkpoint.
+    pub fn checkpoint_stream(&self) -> UnboundedReceiver<()> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        self.subscribers.lock().push(sender);
+        receiver
+    }
+}
+
+/// Minimum number of the most recent blocks whose bodies are kept when pruning, same as
+/// Bitcoin Core.
+pub const MIN_BLOCKS_TO_KEEP: u32 = 288;
+
+/// Minimum prune target in MiB, same as Bitcoin Core.
+pub const MIN_PRUNE_TARGET: u64 = 550;
+
+/// Upper bound of the serialized block size, i.e., the maximum block weight.
+const MAX_BLOCK_SERIALIZED_SIZE: u64 = 4_000_000;
+
+/// Block body pruning, configured by the target disk usage of the block bodies.
+///
+/// Only the bodies of the finalized blocks are pruned, headers are always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockPruning {
+    target_size: u64,
+}
+
+impl BlockPruning {
+    /// Constructs a new instance of [`BlockPruning`] with the target size in MiB.
+    ///
+    /// Returns `None` if the target is below [`MIN_PRUNE_TARGET`].
+    pub fn new(target_size_mib: u64) -> Option<Self> {
+        (target_size_mib >= MIN_PRUNE_TARGET).then_some(Self {
+            target_size: target_size_mib * 1024 * 1024,
+        })
+    }
+
+    /// Returns the target size in bytes.
+    pub fn target_size(&self) -> u64 {
+        self.target_size
+    }
+
+    /// Returns the number of the finalized blocks whose bodies are kept.
+    ///
+    /// The target is honored even if every block is full, at least [`MIN_BLOCKS_TO_KEEP`]
+    /// blocks are kept regardless.
+    pub fn blocks_to_keep(&self) -> u32 {
+        (self.target_size / MAX_BLOCK_SERIALIZED_SIZE)
+            .try_into()
+            .unwrap_or(u32::MAX)
+            .max(MIN_BLOCKS_TO_KEEP)
+    }
+
+    /// Returns the height of the lowest block whose body is kept, given the finalized height.
+    pub fn prune_height(&self, finalized_number: u32) -> u32 {
+        (finalized_number + 1).saturating_sub(self.blocks_to_keep())
+    }
+}
+
 /// Returns the encoded Bitcoin genesis block.
 ///
 /// Used in the Substrate genesis block construction.
@@ -73,6 +266,10 @@ pub trait BitcoinTransactionAdapter<Block: BlockT> {
 
     /// Converts a Bitcoin transaction into a Substrate extrinsic.
     fn bitcoin_transaction_into_extrinsic(btc_tx: &Transaction) -> Block::Extrinsic;
+
+    /// Returns the provider of the inherent data of the Bitcoin block, from which the runtime
+    /// creates the extrinsics of [`Self::bitcoin_transaction_into_extrinsic`].
+    fn inherent_data_provider(block: &BitcoinBlock) -> Box<dyn InherentDataProvider>;
 }
 
 /// Trait for interfacing with the Bitcoin storage.
@@ -96,6 +293,8 @@ pub trait BackendExt<Block: BlockT> {
     fn block_hash(&self, block_number: u32) -> Option<BlockHash>;
 
     /// Returns the header for given bitcoin block hash.
+    ///
+    /// Falls back to the header stored under [`header_key`] if the block is not in the database.
     fn block_header(&self, bitcoin_block_hash: BlockHash) -> Option<BitcoinHeader>;
 
     /// Returns `Some(BlockHash)` if a corresponding Bitcoin block hash is found, otherwise returns `None`.
@@ -109,6 +308,17 @@ pub trait BackendExt<Block: BlockT> {
         &self,
         bitcoin_block_hash: BlockHash,
     ) -> Option<<Block as BlockT>::Hash>;
+
+    /// Returns the cumulative chainwork up to the given bitcoin block (inclusive).
+    ///
+    /// The chainwork of the blocks imported before it was tracked is recalculated from the
+    /// headers and persisted.
+    fn chain_work(&self, bitcoin_block_hash: BlockHash) -> Option<Work>;
+
+    /// Returns the statistics of the given bitcoin block.
+    ///
+    /// Only available for the blocks fully verified on import.
+    fn block_stats(&self, bitcoin_block_hash: BlockHash) -> Option<BlockStats>;
 }
 
 impl<Block, Client> BackendExt<Block> for Arc<Client>
@@ -143,7 +353,13 @@ where
     fn block_header(&self, bitcoin_block_hash: BlockHash) -> Option<BitcoinHeader> {
         self.substrate_block_hash_for(bitcoin_block_hash)
             .and_then(|substrate_block_hash| self.header(substrate_block_hash).ok().flatten())
-            .and_then(|header| extract_bitcoin_block_header::<Block>(&header).ok())
+            .and_then(|header| extract_bitcoin_header::<Block>(&header).ok())
+            .or_else(|| {
+                self.get_aux(&header_key(bitcoin_block_hash))
+                    .ok()
+                    .flatten()
+                    .and_then(|header| BitcoinHeader::consensus_decode(&mut header.as_slice()).ok())
+            })
     }
 
     fn bitcoin_block_hash_for(
@@ -173,6 +389,69 @@ where
             .flatten()
             .and_then(|substrate_hash| Decode::decode(&mut substrate_hash.as_slice()).ok())
     }
+
+    fn chain_work(&self, bitcoin_block_hash: BlockHash) -> Option<Work> {
+        let stored_chain_work = |block_hash: BlockHash| {
+            self.get_aux(&chain_work_key(block_hash))
+                .ok()
+                .flatten()
+                .and_then(|work| work.try_into().ok())
+                .map(Work::from_be_bytes)
+        };
+
+        if let Some(chain_work) = stored_chain_work(bitcoin_block_hash) {
+            return Some(chain_work);
+        }
+
+        // Blocks missing the chainwork, from the given block back to the first one having it.
+        let mut missing = Vec::new();
+        let mut block_hash = bitcoin_block_hash;
+
+        let mut chain_work = loop {
+            let header = self.block_header(block_hash)?;
+
+            missing.push((block_hash, header.work()));
+
+            // Genesis block.
+            if header.prev_blockhash == BlockHash::all_zeros() {
+                break Work::from_be_bytes([0u8; 32]);
+            }
+
+            block_hash = header.prev_blockhash;
+
+            if let Some(parent_chain_work) = stored_chain_work(block_hash) {
+                break parent_chain_work;
+            }
+        };
+
+        // Backfill the recalculated chainwork so that the walk is done only once.
+        let backfill = missing
+            .into_iter()
+            .rev()
+            .map(|(block_hash, work)| {
+                chain_work = chain_work + work;
+                (chain_work_key(block_hash), chain_work.to_be_bytes())
+            })
+            .collect::<Vec<_>>();
+
+        let insert = backfill
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+            .collect::<Vec<_>>();
+
+        if let Err(err) = self.insert_aux(&insert, &[]) {
+            tracing::error!(?bitcoin_block_hash, "Failed to backfill chainwork: {err:?}");
+        }
+
+        Some(chain_work)
+    }
+
+    fn block_stats(&self, bitcoin_block_hash: BlockHash) -> Option<BlockStats> {
+        self.get_aux(&block_stats_key(bitcoin_block_hash))
+            .ok()
+            .flatten()
+            .and_then(|stats| BlockStats::decode(&mut stats.as_slice()).ok())
+    }
 }
 
 /// A trait to extend the Substrate Client.
@@ -201,6 +480,34 @@ pub trait CoinStorageKey: Send + Sync {
 
     /// Returns the final storage prefix for Coins.
     fn storage_prefix(&self) -> [u8; 32];
+
+    /// Returns the storage key of the cursor of the ongoing coins migration.
+    ///
+    /// The coins are stored in both the old and new layouts while the cursor exists.
+    fn ongoing_coins_migration_key(&self) -> [u8; 32];
+
+    /// Returns the maximum size of the scripts stored in the UTXO set as is.
+    ///
+    /// The larger ones are stored as their hash, see `stored_script_pubkey`.
+    fn max_script_size(&self) -> usize;
+
+    /// Returns the storage key prefix of all the outputs of the transaction.
+    fn txid_storage_prefix(&self, txid: bitcoin::Txid) -> Vec<u8> {
+        let mut key = self.storage_key(txid, 0);
+        // The vout is encoded after the txid.
+        key.truncate(key.len() - std::mem::size_of::<u32>());
+        key
+    }
+
+    /// Decodes the output from a full storage key of Coins.
+    ///
+    /// The keys of Coins are not hashed, (txid, vout) follows the final storage prefix.
+    fn outpoint(&self, storage_key: &[u8]) -> Option<bitcoin::OutPoint> {
+        let key = storage_key.get(32..)?;
+        let txid = bitcoin::Txid::from_slice(key.get(..32)?).ok()?;
+        let vout = u32::from_le_bytes(key.get(32..36)?.try_into().ok()?);
+        Some(bitcoin::OutPoint { txid, vout })
+    }
 }
 
 /// Represents a Bitcoin block locator, used to sync blockchain data between nodes.
@@ -243,39 +550,10 @@ where
         from: Option<Height>,
         search_pending_block: impl Fn(Height) -> Option<BlockHash>,
     ) -> BlockLocator {
-        let mut locator_hashes = Vec::new();
-
         let from = from.unwrap_or_else(|| self.best_number());
 
-        for height in locator_indexes(from) {
-            // if height < last_checkpoint {
-            // Don't go past the latest checkpoint. We never want to accept a fork
-            // older than our last checkpoint.
-            // break;
-            // }
-
-            if let Some(bitcoin_hash) = search_pending_block(height) {
-                locator_hashes.push(bitcoin_hash);
-                continue;
-            }
-
-            let Ok(Some(hash)) = self.hash(height.into()) else {
-                continue;
-            };
-
-            if let Ok(Some(header)) = self.header(hash) {
-                let maybe_bitcoin_block_hash =
-                    BackendExt::<Block>::bitcoin_block_hash_for(self, header.hash());
-                if let Some(bitcoin_block_hash) = maybe_bitcoin_block_hash {
-                    locator_hashes.push(bitcoin_block_hash);
-                }
-            }
-        }
-
-        BlockLocator {
-            latest_block: from,
-            locator_hashes,
-        }
+        HeaderChain::<Block, Client>::new(self.clone())
+            .best_block_locator(from, search_pending_block)
     }
 }
 
@@ -300,26 +578,38 @@ fn locator_indexes(mut from: Height) -> Vec<Height> {
     indexes
 }
 
-/// Constructs a Substrate header digest from a Bitcoin header.
-pub fn substrate_header_digest(bitcoin_header: &BitcoinHeader) -> Digest {
-    let bitcoin_block_hash = bitcoin_header.block_hash();
+/// Size of the consensus encoded Bitcoin header.
+const BITCOIN_HEADER_SIZE: usize = 80;
 
-    let mut encoded_bitcoin_header = Vec::new();
+/// Returns the digest item committing to the Bitcoin header.
+///
+/// The item is a pre-runtime digest of [`NAKAMOTO_HEADER_ENGINE_ID`] holding the 80-byte
+/// consensus encoded header, which is enough to verify the proof of work of the block from
+/// the Substrate header alone.
+pub fn bitcoin_header_digest_item(bitcoin_header: &BitcoinHeader) -> DigestItem {
+    let mut encoded_bitcoin_header = Vec::with_capacity(BITCOIN_HEADER_SIZE);
     bitcoin_header
         .consensus_encode(&mut encoded_bitcoin_header)
         .expect("Bitcoin header must be valid; qed");
 
+    DigestItem::PreRuntime(NAKAMOTO_HEADER_ENGINE_ID, encoded_bitcoin_header)
+}
+
+/// Constructs a Substrate header digest from a Bitcoin header.
+pub fn substrate_header_digest(bitcoin_header: &BitcoinHeader) -> Digest {
+    let bitcoin_block_hash = bitcoin_header.block_hash();
+
     // Store the Bitcoin block hash and the bitcoin header itself in the header digest.
     //
-    // Storing the Bitcoin block hash redundantly is used to retrieve it quickly without
-    // decoding the entire bitcoin header later.
+    // The Bitcoin block hash is redundant, it's kept as the Substrate block hashes of the
+    // existing chains commit to it.
     Digest {
         logs: vec![
             DigestItem::PreRuntime(
                 NAKAMOTO_HASH_ENGINE_ID,
                 bitcoin_block_hash.to_byte_array().to_vec(),
             ),
-            DigestItem::PreRuntime(NAKAMOTO_HEADER_ENGINE_ID, encoded_bitcoin_header),
+            bitcoin_header_digest_item(bitcoin_header),
         ],
     }
 }
@@ -332,56 +622,85 @@ pub enum HeaderError {
     InvalidBitcoinBlockHashDigest,
     MissingBitcoinBlockHeader,
     InvalidBitcoinBlockHeader,
+    /// The Bitcoin block hash digest doesn't match the Bitcoin header.
+    BitcoinBlockHashMismatch,
 }
 
-/// Extracts the Bitcoin block hash from the given Substrate header.
-pub fn extract_bitcoin_block_hash<Block: BlockT>(
-    header: &Block::Header,
-) -> Result<BlockHash, HeaderError> {
+/// Returns the payload of the only pre-runtime digest of `engine_id`.
+fn pre_runtime_digest(
+    digest: &Digest,
+    engine_id: sp_runtime::ConsensusEngineId,
+) -> Result<Option<&Vec<u8>>, HeaderError> {
     let mut pre_digest: Option<_> = None;
 
-    for log in header.digest().logs() {
+    for log in digest.logs() {
         tracing::trace!("Checking log {:?}, looking for pre runtime digest", log);
         match (log, pre_digest.is_some()) {
-            (DigestItem::PreRuntime(NAKAMOTO_HASH_ENGINE_ID, _), true) => {
+            (DigestItem::PreRuntime(id, _), true) if *id == engine_id => {
                 return Err(HeaderError::MultiplePreRuntimeDigests)
             }
-            (DigestItem::PreRuntime(NAKAMOTO_HASH_ENGINE_ID, v), false) => {
+            (DigestItem::PreRuntime(id, v), false) if *id == engine_id => {
                 pre_digest.replace(v);
             }
             (_, _) => tracing::trace!("Ignoring digest not meant for us"),
         }
     }
 
-    let bitcoin_block_hash = pre_digest.ok_or(HeaderError::MissingBitcoinBlockHashDigest)?;
+    Ok(pre_digest)
+}
+
+/// Extracts the Bitcoin header from the Substrate header digest.
+///
+/// The Bitcoin block hash digest, if any, must match the extracted header.
+pub fn bitcoin_header_from_digest(digest: &Digest) -> Result<BitcoinHeader, HeaderError> {
+    let encoded_header = pre_runtime_digest(digest, NAKAMOTO_HEADER_ENGINE_ID)?
+        .ok_or(HeaderError::MissingBitcoinBlockHeader)?;
+
+    if encoded_header.len() != BITCOIN_HEADER_SIZE {
+        return Err(HeaderError::InvalidBitcoinBlockHeader);
+    }
+
+    let bitcoin_header = BitcoinHeader::consensus_decode(&mut encoded_header.as_slice())
+        .map_err(|_| HeaderError::InvalidBitcoinBlockHeader)?;
+
+    if let Some(block_hash) = pre_runtime_digest(digest, NAKAMOTO_HASH_ENGINE_ID)? {
+        let block_hash = BlockHash::from_slice(block_hash)
+            .map_err(|_| HeaderError::InvalidBitcoinBlockHashDigest)?;
+        if block_hash != bitcoin_header.block_hash() {
+            return Err(HeaderError::BitcoinBlockHashMismatch);
+        }
+    }
+
+    Ok(bitcoin_header)
+}
+
+/// Extracts the Bitcoin header from the given Substrate header.
+pub fn extract_bitcoin_header<Block: BlockT>(
+    header: &Block::Header,
+) -> Result<BitcoinHeader, HeaderError> {
+    bitcoin_header_from_digest(header.digest())
+}
+
+/// Extracts the Bitcoin block hash from the given Substrate header.
+///
+/// Reads the stored hash digest as is, use [`extract_bitcoin_header`] for the headers not
+/// imported locally to check the hash against the Bitcoin header.
+pub fn extract_bitcoin_block_hash<Block: BlockT>(
+    header: &Block::Header,
+) -> Result<BlockHash, HeaderError> {
+    let bitcoin_block_hash = pre_runtime_digest(header.digest(), NAKAMOTO_HASH_ENGINE_ID)?
+        .ok_or(HeaderError::MissingBitcoinBlockHashDigest)?;
 
     BlockHash::from_slice(bitcoin_block_hash)
         .map_err(|_| HeaderError::InvalidBitcoinBlockHashDigest)
 }
 
 /// Extracts the Bitcoin block header from the given Substrate header.
+#[deprecated(note = "Use `extract_bitcoin_header()`")]
 pub fn extract_bitcoin_block_header<Block: BlockT>(
     header: &Block::Header,
 ) -> Result<BitcoinHeader, HeaderError> {
-    let mut pre_digest: Option<_> = None;
-
-    for log in header.digest().logs() {
-        tracing::trace!("Checking log {:?}, looking for pre runtime digest", log);
-        match (log, pre_digest.is_some()) {
-            (DigestItem::PreRuntime(NAKAMOTO_HEADER_ENGINE_ID, _), true) => {
-                return Err(HeaderError::MultiplePreRuntimeDigests)
-            }
-            (DigestItem::PreRuntime(NAKAMOTO_HEADER_ENGINE_ID, v), false) => {
-                pre_digest.replace(v);
-            }
-            (_, _) => tracing::trace!("Ignoring digest not meant for us"),
-        }
-    }
-
-    let bitcoin_block_header = pre_digest.ok_or(HeaderError::MissingBitcoinBlockHeader)?;
-
-    BitcoinHeader::consensus_decode(&mut bitcoin_block_header.as_slice())
-        .map_err(|_| HeaderError::InvalidBitcoinBlockHeader)
+    extract_bitcoin_header::<Block>(header)
 }
 
 /// Converts a Substrate block to a Bitcoin block.
@@ -391,7 +710,7 @@ pub fn convert_to_bitcoin_block<
 >(
     substrate_block: Block,
 ) -> Result<BitcoinBlock, HeaderError> {
-    let header = extract_bitcoin_block_header::<Block>(substrate_block.header())?;
+    let header = extract_bitcoin_header::<Block>(substrate_block.header())?;
 
     let txdata = substrate_block
         .extrinsics()
@@ -401,3 +720,109 @@ pub fn convert_to_bitcoin_block<
 
     Ok(BitcoinBlock { header, txdata })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestCoinStorageKey;
+
+    impl CoinStorageKey for TestCoinStorageKey {
+        fn storage_key(&self, txid: bitcoin::Txid, vout: u32) -> Vec<u8> {
+            let mut key = self.storage_prefix().to_vec();
+            key.extend(txid.to_byte_array());
+            key.extend(vout.to_le_bytes());
+            key
+        }
+
+        fn storage_prefix(&self) -> [u8; 32] {
+            [0u8; 32]
+        }
+
+        fn ongoing_coins_migration_key(&self) -> [u8; 32] {
+            [1u8; 32]
+        }
+
+        fn max_script_size(&self) -> usize {
+            subcoin_runtime_primitives::MAX_SCRIPT_SIZE
+        }
+    }
+
+    #[test]
+    fn test_decode_outpoint() {
+        let txid = bitcoin::Txid::from_byte_array([1u8; 32]);
+        let key = TestCoinStorageKey.storage_key(txid, 3);
+        assert_eq!(
+            TestCoinStorageKey.outpoint(&key),
+            Some(bitcoin::OutPoint { txid, vout: 3 })
+        );
+        assert_eq!(TestCoinStorageKey.outpoint(&key[..40]), None);
+    }
+
+    #[test]
+    fn test_txid_storage_prefix() {
+        let txid = bitcoin::Txid::from_byte_array([1u8; 32]);
+        let prefix = TestCoinStorageKey.txid_storage_prefix(txid);
+        assert_eq!(prefix.len(), 64);
+        assert!(TestCoinStorageKey.storage_key(txid, 7).starts_with(&prefix));
+        assert!(!TestCoinStorageKey
+            .storage_key(bitcoin::Txid::from_byte_array([2u8; 32]), 0)
+            .starts_with(&prefix));
+    }
+
+    #[test]
+    fn test_block_pruning() {
+        assert!(BlockPruning::new(MIN_PRUNE_TARGET - 1).is_none());
+
+        let pruning = BlockPruning::new(MIN_PRUNE_TARGET).unwrap();
+        assert_eq!(pruning.blocks_to_keep(), MIN_BLOCKS_TO_KEEP);
+        assert_eq!(pruning.prune_height(100), 0);
+        assert_eq!(pruning.prune_height(287), 0);
+        assert_eq!(pruning.prune_height(288), 1);
+
+        // 10 GiB keeps the bodies of 2684 full blocks.
+        let pruning = BlockPruning::new(10 * 1024).unwrap();
+        assert_eq!(pruning.blocks_to_keep(), 2684);
+        assert_eq!(pruning.prune_height(10_000), 7317);
+    }
+
+    #[test]
+    fn test_bitcoin_header_digest() {
+        let bitcoin_header = genesis_block(bitcoin::Network::Bitcoin).header;
+
+        let digest = substrate_header_digest(&bitcoin_header);
+        assert_eq!(bitcoin_header_from_digest(&digest).unwrap(), bitcoin_header);
+
+        let digest = Digest {
+            logs: vec![bitcoin_header_digest_item(&bitcoin_header)],
+        };
+        assert_eq!(bitcoin_header_from_digest(&digest).unwrap(), bitcoin_header);
+
+        let digest = Digest {
+            logs: vec![
+                DigestItem::PreRuntime(NAKAMOTO_HASH_ENGINE_ID, vec![0u8; 32]),
+                bitcoin_header_digest_item(&bitcoin_header),
+            ],
+        };
+        assert!(matches!(
+            bitcoin_header_from_digest(&digest),
+            Err(HeaderError::BitcoinBlockHashMismatch)
+        ));
+
+        let mut encoded_header = Vec::new();
+        bitcoin_header
+            .consensus_encode(&mut encoded_header)
+            .unwrap();
+        encoded_header.push(0);
+        let digest = Digest {
+            logs: vec![DigestItem::PreRuntime(
+                NAKAMOTO_HEADER_ENGINE_ID,
+                encoded_header,
+            )],
+        };
+        assert!(matches!(
+            bitcoin_header_from_digest(&digest),
+            Err(HeaderError::InvalidBitcoinBlockHeader)
+        ));
+    }
+}
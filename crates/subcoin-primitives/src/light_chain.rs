@@ -0,0 +1,217 @@
+//! Best header chain of the light client.
+//!
+//! The light client keeps neither the blocks nor the UTXO set, the verified headers are stored
+//! as the [`HeaderEntry`]s of the [`HeaderChain`]. The best chain is the one with the most
+//! work, tracked by its tip and a height index in the aux-db. The heights with no index entry
+//! fall back to the Substrate blocks, i.e., the genesis block on a fresh light client.
+
+use crate::{locator_indexes, BackendExt, BlockLocator, HeaderChain, HeaderEntry, Height};
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::hashes::Hash;
+use bitcoin::merkle_tree::{MerkleBlock, MerkleBlockError};
+use bitcoin::{BlockHash, Txid};
+use sc_client_api::AuxStore;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::sync::Arc;
+
+/// Aux-db key of the hash of the light chain tip.
+const LIGHT_TIP_KEY: &[u8] = b"light_tip";
+
+/// Prefix of the aux-db key of the best block hash at a height.
+const LIGHT_HASH_PREFIX: &[u8] = b"lighthash";
+
+fn light_hash_key(height: Height) -> Vec<u8> {
+    let mut key = LIGHT_HASH_PREFIX.to_vec();
+    key.extend_from_slice(&height.to_le_bytes());
+    key
+}
+
+/// Error of verifying a merkle inclusion proof.
+#[derive(Debug, thiserror::Error)]
+pub enum ProofError {
+    #[error("Invalid merkle proof: {0}")]
+    InvalidProof(MerkleBlockError),
+    #[error("Block {0} is not in the best header chain")]
+    BlockNotInBestChain(BlockHash),
+}
+
+/// Transactions proven to be included in a block of the best header chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxInclusion {
+    pub block_hash: BlockHash,
+    pub height: Height,
+    /// Number of the blocks on top of the block, including itself.
+    pub confirmations: u32,
+    pub txids: Vec<Txid>,
+}
+
+/// Best header chain of the light client, backed by the aux-db of the client.
+pub struct LightChain<Block, Client> {
+    client: Arc<Client>,
+    header_chain: HeaderChain<Block, Client>,
+}
+
+impl<Block, Client> Clone for LightChain<Block, Client> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            header_chain: self.header_chain.clone(),
+        }
+    }
+}
+
+impl<Block, Client> LightChain<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    /// Constructs a new instance of [`LightChain`].
+    pub fn new(client: Arc<Client>) -> Self {
+        Self {
+            header_chain: HeaderChain::new(client.clone()),
+            client,
+        }
+    }
+
+    /// Returns the entry of the tip, the best Substrate block if no header has been synced.
+    pub fn tip(&self) -> HeaderEntry {
+        self.client
+            .get_aux(LIGHT_TIP_KEY)
+            .ok()
+            .flatten()
+            .and_then(|hash| BlockHash::from_slice(&hash).ok())
+            .and_then(|hash| self.header_chain.entry(hash))
+            .or_else(|| {
+                let best_hash =
+                    BackendExt::<Block>::block_hash(&self.client, self.client.info().best_number)?;
+                self.header_chain.entry(best_hash)
+            })
+            .expect("Best block must exist; qed")
+    }
+
+    /// Returns the hash of the block at `height` in the best chain.
+    pub fn best_hash(&self, height: Height) -> Option<BlockHash> {
+        self.best_hash_below(height, self.tip().height)
+    }
+
+    fn best_hash_below(&self, height: Height, tip_height: Height) -> Option<BlockHash> {
+        if height > tip_height {
+            return None;
+        }
+
+        self.client
+            .get_aux(&light_hash_key(height))
+            .ok()
+            .flatten()
+            .and_then(|hash| BlockHash::from_slice(&hash).ok())
+            .or_else(|| self.header_chain.best_hash(height))
+    }
+
+    /// Returns the entry of the Bitcoin block, which is not necessarily in the best chain.
+    pub fn entry(&self, block_hash: BlockHash) -> Option<HeaderEntry> {
+        self.header_chain.entry(block_hash)
+    }
+
+    /// Returns the entry of the block if it's in the best chain.
+    pub fn best_entry(&self, block_hash: BlockHash) -> Option<HeaderEntry> {
+        self.entry(block_hash)
+            .filter(|entry| self.best_hash(entry.height) == Some(block_hash))
+    }
+
+    /// Inserts the verified header on top of its parent, returns `true` if the header became
+    /// the new tip.
+    ///
+    /// The best chain switches to the header once it has more work than the current tip.
+    pub fn insert(&self, header: BitcoinHeader) -> sp_blockchain::Result<bool> {
+        let entry = self.header_chain.insert(header)?;
+        let tip = self.tip();
+
+        if entry.chain_work <= tip.chain_work {
+            return Ok(false);
+        }
+
+        let block_hash = header.block_hash();
+
+        // Index the new best chain down to the fork point.
+        let mut index = vec![(light_hash_key(entry.height), block_hash)];
+        let mut cursor = entry;
+        while cursor.height > 0 {
+            let parent_hash = cursor.header.prev_blockhash;
+            if self.best_hash_below(cursor.height - 1, tip.height) == Some(parent_hash) {
+                break;
+            }
+            cursor = self
+                .entry(parent_hash)
+                .ok_or_else(|| sp_blockchain::Error::MissingHeader(parent_hash.to_string()))?;
+            index.push((light_hash_key(cursor.height), parent_hash));
+        }
+
+        let stale = ((entry.height + 1)..=tip.height)
+            .map(light_hash_key)
+            .collect::<Vec<_>>();
+
+        let inserts = index
+            .iter()
+            .map(|(key, hash)| (key.as_slice(), hash.as_byte_array().as_slice()))
+            .chain(std::iter::once((
+                LIGHT_TIP_KEY,
+                block_hash.as_byte_array().as_slice(),
+            )))
+            .collect::<Vec<_>>();
+        let deletes = stale.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        self.client.insert_aux(&inserts, &deletes)?;
+
+        if index.len() > 1 {
+            tracing::debug!(
+                new_tip = ?block_hash,
+                reorg_depth = index.len() - 1,
+                "Light chain switched to a fork with more work"
+            );
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the block locator of the best chain.
+    pub fn block_locator(&self) -> BlockLocator {
+        let tip = self.tip();
+
+        let locator_hashes = locator_indexes(tip.height)
+            .into_iter()
+            .filter_map(|height| self.best_hash_below(height, tip.height))
+            .collect();
+
+        BlockLocator {
+            latest_block: tip.height,
+            locator_hashes,
+        }
+    }
+
+    /// Verifies the merkle proof in the format of `gettxoutproof`, returns the proven
+    /// transactions if the block is in the best chain.
+    pub fn verify_tx_out_proof(
+        &self,
+        merkle_block: &MerkleBlock,
+    ) -> Result<TxInclusion, ProofError> {
+        let mut txids = Vec::new();
+        let mut indexes = Vec::new();
+        merkle_block
+            .extract_matches(&mut txids, &mut indexes)
+            .map_err(ProofError::InvalidProof)?;
+
+        let block_hash = merkle_block.header.block_hash();
+
+        let entry = self
+            .best_entry(block_hash)
+            .ok_or(ProofError::BlockNotInBestChain(block_hash))?;
+
+        Ok(TxInclusion {
+            block_hash,
+            height: entry.height,
+            confirmations: self.tip().height.saturating_sub(entry.height) + 1,
+            txids,
+        })
+    }
+}
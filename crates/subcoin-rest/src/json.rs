@@ -0,0 +1,134 @@
+//! JSON responses, in the same format as Bitcoin Core.
+
+use bitcoin::{Address, Amount, BlockHash, Network, ScriptBuf, Transaction, TxMerkleNode, Txid};
+use serde::Serialize;
+use subcoin_primitives::descriptor::ScriptType;
+
+/// Header of a block.
+#[derive(Debug, Serialize)]
+pub struct HeaderJson {
+    pub hash: BlockHash,
+    /// `-1` if the block is not in the best chain.
+    pub confirmations: i64,
+    pub height: u32,
+    pub version: i32,
+    #[serde(rename = "versionHex")]
+    pub version_hex: String,
+    pub merkleroot: TxMerkleNode,
+    pub time: u32,
+    pub mediantime: u32,
+    pub nonce: u32,
+    pub bits: String,
+    pub difficulty: f64,
+    #[serde(rename = "nTx", skip_serializing_if = "Option::is_none")]
+    pub n_tx: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previousblockhash: Option<BlockHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nextblockhash: Option<BlockHash>,
+}
+
+/// Transaction included in a block.
+#[derive(Debug, Serialize)]
+pub struct TransactionJson {
+    pub txid: Txid,
+    pub hash: bitcoin::Wtxid,
+    pub version: i32,
+    pub size: usize,
+    pub vsize: usize,
+    pub weight: u64,
+    pub locktime: u32,
+    pub hex: String,
+}
+
+impl From<&Transaction> for TransactionJson {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            txid: tx.compute_txid(),
+            hash: tx.compute_wtxid(),
+            version: tx.version.0,
+            size: tx.total_size(),
+            vsize: tx.vsize(),
+            weight: tx.weight().to_wu(),
+            locktime: tx.lock_time.to_consensus_u32(),
+            hex: bitcoin::consensus::encode::serialize_hex(tx),
+        }
+    }
+}
+
+/// Transactions of a block, either the full details or the txids only.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BlockTransactions {
+    Details(Vec<TransactionJson>),
+    Txids(Vec<Txid>),
+}
+
+/// Block with the transactions.
+#[derive(Debug, Serialize)]
+pub struct BlockJson {
+    #[serde(flatten)]
+    pub header: HeaderJson,
+    pub size: usize,
+    pub strippedsize: usize,
+    pub weight: u64,
+    pub tx: BlockTransactions,
+}
+
+/// Script of an unspent output.
+#[derive(Debug, Serialize)]
+pub struct ScriptPubKeyJson {
+    pub asm: String,
+    pub hex: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(rename = "type")]
+    pub script_type: &'static str,
+}
+
+impl ScriptPubKeyJson {
+    pub fn new(script_pubkey: &ScriptBuf, network: Network) -> Self {
+        Self {
+            asm: script_pubkey.to_asm_string(),
+            hex: script_pubkey.to_hex_string(),
+            address: Address::from_script(script_pubkey, network)
+                .ok()
+                .map(|address| address.to_string()),
+            script_type: ScriptType::from_script(script_pubkey).as_str(),
+        }
+    }
+}
+
+/// Unspent output returned by `getutxos`.
+#[derive(Debug, Serialize)]
+pub struct UtxoJson {
+    pub height: u32,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub value: Amount,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: ScriptPubKeyJson,
+}
+
+/// Response of `getutxos`.
+#[derive(Debug, Serialize)]
+pub struct GetUtxosJson {
+    #[serde(rename = "chainHeight")]
+    pub chain_height: u32,
+    #[serde(rename = "chaintipHash")]
+    pub chaintip_hash: BlockHash,
+    /// One character per requested outpoint, `1` if the output is unspent.
+    pub bitmap: String,
+    pub utxos: Vec<UtxoJson>,
+}
+
+/// Response of `chaininfo`.
+#[derive(Debug, Serialize)]
+pub struct ChainInfoJson {
+    pub chain: &'static str,
+    pub blocks: u32,
+    pub headers: u32,
+    pub bestblockhash: BlockHash,
+    pub difficulty: f64,
+    pub mediantime: u32,
+    pub pruned: bool,
+}
@@ -0,0 +1,684 @@
+//! REST interface compatible with Bitcoin Core's `/rest` endpoints.
+//!
+//! Supported endpoints:
+//!
+//! - `/rest/block/<hash>.<bin|hex|json>`
+//! - `/rest/block/notxdetails/<hash>.<bin|hex|json>`
+//! - `/rest/headers/<hash>.<bin|hex|json>?count=<count>`
+//! - `/rest/headers/<count>/<hash>.<bin|hex|json>` (deprecated in Bitcoin Core)
+//! - `/rest/blockhashbyheight/<height>.<bin|hex|json>`
+//! - `/rest/getutxos/<txid>-<n>/<txid>-<n>/.../<bin|hex|json>`
+//! - `/rest/chaininfo.json`
+//!
+//! The interface is unauthenticated and read-only, it's meant to be served on a trusted
+//! network only.
+
+mod json;
+
+use self::json::{
+    BlockJson, BlockTransactions, ChainInfoJson, GetUtxosJson, HeaderJson, ScriptPubKeyJson,
+    TransactionJson, UtxoJson,
+};
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::DisplayHex;
+use bitcoin::{Amount, Block as BitcoinBlock, BlockHash, OutPoint, ScriptBuf, TxOut, Txid};
+use codec::Decode;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use sc_client_api::{AuxStore, Backend, BlockBackend, HeaderBackend, StorageProvider};
+use sp_runtime::traits::Block as BlockT;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{
+    convert_to_bitcoin_block, BackendExt, BitcoinTransactionAdapter, ClientExt, CoinStorageKey,
+};
+
+/// Maximum number of headers returned by `/rest/headers`, same as Bitcoin Core.
+const MAX_REST_HEADERS_RESULTS: u32 = 2000;
+
+/// Maximum number of outpoints queried by `/rest/getutxos`, same as Bitcoin Core.
+const MAX_GETUTXOS_OUTPOINTS: usize = 15;
+
+/// Number of blocks used for calculating the median time past.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Format of the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Bin,
+    Hex,
+    Json,
+}
+
+/// Error response, sent as plain text.
+#[derive(Debug)]
+struct RestError {
+    status: StatusCode,
+    message: String,
+}
+
+impl RestError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<sp_blockchain::Error> for RestError {
+    fn from(err: sp_blockchain::Error) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: err.to_string(),
+        }
+    }
+}
+
+type RestResult = Result<Response<Full<Bytes>>, RestError>;
+
+/// Splits `<param>.<format>` and checks the format is one of `available`.
+fn parse_format<'a>(s: &'a str, available: &[Format]) -> Result<(&'a str, Format), RestError> {
+    let unavailable = || {
+        let names = available
+            .iter()
+            .map(|format| match format {
+                Format::Bin => "bin",
+                Format::Hex => "hex",
+                Format::Json => "json",
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        RestError::not_found(format!("output format not found (available: {names})"))
+    };
+
+    let (param, format) = s.rsplit_once('.').ok_or_else(unavailable)?;
+
+    let format = match format {
+        "bin" => Format::Bin,
+        "hex" => Format::Hex,
+        "json" => Format::Json,
+        _ => return Err(unavailable()),
+    };
+
+    if !available.contains(&format) {
+        return Err(unavailable());
+    }
+
+    Ok((param, format))
+}
+
+fn parse_hash(s: &str) -> Result<BlockHash, RestError> {
+    BlockHash::from_str(s).map_err(|_| RestError::bad_request(format!("Invalid hash: {s}")))
+}
+
+fn parse_outpoint(s: &str) -> Result<OutPoint, RestError> {
+    let invalid = || RestError::bad_request("Parse error");
+    let (txid, vout) = s.split_once('-').ok_or_else(invalid)?;
+    Ok(OutPoint {
+        txid: Txid::from_str(txid).map_err(|_| invalid())?,
+        vout: vout.parse().map_err(|_| invalid())?,
+    })
+}
+
+fn query_param<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(k, v)| (k == key).then_some(v))
+}
+
+fn response(format: Format, bin: Vec<u8>, json: impl FnOnce() -> serde_json::Value) -> RestResult {
+    let (content_type, body) = match format {
+        Format::Bin => ("application/octet-stream", bin),
+        Format::Hex => {
+            let mut hex = bin.as_slice().to_lower_hex_string();
+            hex.push('\n');
+            ("text/plain", hex.into_bytes())
+        }
+        Format::Json => {
+            let mut json = json().to_string();
+            json.push('\n');
+            ("application/json", json.into_bytes())
+        }
+    };
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(Full::new(Bytes::from(body)))
+        .expect("Response is valid; qed"))
+}
+
+fn to_json(value: impl serde::Serialize) -> serde_json::Value {
+    serde_json::to_value(value).expect("JSON responses are serializable; qed")
+}
+
+/// Returns the name of the network used in `chaininfo`, same as Bitcoin Core.
+fn chain_name(network: bitcoin::Network) -> &'static str {
+    match network {
+        bitcoin::Network::Bitcoin => "main",
+        bitcoin::Network::Testnet => "test",
+        bitcoin::Network::Signet => "signet",
+        bitcoin::Network::Regtest => "regtest",
+        _ => "unknown",
+    }
+}
+
+/// Serializes the unspent outputs in the binary format of Bitcoin Core's `getutxos`.
+fn encode_utxos(
+    chain_height: u32,
+    chaintip_hash: BlockHash,
+    bitmap: &[bool],
+    utxos: &[(u32, TxOut)],
+) -> Vec<u8> {
+    let mut bitmap_bytes = vec![0u8; bitmap.len().div_ceil(8)];
+    for (i, hit) in bitmap.iter().enumerate() {
+        bitmap_bytes[i / 8] |= (*hit as u8) << (i % 8);
+    }
+
+    let mut data = Vec::new();
+    (chain_height as i32)
+        .consensus_encode(&mut data)
+        .expect("Encoding into vec never fails; qed");
+    chaintip_hash
+        .consensus_encode(&mut data)
+        .expect("Encoding into vec never fails; qed");
+    bitmap_bytes
+        .consensus_encode(&mut data)
+        .expect("Encoding into vec never fails; qed");
+    bitcoin::VarInt(utxos.len() as u64)
+        .consensus_encode(&mut data)
+        .expect("Encoding into vec never fails; qed");
+    for (height, txout) in utxos {
+        // Dummy transaction version kept for compatibility.
+        0u32.consensus_encode(&mut data)
+            .expect("Encoding into vec never fails; qed");
+        height
+            .consensus_encode(&mut data)
+            .expect("Encoding into vec never fails; qed");
+        txout
+            .consensus_encode(&mut data)
+            .expect("Encoding into vec never fails; qed");
+    }
+    data
+}
+
+/// REST service.
+pub struct Rest<Block, Client, BE, TransactionAdapter> {
+    client: Arc<Client>,
+    network: bitcoin::Network,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    _phantom: PhantomData<(Block, BE, TransactionAdapter)>,
+}
+
+impl<Block, Client, BE, TransactionAdapter> Rest<Block, Client, BE, TransactionAdapter>
+where
+    Block: BlockT + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore
+        + Send
+        + Sync
+        + 'static,
+    TransactionAdapter: BitcoinTransactionAdapter<Block> + Send + Sync + 'static,
+{
+    /// Constructs a new instance of [`Rest`].
+    pub fn new(
+        client: Arc<Client>,
+        network: bitcoin::Network,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+    ) -> Self {
+        Self {
+            client,
+            network,
+            coin_storage_key,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Serves the REST interface on `addr`.
+    pub async fn run(self, addr: SocketAddr) {
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!(?err, "Failed to bind the REST server on {addr}");
+                return;
+            }
+        };
+
+        tracing::info!("Running REST server: addr={addr}");
+
+        let rest = Arc::new(self);
+
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _remote_addr)) => stream,
+                Err(err) => {
+                    tracing::debug!(?err, "Failed to accept the REST connection");
+                    continue;
+                }
+            };
+
+            let rest = rest.clone();
+
+            tokio::spawn(async move {
+                let service = hyper::service::service_fn(move |req: Request<Incoming>| {
+                    let rest = rest.clone();
+                    async move { Ok::<_, Infallible>(rest.serve(req).await) }
+                });
+
+                if let Err(err) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!(?err, "Error serving the REST connection");
+                }
+            });
+        }
+    }
+
+    async fn serve(self: Arc<Self>, req: Request<Incoming>) -> Response<Full<Bytes>> {
+        if req.method() != Method::GET {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Full::new(Bytes::new()))
+                .expect("Response is valid; qed");
+        }
+
+        let path = req.uri().path().to_string();
+        let query = req.uri().query().map(ToString::to_string);
+
+        let result =
+            tokio::task::spawn_blocking(move || self.handle(&path, query.as_deref())).await;
+
+        match result {
+            Ok(Ok(response)) => response,
+            Ok(Err(RestError { status, message })) => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "text/plain")
+                .body(Full::new(Bytes::from(format!("{message}\r\n"))))
+                .expect("Response is valid; qed"),
+            Err(err) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Full::new(Bytes::from(err.to_string())))
+                .expect("Response is valid; qed"),
+        }
+    }
+
+    fn handle(&self, path: &str, query: Option<&str>) -> RestResult {
+        let path = path
+            .strip_prefix("/rest/")
+            .ok_or_else(|| RestError::not_found("Not found"))?;
+
+        if let Some(param) = path.strip_prefix("block/notxdetails/") {
+            self.block(param, false)
+        } else if let Some(param) = path.strip_prefix("block/") {
+            self.block(param, true)
+        } else if let Some(param) = path.strip_prefix("headers/") {
+            self.headers(param, query)
+        } else if let Some(param) = path.strip_prefix("blockhashbyheight/") {
+            self.block_hash_by_height(param)
+        } else if let Some(param) = path.strip_prefix("getutxos/") {
+            self.get_utxos(param)
+        } else if let Some(param) = path.strip_prefix("chaininfo") {
+            parse_format(param, &[Format::Json])?;
+            self.chain_info()
+        } else {
+            Err(RestError::not_found("Not found"))
+        }
+    }
+
+    fn bitcoin_block(&self, block_hash: BlockHash) -> Result<BitcoinBlock, RestError> {
+        let not_found = || RestError::not_found(format!("{block_hash} not found"));
+
+        let substrate_block_hash = self
+            .client
+            .substrate_block_hash_for(block_hash)
+            .ok_or_else(not_found)?;
+
+        let block = match self.client.block(substrate_block_hash)? {
+            Some(signed_block) => signed_block.block,
+            None if self.client.header(substrate_block_hash)?.is_some() => {
+                return Err(RestError::not_found(format!(
+                    "{block_hash} not available (pruned data)"
+                )));
+            }
+            None => return Err(not_found()),
+        };
+
+        convert_to_bitcoin_block::<Block, TransactionAdapter>(block).map_err(|err| RestError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: format!("Invalid block {block_hash}: {err:?}"),
+        })
+    }
+
+    fn header_json(
+        &self,
+        header: &bitcoin::block::Header,
+        height: u32,
+        n_tx: Option<usize>,
+    ) -> HeaderJson {
+        let block_hash = header.block_hash();
+        let best_number = self.client.best_number();
+
+        let in_best_chain = self.client.block_hash(height) == Some(block_hash);
+
+        HeaderJson {
+            hash: block_hash,
+            confirmations: if in_best_chain {
+                (best_number - height + 1) as i64
+            } else {
+                -1
+            },
+            height,
+            version: header.version.to_consensus(),
+            version_hex: format!("{:08x}", header.version.to_consensus()),
+            merkleroot: header.merkle_root,
+            time: header.time,
+            mediantime: self.median_time_past(header),
+            nonce: header.nonce,
+            bits: format!("{:08x}", header.bits.to_consensus()),
+            difficulty: header.difficulty_float(),
+            n_tx,
+            previousblockhash: (height > 0).then_some(header.prev_blockhash),
+            nextblockhash: in_best_chain
+                .then(|| self.client.block_hash(height + 1))
+                .flatten(),
+        }
+    }
+
+    fn median_time_past(&self, header: &bitcoin::block::Header) -> u32 {
+        let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        timestamps.push(header.time);
+
+        let mut block_hash = header.prev_blockhash;
+
+        while timestamps.len() < MEDIAN_TIME_SPAN && block_hash != BlockHash::all_zeros() {
+            let Some(header) = self.client.block_header(block_hash) else {
+                break;
+            };
+            timestamps.push(header.time);
+            block_hash = header.prev_blockhash;
+        }
+
+        timestamps.sort_unstable();
+
+        timestamps[timestamps.len() / 2]
+    }
+
+    fn block(&self, param: &str, tx_details: bool) -> RestResult {
+        let (hash, format) = parse_format(param, &[Format::Bin, Format::Hex, Format::Json])?;
+        let block_hash = parse_hash(hash)?;
+
+        let block = self.bitcoin_block(block_hash)?;
+
+        let height = self
+            .client
+            .block_number(block_hash)
+            .ok_or_else(|| RestError::not_found(format!("{block_hash} not found")))?;
+
+        response(format, bitcoin::consensus::serialize(&block), || {
+            let tx = if tx_details {
+                BlockTransactions::Details(block.txdata.iter().map(TransactionJson::from).collect())
+            } else {
+                BlockTransactions::Txids(block.txdata.iter().map(|tx| tx.compute_txid()).collect())
+            };
+
+            to_json(BlockJson {
+                header: self.header_json(&block.header, height, Some(block.txdata.len())),
+                size: block.total_size(),
+                strippedsize: block.txdata.iter().map(|tx| tx.base_size()).sum::<usize>()
+                    + bitcoin::block::Header::SIZE
+                    + bitcoin::VarInt(block.txdata.len() as u64).size(),
+                weight: block.weight().to_wu(),
+                tx,
+            })
+        })
+    }
+
+    fn headers(&self, param: &str, query: Option<&str>) -> RestResult {
+        let (count, param) = match param.split_once('/') {
+            Some((count, param)) => (Some(count), param),
+            None => (query_param(query, "count"), param),
+        };
+
+        let count = count.unwrap_or("5");
+
+        let (hash, format) = parse_format(param, &[Format::Bin, Format::Hex, Format::Json])?;
+
+        let count = count
+            .parse::<u32>()
+            .ok()
+            .filter(|count| (1..=MAX_REST_HEADERS_RESULTS).contains(count))
+            .ok_or_else(|| {
+                RestError::bad_request(format!(
+                    "Header count is invalid or out of acceptable range (1-{MAX_REST_HEADERS_RESULTS}): {count}"
+                ))
+            })?;
+
+        let block_hash = parse_hash(hash)?;
+
+        let mut headers = Vec::new();
+
+        if let (Some(header), Some(height)) = (
+            self.client.block_header(block_hash),
+            self.client.block_number(block_hash),
+        ) {
+            headers.push((header, height));
+
+            // Follow the best chain if the requested block is in it.
+            if self.client.block_hash(height) == Some(block_hash) {
+                for next in height + 1..height + count {
+                    let Some(header) = self
+                        .client
+                        .block_hash(next)
+                        .and_then(|hash| self.client.block_header(hash))
+                    else {
+                        break;
+                    };
+                    headers.push((header, next));
+                }
+            }
+        }
+
+        let mut bin = Vec::with_capacity(headers.len() * bitcoin::block::Header::SIZE);
+        for (header, _) in &headers {
+            header
+                .consensus_encode(&mut bin)
+                .expect("Encoding into vec never fails; qed");
+        }
+
+        response(format, bin, || {
+            to_json(
+                headers
+                    .iter()
+                    .map(|(header, height)| self.header_json(header, *height, None))
+                    .collect::<Vec<_>>(),
+            )
+        })
+    }
+
+    fn block_hash_by_height(&self, param: &str) -> RestResult {
+        let (height, format) = parse_format(param, &[Format::Bin, Format::Hex, Format::Json])?;
+
+        let block_hash = height
+            .parse::<u32>()
+            .ok()
+            .and_then(|height| self.client.block_hash(height))
+            .ok_or_else(|| RestError::not_found("Block height out of range"))?;
+
+        response(
+            format,
+            block_hash.to_byte_array().to_vec(),
+            || serde_json::json!({ "blockhash": block_hash }),
+        )
+    }
+
+    fn get_utxos(&self, param: &str) -> RestResult {
+        let (outpoints, format) = parse_format(param, &[Format::Bin, Format::Hex, Format::Json])?;
+
+        // There is no mempool, `checkmempool` is accepted for compatibility.
+        let outpoints = outpoints
+            .strip_prefix("checkmempool")
+            .map(|outpoints| outpoints.trim_start_matches('/'))
+            .unwrap_or(outpoints);
+
+        let outpoints = outpoints
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(parse_outpoint)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if outpoints.is_empty() {
+            return Err(RestError::bad_request("Error: empty request"));
+        }
+
+        if outpoints.len() > MAX_GETUTXOS_OUTPOINTS {
+            return Err(RestError::bad_request(format!(
+                "Error: max outpoints exceeded (max: {MAX_GETUTXOS_OUTPOINTS}, tried: {})",
+                outpoints.len()
+            )));
+        }
+
+        let best_hash = self.client.info().best_hash;
+        let chain_height = self.client.best_number();
+        let chaintip_hash = self
+            .client
+            .block_hash(chain_height)
+            .ok_or_else(|| RestError::not_found("Best block not found"))?;
+
+        let mut bitmap = Vec::with_capacity(outpoints.len());
+        let mut utxos = Vec::new();
+
+        for OutPoint { txid, vout } in outpoints {
+            let storage_key = self.coin_storage_key.storage_key(txid, vout);
+            let coin = self
+                .client
+                .storage(best_hash, &sc_client_api::StorageKey(storage_key))?
+                .and_then(|data| Coin::decode(&mut data.0.as_slice()).ok());
+
+            bitmap.push(coin.is_some());
+
+            if let Some(coin) = coin {
+                utxos.push((
+                    coin.height,
+                    TxOut {
+                        value: Amount::from_sat(coin.amount),
+                        script_pubkey: ScriptBuf::from_bytes(coin.script_pubkey),
+                    },
+                ));
+            }
+        }
+
+        response(
+            format,
+            encode_utxos(chain_height, chaintip_hash, &bitmap, &utxos),
+            || {
+                to_json(GetUtxosJson {
+                    chain_height,
+                    chaintip_hash,
+                    bitmap: bitmap
+                        .iter()
+                        .map(|hit| if *hit { '1' } else { '0' })
+                        .collect(),
+                    utxos: utxos
+                        .iter()
+                        .map(|(height, txout)| UtxoJson {
+                            height: *height,
+                            value: txout.value,
+                            script_pub_key: ScriptPubKeyJson::new(
+                                &txout.script_pubkey,
+                                self.network,
+                            ),
+                        })
+                        .collect(),
+                })
+            },
+        )
+    }
+
+    fn chain_info(&self) -> RestResult {
+        let blocks = self.client.best_number();
+        let bestblockhash = self
+            .client
+            .block_hash(blocks)
+            .ok_or_else(|| RestError::not_found("Best block not found"))?;
+        let header = self
+            .client
+            .block_header(bestblockhash)
+            .ok_or_else(|| RestError::not_found("Best block not found"))?;
+
+        response(Format::Json, Vec::new(), || {
+            to_json(ChainInfoJson {
+                chain: chain_name(self.network),
+                blocks,
+                headers: blocks,
+                bestblockhash,
+                difficulty: header.difficulty_float(),
+                mediantime: self.median_time_past(&header),
+                pruned: false,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        let all = [Format::Bin, Format::Hex, Format::Json];
+        assert_eq!(
+            parse_format("abc.json", &all).unwrap(),
+            ("abc", Format::Json)
+        );
+        assert_eq!(parse_format("a.b.hex", &all).unwrap(), ("a.b", Format::Hex));
+        assert!(parse_format("abc", &all).is_err());
+        assert!(parse_format("abc.xml", &all).is_err());
+        assert!(parse_format(".bin", &[Format::Json]).is_err());
+    }
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param(Some("count=10&foo=bar"), "count"), Some("10"));
+        assert_eq!(query_param(Some("foo=bar"), "count"), None);
+        assert_eq!(query_param(None, "count"), None);
+    }
+
+    #[test]
+    fn test_encode_utxos() {
+        let txout = TxOut {
+            value: Amount::from_sat(1),
+            script_pubkey: ScriptBuf::new(),
+        };
+        let data = encode_utxos(
+            7,
+            BlockHash::all_zeros(),
+            &[true, false, true],
+            &[(1, txout.clone()), (2, txout)],
+        );
+
+        // height + tip hash + bitmap + 2 coins of (dummy version + height + txout)
+        assert_eq!(data.len(), 4 + 32 + 2 + 1 + 2 * (4 + 4 + 9));
+        assert_eq!(&data[..4], &7i32.to_le_bytes());
+        assert_eq!(&data[36..38], &[1, 0b101]);
+    }
+}
@@ -0,0 +1,667 @@
+//! Authentication and per-method access control of the RPC server.
+//!
+//! Each RPC method belongs to one [`PermissionGroup`], the methods not classified explicitly
+//! are admin-only. A client authenticates with HTTP basic
+//! auth, either using the credentials from the `.cookie` file generated on startup, similar
+//! to Bitcoin Core, or the static credentials specified on the command line. A request is only
+//! served if the method group is granted to the authenticated user.
+
+use bitcoin::base64::engine::general_purpose::STANDARD as BASE64;
+use bitcoin::base64::Engine;
+use rand::RngCore;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File name of the cookie, same as Bitcoin Core.
+pub const COOKIE_FILE_NAME: &str = ".cookie";
+
+/// User name of the cookie credentials, same as Bitcoin Core.
+const COOKIE_USER: &str = "__cookie__";
+
+/// Read-only chain and network queries.
+const PUBLIC_METHODS: &[&str] = &[
+    "rpc_methods",
+    // Bitcoin chain.
+    "btc_getHeader",
+    "btc_getBlock",
+    "subcoin_getBlockVerbose3",
+    "subcoin_getDifficulty",
+    "subcoin_getChainWork",
+    "subcoin_getPruneInfo",
+    "subcoin_getBlockStats",
+    "subcoin_subscribeNewBlocks",
+    "subcoin_unsubscribeNewBlocks",
+    "subcoin_subscribeFinalizedBlocks",
+    "subcoin_unsubscribeFinalizedBlocks",
+    "subcoin_subscribeChainReorgs",
+    "subcoin_unsubscribeChainReorgs",
+    "subcoin_getFinalizedBitcoinBlock",
+    "subcoin_getConfirmationDepth",
+    "subcoin_getFinalityConflict",
+    "subcoin_getStateRootAudit",
+    // Transactions and coins.
+    "subcoin_decodeRawTransaction",
+    "subcoin_getRawTransaction",
+    "subcoin_validateTransaction",
+    "subcoin_estimateSmartFee",
+    "subcoin_analyzeDescriptor",
+    "subcoin_scanTxOutSet",
+    "subcoin_listCoins",
+    "subcoin_getCoin",
+    "subcoin_getCoinsForTxid",
+    "subcoin_getUtxoProof",
+    "subcoin_subscribeUtxoProof",
+    "subcoin_unsubscribeUtxoProof",
+    // Indexes.
+    "subcoin_getStatsRange",
+    "subcoin_getUtxoComposition",
+    "subcoin_searchOpReturn",
+    "subcoin_getSilentPaymentTweaks",
+    // Network.
+    "subcoin_networkStatus",
+    "subcoin_networkPeers",
+    "subcoin_getNetworkInfo",
+    // Light client.
+    "light_getBestHeader",
+    "light_getHeader",
+    "light_getHeaders",
+    "light_verifyTxOutProof",
+    // Substrate chain.
+    "chain_getHeader",
+    "chain_getBlock",
+    "chain_getBlockHash",
+    "chain_getHead",
+    "chain_getFinalizedHead",
+    "chain_getFinalisedHead",
+    "chain_subscribeAllHeads",
+    "chain_unsubscribeAllHeads",
+    "chain_subscribeNewHeads",
+    "chain_unsubscribeNewHeads",
+    "chain_subscribeNewHead",
+    "chain_unsubscribeNewHead",
+    "subscribe_newHead",
+    "unsubscribe_newHead",
+    "chain_subscribeFinalizedHeads",
+    "chain_unsubscribeFinalizedHeads",
+    "chain_subscribeFinalisedHeads",
+    "chain_unsubscribeFinalisedHeads",
+    "chain_getRuntimeVersion",
+    "chain_subscribeRuntimeVersion",
+    "chain_unsubscribeRuntimeVersion",
+    // Substrate state.
+    "state_call",
+    "state_callAt",
+    "state_getKeysPaged",
+    "state_getKeysPagedAt",
+    "state_getStorage",
+    "state_getStorageAt",
+    "state_getStorageHash",
+    "state_getStorageHashAt",
+    "state_getStorageSize",
+    "state_getStorageSizeAt",
+    "state_getMetadata",
+    "state_getRuntimeVersion",
+    "state_queryStorageAt",
+    "state_getReadProof",
+    "state_subscribeRuntimeVersion",
+    "state_unsubscribeRuntimeVersion",
+    "state_subscribeStorage",
+    "state_unsubscribeStorage",
+    "childstate_getKeysPaged",
+    "childstate_getKeysPagedAt",
+    "childstate_getStorage",
+    "childstate_getStorageEntries",
+    "childstate_getStorageHash",
+    "childstate_getStorageSize",
+    "childstate_getReadProof",
+    // Substrate system.
+    "system_name",
+    "system_version",
+    "system_chain",
+    "system_chainType",
+    "system_properties",
+    "system_health",
+    "system_localPeerId",
+    "system_localListenAddresses",
+    "system_nodeRoles",
+    "system_syncState",
+];
+
+/// Wallet methods.
+const WALLET_METHODS: &[&str] = &[
+    "subcoin_importDescriptors",
+    "subcoin_listUnspent",
+    "subcoin_getBalances",
+    "subcoin_walletCreateFundedPsbt",
+    "subcoin_finalizeAndBroadcastPsbt",
+    "subcoin_rescanBlockchain",
+    "subcoin_resumeRescan",
+    "subcoin_abortRescan",
+    "subcoin_getRescanProgress",
+];
+
+/// Methods mutating the node state or exposing the node internals.
+const ADMIN_METHODS: &[&str] = &[
+    "subcoin_sendRawTransaction",
+    "subcoin_getPeerInfo",
+    "subcoin_invalidateBlock",
+    "subcoin_reconsiderBlock",
+    "subcoin_submitFinalityCheckpoint",
+    "subcoin_saveMempool",
+    "subcoin_submitPackage",
+    "subcoin_submitBlock",
+    "subcoin_getBlockTemplate",
+    "subcoin_getTransactionBroadcastStatus",
+    "subcoin_startJob",
+    "subcoin_getJob",
+    "subcoin_listJobs",
+    "subcoin_cancelJob",
+    "system_addLogFilter",
+    "system_resetLogFilter",
+    "system_addReservedPeer",
+    "system_removeReservedPeer",
+    "system_reservedPeers",
+    "system_peers",
+    "system_unstable_networkState",
+    "state_getPairs",
+    "state_getKeys",
+    "state_queryStorage",
+    "state_traceBlock",
+];
+
+/// Authentication error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Missing credentials")]
+    MissingCredentials,
+    #[error("Malformed authorization header")]
+    MalformedHeader,
+    #[error("Invalid credentials")]
+    InvalidCredentials,
+    #[error("Invalid permission group: {0}")]
+    InvalidGroup(String),
+    #[error("Invalid credential, expected `user:password[:group,...]`")]
+    InvalidCredential,
+    #[error("Invalid method permission, expected `method=group`")]
+    InvalidMethodPermission,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Permission group of the RPC methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PermissionGroup {
+    /// Read-only chain and network queries.
+    Public,
+    /// Watch-only wallet.
+    Wallet,
+    /// Node administration and transaction broadcasting.
+    Admin,
+}
+
+impl PermissionGroup {
+    /// Returns all the groups.
+    pub fn all() -> BTreeSet<Self> {
+        [Self::Public, Self::Wallet, Self::Admin].into()
+    }
+}
+
+impl fmt::Display for PermissionGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Public => write!(f, "public"),
+            Self::Wallet => write!(f, "wallet"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for PermissionGroup {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(Self::Public),
+            "wallet" => Ok(Self::Wallet),
+            "admin" => Ok(Self::Admin),
+            s => Err(Error::InvalidGroup(s.to_string())),
+        }
+    }
+}
+
+/// Override of the permission group of a method, in the form of `method=group`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodPermission {
+    pub method: String,
+    pub group: PermissionGroup,
+}
+
+impl FromStr for MethodPermission {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, group) = s.split_once('=').ok_or(Error::InvalidMethodPermission)?;
+
+        if method.is_empty() {
+            return Err(Error::InvalidMethodPermission);
+        }
+
+        Ok(Self {
+            method: method.to_string(),
+            group: group.parse()?,
+        })
+    }
+}
+
+/// Mapping from the RPC methods to their permission groups.
+#[derive(Debug, Clone, Default)]
+pub struct MethodPermissions {
+    overrides: HashMap<String, PermissionGroup>,
+}
+
+impl MethodPermissions {
+    /// Constructs a new instance of [`MethodPermissions`] with the overrides of the builtin
+    /// groups.
+    pub fn new(overrides: impl IntoIterator<Item = MethodPermission>) -> Self {
+        Self {
+            overrides: overrides
+                .into_iter()
+                .map(|MethodPermission { method, group }| (method, group))
+                .collect(),
+        }
+    }
+
+    /// Returns the permission group of the method.
+    ///
+    /// Methods not classified explicitly are admin-only, e.g., `author_*` and the methods
+    /// added without updating the lists, so that a new method is never exposed by mistake.
+    pub fn group(&self, method: &str) -> PermissionGroup {
+        if let Some(group) = self.overrides.get(method) {
+            *group
+        } else if PUBLIC_METHODS.contains(&method) {
+            PermissionGroup::Public
+        } else if WALLET_METHODS.contains(&method) {
+            PermissionGroup::Wallet
+        } else {
+            PermissionGroup::Admin
+        }
+    }
+}
+
+/// Credentials of a RPC user.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credential {
+    pub user: String,
+    pub password: String,
+    /// Groups granted to the user.
+    pub groups: BTreeSet<PermissionGroup>,
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credential")
+            .field("user", &self.user)
+            .field("groups", &self.groups)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for Credential {
+    type Err = Error;
+
+    /// Parses `user:password[:group,...]`, all the groups are granted if not specified.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+
+        let (Some(user), Some(password)) = (parts.next(), parts.next()) else {
+            return Err(Error::InvalidCredential);
+        };
+
+        if user.is_empty() || password.is_empty() || user == COOKIE_USER {
+            return Err(Error::InvalidCredential);
+        }
+
+        let groups = match parts.next() {
+            Some(groups) => groups
+                .split(',')
+                .map(PermissionGroup::from_str)
+                .collect::<Result<BTreeSet<_>, _>>()?,
+            None => PermissionGroup::all(),
+        };
+
+        Ok(Self {
+            user: user.to_string(),
+            password: password.to_string(),
+            groups,
+        })
+    }
+}
+
+/// Cookie file holding the credentials generated on startup, removed on drop.
+#[derive(Debug)]
+pub struct Cookie {
+    path: PathBuf,
+}
+
+impl Cookie {
+    /// Writes a cookie with a random password to `dir`.
+    ///
+    /// The cookie user is granted all the groups.
+    pub fn generate(dir: &Path) -> Result<(Self, Credential), Error> {
+        let mut secret = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut secret);
+
+        let credential = Credential {
+            user: COOKIE_USER.to_string(),
+            password: hex::encode(secret),
+            groups: PermissionGroup::all(),
+        };
+
+        let path = dir.join(COOKIE_FILE_NAME);
+
+        std::fs::create_dir_all(dir)?;
+        write_private(
+            &path,
+            format!("{}:{}", credential.user, credential.password).as_bytes(),
+        )?;
+
+        tracing::info!("Generated RPC authentication cookie {}", path.display());
+
+        Ok((Self { path }, credential))
+    }
+
+    /// Returns the path of the cookie file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Cookie {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            tracing::warn!(
+                ?err,
+                "Failed to remove the cookie file {}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, contents)
+}
+
+/// RPC authentication config.
+#[derive(Debug, Clone, Default)]
+pub struct RpcAuth {
+    credentials: Vec<Credential>,
+    /// Whether the unauthenticated requests are allowed to call the public methods.
+    anonymous_public: bool,
+    permissions: MethodPermissions,
+}
+
+impl RpcAuth {
+    /// Constructs a new instance of [`RpcAuth`].
+    pub fn new(
+        credentials: Vec<Credential>,
+        anonymous_public: bool,
+        permissions: MethodPermissions,
+    ) -> Self {
+        Self {
+            credentials,
+            anonymous_public,
+            permissions,
+        }
+    }
+
+    /// Adds the credentials of a RPC user.
+    pub fn add_credential(&mut self, credential: Credential) {
+        self.credentials.push(credential);
+    }
+
+    /// Returns the method permissions.
+    pub fn permissions(&self) -> &MethodPermissions {
+        &self.permissions
+    }
+
+    /// Returns the groups granted to the client of the `Authorization` header.
+    pub fn authenticate(
+        &self,
+        authorization: Option<&str>,
+    ) -> Result<BTreeSet<PermissionGroup>, Error> {
+        let Some(authorization) = authorization else {
+            return if self.anonymous_public {
+                Ok([PermissionGroup::Public].into())
+            } else {
+                Err(Error::MissingCredentials)
+            };
+        };
+
+        let encoded = authorization
+            .strip_prefix("Basic ")
+            .ok_or(Error::MalformedHeader)?;
+
+        let decoded = BASE64
+            .decode(encoded.trim())
+            .map_err(|_| Error::MalformedHeader)?;
+
+        let decoded = String::from_utf8(decoded).map_err(|_| Error::MalformedHeader)?;
+
+        let (user, password) = decoded.split_once(':').ok_or(Error::MalformedHeader)?;
+
+        // Check all the credentials to not leak the position of the matching one.
+        let mut granted = None;
+        for credential in &self.credentials {
+            let matched = constant_time_eq(credential.user.as_bytes(), user.as_bytes())
+                & constant_time_eq(credential.password.as_bytes(), password.as_bytes());
+            if matched && granted.is_none() {
+                granted.replace(credential.groups.clone());
+            }
+        }
+
+        granted.ok_or(Error::InvalidCredentials)
+    }
+}
+
+/// Compares two byte strings in constant time with respect to their contents.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", BASE64.encode(credentials))
+    }
+
+    #[test]
+    fn test_parse_credential() {
+        let credential: Credential = "alice:secret:public,wallet".parse().unwrap();
+        assert_eq!(credential.user, "alice");
+        assert_eq!(credential.password, "secret");
+        assert_eq!(
+            credential.groups,
+            [PermissionGroup::Public, PermissionGroup::Wallet].into()
+        );
+
+        let credential: Credential = "bob:secret".parse().unwrap();
+        assert_eq!(credential.groups, PermissionGroup::all());
+
+        assert!("alice".parse::<Credential>().is_err());
+        assert!("alice:".parse::<Credential>().is_err());
+        assert!("__cookie__:secret".parse::<Credential>().is_err());
+        assert!("alice:secret:root".parse::<Credential>().is_err());
+    }
+
+    #[test]
+    fn test_method_permissions() {
+        let permissions = MethodPermissions::new(["subcoin_networkPeers=admin"
+            .parse::<MethodPermission>()
+            .unwrap()]);
+
+        assert_eq!(
+            permissions.group("subcoin_getBalances"),
+            PermissionGroup::Wallet
+        );
+        assert_eq!(
+            permissions.group("author_rotateKeys"),
+            PermissionGroup::Admin
+        );
+        assert_eq!(
+            permissions.group("subcoin_networkPeers"),
+            PermissionGroup::Admin
+        );
+        assert_eq!(
+            permissions.group("subcoin_submitBlock"),
+            PermissionGroup::Admin
+        );
+        assert_eq!(
+            permissions.group("subcoin_submitFinalityCheckpoint"),
+            PermissionGroup::Admin
+        );
+        assert_eq!(
+            permissions.group("chain_getHeader"),
+            PermissionGroup::Public
+        );
+        assert_eq!(
+            permissions.group("subcoin_unknownMethod"),
+            PermissionGroup::Admin
+        );
+    }
+
+    /// Returns the method names, including the aliases and the unsubscribe methods, of the
+    /// `#[method]` and `#[subscription]` attributes in the source.
+    fn registered_methods(source: &str) -> Vec<&str> {
+        let mut methods = Vec::new();
+
+        for attribute in source
+            .split("#[method(")
+            .skip(1)
+            .chain(source.split("#[subscription(").skip(1))
+        {
+            let attribute = &attribute[..attribute.find(")]").unwrap()];
+
+            let parts = attribute.split('"').collect::<Vec<_>>();
+
+            for index in (1..parts.len()).step_by(2) {
+                // Skip the notification name of `name = "method" => "notification"`.
+                if !parts[index - 1].trim_end().ends_with("=>") {
+                    methods.push(parts[index]);
+                }
+            }
+        }
+
+        methods
+    }
+
+    #[test]
+    fn test_all_methods_classified() {
+        let sources = [
+            include_str!("blockchain.rs"),
+            include_str!("fee_estimation.rs"),
+            include_str!("jobs.rs"),
+            include_str!("light.rs"),
+            include_str!("mining.rs"),
+            include_str!("op_return.rs"),
+            include_str!("raw_transactions.rs"),
+            include_str!("scan.rs"),
+            include_str!("silent_payments.rs"),
+            include_str!("state_root_audit.rs"),
+            include_str!("stats.rs"),
+            include_str!("subcoin.rs"),
+            include_str!("utxo_proof.rs"),
+            include_str!("wallet.rs"),
+        ];
+
+        let methods = sources
+            .into_iter()
+            .flat_map(registered_methods)
+            .collect::<Vec<_>>();
+        assert!(methods.contains(&"chain_unsubscribeFinalisedHeads"));
+        assert!(!methods.contains(&"chain_finalizedHead"));
+
+        for method in methods {
+            let lists = [PUBLIC_METHODS, WALLET_METHODS, ADMIN_METHODS]
+                .into_iter()
+                .filter(|list| list.contains(&method))
+                .count();
+            assert_eq!(
+                lists, 1,
+                "{method} must be in exactly one of the method lists"
+            );
+        }
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let auth = RpcAuth::new(
+            vec!["alice:secret:public".parse().unwrap()],
+            false,
+            MethodPermissions::default(),
+        );
+
+        assert_eq!(
+            auth.authenticate(Some(&basic("alice:secret"))).unwrap(),
+            [PermissionGroup::Public].into()
+        );
+        assert!(matches!(
+            auth.authenticate(Some(&basic("alice:wrong"))),
+            Err(Error::InvalidCredentials)
+        ));
+        assert!(matches!(
+            auth.authenticate(Some("Bearer token")),
+            Err(Error::MalformedHeader)
+        ));
+        assert!(matches!(
+            auth.authenticate(None),
+            Err(Error::MissingCredentials)
+        ));
+
+        let auth = RpcAuth::new(Vec::new(), true, MethodPermissions::default());
+        assert_eq!(
+            auth.authenticate(None).unwrap(),
+            [PermissionGroup::Public].into()
+        );
+    }
+
+    #[test]
+    fn test_cookie() {
+        let dir = std::env::temp_dir().join(format!("subcoin-cookie-{}", std::process::id()));
+
+        let (cookie, credential) = Cookie::generate(&dir).unwrap();
+        let contents = std::fs::read_to_string(cookie.path()).unwrap();
+        assert_eq!(
+            contents,
+            format!("{}:{}", credential.user, credential.password)
+        );
+
+        let path = cookie.path().to_path_buf();
+        drop(cookie);
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_dir(dir);
+    }
+}
@@ -1,15 +1,241 @@
 use crate::error::Error;
 use bitcoin::blockdata::block::Header as BitcoinHeader;
-use bitcoin::{Block as BitcoinBlock, BlockHash};
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hex::DisplayHex;
+use bitcoin::{
+    Address, Amount, Block as BitcoinBlock, BlockHash, Network, OutPoint, Script, Transaction,
+    TxMerkleNode, TxOut, Txid, Wtxid,
+};
+use codec::Decode;
+use futures::future::Either;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use jsonrpsee::core::SubscriptionResult;
 use jsonrpsee::proc_macros::rpc;
-use sc_client_api::{AuxStore, BlockBackend, HeaderBackend};
-use sp_runtime::traits::Block as BlockT;
+use jsonrpsee::{PendingSubscriptionSink, SubscriptionMessage};
+use sc_client_api::{
+    AuxStore, Backend, BlockBackend, BlockchainEvents, HeaderBackend, StorageKey, StorageProvider,
+};
+use sc_consensus_nakamoto::{chain_reorg_stream, ChainReorg};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, SaturatedConversion};
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use subcoin_primitives::descriptor::ScriptType;
+use subcoin_primitives::runtime::Coin;
 use subcoin_primitives::{
-    convert_to_bitcoin_block, extract_bitcoin_block_header, BackendExt, BitcoinTransactionAdapter,
+    convert_to_bitcoin_block, extract_bitcoin_header, BackendExt, BitcoinTransactionAdapter,
+    BlockPruning, BlockStats, CoinStorageKey,
 };
 
+/// Number of the blocks used for calculating the median time past.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Notification of a new best or finalized block.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockNotification {
+    pub hash: BlockHash,
+    pub height: u32,
+    pub header: BitcoinHeader,
+    /// Full block, only present if requested on subscribing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<BitcoinBlock>,
+}
+
+/// Block in a chain reorg notification.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ReorgBlock {
+    pub hash: BlockHash,
+    pub height: u32,
+}
+
+impl From<sc_consensus_nakamoto::ReorgBlock> for ReorgBlock {
+    fn from(block: sc_consensus_nakamoto::ReorgBlock) -> Self {
+        Self {
+            hash: block.hash,
+            height: block.height,
+        }
+    }
+}
+
+/// Notification of a switch of the best chain to a fork with more work.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReorgNotification {
+    pub old_tip: ReorgBlock,
+    pub new_tip: ReorgBlock,
+    pub common_ancestor: ReorgBlock,
+    /// Blocks removed from the best chain, ordered from the old tip.
+    pub disconnected: Vec<BlockHash>,
+    /// Blocks added to the best chain, ordered from the common ancestor.
+    pub connected: Vec<BlockHash>,
+}
+
+impl From<ChainReorg> for ReorgNotification {
+    fn from(reorg: ChainReorg) -> Self {
+        Self {
+            old_tip: reorg.old_tip.into(),
+            new_tip: reorg.new_tip.into(),
+            common_ancestor: reorg.common_ancestor.into(),
+            disconnected: reorg.disconnected,
+            connected: reorg.connected,
+        }
+    }
+}
+
+/// Block specified by either hash or height.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HashOrHeight {
+    Height(u32),
+    Hash(BlockHash),
+}
+
+/// Block pruning status.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneInfo {
+    /// Whether the block bodies are pruned.
+    pub pruned: bool,
+    /// Height of the lowest block whose body is available, only present if pruned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prune_height: Option<u32>,
+    /// Target disk usage of the block bodies in bytes, only present if pruned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prune_target_size: Option<u64>,
+}
+
+/// Script signature of a transaction input.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptSig {
+    pub asm: String,
+    pub hex: String,
+}
+
+/// Script pubkey of a transaction output.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptPubKey {
+    pub asm: String,
+    pub hex: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(rename = "type")]
+    pub script_type: String,
+}
+
+impl ScriptPubKey {
+    fn new(script_pubkey: &Script, network: Network) -> Self {
+        Self {
+            asm: script_pubkey.to_asm_string(),
+            hex: script_pubkey.to_hex_string(),
+            address: Address::from_script(script_pubkey, network)
+                .ok()
+                .map(|address| address.to_string()),
+            script_type: ScriptType::from_script(script_pubkey).to_string(),
+        }
+    }
+}
+
+/// Output spent by a transaction input.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Prevout {
+    /// Whether the output was created by a coinbase transaction.
+    pub generated: bool,
+    /// Height of the block in which the output was created.
+    pub height: u32,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub value: Amount,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: ScriptPubKey,
+}
+
+/// Transaction input with the spent output.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerboseTxIn {
+    /// Hex-encoded script signature, only present for the coinbase input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coinbase: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<Txid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vout: Option<u32>,
+    #[serde(rename = "scriptSig", default, skip_serializing_if = "Option::is_none")]
+    pub script_sig: Option<ScriptSig>,
+    /// Hex-encoded witness items.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txinwitness: Option<Vec<String>>,
+    /// Spent output, not present for the coinbase input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prevout: Option<Prevout>,
+    pub sequence: u32,
+}
+
+/// Transaction output.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerboseTxOut {
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub value: Amount,
+    pub n: u32,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: ScriptPubKey,
+}
+
+/// Transaction with the spent outputs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerboseTransaction {
+    pub txid: Txid,
+    /// Witness transaction id.
+    pub hash: Wtxid,
+    pub version: i32,
+    pub size: usize,
+    pub vsize: usize,
+    pub weight: u64,
+    pub locktime: u32,
+    pub vin: Vec<VerboseTxIn>,
+    pub vout: Vec<VerboseTxOut>,
+    /// Transaction fee, not present for the coinbase transaction.
+    #[serde(
+        default,
+        with = "bitcoin::amount::serde::as_btc::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fee: Option<Amount>,
+    /// Serialized, hex-encoded transaction.
+    pub hex: String,
+}
+
+/// Block with the transactions and their spent outputs, in the format of `getblock` with
+/// verbosity 3 in Bitcoin Core.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerboseBlock {
+    pub hash: BlockHash,
+    /// `-1` if the block is not in the best chain.
+    pub confirmations: i64,
+    pub height: u32,
+    pub version: i32,
+    #[serde(rename = "versionHex")]
+    pub version_hex: String,
+    pub merkleroot: TxMerkleNode,
+    pub time: u32,
+    pub mediantime: u32,
+    pub nonce: u32,
+    pub bits: String,
+    pub difficulty: f64,
+    pub chainwork: String,
+    #[serde(rename = "nTx")]
+    pub n_tx: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previousblockhash: Option<BlockHash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nextblockhash: Option<BlockHash>,
+    pub strippedsize: usize,
+    pub size: usize,
+    pub weight: u64,
+    pub tx: Vec<VerboseTransaction>,
+}
+
 /// Bitcoin blockchain API.
 #[rpc(client, server)]
 pub trait BlockchainApi {
@@ -21,6 +247,81 @@ pub trait BlockchainApi {
     #[method(name = "btc_getBlock", blocking)]
     fn block(&self, hash: Option<BlockHash>) -> Result<Option<BitcoinBlock>, Error>;
 
+    /// Get the block with the decoded transactions, each input including the output it spends,
+    /// same with `getblock` with verbosity 3 in Bitcoin Core.
+    ///
+    /// The spent outputs are read from the state of the parent block, which is unavailable
+    /// once the state is pruned.
+    #[method(name = "subcoin_getBlockVerbose3", blocking)]
+    fn block_verbose3(&self, hash: BlockHash) -> Result<VerboseBlock, Error>;
+
+    /// Get the difficulty of the block at given height, defaults to the best block.
+    #[method(name = "subcoin_getDifficulty", blocking)]
+    fn difficulty(&self, height: Option<u32>) -> Result<f64, Error>;
+
+    /// Get the hex-encoded cumulative chainwork up to the block at given height (inclusive),
+    /// defaults to the best block.
+    #[method(name = "subcoin_getChainWork", blocking)]
+    fn chain_work(&self, height: Option<u32>) -> Result<String, Error>;
+
+    /// Get the block pruning status.
+    #[method(name = "subcoin_getPruneInfo", blocking)]
+    fn prune_info(&self) -> Result<PruneInfo, Error>;
+
+    /// Get the per-block statistics, same with `getblockstats` in Bitcoin Core except that
+    /// `utxo_size_inc` is not available.
+    ///
+    /// # Arguments
+    ///
+    /// - `hash_or_height`: Block hash or height.
+    /// - `stats`: Statistics to return, all by default.
+    #[method(name = "subcoin_getBlockStats", blocking)]
+    fn block_stats(
+        &self,
+        hash_or_height: HashOrHeight,
+        stats: Option<Vec<String>>,
+    ) -> Result<Map<String, Value>, Error>;
+
+    /// New best block subscription.
+    ///
+    /// Blocks imported during the major sync are not notified.
+    ///
+    /// # Arguments
+    ///
+    /// - `full_block`: Whether to include the full block, `false` by default.
+    #[subscription(
+        name = "subcoin_subscribeNewBlocks" => "subcoin_newBlock",
+        unsubscribe = "subcoin_unsubscribeNewBlocks",
+        item = BlockNotification
+    )]
+    async fn subscribe_new_blocks(&self, full_block: Option<bool>) -> SubscriptionResult;
+
+    /// Finalized block subscription.
+    ///
+    /// Every finalized block is notified in ascending order, including the ones finalized
+    /// implicitly.
+    ///
+    /// # Arguments
+    ///
+    /// - `full_block`: Whether to include the full block, `false` by default.
+    #[subscription(
+        name = "subcoin_subscribeFinalizedBlocks" => "subcoin_finalizedBlock",
+        unsubscribe = "subcoin_unsubscribeFinalizedBlocks",
+        item = BlockNotification
+    )]
+    async fn subscribe_finalized_blocks(&self, full_block: Option<bool>) -> SubscriptionResult;
+
+    /// Best chain reorg subscription.
+    ///
+    /// A notification is sent whenever the best chain switches to a fork with more work,
+    /// disconnecting at least one block of the previous best chain.
+    #[subscription(
+        name = "subcoin_subscribeChainReorgs" => "subcoin_chainReorg",
+        unsubscribe = "subcoin_unsubscribeChainReorgs",
+        item = ReorgNotification
+    )]
+    async fn subscribe_chain_reorgs(&self) -> SubscriptionResult;
+
     /*
     /// Get hash of the n-th block in the canon chain.
     ///
@@ -66,24 +367,50 @@ pub trait BlockchainApi {
 }
 
 /// This struct provides the Bitcoin Blockchain API.
-pub struct Blockchain<Block, Client, TransactionAdapter> {
+pub struct Blockchain<Block, Client, BE, TransactionAdapter> {
     client: Arc<Client>,
-    _phantom: PhantomData<(Block, TransactionAdapter)>,
+    network: Network,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    block_pruning: Option<BlockPruning>,
+    _phantom: PhantomData<(Block, BE, TransactionAdapter)>,
 }
 
-impl<Block, Client, TransactionAdapter> Blockchain<Block, Client, TransactionAdapter>
+impl<Block, Client, BE, TransactionAdapter> Blockchain<Block, Client, BE, TransactionAdapter>
 where
     Block: BlockT + 'static,
-    Client: HeaderBackend<Block> + BlockBackend<Block> + AuxStore + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore
+        + 'static,
+    TransactionAdapter: BitcoinTransactionAdapter<Block>,
 {
     /// Constructs a new instance of [`Blockchain`].
-    pub fn new(client: Arc<Client>) -> Self {
+    pub fn new(
+        client: Arc<Client>,
+        network: Network,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+        block_pruning: Option<BlockPruning>,
+    ) -> Self {
         Self {
             client,
+            network,
+            coin_storage_key,
+            block_pruning,
             _phantom: Default::default(),
         }
     }
 
+    /// Returns the block, [`Error::BlockPruned`] if only the header is available.
+    fn substrate_block(&self, substrate_block_hash: Block::Hash) -> Result<Block, Error> {
+        match self.client.block(substrate_block_hash)? {
+            Some(signed_block) => Ok(signed_block.block),
+            None if self.client.header(substrate_block_hash)?.is_some() => Err(Error::BlockPruned),
+            None => Err(Error::BlockNotFound),
+        }
+    }
+
     fn substrate_block_hash(&self, bitcoin_hash: Option<BlockHash>) -> Result<Block::Hash, Error> {
         match bitcoin_hash {
             Some(h) => self
@@ -93,28 +420,248 @@ where
             None => Ok(self.client.info().best_hash),
         }
     }
+
+    fn bitcoin_block_hash_at(&self, height: Option<u32>) -> Result<BlockHash, Error> {
+        let height = height.unwrap_or_else(|| self.client.info().best_number.saturated_into());
+        self.client.block_hash(height).ok_or(Error::BlockNotFound)
+    }
+
+    fn median_time_past(&self, mut block_hash: BlockHash) -> u32 {
+        let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+
+        while timestamps.len() < MEDIAN_TIME_SPAN {
+            let Some(header) = self.client.block_header(block_hash) else {
+                break;
+            };
+            timestamps.push(header.time);
+            block_hash = header.prev_blockhash;
+        }
+
+        timestamps.sort_unstable();
+
+        timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+    }
+
+    /// Returns the output spent by `out_point` from the state of the parent block.
+    fn prevout(&self, parent_hash: Block::Hash, out_point: OutPoint) -> Result<Prevout, Error> {
+        let storage_key = self
+            .coin_storage_key
+            .storage_key(out_point.txid, out_point.vout);
+
+        let data = self
+            .client
+            .storage(parent_hash, &StorageKey(storage_key))
+            .map_err(|_| Error::UndoDataPruned)?
+            .ok_or_else(|| Error::Other(format!("Spent output {out_point} not found")))?;
+
+        let coin = Coin::decode(&mut data.0.as_slice())
+            .map_err(|err| Error::Other(format!("Failed to decode coin {out_point}: {err}")))?;
+
+        Ok(Prevout {
+            generated: coin.is_coinbase,
+            height: coin.height,
+            value: Amount::from_sat(coin.amount),
+            script_pub_key: ScriptPubKey::new(
+                Script::from_bytes(&coin.script_pubkey),
+                self.network,
+            ),
+        })
+    }
+
+    /// Decodes the transaction, resolving the spent outputs either from `block_outputs` or the
+    /// state of the parent block.
+    fn verbose_transaction(
+        &self,
+        tx: &Transaction,
+        parent_hash: Block::Hash,
+        block_outputs: &HashMap<OutPoint, (u32, TxOut)>,
+    ) -> Result<VerboseTransaction, Error> {
+        let is_coinbase = tx.is_coinbase();
+
+        let vin = tx
+            .input
+            .iter()
+            .map(|input| {
+                let txinwitness = (!input.witness.is_empty()).then(|| {
+                    input
+                        .witness
+                        .iter()
+                        .map(|item| item.to_lower_hex_string())
+                        .collect()
+                });
+
+                if is_coinbase {
+                    return Ok(VerboseTxIn {
+                        coinbase: Some(input.script_sig.to_hex_string()),
+                        txid: None,
+                        vout: None,
+                        script_sig: None,
+                        txinwitness,
+                        prevout: None,
+                        sequence: input.sequence.to_consensus_u32(),
+                    });
+                }
+
+                let out_point = input.previous_output;
+
+                let prevout = match block_outputs.get(&out_point) {
+                    Some((height, txout)) => Prevout {
+                        generated: false,
+                        height: *height,
+                        value: txout.value,
+                        script_pub_key: ScriptPubKey::new(&txout.script_pubkey, self.network),
+                    },
+                    None => self.prevout(parent_hash, out_point)?,
+                };
+
+                Ok(VerboseTxIn {
+                    coinbase: None,
+                    txid: Some(out_point.txid),
+                    vout: Some(out_point.vout),
+                    script_sig: Some(ScriptSig {
+                        asm: input.script_sig.to_asm_string(),
+                        hex: input.script_sig.to_hex_string(),
+                    }),
+                    txinwitness,
+                    prevout: Some(prevout),
+                    sequence: input.sequence.to_consensus_u32(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let vout = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(n, txout)| VerboseTxOut {
+                value: txout.value,
+                n: n as u32,
+                script_pub_key: ScriptPubKey::new(&txout.script_pubkey, self.network),
+            })
+            .collect();
+
+        let fee = if is_coinbase {
+            None
+        } else {
+            let value_in = vin
+                .iter()
+                .filter_map(|input| input.prevout.as_ref())
+                .map(|prevout| prevout.value)
+                .sum::<Amount>();
+            let value_out = tx.output.iter().map(|txout| txout.value).sum::<Amount>();
+            Some(value_in.checked_sub(value_out).unwrap_or(Amount::ZERO))
+        };
+
+        Ok(VerboseTransaction {
+            txid: tx.compute_txid(),
+            hash: tx.compute_wtxid(),
+            version: tx.version.0,
+            size: tx.total_size(),
+            vsize: tx.vsize(),
+            weight: tx.weight().to_wu(),
+            locktime: tx.lock_time.to_consensus_u32(),
+            vin,
+            vout,
+            fee,
+            hex: serialize_hex(tx),
+        })
+    }
+
+    fn block_notification(
+        &self,
+        substrate_block_hash: Block::Hash,
+        full_block: bool,
+    ) -> Result<BlockNotification, Error> {
+        let substrate_header = self
+            .client
+            .header(substrate_block_hash)?
+            .ok_or(Error::BlockNotFound)?;
+
+        let height = (*substrate_header.number()).saturated_into::<u32>();
+        let header = extract_bitcoin_header::<Block>(&substrate_header).map_err(Error::Header)?;
+
+        let block = if full_block {
+            Some(
+                convert_to_bitcoin_block::<Block, TransactionAdapter>(
+                    self.substrate_block(substrate_block_hash)?,
+                )
+                .map_err(Error::Header)?,
+            )
+        } else {
+            None
+        };
+
+        Ok(BlockNotification {
+            hash: header.block_hash(),
+            height,
+            header,
+            block,
+        })
+    }
+
+    /// Sends the notifications of the blocks in `hashes` until the subscription is closed.
+    async fn pipe_block_notifications(
+        &self,
+        pending: PendingSubscriptionSink,
+        mut hashes: BoxStream<'static, Block::Hash>,
+        full_block: bool,
+    ) -> SubscriptionResult {
+        let sink = pending.accept().await?;
+
+        let mut closed = std::pin::pin!(sink.closed());
+
+        loop {
+            let hash = match futures::future::select(&mut closed, hashes.next()).await {
+                Either::Left(_) | Either::Right((None, _)) => break,
+                Either::Right((Some(hash), _)) => hash,
+            };
+
+            let notification = match self.block_notification(hash, full_block) {
+                Ok(notification) => notification,
+                Err(err) => {
+                    tracing::debug!(?err, "Failed to build the notification of block {hash}");
+                    continue;
+                }
+            };
+
+            if sink
+                .send(SubscriptionMessage::from_json(&notification)?)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
-impl<Block, Client, TransactionAdapter> BlockchainApiServer
-    for Blockchain<Block, Client, TransactionAdapter>
+impl<Block, Client, BE, TransactionAdapter> BlockchainApiServer
+    for Blockchain<Block, Client, BE, TransactionAdapter>
 where
     Block: BlockT + 'static,
-    Client: HeaderBackend<Block> + BlockBackend<Block> + AuxStore + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore
+        + 'static,
     TransactionAdapter: BitcoinTransactionAdapter<Block> + Send + Sync + 'static,
 {
     fn header(&self, hash: Option<BlockHash>) -> Result<Option<BitcoinHeader>, Error> {
         let substrate_block_hash = self.substrate_block_hash(hash)?;
 
-        let signed_block = self
+        // Headers are never pruned.
+        let substrate_header = self
             .client
-            .block(substrate_block_hash)?
+            .header(substrate_block_hash)?
             .ok_or(Error::BlockNotFound)?;
 
-        let block = signed_block.block;
-
         let bitcoin_header =
-            extract_bitcoin_block_header::<Block>(block.header()).map_err(Error::Header)?;
+            extract_bitcoin_header::<Block>(&substrate_header).map_err(Error::Header)?;
 
         Ok(Some(bitcoin_header))
     }
@@ -122,17 +669,291 @@ where
     fn block(&self, hash: Option<BlockHash>) -> Result<Option<BitcoinBlock>, Error> {
         let substrate_block_hash = self.substrate_block_hash(hash)?;
 
-        let substrate_block = self
-            .client
-            .block(substrate_block_hash)?
-            .ok_or(Error::BlockNotFound)?
-            .block;
+        let substrate_block = self.substrate_block(substrate_block_hash)?;
 
         let bitcoin_block = convert_to_bitcoin_block::<Block, TransactionAdapter>(substrate_block)
             .map_err(Error::Header)?;
 
         Ok(Some(bitcoin_block))
     }
+
+    fn block_verbose3(&self, hash: BlockHash) -> Result<VerboseBlock, Error> {
+        let substrate_block_hash = self.substrate_block_hash(Some(hash))?;
+
+        let substrate_block = self.substrate_block(substrate_block_hash)?;
+
+        let parent_hash = *substrate_block.header().parent_hash();
+        let height = (*substrate_block.header().number()).saturated_into::<u32>();
+
+        let block = convert_to_bitcoin_block::<Block, TransactionAdapter>(substrate_block)
+            .map_err(Error::Header)?;
+
+        // Outputs created by the earlier transactions in the same block.
+        let mut block_outputs = HashMap::new();
+
+        let tx = block
+            .txdata
+            .iter()
+            .map(|tx| {
+                let verbose_tx = self.verbose_transaction(tx, parent_hash, &block_outputs)?;
+
+                block_outputs.extend(tx.output.iter().enumerate().map(|(vout, txout)| {
+                    (
+                        OutPoint {
+                            txid: verbose_tx.txid,
+                            vout: vout as u32,
+                        },
+                        (height, txout.clone()),
+                    )
+                }));
+
+                Ok(verbose_tx)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let header = block.header;
+        let in_best_chain = self.client.block_hash(height) == Some(hash);
+        let best_number = self.client.info().best_number.saturated_into::<u32>();
+        let chain_work = self.client.chain_work(hash).ok_or(Error::BlockNotFound)?;
+
+        Ok(VerboseBlock {
+            hash,
+            confirmations: if in_best_chain {
+                i64::from(best_number - height + 1)
+            } else {
+                -1
+            },
+            height,
+            version: header.version.to_consensus(),
+            version_hex: format!("{:08x}", header.version.to_consensus()),
+            merkleroot: header.merkle_root,
+            time: header.time,
+            mediantime: self.median_time_past(hash),
+            nonce: header.nonce,
+            bits: format!("{:08x}", header.bits.to_consensus()),
+            difficulty: header.difficulty_float(),
+            chainwork: chain_work.to_be_bytes().to_lower_hex_string(),
+            n_tx: block.txdata.len(),
+            previousblockhash: (height > 0).then_some(header.prev_blockhash),
+            nextblockhash: in_best_chain
+                .then(|| self.client.block_hash(height + 1))
+                .flatten(),
+            strippedsize: block.base_size(),
+            size: block.total_size(),
+            weight: block.weight().to_wu(),
+            tx,
+        })
+    }
+
+    fn difficulty(&self, height: Option<u32>) -> Result<f64, Error> {
+        let block_hash = self.bitcoin_block_hash_at(height)?;
+        let header = self
+            .client
+            .block_header(block_hash)
+            .ok_or(Error::BlockNotFound)?;
+        Ok(header.difficulty_float())
+    }
+
+    fn chain_work(&self, height: Option<u32>) -> Result<String, Error> {
+        let block_hash = self.bitcoin_block_hash_at(height)?;
+        let chain_work = self
+            .client
+            .chain_work(block_hash)
+            .ok_or(Error::BlockNotFound)?;
+        Ok(chain_work.to_be_bytes().to_lower_hex_string())
+    }
+
+    fn prune_info(&self) -> Result<PruneInfo, Error> {
+        let Some(block_pruning) = self.block_pruning else {
+            return Ok(PruneInfo {
+                pruned: false,
+                prune_height: None,
+                prune_target_size: None,
+            });
+        };
+
+        let finalized_number = self.client.info().finalized_number.saturated_into();
+
+        Ok(PruneInfo {
+            pruned: true,
+            prune_height: Some(block_pruning.prune_height(finalized_number)),
+            prune_target_size: Some(block_pruning.target_size()),
+        })
+    }
+
+    fn block_stats(
+        &self,
+        hash_or_height: HashOrHeight,
+        stats: Option<Vec<String>>,
+    ) -> Result<Map<String, Value>, Error> {
+        let block_hash = match hash_or_height {
+            HashOrHeight::Height(height) => self.bitcoin_block_hash_at(Some(height))?,
+            HashOrHeight::Hash(hash) => hash,
+        };
+
+        let height = self
+            .client
+            .block_number(block_hash)
+            .ok_or(Error::BlockNotFound)?;
+        let header = self
+            .client
+            .block_header(block_hash)
+            .ok_or(Error::BlockNotFound)?;
+        let block_stats = self
+            .client
+            .block_stats(block_hash)
+            .ok_or(Error::BlockStatsUnavailable)?;
+
+        let mut all_stats = block_stats_json(block_stats);
+        all_stats.insert("blockhash".into(), json!(block_hash));
+        all_stats.insert("height".into(), json!(height));
+        all_stats.insert("time".into(), json!(header.time));
+        all_stats.insert(
+            "mediantime".into(),
+            json!(self.median_time_past(block_hash)),
+        );
+
+        let Some(selected) = stats else {
+            return Ok(all_stats);
+        };
+
+        selected
+            .into_iter()
+            .map(|stat| match all_stats.get(&stat) {
+                Some(value) => Ok((stat, value.clone())),
+                None => Err(Error::InvalidStatistic(stat)),
+            })
+            .collect()
+    }
+
+    async fn subscribe_new_blocks(
+        &self,
+        pending: PendingSubscriptionSink,
+        full_block: Option<bool>,
+    ) -> SubscriptionResult {
+        let hashes = self
+            .client
+            .import_notification_stream()
+            .filter_map(|notification| {
+                futures::future::ready(notification.is_new_best.then_some(notification.hash))
+            })
+            .boxed();
+
+        self.pipe_block_notifications(pending, hashes, full_block.unwrap_or(false))
+            .await
+    }
+
+    async fn subscribe_finalized_blocks(
+        &self,
+        pending: PendingSubscriptionSink,
+        full_block: Option<bool>,
+    ) -> SubscriptionResult {
+        let hashes = self
+            .client
+            .finality_notification_stream()
+            .flat_map(|notification| {
+                // `tree_route` contains the implicitly finalized blocks ordered from the
+                // oldest one, excluding the newly finalized block.
+                let hashes = notification
+                    .tree_route
+                    .iter()
+                    .copied()
+                    .chain(std::iter::once(notification.hash))
+                    .collect::<Vec<_>>();
+                futures::stream::iter(hashes)
+            })
+            .boxed();
+
+        self.pipe_block_notifications(pending, hashes, full_block.unwrap_or(false))
+            .await
+    }
+
+    async fn subscribe_chain_reorgs(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        let sink = pending.accept().await?;
+
+        let mut reorgs = std::pin::pin!(chain_reorg_stream(self.client.clone()));
+        let mut closed = std::pin::pin!(sink.closed());
+
+        loop {
+            let reorg = match futures::future::select(&mut closed, reorgs.next()).await {
+                Either::Left(_) | Either::Right((None, _)) => break,
+                Either::Right((Some(reorg), _)) => reorg,
+            };
+
+            let notification = ReorgNotification::from(reorg);
+
+            if sink
+                .send(SubscriptionMessage::from_json(&notification)?)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn block_stats_json(stats: BlockStats) -> Map<String, Value> {
+    let BlockStats {
+        avg_fee,
+        avg_fee_rate,
+        avg_tx_size,
+        fee_rate_percentiles,
+        ins,
+        max_fee,
+        max_fee_rate,
+        max_tx_size,
+        median_fee,
+        median_tx_size,
+        min_fee,
+        min_fee_rate,
+        min_tx_size,
+        outs,
+        subsidy,
+        sw_total_size,
+        sw_total_weight,
+        sw_txs,
+        total_out,
+        total_size,
+        total_weight,
+        total_fee,
+        txs,
+        utxo_increase,
+    } = stats;
+
+    let json = json!({
+        "avgfee": avg_fee,
+        "avgfeerate": avg_fee_rate,
+        "avgtxsize": avg_tx_size,
+        "feerate_percentiles": fee_rate_percentiles,
+        "ins": ins,
+        "maxfee": max_fee,
+        "maxfeerate": max_fee_rate,
+        "maxtxsize": max_tx_size,
+        "medianfee": median_fee,
+        "mediantxsize": median_tx_size,
+        "minfee": min_fee,
+        "minfeerate": min_fee_rate,
+        "mintxsize": min_tx_size,
+        "outs": outs,
+        "subsidy": subsidy,
+        "swtotal_size": sw_total_size,
+        "swtotal_weight": sw_total_weight,
+        "swtxs": sw_txs,
+        "total_out": total_out,
+        "total_size": total_size,
+        "total_weight": total_weight,
+        "totalfee": total_fee,
+        "txs": txs,
+        "utxo_increase": utxo_increase,
+    });
+
+    match json {
+        Value::Object(map) => map,
+        _ => unreachable!("Must be an object as constructed above; qed"),
+    }
 }
 
 #[cfg(test)]
@@ -148,4 +969,46 @@ mod tests {
                 .expect("failed to parse block hash");
         println!("==== {:?}", serde_json::to_string(&block_hash).unwrap());
     }
+
+    #[test]
+    fn test_hash_or_height_serde() {
+        use super::HashOrHeight;
+
+        assert!(matches!(
+            serde_json::from_str::<HashOrHeight>("100").unwrap(),
+            HashOrHeight::Height(100)
+        ));
+        assert!(matches!(
+            serde_json::from_str::<HashOrHeight>(
+                "\"ef537f25c895bfa782526529a9b63d97aa631564d5d789c2b765448c8635fb6c\""
+            )
+            .unwrap(),
+            HashOrHeight::Hash(_)
+        ));
+    }
+
+    #[test]
+    fn test_script_pub_key() {
+        use super::ScriptPubKey;
+        use bitcoin::{Network, ScriptBuf};
+
+        let script_pubkey =
+            ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let json =
+            serde_json::to_value(ScriptPubKey::new(&script_pubkey, Network::Bitcoin)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "asm": "OP_0 OP_PUSHBYTES_20 751e76e8199196d454941c45d1b3a323f1433bd6",
+                "hex": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                "address": "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+                "type": "witness_v0_keyhash",
+            })
+        );
+
+        let op_return = ScriptBuf::from_hex("6a00").unwrap();
+        let json = serde_json::to_value(ScriptPubKey::new(&op_return, Network::Bitcoin)).unwrap();
+        assert!(json.get("address").is_none());
+        assert_eq!(json["type"], "nulldata");
+    }
 }
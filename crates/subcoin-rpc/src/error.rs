@@ -10,6 +10,14 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     #[error("block not found")]
     BlockNotFound,
+    #[error("Block not available (pruned data)")]
+    BlockPruned,
+    #[error("Undo data not available (pruned state)")]
+    UndoDataPruned,
+    #[error("Block stats not available, the block was not fully verified on import")]
+    BlockStatsUnavailable,
+    #[error("Invalid selected statistic '{0}'")]
+    InvalidStatistic(String),
     #[error("substrate block hash not found")]
     SubstrateBlockHashNotFound,
     #[error("Invalid header: {0:?}")]
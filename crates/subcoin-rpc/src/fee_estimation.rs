@@ -0,0 +1,487 @@
+//! Fee estimation based on the fee rates of the recently confirmed transactions.
+//!
+//! There is no mempool yet, so unlike the `estimatesmartfee` in Bitcoin Core which tracks how
+//! long the transactions take to be confirmed since they enter the mempool, a simplified
+//! percentile model is used here:
+//!
+//! - For each new best block, the low percentile fee rate of its transactions is recorded as
+//!   the fee rate required to be included in that block.
+//! - A transaction paying fee rate `f` is considered to confirm within `n` blocks if `f` is no
+//!   less than the lowest required fee rate in a window of `n` consecutive blocks.
+//! - The estimate for `n` is the fee rate that would have succeeded in
+//!   [`SUCCESS_THRESHOLD_PERCENT`] of the windows of `n` blocks in the tracked history.
+//!
+//! The tracked history is saved to [`FEE_ESTIMATES_FILE_NAME`] on shutdown and reloaded on
+//! startup, as it would otherwise take many blocks to produce an estimate again.
+
+use crate::error::Error;
+use bitcoin::{Amount, OutPoint, Txid};
+use codec::{Decode, Encode};
+use futures::StreamExt;
+use jsonrpsee::proc_macros::rpc;
+use parking_lot::RwLock;
+use sc_client_api::{Backend, BlockBackend, BlockchainEvents, StorageProvider};
+use serde::{Deserialize, Serialize};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_runtime::SaturatedConversion;
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{convert_to_bitcoin_block, BitcoinTransactionAdapter, CoinStorageKey};
+
+/// Maximum confirmation target supported, same as Bitcoin Core.
+const MAX_CONF_TARGET: u32 = 1008;
+
+/// Number of recent blocks tracked by the estimator.
+const MAX_TRACKED_BLOCKS: usize = MAX_CONF_TARGET as usize;
+
+/// Percentile of the fee rates in a block considered as the fee rate required for inclusion.
+///
+/// The very bottom of the block is ignored as it's often filled with the transactions
+/// prioritised out-of-band by the miners.
+const INCLUSION_PERCENTILE: usize = 10;
+
+/// Percent of the historical windows the estimated fee rate must have succeeded in.
+const SUCCESS_THRESHOLD_PERCENT: usize = 85;
+
+/// Minimum number of windows required to produce an estimate.
+const MIN_WINDOWS: usize = 6;
+
+/// Name of the file the fee rates are saved to, next to `mempool.dat`.
+pub const FEE_ESTIMATES_FILE_NAME: &str = "fee_estimates.dat";
+
+/// Version of the fee estimates file format.
+const FEE_ESTIMATES_VERSION: u8 = 1;
+
+/// Tracks the fee rates of the recently confirmed transactions.
+#[derive(Debug, Clone, Default)]
+pub struct FeeEstimator {
+    /// Required fee rates (sat/kvB) of the recent best blocks, keyed by block number.
+    ///
+    /// `None` if the block contains no transactions other than coinbase.
+    required_fee_rates: Arc<RwLock<BTreeMap<u32, Option<u64>>>>,
+    /// File the fee rates are persisted to.
+    path: Option<Arc<PathBuf>>,
+}
+
+impl FeeEstimator {
+    /// Constructs a new instance of [`FeeEstimator`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a new instance of [`FeeEstimator`] persisted to `path`, with the fee rates
+    /// saved previously.
+    ///
+    /// Starts with no history if the file does not exist or can not be read.
+    pub fn load(path: PathBuf) -> Self {
+        let required_fee_rates = match std::fs::read(&path) {
+            Ok(data) => decode_fee_estimates(&data).unwrap_or_else(|err| {
+                tracing::warn!(
+                    "Failed to decode the fee estimates {}: {err}",
+                    path.display()
+                );
+                BTreeMap::new()
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                tracing::warn!("Failed to read the fee estimates {}: {err}", path.display());
+                BTreeMap::new()
+            }
+        };
+
+        Self {
+            required_fee_rates: Arc::new(RwLock::new(required_fee_rates)),
+            path: Some(Arc::new(path)),
+        }
+    }
+
+    /// Writes the fee rates to the file passed to [`Self::load`], replacing the existing one.
+    ///
+    /// Returns the path of the file, `None` if the fee rates are not persisted.
+    pub fn save(&self) -> std::io::Result<Option<&Path>> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+
+        let data = encode_fee_estimates(&self.required_fee_rates.read());
+
+        // Written to a temporary file first, same as `mempool.dat`.
+        let tmp_path = path.with_extension("dat.new");
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(tmp_path, path.as_path())?;
+
+        Ok(Some(path.as_path()))
+    }
+
+    /// Returns a guard saving the fee rates when dropped on shutdown.
+    pub fn save_on_drop(&self) -> SaveFeeEstimatesOnDrop {
+        SaveFeeEstimatesOnDrop(self.clone())
+    }
+
+    /// Returns the number of blocks tracked by the estimator.
+    pub fn tracked_blocks(&self) -> usize {
+        self.required_fee_rates.read().len()
+    }
+
+    /// Records the required fee rate of the new best block at `number`.
+    ///
+    /// The blocks at and above `number` are no longer in the best chain in case of a reorg,
+    /// their fee rates are replaced.
+    fn note_block(&self, number: u32, required_fee_rate: Option<u64>) {
+        let mut required_fee_rates = self.required_fee_rates.write();
+        required_fee_rates.split_off(&number);
+        required_fee_rates.insert(number, required_fee_rate);
+        while required_fee_rates.len() > MAX_TRACKED_BLOCKS {
+            required_fee_rates.pop_first();
+        }
+    }
+
+    /// Estimates the fee rate required for a transaction to be confirmed within `conf_target`
+    /// blocks.
+    ///
+    /// Returns the fee rate in sat/kvB, or `None` if there is no sufficient data.
+    pub fn estimate(&self, conf_target: u32) -> Option<u64> {
+        let conf_target = conf_target.clamp(1, MAX_CONF_TARGET) as usize;
+
+        let required_fee_rates = self.required_fee_rates.read();
+
+        if required_fee_rates.len() < conf_target {
+            return None;
+        }
+
+        let required_fee_rates = required_fee_rates.values().copied().collect::<Vec<_>>();
+
+        let mut window_fee_rates = required_fee_rates
+            .windows(conf_target)
+            .filter_map(|window| window.iter().flatten().min().copied())
+            .collect::<Vec<_>>();
+
+        if window_fee_rates.len() < MIN_WINDOWS {
+            return None;
+        }
+
+        window_fee_rates.sort_unstable();
+
+        let index = (window_fee_rates.len() * SUCCESS_THRESHOLD_PERCENT).div_ceil(100) - 1;
+
+        window_fee_rates.get(index).copied()
+    }
+
+    /// Returns a future tracking the fee rates of the new best blocks.
+    ///
+    /// The blocks imported during the major sync are skipped, only the recent blocks matter
+    /// and reading the spent coins of every block would slow down the sync.
+    ///
+    /// The future needs to be spawned in the background.
+    pub async fn run<Block, Client, BE, TransactionAdapter>(
+        self,
+        client: Arc<Client>,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+        is_major_syncing: Arc<AtomicBool>,
+    ) where
+        Block: BlockT,
+        BE: Backend<Block>,
+        Client: BlockBackend<Block> + BlockchainEvents<Block> + StorageProvider<Block, BE>,
+        TransactionAdapter: BitcoinTransactionAdapter<Block>,
+    {
+        let mut block_import_stream = client.import_notification_stream();
+
+        while let Some(notification) = block_import_stream.next().await {
+            if !notification.is_new_best || is_major_syncing.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            // The blocks enacted by a reorg are not notified as the new best blocks.
+            let connected = notification
+                .tree_route
+                .as_ref()
+                .map(|tree_route| tree_route.enacted().to_vec())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|block| (block.hash, block.number))
+                .chain(std::iter::once((
+                    notification.hash,
+                    *notification.header.number(),
+                )));
+
+            for (block_hash, number) in connected {
+                let Some(fee_rates) = block_fee_rates::<Block, Client, BE, TransactionAdapter>(
+                    &client,
+                    &coin_storage_key,
+                    block_hash,
+                ) else {
+                    continue;
+                };
+
+                self.note_block(number.saturated_into(), required_fee_rate(fee_rates));
+            }
+        }
+    }
+}
+
+/// Returns the fee rates of the transactions in the block.
+fn block_fee_rates<Block, Client, BE, TransactionAdapter>(
+    client: &Client,
+    coin_storage_key: &Arc<dyn CoinStorageKey>,
+    block_hash: Block::Hash,
+) -> Option<Vec<u64>>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: BlockBackend<Block> + StorageProvider<Block, BE>,
+    TransactionAdapter: BitcoinTransactionAdapter<Block>,
+{
+    let signed_block = client.block(block_hash).ok().flatten()?;
+
+    let parent_hash = *signed_block.block.header().parent_hash();
+
+    let block = convert_to_bitcoin_block::<Block, TransactionAdapter>(signed_block.block).ok()?;
+
+    let access_coin = |out_point: OutPoint| -> Option<u64> {
+        let storage_key = coin_storage_key.storage_key(out_point.txid, out_point.vout);
+        client
+            .storage(parent_hash, &sc_client_api::StorageKey(storage_key))
+            .ok()
+            .flatten()
+            .and_then(|data| Coin::decode(&mut data.0.as_slice()).ok())
+            .map(|coin| coin.amount)
+    };
+
+    // Outputs created in the current block, which can be spent by the later transactions.
+    let mut block_outputs = HashMap::<Txid, Vec<u64>>::new();
+    let mut fee_rates = Vec::with_capacity(block.txdata.len());
+
+    for (index, tx) in block.txdata.iter().enumerate() {
+        let txid = tx.compute_txid();
+
+        if index > 0 {
+            let value_in = tx
+                .input
+                .iter()
+                .map(|input| {
+                    let out_point = input.previous_output;
+                    block_outputs
+                        .get(&out_point.txid)
+                        .and_then(|outputs| outputs.get(out_point.vout as usize).copied())
+                        .or_else(|| access_coin(out_point))
+                })
+                .sum::<Option<u64>>();
+
+            let value_out = tx.output.iter().map(|txout| txout.value.to_sat()).sum();
+
+            if let Some(fee) = value_in.and_then(|value_in| value_in.checked_sub(value_out)) {
+                fee_rates.push(fee * 1000 / tx.vsize() as u64);
+            }
+        }
+
+        block_outputs.insert(
+            txid,
+            tx.output.iter().map(|txout| txout.value.to_sat()).collect(),
+        );
+    }
+
+    Some(fee_rates)
+}
+
+/// Saves the fee rates of [`FeeEstimator`] when dropped.
+pub struct SaveFeeEstimatesOnDrop(FeeEstimator);
+
+impl Drop for SaveFeeEstimatesOnDrop {
+    fn drop(&mut self) {
+        match self.0.save() {
+            Ok(Some(path)) => tracing::info!(
+                "Saved the fee rates of {} blocks to {}",
+                self.0.tracked_blocks(),
+                path.display()
+            ),
+            Ok(None) => {}
+            Err(err) => tracing::warn!("Failed to save the fee estimates: {err}"),
+        }
+    }
+}
+
+fn encode_fee_estimates(required_fee_rates: &BTreeMap<u32, Option<u64>>) -> Vec<u8> {
+    (FEE_ESTIMATES_VERSION, required_fee_rates).encode()
+}
+
+fn decode_fee_estimates(mut data: &[u8]) -> Result<BTreeMap<u32, Option<u64>>, String> {
+    let (version, required_fee_rates) =
+        <(u8, BTreeMap<u32, Option<u64>>)>::decode(&mut data).map_err(|err| err.to_string())?;
+
+    if version != FEE_ESTIMATES_VERSION {
+        return Err(format!("Unsupported fee estimates version {version}"));
+    }
+
+    Ok(required_fee_rates)
+}
+
+/// Returns the fee rate required for inclusion given the fee rates of transactions in a block.
+fn required_fee_rate(mut fee_rates: Vec<u64>) -> Option<u64> {
+    if fee_rates.is_empty() {
+        return None;
+    }
+
+    fee_rates.sort_unstable();
+
+    fee_rates
+        .get(fee_rates.len() * INCLUSION_PERCENTILE / 100)
+        .copied()
+}
+
+/// Result of `estimatesmartfee`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EstimateSmartFeeResult {
+    /// Estimated fee rate in BTC/kvB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feerate: Option<f64>,
+    /// Errors encountered during the estimation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<String>>,
+    /// Block number where the estimate was found.
+    pub blocks: u32,
+}
+
+/// Fee estimation API.
+#[rpc(client, server)]
+pub trait FeeEstimationApi {
+    /// Estimates the fee rate needed for a transaction to begin confirmation within
+    /// `conf_target` blocks.
+    #[method(name = "subcoin_estimateSmartFee", blocking)]
+    fn estimate_smart_fee(&self, conf_target: u32) -> Result<EstimateSmartFeeResult, Error>;
+}
+
+/// This struct provides the fee estimation API.
+pub struct FeeEstimation<Block> {
+    fee_estimator: FeeEstimator,
+    _phantom: PhantomData<Block>,
+}
+
+impl<Block> FeeEstimation<Block> {
+    /// Constructs a new instance of [`FeeEstimation`].
+    pub fn new(fee_estimator: FeeEstimator) -> Self {
+        Self {
+            fee_estimator,
+            _phantom: Default::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Block> FeeEstimationApiServer for FeeEstimation<Block>
+where
+    Block: BlockT + 'static,
+{
+    fn estimate_smart_fee(&self, conf_target: u32) -> Result<EstimateSmartFeeResult, Error> {
+        let blocks = conf_target.clamp(1, MAX_CONF_TARGET);
+
+        let result = match self.fee_estimator.estimate(conf_target) {
+            Some(fee_rate) => EstimateSmartFeeResult {
+                feerate: Some(Amount::from_sat(fee_rate).to_btc()),
+                errors: None,
+                blocks,
+            },
+            None => EstimateSmartFeeResult {
+                feerate: None,
+                errors: Some(vec!["Insufficient data or no feerate found".to_string()]),
+                blocks: 0,
+            },
+        };
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_fee_rate() {
+        assert_eq!(required_fee_rate(vec![]), None);
+        assert_eq!(required_fee_rate(vec![5000]), Some(5000));
+        assert_eq!(
+            required_fee_rate((1..=20).rev().map(|x| x * 1000).collect()),
+            Some(3000)
+        );
+    }
+
+    #[test]
+    fn test_fee_estimation() {
+        let fee_estimator = FeeEstimator::new();
+
+        for number in 0..5 {
+            fee_estimator.note_block(number, Some(4000));
+        }
+        assert_eq!(fee_estimator.estimate(1), None);
+
+        for i in 0..40 {
+            fee_estimator.note_block(5 + i, if i % 2 == 0 { Some(2000) } else { None });
+        }
+
+        assert_eq!(fee_estimator.tracked_blocks(), 45);
+
+        // 2000 sat/kvB only succeeds in 20 of the 25 non-empty blocks.
+        assert_eq!(fee_estimator.estimate(1), Some(4000));
+
+        // Every window of two blocks contains one block requiring 2000 sat/kvB at most,
+        // apart from the first 4 windows.
+        assert_eq!(fee_estimator.estimate(2), Some(2000));
+
+        // Confirmation target larger than the history.
+        assert_eq!(fee_estimator.estimate(100), None);
+    }
+
+    #[test]
+    fn test_fee_estimation_reorg() {
+        let fee_estimator = FeeEstimator::new();
+
+        for number in 0..10 {
+            fee_estimator.note_block(number, Some(2000));
+        }
+
+        // Blocks #8 and #9 are reorged out by a new block #8.
+        fee_estimator.note_block(8, Some(6000));
+        assert_eq!(fee_estimator.tracked_blocks(), 9);
+        assert_eq!(
+            fee_estimator.required_fee_rates.read().get(&8),
+            Some(&Some(6000))
+        );
+        assert_eq!(fee_estimator.estimate(1), Some(2000));
+
+        // The history is capped.
+        for number in 9..2000 {
+            fee_estimator.note_block(number, Some(2000));
+        }
+        assert_eq!(fee_estimator.tracked_blocks(), MAX_TRACKED_BLOCKS);
+    }
+
+    #[test]
+    fn test_fee_estimates_roundtrip() {
+        let path =
+            std::env::temp_dir().join(format!("subcoin-fee-estimates-{}.dat", std::process::id()));
+
+        let fee_estimator = FeeEstimator::load(path.clone());
+        assert_eq!(fee_estimator.tracked_blocks(), 0);
+
+        for number in 0..20 {
+            fee_estimator.note_block(number, (number % 3 != 0).then_some(1000 * number as u64));
+        }
+        drop(fee_estimator.save_on_drop());
+
+        let loaded = FeeEstimator::load(path.clone());
+        assert_eq!(
+            *loaded.required_fee_rates.read(),
+            *fee_estimator.required_fee_rates.read()
+        );
+        assert_eq!(loaded.estimate(2), fee_estimator.estimate(2));
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(decode_fee_estimates(&[2, 0]).is_err());
+        assert!(FeeEstimator::new().save().unwrap().is_none());
+    }
+}
@@ -0,0 +1,64 @@
+use crate::error::Error;
+use jsonrpsee::proc_macros::rpc;
+use sc_client_api::AuxStore;
+use serde_json::Value;
+use subcoin_service::{JobError, JobId, JobInfo, JobManager};
+
+#[rpc(client, server)]
+pub trait JobsApi {
+    /// Starts a background job of `kind`, returning its id.
+    ///
+    /// The job runs on its own thread and resumes from its last checkpoint if the node
+    /// restarts before the job is finished.
+    #[method(name = "subcoin_startJob", blocking)]
+    fn start_job(&self, kind: String, params: Value) -> Result<JobId, Error>;
+
+    /// Returns the job, including its state and progress.
+    #[method(name = "subcoin_getJob", blocking)]
+    fn get_job(&self, id: JobId) -> Result<Option<JobInfo>, Error>;
+
+    /// Returns the running and the recently finished jobs.
+    #[method(name = "subcoin_listJobs", blocking)]
+    fn list_jobs(&self) -> Result<Vec<JobInfo>, Error>;
+
+    /// Requests the job to stop, returns `false` if the job is not running.
+    #[method(name = "subcoin_cancelJob", blocking)]
+    fn cancel_job(&self, id: JobId) -> Result<bool, Error>;
+}
+
+/// This struct provides the background jobs API.
+pub struct Jobs<Client> {
+    job_manager: JobManager<Client>,
+}
+
+impl<Client> Jobs<Client> {
+    /// Constructs a new instance of [`Jobs`].
+    pub fn new(job_manager: JobManager<Client>) -> Self {
+        Self { job_manager }
+    }
+}
+
+fn job_error(err: JobError) -> Error {
+    Error::Other(err.to_string())
+}
+
+impl<Client> JobsApiServer for Jobs<Client>
+where
+    Client: AuxStore + Send + Sync + 'static,
+{
+    fn start_job(&self, kind: String, params: Value) -> Result<JobId, Error> {
+        self.job_manager.start(&kind, params).map_err(job_error)
+    }
+
+    fn get_job(&self, id: JobId) -> Result<Option<JobInfo>, Error> {
+        self.job_manager.job(id).map_err(job_error)
+    }
+
+    fn list_jobs(&self) -> Result<Vec<JobInfo>, Error> {
+        self.job_manager.jobs().map_err(job_error)
+    }
+
+    fn cancel_job(&self, id: JobId) -> Result<bool, Error> {
+        Ok(self.job_manager.cancel(id))
+    }
+}
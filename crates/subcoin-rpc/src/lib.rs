@@ -1,3 +1,17 @@
+pub mod auth;
 pub mod blockchain;
 pub mod error;
+pub mod fee_estimation;
+pub mod jobs;
+pub mod light;
+pub mod mining;
+pub mod op_return;
+pub mod raw_transactions;
+pub mod scan;
+pub mod server;
+pub mod silent_payments;
+pub mod state_root_audit;
+pub mod stats;
 pub mod subcoin;
+pub mod utxo_proof;
+pub mod wallet;
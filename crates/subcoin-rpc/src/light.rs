@@ -0,0 +1,149 @@
+use crate::blockchain::HashOrHeight;
+use crate::error::Error;
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::hex::DisplayHex;
+use bitcoin::merkle_tree::MerkleBlock;
+use bitcoin::{BlockHash, Txid};
+use jsonrpsee::proc_macros::rpc;
+use sc_client_api::{AuxStore, HeaderBackend};
+use serde::{Deserialize, Serialize};
+use sp_runtime::traits::Block as BlockT;
+use std::sync::Arc;
+use subcoin_primitives::{HeaderEntry, LightChain};
+
+/// Maximum number of the headers returned by `light_getHeaders`.
+const MAX_HEADERS: u32 = 2000;
+
+/// Header in the best header chain.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LightHeader {
+    pub hash: BlockHash,
+    pub height: u32,
+    pub header: BitcoinHeader,
+    /// Cumulative chainwork up to the block in hex.
+    pub chainwork: String,
+    pub confirmations: u32,
+}
+
+impl LightHeader {
+    fn new(entry: HeaderEntry, tip_height: u32) -> Self {
+        Self {
+            hash: entry.header.block_hash(),
+            height: entry.height,
+            header: entry.header,
+            chainwork: entry.chain_work.to_be_bytes().to_lower_hex_string(),
+            confirmations: tip_height.saturating_sub(entry.height) + 1,
+        }
+    }
+}
+
+/// Transactions proven by a merkle proof.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxOutProof {
+    pub block_hash: BlockHash,
+    pub height: u32,
+    pub confirmations: u32,
+    pub txids: Vec<Txid>,
+}
+
+#[rpc(client, server)]
+pub trait LightApi {
+    /// Returns the tip of the header chain synced by the light client.
+    #[method(name = "light_getBestHeader", blocking)]
+    fn best_header(&self) -> Result<LightHeader, Error>;
+
+    /// Returns the header in the best header chain.
+    #[method(name = "light_getHeader", blocking)]
+    fn header(&self, hash_or_height: HashOrHeight) -> Result<Option<LightHeader>, Error>;
+
+    /// Returns up to `count` consecutive headers of the best header chain from `start_height`,
+    /// at most 2000.
+    #[method(name = "light_getHeaders", blocking)]
+    fn headers(&self, start_height: u32, count: u32) -> Result<Vec<BitcoinHeader>, Error>;
+
+    /// Verifies the hex encoded proof returned by `gettxoutproof`, returns the proven
+    /// transactions if the block is in the best header chain.
+    #[method(name = "light_verifyTxOutProof", blocking)]
+    fn verify_tx_out_proof(&self, proof: String) -> Result<TxOutProof, Error>;
+}
+
+/// This struct provides the RPCs of the light client.
+pub struct Light<Block, Client> {
+    light_chain: LightChain<Block, Client>,
+}
+
+impl<Block, Client> Light<Block, Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    /// Constructs a new instance of [`Light`].
+    pub fn new(client: Arc<Client>) -> Self {
+        Self {
+            light_chain: LightChain::new(client),
+        }
+    }
+}
+
+impl<Block, Client> LightApiServer for Light<Block, Client>
+where
+    Block: BlockT + 'static,
+    Client: HeaderBackend<Block> + AuxStore + 'static,
+{
+    fn best_header(&self) -> Result<LightHeader, Error> {
+        let tip = self.light_chain.tip();
+        Ok(LightHeader::new(tip, tip.height))
+    }
+
+    fn header(&self, hash_or_height: HashOrHeight) -> Result<Option<LightHeader>, Error> {
+        let block_hash = match hash_or_height {
+            HashOrHeight::Height(height) => match self.light_chain.best_hash(height) {
+                Some(block_hash) => block_hash,
+                None => return Ok(None),
+            },
+            HashOrHeight::Hash(block_hash) => block_hash,
+        };
+
+        let tip_height = self.light_chain.tip().height;
+
+        Ok(self
+            .light_chain
+            .best_entry(block_hash)
+            .map(|entry| LightHeader::new(entry, tip_height)))
+    }
+
+    fn headers(&self, start_height: u32, count: u32) -> Result<Vec<BitcoinHeader>, Error> {
+        if count > MAX_HEADERS {
+            return Err(Error::Other(format!(
+                "Too many headers requested, at most {MAX_HEADERS}"
+            )));
+        }
+
+        let headers = (start_height..start_height.saturating_add(count))
+            .map_while(|height| self.light_chain.best_hash(height))
+            .map_while(|block_hash| self.light_chain.entry(block_hash))
+            .map(|entry| entry.header)
+            .collect();
+
+        Ok(headers)
+    }
+
+    fn verify_tx_out_proof(&self, proof: String) -> Result<TxOutProof, Error> {
+        let merkle_block = deserialize_hex::<MerkleBlock>(&proof)?;
+
+        let inclusion = self
+            .light_chain
+            .verify_tx_out_proof(&merkle_block)
+            .map_err(|err| Error::Other(err.to_string()))?;
+
+        Ok(TxOutProof {
+            block_hash: inclusion.block_hash,
+            height: inclusion.height,
+            confirmations: inclusion.confirmations,
+            txids: inclusion.txids,
+        })
+    }
+}
@@ -0,0 +1,317 @@
+use crate::error::Error;
+use crate::raw_transactions::reject_reason;
+use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
+use bitcoin::{Block as BitcoinBlock, BlockHash, Txid, Wtxid};
+use jsonrpsee::proc_macros::rpc;
+use sc_client_api::{AuxStore, Backend, StorageProvider};
+use sc_consensus_nakamoto::{
+    block_verification_failure, is_invalid_block, BlockVerification, BlockVerifier, ImportStatus,
+    LocalBlockImport, VerificationError,
+};
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subcoin_network::NetworkHandle;
+use subcoin_primitives::{BackendExt, CoinStorageKey};
+
+/// Transaction in the block template.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockTemplateTransaction {
+    /// Serialized, hex-encoded transaction.
+    pub data: String,
+    pub txid: Txid,
+    /// Witness transaction id.
+    pub hash: Wtxid,
+    /// 1-based indices of the transactions in the template this transaction depends on.
+    pub depends: Vec<usize>,
+    /// Transaction fee in satoshis.
+    pub fee: u64,
+    /// Sigops cost of the transaction.
+    pub sigops: u64,
+    /// Weight of the transaction.
+    pub weight: u64,
+}
+
+/// Block template, in the format of `getblocktemplate` in Bitcoin Core.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockTemplate {
+    pub version: i32,
+    /// Consensus rules activated in the block.
+    pub rules: Vec<String>,
+    #[serde(rename = "previousblockhash")]
+    pub previous_block_hash: BlockHash,
+    /// Transactions to be included in the block, excluding the coinbase.
+    pub transactions: Vec<BlockTemplateTransaction>,
+    /// Maximum total value of the coinbase outputs in satoshis.
+    #[serde(rename = "coinbasevalue")]
+    pub coinbase_value: u64,
+    /// Hex-encoded target of the proof of work.
+    pub target: String,
+    #[serde(rename = "mintime")]
+    pub min_time: u32,
+    /// Parts of the template the miner is allowed to change.
+    pub mutable: Vec<String>,
+    #[serde(rename = "noncerange")]
+    pub nonce_range: String,
+    #[serde(rename = "sigoplimit")]
+    pub sigop_limit: u64,
+    #[serde(rename = "weightlimit")]
+    pub weight_limit: u64,
+    #[serde(rename = "curtime")]
+    pub cur_time: u32,
+    /// Hex-encoded compact target.
+    pub bits: String,
+    pub height: u32,
+    /// Hex-encoded script pubkey of the witness commitment output in the coinbase.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_witness_commitment: Option<String>,
+}
+
+impl From<sc_consensus_nakamoto::BlockTemplate> for BlockTemplate {
+    fn from(template: sc_consensus_nakamoto::BlockTemplate) -> Self {
+        let mut rules = vec!["csv".to_string()];
+        if template.default_witness_commitment.is_some() {
+            rules.push("segwit".to_string());
+        }
+
+        Self {
+            version: template.version.to_consensus(),
+            rules,
+            previous_block_hash: template.previous_block_hash,
+            transactions: template
+                .transactions
+                .into_iter()
+                .map(|tx| BlockTemplateTransaction {
+                    data: serialize_hex(&tx.transaction),
+                    txid: tx.txid,
+                    hash: tx.wtxid,
+                    depends: tx.depends.into_iter().map(|index| index + 1).collect(),
+                    fee: tx.fee,
+                    sigops: tx.sigops,
+                    weight: tx.weight,
+                })
+                .collect(),
+            coinbase_value: template.coinbase_value,
+            target: hex::encode(template.target.to_be_bytes()),
+            min_time: template.min_time,
+            mutable: vec![
+                "time".to_string(),
+                "transactions".to_string(),
+                "prevblock".to_string(),
+            ],
+            nonce_range: "00000000ffffffff".to_string(),
+            sigop_limit: template.sigop_limit,
+            weight_limit: template.weight_limit,
+            cur_time: template.cur_time,
+            bits: format!("{:08x}", template.bits.to_consensus()),
+            height: template.height,
+            default_witness_commitment: template
+                .default_witness_commitment
+                .map(|script| hex::encode(script.as_bytes())),
+        }
+    }
+}
+
+/// Mining API.
+#[rpc(client, server)]
+pub trait MiningApi {
+    /// Returns the template of the next block assembled from the unconfirmed transactions,
+    /// similar to `getblocktemplate` in Bitcoin Core.
+    #[method(name = "subcoin_getBlockTemplate")]
+    async fn get_block_template(&self) -> Result<BlockTemplate, Error>;
+
+    /// Fully verifies the raw block (serialized, hex-encoded) and imports it, similar to
+    /// `submitblock` in Bitcoin Core.
+    ///
+    /// Returns `null` if the block was accepted, the rejection reason otherwise.
+    #[method(name = "subcoin_submitBlock")]
+    async fn submit_block(&self, raw_block: String) -> Result<Option<String>, Error>;
+}
+
+/// This struct provides the mining API.
+pub struct Mining<Block, Client, BE> {
+    client: Arc<Client>,
+    verifier: Arc<BlockVerifier<Block, Client, BE>>,
+    network_handle: NetworkHandle,
+    local_block_import: LocalBlockImport,
+}
+
+impl<Block, Client, BE> Mining<Block, Client, BE> {
+    /// Constructs a new instance of [`Mining`].
+    pub fn new(
+        client: Arc<Client>,
+        network: bitcoin::Network,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+        network_handle: NetworkHandle,
+        local_block_import: LocalBlockImport,
+    ) -> Self {
+        Self {
+            verifier: Arc::new(BlockVerifier::new(
+                client.clone(),
+                network,
+                BlockVerification::Full,
+                coin_storage_key,
+                true,
+            )),
+            client,
+            network_handle,
+            local_block_import,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Block, Client, BE> MiningApiServer for Mining<Block, Client, BE>
+where
+    Block: BlockT + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block> + StorageProvider<Block, BE> + AuxStore + 'static,
+{
+    async fn get_block_template(&self) -> Result<BlockTemplate, Error> {
+        if self
+            .network_handle
+            .is_major_syncing()
+            .load(Ordering::Relaxed)
+        {
+            return Err(Error::Other(
+                "Subcoin is in initial sync and waiting for blocks".to_string(),
+            ));
+        }
+
+        let transactions = self.network_handle.transactions().await;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Current time must be later than UNIX epoch; qed")
+            .as_secs() as u32;
+
+        let template = self
+            .verifier
+            .create_block_template(transactions, now)
+            .map_err(|err| match err {
+                VerificationError::Client(err) => err.into(),
+                err => Error::Other(err.to_string()),
+            })?;
+
+        Ok(template.into())
+    }
+
+    async fn submit_block(&self, raw_block: String) -> Result<Option<String>, Error> {
+        let block = deserialize_hex::<BitcoinBlock>(&raw_block)?;
+        let block_hash = block.block_hash();
+
+        if self.client.block_number(block_hash).is_some() {
+            return Ok(Some("duplicate".to_string()));
+        }
+
+        if is_invalid_block(&*self.client, block_hash) {
+            return Ok(Some("duplicate-invalid".to_string()));
+        }
+
+        let Some(parent_number) = self.client.block_number(block.header.prev_blockhash) else {
+            return Ok(Some("prev-blk-not-found".to_string()));
+        };
+
+        // The blocks from the import queue are verified according to the node configuration,
+        // which is not necessarily the full verification.
+        let verifier = self.verifier.clone();
+        let (block, verify_result) = tokio::task::spawn_blocking(move || {
+            let verify_result = verifier.verify_block(parent_number + 1, &block);
+            (block, verify_result)
+        })
+        .await
+        .map_err(|err| Error::Other(format!("Block verification task failed: {err}")))?;
+
+        match verify_result {
+            Ok(_) => {}
+            Err(VerificationError::Client(err)) => return Err(err.into()),
+            Err(err) => return Ok(Some(block_reject_reason(err))),
+        }
+
+        let result = match self.local_block_import.import_block(block).await {
+            Ok(ImportStatus::Imported { .. }) => None,
+            Ok(ImportStatus::AlreadyInChain(_)) => Some("duplicate".to_string()),
+            Ok(ImportStatus::KnownBad) => Some("duplicate-invalid".to_string()),
+            Ok(ImportStatus::UnknownParent | ImportStatus::MissingState) => {
+                Some("inconclusive".to_string())
+            }
+            Err(err) => {
+                tracing::debug!(?err, "Failed to import the submitted block {block_hash}");
+                Some(import_reject_reason(err))
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+/// Converts the error of importing the block to the rejection reason.
+fn import_reject_reason(err: sp_consensus::Error) -> String {
+    if let Some(verification_error) = block_verification_failure(&err) {
+        return verification_error.reason().to_string();
+    }
+
+    match err {
+        sp_consensus::Error::Other(err) => match err.downcast::<VerificationError>() {
+            Ok(verification_error) => block_reject_reason(*verification_error),
+            Err(err) => err.to_string(),
+        },
+        err => err.to_string(),
+    }
+}
+
+/// Converts the block verification error to the rejection reason used by Bitcoin Core when
+/// possible.
+fn block_reject_reason(err: VerificationError) -> String {
+    match err.block_verification_error() {
+        Some(verification_error) => verification_error.reason().to_string(),
+        None => reject_reason(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc_consensus_nakamoto::{ConsensusError, HeaderError, ScriptError};
+
+    #[test]
+    fn test_import_reject_reason() {
+        let err = sp_consensus::Error::Other(Box::new(
+            sc_consensus_nakamoto::BlockVerificationError::BadTxnMrklRoot,
+        ));
+        assert_eq!(import_reject_reason(err), "bad-txnmrklroot");
+
+        let err = sp_consensus::Error::Other(Box::new(VerificationError::Consensus(
+            ConsensusError::Script(ScriptError::Failed),
+        )));
+        assert_eq!(
+            import_reject_reason(err),
+            "mandatory-script-verify-flag-failed (Failed)"
+        );
+
+        let err = sp_consensus::Error::ClientImport("Database is corrupted".to_string());
+        assert!(import_reject_reason(err).contains("Database is corrupted"));
+    }
+
+    #[test]
+    fn test_block_reject_reason() {
+        assert_eq!(
+            block_reject_reason(VerificationError::Consensus(ConsensusError::BadMerkleRoot)),
+            "bad-txnmrklroot"
+        );
+        assert_eq!(
+            block_reject_reason(VerificationError::Header(HeaderError::TimeTooOld)),
+            "time-too-old"
+        );
+        // Falls back to the transaction rejection reasons.
+        assert_eq!(
+            block_reject_reason(VerificationError::Consensus(
+                ConsensusError::PrematureSpendOfCoinbase
+            )),
+            "bad-txns-premature-spend-of-coinbase"
+        );
+    }
+}
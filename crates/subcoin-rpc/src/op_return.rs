@@ -0,0 +1,92 @@
+use crate::error::Error;
+use bitcoin::Txid;
+use jsonrpsee::proc_macros::rpc;
+use serde::{Deserialize, Serialize};
+use subcoin_db::SubcoinDb;
+
+/// Maximum number of outputs returned by a search.
+const MAX_OUTPUTS: usize = 1000;
+
+/// `OP_RETURN` output matching the searched prefix.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpReturnOutput {
+    pub txid: Txid,
+    pub vout: u32,
+    pub height: u32,
+    /// Hex-encoded data pushed after `OP_RETURN`, truncated to 80 bytes.
+    pub payload: String,
+}
+
+/// Result of `subcoin_searchOpReturn`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpReturnSearch {
+    pub outputs: Vec<OpReturnOutput>,
+    /// Height to resume the search from, `None` once the last indexed block is searched.
+    pub next_height: Option<u32>,
+}
+
+#[rpc(client, server)]
+pub trait OpReturnApi {
+    /// Search the `OP_RETURN` outputs of the finalized blocks whose payload starts with the
+    /// hex-encoded `prefix`.
+    ///
+    /// At most 2016 blocks and 1000 outputs are searched per call, the search continues from
+    /// `nextHeight` in the result.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Hex-encoded payload prefix, all the outputs match an empty prefix.
+    /// - `from_height`: Start height, defaults to 0.
+    #[method(name = "subcoin_searchOpReturn", blocking)]
+    fn search_op_return(
+        &self,
+        prefix: String,
+        from_height: Option<u32>,
+    ) -> Result<OpReturnSearch, Error>;
+}
+
+/// This struct provides the `OP_RETURN` index API.
+pub struct OpReturn {
+    db: SubcoinDb,
+}
+
+impl OpReturn {
+    /// Constructs a new instance of [`OpReturn`].
+    pub fn new(db: SubcoinDb) -> Self {
+        Self { db }
+    }
+}
+
+impl OpReturnApiServer for OpReturn {
+    fn search_op_return(
+        &self,
+        prefix: String,
+        from_height: Option<u32>,
+    ) -> Result<OpReturnSearch, Error> {
+        let prefix = hex::decode(prefix.trim_start_matches("0x"))
+            .map_err(|err| Error::Other(format!("Invalid prefix: {err}")))?;
+
+        let search = subcoin_indexer::search_op_return(
+            &self.db,
+            &prefix,
+            from_height.unwrap_or(0),
+            MAX_OUTPUTS,
+        )
+        .map_err(|err| Error::Other(err.to_string()))?;
+
+        Ok(OpReturnSearch {
+            outputs: search
+                .outputs
+                .into_iter()
+                .map(|output| OpReturnOutput {
+                    txid: output.txid(),
+                    vout: output.vout,
+                    height: output.height,
+                    payload: hex::encode(&output.payload),
+                })
+                .collect(),
+            next_height: search.next_height,
+        })
+    }
+}
@@ -0,0 +1,316 @@
+use crate::error::Error;
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::{Amount, Transaction, Txid, Wtxid};
+use jsonrpsee::proc_macros::rpc;
+use sc_client_api::{AuxStore, Backend, StorageProvider};
+use sc_consensus_nakamoto::{
+    BlockVerification, BlockVerifier, ConsensusError, PackageError, PackageTransactionStatus,
+    TxError, VerificationError, MAX_PACKAGE_COUNT,
+};
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use subcoin_network::{NetworkHandle, SendTransactionResult};
+use subcoin_primitives::CoinStorageKey;
+
+/// Fees of the transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Fees {
+    /// Transaction fee in BTC.
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub base: Amount,
+}
+
+/// Result of validating a transaction, in the format of `testmempoolaccept` in Bitcoin Core.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TestMempoolAcceptResult {
+    /// Transaction id.
+    pub txid: Txid,
+    /// Witness transaction id.
+    pub wtxid: Wtxid,
+    /// Whether the transaction would be accepted.
+    pub allowed: bool,
+    /// Virtual transaction size as defined in BIP 141, only present when `allowed` is `true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsize: Option<usize>,
+    /// Transaction fees, only present when `allowed` is `true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fees: Option<Fees>,
+    /// Rejection reason, only present when `allowed` is `false`.
+    #[serde(
+        rename = "reject-reason",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub reject_reason: Option<String>,
+}
+
+/// Fees of the transaction in the package.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackageFees {
+    /// Transaction fee in BTC.
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub base: Amount,
+    /// Fee rate in BTC/kvB the transaction was accepted at.
+    #[serde(rename = "effective-feerate", with = "bitcoin::amount::serde::as_btc")]
+    pub effective_fee_rate: Amount,
+    /// Transactions whose fees and sizes are included in the effective fee rate.
+    #[serde(rename = "effective-includes")]
+    pub effective_includes: Vec<Wtxid>,
+}
+
+/// Result of a transaction in the package.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackageTransactionResult {
+    pub txid: Txid,
+    /// Virtual transaction size as defined in BIP 141.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsize: Option<u64>,
+    /// Transaction fees, only present if the transaction was newly accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fees: Option<PackageFees>,
+    /// Rejection reason of the transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of submitting a package, in the format of `submitpackage` in Bitcoin Core.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SubmitPackageResult {
+    /// `success` if the package was accepted, the rejection reason otherwise.
+    pub package_msg: String,
+    /// Results of the transactions keyed by the witness transaction id.
+    #[serde(rename = "tx-results")]
+    pub tx_results: BTreeMap<Wtxid, PackageTransactionResult>,
+    /// Always empty, replacing the unconfirmed transactions is not supported.
+    #[serde(rename = "replaced-transactions")]
+    pub replaced_transactions: Vec<Txid>,
+}
+
+/// Raw transaction API.
+#[rpc(client, server)]
+pub trait RawTransactionsApi {
+    /// Validates a raw transaction (serialized, hex-encoded) against the consensus rules and
+    /// the UTXO set of the best block, without broadcasting it.
+    #[method(name = "subcoin_validateTransaction", blocking)]
+    fn validate_transaction(&self, raw_tx: String) -> Result<TestMempoolAcceptResult, Error>;
+
+    /// Submits a package of raw transactions (serialized, hex-encoded) consisting of a child
+    /// and its unconfirmed parents, similar to `submitpackage` in Bitcoin Core.
+    ///
+    /// The parents are sorted before the child. A parent whose fee rate is below the minimum
+    /// relay fee rate is accepted if the child pays enough for the package. The accepted
+    /// transactions are broadcast.
+    #[method(name = "subcoin_submitPackage")]
+    async fn submit_package(&self, raw_txs: Vec<String>) -> Result<SubmitPackageResult, Error>;
+}
+
+/// This struct provides the raw transaction API.
+pub struct RawTransactions<Block, Client, BE> {
+    verifier: BlockVerifier<Block, Client, BE>,
+    network_handle: NetworkHandle,
+}
+
+impl<Block, Client, BE> RawTransactions<Block, Client, BE> {
+    /// Constructs a new instance of [`RawTransactions`].
+    pub fn new(
+        client: Arc<Client>,
+        network: bitcoin::Network,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+        network_handle: NetworkHandle,
+    ) -> Self {
+        Self {
+            verifier: BlockVerifier::new(
+                client,
+                network,
+                BlockVerification::Full,
+                coin_storage_key,
+                true,
+            ),
+            network_handle,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Block, Client, BE> RawTransactionsApiServer for RawTransactions<Block, Client, BE>
+where
+    Block: BlockT + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block> + StorageProvider<Block, BE> + AuxStore + 'static,
+{
+    fn validate_transaction(&self, raw_tx: String) -> Result<TestMempoolAcceptResult, Error> {
+        let tx = deserialize_hex::<Transaction>(&raw_tx)?;
+
+        let (allowed, vsize, fees, reject_reason) = match self.verifier.verify_transaction(&tx) {
+            Ok(fee) => (true, Some(tx.vsize()), Some(Fees { base: fee }), None),
+            Err(VerificationError::Client(err)) => return Err(err.into()),
+            Err(err) => (false, None, None, Some(reject_reason(err))),
+        };
+
+        Ok(TestMempoolAcceptResult {
+            txid: tx.compute_txid(),
+            wtxid: tx.compute_wtxid(),
+            allowed,
+            vsize,
+            fees,
+            reject_reason,
+        })
+    }
+
+    async fn submit_package(&self, raw_txs: Vec<String>) -> Result<SubmitPackageResult, Error> {
+        if raw_txs.is_empty() || raw_txs.len() > MAX_PACKAGE_COUNT {
+            return Err(Error::Other(format!(
+                "Array must contain between 1 and {MAX_PACKAGE_COUNT} transactions"
+            )));
+        }
+
+        let package = raw_txs
+            .iter()
+            .map(|raw_tx| deserialize_hex::<Transaction>(raw_tx))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mempool = self.network_handle.transactions().await;
+
+        let acceptance =
+            self.verifier
+                .verify_package(&package, mempool)
+                .map_err(|err| match err {
+                    VerificationError::Client(err) => err.into(),
+                    err => Error::Other(err.to_string()),
+                })?;
+
+        let mut package_error = acceptance.error;
+        let mut tx_results = BTreeMap::new();
+
+        for (tx, result) in package.into_iter().zip(acceptance.transactions) {
+            let (fees, error) = match result.status {
+                PackageTransactionStatus::Accepted {
+                    effective_fee_rate,
+                    effective_includes,
+                } => {
+                    let fees = PackageFees {
+                        base: result.fee.unwrap_or(Amount::ZERO),
+                        effective_fee_rate: Amount::from_sat(effective_fee_rate),
+                        effective_includes,
+                    };
+
+                    match self.network_handle.send_transaction(tx).await {
+                        SendTransactionResult::Success(_) => (Some(fees), None),
+                        SendTransactionResult::Failure(reason) => {
+                            package_error.get_or_insert(PackageError::TransactionFailed);
+                            (None, Some(reason))
+                        }
+                    }
+                }
+                PackageTransactionStatus::AlreadyInMempool => (None, None),
+                PackageTransactionStatus::Invalid(err) => (None, Some(reject_reason(err))),
+                PackageTransactionStatus::MempoolConflict => {
+                    (None, Some("txn-mempool-conflict".to_string()))
+                }
+                PackageTransactionStatus::FeeTooLow => {
+                    (None, Some("min relay fee not met".to_string()))
+                }
+                PackageTransactionStatus::NotValidated => {
+                    (None, Some("package-not-validated".to_string()))
+                }
+            };
+
+            tx_results.insert(
+                result.wtxid,
+                PackageTransactionResult {
+                    txid: result.txid,
+                    vsize: Some(result.vsize),
+                    fees,
+                    error,
+                },
+            );
+        }
+
+        Ok(SubmitPackageResult {
+            package_msg: package_error
+                .map(|err| err.to_string())
+                .unwrap_or_else(|| "success".to_string()),
+            tx_results,
+            replaced_transactions: Vec::new(),
+        })
+    }
+}
+
+/// Converts the verification error to the rejection reason used by Bitcoin Core when possible.
+pub(crate) fn reject_reason(err: VerificationError) -> String {
+    let VerificationError::Consensus(err) = err else {
+        return err.to_string();
+    };
+
+    let reason = match err {
+        ConsensusError::UnexpectedCoinbase => "coinbase",
+        ConsensusError::TransactionNotFinal => "non-final",
+        ConsensusError::UtxoNotFound { .. } => "missing-inputs",
+        ConsensusError::PrematureSpendOfCoinbase => "bad-txns-premature-spend-of-coinbase",
+        ConsensusError::InsufficientFunds { .. } => "bad-txns-in-belowout",
+        ConsensusError::TooManySigOps { .. } => "bad-txns-too-many-sigops",
+        ConsensusError::Transaction(tx_err) => match tx_err {
+            TxError::EmptyInput => "bad-txns-vin-empty",
+            TxError::EmptyOutput => "bad-txns-vout-empty",
+            TxError::TransactionOversize => "bad-txns-oversize",
+            TxError::DuplicateTxInput(_) => "bad-txns-inputs-duplicate",
+            TxError::OutputValueTooLarge(_) => "bad-txns-vout-toolarge",
+            TxError::TotalOutputValueTooLarge(_) => "bad-txns-txouttotal-toolarge",
+            TxError::BadCoinbaseLength(_) => "bad-cb-length",
+            TxError::PreviousOutputNull => "bad-txns-prevout-null",
+        },
+        ConsensusError::Script(script_err) => {
+            return format!("mandatory-script-verify-flag-failed ({script_err:?})")
+        }
+        err => return err.to_string(),
+    };
+
+    reason.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::OutPoint;
+    use sc_consensus_nakamoto::{HeaderError, ScriptError};
+
+    #[test]
+    fn test_reject_reason() {
+        let utxo_not_found = ConsensusError::UtxoNotFound {
+            block_number: 800_000,
+            txid: Txid::all_zeros(),
+            utxo: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+        };
+        assert_eq!(
+            reject_reason(VerificationError::Consensus(utxo_not_found)),
+            "missing-inputs"
+        );
+        assert_eq!(
+            reject_reason(VerificationError::Consensus(
+                ConsensusError::PrematureSpendOfCoinbase
+            )),
+            "bad-txns-premature-spend-of-coinbase"
+        );
+        assert_eq!(
+            reject_reason(VerificationError::Consensus(ConsensusError::Script(
+                ScriptError::Failed
+            ))),
+            "mandatory-script-verify-flag-failed (Failed)"
+        );
+        assert_eq!(
+            reject_reason(VerificationError::Consensus(ConsensusError::Transaction(
+                TxError::EmptyInput
+            ))),
+            "bad-txns-vin-empty"
+        );
+
+        // No equivalent in Bitcoin Core.
+        let err = VerificationError::Header(HeaderError::TimeTooOld);
+        let reason = err.to_string();
+        assert_eq!(reject_reason(err), reason);
+    }
+}
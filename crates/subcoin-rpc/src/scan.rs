@@ -0,0 +1,581 @@
+//! Scanning the UTXO set, the equivalent of `scantxoutset` in Bitcoin Core.
+//!
+//! There is no address index yet, every entry of `Coins` is visited. Scanning the whole set
+//! takes a while on mainnet, the scan can be split into multiple calls with `limit`, each call
+//! returns a cursor used to resume the scan from where it stopped at the same block.
+//!
+//! The whole UTXO set can be dumped page by page in the same way with `subcoin_listCoins`.
+//!
+//! The coins of a transaction can be looked up with `subcoin_getCoin` and
+//! `subcoin_getCoinsForTxid` at any block whose state is retained, the txids are translated
+//! into the storage keys of `Coins` by the node.
+
+use crate::error::Error;
+use crate::wallet::DescriptorRange;
+use bitcoin::hashes::Hash;
+use bitcoin::{Address, Amount, BlockHash, Script, ScriptBuf, Txid};
+use codec::{Decode, Encode};
+use jsonrpsee::proc_macros::rpc;
+use sc_client_api::{AuxStore, Backend, StorageKey, StorageProvider};
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Arc;
+use subcoin_indexer::ScriptType;
+use subcoin_primitives::descriptor::{descriptor_checksum, with_checksum, Descriptor};
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{BackendExt, CoinStorageKey};
+
+/// Object to scan for, either a descriptor or a descriptor with the derivation range.
+///
+/// `addr(ADDRESS)` and `raw(HEX)` are supported in addition to the wallet descriptors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScanObject {
+    Desc(String),
+    Ranged {
+        desc: String,
+        /// `[0, 999]` by default.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        range: Option<DescriptorRange>,
+    },
+}
+
+/// Position of an interrupted scan.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanCursor {
+    /// Block at which the UTXO set is scanned.
+    pub block_hash: BlockHash,
+    /// Hex-encoded storage key of the last visited coin.
+    pub last_key: String,
+}
+
+/// Unspent output matching one of the scan objects.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanUnspent {
+    pub txid: Txid,
+    pub vout: u32,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: ScriptBuf,
+    /// The matched scan object, with the checksum.
+    pub desc: String,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+    pub coinbase: bool,
+    pub height: u32,
+}
+
+/// Result of `scantxoutset`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanTxOutSetResult {
+    pub height: u32,
+    pub bestblock: BlockHash,
+    /// Number of the coins visited by this call.
+    pub txouts: usize,
+    pub unspents: Vec<ScanUnspent>,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub total_amount: Amount,
+    /// Cursor to continue the scan, `None` if the scan is complete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<ScanCursor>,
+}
+
+/// Default number of coins returned by `subcoin_listCoins`.
+const DEFAULT_LIST_COINS_LIMIT: usize = 1000;
+
+/// Maximum number of coins returned by `subcoin_listCoins`.
+const MAX_LIST_COINS_LIMIT: usize = 10_000;
+
+/// Position in the coins iteration, hex-encoded as the opaque `startKey` of
+/// `subcoin_listCoins`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+struct ListCoinsCursor {
+    /// Bitcoin block at which the coins are listed.
+    block_hash: [u8; 32],
+    /// Storage key of the last listed coin, without the `Coins` prefix.
+    last_key: Vec<u8>,
+}
+
+impl ListCoinsCursor {
+    fn from_hex(cursor: &str) -> Result<Self, Error> {
+        hex::decode(cursor)
+            .ok()
+            .and_then(|encoded| Self::decode(&mut encoded.as_slice()).ok())
+            .ok_or_else(|| Error::Other("Invalid start key".to_string()))
+    }
+
+    fn to_hex(&self) -> String {
+        hex::encode(self.encode())
+    }
+}
+
+/// Coin in the UTXO set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ListedCoin {
+    pub txid: Txid,
+    pub vout: u32,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: ScriptBuf,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+    pub coinbase: bool,
+    pub height: u32,
+}
+
+/// Result of `subcoin_listCoins`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListCoinsResult {
+    pub height: u32,
+    pub bestblock: BlockHash,
+    pub coins: Vec<ListedCoin>,
+    /// Start key of the next page, `None` once all the coins are listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_key: Option<String>,
+}
+
+/// UTXO set scanning API.
+#[rpc(client, server)]
+pub trait ScanApi {
+    /// Scans the UTXO set for the outputs matching the scan objects.
+    ///
+    /// At most `limit` coins are visited if specified, the scan continues from `cursor`
+    /// returned by the previous call.
+    #[method(name = "subcoin_scanTxOutSet", blocking)]
+    fn scan_tx_out_set(
+        &self,
+        scan_objects: Vec<ScanObject>,
+        cursor: Option<ScanCursor>,
+        limit: Option<usize>,
+    ) -> Result<ScanTxOutSetResult, Error>;
+
+    /// Lists the coins of the UTXO set in the storage key order.
+    ///
+    /// The first page is listed at the best block, the following pages are listed at the same
+    /// block by passing `nextKey` of the previous page as `start_key`.
+    ///
+    /// # Arguments
+    ///
+    /// - `start_key`: Opaque key returned by the previous call.
+    /// - `limit`: Number of coins per page, defaults to 1000, at most 10000.
+    /// - `script_type`: Only lists the coins of the script type if specified, the other
+    ///   coins are skipped and a page may visit much more coins than `limit`.
+    #[method(name = "subcoin_listCoins", blocking)]
+    fn list_coins(
+        &self,
+        start_key: Option<String>,
+        limit: Option<usize>,
+        script_type: Option<ScriptType>,
+    ) -> Result<ListCoinsResult, Error>;
+
+    /// Returns the unspent output at the block, the best block by default.
+    ///
+    /// `None` if the output does not exist or has been spent.
+    #[method(name = "subcoin_getCoin", blocking)]
+    fn get_coin(
+        &self,
+        txid: Txid,
+        vout: u32,
+        block_hash: Option<BlockHash>,
+    ) -> Result<Option<ListedCoin>, Error>;
+
+    /// Returns the unspent outputs of the transaction at the block, the best block by default.
+    #[method(name = "subcoin_getCoinsForTxid", blocking)]
+    fn get_coins_for_txid(
+        &self,
+        txid: Txid,
+        block_hash: Option<BlockHash>,
+    ) -> Result<Vec<ListedCoin>, Error>;
+}
+
+/// This struct provides the UTXO set scanning API.
+pub struct Scan<Block, Client, BE> {
+    client: Arc<Client>,
+    network: bitcoin::Network,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    _phantom: PhantomData<(Block, BE)>,
+}
+
+impl<Block, Client, BE> Scan<Block, Client, BE>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    /// Returns the substrate block hash of the block, the best block by default.
+    fn substrate_block_hash(&self, block_hash: Option<BlockHash>) -> Result<Block::Hash, Error> {
+        match block_hash {
+            Some(block_hash) => self
+                .client
+                .substrate_block_hash_for(block_hash)
+                .ok_or(Error::BlockNotFound),
+            None => Ok(self.client.info().best_hash),
+        }
+    }
+}
+
+impl<Block, Client, BE> Scan<Block, Client, BE> {
+    /// Constructs a new instance of [`Scan`].
+    pub fn new(
+        client: Arc<Client>,
+        network: bitcoin::Network,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+    ) -> Self {
+        Self {
+            client,
+            network,
+            coin_storage_key,
+            _phantom: Default::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Block, Client, BE> ScanApiServer for Scan<Block, Client, BE>
+where
+    Block: BlockT + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block> + StorageProvider<Block, BE> + AuxStore + 'static,
+{
+    fn scan_tx_out_set(
+        &self,
+        scan_objects: Vec<ScanObject>,
+        cursor: Option<ScanCursor>,
+        limit: Option<usize>,
+    ) -> Result<ScanTxOutSetResult, Error> {
+        if limit == Some(0) {
+            return Err(Error::Other("limit must be greater than 0".to_string()));
+        }
+
+        let mut scripts = HashMap::new();
+        for scan_object in &scan_objects {
+            scripts.extend(scan_object_scripts(scan_object, self.network)?);
+        }
+
+        let storage_prefix = StorageKey(self.coin_storage_key.storage_prefix().to_vec());
+
+        let (bestblock, start_key) = match cursor {
+            Some(ScanCursor {
+                block_hash,
+                last_key,
+            }) => {
+                let last_key = hex::decode(last_key)
+                    .map_err(|err| Error::Other(format!("Invalid cursor key: {err}")))?;
+                if !last_key.starts_with(&storage_prefix.0) {
+                    return Err(Error::Other("Invalid cursor key".to_string()));
+                }
+                (block_hash, Some(StorageKey(last_key)))
+            }
+            None => {
+                let best_hash = self.client.info().best_hash;
+                let block_hash = self
+                    .client
+                    .bitcoin_block_hash_for(best_hash)
+                    .ok_or(Error::BlockNotFound)?;
+                (block_hash, None)
+            }
+        };
+
+        let height = self
+            .client
+            .block_number(bestblock)
+            .ok_or(Error::BlockNotFound)?;
+        let substrate_block_hash = self
+            .client
+            .substrate_block_hash_for(bestblock)
+            .ok_or(Error::SubstrateBlockHashNotFound)?;
+
+        let mut txouts = 0;
+        let mut unspents = Vec::new();
+        let mut total_amount = Amount::ZERO;
+        let mut last_key: Option<StorageKey> = None;
+        let mut next_cursor = None;
+
+        for (key, value) in self.client.storage_pairs(
+            substrate_block_hash,
+            Some(&storage_prefix),
+            start_key.as_ref(),
+        )? {
+            if limit.is_some_and(|limit| txouts >= limit) {
+                next_cursor = last_key.map(|last_key| ScanCursor {
+                    block_hash: bestblock,
+                    last_key: hex::encode(last_key.0),
+                });
+                break;
+            }
+
+            txouts += 1;
+
+            if let Ok(coin) = Coin::decode(&mut value.0.as_slice()) {
+                let script_pub_key = ScriptBuf::from_bytes(coin.script_pubkey);
+
+                if let Some(desc) = scripts.get(&script_pub_key) {
+                    if let Some(outpoint) = self.coin_storage_key.outpoint(&key.0) {
+                        let amount = Amount::from_sat(coin.amount);
+                        total_amount += amount;
+                        unspents.push(ScanUnspent {
+                            txid: outpoint.txid,
+                            vout: outpoint.vout,
+                            script_pub_key,
+                            desc: desc.clone(),
+                            amount,
+                            coinbase: coin.is_coinbase,
+                            height: coin.height,
+                        });
+                    }
+                }
+            }
+
+            last_key.replace(key);
+        }
+
+        Ok(ScanTxOutSetResult {
+            height,
+            bestblock,
+            txouts,
+            unspents,
+            total_amount,
+            next_cursor,
+        })
+    }
+
+    fn list_coins(
+        &self,
+        start_key: Option<String>,
+        limit: Option<usize>,
+        script_type: Option<ScriptType>,
+    ) -> Result<ListCoinsResult, Error> {
+        let limit = limit
+            .unwrap_or(DEFAULT_LIST_COINS_LIMIT)
+            .min(MAX_LIST_COINS_LIMIT);
+        if limit == 0 {
+            return Err(Error::Other("limit must be greater than 0".to_string()));
+        }
+
+        let storage_prefix = self.coin_storage_key.storage_prefix();
+
+        let (bestblock, start_key) = match start_key {
+            Some(start_key) => {
+                let cursor = ListCoinsCursor::from_hex(&start_key)?;
+                let mut last_key = storage_prefix.to_vec();
+                last_key.extend(cursor.last_key);
+                (
+                    BlockHash::from_byte_array(cursor.block_hash),
+                    Some(StorageKey(last_key)),
+                )
+            }
+            None => {
+                let best_hash = self.client.info().best_hash;
+                let block_hash = self
+                    .client
+                    .bitcoin_block_hash_for(best_hash)
+                    .ok_or(Error::BlockNotFound)?;
+                (block_hash, None)
+            }
+        };
+
+        let height = self
+            .client
+            .block_number(bestblock)
+            .ok_or(Error::BlockNotFound)?;
+        let substrate_block_hash = self
+            .client
+            .substrate_block_hash_for(bestblock)
+            .ok_or(Error::SubstrateBlockHashNotFound)?;
+
+        let mut coins = Vec::new();
+        let mut last_key: Option<StorageKey> = None;
+        let mut next_key = None;
+
+        for (key, value) in self.client.storage_pairs(
+            substrate_block_hash,
+            Some(&StorageKey(storage_prefix.to_vec())),
+            start_key.as_ref(),
+        )? {
+            if coins.len() >= limit {
+                next_key = last_key.map(|last_key| {
+                    ListCoinsCursor {
+                        block_hash: bestblock.to_byte_array(),
+                        last_key: last_key.0[storage_prefix.len()..].to_vec(),
+                    }
+                    .to_hex()
+                });
+                break;
+            }
+
+            let coin = Coin::decode(&mut value.0.as_slice())
+                .map_err(|err| Error::Other(format!("Invalid coin: {err}")))?;
+            let outpoint = self.coin_storage_key.outpoint(&key.0).ok_or_else(|| {
+                Error::Other(format!("Invalid coin key: {}", hex::encode(&key.0)))
+            })?;
+
+            if script_type.map_or(true, |script_type| {
+                ScriptType::classify(Script::from_bytes(&coin.script_pubkey)) == script_type
+            }) {
+                coins.push(listed_coin(outpoint.txid, outpoint.vout, coin));
+            }
+
+            last_key.replace(key);
+        }
+
+        Ok(ListCoinsResult {
+            height,
+            bestblock,
+            coins,
+            next_key,
+        })
+    }
+
+    fn get_coin(
+        &self,
+        txid: Txid,
+        vout: u32,
+        block_hash: Option<BlockHash>,
+    ) -> Result<Option<ListedCoin>, Error> {
+        let substrate_block_hash = self.substrate_block_hash(block_hash)?;
+        let storage_key = StorageKey(self.coin_storage_key.storage_key(txid, vout));
+
+        self.client
+            .storage(substrate_block_hash, &storage_key)?
+            .map(|value| {
+                let coin = Coin::decode(&mut value.0.as_slice())
+                    .map_err(|err| Error::Other(format!("Invalid coin: {err}")))?;
+                Ok(listed_coin(txid, vout, coin))
+            })
+            .transpose()
+    }
+
+    fn get_coins_for_txid(
+        &self,
+        txid: Txid,
+        block_hash: Option<BlockHash>,
+    ) -> Result<Vec<ListedCoin>, Error> {
+        let substrate_block_hash = self.substrate_block_hash(block_hash)?;
+        let prefix = StorageKey(self.coin_storage_key.txid_storage_prefix(txid));
+
+        self.client
+            .storage_pairs(substrate_block_hash, Some(&prefix), None)?
+            .map(|(key, value)| {
+                let coin = Coin::decode(&mut value.0.as_slice())
+                    .map_err(|err| Error::Other(format!("Invalid coin: {err}")))?;
+                let outpoint = self.coin_storage_key.outpoint(&key.0).ok_or_else(|| {
+                    Error::Other(format!("Invalid coin key: {}", hex::encode(&key.0)))
+                })?;
+                Ok(listed_coin(outpoint.txid, outpoint.vout, coin))
+            })
+            .collect()
+    }
+}
+
+fn listed_coin(txid: Txid, vout: u32, coin: Coin) -> ListedCoin {
+    ListedCoin {
+        txid,
+        vout,
+        script_pub_key: ScriptBuf::from_bytes(coin.script_pubkey),
+        amount: Amount::from_sat(coin.amount),
+        coinbase: coin.is_coinbase,
+        height: coin.height,
+    }
+}
+
+/// Returns the scripts of the scan object, along with the descriptor including the checksum.
+fn scan_object_scripts(
+    scan_object: &ScanObject,
+    network: bitcoin::Network,
+) -> Result<HashMap<ScriptBuf, String>, Error> {
+    let (desc, range) = match scan_object {
+        ScanObject::Desc(desc) => (desc, None),
+        ScanObject::Ranged { desc, range } => (desc, range.clone()),
+    };
+
+    let invalid = |err: String| Error::Other(format!("Invalid descriptor {desc}: {err}"));
+
+    let body = match desc.split_once('#') {
+        Some((body, checksum)) => {
+            let expected = descriptor_checksum(body).map_err(|err| invalid(err.to_string()))?;
+            if expected != checksum {
+                return Err(invalid(format!("expected checksum {expected}")));
+            }
+            body
+        }
+        None => desc.as_str(),
+    };
+
+    let desc_with_checksum = with_checksum(body).map_err(|err| invalid(err.to_string()))?;
+
+    if let Some(address) = body.strip_prefix("addr(").and_then(|s| s.strip_suffix(')')) {
+        let script_pubkey = Address::from_str(address)
+            .and_then(|address| address.require_network(network))
+            .map_err(|err| invalid(err.to_string()))?
+            .script_pubkey();
+        return Ok(HashMap::from([(script_pubkey, desc_with_checksum)]));
+    }
+
+    if let Some(hex) = body.strip_prefix("raw(").and_then(|s| s.strip_suffix(')')) {
+        let script_pubkey = ScriptBuf::from_hex(hex).map_err(|err| invalid(err.to_string()))?;
+        return Ok(HashMap::from([(script_pubkey, desc_with_checksum)]));
+    }
+
+    let descriptor = Descriptor::from_str(body).map_err(|err| invalid(err.to_string()))?;
+
+    let scripts = descriptor
+        .script_pubkeys(range.map(Into::into))
+        .map_err(|err| invalid(err.to_string()))?;
+
+    Ok(scripts
+        .into_iter()
+        .map(|(_, script_pubkey)| (script_pubkey, desc_with_checksum.clone()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_coins_cursor() {
+        let cursor = ListCoinsCursor {
+            block_hash: [7u8; 32],
+            last_key: vec![1, 2, 3],
+        };
+        assert_eq!(ListCoinsCursor::from_hex(&cursor.to_hex()).unwrap(), cursor);
+        assert!(ListCoinsCursor::from_hex("zz").is_err());
+        assert!(ListCoinsCursor::from_hex("0102").is_err());
+    }
+
+    #[test]
+    fn test_scan_object_scripts() {
+        let network = bitcoin::Network::Bitcoin;
+        let address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+
+        let scripts =
+            scan_object_scripts(&ScanObject::Desc(format!("addr({address})")), network).unwrap();
+        let script_pubkey = Address::from_str(address)
+            .unwrap()
+            .assume_checked()
+            .script_pubkey();
+        let desc = with_checksum(&format!("addr({address})")).unwrap();
+        assert_eq!(scripts, HashMap::from([(script_pubkey.clone(), desc)]));
+
+        // Raw script with the checksum.
+        let raw = with_checksum(&format!("raw({})", script_pubkey.to_hex_string())).unwrap();
+        let scripts = scan_object_scripts(&ScanObject::Desc(raw.clone()), network).unwrap();
+        assert_eq!(scripts, HashMap::from([(script_pubkey, raw)]));
+
+        // Address of another network.
+        assert!(scan_object_scripts(
+            &ScanObject::Desc(format!("addr({address})")),
+            bitcoin::Network::Testnet
+        )
+        .is_err());
+
+        // Bad checksum.
+        assert!(scan_object_scripts(
+            &ScanObject::Desc(format!("addr({address})#00000000")),
+            network
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! RPC server enforcing [`RpcAuth`].
+//!
+//! Substrate's RPC server has no hook for the HTTP headers, the authenticated server is used
+//! instead of it once the RPC authentication is enabled.
+
+use crate::auth::{PermissionGroup, RpcAuth};
+use futures::future::{BoxFuture, Either, FutureExt, Ready};
+use jsonrpsee::server::middleware::rpc::{RpcServiceBuilder, RpcServiceT};
+use jsonrpsee::server::{
+    serve_with_graceful_shutdown, stop_channel, ws, BatchRequestConfig, ConnectionGuard, HttpBody,
+    HttpRequest, HttpResponse, Methods, ServerHandle,
+};
+use jsonrpsee::types::{ErrorObject, Request};
+use jsonrpsee::{MethodResponse, RpcModule};
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower::Service;
+
+type ResponseFuture =
+    BoxFuture<'static, Result<HttpResponse, Box<dyn std::error::Error + Send + Sync>>>;
+
+/// Error code of the calls to the methods not granted to the client.
+const FORBIDDEN_ERROR_CODE: i32 = -32604;
+
+/// Config of the authenticated RPC server.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    pub max_connections: u32,
+    pub max_subs_per_conn: u32,
+    pub max_payload_in_mb: u32,
+    pub max_payload_out_mb: u32,
+    pub message_buffer_capacity: u32,
+    pub batch_config: BatchRequestConfig,
+}
+
+/// Starts the RPC server, requiring the clients to authenticate as specified by `auth`.
+///
+/// Returns the handle of the server, the server stops once the handle is dropped.
+pub fn start_server(
+    config: ServerConfig,
+    rpc_api: RpcModule<()>,
+    auth: RpcAuth,
+    tokio_handle: tokio::runtime::Handle,
+) -> std::io::Result<ServerHandle> {
+    let ServerConfig {
+        addr,
+        max_connections,
+        max_subs_per_conn,
+        max_payload_in_mb,
+        max_payload_out_mb,
+        message_buffer_capacity,
+        batch_config,
+    } = config;
+
+    let _guard = tokio_handle.enter();
+
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let local_addr = listener.local_addr()?;
+
+    let (stop_handle, server_handle) = stop_channel();
+
+    let service_builder = jsonrpsee::server::Server::builder()
+        .max_request_body_size(max_payload_in_mb.saturating_mul(1024 * 1024))
+        .max_response_body_size(max_payload_out_mb.saturating_mul(1024 * 1024))
+        .max_connections(max_connections)
+        .max_subscriptions_per_connection(max_subs_per_conn)
+        .set_message_buffer_capacity(message_buffer_capacity)
+        .set_batch_request_config(batch_config)
+        .to_service_builder();
+
+    let methods: Methods = rpc_api.into();
+    let conn_guard = ConnectionGuard::new(max_connections as usize);
+    let auth = Arc::new(auth);
+
+    tokio_handle.spawn(async move {
+        loop {
+            let socket = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((socket, _remote_addr)) => socket,
+                    Err(err) => {
+                        tracing::debug!(?err, "Failed to accept the RPC connection");
+                        continue;
+                    }
+                },
+                _ = stop_handle.clone().shutdown() => break,
+            };
+
+            let auth = auth.clone();
+            let methods = methods.clone();
+            let service_builder = service_builder.clone();
+            let stop_handle = stop_handle.clone();
+            let conn_guard = conn_guard.clone();
+
+            let svc = tower::service_fn(
+                move |req: HttpRequest<hyper::body::Incoming>| -> ResponseFuture {
+                    let authorization = req
+                        .headers()
+                        .get(http::header::AUTHORIZATION)
+                        .and_then(|value| value.to_str().ok());
+
+                    let granted = match auth.authenticate(authorization) {
+                        Ok(granted) => granted,
+                        Err(err) => {
+                            tracing::debug!(?err, "Rejected unauthenticated RPC request");
+                            return async { Ok(unauthorized()) }.boxed();
+                        }
+                    };
+
+                    let Some(conn_permit) = conn_guard.try_acquire() else {
+                        return async { Ok(too_many_connections()) }.boxed();
+                    };
+
+                    let granted = Arc::new(granted);
+                    let auth = auth.clone();
+                    let rpc_middleware = RpcServiceBuilder::new().layer_fn(move |service| Acl {
+                        service,
+                        granted: granted.clone(),
+                        auth: auth.clone(),
+                    });
+
+                    let is_websocket = ws::is_upgrade_request(&req);
+                    let mut svc = service_builder
+                        .clone()
+                        .set_rpc_middleware(rpc_middleware)
+                        .build(methods.clone(), stop_handle.clone());
+
+                    if is_websocket {
+                        let on_disconnect = svc.on_session_closed();
+                        tokio::spawn(async move {
+                            on_disconnect.await;
+                            drop(conn_permit);
+                        });
+                        async move { svc.call(req).await }.boxed()
+                    } else {
+                        async move {
+                            let response = svc.call(req).await;
+                            drop(conn_permit);
+                            response
+                        }
+                        .boxed()
+                    }
+                },
+            );
+
+            tokio::spawn(serve_with_graceful_shutdown(
+                socket,
+                svc,
+                stop_handle.clone().shutdown(),
+            ));
+        }
+    });
+
+    tracing::info!("Running authenticated JSON-RPC server: addr={local_addr}");
+
+    Ok(server_handle)
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::builder()
+        .status(http::StatusCode::UNAUTHORIZED)
+        .header(http::header::WWW_AUTHENTICATE, "Basic realm=\"jsonrpc\"")
+        .body(HttpBody::from("Unauthorized"))
+        .expect("Unauthorized response is valid; qed")
+}
+
+fn too_many_connections() -> HttpResponse {
+    HttpResponse::builder()
+        .status(http::StatusCode::TOO_MANY_REQUESTS)
+        .body(HttpBody::from(
+            "Too many connections. Please try again later.",
+        ))
+        .expect("Too many connections response is valid; qed")
+}
+
+/// RPC middleware rejecting the calls to the methods not granted to the client.
+#[derive(Debug, Clone)]
+struct Acl<S> {
+    service: S,
+    granted: Arc<BTreeSet<PermissionGroup>>,
+    auth: Arc<RpcAuth>,
+}
+
+impl<'a, S> RpcServiceT<'a> for Acl<S>
+where
+    S: RpcServiceT<'a> + Send + Sync,
+{
+    type Future = Either<S::Future, Ready<MethodResponse>>;
+
+    fn call(&self, request: Request<'a>) -> Self::Future {
+        let group = self.auth.permissions().group(request.method_name());
+
+        if self.granted.contains(&group) {
+            Either::Left(self.service.call(request))
+        } else {
+            Either::Right(futures::future::ready(MethodResponse::error(
+                request.id(),
+                ErrorObject::owned(
+                    FORBIDDEN_ERROR_CODE,
+                    format!(
+                        "Method {} requires the {group} permission",
+                        request.method_name()
+                    ),
+                    None::<()>,
+                ),
+            )))
+        }
+    }
+}
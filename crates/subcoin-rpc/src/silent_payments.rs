@@ -0,0 +1,80 @@
+use crate::error::Error;
+use bitcoin::BlockHash;
+use jsonrpsee::proc_macros::rpc;
+use sc_client_api::{AuxStore, HeaderBackend};
+use sp_runtime::traits::Block as BlockT;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use subcoin_db::SubcoinDb;
+use subcoin_primitives::BackendExt;
+
+#[rpc(client, server)]
+pub trait SilentPaymentsApi {
+    /// Get the BIP-352 tweaks of the eligible transactions in a finalized block.
+    ///
+    /// Each tweak is a hex-encoded compressed public key `input_hash·A`.
+    ///
+    /// # Arguments
+    ///
+    /// - `blockhash`: Hash of the block.
+    /// - `dust_limit`: Skips the transactions whose taproot outputs are all below the value
+    ///   in satoshis, defaults to 0.
+    #[method(name = "subcoin_getSilentPaymentTweaks", blocking)]
+    fn silent_payment_tweaks(
+        &self,
+        blockhash: BlockHash,
+        dust_limit: Option<u64>,
+    ) -> Result<Vec<String>, Error>;
+}
+
+/// This struct provides the silent payments index API.
+pub struct SilentPayments<Block, Client> {
+    client: Arc<Client>,
+    db: SubcoinDb,
+    _phantom: PhantomData<Block>,
+}
+
+impl<Block, Client> SilentPayments<Block, Client> {
+    /// Constructs a new instance of [`SilentPayments`].
+    pub fn new(client: Arc<Client>, db: SubcoinDb) -> Self {
+        Self {
+            client,
+            db,
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<Block, Client> SilentPaymentsApiServer for SilentPayments<Block, Client>
+where
+    Block: BlockT + 'static,
+    Client: HeaderBackend<Block> + AuxStore + 'static,
+{
+    fn silent_payment_tweaks(
+        &self,
+        blockhash: BlockHash,
+        dust_limit: Option<u64>,
+    ) -> Result<Vec<String>, Error> {
+        let height = self
+            .client
+            .block_number(blockhash)
+            .ok_or(Error::BlockNotFound)?;
+
+        // Only the blocks in the best chain are indexed.
+        if self.client.block_hash(height) != Some(blockhash) {
+            return Err(Error::BlockNotFound);
+        }
+
+        let tweaks = subcoin_indexer::silent_payment_tweaks(&self.db, height)
+            .map_err(|err| Error::Other(err.to_string()))?
+            .ok_or_else(|| Error::Other(format!("Block #{height} not indexed yet")))?;
+
+        let dust_limit = dust_limit.unwrap_or(0);
+
+        Ok(tweaks
+            .into_iter()
+            .filter(|tweak| tweak.max_output_value >= dust_limit)
+            .map(|tweak| hex::encode(tweak.tweak))
+            .collect())
+    }
+}
@@ -0,0 +1,32 @@
+use crate::error::Error;
+use jsonrpsee::proc_macros::rpc;
+use sc_consensus_nakamoto::{StateRootAudit, StateRootAuditStatus};
+use serde::Serialize;
+
+#[rpc(client, server)]
+pub trait StateRootAuditApi<Hash> {
+    /// Returns the progress of the background state root audit with the runtime.
+    #[method(name = "subcoin_getStateRootAudit", blocking)]
+    fn state_root_audit(&self) -> Result<StateRootAuditStatus<Hash>, Error>;
+}
+
+/// This struct provides the state root audit API.
+pub struct StateRootAuditRpc<Hash> {
+    audit: StateRootAudit<Hash>,
+}
+
+impl<Hash> StateRootAuditRpc<Hash> {
+    /// Constructs a new instance of [`StateRootAuditRpc`].
+    pub fn new(audit: StateRootAudit<Hash>) -> Self {
+        Self { audit }
+    }
+}
+
+impl<Hash> StateRootAuditApiServer<Hash> for StateRootAuditRpc<Hash>
+where
+    Hash: Clone + Send + Sync + Serialize + 'static,
+{
+    fn state_root_audit(&self) -> Result<StateRootAuditStatus<Hash>, Error> {
+        Ok(self.audit.status())
+    }
+}
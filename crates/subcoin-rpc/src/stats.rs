@@ -0,0 +1,64 @@
+use crate::error::Error;
+use jsonrpsee::proc_macros::rpc;
+use subcoin_db::SubcoinDb;
+use subcoin_indexer::{ChainStats, UtxoComposition};
+
+#[rpc(client, server)]
+pub trait StatsApi {
+    /// Get the cumulative UTXO set statistics within the height range (inclusive).
+    ///
+    /// The statistics are recorded every 144 blocks, followed by the ones at the last
+    /// indexed block if it's in the range.
+    ///
+    /// # Arguments
+    ///
+    /// - `from_height`: Start height, defaults to 0.
+    /// - `to_height`: End height, defaults to the last indexed block.
+    #[method(name = "subcoin_getStatsRange", blocking)]
+    fn stats_range(
+        &self,
+        from_height: Option<u32>,
+        to_height: Option<u32>,
+    ) -> Result<Vec<ChainStats>, Error>;
+
+    /// Get the composition of the UTXO set by script type.
+    ///
+    /// `height` must be one of the recorded heights, i.e., a multiple of 144, or the last
+    /// indexed block, which is the default.
+    #[method(name = "subcoin_getUtxoComposition", blocking)]
+    fn utxo_composition(&self, height: Option<u32>) -> Result<Option<UtxoComposition>, Error>;
+}
+
+/// This struct provides the chain stats API.
+pub struct Stats {
+    db: SubcoinDb,
+}
+
+impl Stats {
+    /// Constructs a new instance of [`Stats`].
+    pub fn new(db: SubcoinDb) -> Self {
+        Self { db }
+    }
+}
+
+impl StatsApiServer for Stats {
+    fn stats_range(
+        &self,
+        from_height: Option<u32>,
+        to_height: Option<u32>,
+    ) -> Result<Vec<ChainStats>, Error> {
+        let from = from_height.unwrap_or(0);
+        let to = to_height.unwrap_or(u32::MAX);
+
+        if from > to {
+            return Err(Error::Other(format!("Invalid height range [{from}, {to}]")));
+        }
+
+        subcoin_indexer::chain_stats_range(&self.db, from, to)
+            .map_err(|err| Error::Other(err.to_string()))
+    }
+    fn utxo_composition(&self, height: Option<u32>) -> Result<Option<UtxoComposition>, Error> {
+        subcoin_indexer::utxo_composition(&self.db, height)
+            .map_err(|err| Error::Other(err.to_string()))
+    }
+}
@@ -1,14 +1,25 @@
 use crate::error::Error;
+use crate::fee_estimation::FeeEstimator;
 use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
-use bitcoin::{Transaction, Txid};
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::schnorr;
+use bitcoin::{BlockHash, Transaction, Txid, Wtxid};
 use jsonrpsee::proc_macros::rpc;
-use sc_client_api::{AuxStore, BlockBackend, HeaderBackend};
+use sc_client_api::{AuxStore, Backend, BlockBackend, HeaderBackend, LockImportRun};
+use sc_consensus_nakamoto::{FinalityConflict, InvalidBlocks};
 use serde::{Deserialize, Serialize};
-use sp_runtime::traits::Block as BlockT;
+use sp_runtime::traits::{Block as BlockT, SaturatedConversion};
 use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use subcoin_db::SubcoinDb;
 use subcoin_network::{
-    NetworkHandle, NetworkStatus, PeerSync, PeerSyncState, SendTransactionResult,
+    BroadcastStatus, NetworkHandle, NetworkInfo, NetworkStatus, PeerDetails, PeerSync,
+    PeerSyncState, SendTransactionResult,
+};
+use subcoin_primitives::{
+    convert_to_bitcoin_block, BackendExt, BitcoinTransactionAdapter, FinalityCheckpointError,
+    FinalityOracle,
 };
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -21,6 +32,53 @@ pub struct NetworkPeers {
     sync_peers: Vec<PeerSync>,
 }
 
+/// Result of `subcoin_saveMempool`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SaveMempoolResult {
+    /// Path of the mempool file.
+    pub filename: String,
+    /// Number of the saved transactions.
+    pub transactions: usize,
+    /// Path of the fee estimates file, if the fee rates are persisted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_estimates_filename: Option<String>,
+}
+
+/// Progress of the block finalization, returned by `subcoin_getFinalizedBitcoinBlock`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinalizedBitcoinBlock {
+    /// Bitcoin block hash of the finalized block.
+    pub hash: BlockHash,
+    pub height: u32,
+    /// Substrate block hash of the finalized block.
+    pub substrate_hash: String,
+    pub best_height: u32,
+    /// Bitcoin confirmations of the finalized block, including itself.
+    pub confirmations: u32,
+    /// Confirmations after which a block is finalized, `None` if the blocks are not finalized
+    /// by depth.
+    pub confirmation_depth: Option<u32>,
+    /// Whether the node is major syncing, the blocks are finalized in larger steps meanwhile.
+    pub major_syncing: bool,
+}
+
+/// Confirmations of a block or transaction, returned by `subcoin_getConfirmationDepth`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmationDepth {
+    /// Hash of the block, or of the block including the transaction.
+    pub block_hash: BlockHash,
+    pub height: u32,
+    /// Bitcoin confirmations, including the block itself.
+    pub confirmations: u32,
+    /// Whether the block is finalized, i.e., it's never reverted by a reorg.
+    pub finalized: bool,
+    /// Confirmations still required for the block to be finalized, `None` if the blocks are
+    /// not finalized by depth.
+    pub confirmations_until_finalized: Option<u32>,
+}
+
 #[rpc(client, server)]
 pub trait SubcoinApi {
     /// Returns a JSON object representing the serialized, hex-encoded transaction.
@@ -31,7 +89,10 @@ pub trait SubcoinApi {
     #[method(name = "subcoin_decodeRawTransaction", blocking)]
     fn decode_raw_transaction(&self, raw_tx: String) -> Result<serde_json::Value, Error>;
 
-    /// Returns the raw transaction data for given txid.
+    /// Returns the raw transaction data for given txid or wtxid.
+    ///
+    /// The unconfirmed transactions known to the node are always looked up, the confirmed
+    /// ones only with `--txindex`.
     #[method(name = "subcoin_getRawTransaction")]
     async fn get_raw_transaction(&self, txid: Txid) -> Result<Option<String>, Error>;
 
@@ -43,6 +104,15 @@ pub trait SubcoinApi {
     #[method(name = "subcoin_networkPeers")]
     async fn network_peers(&self) -> Result<NetworkPeers, Error>;
 
+    /// Returns the details of each connected peer, similar to `getpeerinfo` in Bitcoin Core.
+    #[method(name = "subcoin_getPeerInfo")]
+    async fn get_peer_info(&self) -> Result<Vec<PeerDetails>, Error>;
+
+    /// Returns the aggregate p2p networking information, similar to `getnetworkinfo` in
+    /// Bitcoin Core.
+    #[method(name = "subcoin_getNetworkInfo")]
+    async fn get_network_info(&self) -> Result<Option<NetworkInfo>, Error>;
+
     /// Submits a raw transaction (serialized, hex-encoded) to local node and network.
     ///
     /// # Arguments
@@ -50,36 +120,182 @@ pub trait SubcoinApi {
     /// - `raw_tx`:  The hex string of the raw transaction.
     #[method(name = "subcoin_sendRawTransaction")]
     async fn send_raw_transaction(&self, raw_tx: String) -> Result<SendTransactionResult, Error>;
+
+    /// Returns the broadcast status of a transaction submitted to this node by txid or wtxid,
+    /// including the peers it was announced to and their feedback. Returns `null` if the
+    /// transaction was not submitted locally or was confirmed more than an hour ago.
+    #[method(name = "subcoin_getTransactionBroadcastStatus")]
+    async fn get_transaction_broadcast_status(
+        &self,
+        txid: Txid,
+    ) -> Result<Option<BroadcastStatus>, Error>;
+
+    /// Writes the unconfirmed transactions to `mempool.dat`, similar to `savemempool` in
+    /// Bitcoin Core. The transactions are also saved on shutdown unless `--no-persist-mempool`
+    /// is specified, along with the fee rates tracked by the fee estimator.
+    #[method(name = "subcoin_saveMempool")]
+    async fn save_mempool(&self) -> Result<SaveMempoolResult, Error>;
+
+    /// Marks the block and its descendants invalid, the best block is switched to the valid
+    /// tip with the most work. Returns the new best block hash.
+    #[method(name = "subcoin_invalidateBlock", blocking)]
+    fn invalidate_block(&self, block_hash: BlockHash) -> Result<BlockHash, Error>;
+
+    /// Removes the invalid mark set by `subcoin_invalidateBlock` or on import from the block,
+    /// its descendants and ancestors. Returns the new best block hash.
+    #[method(name = "subcoin_reconsiderBlock", blocking)]
+    fn reconsider_block(&self, block_hash: BlockHash) -> Result<BlockHash, Error>;
+
+    /// Returns the last finalized block and its Bitcoin confirmations.
+    #[method(name = "subcoin_getFinalizedBitcoinBlock", blocking)]
+    fn get_finalized_bitcoin_block(&self) -> Result<FinalizedBitcoinBlock, Error>;
+
+    /// Returns the confirmations and the finality of the block or of the block including the
+    /// transaction, the confirmed transactions are only looked up with `--txindex`.
+    ///
+    /// Returns `null` if neither a block in the best chain nor a confirmed transaction is found.
+    #[method(name = "subcoin_getConfirmationDepth", blocking)]
+    fn get_confirmation_depth(
+        &self,
+        txid_or_block_hash: String,
+    ) -> Result<Option<ConfirmationDepth>, Error>;
+
+    /// Submits a checkpoint to the external finality oracle, the block is finalized once it's
+    /// in the best chain.
+    ///
+    /// `signature` is the hex-encoded BIP340 signature of the `--finality-signer` over the
+    /// checkpoint message of the block. Only available with `--finality-policy external`.
+    #[method(name = "subcoin_submitFinalityCheckpoint", blocking)]
+    fn submit_finality_checkpoint(
+        &self,
+        block_hash: BlockHash,
+        signature: schnorr::Signature,
+    ) -> Result<(), Error>;
+
+    /// Returns the detected fork below the finalized block, which can't be followed until
+    /// the finalized blocks are reverted with `subcoin revert-finalized --force`.
+    #[method(name = "subcoin_getFinalityConflict", blocking)]
+    fn get_finality_conflict(&self) -> Result<Option<FinalityConflict>, Error>;
 }
 
 /// This struct provides the Subcoin API.
-pub struct Subcoin<Block, Client> {
-    #[allow(unused)]
+pub struct Subcoin<Block, Client, BE, TransactionAdapter> {
     client: Arc<Client>,
     network_handle: NetworkHandle,
-    _phantom: PhantomData<Block>,
+    invalid_blocks: InvalidBlocks<Block, Client, BE>,
+    /// Database of the transaction index, if enabled.
+    tx_index_db: Option<SubcoinDb>,
+    /// Confirmations after which a block is finalized, `None` if the blocks are not finalized
+    /// by depth.
+    confirmation_depth: Option<u32>,
+    /// Oracle of the external finality policy.
+    finality_oracle: Option<FinalityOracle>,
+    /// Fee estimator saved along with the mempool.
+    fee_estimator: Option<FeeEstimator>,
+    _phantom: PhantomData<(Block, TransactionAdapter)>,
 }
 
-impl<Block, Client> Subcoin<Block, Client>
+impl<Block, Client, BE, TransactionAdapter> Subcoin<Block, Client, BE, TransactionAdapter>
 where
     Block: BlockT + 'static,
-    Client: HeaderBackend<Block> + BlockBackend<Block> + AuxStore + 'static,
+    BE: Backend<Block> + 'static,
+    Client:
+        HeaderBackend<Block> + BlockBackend<Block> + AuxStore + LockImportRun<Block, BE> + 'static,
+    TransactionAdapter: BitcoinTransactionAdapter<Block>,
 {
     /// Constructs a new instance of [`Subcoin`].
-    pub fn new(client: Arc<Client>, network_handle: NetworkHandle) -> Self {
+    pub fn new(
+        client: Arc<Client>,
+        backend: Arc<BE>,
+        network_handle: NetworkHandle,
+        tx_index_db: Option<SubcoinDb>,
+    ) -> Self {
         Self {
+            invalid_blocks: InvalidBlocks::new(client.clone(), backend),
             client,
             network_handle,
+            tx_index_db,
+            confirmation_depth: None,
+            finality_oracle: None,
+            fee_estimator: None,
             _phantom: Default::default(),
         }
     }
+
+    /// Sets the confirmation depth of the finalizer, `None` by default.
+    pub fn with_confirmation_depth(mut self, confirmation_depth: Option<u32>) -> Self {
+        self.confirmation_depth = confirmation_depth;
+        self
+    }
+
+    /// Sets the oracle receiving the checkpoints of the external finality policy.
+    pub fn with_finality_oracle(mut self, finality_oracle: Option<FinalityOracle>) -> Self {
+        self.finality_oracle = finality_oracle;
+        self
+    }
+
+    /// Sets the fee estimator whose fee rates are saved by `subcoin_saveMempool`.
+    pub fn with_fee_estimator(mut self, fee_estimator: FeeEstimator) -> Self {
+        self.fee_estimator = Some(fee_estimator);
+        self
+    }
+
+    /// Returns the height of the block if it's in the best chain.
+    fn best_chain_height(&self, block_hash: BlockHash) -> Option<u32> {
+        BackendExt::<Block>::block_number(&self.client, block_hash).filter(|height| {
+            BackendExt::<Block>::block_hash(&self.client, *height) == Some(block_hash)
+        })
+    }
+
+    /// Returns the confirmed transaction with the txid or wtxid from the transaction index.
+    fn confirmed_transaction(
+        &self,
+        db: &SubcoinDb,
+        txid: Txid,
+    ) -> Result<Option<Transaction>, Error> {
+        let index_error = |err: subcoin_indexer::Error| Error::Other(err.to_string());
+
+        let location = match subcoin_indexer::tx_location(db, &txid).map_err(index_error)? {
+            Some(location) => location,
+            None => {
+                let wtxid = Wtxid::from_raw_hash(txid.to_raw_hash());
+                let Some(txid) =
+                    subcoin_indexer::txid_for_wtxid(db, &wtxid).map_err(index_error)?
+                else {
+                    return Ok(None);
+                };
+                let Some(location) =
+                    subcoin_indexer::tx_location(db, &txid).map_err(index_error)?
+                else {
+                    return Ok(None);
+                };
+                location
+            }
+        };
+
+        let Some(block_hash) = self.client.hash(location.height.into())? else {
+            return Ok(None);
+        };
+        let Some(signed_block) = self.client.block(block_hash)? else {
+            return Err(Error::BlockPruned);
+        };
+
+        let block = convert_to_bitcoin_block::<Block, TransactionAdapter>(signed_block.block)
+            .map_err(Error::Header)?;
+
+        Ok(block.txdata.into_iter().nth(location.index as usize))
+    }
 }
 
 #[async_trait::async_trait]
-impl<Block, Client> SubcoinApiServer for Subcoin<Block, Client>
+impl<Block, Client, BE, TransactionAdapter> SubcoinApiServer
+    for Subcoin<Block, Client, BE, TransactionAdapter>
 where
     Block: BlockT + 'static,
-    Client: HeaderBackend<Block> + BlockBackend<Block> + AuxStore + 'static,
+    BE: Backend<Block> + 'static,
+    Client:
+        HeaderBackend<Block> + BlockBackend<Block> + AuxStore + LockImportRun<Block, BE> + 'static,
+    TransactionAdapter: BitcoinTransactionAdapter<Block> + Send + Sync + 'static,
 {
     fn decode_raw_transaction(&self, raw_tx: String) -> Result<serde_json::Value, Error> {
         let transaction = deserialize_hex::<Transaction>(&raw_tx)?;
@@ -87,7 +303,14 @@ where
     }
 
     async fn get_raw_transaction(&self, txid: Txid) -> Result<Option<String>, Error> {
-        let maybe_transaction = self.network_handle.get_transaction(txid).await;
+        let mut maybe_transaction = self.network_handle.get_transaction(txid).await;
+
+        if maybe_transaction.is_none() {
+            if let Some(db) = &self.tx_index_db {
+                maybe_transaction = self.confirmed_transaction(db, txid)?;
+            }
+        }
+
         Ok(maybe_transaction.as_ref().map(serialize_hex))
     }
 
@@ -123,10 +346,155 @@ where
         Ok(self.network_handle.status().await)
     }
 
+    async fn get_peer_info(&self) -> Result<Vec<PeerDetails>, Error> {
+        Ok(self.network_handle.peer_details().await)
+    }
+
+    async fn get_network_info(&self) -> Result<Option<NetworkInfo>, Error> {
+        Ok(self.network_handle.network_info().await)
+    }
+
     async fn send_raw_transaction(&self, raw_tx: String) -> Result<SendTransactionResult, Error> {
         Ok(self
             .network_handle
             .send_transaction(deserialize_hex::<Transaction>(&raw_tx)?)
             .await)
     }
+
+    async fn get_transaction_broadcast_status(
+        &self,
+        txid: Txid,
+    ) -> Result<Option<BroadcastStatus>, Error> {
+        Ok(self.network_handle.broadcast_status(txid).await)
+    }
+
+    async fn save_mempool(&self) -> Result<SaveMempoolResult, Error> {
+        let (path, transactions) = self
+            .network_handle
+            .save_mempool()
+            .await
+            .map_err(|err| Error::Other(err.to_string()))?;
+
+        let fee_estimates_filename = match &self.fee_estimator {
+            Some(fee_estimator) => fee_estimator
+                .save()
+                .map_err(|err| Error::Other(format!("Failed to save the fee estimates: {err}")))?
+                .map(|path| path.display().to_string()),
+            None => None,
+        };
+
+        Ok(SaveMempoolResult {
+            filename: path.display().to_string(),
+            transactions,
+            fee_estimates_filename,
+        })
+    }
+
+    fn invalidate_block(&self, block_hash: BlockHash) -> Result<BlockHash, Error> {
+        self.invalid_blocks
+            .invalidate_block(block_hash)
+            .map_err(|err| Error::Client(Box::new(err)))
+    }
+
+    fn reconsider_block(&self, block_hash: BlockHash) -> Result<BlockHash, Error> {
+        self.invalid_blocks
+            .reconsider_block(block_hash)
+            .map_err(|err| Error::Client(Box::new(err)))
+    }
+
+    fn get_finalized_bitcoin_block(&self) -> Result<FinalizedBitcoinBlock, Error> {
+        let info = self.client.info();
+        let height = info.finalized_number.saturated_into::<u32>();
+        let best_height = info.best_number.saturated_into::<u32>();
+
+        let hash = BackendExt::<Block>::bitcoin_block_hash_for(&self.client, info.finalized_hash)
+            .ok_or(Error::BlockNotFound)?;
+
+        Ok(FinalizedBitcoinBlock {
+            hash,
+            height,
+            substrate_hash: format!("{:?}", info.finalized_hash),
+            best_height,
+            confirmations: best_height.saturating_sub(height) + 1,
+            confirmation_depth: self.confirmation_depth,
+            major_syncing: self
+                .network_handle
+                .is_major_syncing()
+                .load(Ordering::Relaxed),
+        })
+    }
+
+    fn get_confirmation_depth(
+        &self,
+        txid_or_block_hash: String,
+    ) -> Result<Option<ConfirmationDepth>, Error> {
+        let hash = txid_or_block_hash
+            .parse::<BlockHash>()
+            .map_err(|err| Error::Other(format!("Invalid txid or block hash: {err}")))?;
+
+        let located = match self.best_chain_height(hash) {
+            Some(height) => Some((hash, height)),
+            None => match &self.tx_index_db {
+                Some(db) => {
+                    let txid = Txid::from_raw_hash(hash.to_raw_hash());
+                    subcoin_indexer::tx_location(db, &txid)
+                        .map_err(|err| Error::Other(err.to_string()))?
+                        .and_then(|location| {
+                            BackendExt::<Block>::block_hash(&self.client, location.height)
+                                .map(|block_hash| (block_hash, location.height))
+                        })
+                }
+                None => None,
+            },
+        };
+
+        let Some((block_hash, height)) = located else {
+            return Ok(None);
+        };
+
+        let info = self.client.info();
+        let best_height = info.best_number.saturated_into::<u32>();
+        let finalized_height = info.finalized_number.saturated_into::<u32>();
+
+        let confirmations = best_height.saturating_sub(height) + 1;
+        let finalized = height <= finalized_height;
+
+        Ok(Some(ConfirmationDepth {
+            block_hash,
+            height,
+            confirmations,
+            finalized,
+            confirmations_until_finalized: self.confirmation_depth.map(|depth| {
+                if finalized {
+                    0
+                } else {
+                    // The block at `best - depth` is finalized.
+                    (depth + 1).saturating_sub(confirmations)
+                }
+            }),
+        }))
+    }
+
+    fn submit_finality_checkpoint(
+        &self,
+        block_hash: BlockHash,
+        signature: schnorr::Signature,
+    ) -> Result<(), Error> {
+        let Some(finality_oracle) = &self.finality_oracle else {
+            return Err(Error::Other(
+                "External finality policy is not enabled".to_string(),
+            ));
+        };
+
+        finality_oracle
+            .submit_checkpoint(&*self.client, block_hash, &signature)
+            .map_err(|err| match err {
+                FinalityCheckpointError::Client(err) => err.into(),
+                err => Error::Other(err.to_string()),
+            })
+    }
+
+    fn get_finality_conflict(&self) -> Result<Option<FinalityConflict>, Error> {
+        Ok(FinalityConflict::load(&*self.client))
+    }
 }
@@ -0,0 +1,197 @@
+use crate::error::Error;
+use bitcoin::OutPoint;
+use jsonrpsee::core::SubscriptionResult;
+use jsonrpsee::proc_macros::rpc;
+use jsonrpsee::{PendingSubscriptionSink, SubscriptionMessage};
+use sc_client_api::{HeaderBackend, ProofProvider};
+use serde::{Deserialize, Serialize};
+use sp_runtime::traits::Block as BlockT;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use subcoin_utxo_proof::{UtxoProof, UtxoProofChunk, UtxoProofHeaders};
+
+/// Maximum number of outputs proven by `subcoin_getUtxoProof`.
+const MAX_OUTPOINTS: usize = 1000;
+
+/// Maximum number of outputs proven by `subcoin_subscribeUtxoProof`.
+const MAX_STREAMED_OUTPOINTS: usize = 100_000;
+
+/// Default number of outputs per chunk of `subcoin_subscribeUtxoProof`.
+const DEFAULT_CHUNK_SIZE: u32 = 256;
+
+/// Maximum confirmations of the block proven by `subcoin_getUtxoProof`.
+const MAX_CONFIRMATIONS: u32 = 2016;
+
+/// Default confirmations of the block proven by `subcoin_getUtxoProof`.
+const DEFAULT_CONFIRMATIONS: u32 = 6;
+
+/// Item of `subcoin_subscribeUtxoProof`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum UtxoProofItem {
+    /// Header chain of the proof, always the first item.
+    Headers(UtxoProofHeaders),
+    /// Proof of the next chunk of the coins.
+    Chunk(UtxoProofChunk),
+    /// All the coins have been proven, always the last item.
+    Done,
+}
+
+#[rpc(client, server)]
+pub trait UtxoProofApi {
+    /// Returns the proof of the coins in the UTXO set at the best chain block with
+    /// `confirmations`, which can be verified without a node by `subcoin verify-utxo`.
+    ///
+    /// # Arguments
+    ///
+    /// - `outpoints`: Outputs in the format of `txid:vout`, at most 1000.
+    /// - `confirmations`: Confirmations of the proven block including itself, defaults to 6,
+    ///   at most 2016.
+    #[method(name = "subcoin_getUtxoProof", blocking)]
+    fn get_utxo_proof(
+        &self,
+        outpoints: Vec<OutPoint>,
+        confirmations: Option<u32>,
+    ) -> Result<UtxoProof, Error>;
+
+    /// Same as `subcoin_getUtxoProof`, but streams the proof in chunks for proving large sets
+    /// of outputs, e.g., all the outputs of an address.
+    ///
+    /// The header chain is sent first, followed by the chunks and a final `done`. The outputs
+    /// are sorted and deduplicated.
+    ///
+    /// # Arguments
+    ///
+    /// - `outpoints`: Outputs in the format of `txid:vout`, at most 100000.
+    /// - `confirmations`: Same as `subcoin_getUtxoProof`.
+    /// - `chunk_size`: Number of outputs per chunk, defaults to 256, at most 1000.
+    #[subscription(
+        name = "subcoin_subscribeUtxoProof" => "subcoin_utxoProof",
+        unsubscribe = "subcoin_unsubscribeUtxoProof",
+        item = UtxoProofItem
+    )]
+    async fn subscribe_utxo_proof(
+        &self,
+        outpoints: Vec<OutPoint>,
+        confirmations: Option<u32>,
+        chunk_size: Option<u32>,
+    ) -> SubscriptionResult;
+}
+
+fn check_limits(
+    num_outpoints: usize,
+    max_outpoints: usize,
+    confirmations: Option<u32>,
+) -> Result<u32, Error> {
+    if num_outpoints > max_outpoints {
+        return Err(Error::Other(format!(
+            "Too many outpoints (max: {max_outpoints}, tried: {num_outpoints})"
+        )));
+    }
+
+    let confirmations = confirmations.unwrap_or(DEFAULT_CONFIRMATIONS);
+
+    if confirmations > MAX_CONFIRMATIONS {
+        return Err(Error::Other(format!(
+            "Too many confirmations (max: {MAX_CONFIRMATIONS}, tried: {confirmations})"
+        )));
+    }
+
+    Ok(confirmations)
+}
+
+/// This struct provides the UTXO proof API.
+pub struct UtxoProofs<Block, Client> {
+    client: Arc<Client>,
+    _phantom: PhantomData<Block>,
+}
+
+impl<Block, Client> UtxoProofs<Block, Client> {
+    /// Constructs a new instance of [`UtxoProofs`].
+    pub fn new(client: Arc<Client>) -> Self {
+        Self {
+            client,
+            _phantom: Default::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Block, Client> UtxoProofApiServer for UtxoProofs<Block, Client>
+where
+    Block: BlockT + 'static,
+    Client: HeaderBackend<Block> + ProofProvider<Block> + Send + Sync + 'static,
+{
+    fn get_utxo_proof(
+        &self,
+        outpoints: Vec<OutPoint>,
+        confirmations: Option<u32>,
+    ) -> Result<UtxoProof, Error> {
+        let confirmations = check_limits(outpoints.len(), MAX_OUTPOINTS, confirmations)?;
+
+        subcoin_utxo_proof::generate_utxo_proof(&*self.client, outpoints, confirmations)
+            .map_err(Error::Blockchain)
+    }
+
+    async fn subscribe_utxo_proof(
+        &self,
+        pending: PendingSubscriptionSink,
+        outpoints: Vec<OutPoint>,
+        confirmations: Option<u32>,
+        chunk_size: Option<u32>,
+    ) -> SubscriptionResult {
+        let confirmations =
+            match check_limits(outpoints.len(), MAX_STREAMED_OUTPOINTS, confirmations) {
+                Ok(confirmations) => confirmations,
+                Err(err) => {
+                    pending.reject(err).await;
+                    return Ok(());
+                }
+            };
+
+        let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE) as usize;
+
+        if chunk_size == 0 || chunk_size > MAX_OUTPOINTS {
+            pending
+                .reject(Error::Other(format!(
+                    "Invalid chunk size (max: {MAX_OUTPOINTS}, tried: {chunk_size})"
+                )))
+                .await;
+            return Ok(());
+        }
+
+        let (headers, chunks) = match subcoin_utxo_proof::generate_utxo_proof_chunks(
+            &*self.client,
+            outpoints,
+            confirmations,
+            chunk_size,
+        ) {
+            Ok(proof) => proof,
+            Err(err) => {
+                pending.reject(Error::Blockchain(err)).await;
+                return Ok(());
+            }
+        };
+
+        let sink = pending.accept().await?;
+
+        let items = std::iter::once(Ok(UtxoProofItem::Headers(headers)))
+            .chain(chunks.map(|chunk| chunk.map(UtxoProofItem::Chunk)))
+            .chain(std::iter::once(Ok(UtxoProofItem::Done)));
+
+        // The next chunk is generated only after the previous one is sent.
+        for item in items {
+            let item = item.map_err(Error::Blockchain)?;
+
+            if sink
+                .send(SubscriptionMessage::from_json(&item)?)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
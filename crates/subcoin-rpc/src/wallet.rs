@@ -0,0 +1,556 @@
+use crate::error::Error;
+use crate::fee_estimation::FeeEstimator;
+use crate::raw_transactions::reject_reason;
+use bitcoin::psbt::Psbt;
+use bitcoin::{Address, Amount, FeeRate, ScriptBuf, TxOut, Txid};
+use jsonrpsee::proc_macros::rpc;
+use sc_client_api::{AuxStore, Backend, BlockBackend, BlockchainEvents, StorageProvider};
+use sc_consensus_nakamoto::{BlockVerification, BlockVerifier, VerificationError};
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::Block as BlockT;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Arc;
+use subcoin_network::{NetworkHandle, SendTransactionResult};
+use subcoin_primitives::descriptor::{self, with_checksum, Descriptor, ScriptType};
+use subcoin_primitives::{BitcoinTransactionAdapter, CoinStorageKey};
+use subcoin_wallet::{CoinSelectionAlgorithm, RescanProgress};
+
+/// Confirmation target used for the fee estimation if the fee rate is not specified.
+const DEFAULT_CONF_TARGET: u32 = 6;
+
+/// Minimum relay fee rate in sat/kvB, same as Bitcoin Core.
+const MIN_RELAY_FEE_RATE: u64 = 1000;
+
+/// Derivation range of a ranged descriptor, either the end or `[begin, end]`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DescriptorRange {
+    End(u32),
+    Range(u32, u32),
+}
+
+impl From<DescriptorRange> for (u32, u32) {
+    fn from(range: DescriptorRange) -> Self {
+        match range {
+            DescriptorRange::End(end) => (0, end),
+            DescriptorRange::Range(begin, end) => (begin, end),
+        }
+    }
+}
+
+/// Request of `importdescriptors`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImportDescriptorRequest {
+    /// Output descriptor, the checksum is optional.
+    pub desc: String,
+    /// Derivation range of a ranged descriptor, `[0, 999]` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<DescriptorRange>,
+}
+
+/// Result of importing a descriptor.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImportDescriptorResult {
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Entry of `listunspent`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListUnspentEntry {
+    pub txid: Txid,
+    pub vout: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    pub script_pub_key: ScriptBuf,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+    pub confirmations: u32,
+    pub desc: String,
+    /// Always `false` as the wallet is watch-only.
+    pub spendable: bool,
+}
+
+/// Balances of the outputs owned by the wallet, in BTC.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MineBalances {
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub trusted: Amount,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub untrusted_pending: Amount,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub immature: Amount,
+}
+
+/// Result of `getbalances`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetBalancesResult {
+    pub mine: MineBalances,
+}
+
+/// Options of `walletcreatefundedpsbt`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CreateFundedPsbtOptions {
+    /// Address receiving the change, derived from the wallet if not specified.
+    #[serde(rename = "changeAddress", default)]
+    pub change_address: Option<String>,
+    /// Fee rate in sat/vB.
+    #[serde(default)]
+    pub fee_rate: Option<f64>,
+    /// Confirmation target in blocks for the fee estimation if `fee_rate` is not specified.
+    #[serde(default)]
+    pub conf_target: Option<u32>,
+    /// Fee rate in sat/vB expected to spend the change in the future, 10 sat/vB by default.
+    #[serde(default)]
+    pub long_term_fee_rate: Option<f64>,
+    /// Coin selection algorithm, `auto` (branch-and-bound with the knapsack fallback) by default.
+    #[serde(default)]
+    pub coin_selection: CoinSelectionAlgorithm,
+}
+
+/// Result of `walletcreatefundedpsbt`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateFundedPsbtResult {
+    /// Base64-encoded PSBT.
+    pub psbt: String,
+    /// Fee in BTC.
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub fee: Amount,
+    /// Position of the change output, -1 if there is no change.
+    pub changepos: i64,
+}
+
+/// Result of `rescanblockchain`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RescanBlockchainResult {
+    pub start_height: u32,
+    pub stop_height: u32,
+}
+
+/// Result of `subcoin_analyzeDescriptor`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DescriptorAnalysis {
+    /// Descriptor with checksum.
+    pub descriptor: String,
+    pub is_range: bool,
+    /// Type of the output scripts, e.g. `witness_v0_scripthash`.
+    pub script_type: String,
+    /// Address of the script at index 0, `None` for the bare scripts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Witness script at index 0, `None` if not spent via a witness script.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness_script: Option<ScriptBuf>,
+    /// Spending policy, e.g. `thresh(2,pk(KEY1),pk(KEY2),older(144))`.
+    pub policy: String,
+    /// Distinct keys in the policy.
+    pub keys: Vec<String>,
+    /// Relative timelocks in the policy, in blocks or in units of 512 seconds.
+    pub relative_timelocks: Vec<u32>,
+    /// Absolute timelocks in the policy, block heights or UNIX timestamps.
+    pub absolute_timelocks: Vec<u32>,
+    /// Maximum weight of an input spending the outputs, including the satisfaction.
+    pub max_satisfaction_weight: u64,
+    /// Whether the descriptor has been imported in the wallet.
+    pub imported: bool,
+}
+
+/// Watch-only wallet API.
+#[rpc(client, server)]
+pub trait WalletApi {
+    /// Imports the descriptors and scans the UTXO set for the matching outputs.
+    #[method(name = "subcoin_importDescriptors", blocking)]
+    fn import_descriptors(
+        &self,
+        requests: Vec<ImportDescriptorRequest>,
+    ) -> Result<Vec<ImportDescriptorResult>, Error>;
+
+    /// Returns the script type, spending policy and the satisfaction weight of the descriptor,
+    /// e.g., to review a multisig or miniscript descriptor before importing it.
+    #[method(name = "subcoin_analyzeDescriptor", blocking)]
+    fn analyze_descriptor(&self, descriptor: String) -> Result<DescriptorAnalysis, Error>;
+
+    /// Returns the wallet UTXOs with confirmations between `minconf` and `maxconf`.
+    #[method(name = "subcoin_listUnspent", blocking)]
+    fn list_unspent(
+        &self,
+        minconf: Option<u32>,
+        maxconf: Option<u32>,
+    ) -> Result<Vec<ListUnspentEntry>, Error>;
+
+    /// Returns the wallet balances.
+    #[method(name = "subcoin_getBalances", blocking)]
+    fn get_balances(&self) -> Result<GetBalancesResult, Error>;
+
+    /// Creates a PSBT paying to `outputs` funded by the wallet UTXOs.
+    ///
+    /// # Arguments
+    ///
+    /// - `outputs`: The address and the amount in BTC of each output.
+    #[method(name = "subcoin_walletCreateFundedPsbt", blocking)]
+    fn wallet_create_funded_psbt(
+        &self,
+        outputs: BTreeMap<String, f64>,
+        options: Option<CreateFundedPsbtOptions>,
+    ) -> Result<CreateFundedPsbtResult, Error>;
+
+    /// Finalizes a fully signed PSBT, validates the extracted transaction and broadcasts it.
+    ///
+    /// # Arguments
+    ///
+    /// - `psbt`: The base64-encoded PSBT.
+    #[method(name = "subcoin_finalizeAndBroadcastPsbt")]
+    async fn finalize_and_broadcast_psbt(
+        &self,
+        psbt: String,
+    ) -> Result<SendTransactionResult, Error>;
+
+    /// Starts rescanning the blocks in the background to repopulate the wallet history.
+    ///
+    /// # Arguments
+    ///
+    /// - `start_height`: The block height where the rescan starts, 0 by default.
+    /// - `stop_height`: The last block height to rescan, the wallet best block by default.
+    #[method(name = "subcoin_rescanBlockchain", blocking)]
+    fn rescan_blockchain(
+        &self,
+        start_height: Option<u32>,
+        stop_height: Option<u32>,
+    ) -> Result<RescanBlockchainResult, Error>;
+
+    /// Resumes the interrupted rescan in the background.
+    #[method(name = "subcoin_resumeRescan", blocking)]
+    fn resume_rescan(&self) -> Result<RescanBlockchainResult, Error>;
+
+    /// Requests the running rescan to stop, returns `false` if there is no rescan running.
+    #[method(name = "subcoin_abortRescan")]
+    fn abort_rescan(&self) -> Result<bool, Error>;
+
+    /// Returns the progress of the running rescan, or the checkpoint of the interrupted one.
+    #[method(name = "subcoin_getRescanProgress")]
+    fn get_rescan_progress(&self) -> Result<Option<RescanProgress>, Error>;
+}
+
+/// This struct provides the watch-only wallet API.
+pub struct Wallet<Block, Client, BE, TransactionAdapter> {
+    wallet: subcoin_wallet::Wallet<Block, Client, BE>,
+    verifier: BlockVerifier<Block, Client, BE>,
+    network: bitcoin::Network,
+    network_handle: NetworkHandle,
+    fee_estimator: FeeEstimator,
+    _phantom: PhantomData<TransactionAdapter>,
+}
+
+impl<Block, Client, BE, TransactionAdapter> Wallet<Block, Client, BE, TransactionAdapter> {
+    /// Constructs a new instance of [`Wallet`].
+    pub fn new(
+        wallet: subcoin_wallet::Wallet<Block, Client, BE>,
+        client: Arc<Client>,
+        network: bitcoin::Network,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+        network_handle: NetworkHandle,
+        fee_estimator: FeeEstimator,
+    ) -> Self {
+        Self {
+            wallet,
+            verifier: BlockVerifier::new(
+                client,
+                network,
+                BlockVerification::Full,
+                coin_storage_key,
+                true,
+            ),
+            network,
+            network_handle,
+            fee_estimator,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn parse_address(&self, address: &str) -> Result<Address, Error> {
+        Address::from_str(address)
+            .and_then(|address| address.require_network(self.network))
+            .map_err(|err| Error::Other(format!("Invalid address {address}: {err}")))
+    }
+}
+
+#[async_trait::async_trait]
+impl<Block, Client, BE, TransactionAdapter> WalletApiServer
+    for Wallet<Block, Client, BE, TransactionAdapter>
+where
+    Block: BlockT + 'static,
+    BE: Backend<Block> + 'static,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore
+        + Send
+        + Sync
+        + 'static,
+    TransactionAdapter: BitcoinTransactionAdapter<Block> + Send + Sync + 'static,
+{
+    fn import_descriptors(
+        &self,
+        requests: Vec<ImportDescriptorRequest>,
+    ) -> Result<Vec<ImportDescriptorResult>, Error> {
+        let results = requests
+            .into_iter()
+            .map(|request| {
+                match self
+                    .wallet
+                    .import_descriptor(&request.desc, request.range.map(Into::into))
+                {
+                    Ok(()) => ImportDescriptorResult {
+                        success: true,
+                        error: None,
+                    },
+                    Err(err) => ImportDescriptorResult {
+                        success: false,
+                        error: Some(err.to_string()),
+                    },
+                }
+            })
+            .collect();
+
+        Ok(results)
+    }
+
+    fn analyze_descriptor(&self, descriptor: String) -> Result<DescriptorAnalysis, Error> {
+        let invalid = |err: descriptor::Error| Error::Other(format!("Invalid descriptor: {err}"));
+
+        let parsed = Descriptor::from_str(&descriptor).map_err(invalid)?;
+        let descriptor = with_checksum(&descriptor).map_err(invalid)?;
+        let script_pubkey = parsed.script_pubkey(0).map_err(invalid)?;
+        let policy = parsed.policy();
+
+        Ok(DescriptorAnalysis {
+            is_range: parsed.is_ranged(),
+            script_type: ScriptType::from_script(&script_pubkey).to_string(),
+            address: Address::from_script(&script_pubkey, self.network)
+                .ok()
+                .map(|address| address.to_string()),
+            witness_script: parsed.psbt_fields(0).map_err(invalid)?.witness_script,
+            keys: policy.keys().into_iter().map(String::from).collect(),
+            relative_timelocks: policy.relative_timelocks(),
+            absolute_timelocks: policy.absolute_timelocks(),
+            policy: policy.to_string(),
+            max_satisfaction_weight: parsed.max_satisfaction_weight().to_wu(),
+            imported: self
+                .wallet
+                .descriptors()
+                .iter()
+                .any(|imported| imported.desc == descriptor),
+            descriptor,
+        })
+    }
+
+    fn list_unspent(
+        &self,
+        minconf: Option<u32>,
+        maxconf: Option<u32>,
+    ) -> Result<Vec<ListUnspentEntry>, Error> {
+        let unspent = self
+            .wallet
+            .list_unspent(minconf.unwrap_or(1), maxconf.unwrap_or(9_999_999))
+            .into_iter()
+            .map(|output| ListUnspentEntry {
+                txid: output.utxo.outpoint.txid,
+                vout: output.utxo.outpoint.vout,
+                address: output.address.map(|address| address.to_string()),
+                script_pub_key: output.utxo.script_pubkey,
+                amount: output.utxo.amount,
+                confirmations: output.confirmations,
+                desc: output.desc,
+                spendable: output.spendable,
+            })
+            .collect();
+
+        Ok(unspent)
+    }
+
+    fn get_balances(&self) -> Result<GetBalancesResult, Error> {
+        let balances = self.wallet.balances();
+
+        Ok(GetBalancesResult {
+            mine: MineBalances {
+                trusted: balances.trusted,
+                untrusted_pending: balances.untrusted_pending,
+                immature: balances.immature,
+            },
+        })
+    }
+
+    fn wallet_create_funded_psbt(
+        &self,
+        outputs: BTreeMap<String, f64>,
+        options: Option<CreateFundedPsbtOptions>,
+    ) -> Result<CreateFundedPsbtResult, Error> {
+        let options = options.unwrap_or_default();
+
+        let outputs = outputs
+            .into_iter()
+            .map(|(address, amount)| {
+                Ok(TxOut {
+                    value: Amount::from_btc(amount)
+                        .map_err(|err| Error::Other(format!("Invalid amount {amount}: {err}")))?,
+                    script_pubkey: self.parse_address(&address)?.script_pubkey(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // sat/kvB
+        let fee_rate = match options.fee_rate {
+            Some(sat_per_vb) => (sat_per_vb * 1000.0).ceil() as u64,
+            None => self
+                .fee_estimator
+                .estimate(options.conf_target.unwrap_or(DEFAULT_CONF_TARGET))
+                .ok_or_else(|| {
+                    Error::Other("Fee estimation failed, specify fee_rate explicitly".to_string())
+                })?,
+        };
+        let fee_rate = FeeRate::from_sat_per_kwu(fee_rate.max(MIN_RELAY_FEE_RATE).div_ceil(4));
+
+        let long_term_fee_rate = options.long_term_fee_rate.map(|sat_per_vb| {
+            FeeRate::from_sat_per_kwu(((sat_per_vb * 1000.0).ceil() as u64).div_ceil(4))
+        });
+
+        let change_script = options
+            .change_address
+            .map(|address| self.parse_address(&address).map(|a| a.script_pubkey()))
+            .transpose()?;
+
+        let funded = self
+            .wallet
+            .create_funded_psbt(
+                outputs,
+                fee_rate,
+                long_term_fee_rate,
+                options.coin_selection,
+                change_script,
+            )
+            .map_err(|err| Error::Other(err.to_string()))?;
+
+        Ok(CreateFundedPsbtResult {
+            psbt: funded.psbt.to_string(),
+            fee: funded.fee,
+            changepos: funded
+                .change_position
+                .map(|position| position as i64)
+                .unwrap_or(-1),
+        })
+    }
+
+    async fn finalize_and_broadcast_psbt(
+        &self,
+        psbt: String,
+    ) -> Result<SendTransactionResult, Error> {
+        let psbt =
+            Psbt::from_str(&psbt).map_err(|err| Error::Other(format!("Invalid PSBT: {err}")))?;
+
+        let tx =
+            subcoin_wallet::finalize_psbt(psbt).map_err(|err| Error::Other(err.to_string()))?;
+
+        match self.verifier.verify_transaction(&tx) {
+            Ok(_fee) => {}
+            Err(VerificationError::Client(err)) => return Err(err.into()),
+            Err(err) => {
+                return Ok(SendTransactionResult::Failure(reject_reason(err)));
+            }
+        }
+
+        Ok(self.network_handle.send_transaction(tx).await)
+    }
+
+    fn rescan_blockchain(
+        &self,
+        start_height: Option<u32>,
+        stop_height: Option<u32>,
+    ) -> Result<RescanBlockchainResult, Error> {
+        if let Some(progress) = self.wallet.rescan_progress() {
+            return Err(Error::Other(format!(
+                "Rescan in progress at #{}",
+                progress.current_height
+            )));
+        }
+
+        let start_height = start_height.unwrap_or(0);
+        let best_number = self
+            .wallet
+            .best_block()
+            .map(|(number, _)| number)
+            .ok_or_else(|| Error::Other("No descriptors imported".to_string()))?;
+        let stop_height = stop_height.unwrap_or(best_number).min(best_number);
+
+        if start_height > stop_height {
+            return Err(Error::Other(format!(
+                "Invalid rescan range [{start_height}, {stop_height}]"
+            )));
+        }
+
+        let wallet = self.wallet.clone();
+        spawn_rescan(move || wallet.rescan::<TransactionAdapter>(start_height, Some(stop_height)))?;
+
+        Ok(RescanBlockchainResult {
+            start_height,
+            stop_height,
+        })
+    }
+
+    fn resume_rescan(&self) -> Result<RescanBlockchainResult, Error> {
+        if self.wallet.rescan_progress().is_some() {
+            return Err(Error::Other("Rescan in progress".to_string()));
+        }
+
+        let progress = self
+            .wallet
+            .interrupted_rescan()
+            .ok_or_else(|| Error::Other("No interrupted rescan to resume".to_string()))?;
+
+        let wallet = self.wallet.clone();
+        spawn_rescan(move || {
+            wallet
+                .resume_rescan::<TransactionAdapter>()
+                .map(|outcome| outcome.expect("Interrupted rescan must exist; qed"))
+        })?;
+
+        Ok(RescanBlockchainResult {
+            start_height: progress.current_height,
+            stop_height: progress.stop_height,
+        })
+    }
+
+    fn abort_rescan(&self) -> Result<bool, Error> {
+        Ok(self.wallet.abort_rescan())
+    }
+
+    fn get_rescan_progress(&self) -> Result<Option<RescanProgress>, Error> {
+        Ok(self
+            .wallet
+            .rescan_progress()
+            .or_else(|| self.wallet.interrupted_rescan()))
+    }
+}
+
+/// Runs the rescan in a dedicated thread as it may take hours on mainnet.
+fn spawn_rescan(
+    rescan: impl FnOnce() -> Result<subcoin_wallet::RescanOutcome, subcoin_wallet::Error>
+        + Send
+        + 'static,
+) -> Result<(), Error> {
+    std::thread::Builder::new()
+        .name("wallet-rescan".to_string())
+        .spawn(move || {
+            if let Err(err) = rescan() {
+                tracing::error!(?err, "Wallet rescan failed");
+            }
+        })
+        .map(|_| ())
+        .map_err(|err| Error::Other(format!("Failed to spawn the rescan thread: {err}")))
+}
@@ -23,10 +23,20 @@ const INITIAL_SUBSIDY: u64 = 50 * COIN;
 
 const HALVING_INTERVAL: u32 = 210_000;
 
-const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Maximum size of a spendable script, the outputs with a larger script can never be spent.
+///
+/// This is the upper bound of the maximum size of the scripts stored in the UTXO set as is,
+/// configured in the runtime, see [`stored_script_pubkey`].
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// `OP_RETURN`.
+const OP_RETURN: u8 = 0x6a;
+
+/// `OP_PUSHBYTES_32`.
+const OP_PUSHBYTES_32: u8 = 0x20;
 
 /// Unspent transaction output.
-#[derive(Debug, TypeInfo, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Eq, TypeInfo, Encode, Decode)]
 pub struct Coin {
     /// Whether the coin is from a coinbase transaction.
     pub is_coinbase: bool,
@@ -41,17 +51,78 @@ pub struct Coin {
 
 impl MaxEncodedLen for Coin {
     fn max_encoded_len() -> usize {
-        bool::max_encoded_len() + u64::max_encoded_len() + MAX_SCRIPT_SIZE
+        bool::max_encoded_len()
+            + u64::max_encoded_len()
+            + u32::max_encoded_len()
+            + codec::Compact::<u32>::max_encoded_len()
+            + MAX_SCRIPT_SIZE
     }
 }
 
+/// Returns the script of an output as stored in the UTXO set.
+///
+/// The consensus allows creating the outputs with a script exceeding [`MAX_SCRIPT_SIZE`] but
+/// not spending them, a script larger than `max_script_size` is stored as
+/// `OP_RETURN <sha256(script)>` so that the output remains unspendable and the original script
+/// can still be identified.
+///
+/// `sha2_256` is passed in as the hashing differs in and outside the runtime.
+pub fn stored_script_pubkey(
+    script_pubkey: Vec<u8>,
+    max_script_size: usize,
+    sha2_256: impl FnOnce(&[u8]) -> [u8; 32],
+) -> Vec<u8> {
+    if script_pubkey.len() <= max_script_size {
+        return script_pubkey;
+    }
+
+    let mut stored = Vec::with_capacity(34);
+    stored.extend([OP_RETURN, OP_PUSHBYTES_32]);
+    stored.extend(sha2_256(&script_pubkey));
+    stored
+}
+
+/// Proof of a coin being locked by a script pubkey in the UTXO set.
+///
+/// The coin is stored under `storage_key`, its encoding in the current layout hashes to
+/// `commitment`. This can be verified outside the runtime against the state root of
+/// `block_number` with a storage read proof of `storage_key`, the raw storage value of a coin
+/// not migrated yet by an ongoing coins migration has to be translated first.
+#[derive(Debug, Clone, PartialEq, Eq, TypeInfo, Encode, Decode)]
+pub struct UtxoAttestation {
+    /// Txid of the coin in the consensus byte order.
+    pub txid: [u8; 32],
+    pub vout: u32,
+    /// Amount of the coin in satoshis.
+    pub amount: u64,
+    /// Block height at which the coin was created.
+    pub height: u32,
+    /// Block number of the state in which the coin is attested.
+    pub block_number: u32,
+    /// Storage key of the coin.
+    pub storage_key: Vec<u8>,
+    /// `blake2_256` of the encoded coin in the current layout.
+    pub commitment: [u8; 32],
+}
+
+/// Reason of a coin not being attested.
+#[derive(Debug, Clone, PartialEq, Eq, TypeInfo, Encode, Decode)]
+pub enum AttestationError {
+    /// The coin is not in the UTXO set, either spent or never created.
+    CoinNotFound,
+    /// The coin is locked by another script pubkey.
+    ScriptPubkeyMismatch,
+    /// The coin holds less than the required amount.
+    InsufficientAmount { amount: u64 },
+}
+
 /// Returns the amount of subsidy in satoshis at given height.
 pub fn bitcoin_block_subsidy(height: u32) -> u64 {
     block_subsidy(height, HALVING_INTERVAL)
 }
 
 /// Returns the block subsidy at given height and halving interval.
-fn block_subsidy(height: u32, subsidy_halving_interval: u32) -> u64 {
+pub fn block_subsidy(height: u32, subsidy_halving_interval: u32) -> u64 {
     let halvings = height / subsidy_halving_interval;
     // Force block reward to zero when right shift is undefined.
     if halvings >= 64 {
@@ -77,4 +148,18 @@ sp_api::decl_runtime_apis! {
         /// Finalize block without checking the extrinsics_root and state_root.
         fn finalize_block_without_checks(header: Block::Header);
     }
+
+    /// UTXO ownership API.
+    pub trait UtxoOwnership {
+        /// Attests that the coin `(txid, vout)` is locked by `script_pubkey` and holds at least
+        /// `min_amount` satoshis in the state of the block the API is called at.
+        ///
+        /// `txid` is in the consensus byte order.
+        fn attest_utxo(
+            txid: [u8; 32],
+            vout: u32,
+            script_pubkey: Vec<u8>,
+            min_amount: u64,
+        ) -> Result<UtxoAttestation, AttestationError>;
+    }
 }
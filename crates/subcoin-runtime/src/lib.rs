@@ -32,6 +32,7 @@ use pallet_executive::Executive;
 use sp_api::impl_runtime_apis;
 use sp_core::{ConstU32, OpaqueMetadata};
 use sp_inherents::{CheckInherentsResult, InherentData};
+use sp_runtime::traits::Extrinsic;
 use sp_runtime::transaction_validity::{TransactionSource, TransactionValidity};
 use sp_runtime::{ApplyExtrinsicResult, ExtrinsicInclusionMode};
 use sp_std::vec;
@@ -39,13 +40,14 @@ use sp_std::vec::Vec;
 #[cfg(feature = "std")]
 use sp_version::NativeVersion;
 use sp_version::{create_runtime_str, runtime_version, RuntimeVersion};
+use subcoin_runtime_primitives::{AttestationError, UtxoAttestation, MAX_SCRIPT_SIZE};
 
 #[runtime_version]
 pub const VERSION: RuntimeVersion = RuntimeVersion {
     spec_name: create_runtime_str!("subcoin"),
     impl_name: create_runtime_str!("subcoin"),
     authoring_version: 0,
-    spec_version: 0,
+    spec_version: 3,
     impl_version: 0,
     apis: RUNTIME_API_VERSIONS,
     transaction_version: 0,
@@ -88,6 +90,9 @@ impl frame_system::Config for Runtime {
 impl pallet_bitcoin::Config for Runtime {
     type RuntimeEvent = RuntimeEvent;
     type WeightInfo = ();
+    type CoinsMigration = pallet_bitcoin::migrations::v2::HashOversizedScripts<Runtime>;
+    type MaxCoinsMigratedPerBlock = ConstU32<10_000>;
+    type MaxScriptSize = ConstU32<{ MAX_SCRIPT_SIZE as u32 }>;
 }
 
 type Signature = crate::types_common::Signature;
@@ -98,8 +103,17 @@ pub type Header = HeaderFor<Runtime>;
 pub type UncheckedExtrinsic =
     sp_runtime::generic::UncheckedExtrinsic<Address, RuntimeCall, Signature, SignedExtra>;
 
-type RuntimeExecutive =
-    Executive<Runtime, Block, frame_system::ChainContext<Runtime>, Runtime, AllPalletsWithSystem>;
+/// Migrations to apply on runtime upgrade.
+type Migrations = (pallet_bitcoin::migrations::v1::MigrateToV1<Runtime>,);
+
+type RuntimeExecutive = Executive<
+    Runtime,
+    Block,
+    frame_system::ChainContext<Runtime>,
+    Runtime,
+    AllPalletsWithSystem,
+    Migrations,
+>;
 
 impl_runtime_apis! {
     impl sp_api::Core<Block> for Runtime {
@@ -141,14 +155,34 @@ impl_runtime_apis! {
         }
 
         fn inherent_extrinsics(data: InherentData) -> Vec<ExtrinsicFor<Runtime>> {
-            data.create_extrinsics()
+            let mut extrinsics = data.create_extrinsics();
+
+            extrinsics.extend(Bitcoin::create_inherents(&data).into_iter().map(|call| {
+                UncheckedExtrinsic::new(call.into(), None)
+                    .expect("Unsigned extrinsic must be constructed; qed")
+            }));
+
+            extrinsics
         }
 
         fn check_inherents(
             block: Block,
             data: InherentData,
         ) -> CheckInherentsResult {
-            data.check_extrinsics(&block)
+            let mut result = data.check_extrinsics(&block);
+
+            let calls = block.extrinsics.iter().filter_map(|extrinsic| match &extrinsic.function {
+                RuntimeCall::Bitcoin(call) => Some(call),
+                _ => None,
+            });
+
+            if let Err(err) = Bitcoin::check_inherents(calls, &data) {
+                result
+                    .put_error(pallet_bitcoin::inherent::INHERENT_IDENTIFIER, &err)
+                    .expect("Bitcoin inherent error is only put once; qed");
+            }
+
+            result
         }
     }
 
@@ -207,6 +241,22 @@ impl_runtime_apis! {
             RuntimeExecutive::finalize_block_without_checks(header);
         }
     }
+
+    impl subcoin_runtime_primitives::UtxoOwnership<Block> for Runtime {
+        fn attest_utxo(
+            txid: [u8; 32],
+            vout: u32,
+            script_pubkey: Vec<u8>,
+            min_amount: u64,
+        ) -> Result<UtxoAttestation, AttestationError> {
+            <Bitcoin as pallet_bitcoin::UtxoAttestor>::attest_utxo(
+                txid,
+                vout,
+                &script_pubkey,
+                min_amount,
+            )
+        }
+    }
 }
 
 /// A set of opinionated types aliases commonly used in runtimes.
@@ -0,0 +1,315 @@
+//! [`BlockSource`] of a trusted bitcoind queried through its JSON-RPC interface.
+//!
+//! The tip is polled with `getbestblockhash`, or long-polled with `waitfornewblock` when
+//! supported, and the blocks are fetched with `getblockhash` and `getblock`. The reorgs of
+//! bitcoind are detected by comparing the block hashes at the same heights, see
+//! [`follow_block_source`](crate::follow_block_source).
+
+use crate::block_source::{BlockSource, BlockSourceError, POLL_INTERVAL};
+use bitcoin::base64::engine::general_purpose::STANDARD as BASE64;
+use bitcoin::base64::Engine;
+use bitcoin::hex::FromHex;
+use bitcoin::{Block as BitcoinBlock, BlockHash};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Timeout of a request to bitcoind.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time to wait for a new block in a `waitfornewblock` call.
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `RPC_INVALID_PARAMETER`, returned by `getblockhash` for a height above the tip.
+const RPC_INVALID_PARAMETER: i64 = -8;
+
+/// `RPC_INVALID_ADDRESS_OR_KEY`, returned by `getblock` for an unknown block.
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+/// `RPC_METHOD_NOT_FOUND`, returned by the versions without `waitfornewblock`.
+const RPC_METHOD_NOT_FOUND: i64 = -32601;
+
+/// bitcoind RPC error.
+#[derive(Debug, thiserror::Error)]
+pub enum BitcoindRpcError {
+    #[error("Invalid bitcoind RPC address {0}, expected host:port")]
+    InvalidAddress(String),
+    #[error("Request to bitcoind timed out")]
+    Timeout,
+    #[error("Unauthorized, check the RPC credentials of bitcoind")]
+    Unauthorized,
+    #[error("bitcoind responded with {0}")]
+    BadStatus(StatusCode),
+    #[error("RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Invalid response of bitcoind: {0}")]
+    InvalidResponse(String),
+    #[error("Failed to read the cookie file {0}: {1}")]
+    Cookie(PathBuf, std::io::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Http(#[from] hyper::Error),
+}
+
+/// Credentials of the RPC server of bitcoind.
+#[derive(Debug, Clone)]
+pub enum BitcoindRpcAuth {
+    /// `-rpcuser` and `-rpcpassword`.
+    UserPass(String, String),
+    /// Cookie file written by bitcoind on startup, re-read on each request as it changes on
+    /// every restart of bitcoind.
+    CookieFile(PathBuf),
+}
+
+impl BitcoindRpcAuth {
+    fn authorization(&self) -> Result<String, BitcoindRpcError> {
+        let credentials = match self {
+            Self::UserPass(user, password) => format!("{user}:{password}"),
+            Self::CookieFile(path) => std::fs::read_to_string(path)
+                .map_err(|err| BitcoindRpcError::Cookie(path.clone(), err))?
+                .trim()
+                .to_string(),
+        };
+        Ok(format!("Basic {}", BASE64.encode(credentials)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcErrorJson {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcErrorJson>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockHeaderJson {
+    height: u32,
+}
+
+/// Trusted bitcoind queried through its JSON-RPC interface.
+#[derive(Debug)]
+pub struct BitcoindRpc {
+    /// `host:port` of the RPC server.
+    addr: String,
+    auth: BitcoindRpcAuth,
+    next_request_id: AtomicU64,
+}
+
+impl fmt::Display for BitcoindRpc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bitcoind RPC at {}", self.addr)
+    }
+}
+
+impl BitcoindRpc {
+    /// Constructs a new instance of [`BitcoindRpc`] connecting to `addr`, e.g.,
+    /// `127.0.0.1:8332` or `http://127.0.0.1:8332`.
+    pub fn new(addr: &str, auth: BitcoindRpcAuth) -> Result<Self, BitcoindRpcError> {
+        let host_port = addr
+            .strip_prefix("http://")
+            .unwrap_or(addr)
+            .trim_end_matches('/');
+
+        if !host_port
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+        {
+            return Err(BitcoindRpcError::InvalidAddress(addr.to_string()));
+        }
+
+        Ok(Self {
+            addr: host_port.to_string(),
+            auth,
+            next_request_id: AtomicU64::new(0),
+        })
+    }
+
+    /// Calls the RPC `method`, returning its result.
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<T, BitcoindRpcError> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": self.next_request_id.fetch_add(1, Ordering::Relaxed),
+            "method": method,
+            "params": params,
+        });
+
+        let request = async {
+            let stream = tokio::net::TcpStream::connect(&self.addr).await?;
+            let (mut sender, connection) =
+                hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+
+            tokio::spawn(async move {
+                if let Err(err) = connection.await {
+                    tracing::debug!("Connection to bitcoind failed: {err:?}");
+                }
+            });
+
+            let request = Request::post("/")
+                .header(hyper::header::HOST, &self.addr)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .header(hyper::header::AUTHORIZATION, self.auth.authorization()?)
+                .body(Full::new(Bytes::from(body.to_string())))
+                .map_err(|err| BitcoindRpcError::InvalidResponse(err.to_string()))?;
+
+            let response = sender.send_request(request).await?;
+            let status = response.status();
+
+            if status == StatusCode::UNAUTHORIZED {
+                return Err(BitcoindRpcError::Unauthorized);
+            }
+
+            let body = response.into_body().collect().await?.to_bytes();
+
+            // The RPC errors are reported with a non-200 status along with the error object.
+            let Ok(response) = serde_json::from_slice::<RpcResponse>(&body) else {
+                return Err(BitcoindRpcError::BadStatus(status));
+            };
+
+            if let Some(RpcErrorJson { code, message }) = response.error {
+                return Err(BitcoindRpcError::Rpc { code, message });
+            }
+
+            serde_json::from_value(response.result.unwrap_or(Value::Null))
+                .map_err(|err| BitcoindRpcError::InvalidResponse(err.to_string()))
+        };
+
+        tokio::time::timeout(timeout, request)
+            .await
+            .map_err(|_| BitcoindRpcError::Timeout)?
+    }
+
+    /// Returns the hash of the tip of the active chain.
+    pub async fn best_block_hash(&self) -> Result<BlockHash, BitcoindRpcError> {
+        self.call("getbestblockhash", json!([]), REQUEST_TIMEOUT)
+            .await
+    }
+
+    /// Returns the hash of the block at `height` in the active chain, `None` if the height
+    /// is above the tip.
+    pub async fn block_hash_at(&self, height: u32) -> Result<Option<BlockHash>, BitcoindRpcError> {
+        match self
+            .call("getblockhash", json!([height]), REQUEST_TIMEOUT)
+            .await
+        {
+            Ok(block_hash) => Ok(Some(block_hash)),
+            Err(BitcoindRpcError::Rpc {
+                code: RPC_INVALID_PARAMETER,
+                ..
+            }) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Fetches the block, `None` if unknown to bitcoind.
+    pub async fn raw_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<BitcoinBlock>, BitcoindRpcError> {
+        let raw_block: String = match self
+            .call("getblock", json!([block_hash, 0]), REQUEST_TIMEOUT)
+            .await
+        {
+            Ok(raw_block) => raw_block,
+            Err(BitcoindRpcError::Rpc {
+                code: RPC_INVALID_ADDRESS_OR_KEY,
+                ..
+            }) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        Vec::<u8>::from_hex(&raw_block)
+            .map_err(|err| BitcoindRpcError::InvalidResponse(err.to_string()))
+            .and_then(|bytes| {
+                bitcoin::consensus::deserialize(&bytes)
+                    .map_err(|err| BitcoindRpcError::InvalidResponse(err.to_string()))
+            })
+            .map(Some)
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockSource for BitcoindRpc {
+    async fn best_block(&self) -> Result<(u32, BlockHash), BlockSourceError> {
+        let best_hash = self.best_block_hash().await?;
+        let header: BlockHeaderJson = self
+            .call("getblockheader", json!([best_hash, true]), REQUEST_TIMEOUT)
+            .await?;
+        Ok((header.height, best_hash))
+    }
+
+    async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>, BlockSourceError> {
+        Ok(self.block_hash_at(height).await?)
+    }
+
+    async fn block(&self, block_hash: BlockHash) -> Result<Option<BitcoinBlock>, BlockSourceError> {
+        Ok(self.raw_block(block_hash).await?)
+    }
+
+    async fn wait_for_new_block(&self) {
+        let timeout_ms = LONG_POLL_TIMEOUT.as_millis() as u64;
+
+        if let Err(err) = self
+            .call::<Value>(
+                "waitfornewblock",
+                json!([timeout_ms]),
+                LONG_POLL_TIMEOUT + REQUEST_TIMEOUT,
+            )
+            .await
+        {
+            // Polled instead if `waitfornewblock` is unavailable.
+            if !matches!(
+                err,
+                BitcoindRpcError::Rpc {
+                    code: RPC_METHOD_NOT_FOUND,
+                    ..
+                }
+            ) {
+                tracing::debug!("Failed to wait for the new block of {self}: {err}");
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitcoind_rpc_address() {
+        let auth = BitcoindRpcAuth::UserPass("user".to_string(), "pass".to_string());
+
+        for addr in ["127.0.0.1:8332", "http://127.0.0.1:8332/"] {
+            let bitcoind = BitcoindRpc::new(addr, auth.clone()).unwrap();
+            assert_eq!(bitcoind.addr, "127.0.0.1:8332");
+        }
+
+        for addr in ["127.0.0.1", ":8332", "127.0.0.1:port"] {
+            assert!(matches!(
+                BitcoindRpc::new(addr, auth.clone()),
+                Err(BitcoindRpcError::InvalidAddress(_))
+            ));
+        }
+
+        assert_eq!(auth.authorization().unwrap(), "Basic dXNlcjpwYXNz");
+    }
+}
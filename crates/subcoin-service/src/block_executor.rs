@@ -1,12 +1,11 @@
 use crate::{
-    initialize_genesis_block_hash_mapping, BitcoinExecutorDispatch, CoinStorageKey, FullBackend,
-    FullClient, GenesisBlockBuilder, InMemoryBackend, InMemoryClient, TransactionAdapter,
+    initialize_genesis_block_hash_mapping, CoinStorageKey, FullBackend, FullClient,
+    GenesisBlockBuilder, InMemoryBackend, InMemoryClient, RuntimeExecutor, TransactionAdapter,
 };
 use sc_client_api::{Backend, HeaderBackend, StateBackend, StorageProvider};
 use sc_consensus_nakamoto::{
     BlockExecutionStrategy, BlockExecutor, ClientContext, ExecutionBackend,
 };
-use sc_executor::NativeElseWasmExecutor;
 use sc_service::{Configuration, Error as ServiceError, SpawnTaskHandle};
 use sp_runtime::traits::{Block as BlockT, HashingFor, Header as HeaderT};
 use sp_runtime::StateVersion;
@@ -104,18 +103,20 @@ fn new_in_memory_backend(
 pub(super) fn new_in_memory_client(
     client: Arc<FullClient>,
     backend: Arc<FullBackend>,
-    executor: NativeElseWasmExecutor<BitcoinExecutorDispatch>,
-    bitcoin_network: bitcoin::Network,
+    executor: RuntimeExecutor,
+    bitcoin_genesis_block: bitcoin::Block,
     spawn_handle: SpawnTaskHandle,
     config: &Configuration,
-) -> Result<Arc<InMemoryClient>, ServiceError> {
+) -> Result<(Arc<InMemoryClient>, Arc<InMemoryBackend>), ServiceError> {
     let (in_memory_backend, is_refresh) = new_in_memory_backend(&client, &backend)?;
     let in_memory_backend = Arc::new(in_memory_backend);
 
     let no_genesis = !is_refresh;
 
+    let bitcoin_genesis_hash = bitcoin_genesis_block.block_hash();
+
     let genesis_block_builder = GenesisBlockBuilder::<_, _, _, TransactionAdapter>::new(
-        bitcoin_network,
+        bitcoin_genesis_block,
         config.chain_spec.as_storage_builder(),
         !no_genesis,
         in_memory_backend.clone(),
@@ -132,7 +133,7 @@ pub(super) fn new_in_memory_client(
     };
 
     let in_memory_client = sc_service::client::new_with_backend(
-        in_memory_backend,
+        in_memory_backend.clone(),
         executor,
         genesis_block_builder,
         Box::new(spawn_handle),
@@ -141,15 +142,43 @@ pub(super) fn new_in_memory_client(
         client_config,
     )?;
 
-    initialize_genesis_block_hash_mapping(&in_memory_client, bitcoin_network);
+    initialize_genesis_block_hash_mapping(&in_memory_client, bitcoin_genesis_hash);
 
-    Ok(Arc::new(in_memory_client))
+    Ok((Arc::new(in_memory_client), in_memory_backend))
 }
 
 fn new_box<T: BlockExecutor<Block> + 'static>(processor: T) -> Box<dyn BlockExecutor<Block>> {
     Box::new(processor) as Box<dyn BlockExecutor<Block>>
 }
 
+/// Returns the executor using the disk backend and the other execution strategy than
+/// `block_execution_strategy`, for cross-checking the state roots.
+pub fn new_reference_block_executor(
+    client: Arc<FullClient>,
+    block_execution_strategy: BlockExecutionStrategy,
+) -> Box<dyn BlockExecutor<Block>> {
+    use sc_consensus_nakamoto::{OffRuntimeBlockExecutor, RuntimeBlockExecutor};
+
+    match block_execution_strategy {
+        BlockExecutionStrategy::OffRuntimeExecution(_) => new_box(RuntimeBlockExecutor::new(
+            client,
+            ClientContext::<FullClient>::Disk,
+        )),
+        BlockExecutionStrategy::RuntimeExecution(_)
+        | BlockExecutionStrategy::Adaptive { .. }
+        | BlockExecutionStrategy::BenchmarkRuntimeExecution
+        | BlockExecutionStrategy::BenchmarkAll => {
+            new_box(
+                OffRuntimeBlockExecutor::<_, _, _, TransactionAdapter, _>::new(
+                    client,
+                    ClientContext::<FullClient>::Disk,
+                    Arc::new(CoinStorageKey),
+                ),
+            )
+        }
+    }
+}
+
 pub(super) fn new_block_executor(
     client: Arc<FullClient>,
     block_execution_strategy: BlockExecutionStrategy,
@@ -237,13 +266,16 @@ pub(super) fn new_block_executor(
                 in_memory_off_runtime_block_executor,
             ))
         }
+        BlockExecutionStrategy::Adaptive { .. } => {
+            unreachable!("Adaptive executor is composed of the other executors in `new_node`")
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{new_node, NodeComponents, SubcoinConfiguration};
+    use crate::{new_node, ExecutorKind, NodeComponents, SubcoinConfiguration};
     use bitcoin::consensus::Encodable;
     use sc_consensus_nakamoto::{
         BitcoinBlockImport, BitcoinBlockImporter, BlockVerification, ImportConfig, ImportStatus,
@@ -255,7 +287,11 @@ mod tests {
     use subcoin_test_service::block_data;
     use tokio::runtime::Handle;
 
-    async fn run_with_runtime_disk_executor(config: &Configuration, up_to: u32) -> Header {
+    async fn import_with_runtime_disk_executor(
+        config: &Configuration,
+        executor: ExecutorKind,
+        up_to: u32,
+    ) -> Arc<FullClient> {
         let NodeComponents {
             block_executor,
             client,
@@ -266,6 +302,8 @@ mod tests {
             config,
             no_hardware_benchmarks: true,
             storage_monitor: Default::default(),
+            in_memory_backend: Default::default(),
+            executor,
         })
         .expect("Failed to create node");
 
@@ -274,9 +312,11 @@ mod tests {
             client.clone(),
             ImportConfig {
                 network: bitcoin::Network::Bitcoin,
+                chain_params: None,
                 block_verification: BlockVerification::None,
                 execute_block: true,
                 verify_script: true,
+                verification_threads: None,
             },
             Arc::new(CoinStorageKey),
             block_executor,
@@ -290,9 +330,63 @@ mod tests {
             assert!(matches!(import_status, ImportStatus::Imported { .. }));
         }
 
+        client
+    }
+
+    async fn run_with_runtime_disk_executor(config: &Configuration, up_to: u32) -> Header {
+        let client =
+            import_with_runtime_disk_executor(config, ExecutorKind::NativeElseWasm, up_to).await;
         client.header(client.info().best_hash).unwrap().unwrap()
     }
 
+    #[tokio::test]
+    async fn replayed_block_should_match_imported_block() {
+        use sc_consensus_nakamoto::{BlockReplayer, OffRuntimeBlockExecutor, RuntimeBlockExecutor};
+
+        let runtime_handle = Handle::current();
+        let config = subcoin_test_service::test_configuration(runtime_handle);
+
+        let client =
+            import_with_runtime_disk_executor(&config, ExecutorKind::NativeElseWasm, 3).await;
+
+        let runtime_executor = RuntimeBlockExecutor::new(client.clone(), ClientContext::Disk);
+        let off_runtime_executor = OffRuntimeBlockExecutor::<_, _, _, TransactionAdapter, _>::new(
+            client.clone(),
+            ClientContext::Disk,
+            Arc::new(CoinStorageKey),
+        );
+
+        let replayer = BlockReplayer::<_, _, _, TransactionAdapter>::new(
+            client,
+            bitcoin::Network::Bitcoin,
+            Arc::new(CoinStorageKey),
+        );
+
+        let block_hash = block_data()[2].block_hash();
+        let replay = replayer
+            .replay(
+                block_hash,
+                &[&runtime_executor, &off_runtime_executor],
+                true,
+            )
+            .unwrap();
+
+        assert_eq!(replay.block_number, 2);
+        assert_eq!(replay.executions.len(), 2);
+        assert!(replay.state_root_mismatches().next().is_none());
+        assert!(replay.diverging_keys().is_empty());
+
+        // The coinbase of block 2 spends no coin and creates one.
+        assert_eq!(replay.transactions.len(), 1);
+        let coinbase = &replay.transactions[0];
+        assert!(coinbase.scripts.is_empty());
+        assert!(coinbase.reads.is_empty());
+        assert_eq!(
+            coinbase.writes.iter().filter(|(_, v)| v.is_some()).count(),
+            1
+        );
+    }
+
     #[tokio::test]
     #[ignore]
     async fn inspect_substrate_header_size() {
@@ -304,6 +398,8 @@ mod tests {
             config: &config,
             no_hardware_benchmarks: true,
             storage_monitor: Default::default(),
+            in_memory_backend: Default::default(),
+            executor: Default::default(),
         })
         .expect("Failed to create node");
 
@@ -375,6 +471,8 @@ mod tests {
             config: &config,
             no_hardware_benchmarks: true,
             storage_monitor: Default::default(),
+            in_memory_backend: Default::default(),
+            executor: Default::default(),
         })
         .expect("Failed to create node");
 
@@ -383,9 +481,11 @@ mod tests {
             client.clone(),
             ImportConfig {
                 network,
+                chain_params: None,
                 block_verification: BlockVerification::None,
                 execute_block: true,
                 verify_script: true,
+                verification_threads: None,
             },
             Arc::new(CoinStorageKey),
             block_executor,
@@ -399,7 +499,7 @@ mod tests {
             .await
             .unwrap();
 
-        let in_mem_client = new_in_memory_client(
+        let (in_mem_client, _) = new_in_memory_client(
             client.clone(),
             backend.clone(),
             executor.clone(),
@@ -437,4 +537,73 @@ mod tests {
 
         let _ = tmp.close();
     }
+
+    /// Points `config` to a new database, the returned directory must be kept alive.
+    fn use_temp_database(config: &mut Configuration) -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        let base_path = BasePath::new(tmp.path());
+        config.database = DatabaseSource::ParityDb {
+            path: base_path.path().join("db"),
+        };
+        config.data_path = base_path.path().into();
+        config.base_path = base_path;
+        tmp
+    }
+
+    #[tokio::test]
+    async fn wasm_executor_should_produce_same_result_as_native_executor() {
+        let runtime_handle = Handle::current();
+        let mut config = subcoin_test_service::test_configuration(runtime_handle);
+
+        let expected_header3 = run_with_runtime_disk_executor(&config, 3).await;
+
+        let _tmp = use_temp_database(&mut config);
+
+        let client = import_with_runtime_disk_executor(&config, ExecutorKind::Wasm, 3).await;
+        let best_header = client.header(client.info().best_hash).unwrap().unwrap();
+
+        assert_eq!(best_header, expected_header3);
+    }
+
+    // cargo test -p subcoin-service --release bench_native_and_wasm_executor -- --ignored --nocapture
+    #[tokio::test]
+    #[ignore]
+    async fn bench_native_and_wasm_executor() {
+        use sc_client_api::BlockBackend;
+
+        const ITERATIONS: u128 = 100;
+
+        let runtime_handle = Handle::current();
+        let mut config = subcoin_test_service::test_configuration(runtime_handle);
+
+        for executor in [ExecutorKind::NativeElseWasm, ExecutorKind::Wasm] {
+            let _tmp = use_temp_database(&mut config);
+
+            let client = import_with_runtime_disk_executor(&config, executor, 3).await;
+            let block_executor =
+                new_block_executor(client.clone(), BlockExecutionStrategy::runtime_disk(), None);
+
+            // Each transaction of the block is executed as a `transact` extrinsic.
+            let block = client
+                .block(client.info().best_hash)
+                .unwrap()
+                .unwrap()
+                .block;
+            let parent_hash = *block.header().parent_hash();
+
+            let mut execute_block_time = 0;
+            for _ in 0..ITERATIONS {
+                let result = block_executor
+                    .execute_block(parent_hash, block.clone())
+                    .unwrap();
+                execute_block_time += result.exec_info.execute_block_time;
+            }
+
+            println!(
+                "{executor:?}: {} transactions, execute_block: {}ns on average",
+                block.extrinsics().len(),
+                execute_block_time / ITERATIONS,
+            );
+        }
+    }
 }
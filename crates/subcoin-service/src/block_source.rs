@@ -0,0 +1,253 @@
+//! External sources of the Bitcoin blocks.
+//!
+//! The blocks are downloaded from the Bitcoin P2P network by default. A [`BlockSource`]
+//! replaces the P2P sync, e.g., to follow a trusted bitcoind through its REST interface
+//! with [`ReferenceNode`] or its JSON-RPC interface with [`BitcoindRpc`], the node then
+//! becomes an index of the chain of that bitcoind without connecting to any Bitcoin peer.
+//!
+//! [`BitcoindRpc`]: crate::BitcoindRpc
+
+use crate::bitcoind_rpc::BitcoindRpcError;
+use bitcoin::{Block as BitcoinBlock, BlockHash};
+use sc_client_api::AuxStore;
+use sc_consensus_nakamoto::{BlockImportQueue, DifferentialError, ImportBlocks, ReferenceNode};
+use sp_blockchain::HeaderBackend;
+use sp_consensus::BlockOrigin;
+use sp_runtime::traits::{Block as BlockT, SaturatedConversion};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use subcoin_primitives::BackendExt;
+
+/// Interval of polling the tip of the source once synced.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of blocks sent to the import queue at once.
+const MAX_BLOCKS_PER_BATCH: u32 = 16;
+
+/// Block source error.
+#[derive(Debug, thiserror::Error)]
+pub enum BlockSourceError {
+    #[error("Block #{0} is not available in the block source")]
+    MissingBlock(u32),
+    #[error("Failed to import block {0}")]
+    ImportFailed(BlockHash),
+    #[error(transparent)]
+    Rest(#[from] DifferentialError),
+    #[error(transparent)]
+    Rpc(#[from] BitcoindRpcError),
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Source of the blocks of a Bitcoin chain, trusted to serve the valid blocks.
+#[async_trait::async_trait]
+pub trait BlockSource: fmt::Display + Send + Sync {
+    /// Returns the height and hash of the tip of the chain.
+    async fn best_block(&self) -> Result<(u32, BlockHash), BlockSourceError>;
+
+    /// Returns the hash of the block at `height` in the chain, `None` if above the tip.
+    async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>, BlockSourceError>;
+
+    /// Returns the block, `None` if unknown to the source.
+    async fn block(&self, block_hash: BlockHash) -> Result<Option<BitcoinBlock>, BlockSourceError>;
+
+    /// Waits until the tip of the chain may have changed.
+    async fn wait_for_new_block(&self) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockSource for ReferenceNode {
+    async fn best_block(&self) -> Result<(u32, BlockHash), BlockSourceError> {
+        Ok(ReferenceNode::best_block(self).await?)
+    }
+
+    async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>, BlockSourceError> {
+        Ok(ReferenceNode::block_hash(self, height).await?)
+    }
+
+    async fn block(&self, block_hash: BlockHash) -> Result<Option<BitcoinBlock>, BlockSourceError> {
+        Ok(self.raw_block(block_hash).await?)
+    }
+}
+
+/// Imports the blocks of `source` as its chain grows, following its reorgs.
+pub async fn follow_block_source<Block, Client>(
+    client: Arc<Client>,
+    source: Arc<dyn BlockSource>,
+    mut import_queue: BlockImportQueue,
+) where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    tracing::info!("🔗 Following the blocks of {source}");
+
+    loop {
+        if let Err(err) = import_new_blocks(&client, source.as_ref(), &mut import_queue).await {
+            tracing::warn!("Failed to sync from {source}: {err}");
+        }
+
+        source.wait_for_new_block().await;
+    }
+}
+
+/// Imports the blocks up to the current tip of the source.
+async fn import_new_blocks<Block, Client>(
+    client: &Arc<Client>,
+    source: &dyn BlockSource,
+    import_queue: &mut BlockImportQueue,
+) -> Result<(), BlockSourceError>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore,
+{
+    let (source_height, source_hash) = source.best_block().await?;
+
+    if BackendExt::<Block>::block_hash(client, source_height) == Some(source_hash) {
+        return Ok(());
+    }
+
+    let best_number: u32 = client.info().best_number.saturated_into();
+
+    // The blocks above the last common block have been reorged out of the source chain.
+    let mut fork_height = best_number.min(source_height);
+    while fork_height > 0
+        && source.block_hash(fork_height).await?
+            != BackendExt::<Block>::block_hash(client, fork_height)
+    {
+        fork_height -= 1;
+    }
+
+    if fork_height < best_number {
+        tracing::info!("Source chain forks at #{fork_height}, following the reorg");
+    }
+
+    let mut height = fork_height + 1;
+
+    while height <= source_height {
+        let batch_end = source_height.min(height + MAX_BLOCKS_PER_BATCH - 1);
+
+        let mut blocks = Vec::with_capacity((batch_end - height + 1) as usize);
+        for height in height..=batch_end {
+            let block_hash = source
+                .block_hash(height)
+                .await?
+                .ok_or(BlockSourceError::MissingBlock(height))?;
+            let block = source
+                .block(block_hash)
+                .await?
+                .ok_or(BlockSourceError::MissingBlock(height))?;
+            blocks.push(block);
+        }
+
+        import_queue.import_blocks(ImportBlocks {
+            origin: BlockOrigin::NetworkInitialSync,
+            blocks,
+        });
+
+        let import_result = import_queue.block_import_results().await;
+
+        if let Some((_, block_hash)) = import_result
+            .results
+            .iter()
+            .find(|(result, _)| result.is_err())
+        {
+            return Err(BlockSourceError::ImportFailed(*block_hash));
+        }
+
+        height = batch_end + 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CoinStorageKey, NodeComponents, TransactionAdapter};
+    use sc_consensus_nakamoto::{BitcoinBlockImporter, BlockVerification, ImportConfig};
+    use subcoin_runtime::interface::OpaqueBlock as Block;
+    use subcoin_test_service::block_data;
+
+    struct TestSource(Vec<BitcoinBlock>);
+
+    impl fmt::Display for TestSource {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "test source")
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BlockSource for TestSource {
+        async fn best_block(&self) -> Result<(u32, BlockHash), BlockSourceError> {
+            let tip = self.0.last().expect("Source has the genesis block");
+            Ok((self.0.len() as u32 - 1, tip.block_hash()))
+        }
+
+        async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>, BlockSourceError> {
+            Ok(self.0.get(height as usize).map(|block| block.block_hash()))
+        }
+
+        async fn block(
+            &self,
+            block_hash: BlockHash,
+        ) -> Result<Option<BitcoinBlock>, BlockSourceError> {
+            Ok(self
+                .0
+                .iter()
+                .find(|block| block.block_hash() == block_hash)
+                .cloned())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_from_block_source() {
+        let NodeComponents {
+            client,
+            block_executor,
+            task_manager,
+            ..
+        } = subcoin_test_service::new_test_node(tokio::runtime::Handle::current())
+            .expect("Create test node");
+
+        let bitcoin_block_import = BitcoinBlockImporter::<_, _, _, _, TransactionAdapter>::new(
+            client.clone(),
+            client.clone(),
+            ImportConfig {
+                network: bitcoin::Network::Bitcoin,
+                chain_params: None,
+                block_verification: BlockVerification::None,
+                execute_block: true,
+                verify_script: true,
+                verification_threads: None,
+            },
+            Arc::new(CoinStorageKey),
+            block_executor,
+            None,
+        );
+        let mut import_queue = sc_consensus_nakamoto::bitcoin_import_queue(
+            &task_manager.spawn_essential_handle(),
+            bitcoin_block_import,
+        );
+
+        let blocks = block_data();
+        let source = TestSource(blocks[..3].to_vec());
+        import_new_blocks::<Block, _>(&client, &source, &mut import_queue)
+            .await
+            .unwrap();
+        assert_eq!(client.info().best_number, 2);
+
+        // Resumes from the best block.
+        let source = TestSource(blocks.clone());
+        import_new_blocks::<Block, _>(&client, &source, &mut import_queue)
+            .await
+            .unwrap();
+        assert_eq!(client.info().best_number, 3);
+        assert_eq!(
+            BackendExt::<Block>::block_hash(&client, 3),
+            Some(blocks[3].block_hash())
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::ChainSpec;
+use crate::{ChainSpec, CustomNetwork, BITCOIN_NETWORK_PROPERTY};
 use sc_service::{ChainType, Properties};
 use serde_json::json;
 use subcoin_primitives::raw_genesis_tx;
@@ -34,3 +34,32 @@ pub fn config(network: bitcoin::Network) -> Result<ChainSpec, String> {
     .with_properties(props())
     .build())
 }
+
+/// Returns the chain spec of the custom network.
+///
+/// The definition is embedded as a property so that the node can derive the genesis block and
+/// the network params from the chain spec alone.
+pub fn custom_config(network: &CustomNetwork) -> Result<ChainSpec, String> {
+    let genesis_tx = bitcoin::consensus::serialize(&network.genesis_block.txdata[0]);
+
+    let mut properties = props();
+    properties.insert(
+        BITCOIN_NETWORK_PROPERTY.to_string(),
+        network.definition().clone(),
+    );
+
+    Ok(ChainSpec::builder(
+        WASM_BINARY.expect("Wasm binary not available"),
+        Default::default(),
+    )
+    .with_name(&network.name)
+    .with_id(&network.id)
+    .with_chain_type(ChainType::Live)
+    .with_genesis_config_patch(json!({
+        "bitcoin": {
+            "genesisTx": genesis_tx,
+        }
+    }))
+    .with_properties(properties)
+    .build())
+}
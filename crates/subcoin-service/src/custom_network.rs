@@ -0,0 +1,311 @@
+//! Custom Bitcoin-like networks.
+//!
+//! A fork or a test network of Bitcoin is defined with a JSON file and turned into a chain
+//! spec with `subcoin build-spec --bitcoin-network <custom.json>`:
+//!
+//! ```json
+//! {
+//!   "name": "Example Regtest Fork",
+//!   "id": "example-regtest-fork",
+//!   "base": "regtest",
+//!   "magic": "0b110907",
+//!   "genesisBlock": "<consensus encoded block in hex>",
+//!   "subsidyHalvingInterval": 150,
+//!   "powLimit": "7fffff0000000000000000000000000000000000000000000000000000000000",
+//!   "powTargetSpacing": 600,
+//!   "powTargetTimespan": 1209600,
+//!   "allowMinDifficultyBlocks": true,
+//!   "noPowRetargeting": true,
+//!   "seednodes": ["seed.example.org:18444"]
+//! }
+//! ```
+//!
+//! The optional fields default to the ones of the base network, which also provides the
+//! rules not covered by the definition, e.g., the address encoding and the soft fork
+//! activation heights. The definition is embedded in the chain spec as the `bitcoinNetwork`
+//! property, the node builds the genesis block and derives the network params from it.
+
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::FromHex;
+use bitcoin::p2p::Magic;
+use bitcoin::{Block as BitcoinBlock, BlockHash, Network, Target};
+use sc_consensus_nakamoto::ChainParams;
+use sc_service::Properties;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Key of the custom network definition in the chain spec properties.
+pub const BITCOIN_NETWORK_PROPERTY: &str = "bitcoinNetwork";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Definition {
+    name: String,
+    id: String,
+    base: String,
+    magic: String,
+    genesis_block: String,
+    subsidy_halving_interval: Option<u32>,
+    pow_limit: Option<String>,
+    pow_target_spacing: Option<u64>,
+    pow_target_timespan: Option<u64>,
+    allow_min_difficulty_blocks: Option<bool>,
+    no_pow_retargeting: Option<bool>,
+    #[serde(default)]
+    seednodes: Vec<String>,
+}
+
+/// Validated definition of a custom network.
+#[derive(Debug, Clone)]
+pub struct CustomNetwork {
+    /// Human readable name of the network.
+    pub name: String,
+    /// Chain spec id of the network.
+    pub id: String,
+    /// Built-in network providing the rules not covered by the definition.
+    pub base: Network,
+    /// Message start of the P2P messages.
+    pub magic: Magic,
+    /// Genesis block of the network.
+    pub genesis_block: BitcoinBlock,
+    /// Consensus params of the network.
+    pub chain_params: ChainParams,
+    /// Seednodes of the network, the built-in seednodes of the base network are never used.
+    pub seednodes: Vec<String>,
+    /// Original definition, embedded in the chain spec as is.
+    definition: serde_json::Value,
+}
+
+impl CustomNetwork {
+    /// Parses and validates the definition.
+    pub fn from_json(definition: serde_json::Value) -> Result<Self, String> {
+        let Definition {
+            name,
+            id,
+            base,
+            magic,
+            genesis_block,
+            subsidy_halving_interval,
+            pow_limit,
+            pow_target_spacing,
+            pow_target_timespan,
+            allow_min_difficulty_blocks,
+            no_pow_retargeting,
+            seednodes,
+        } = Definition::deserialize(&definition)
+            .map_err(|err| format!("Invalid network definition: {err}"))?;
+
+        if id.is_empty() {
+            return Err("Network id must not be empty".to_string());
+        }
+
+        let base = base
+            .parse::<Network>()
+            .map_err(|err| format!("Invalid base network {base}: {err}"))?;
+
+        let magic = magic
+            .parse::<Magic>()
+            .map_err(|err| format!("Invalid magic {magic}: {err}"))?;
+
+        let genesis_block = deserialize_hex::<BitcoinBlock>(&genesis_block)
+            .map_err(|err| format!("Invalid genesis block: {err}"))?;
+
+        let mut chain_params = ChainParams::new(base);
+
+        if let Some(pow_limit) = pow_limit {
+            let pow_limit = <[u8; 32]>::from_hex(&pow_limit)
+                .map(Target::from_be_bytes)
+                .map_err(|err| format!("Invalid pow limit {pow_limit}: {err}"))?;
+            chain_params.params.pow_limit = pow_limit;
+            chain_params.params.max_attainable_target = pow_limit;
+        }
+        if let Some(pow_target_spacing) = pow_target_spacing {
+            chain_params.params.pow_target_spacing = pow_target_spacing;
+        }
+        if let Some(pow_target_timespan) = pow_target_timespan {
+            chain_params.params.pow_target_timespan = pow_target_timespan;
+        }
+        if let Some(allow_min_difficulty_blocks) = allow_min_difficulty_blocks {
+            chain_params.params.allow_min_difficulty_blocks = allow_min_difficulty_blocks;
+        }
+        if let Some(no_pow_retargeting) = no_pow_retargeting {
+            chain_params.params.no_pow_retargeting = no_pow_retargeting;
+        }
+        if let Some(subsidy_halving_interval) = subsidy_halving_interval {
+            chain_params.subsidy_halving_interval = subsidy_halving_interval;
+        }
+
+        if chain_params.params.pow_target_spacing == 0
+            || chain_params.params.pow_target_timespan < chain_params.params.pow_target_spacing
+        {
+            return Err("Pow target timespan must not be shorter than the spacing".to_string());
+        }
+        if chain_params.subsidy_halving_interval == 0 {
+            return Err("Subsidy halving interval must not be zero".to_string());
+        }
+
+        // The script flag and BIP30 exceptions are bound to the blocks of the base network.
+        chain_params.script_flag_exceptions.clear();
+        chain_params.bip30_exceptions.clear();
+
+        check_genesis_block(&genesis_block, &chain_params)?;
+
+        Ok(Self {
+            name,
+            id,
+            base,
+            magic,
+            genesis_block,
+            chain_params,
+            seednodes,
+            definition,
+        })
+    }
+
+    /// Reads the definition from a JSON file.
+    pub fn from_json_file(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|err| format!("Failed to open {}: {err}", path.display()))?;
+        let definition = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|err| format!("Failed to parse {}: {err}", path.display()))?;
+        Self::from_json(definition)
+    }
+
+    /// Reads the definition embedded in the chain spec properties, `None` if the chain spec
+    /// is not of a custom network.
+    pub fn from_properties(properties: &Properties) -> Result<Option<Self>, String> {
+        properties
+            .get(BITCOIN_NETWORK_PROPERTY)
+            .map(|definition| Self::from_json(definition.clone()))
+            .transpose()
+    }
+
+    /// Returns the definition to embed in the chain spec.
+    pub fn definition(&self) -> &serde_json::Value {
+        &self.definition
+    }
+
+    /// Returns the hash of the genesis block.
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.genesis_block.block_hash()
+    }
+}
+
+fn check_genesis_block(block: &BitcoinBlock, chain_params: &ChainParams) -> Result<(), String> {
+    if block.header.prev_blockhash != BlockHash::all_zeros() {
+        return Err("Genesis block must not have a parent".to_string());
+    }
+
+    // Only the coinbase is stored in the genesis config of pallet-bitcoin.
+    if block.txdata.len() != 1 || !block.txdata[0].is_coinbase() {
+        return Err("Genesis block must contain the coinbase only".to_string());
+    }
+
+    if !block.check_merkle_root() {
+        return Err("Invalid merkle root of the genesis block".to_string());
+    }
+
+    let target = block.header.target();
+    if target > chain_params.params.pow_limit {
+        return Err("Genesis block target exceeds the pow limit".to_string());
+    }
+    block
+        .header
+        .validate_pow(target)
+        .map_err(|err| format!("Invalid proof of work of the genesis block: {err}"))?;
+
+    Ok(())
+}
+
+/// Returns the Bitcoin genesis block of the chain spec, which is either the genesis block of
+/// the custom network defined in the chain spec or the one of `network`.
+pub fn bitcoin_genesis_block(
+    chain_spec: &dyn sc_service::ChainSpec,
+    network: Network,
+) -> Result<BitcoinBlock, String> {
+    match CustomNetwork::from_properties(&chain_spec.properties())? {
+        Some(custom_network) => {
+            if custom_network.base != network {
+                return Err(format!(
+                    "Custom network {} is based on {}, not {network}",
+                    custom_network.id, custom_network.base
+                ));
+            }
+            Ok(custom_network.genesis_block)
+        }
+        None => Ok(bitcoin::constants::genesis_block(network)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::encode::serialize_hex;
+    use serde_json::json;
+
+    fn regtest_definition() -> serde_json::Value {
+        json!({
+            "name": "Test Network",
+            "id": "test-network",
+            "base": "regtest",
+            "magic": "0a0b0c0d",
+            "genesisBlock": serialize_hex(&bitcoin::constants::genesis_block(Network::Regtest)),
+            "subsidyHalvingInterval": 1000,
+            "seednodes": ["127.0.0.1:18444"],
+        })
+    }
+
+    #[test]
+    fn test_custom_network() {
+        let custom_network = CustomNetwork::from_json(regtest_definition()).unwrap();
+
+        assert_eq!(custom_network.base, Network::Regtest);
+        assert_eq!(
+            custom_network.magic,
+            Magic::from_bytes([0x0a, 0x0b, 0x0c, 0x0d])
+        );
+        assert_eq!(
+            custom_network.genesis_hash(),
+            bitcoin::constants::genesis_block(Network::Regtest).block_hash()
+        );
+        assert_eq!(custom_network.chain_params.subsidy_halving_interval, 1000);
+        assert_eq!(
+            custom_network.chain_params.params.pow_limit,
+            ChainParams::new(Network::Regtest).params.pow_limit
+        );
+
+        let mut properties = Properties::new();
+        assert!(CustomNetwork::from_properties(&properties)
+            .unwrap()
+            .is_none());
+        properties.insert(
+            BITCOIN_NETWORK_PROPERTY.to_string(),
+            custom_network.definition().clone(),
+        );
+        assert_eq!(
+            CustomNetwork::from_properties(&properties)
+                .unwrap()
+                .unwrap()
+                .id,
+            "test-network"
+        );
+    }
+
+    #[test]
+    fn test_invalid_custom_network() {
+        let mut definition = regtest_definition();
+        definition["magic"] = json!("0a0b0c");
+        assert!(CustomNetwork::from_json(definition).is_err());
+
+        // The regtest genesis block doesn't meet the mainnet pow limit.
+        let mut definition = regtest_definition();
+        definition["powLimit"] =
+            json!("00000000ffff0000000000000000000000000000000000000000000000000000");
+        assert!(CustomNetwork::from_json(definition).is_err());
+
+        let mut definition = regtest_definition();
+        definition["unknown"] = json!(true);
+        assert!(CustomNetwork::from_json(definition).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::BitcoinExecutorDispatch;
+use sc_executor::{NativeElseWasmExecutor, RuntimeVersion, RuntimeVersionOf, WasmExecutor};
+use sc_service::Configuration;
+use sp_core::traits::{CallContext, CodeExecutor, Externalities, ReadRuntimeVersion, RuntimeCode};
+
+/// Host functions of the wasm executor, no host functions are added by
+/// [`BitcoinExecutorDispatch`].
+type HostFunctions = sp_io::SubstrateHostFunctions;
+
+/// Kind of the executor running the runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecutorKind {
+    /// Calls the native runtime if its version matches the on-chain runtime, the wasm
+    /// runtime otherwise.
+    #[default]
+    NativeElseWasm,
+    /// Always calls the wasm runtime.
+    Wasm,
+}
+
+/// Executor of the subcoin runtime.
+///
+/// `NativeElseWasmExecutor` is deprecated upstream, the wasm executor is kept alongside to
+/// migrate off the native runtime once it's proven to be no longer necessary for performance.
+#[derive(Clone)]
+pub enum RuntimeExecutor {
+    NativeElseWasm(NativeElseWasmExecutor<BitcoinExecutorDispatch>),
+    Wasm(WasmExecutor<HostFunctions>),
+}
+
+impl RuntimeExecutor {
+    /// Constructs a new instance of [`RuntimeExecutor`] from the executor settings in `config`.
+    pub fn new(config: &Configuration, kind: ExecutorKind) -> Self {
+        match kind {
+            ExecutorKind::NativeElseWasm => {
+                Self::NativeElseWasm(sc_service::new_native_or_wasm_executor(config))
+            }
+            ExecutorKind::Wasm => Self::Wasm(sc_service::new_wasm_executor(config)),
+        }
+    }
+
+    /// Returns the kind of this executor.
+    pub fn kind(&self) -> ExecutorKind {
+        match self {
+            Self::NativeElseWasm(_) => ExecutorKind::NativeElseWasm,
+            Self::Wasm(_) => ExecutorKind::Wasm,
+        }
+    }
+}
+
+impl CodeExecutor for RuntimeExecutor {
+    type Error = sc_executor::error::Error;
+
+    fn call(
+        &self,
+        ext: &mut dyn Externalities,
+        runtime_code: &RuntimeCode,
+        method: &str,
+        data: &[u8],
+        context: CallContext,
+    ) -> (Result<Vec<u8>, Self::Error>, bool) {
+        match self {
+            Self::NativeElseWasm(executor) => {
+                executor.call(ext, runtime_code, method, data, context)
+            }
+            Self::Wasm(executor) => executor.call(ext, runtime_code, method, data, context),
+        }
+    }
+}
+
+impl ReadRuntimeVersion for RuntimeExecutor {
+    fn read_runtime_version(
+        &self,
+        wasm_code: &[u8],
+        ext: &mut dyn Externalities,
+    ) -> Result<Vec<u8>, String> {
+        match self {
+            Self::NativeElseWasm(executor) => executor.read_runtime_version(wasm_code, ext),
+            Self::Wasm(executor) => executor.read_runtime_version(wasm_code, ext),
+        }
+    }
+}
+
+impl RuntimeVersionOf for RuntimeExecutor {
+    fn runtime_version(
+        &self,
+        ext: &mut dyn Externalities,
+        runtime_code: &RuntimeCode,
+    ) -> sc_executor::error::Result<RuntimeVersion> {
+        match self {
+            Self::NativeElseWasm(executor) => executor.runtime_version(ext, runtime_code),
+            Self::Wasm(executor) => executor.runtime_version(ext, runtime_code),
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Policies of the finalizer.
+//!
+//! A Bitcoin block is never final in the strict sense, the finalizer finalizes the Substrate
+//! blocks deemed irreversible by a [`FinalityPolicy`]:
+//!
+//! - [`DepthFinality`]: the blocks with enough confirmations, the default.
+//! - [`WorkFinality`]: the blocks buried under enough proof of work, which is independent
+//!   from the difficulty of the network.
+//! - [`ExternalFinality`]: the checkpoints submitted to a [`FinalityOracle`], e.g., by a
+//!   sidechain verifying the checkpoint signatures.
+
+use bitcoin::Work;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use sc_client_api::AuxStore;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::{Block as BlockT, CheckedSub, NumberFor, SaturatedConversion};
+use std::sync::Arc;
+use subcoin_primitives::{BackendExt, FinalityOracle, CONFIRMATION_DEPTH};
+
+/// Decides the block to finalize as the best chain grows.
+pub trait FinalityPolicy<Block: BlockT>: Send + Sync {
+    /// Returns the number of the block to finalize given the new best block, `None` if no
+    /// block is eligible.
+    ///
+    /// The returned block is finalized only if it's above the finalized block.
+    fn finality_target(
+        &self,
+        best_number: NumberFor<Block>,
+        best_hash: Block::Hash,
+    ) -> Option<NumberFor<Block>>;
+
+    /// Returns the stream notified when the finality target may change without a block
+    /// import, e.g., on a new checkpoint of [`ExternalFinality`].
+    fn target_updates(&self) -> BoxStream<'static, ()> {
+        futures::stream::pending().boxed()
+    }
+}
+
+/// Finalizes the blocks with `confirmation_depth` blocks on top of them.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthFinality {
+    confirmation_depth: u32,
+}
+
+impl DepthFinality {
+    /// Constructs a new instance of [`DepthFinality`].
+    pub fn new(confirmation_depth: u32) -> Self {
+        Self { confirmation_depth }
+    }
+}
+
+impl Default for DepthFinality {
+    fn default() -> Self {
+        Self::new(CONFIRMATION_DEPTH)
+    }
+}
+
+impl<Block: BlockT> FinalityPolicy<Block> for DepthFinality {
+    fn finality_target(
+        &self,
+        best_number: NumberFor<Block>,
+        _best_hash: Block::Hash,
+    ) -> Option<NumberFor<Block>> {
+        best_number.checked_sub(&self.confirmation_depth.into())
+    }
+}
+
+/// Finalizes the blocks with at least `min_work` of the chain work on top of them.
+pub struct WorkFinality<Client> {
+    client: Arc<Client>,
+    min_work: Work,
+}
+
+impl<Client> WorkFinality<Client> {
+    /// Constructs a new instance of [`WorkFinality`].
+    pub fn new(client: Arc<Client>, min_work: Work) -> Self {
+        Self { client, min_work }
+    }
+}
+
+impl<Block, Client> FinalityPolicy<Block> for WorkFinality<Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore + Send + Sync,
+{
+    fn finality_target(
+        &self,
+        best_number: NumberFor<Block>,
+        best_hash: Block::Hash,
+    ) -> Option<NumberFor<Block>> {
+        let chain_work_at = |height: u32| {
+            BackendExt::<Block>::block_hash(&self.client, height)
+                .and_then(|block_hash| self.client.chain_work(block_hash))
+        };
+
+        let best_work = BackendExt::<Block>::bitcoin_block_hash_for(&self.client, best_hash)
+            .and_then(|block_hash| self.client.chain_work(block_hash))?;
+
+        let is_buried = |height: u32| {
+            chain_work_at(height).is_some_and(|chain_work| chain_work + self.min_work <= best_work)
+        };
+
+        // The chain work is strictly increasing, find the highest buried block.
+        let finalized_number = self.client.info().finalized_number.saturated_into::<u32>();
+        let (mut low, mut high) = (finalized_number, best_number.saturated_into::<u32>());
+
+        if !is_buried(low) {
+            return None;
+        }
+
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if is_buried(mid) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        Some(low.into())
+    }
+}
+
+/// Finalizes the latest checkpoint of the [`FinalityOracle`] in the best chain.
+pub struct ExternalFinality<Client> {
+    client: Arc<Client>,
+    oracle: FinalityOracle,
+}
+
+impl<Client> ExternalFinality<Client> {
+    /// Constructs a new instance of [`ExternalFinality`].
+    pub fn new(client: Arc<Client>, oracle: FinalityOracle) -> Self {
+        Self { client, oracle }
+    }
+}
+
+impl<Block, Client> FinalityPolicy<Block> for ExternalFinality<Client>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + AuxStore + Send + Sync,
+{
+    fn finality_target(
+        &self,
+        _best_number: NumberFor<Block>,
+        _best_hash: Block::Hash,
+    ) -> Option<NumberFor<Block>> {
+        let checkpoint = FinalityOracle::checkpoint(&*self.client)?;
+
+        let height = self.client.block_number(checkpoint)?;
+
+        if BackendExt::<Block>::block_hash(&self.client, height) != Some(checkpoint) {
+            tracing::warn!(?checkpoint, "Finality checkpoint is not in the best chain");
+            return None;
+        }
+
+        Some(height.into())
+    }
+
+    fn target_updates(&self) -> BoxStream<'static, ()> {
+        self.oracle.checkpoint_stream().boxed()
+    }
+}
+
+/// Finality policy of the node.
+#[derive(Debug, Clone)]
+pub enum FinalityPolicyConfig {
+    /// See [`DepthFinality`].
+    Depth(u32),
+    /// See [`WorkFinality`].
+    Work(Work),
+    /// See [`ExternalFinality`].
+    External(FinalityOracle),
+}
+
+impl Default for FinalityPolicyConfig {
+    fn default() -> Self {
+        Self::Depth(CONFIRMATION_DEPTH)
+    }
+}
+
+impl FinalityPolicyConfig {
+    /// Returns the confirmation depth of the policy, if it finalizes by depth.
+    pub fn confirmation_depth(&self) -> Option<u32> {
+        match self {
+            Self::Depth(confirmation_depth) => Some(*confirmation_depth),
+            Self::Work(_) | Self::External(_) => None,
+        }
+    }
+
+    /// Instantiates the policy.
+    pub fn into_policy<Block, Client>(self, client: Arc<Client>) -> Arc<dyn FinalityPolicy<Block>>
+    where
+        Block: BlockT,
+        Client: HeaderBackend<Block> + AuxStore + Send + Sync + 'static,
+    {
+        match self {
+            Self::Depth(confirmation_depth) => Arc::new(DepthFinality::new(confirmation_depth)),
+            Self::Work(min_work) => Arc::new(WorkFinality::new(client, min_work)),
+            Self::External(oracle) => Arc::new(ExternalFinality::new(client, oracle)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CoinStorageKey, NodeComponents, TransactionAdapter};
+    use bitcoin::secp256k1::{Keypair, Secp256k1};
+    use sc_consensus_nakamoto::{
+        BitcoinBlockImport, BitcoinBlockImporter, BlockVerification, ImportConfig,
+    };
+    use subcoin_primitives::FinalityCheckpointError;
+    use subcoin_runtime::interface::OpaqueBlock as Block;
+    use subcoin_test_service::block_data;
+
+    #[tokio::test]
+    async fn test_finality_policies() {
+        let NodeComponents {
+            client,
+            block_executor,
+            ..
+        } = subcoin_test_service::new_test_node(tokio::runtime::Handle::current())
+            .expect("Create test node");
+
+        let mut bitcoin_block_import = BitcoinBlockImporter::<_, _, _, _, TransactionAdapter>::new(
+            client.clone(),
+            client.clone(),
+            ImportConfig {
+                network: bitcoin::Network::Bitcoin,
+                chain_params: None,
+                block_verification: BlockVerification::None,
+                execute_block: true,
+                verify_script: true,
+                verification_threads: None,
+            },
+            Arc::new(CoinStorageKey),
+            block_executor,
+            None,
+        );
+
+        let blocks = block_data();
+        for block in &blocks[1..] {
+            bitcoin_block_import
+                .import_block(block.clone())
+                .await
+                .unwrap();
+        }
+
+        let best_hash = client.info().best_hash;
+
+        let depth = DepthFinality::new(1);
+        assert_eq!(
+            FinalityPolicy::<Block>::finality_target(&depth, 3, best_hash),
+            Some(2)
+        );
+        assert_eq!(
+            FinalityPolicy::<Block>::finality_target(&depth, 0, best_hash),
+            None
+        );
+
+        // The early blocks have the same work.
+        let block_work = blocks[1].header.work();
+        let work = WorkFinality::new(client.clone(), block_work);
+        assert_eq!(
+            FinalityPolicy::<Block>::finality_target(&work, 3, best_hash),
+            Some(2)
+        );
+        let work = WorkFinality::new(client.clone(), block_work + block_work);
+        assert_eq!(
+            FinalityPolicy::<Block>::finality_target(&work, 3, best_hash),
+            Some(1)
+        );
+
+        let secp = Secp256k1::new();
+        let signer = Keypair::from_seckey_slice(&secp, &[1u8; 32]).unwrap();
+        let sign = |block_hash| {
+            secp.sign_schnorr_no_aux_rand(&FinalityOracle::checkpoint_message(block_hash), &signer)
+        };
+
+        let oracle = FinalityOracle::new(signer.x_only_public_key().0);
+        let external = ExternalFinality::new(client.clone(), oracle.clone());
+        let mut target_updates = FinalityPolicy::<Block>::target_updates(&external);
+        assert_eq!(
+            FinalityPolicy::<Block>::finality_target(&external, 3, best_hash),
+            None
+        );
+
+        let checkpoint = blocks[2].block_hash();
+        assert!(matches!(
+            oracle.submit_checkpoint(&*client, checkpoint, &sign(blocks[3].block_hash())),
+            Err(FinalityCheckpointError::InvalidSignature)
+        ));
+        assert_eq!(FinalityOracle::checkpoint(&*client), None);
+
+        oracle
+            .submit_checkpoint(&*client, checkpoint, &sign(checkpoint))
+            .unwrap();
+        assert_eq!(target_updates.next().await, Some(()));
+        assert_eq!(FinalityOracle::checkpoint(&*client), Some(checkpoint));
+        assert_eq!(
+            FinalityPolicy::<Block>::finality_target(&external, 3, best_hash),
+            Some(2)
+        );
+    }
+}
@@ -1,10 +1,10 @@
 use sc_client_api::backend::Backend;
 use sc_client_api::BlockImportOperation;
+use sc_consensus_nakamoto::BitcoinBlockBuilder;
 use sc_executor::RuntimeVersionOf;
 use sc_service::BuildGenesisBlock;
 use sp_core::storage::Storage;
-use sp_core::Encode;
-use sp_runtime::traits::{Block as BlockT, HashingFor, Header as HeaderT};
+use sp_runtime::traits::{Block as BlockT, HashingFor};
 use sp_runtime::BuildStorage;
 use std::marker::PhantomData;
 use std::sync::Arc;
@@ -14,7 +14,7 @@ use std::sync::Arc;
 /// The genesis state is handled within the pallet-bitcoin, the genesis block data is generated
 /// from the corresponding Bitcoin genesis block.
 pub struct GenesisBlockBuilder<Block: BlockT, B, E, TransactionAdapter> {
-    network: bitcoin::Network,
+    bitcoin_genesis_block: bitcoin::Block,
     genesis_storage: Storage,
     commit_genesis_state: bool,
     backend: Arc<B>,
@@ -27,7 +27,7 @@ impl<Block: BlockT, B, E: Clone, TransactionAdapter> Clone
 {
     fn clone(&self) -> Self {
         Self {
-            network: self.network,
+            bitcoin_genesis_block: self.bitcoin_genesis_block.clone(),
             genesis_storage: self.genesis_storage.clone(),
             commit_genesis_state: self.commit_genesis_state,
             backend: self.backend.clone(),
@@ -46,7 +46,7 @@ where
 {
     /// Constructs a new instance of [`GenesisBlockBuilder`].
     pub fn new(
-        network: bitcoin::Network,
+        bitcoin_genesis_block: bitcoin::Block,
         build_genesis_storage: &dyn BuildStorage,
         commit_genesis_state: bool,
         backend: Arc<B>,
@@ -56,7 +56,7 @@ where
             .build_storage()
             .map_err(sp_blockchain::Error::Storage)?;
         Ok(Self {
-            network,
+            bitcoin_genesis_block,
             genesis_storage,
             commit_genesis_state,
             backend,
@@ -66,39 +66,6 @@ where
     }
 }
 
-fn substrate_genesis_block<Block, TransactionAdapter>(
-    network: bitcoin::Network,
-    state_root: Block::Hash,
-) -> Block
-where
-    Block: BlockT,
-    TransactionAdapter: subcoin_primitives::BitcoinTransactionAdapter<Block>,
-{
-    let block = bitcoin::constants::genesis_block(network);
-
-    let extrinsics = block
-        .txdata
-        .iter()
-        .map(TransactionAdapter::bitcoin_transaction_into_extrinsic)
-        .collect::<Vec<_>>();
-
-    let extrinsics_root = frame_system::extrinsics_data_root::<HashingFor<Block>>(
-        extrinsics.iter().map(|xt| xt.encode()).collect(),
-    );
-
-    let digest = subcoin_primitives::substrate_header_digest(&block.header);
-
-    let header = <<Block as BlockT>::Header as HeaderT>::new(
-        0u32.into(),
-        extrinsics_root,
-        state_root,
-        Default::default(),
-        digest,
-    );
-
-    Block::new(header, extrinsics)
-}
-
 impl<Block, B, E, TransactionAdapter> BuildGenesisBlock<Block>
     for GenesisBlockBuilder<Block, B, E, TransactionAdapter>
 where
@@ -111,7 +78,7 @@ where
 
     fn build_genesis_block(self) -> sp_blockchain::Result<(Block, Self::BlockImportOperation)> {
         let Self {
-            network,
+            bitcoin_genesis_block,
             genesis_storage,
             commit_genesis_state,
             backend,
@@ -128,8 +95,12 @@ where
         let state_root =
             op.set_genesis_state(genesis_storage, commit_genesis_state, genesis_state_version)?;
 
-        let genesis_block =
-            substrate_genesis_block::<Block, TransactionAdapter>(network, state_root);
+        let genesis_block = BitcoinBlockBuilder::<Block, TransactionAdapter>::build(
+            &bitcoin_genesis_block,
+            0u32.into(),
+            Default::default(),
+            state_root,
+        );
 
         Ok((genesis_block, op))
     }
@@ -0,0 +1,264 @@
+//! Bounded memory usage of the in-memory block execution.
+//!
+//! The state changes of every block are written to the disk backend on import regardless
+//! of the execution strategy, the in-memory backend only serves the block execution. The
+//! blocks accumulated in the in-memory backend are flushed periodically, and the in-memory
+//! backend is released once the memory cap is exceeded, the blocks being executed on top of
+//! the disk state from then on.
+
+use crate::InMemoryBackend;
+use async_trait::async_trait;
+use sc_client_api::Backend;
+use sc_consensus::{BlockImportParams, ImportResult};
+use sc_consensus_nakamoto::{
+    BlockExecutionStrategy, BlockExecutor, ExecuteBlockResult, ExecutionBackend,
+};
+use sp_runtime::traits::Block as BlockT;
+use std::sync::Arc;
+use std::time::Instant;
+use subcoin_runtime::interface::OpaqueBlock as Block;
+use substrate_prometheus_endpoint::{register, Counter, Gauge, PrometheusError, Registry, U64};
+
+/// Number of the latest blocks kept in the in-memory backend on flush.
+const KEPT_BLOCKS: u32 = 1;
+
+/// Memory limits of the in-memory backend.
+#[derive(Debug, Clone, Copy)]
+pub struct InMemoryBackendConfig {
+    /// Number of imported blocks between two flushes of the in-memory backend.
+    pub flush_interval: u32,
+    /// Resident memory of the node in bytes beyond which the in-memory backend is released.
+    pub max_memory: Option<u64>,
+}
+
+impl Default for InMemoryBackendConfig {
+    fn default() -> Self {
+        Self {
+            flush_interval: 1000,
+            max_memory: None,
+        }
+    }
+}
+
+struct Metrics {
+    blocks: Gauge<U64>,
+    resident_memory: Gauge<U64>,
+    flushes: Counter<U64>,
+    flush_time: Gauge<U64>,
+    enabled: Gauge<U64>,
+}
+
+impl Metrics {
+    fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+        Ok(Self {
+            blocks: register(
+                Gauge::new(
+                    "subcoin_in_memory_backend_blocks",
+                    "Number of blocks held by the in-memory backend",
+                )?,
+                registry,
+            )?,
+            resident_memory: register(
+                Gauge::new(
+                    "subcoin_in_memory_backend_resident_memory_bytes",
+                    "Resident memory of the node measured on flush in bytes",
+                )?,
+                registry,
+            )?,
+            flushes: register(
+                Counter::new(
+                    "subcoin_in_memory_backend_flushes_total",
+                    "Number of flushes of the in-memory backend",
+                )?,
+                registry,
+            )?,
+            flush_time: register(
+                Gauge::new(
+                    "subcoin_in_memory_backend_flush_time_milliseconds",
+                    "Time taken by the last flush of the in-memory backend in milliseconds",
+                )?,
+                registry,
+            )?,
+            enabled: register(
+                Gauge::new(
+                    "subcoin_in_memory_backend_enabled",
+                    "Whether the blocks are executed in the in-memory backend",
+                )?,
+                registry,
+            )?,
+        })
+    }
+}
+
+/// Block executor using the in-memory backend within the memory limits, falls back to the
+/// disk backend once the memory cap is exceeded.
+pub(crate) struct BoundedInMemoryBlockExecutor {
+    in_memory: Option<(Box<dyn BlockExecutor<Block>>, Arc<InMemoryBackend>)>,
+    disk: Box<dyn BlockExecutor<Block>>,
+    config: InMemoryBackendConfig,
+    imported_since_flush: u32,
+    metrics: Option<Metrics>,
+}
+
+impl BoundedInMemoryBlockExecutor {
+    pub(crate) fn new(
+        in_memory_executor: Box<dyn BlockExecutor<Block>>,
+        in_memory_backend: Arc<InMemoryBackend>,
+        disk_executor: Box<dyn BlockExecutor<Block>>,
+        config: InMemoryBackendConfig,
+        registry: Option<&Registry>,
+    ) -> Self {
+        if config.max_memory.is_some() && resident_memory().is_none() {
+            tracing::warn!("Resident memory is unavailable, the memory cap is not enforced");
+        }
+
+        let metrics = registry.and_then(|registry| {
+            Metrics::register(registry)
+                .map_err(|err| tracing::error!("Failed to register metrics: {err:?}"))
+                .ok()
+        });
+
+        if let Some(metrics) = &metrics {
+            metrics.enabled.set(1);
+        }
+
+        Self {
+            in_memory: Some((in_memory_executor, in_memory_backend)),
+            disk: disk_executor,
+            config,
+            imported_since_flush: 0,
+            metrics,
+        }
+    }
+
+    fn executor(&self) -> &dyn BlockExecutor<Block> {
+        match &self.in_memory {
+            Some((executor, _)) => &**executor,
+            None => &*self.disk,
+        }
+    }
+
+    /// Flushes the in-memory backend and releases it if the memory cap is still exceeded.
+    fn flush(&mut self) {
+        let Some((_, backend)) = &self.in_memory else {
+            return;
+        };
+
+        let now = Instant::now();
+        let pruned = backend.blockchain().prune_blocks(KEPT_BLOCKS);
+        let flush_time = now.elapsed().as_millis();
+
+        let memory = resident_memory();
+
+        tracing::debug!(
+            "Flushed {pruned} blocks from the in-memory backend in {flush_time}ms, \
+            resident memory: {memory:?} bytes",
+        );
+
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .blocks
+                .set(backend.blockchain().blocks_count() as u64);
+            metrics.flushes.inc();
+            metrics.flush_time.set(flush_time as u64);
+            if let Some(memory) = memory {
+                metrics.resident_memory.set(memory);
+            }
+        }
+
+        if let (Some(memory), Some(max_memory)) = (memory, self.config.max_memory) {
+            if memory > max_memory {
+                tracing::warn!(
+                    "Resident memory {memory} bytes exceeds the cap {max_memory} bytes, \
+                    releasing the in-memory backend and executing the blocks on disk",
+                );
+                self.in_memory.take();
+                if let Some(metrics) = &self.metrics {
+                    metrics.enabled.set(0);
+                    metrics.blocks.set(0);
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BlockExecutor<Block> for BoundedInMemoryBlockExecutor {
+    fn execution_strategy(&self) -> BlockExecutionStrategy {
+        self.executor().execution_strategy()
+    }
+
+    fn execute_block(
+        &self,
+        parent_hash: <Block as BlockT>::Hash,
+        block: Block,
+    ) -> sp_blockchain::Result<ExecuteBlockResult<Block>> {
+        self.executor().execute_block(parent_hash, block)
+    }
+
+    async fn import_block(
+        &mut self,
+        import_params: BlockImportParams<Block>,
+    ) -> Result<ImportResult, sp_consensus::Error> {
+        let Some((executor, _)) = &mut self.in_memory else {
+            unreachable!("Not needed in disk backend context")
+        };
+
+        let import_result = executor.import_block(import_params).await?;
+
+        self.imported_since_flush += 1;
+
+        // The memory cap is checked on every block, the flush interval only bounds the
+        // blocks held in memory.
+        let memory_exceeded = self
+            .config
+            .max_memory
+            .zip(resident_memory())
+            .is_some_and(|(max_memory, memory)| memory > max_memory);
+
+        if memory_exceeded || self.imported_since_flush >= self.config.flush_interval {
+            self.flush();
+            self.imported_since_flush = 0;
+        }
+
+        Ok(import_result)
+    }
+}
+
+/// Returns the disk counterpart of the in-memory execution strategy.
+pub(crate) fn disk_execution_strategy(
+    block_execution_strategy: BlockExecutionStrategy,
+) -> Option<BlockExecutionStrategy> {
+    match block_execution_strategy {
+        BlockExecutionStrategy::RuntimeExecution(ExecutionBackend::InMemory) => Some(
+            BlockExecutionStrategy::RuntimeExecution(ExecutionBackend::Disk),
+        ),
+        BlockExecutionStrategy::OffRuntimeExecution(ExecutionBackend::InMemory) => Some(
+            BlockExecutionStrategy::OffRuntimeExecution(ExecutionBackend::Disk),
+        ),
+        _ => None,
+    }
+}
+
+/// Returns the resident memory of the process in bytes, only available on Linux.
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|kilobytes| kilobytes.trim().parse::<u64>().ok())
+        .map(|kilobytes| kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_resident_memory() {
+        assert!(resident_memory().is_some_and(|memory| memory > 0));
+    }
+}
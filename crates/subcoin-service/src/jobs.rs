@@ -0,0 +1,533 @@
+//! Background jobs of the long-running operations.
+//!
+//! A job, e.g., an analytics export or a rescan, runs on its own thread instead of blocking
+//! the RPC server. Each job is recorded in the aux-db along with its progress and the last
+//! checkpoint reported by the [`JobHandler`]. The jobs still running on shutdown are resumed
+//! from their checkpoint by [`JobManager::resume_interrupted`] on the next start.
+
+use parking_lot::Mutex;
+use sc_client_api::AuxStore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Key of the ids of the recorded jobs in the aux-db.
+const JOB_IDS_KEY: &[u8] = b"subcoin_job_ids";
+
+/// Maximum number of the finished jobs kept in the aux-db, the oldest ones are removed.
+const MAX_FINISHED_JOBS: usize = 100;
+
+fn job_key(id: JobId) -> Vec<u8> {
+    [b"subcoin_job_".as_slice(), &id.to_le_bytes()].concat()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Job id.
+pub type JobId = u64;
+
+/// Job error.
+#[derive(Debug, thiserror::Error)]
+pub enum JobError {
+    #[error("Unknown job kind {0}")]
+    UnknownKind(String),
+    #[error("Job {0} not found")]
+    UnknownJob(JobId),
+    #[error("Invalid job params: {0}")]
+    InvalidParams(String),
+    #[error("Job cancelled")]
+    Cancelled,
+    #[error("{0}")]
+    Failed(String),
+    #[error("Failed to spawn the job thread: {0}")]
+    Spawn(std::io::Error),
+    #[error(transparent)]
+    Blockchain(#[from] sp_blockchain::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// State of a job.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Running,
+    Completed,
+    Cancelled,
+    Failed(String),
+    /// Stopped by a shutdown and not resumable, as no handler of its kind is registered.
+    Interrupted,
+}
+
+impl JobState {
+    fn is_finished(&self) -> bool {
+        !matches!(self, Self::Running)
+    }
+}
+
+/// Job recorded in the aux-db.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+    pub id: JobId,
+    pub kind: String,
+    pub params: Value,
+    pub state: JobState,
+    /// Units of work done, in the unit of the job, e.g., blocks.
+    pub done: u64,
+    /// Total units of work, `0` if unknown yet.
+    pub total: u64,
+    /// Last checkpoint reported by the job, the job resumes from there after a restart.
+    pub checkpoint: Option<Value>,
+    /// UNIX timestamp of the creation.
+    pub created_at: u64,
+    /// UNIX timestamp of the last update.
+    pub updated_at: u64,
+}
+
+/// Executes the jobs of a kind.
+pub trait JobHandler: Send + Sync {
+    /// Validates the params of a new job, returning the total units of work if known.
+    fn validate(&self, params: &Value) -> Result<u64, JobError>;
+
+    /// Runs the job to completion on the job thread.
+    ///
+    /// The job resumes from [`JobContext::checkpoint`] if any and should check
+    /// [`JobContext::is_cancelled`] regularly, returning [`JobError::Cancelled`] once
+    /// cancelled.
+    fn run(&self, params: &Value, ctx: &JobContext) -> Result<(), JobError>;
+}
+
+/// Context of a running job.
+pub struct JobContext {
+    id: JobId,
+    checkpoint: Option<Value>,
+    cancelled: Arc<AtomicBool>,
+    report: Box<dyn Fn(u64, u64, Value) -> Result<(), JobError> + Send + Sync>,
+}
+
+impl JobContext {
+    /// Returns the id of the job.
+    pub fn id(&self) -> JobId {
+        self.id
+    }
+
+    /// Returns the checkpoint to resume from, `None` for a new job.
+    pub fn checkpoint(&self) -> Option<&Value> {
+        self.checkpoint.as_ref()
+    }
+
+    /// Whether the job has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Records the progress and the checkpoint to resume from after a restart.
+    pub fn report(&self, done: u64, total: u64, checkpoint: Value) -> Result<(), JobError> {
+        (self.report)(done, total, checkpoint)
+    }
+}
+
+struct Inner<Client> {
+    client: Arc<Client>,
+    handlers: Mutex<HashMap<String, Arc<dyn JobHandler>>>,
+    /// Cancellation flags of the running jobs.
+    running: Mutex<HashMap<JobId, Arc<AtomicBool>>>,
+    /// Serializes the updates of the job records.
+    records: Mutex<()>,
+}
+
+/// Runs the background jobs and keeps their records in the aux-db.
+pub struct JobManager<Client> {
+    inner: Arc<Inner<Client>>,
+}
+
+impl<Client> Clone for JobManager<Client> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<Client> JobManager<Client>
+where
+    Client: AuxStore + Send + Sync + 'static,
+{
+    /// Constructs a new instance of [`JobManager`].
+    pub fn new(client: Arc<Client>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                client,
+                handlers: Default::default(),
+                running: Default::default(),
+                records: Default::default(),
+            }),
+        }
+    }
+
+    /// Registers the handler of the jobs of `kind`.
+    pub fn register(&self, kind: impl Into<String>, handler: Arc<dyn JobHandler>) {
+        self.inner.handlers.lock().insert(kind.into(), handler);
+    }
+
+    /// Starts a new job, returning its id.
+    pub fn start(&self, kind: &str, params: Value) -> Result<JobId, JobError> {
+        let handler = self.handler(kind)?;
+        let total = handler.validate(&params)?;
+
+        let id = {
+            let _guard = self.inner.records.lock();
+            let mut ids = self.job_ids()?;
+            let id = ids.last().map_or(0, |id| id + 1);
+            let timestamp = now();
+            let job = JobInfo {
+                id,
+                kind: kind.to_string(),
+                params,
+                state: JobState::Running,
+                done: 0,
+                total,
+                checkpoint: None,
+                created_at: timestamp,
+                updated_at: timestamp,
+            };
+            ids.push(id);
+            self.write(&job, Some(&ids))?;
+            id
+        };
+
+        self.spawn(id, handler)?;
+
+        Ok(id)
+    }
+
+    /// Requests the job to stop, returns `false` if the job is not running.
+    pub fn cancel(&self, id: JobId) -> bool {
+        match self.inner.running.lock().get(&id) {
+            Some(cancelled) => {
+                cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the job.
+    pub fn job(&self, id: JobId) -> Result<Option<JobInfo>, JobError> {
+        self.read(id)
+    }
+
+    /// Returns all the recorded jobs, the oldest first.
+    pub fn jobs(&self) -> Result<Vec<JobInfo>, JobError> {
+        let mut jobs = Vec::new();
+        for id in self.job_ids()? {
+            jobs.extend(self.read(id)?);
+        }
+        Ok(jobs)
+    }
+
+    /// Resumes the jobs interrupted by the last shutdown.
+    ///
+    /// Must be called once on startup after registering the handlers.
+    pub fn resume_interrupted(&self) -> Result<(), JobError> {
+        for mut job in self.jobs()? {
+            if job.state != JobState::Running || self.inner.running.lock().contains_key(&job.id) {
+                continue;
+            }
+
+            match self.handler(&job.kind) {
+                Ok(handler) => {
+                    tracing::info!("Resuming job {} ({})", job.id, job.kind);
+                    self.spawn(job.id, handler)?;
+                }
+                Err(_) => {
+                    tracing::warn!(
+                        "No handler for the interrupted job {} ({})",
+                        job.id,
+                        job.kind
+                    );
+                    job.state = JobState::Interrupted;
+                    job.updated_at = now();
+                    let _guard = self.inner.records.lock();
+                    self.write(&job, None)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handler(&self, kind: &str) -> Result<Arc<dyn JobHandler>, JobError> {
+        self.inner
+            .handlers
+            .lock()
+            .get(kind)
+            .cloned()
+            .ok_or_else(|| JobError::UnknownKind(kind.to_string()))
+    }
+
+    fn spawn(&self, id: JobId, handler: Arc<dyn JobHandler>) -> Result<(), JobError> {
+        let job = self.read(id)?.ok_or(JobError::UnknownJob(id))?;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.inner.running.lock().insert(id, cancelled.clone());
+
+        let manager = self.clone();
+        let ctx = JobContext {
+            id,
+            checkpoint: job.checkpoint.clone(),
+            cancelled,
+            report: Box::new(move |done, total, checkpoint| {
+                manager.update(id, |job| {
+                    job.done = done;
+                    job.total = total;
+                    job.checkpoint = Some(checkpoint);
+                })
+            }),
+        };
+
+        let manager = self.clone();
+        let spawned = std::thread::Builder::new()
+            .name(format!("job-{}", job.kind))
+            .spawn(move || {
+                let state = match handler.run(&job.params, &ctx) {
+                    Ok(()) => JobState::Completed,
+                    Err(JobError::Cancelled) => JobState::Cancelled,
+                    Err(err) => {
+                        tracing::error!("Job {id} ({}) failed: {err}", job.kind);
+                        JobState::Failed(err.to_string())
+                    }
+                };
+
+                manager.inner.running.lock().remove(&id);
+
+                if let Err(err) = manager.finish(id, state) {
+                    tracing::error!("Failed to record the end of job {id}: {err}");
+                }
+            });
+
+        if let Err(err) = spawned {
+            self.inner.running.lock().remove(&id);
+            self.update(id, |job| job.state = JobState::Failed(err.to_string()))?;
+            return Err(JobError::Spawn(err));
+        }
+
+        Ok(())
+    }
+
+    fn update(&self, id: JobId, f: impl FnOnce(&mut JobInfo)) -> Result<(), JobError> {
+        let _guard = self.inner.records.lock();
+        let mut job = self.read(id)?.ok_or(JobError::UnknownJob(id))?;
+        f(&mut job);
+        job.updated_at = now();
+        self.write(&job, None)
+    }
+
+    /// Records the final state of the job and removes the oldest finished jobs.
+    fn finish(&self, id: JobId, state: JobState) -> Result<(), JobError> {
+        self.update(id, |job| job.state = state)?;
+
+        let _guard = self.inner.records.lock();
+        let ids = self.job_ids()?;
+
+        let mut finished = Vec::new();
+        for id in &ids {
+            if self.read(*id)?.is_some_and(|job| job.state.is_finished()) {
+                finished.push(*id);
+            }
+        }
+
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return Ok(());
+        }
+
+        let removed = &finished[..finished.len() - MAX_FINISHED_JOBS];
+        let ids = ids
+            .into_iter()
+            .filter(|id| !removed.contains(id))
+            .collect::<Vec<_>>();
+        let encoded_ids = serde_json::to_vec(&ids)?;
+        let removed_keys = removed.iter().map(|id| job_key(*id)).collect::<Vec<_>>();
+
+        self.inner.client.insert_aux(
+            &[(JOB_IDS_KEY, encoded_ids.as_slice())],
+            removed_keys.iter().map(|key| key.as_slice()),
+        )?;
+
+        Ok(())
+    }
+
+    fn job_ids(&self) -> Result<Vec<JobId>, JobError> {
+        match self.inner.client.get_aux(JOB_IDS_KEY)? {
+            Some(encoded) => Ok(serde_json::from_slice(&encoded)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn read(&self, id: JobId) -> Result<Option<JobInfo>, JobError> {
+        self.inner
+            .client
+            .get_aux(&job_key(id))?
+            .map(|encoded| serde_json::from_slice(&encoded))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Writes the job record, along with the job ids if updated.
+    fn write(&self, job: &JobInfo, ids: Option<&[JobId]>) -> Result<(), JobError> {
+        let key = job_key(job.id);
+        let encoded = serde_json::to_vec(job)?;
+        let encoded_ids = ids.map(serde_json::to_vec).transpose()?;
+
+        let mut insert = vec![(key.as_slice(), encoded.as_slice())];
+        if let Some(encoded_ids) = &encoded_ids {
+            insert.push((JOB_IDS_KEY, encoded_ids.as_slice()));
+        }
+
+        self.inner.client.insert_aux(&insert, &[])?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct MemoryAux(Mutex<HashMap<Vec<u8>, Vec<u8>>>);
+
+    impl AuxStore for MemoryAux {
+        fn insert_aux<
+            'a,
+            'b: 'a,
+            'c: 'a,
+            I: IntoIterator<Item = &'a (&'c [u8], &'c [u8])>,
+            D: IntoIterator<Item = &'a &'b [u8]>,
+        >(
+            &self,
+            insert: I,
+            delete: D,
+        ) -> sp_blockchain::Result<()> {
+            let mut aux = self.0.lock();
+            for (key, value) in insert {
+                aux.insert(key.to_vec(), value.to_vec());
+            }
+            for key in delete {
+                aux.remove(*key);
+            }
+            Ok(())
+        }
+
+        fn get_aux(&self, key: &[u8]) -> sp_blockchain::Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().get(key).cloned())
+        }
+    }
+
+    /// Counts up to `params`, one unit per checkpoint.
+    struct CountJob;
+
+    impl JobHandler for CountJob {
+        fn validate(&self, params: &Value) -> Result<u64, JobError> {
+            params
+                .as_u64()
+                .ok_or_else(|| JobError::InvalidParams("Expected a number".to_string()))
+        }
+
+        fn run(&self, params: &Value, ctx: &JobContext) -> Result<(), JobError> {
+            let total = self.validate(params)?;
+            let from = ctx.checkpoint().and_then(Value::as_u64).unwrap_or(0);
+            for done in from + 1..=total {
+                if ctx.is_cancelled() {
+                    return Err(JobError::Cancelled);
+                }
+                std::thread::sleep(Duration::from_millis(1));
+                ctx.report(done, total, Value::from(done))?;
+            }
+            Ok(())
+        }
+    }
+
+    fn wait_until_finished(manager: &JobManager<MemoryAux>, id: JobId) -> JobInfo {
+        loop {
+            let job = manager.job(id).unwrap().unwrap();
+            if job.state.is_finished() && !manager.inner.running.lock().contains_key(&id) {
+                return job;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_job_lifecycle() {
+        let client = Arc::new(MemoryAux::default());
+        let manager = JobManager::new(client.clone());
+        manager.register("count", Arc::new(CountJob));
+
+        assert!(matches!(
+            manager.start("unknown", Value::Null),
+            Err(JobError::UnknownKind(_))
+        ));
+        assert!(matches!(
+            manager.start("count", Value::Null),
+            Err(JobError::InvalidParams(_))
+        ));
+
+        let id = manager.start("count", Value::from(10)).unwrap();
+        let job = wait_until_finished(&manager, id);
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!((job.done, job.total), (10, 10));
+
+        // A job left running by a shutdown resumes from its checkpoint.
+        let interrupted = JobInfo {
+            id: 1,
+            kind: "count".to_string(),
+            params: Value::from(20),
+            state: JobState::Running,
+            done: 15,
+            total: 20,
+            checkpoint: Some(Value::from(15)),
+            created_at: 0,
+            updated_at: 0,
+        };
+        manager.write(&interrupted, Some(&[0, 1])).unwrap();
+
+        let manager = JobManager::new(client);
+        manager.resume_interrupted().unwrap();
+        assert_eq!(
+            manager.job(1).unwrap().unwrap().state,
+            JobState::Interrupted
+        );
+
+        manager.write(&interrupted, None).unwrap();
+        manager.register("count", Arc::new(CountJob));
+        manager.resume_interrupted().unwrap();
+        let job = wait_until_finished(&manager, 1);
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!(job.done, 20);
+        assert_eq!(manager.jobs().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_cancel_job() {
+        let manager = JobManager::new(Arc::new(MemoryAux::default()));
+        manager.register("count", Arc::new(CountJob));
+
+        let id = manager.start("count", Value::from(u32::MAX)).unwrap();
+        assert!(manager.cancel(id));
+
+        let job = wait_until_finished(&manager, id);
+        assert_eq!(job.state, JobState::Cancelled);
+        assert!(!manager.cancel(id));
+    }
+}
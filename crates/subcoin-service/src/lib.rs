@@ -2,9 +2,16 @@
 
 #![allow(deprecated)]
 
+mod bitcoind_rpc;
 mod block_executor;
+mod block_source;
 pub mod chain_spec;
+mod custom_network;
+mod executor;
+mod finality;
 mod genesis_block_builder;
+mod in_memory_backend;
+mod jobs;
 mod transaction_adapter;
 
 use bitcoin::hashes::Hash;
@@ -12,50 +19,59 @@ use block_executor::{new_block_executor, new_in_memory_client};
 use frame_benchmarking_cli::SUBSTRATE_REFERENCE_HARDWARE;
 use futures::{FutureExt, StreamExt};
 use genesis_block_builder::GenesisBlockBuilder;
+use in_memory_backend::{disk_execution_strategy, BoundedInMemoryBlockExecutor};
 use sc_client_api::{AuxStore, BlockchainEvents, Finalizer, HeaderBackend};
 use sc_consensus::import_queue::BasicQueue;
 use sc_consensus::{BlockImportParams, Verifier};
-use sc_consensus_nakamoto::{BlockExecutionStrategy, BlockExecutor};
-use sc_executor::NativeElseWasmExecutor;
+use sc_consensus_nakamoto::{AdaptiveBlockExecutor, BlockExecutionStrategy, BlockExecutor};
 use sc_network_sync::SyncingService;
-use sc_service::config::PrometheusConfig;
+use sc_service::config::{DatabaseSource, PrometheusConfig};
 use sc_service::error::Error as ServiceError;
 use sc_service::{
     Configuration, KeystoreContainer, MetricsService, NativeExecutionDispatch, TaskManager,
 };
 use sc_telemetry::{Telemetry, TelemetryWorker};
 use sc_utils::mpsc::TracingUnboundedSender;
+use sp_api::ProvideRuntimeApi;
+use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_consensus::SyncOracle;
 use sp_core::traits::SpawnNamed;
 use sp_core::Encode;
 use sp_keystore::KeystorePtr;
-use sp_runtime::traits::{Block as BlockT, CheckedSub, Header as HeaderT};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
 use std::ops::Deref;
-use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
+use subcoin_db::SubcoinDb;
 use subcoin_runtime::interface::OpaqueBlock as Block;
 use subcoin_runtime::RuntimeApi;
 
+pub use bitcoind_rpc::{BitcoindRpc, BitcoindRpcAuth, BitcoindRpcError};
+pub use block_executor::new_reference_block_executor;
+pub use block_source::{follow_block_source, BlockSource, BlockSourceError};
+pub use custom_network::{bitcoin_genesis_block, CustomNetwork, BITCOIN_NETWORK_PROPERTY};
+pub use executor::{ExecutorKind, RuntimeExecutor};
+pub use finality::{
+    DepthFinality, ExternalFinality, FinalityPolicy, FinalityPolicyConfig, WorkFinality,
+};
+pub use in_memory_backend::InMemoryBackendConfig;
+pub use jobs::{JobContext, JobError, JobHandler, JobId, JobInfo, JobManager, JobState};
 pub use transaction_adapter::TransactionAdapter;
 
 /// This is a specialization of the general Substrate ChainSpec type.
 pub type ChainSpec = sc_service::GenericChainSpec;
 
 /// Disk backend client type.
-pub type FullClient =
-    sc_service::TFullClient<Block, RuntimeApi, NativeElseWasmExecutor<BitcoinExecutorDispatch>>;
-type FullBackend = sc_service::TFullBackend<Block>;
+pub type FullClient = sc_service::TFullClient<Block, RuntimeApi, RuntimeExecutor>;
+/// Disk backend type.
+pub type FullBackend = sc_service::TFullBackend<Block>;
 type FullSelectChain = sc_consensus::LongestChain<FullBackend, Block>;
 
 /// In memory client type.
 pub type InMemoryClient = sc_service::client::Client<
     InMemoryBackend,
-    sc_service::LocalCallExecutor<
-        Block,
-        sc_fast_sync_backend::Backend<Block>,
-        NativeElseWasmExecutor<BitcoinExecutorDispatch>,
-    >,
+    sc_service::LocalCallExecutor<Block, sc_fast_sync_backend::Backend<Block>, RuntimeExecutor>,
     Block,
     RuntimeApi,
 >;
@@ -85,6 +101,14 @@ impl subcoin_primitives::CoinStorageKey for CoinStorageKey {
     fn storage_prefix(&self) -> [u8; 32] {
         pallet_bitcoin::coin_storage_prefix::<subcoin_runtime::Runtime>()
     }
+
+    fn ongoing_coins_migration_key(&self) -> [u8; 32] {
+        pallet_bitcoin::ongoing_coins_migration_key::<subcoin_runtime::Runtime>()
+    }
+
+    fn max_script_size(&self) -> usize {
+        pallet_bitcoin::max_script_size::<subcoin_runtime::Runtime>()
+    }
 }
 
 /// Subcoin node components.
@@ -94,13 +118,21 @@ pub struct NodeComponents {
     /// Backend.
     pub backend: Arc<FullBackend>,
     /// Executor
-    pub executor: NativeElseWasmExecutor<BitcoinExecutorDispatch>,
+    pub executor: RuntimeExecutor,
     /// Task manager.
     pub task_manager: TaskManager,
     /// Block processor used in the block import pipeline.
     pub block_executor: Box<dyn BlockExecutor<Block>>,
+    /// Best block number known in the network, `0` if unknown.
+    ///
+    /// Must be kept up to date for [`BlockExecutionStrategy::Adaptive`] to switch the execution.
+    pub network_tip: Arc<AtomicU32>,
     pub keystore_container: KeystoreContainer,
     pub telemetry: Option<Telemetry>,
+    /// Database of the subcoin data not tied to the block import.
+    pub subcoin_db: SubcoinDb,
+    /// Default number of the block and header verification threads.
+    pub verification_threads: usize,
 }
 
 /// Subcoin node configuration.
@@ -110,6 +142,10 @@ pub struct SubcoinConfiguration<'a> {
     pub block_execution_strategy: BlockExecutionStrategy,
     pub no_hardware_benchmarks: bool,
     pub storage_monitor: sc_storage_monitor::StorageMonitorParams,
+    /// Memory limits of the in-memory backend, if used by the execution strategy.
+    pub in_memory_backend: InMemoryBackendConfig,
+    /// Executor running the runtime.
+    pub executor: ExecutorKind,
 }
 
 impl<'a> Deref for SubcoinConfiguration<'a> {
@@ -122,11 +158,10 @@ impl<'a> Deref for SubcoinConfiguration<'a> {
 
 fn initialize_genesis_block_hash_mapping<Block: BlockT, Client: HeaderBackend<Block> + AuxStore>(
     client: &Client,
-    bitcoin_network: bitcoin::Network,
+    bitcoin_genesis_hash: bitcoin::BlockHash,
 ) {
     // Initialize the genesis block hash mapping.
     let substrate_genesis_hash: <Block as BlockT>::Hash = client.info().genesis_hash;
-    let bitcoin_genesis_hash = bitcoin::constants::genesis_block(bitcoin_network).block_hash();
     client
         .insert_aux(
             &[(
@@ -146,6 +181,8 @@ pub fn new_node(config: SubcoinConfiguration) -> Result<NodeComponents, ServiceE
         block_execution_strategy,
         no_hardware_benchmarks,
         storage_monitor,
+        in_memory_backend: in_memory_backend_config,
+        executor: executor_kind,
     } = config;
 
     let telemetry = config
@@ -159,13 +196,16 @@ pub fn new_node(config: SubcoinConfiguration) -> Result<NodeComponents, ServiceE
         })
         .transpose()?;
 
-    // TODO: maintain the native executor on our own since it's deprecated upstream
-    let executor = sc_service::new_native_or_wasm_executor(config);
+    let executor = RuntimeExecutor::new(config, executor_kind);
 
     let backend = sc_service::new_db_backend(config.db_config())?;
 
+    let bitcoin_genesis_block = bitcoin_genesis_block(config.chain_spec.as_ref(), bitcoin_network)
+        .map_err(ServiceError::Other)?;
+    let bitcoin_genesis_hash = bitcoin_genesis_block.block_hash();
+
     let genesis_block_builder = GenesisBlockBuilder::<_, _, _, TransactionAdapter>::new(
-        bitcoin_network,
+        bitcoin_genesis_block.clone(),
         config.chain_spec.as_storage_builder(),
         !config.no_genesis(),
         backend.clone(),
@@ -183,27 +223,60 @@ pub fn new_node(config: SubcoinConfiguration) -> Result<NodeComponents, ServiceE
         )?;
 
     // Initialize the genesis block hash mapping.
-    initialize_genesis_block_hash_mapping(&client, bitcoin_network);
+    initialize_genesis_block_hash_mapping(&client, bitcoin_genesis_hash);
 
     let client = Arc::new(client);
 
-    let should_create_in_memory_client = block_execution_strategy.in_memory_backend_used();
-    let block_executor = new_block_executor(
-        client.clone(),
-        block_execution_strategy,
-        if should_create_in_memory_client {
-            Some(new_in_memory_client(
-                client.clone(),
-                backend.clone(),
-                executor.clone(),
-                bitcoin_network,
-                task_manager.spawn_handle(),
-                config,
-            )?)
-        } else {
-            None
-        },
-    );
+    let (in_memory_client, in_memory_backend) = if block_execution_strategy.in_memory_backend_used()
+    {
+        let (in_memory_client, in_memory_backend) = new_in_memory_client(
+            client.clone(),
+            backend.clone(),
+            executor.clone(),
+            bitcoin_genesis_block,
+            task_manager.spawn_handle(),
+            config,
+        )?;
+        (Some(in_memory_client), Some(in_memory_backend))
+    } else {
+        (None, None)
+    };
+
+    let bounded_block_executor = |block_execution_strategy: BlockExecutionStrategy| {
+        let block_executor = new_block_executor(
+            client.clone(),
+            block_execution_strategy,
+            in_memory_client.clone(),
+        );
+
+        match (
+            disk_execution_strategy(block_execution_strategy),
+            in_memory_backend.clone(),
+        ) {
+            (Some(disk_execution_strategy), Some(in_memory_backend)) => {
+                Box::new(BoundedInMemoryBlockExecutor::new(
+                    block_executor,
+                    in_memory_backend,
+                    new_block_executor(client.clone(), disk_execution_strategy, None),
+                    in_memory_backend_config,
+                    config.prometheus_registry(),
+                )) as Box<dyn BlockExecutor<Block>>
+            }
+            _ => block_executor,
+        }
+    };
+
+    let network_tip = Arc::new(AtomicU32::new(0));
+
+    let block_executor: Box<dyn BlockExecutor<Block>> = match block_execution_strategy {
+        BlockExecutionStrategy::Adaptive { switch_depth } => Box::new(AdaptiveBlockExecutor::new(
+            bounded_block_executor(BlockExecutionStrategy::off_runtime_in_memory()),
+            new_block_executor(client.clone(), BlockExecutionStrategy::runtime_disk(), None),
+            network_tip.clone(),
+            switch_depth,
+        )),
+        block_execution_strategy => bounded_block_executor(block_execution_strategy),
+    };
 
     let mut telemetry = telemetry.map(|(worker, telemetry)| {
         task_manager
@@ -220,6 +293,8 @@ pub fn new_node(config: SubcoinConfiguration) -> Result<NodeComponents, ServiceE
         }))
         .flatten();
 
+    let verification_threads = default_verification_threads(maybe_hwbench.as_ref());
+
     if let Some(hwbench) = maybe_hwbench {
         sc_sysinfo::print_hwbench(&hwbench);
         match SUBSTRATE_REFERENCE_HARDWARE.check_hardware(&hwbench) {
@@ -241,6 +316,12 @@ pub fn new_node(config: SubcoinConfiguration) -> Result<NodeComponents, ServiceE
         }
     }
 
+    let subcoin_db = match &database_path {
+        Some(db_path) => SubcoinDb::open(&subcoin_db_path(db_path))
+            .map_err(|e| ServiceError::Application(e.into()))?,
+        None => SubcoinDb::in_memory(),
+    };
+
     if let Some(database_path) = database_path {
         sc_storage_monitor::StorageMonitorService::try_spawn(
             storage_monitor,
@@ -256,11 +337,42 @@ pub fn new_node(config: SubcoinConfiguration) -> Result<NodeComponents, ServiceE
         executor,
         task_manager,
         block_executor,
+        network_tip,
         keystore_container,
         telemetry,
+        subcoin_db,
+        verification_threads,
     })
 }
 
+/// Returns the number of the verification threads used unless specified.
+///
+/// All the cores are used, except that half of them are left to the other workloads if the
+/// hardware benchmarks fall short of the reference hardware.
+fn default_verification_threads(hwbench: Option<&sc_sysinfo::HwBench>) -> usize {
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    match hwbench {
+        Some(hwbench)
+            if SUBSTRATE_REFERENCE_HARDWARE
+                .check_hardware(hwbench)
+                .is_err() =>
+        {
+            (cores / 2).max(1)
+        }
+        _ => cores,
+    }
+}
+
+/// Returns the path of the subcoin database of the Substrate database at `database_path`.
+///
+/// It's placed next to the Substrate database, e.g., `db/full` and `db/subcoin`.
+pub fn subcoin_db_path(database_path: &Path) -> PathBuf {
+    database_path.with_file_name("subcoin")
+}
+
 type SubstrateNetworkingParts = (
     TracingUnboundedSender<sc_rpc::system::Request<Block>>,
     Arc<SyncingService<Block>>,
@@ -294,7 +406,7 @@ where
     );
 
     let import_queue = BasicQueue::new(
-        SubstrateImportQueueVerifier,
+        SubstrateImportQueueVerifier::new(client.clone()),
         Box::new(client.clone()),
         None,
         &task_manager.spawn_essential_handle(),
@@ -373,13 +485,13 @@ where
     Ok((system_rpc_tx, sync_service))
 }
 
-/// Creates a future to finalize blocks with enough confirmations.
+/// Creates a future to finalize the blocks deemed irreversible by the finality policy.
 ///
 /// The future needs to be spawned in the background.
 pub async fn finalize_confirmed_blocks<Block, Client, Backend>(
     client: Arc<Client>,
     spawn_handle: impl SpawnNamed,
-    confirmation_depth: u32,
+    finality_policy: Arc<dyn FinalityPolicy<Block>>,
     major_sync_confirmation_depth: u32,
     subcoin_networking_is_major_syncing: Arc<AtomicBool>,
     substrate_sync_service: Option<Arc<SyncingService<Block>>>,
@@ -390,16 +502,28 @@ pub async fn finalize_confirmed_blocks<Block, Client, Backend>(
 {
     // Use `every_import_notification_stream()` so that we can receive the notifications even when
     // major syncing.
-    let mut block_import_stream = client.every_import_notification_stream();
+    let block_import_stream = client
+        .every_import_notification_stream()
+        .map(|notification| Some(notification.hash));
+
+    // `None` is an update of the finality target, e.g., a new checkpoint, which is applied to
+    // the best block right away instead of waiting for the next block import.
+    let mut finality_triggers = futures::stream::select(
+        block_import_stream,
+        finality_policy.target_updates().map(|()| None),
+    );
+
+    while let Some(maybe_imported) = finality_triggers.next().await {
+        let block_hash = maybe_imported.unwrap_or_else(|| client.info().best_hash);
 
-    while let Some(notification) = block_import_stream.next().await {
         let block_number = client
-            .number(notification.hash)
+            .number(block_hash)
             .ok()
             .flatten()
             .expect("Imported Block must be available; qed");
 
-        let Some(confirmed_block_number) = block_number.checked_sub(&confirmation_depth.into())
+        let Some(confirmed_block_number) =
+            finality_policy.finality_target(block_number, block_hash)
         else {
             continue;
         };
@@ -467,6 +591,35 @@ pub async fn finalize_confirmed_blocks<Block, Client, Backend>(
     }
 }
 
+/// Replaces the database of the configuration with the same database opened read-only, the
+/// backend created by [`new_partial`] afterwards rejects any write.
+///
+/// Only the ParityDb database is supported.
+pub fn use_read_only_database(config: &mut Configuration) -> Result<(), ServiceError> {
+    let path = match &config.database {
+        DatabaseSource::ParityDb { path }
+        | DatabaseSource::Auto {
+            paritydb_path: path,
+            ..
+        } => path.clone(),
+        _ => {
+            return Err(ServiceError::Other(
+                "Only the ParityDb database can be opened read-only".into(),
+            ))
+        }
+    };
+
+    let db =
+        subcoin_db::ReadOnlyDb::open(&path).map_err(|e| ServiceError::Application(e.into()))?;
+
+    config.database = DatabaseSource::Custom {
+        db: Arc::new(db),
+        require_create_flag: false,
+    };
+
+    Ok(())
+}
+
 type PartialComponents = sc_service::PartialComponents<
     FullClient,
     FullBackend,
@@ -477,7 +630,10 @@ type PartialComponents = sc_service::PartialComponents<
 >;
 
 /// Creates a partial node, for the chain ops commands.
-pub fn new_partial(config: &Configuration) -> Result<PartialComponents, ServiceError> {
+pub fn new_partial(
+    config: &Configuration,
+    executor_kind: ExecutorKind,
+) -> Result<PartialComponents, ServiceError> {
     let telemetry = config
         .telemetry_endpoints
         .clone()
@@ -489,7 +645,7 @@ pub fn new_partial(config: &Configuration) -> Result<PartialComponents, ServiceE
         })
         .transpose()?;
 
-    let executor = sc_service::new_native_or_wasm_executor(config);
+    let executor = RuntimeExecutor::new(config, executor_kind);
 
     let (client, backend, keystore_container, task_manager) =
         sc_service::new_full_parts::<Block, RuntimeApi, _>(
@@ -517,7 +673,7 @@ pub fn new_partial(config: &Configuration) -> Result<PartialComponents, ServiceE
     );
 
     let import_queue = BasicQueue::new(
-        SubstrateImportQueueVerifier,
+        SubstrateImportQueueVerifier::new(client.clone()),
         Box::new(client.clone()),
         None,
         &task_manager.spawn_essential_handle(),
@@ -539,21 +695,48 @@ pub fn new_partial(config: &Configuration) -> Result<PartialComponents, ServiceE
 /// Verifier used by the Substrate import queue.
 ///
 /// Verifies the blocks received from the Substrate networking.
-pub struct SubstrateImportQueueVerifier;
+pub struct SubstrateImportQueueVerifier<Client> {
+    client: Arc<Client>,
+}
+
+impl<Client> SubstrateImportQueueVerifier<Client> {
+    /// Constructs a new instance of [`SubstrateImportQueueVerifier`].
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+}
 
 #[async_trait::async_trait]
-impl<Block: BlockT> Verifier<Block> for SubstrateImportQueueVerifier {
+impl<Block, Client> Verifier<Block> for SubstrateImportQueueVerifier<Client>
+where
+    Block: BlockT,
+    Client: ProvideRuntimeApi<Block> + Send + Sync,
+    Client::Api: BlockBuilderApi<Block>,
+{
     async fn verify(
         &self,
         mut block_import_params: BlockImportParams<Block>,
     ) -> Result<BlockImportParams<Block>, String> {
         // TODO: Verify header.
 
+        // Unlike the blocks built from the Bitcoin blocks locally, the extrinsics come from
+        // the peer.
+        if let Some(extrinsics) = &block_import_params.body {
+            let block = Block::new(block_import_params.header.clone(), extrinsics.clone());
+            sc_consensus_nakamoto::BitcoinBlockBuilder::<Block, TransactionAdapter>::check_inherents(
+                &*self.client,
+                block,
+            )
+            .await
+            .map_err(|err| err.to_string())?;
+        }
+
         block_import_params.fork_choice = Some(sc_consensus::ForkChoiceStrategy::LongestChain);
 
         let bitcoin_block_hash =
-            subcoin_primitives::extract_bitcoin_block_hash::<Block>(&block_import_params.header)
-                .map_err(|err| format!("Failed to extract bitcoin block hash: {err:?}"))?;
+            subcoin_primitives::extract_bitcoin_header::<Block>(&block_import_params.header)
+                .map_err(|err| format!("Failed to extract bitcoin header: {err:?}"))?
+                .block_hash();
 
         let substrate_block_hash = block_import_params.header.hash();
 
@@ -1,6 +1,7 @@
 use bitcoin::consensus::{Decodable, Encodable};
 use bitcoin::Transaction;
 use sp_core::{Decode, Encode};
+use sp_inherents::InherentDataProvider;
 use sp_runtime::traits::{Block as BlockT, Extrinsic};
 use subcoin_primitives::BitcoinTransactionAdapter;
 
@@ -16,6 +17,10 @@ use subcoin_primitives::BitcoinTransactionAdapter;
 /// choose to pull in the pallet-bitcoin dependency directly for saving the cost of
 /// calling a runtime api.
 ///
+/// The extrinsics are the same as the inherents created by the runtime from the Bitcoin block
+/// inherent data of `pallet_bitcoin::inherent::InherentDataProvider`, which is checked when
+/// the block is imported.
+///
 /// Using a trait also allows not to introduce the subcoin_runtime and pallet_bitcoin
 /// deps when the adapter is needed in other crates, making the compilation faster.
 pub struct TransactionAdapter;
@@ -53,4 +58,73 @@ impl<Block: BlockT> BitcoinTransactionAdapter<Block> for TransactionAdapter {
         )
         .expect("Internally construct extrinsic must not fail; qed")
     }
+
+    fn inherent_data_provider(block: &bitcoin::Block) -> Box<dyn InherentDataProvider> {
+        Box::new(pallet_bitcoin::inherent::InherentDataProvider::new(block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use sc_consensus_nakamoto::BitcoinBlockBuilder;
+    use sp_core::hexdisplay::HexDisplay;
+    use sp_runtime::traits::Header as HeaderT;
+
+    type Block = subcoin_runtime::interface::OpaqueBlock;
+    type Builder = BitcoinBlockBuilder<Block, TransactionAdapter>;
+
+    fn hex(data: &[u8]) -> String {
+        HexDisplay::from(&data).to_string()
+    }
+
+    #[test]
+    fn test_bitcoin_block_builder_golden_vectors() {
+        let genesis_block = bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin);
+
+        let block = Builder::build(&genesis_block, 0, Default::default(), Default::default());
+
+        assert_eq!(block.extrinsics.len(), 1);
+        assert_eq!(
+            hex(&block.extrinsics[0].encode()),
+            "45030401003103\
+             01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff\
+             4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72\
+             206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff\
+             0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f\
+             61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000"
+        );
+        assert_eq!(
+            hex(block.header.extrinsics_root.as_bytes()),
+            "3fdf3944f27fe52044b7f60a2e4e76f69284f95f0033f8d132dc588aa4e41dcc"
+        );
+        assert_eq!(
+            hex(&block.header.digest.encode()),
+            "0806686173688\
+             06fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000\
+             066865647241010100000000000000000000000000000000000000000000000000000000000000000000\
+             003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d\
+             1dac2b7c"
+        );
+        assert_eq!(
+            hex(block.header.hash().as_bytes()),
+            "13577baabebd87eeb8a45a3fa5c03588a65ef1fb8e40508eff093a51f0947f19"
+        );
+    }
+
+    #[test]
+    fn test_bitcoin_block_builder_preserves_transaction_order() {
+        let mut block = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let mut tx = block.txdata[0].clone();
+        tx.lock_time = LockTime::from_consensus(1);
+        block.txdata.push(tx);
+
+        let transactions = Builder::extrinsics(&block)
+            .iter()
+            .map(<TransactionAdapter as BitcoinTransactionAdapter<Block>>::extrinsic_to_bitcoin_transaction)
+            .collect::<Vec<_>>();
+
+        assert_eq!(transactions, block.txdata);
+    }
 }
@@ -0,0 +1,636 @@
+//! Snapshot artifacts, for distributing the snapshots out of band (torrents, CDNs, etc).
+//!
+//! An artifact is a directory of zstd-compressed files, each packing a range of consecutive
+//! [`SnapshotChunk`]s and named after the blake3 hash of its content, along with the
+//! [`ArtifactManifest`] in [`MANIFEST_FILE`]. The manifest can be signed with an ed25519 key
+//! so that the artifacts mirrored by third parties can be attributed to a known node.
+//!
+//! The Bitcoin headers leading to the snapshot block are packed in a [`HeadersFile`], which
+//! authenticates the entire header chain once the snapshot block hash is trusted.
+
+use crate::chunk::{SnapshotChunk, TOTAL_CHUNKS};
+use crate::manifest::SnapshotManifest;
+use crate::store::read_chunk;
+use crate::Error;
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use codec::{Decode, Encode, Input, Output};
+use sc_client_api::{Backend, StorageProvider};
+use sp_blockchain::HeaderBackend;
+use sp_core::{ed25519, Pair};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_runtime::SaturatedConversion;
+use std::path::Path;
+use subcoin_primitives::{extract_bitcoin_block_hash, extract_bitcoin_header};
+
+/// Name of the manifest file in the artifact directory.
+pub const MANIFEST_FILE: &str = "manifest.scale";
+
+/// Uncompressed size of a file in bytes, beyond which the following chunks go to the next file.
+const TARGET_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Default zstd compression level.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Size of a consensus-encoded Bitcoin header.
+const BITCOIN_HEADER_SIZE: usize = 80;
+
+fn file_name(hash: &[u8; 32]) -> String {
+    format!("{}.zst", hex::encode(hash))
+}
+
+/// Compressed file of the artifact.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct ArtifactFile {
+    /// blake3 hash of the compressed file.
+    pub hash: [u8; 32],
+    /// Size of the compressed file in bytes.
+    pub size: u64,
+    /// Index of the first chunk in the file.
+    pub first_chunk: u32,
+    /// Number of the chunks in the file.
+    pub chunks: u32,
+}
+
+impl ArtifactFile {
+    /// Returns the name of the file in the artifact directory.
+    pub fn name(&self) -> String {
+        file_name(&self.hash)
+    }
+}
+
+/// Compressed file of the consensus-encoded Bitcoin headers from height 1 to the snapshot block.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct HeadersFile {
+    /// blake3 hash of the compressed file.
+    pub hash: [u8; 32],
+    /// Size of the compressed file in bytes.
+    pub size: u64,
+}
+
+impl HeadersFile {
+    /// Returns the name of the file in the artifact directory.
+    pub fn name(&self) -> String {
+        file_name(&self.hash)
+    }
+}
+
+/// Signature of the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct ArtifactSignature {
+    pub public: ed25519::Public,
+    pub signature: ed25519::Signature,
+}
+
+/// Manifest of a snapshot artifact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactManifest {
+    /// Bitcoin block hash of the snapshot block.
+    pub block_hash: BlockHash,
+    /// Height of the snapshot block.
+    pub height: u32,
+    /// State root of the snapshot block.
+    pub state_root: [u8; 32],
+    /// Encoded Substrate header of the snapshot block.
+    pub header: Vec<u8>,
+    /// Root of the [`SnapshotManifest`], identical to the one announced over the network for
+    /// the same snapshot.
+    pub snapshot_root: [u8; 32],
+    /// Files of the artifact, ordered by the chunk index.
+    pub files: Vec<ArtifactFile>,
+    /// File of the Bitcoin headers leading to the snapshot block.
+    pub headers: HeadersFile,
+    /// Signature over the rest of the manifest.
+    pub signature: Option<ArtifactSignature>,
+}
+
+impl ArtifactManifest {
+    fn encode_unsigned_to<T: Output + ?Sized>(&self, dest: &mut T) {
+        self.block_hash.to_byte_array().encode_to(dest);
+        self.height.encode_to(dest);
+        self.state_root.encode_to(dest);
+        self.header.encode_to(dest);
+        self.snapshot_root.encode_to(dest);
+        self.files.encode_to(dest);
+        self.headers.encode_to(dest);
+    }
+
+    fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        self.encode_unsigned_to(&mut payload);
+        payload
+    }
+
+    /// Signs the manifest with `pair`, replacing the existing signature.
+    pub fn sign(&mut self, pair: &ed25519::Pair) {
+        self.signature.replace(ArtifactSignature {
+            public: pair.public(),
+            signature: pair.sign(&self.signing_payload()),
+        });
+    }
+
+    /// Returns the signer of the manifest, `None` if the manifest is unsigned.
+    ///
+    /// Returns an error if the signature is invalid.
+    pub fn signer(&self) -> Result<Option<ed25519::Public>, Error> {
+        let Some(ArtifactSignature { public, signature }) = &self.signature else {
+            return Ok(None);
+        };
+
+        if ed25519::Pair::verify(signature, self.signing_payload(), public) {
+            Ok(Some(*public))
+        } else {
+            Err(Error::InvalidArtifact(
+                "invalid manifest signature".to_string(),
+            ))
+        }
+    }
+
+    /// Returns the blake3 hash of the encoded manifest, identifying the artifact.
+    pub fn id(&self) -> [u8; 32] {
+        *blake3::hash(&self.encode()).as_bytes()
+    }
+}
+
+impl Encode for ArtifactManifest {
+    fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+        self.encode_unsigned_to(dest);
+        self.signature.encode_to(dest);
+    }
+}
+
+impl Decode for ArtifactManifest {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        Ok(Self {
+            block_hash: BlockHash::from_byte_array(Decode::decode(input)?),
+            height: Decode::decode(input)?,
+            state_root: Decode::decode(input)?,
+            header: Decode::decode(input)?,
+            snapshot_root: Decode::decode(input)?,
+            files: Decode::decode(input)?,
+            headers: Decode::decode(input)?,
+            signature: Decode::decode(input)?,
+        })
+    }
+}
+
+/// Compresses `data` to a file in `dir`, returning the hash and the size of the file.
+fn write_compressed(dir: &Path, data: &[u8]) -> Result<([u8; 32], u64), Error> {
+    let compressed = zstd::encode_all(data, COMPRESSION_LEVEL)?;
+    let hash = *blake3::hash(&compressed).as_bytes();
+
+    std::fs::write(dir.join(file_name(&hash)), &compressed)?;
+
+    Ok((hash, compressed.len() as u64))
+}
+
+/// Decompresses the content of a file, checking it against the hash and the size in the
+/// manifest.
+fn decompress(compressed: &[u8], hash: &[u8; 32], size: u64) -> Result<Vec<u8>, Error> {
+    if compressed.len() as u64 != size || blake3::hash(compressed).as_bytes() != hash {
+        return Err(Error::InvalidArtifact(format!(
+            "{}: content does not match the manifest",
+            file_name(hash)
+        )));
+    }
+
+    Ok(zstd::decode_all(compressed)?)
+}
+
+/// Writes the concatenated encoded chunks to a compressed file in `dir`.
+fn write_file(
+    dir: &Path,
+    encoded_chunks: &[u8],
+    first_chunk: u32,
+    chunks: u32,
+) -> Result<ArtifactFile, Error> {
+    let (hash, size) = write_compressed(dir, encoded_chunks)?;
+
+    Ok(ArtifactFile {
+        hash,
+        size,
+        first_chunk,
+        chunks,
+    })
+}
+
+/// Writes the headers to a compressed file in `dir`.
+fn write_headers(dir: &Path, headers: &[BitcoinHeader]) -> Result<HeadersFile, Error> {
+    let encoded_headers = headers
+        .iter()
+        .flat_map(bitcoin::consensus::serialize)
+        .collect::<Vec<_>>();
+
+    let (hash, size) = write_compressed(dir, &encoded_headers)?;
+
+    Ok(HeadersFile { hash, size })
+}
+
+/// Writes the artifact of the snapshot block to `dir`.
+///
+/// `headers` must be the headers from height 1 to the snapshot block, `chunks` must yield all
+/// the chunks of the snapshot in order.
+#[allow(clippy::too_many_arguments)]
+fn write_artifact(
+    dir: &Path,
+    block_hash: BlockHash,
+    height: u32,
+    state_root: [u8; 32],
+    header: Vec<u8>,
+    headers: &[BitcoinHeader],
+    chunks: impl Iterator<Item = Result<SnapshotChunk, Error>>,
+    signer: Option<&ed25519::Pair>,
+) -> Result<ArtifactManifest, Error> {
+    std::fs::create_dir_all(dir)?;
+
+    let mut files = Vec::new();
+    let mut chunk_hashes = Vec::with_capacity(TOTAL_CHUNKS as usize);
+
+    let mut buffer = Vec::new();
+    let mut first_chunk = 0;
+
+    for chunk in chunks {
+        let chunk = chunk?;
+        chunk_hashes.push(chunk.hash());
+        chunk.encode_to(&mut buffer);
+
+        if buffer.len() >= TARGET_FILE_SIZE {
+            files.push(write_file(
+                dir,
+                &buffer,
+                first_chunk,
+                chunk.index + 1 - first_chunk,
+            )?);
+            buffer.clear();
+            first_chunk = chunk.index + 1;
+        }
+    }
+
+    let total_chunks = chunk_hashes.len() as u32;
+    if total_chunks > first_chunk {
+        files.push(write_file(
+            dir,
+            &buffer,
+            first_chunk,
+            total_chunks - first_chunk,
+        )?);
+    }
+
+    let headers = write_headers(dir, headers)?;
+
+    let snapshot_root = SnapshotManifest {
+        block_hash,
+        height,
+        header: header.clone(),
+        chunk_hashes,
+    }
+    .root();
+
+    let mut manifest = ArtifactManifest {
+        block_hash,
+        height,
+        state_root,
+        header,
+        snapshot_root,
+        files,
+        headers,
+        signature: None,
+    };
+
+    if let Some(pair) = signer {
+        manifest.sign(pair);
+    }
+
+    std::fs::write(dir.join(MANIFEST_FILE), manifest.encode())?;
+
+    Ok(manifest)
+}
+
+/// Creates the artifact of the snapshot at `height` in `dir`, optionally signed by `signer`.
+///
+/// This reads the entire state at the block, the state must not be pruned.
+pub fn create_artifact<Block, BE, Client>(
+    client: &Client,
+    coins_prefix: &[u8; 32],
+    height: u32,
+    dir: &Path,
+    signer: Option<&ed25519::Pair>,
+) -> Result<ArtifactManifest, Error>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block> + StorageProvider<Block, BE>,
+{
+    let substrate_block_hash = client
+        .hash(height.into())?
+        .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("#{height}")))?;
+    let header = client
+        .header(substrate_block_hash)?
+        .ok_or_else(|| sp_blockchain::Error::MissingHeader(format!("#{height}")))?;
+    let block_hash = extract_bitcoin_block_hash::<Block>(&header)
+        .map_err(|err| Error::InvalidHeader(format!("{err:?}")))?;
+
+    let mut state_root = [0u8; 32];
+    state_root.copy_from_slice(header.state_root().as_ref());
+
+    let headers = (1..=height)
+        .map(|number| {
+            let header = client
+                .hash(number.into())?
+                .and_then(|hash| client.header(hash).transpose())
+                .transpose()?
+                .ok_or_else(|| sp_blockchain::Error::MissingHeader(format!("#{number}")))?;
+            extract_bitcoin_header::<Block>(&header)
+                .map_err(|err| Error::InvalidHeader(format!("{err:?}")))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    write_artifact(
+        dir,
+        block_hash,
+        height,
+        state_root,
+        header.encode(),
+        &headers,
+        (0..TOTAL_CHUNKS)
+            .map(|index| read_chunk(client, substrate_block_hash, coins_prefix, index)),
+        signer,
+    )
+}
+
+/// Reads the chunks in a file of the artifact, checking the file against the manifest.
+pub fn read_artifact_file(dir: &Path, file: &ArtifactFile) -> Result<Vec<SnapshotChunk>, Error> {
+    decode_artifact_file(file, &std::fs::read(dir.join(file.name()))?)
+}
+
+/// Decodes the chunks in the compressed content of a file, checking it against the manifest.
+pub fn decode_artifact_file(
+    file: &ArtifactFile,
+    compressed: &[u8],
+) -> Result<Vec<SnapshotChunk>, Error> {
+    let encoded_chunks = decompress(compressed, &file.hash, file.size)?;
+
+    let mut input = encoded_chunks.as_slice();
+    let mut chunks = Vec::with_capacity(file.chunks as usize);
+    while !input.is_empty() {
+        chunks.push(SnapshotChunk::decode(&mut input)?);
+    }
+
+    let expected_indices = file.first_chunk..file.first_chunk.saturating_add(file.chunks);
+    if !chunks.iter().map(|chunk| chunk.index).eq(expected_indices) {
+        return Err(Error::InvalidArtifact(format!(
+            "{}: unexpected chunks",
+            file.name()
+        )));
+    }
+
+    Ok(chunks)
+}
+
+/// Decodes the headers in the compressed content of the headers file.
+///
+/// The headers are checked to be linked one by one up to the snapshot block, the parent of the
+/// first header is left to the caller to be checked against the genesis block.
+pub fn decode_headers(
+    manifest: &ArtifactManifest,
+    compressed: &[u8],
+) -> Result<Vec<BitcoinHeader>, Error> {
+    let invalid_headers = |reason: &str| Error::InvalidArtifact(format!("headers: {reason}"));
+
+    let encoded_headers = decompress(compressed, &manifest.headers.hash, manifest.headers.size)?;
+
+    if encoded_headers.len() != manifest.height as usize * BITCOIN_HEADER_SIZE {
+        return Err(invalid_headers("unexpected number of headers"));
+    }
+
+    let headers = encoded_headers
+        .chunks_exact(BITCOIN_HEADER_SIZE)
+        .map(bitcoin::consensus::deserialize::<BitcoinHeader>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid_headers(&err.to_string()))?;
+
+    let mut tip = None;
+    for header in &headers {
+        if tip.is_some_and(|tip| tip != header.prev_blockhash) {
+            return Err(invalid_headers("headers are not linked"));
+        }
+        tip.replace(header.block_hash());
+    }
+
+    if tip != Some(manifest.block_hash) {
+        return Err(invalid_headers("headers do not lead to the snapshot block"));
+    }
+
+    Ok(headers)
+}
+
+/// Hashes of the chunks in the files of an artifact, checked as the files are added in order.
+pub(crate) struct ChunkHashes<'a> {
+    manifest: &'a ArtifactManifest,
+    coins_prefix: &'a [u8; 32],
+    chunk_hashes: Vec<[u8; 32]>,
+}
+
+impl<'a> ChunkHashes<'a> {
+    pub(crate) fn new(manifest: &'a ArtifactManifest, coins_prefix: &'a [u8; 32]) -> Self {
+        Self {
+            manifest,
+            coins_prefix,
+            chunk_hashes: Vec::with_capacity(TOTAL_CHUNKS as usize),
+        }
+    }
+
+    /// Adds the chunks decoded from the next file.
+    pub(crate) fn add_file(
+        &mut self,
+        file: &ArtifactFile,
+        chunks: &[SnapshotChunk],
+    ) -> Result<(), Error> {
+        if file.first_chunk != self.chunk_hashes.len() as u32 {
+            return Err(Error::MissingChunk(self.chunk_hashes.len() as u32));
+        }
+
+        for chunk in chunks {
+            if !chunk.has_valid_keys(self.coins_prefix) {
+                return Err(Error::InvalidChunk(chunk.index));
+            }
+            self.chunk_hashes.push(chunk.hash());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the [`SnapshotManifest`] once the chunks match the snapshot root.
+    pub(crate) fn finish(self) -> Result<SnapshotManifest, Error> {
+        if self.chunk_hashes.len() != TOTAL_CHUNKS as usize {
+            return Err(Error::MissingChunk(self.chunk_hashes.len() as u32));
+        }
+
+        let snapshot_manifest = SnapshotManifest {
+            block_hash: self.manifest.block_hash,
+            height: self.manifest.height,
+            header: self.manifest.header.clone(),
+            chunk_hashes: self.chunk_hashes,
+        };
+
+        if snapshot_manifest.root() != self.manifest.snapshot_root {
+            return Err(Error::InvalidArtifact(
+                "chunks do not match the snapshot root".to_string(),
+            ));
+        }
+
+        Ok(snapshot_manifest)
+    }
+}
+
+/// Verifies the files of the artifact against the manifest.
+fn verify_files(
+    dir: &Path,
+    manifest: &ArtifactManifest,
+    coins_prefix: &[u8; 32],
+) -> Result<(), Error> {
+    decode_headers(manifest, &std::fs::read(dir.join(manifest.headers.name()))?)?;
+
+    let mut chunk_hashes = ChunkHashes::new(manifest, coins_prefix);
+
+    for file in &manifest.files {
+        chunk_hashes.add_file(file, &read_artifact_file(dir, file)?)?;
+    }
+
+    chunk_hashes.finish()?;
+
+    Ok(())
+}
+
+/// Verifies the artifact in `dir` offline, returning the verified manifest.
+///
+/// The signature, the header, the header chain and all the chunks are checked against the
+/// manifest. The state root is only checked against the chunks when the snapshot is imported.
+pub fn verify_artifact<Block: BlockT>(
+    dir: &Path,
+    coins_prefix: &[u8; 32],
+) -> Result<ArtifactManifest, Error> {
+    let manifest =
+        ArtifactManifest::decode(&mut std::fs::read(dir.join(MANIFEST_FILE))?.as_slice())?;
+
+    manifest.signer()?;
+
+    let header = Block::Header::decode(&mut manifest.header.as_slice())?;
+    let block_hash = extract_bitcoin_header::<Block>(&header)
+        .map_err(|err| Error::InvalidHeader(format!("{err:?}")))?
+        .block_hash();
+
+    if block_hash != manifest.block_hash
+        || (*header.number()).saturated_into::<u32>() != manifest.height
+        || header.state_root().as_ref() != manifest.state_root.as_slice()
+    {
+        return Err(Error::InvalidHeader(
+            "header does not match the manifest".to_string(),
+        ));
+    }
+
+    verify_files(dir, &manifest, coins_prefix)?;
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::COINS_CHUNKS;
+
+    fn test_chunks(coins_prefix: &[u8; 32]) -> impl Iterator<Item = Result<SnapshotChunk, Error>> {
+        let coins_prefix = *coins_prefix;
+        (0..TOTAL_CHUNKS).map(move |index| {
+            let entries = match crate::chunk::chunk_prefix(&coins_prefix, index) {
+                // A coin in every 4096 chunks.
+                Some(mut key) if index % 4096 == 0 => {
+                    key.push(index as u8);
+                    vec![(key, vec![index as u8; 40])]
+                }
+                Some(_) => Vec::new(),
+                None => vec![(b":code".to_vec(), vec![1, 2, 3])],
+            };
+            Ok(SnapshotChunk { index, entries })
+        })
+    }
+
+    fn test_headers(height: u32) -> Vec<BitcoinHeader> {
+        let mut prev_blockhash =
+            bitcoin::constants::genesis_block(bitcoin::Network::Regtest).block_hash();
+        (1..=height)
+            .map(|time| {
+                let header = BitcoinHeader {
+                    version: bitcoin::block::Version::ONE,
+                    prev_blockhash,
+                    merkle_root: bitcoin::TxMerkleNode::all_zeros(),
+                    time,
+                    bits: bitcoin::CompactTarget::from_consensus(0x207fffff),
+                    nonce: 0,
+                };
+                prev_blockhash = header.block_hash();
+                header
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_artifact() {
+        let coins_prefix = [7u8; 32];
+        let tmp = tempfile::tempdir().unwrap();
+        let pair = ed25519::Pair::from_seed(&[1u8; 32]);
+        let headers = test_headers(2016);
+
+        let manifest = write_artifact(
+            tmp.path(),
+            headers.last().unwrap().block_hash(),
+            2016,
+            [2u8; 32],
+            vec![0u8; 100],
+            &headers,
+            test_chunks(&coins_prefix),
+            Some(&pair),
+        )
+        .unwrap();
+
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(manifest.files[0].chunks, TOTAL_CHUNKS);
+        assert_eq!(manifest.signer().unwrap(), Some(pair.public()));
+        verify_files(tmp.path(), &manifest, &coins_prefix).unwrap();
+
+        let compressed_headers = std::fs::read(tmp.path().join(manifest.headers.name())).unwrap();
+        assert_eq!(
+            decode_headers(&manifest, &compressed_headers).unwrap(),
+            headers
+        );
+
+        // Headers leading to another block.
+        let mut forked = manifest.clone();
+        forked.block_hash = headers[2014].block_hash();
+        assert!(decode_headers(&forked, &compressed_headers).is_err());
+
+        let encoded = std::fs::read(tmp.path().join(MANIFEST_FILE)).unwrap();
+        assert_eq!(
+            ArtifactManifest::decode(&mut encoded.as_slice()).unwrap(),
+            manifest
+        );
+
+        // The signature is bound to the content of the manifest.
+        let mut tampered = manifest.clone();
+        tampered.height += 1;
+        assert!(tampered.signer().is_err());
+
+        // Missing chunks.
+        let mut incomplete = manifest.clone();
+        incomplete.files[0].chunks = COINS_CHUNKS;
+        assert!(verify_files(tmp.path(), &incomplete, &coins_prefix).is_err());
+
+        // Corrupted file.
+        let path = tmp.path().join(manifest.files[0].name());
+        let mut content = std::fs::read(&path).unwrap();
+        *content.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, content).unwrap();
+        assert!(verify_files(tmp.path(), &manifest, &coins_prefix).is_err());
+    }
+}
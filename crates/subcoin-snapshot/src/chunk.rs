@@ -0,0 +1,191 @@
+use crate::Error;
+use bitcoin::hashes::{sha256d, Hash};
+use codec::{Decode, DecodeAll, Encode};
+use std::io::Read;
+
+/// Number of the chunks of Coins, one for each value of the first two bytes of txid.
+pub const COINS_CHUNKS: u32 = 1 << 16;
+
+/// Total number of the chunks of a snapshot, the last one contains the state other than Coins.
+pub const TOTAL_CHUNKS: u32 = COINS_CHUNKS + 1;
+
+/// Default zstd compression level of the chunks transferred to the peers.
+pub const DEFAULT_CHUNK_COMPRESSION_LEVEL: i32 = 3;
+
+/// Returns the key prefix of the chunk at `index`.
+///
+/// Returns `None` for the last chunk, which is not keyed by a prefix.
+pub fn chunk_prefix(coins_prefix: &[u8; 32], index: u32) -> Option<Vec<u8>> {
+    if index >= COINS_CHUNKS {
+        return None;
+    }
+    let mut prefix = coins_prefix.to_vec();
+    prefix.extend_from_slice(&(index as u16).to_be_bytes());
+    Some(prefix)
+}
+
+/// A chunk of the state, the storage entries are ordered by key.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SnapshotChunk {
+    /// Index of the chunk.
+    pub index: u32,
+    /// Storage entries in this chunk.
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl SnapshotChunk {
+    /// Compresses the chunk at the zstd compression `level`.
+    pub fn compress(&self, level: i32) -> Result<CompressedChunk, Error> {
+        let data = zstd::encode_all(self.encode().as_slice(), level)?;
+        Ok(CompressedChunk {
+            index: self.index,
+            checksum: *blake3::hash(&data).as_bytes(),
+            data,
+        })
+    }
+
+    /// Returns the hash of the chunk committed in the manifest.
+    pub fn hash(&self) -> [u8; 32] {
+        sha256d::Hash::hash(&self.encode()).to_byte_array()
+    }
+
+    /// Returns `true` if the keys are ascending and all belong to this chunk.
+    pub fn has_valid_keys(&self, coins_prefix: &[u8; 32]) -> bool {
+        let ascending = self.entries.windows(2).all(|pair| pair[0].0 < pair[1].0);
+
+        if !ascending {
+            return false;
+        }
+
+        match chunk_prefix(coins_prefix, self.index) {
+            Some(prefix) => self.entries.iter().all(|(key, _)| key.starts_with(&prefix)),
+            None => {
+                self.index == COINS_CHUNKS
+                    && self
+                        .entries
+                        .iter()
+                        .all(|(key, _)| !key.starts_with(coins_prefix))
+            }
+        }
+    }
+}
+
+/// A [`SnapshotChunk`] compressed with zstd for the transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct CompressedChunk {
+    /// Index of the chunk.
+    pub index: u32,
+    /// blake3 hash of `data`.
+    pub checksum: [u8; 32],
+    /// Compressed encoding of the chunk.
+    pub data: Vec<u8>,
+}
+
+impl CompressedChunk {
+    /// Decompresses the chunk, rejecting it once the decompressed size exceeds `max_size`.
+    ///
+    /// The checksum is verified before decompressing anything.
+    pub fn decompress(&self, max_size: usize) -> Result<SnapshotChunk, Error> {
+        if blake3::hash(&self.data).as_bytes() != &self.checksum {
+            return Err(Error::ChunkChecksumMismatch(self.index));
+        }
+
+        let mut encoded = Vec::new();
+        zstd::stream::read::Decoder::with_buffer(self.data.as_slice())?
+            .take((max_size as u64).saturating_add(1))
+            .read_to_end(&mut encoded)?;
+
+        if encoded.len() > max_size {
+            return Err(Error::ChunkTooLarge(self.index));
+        }
+
+        let chunk = SnapshotChunk::decode_all(&mut encoded.as_slice())?;
+
+        if chunk.index != self.index {
+            return Err(Error::InvalidChunk(self.index));
+        }
+
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_keys() {
+        let coins_prefix = [7u8; 32];
+
+        let prefix = chunk_prefix(&coins_prefix, 0x0102).unwrap();
+        assert_eq!(&prefix[32..], &[1, 2]);
+        assert_eq!(chunk_prefix(&coins_prefix, COINS_CHUNKS), None);
+
+        let coin_key = |first: u8| {
+            let mut key = prefix.clone();
+            key.push(first);
+            key
+        };
+
+        let chunk = SnapshotChunk {
+            index: 0x0102,
+            entries: vec![(coin_key(0), vec![]), (coin_key(1), vec![])],
+        };
+        assert!(chunk.has_valid_keys(&coins_prefix));
+
+        // Not ordered.
+        let mut unordered = chunk.clone();
+        unordered.entries.reverse();
+        assert!(!unordered.has_valid_keys(&coins_prefix));
+
+        // Coins in the wrong chunk.
+        let wrong_index = SnapshotChunk {
+            index: 0x0103,
+            ..chunk.clone()
+        };
+        assert!(!wrong_index.has_valid_keys(&coins_prefix));
+
+        let base_chunk = SnapshotChunk {
+            index: COINS_CHUNKS,
+            entries: vec![(b":code".to_vec(), vec![])],
+        };
+        assert!(base_chunk.has_valid_keys(&coins_prefix));
+
+        let base_chunk_with_coins = SnapshotChunk {
+            index: COINS_CHUNKS,
+            entries: chunk.entries,
+        };
+        assert!(!base_chunk_with_coins.has_valid_keys(&coins_prefix));
+    }
+
+    #[test]
+    fn test_compressed_chunk() {
+        let chunk = SnapshotChunk {
+            index: 9,
+            entries: vec![(vec![1u8; 36], vec![0u8; 1000]); 10],
+        };
+
+        let compressed = chunk.compress(DEFAULT_CHUNK_COMPRESSION_LEVEL).unwrap();
+        assert!(compressed.data.len() < chunk.encode().len());
+        assert_eq!(compressed.decompress(chunk.encode().len()).unwrap(), chunk);
+
+        assert!(matches!(
+            compressed.decompress(chunk.encode().len() - 1),
+            Err(Error::ChunkTooLarge(9))
+        ));
+
+        let mut corrupted = compressed.clone();
+        corrupted.data[0] ^= 1;
+        assert!(matches!(
+            corrupted.decompress(usize::MAX),
+            Err(Error::ChunkChecksumMismatch(9))
+        ));
+
+        let mut wrong_index = compressed;
+        wrong_index.index = 10;
+        assert!(matches!(
+            wrong_index.decompress(usize::MAX),
+            Err(Error::InvalidChunk(10))
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+use crate::store::{ClientSnapshotStore, SnapshotStore};
+use futures::StreamExt;
+use sc_client_api::{AuxStore, Backend, BlockchainEvents, StorageProvider};
+use sc_consensus::BlockImport;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_runtime::SaturatedConversion;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Snapshots are generated at the heights of every retarget period.
+pub const SNAPSHOT_INTERVAL: u32 = 2016;
+
+/// Generates the snapshot at the latest finalized multiple of [`SNAPSHOT_INTERVAL`].
+///
+/// Generating a snapshot blocks the task for a while, the future needs to be spawned as
+/// a blocking task in the background.
+pub async fn snapshot_generator<Block, Client, BE, BI>(
+    client: Arc<Client>,
+    store: Arc<ClientSnapshotStore<Block, Client, BE, BI>>,
+    is_major_syncing: Arc<AtomicBool>,
+) where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore
+        + BlockchainEvents<Block>
+        + Send
+        + Sync,
+    BI: BlockImport<Block> + Send + Sync,
+{
+    let mut finality_notification_stream = client.finality_notification_stream();
+
+    while let Some(notification) = finality_notification_stream.next().await {
+        // The snapshots generated during the major sync are outdated soon.
+        if is_major_syncing.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        let finalized_number: u32 = (*notification.header.number()).saturated_into();
+        let height = finalized_number - finalized_number % SNAPSHOT_INTERVAL;
+
+        if height == 0
+            || store
+                .latest_snapshot()
+                .is_some_and(|latest| latest.height >= height)
+        {
+            continue;
+        }
+
+        let now = Instant::now();
+
+        match store.generate_snapshot(height) {
+            Ok(info) => {
+                tracing::info!(
+                    "📸 Generated snapshot at #{height},{} in {}s",
+                    info.block_hash,
+                    now.elapsed().as_secs()
+                );
+            }
+            Err(err) => {
+                tracing::error!(?err, "Failed to generate snapshot at #{height}");
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! Bootstrapping from a snapshot artifact served over HTTP(S).
+//!
+//! The artifact directory created by [`crate::create_artifact`] can be served as is by any
+//! static file server or CDN. The snapshot block of the downloaded manifest must be either the
+//! block trusted by the user or one of the embedded checkpoints, everything else is checked
+//! against it before the snapshot is imported.
+
+use crate::artifact::{decode_artifact_file, decode_headers, ArtifactManifest, ChunkHashes};
+use crate::checkpoint::checkpoint;
+use crate::store::{DownloadedSnapshot, SnapshotStore, ANCESTOR_HEADERS};
+use crate::{Error, MANIFEST_FILE};
+use bitcoin::{BlockHash, Network};
+use codec::Decode;
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// Timeout of downloading a file.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Maximum size of the manifest in bytes.
+const MAX_MANIFEST_SIZE: usize = 16 * 1024 * 1024;
+
+/// Maximum number of the redirects followed by a request.
+const MAX_REDIRECTS: usize = 5;
+
+/// URL of an artifact directory, `http(s)://host[:port][/path]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotUrl {
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for SnapshotUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid_url = || Error::InvalidUrl(s.to_string());
+
+        let (tls, rest) = if let Some(rest) = s.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = s.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(invalid_url());
+        };
+
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid_url())?),
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+
+        if host.is_empty() {
+            return Err(invalid_url());
+        }
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+impl fmt::Display for SnapshotUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        write!(f, "{scheme}://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+impl SnapshotUrl {
+    /// Returns the URL of the file in the directory.
+    fn join(&self, file: &str) -> Self {
+        Self {
+            path: format!("{}/{file}", self.path),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the URL a response of this URL redirects to.
+    fn redirect(&self, location: &str) -> Result<Self, Error> {
+        if location.starts_with('/') {
+            Ok(Self {
+                path: location.to_string(),
+                ..self.clone()
+            })
+        } else {
+            location.parse()
+        }
+    }
+
+    fn host_header(&self) -> String {
+        match (self.tls, self.port) {
+            (true, 443) | (false, 80) => self.host.clone(),
+            _ => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
+
+async fn send_request<S>(stream: S, url: &SnapshotUrl) -> Result<Response<Incoming>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            tracing::debug!("Connection to the snapshot server failed: {err:?}");
+        }
+    });
+
+    let path = if url.path.is_empty() { "/" } else { &url.path };
+
+    let request = Request::get(path)
+        .header(hyper::header::HOST, url.host_header())
+        .body(Empty::<Bytes>::new())
+        .map_err(|err| Error::Http(err.to_string()))?;
+
+    Ok(sender.send_request(request).await?)
+}
+
+fn tls_connector() -> Result<tokio_rustls::TlsConnector, Error> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|err| Error::Http(err.to_string()))?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+
+    Ok(tokio_rustls::TlsConnector::from(Arc::new(config)))
+}
+
+/// Downloads the file at `url`, up to `limit` bytes.
+async fn fetch(url: &SnapshotUrl, limit: usize) -> Result<Bytes, Error> {
+    let mut url = url.clone();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+
+        let response = if url.tls {
+            let server_name = ServerName::try_from(url.host.clone())
+                .map_err(|_| Error::InvalidUrl(url.to_string()))?;
+            let stream = tls_connector()?.connect(server_name, stream).await?;
+            send_request(stream, &url).await?
+        } else {
+            send_request(stream, &url).await?
+        };
+
+        match response.status() {
+            StatusCode::OK => {
+                return Limited::new(response.into_body(), limit)
+                    .collect()
+                    .await
+                    .map(|body| body.to_bytes())
+                    .map_err(|err| Error::Http(format!("{url}: {err}")));
+            }
+            status if status.is_redirection() => {
+                let location = response
+                    .headers()
+                    .get(hyper::header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .ok_or_else(|| Error::Http(format!("{url}: {status} without location")))?;
+                url = url.redirect(location)?;
+            }
+            status => return Err(Error::Http(format!("{url}: {status}"))),
+        }
+    }
+
+    Err(Error::Http(format!("{url}: too many redirects")))
+}
+
+async fn fetch_with_timeout(url: &SnapshotUrl, limit: usize) -> Result<Bytes, Error> {
+    tokio::time::timeout(REQUEST_TIMEOUT, fetch(url, limit))
+        .await
+        .map_err(|_| Error::Http(format!("{url}: timed out")))?
+}
+
+/// Bootstraps the state from the snapshot artifact at an HTTP(S) URL.
+#[derive(Debug, Clone)]
+pub struct HttpBootstrap {
+    url: SnapshotUrl,
+    network: Network,
+    trusted_block_hash: Option<BlockHash>,
+    coins_prefix: [u8; 32],
+}
+
+impl HttpBootstrap {
+    /// Constructs a new instance of [`HttpBootstrap`].
+    ///
+    /// Only the snapshots at the embedded checkpoints are accepted unless a trusted block is
+    /// specified with [`Self::with_trusted_block_hash`].
+    pub fn new(url: SnapshotUrl, network: Network, coins_prefix: [u8; 32]) -> Self {
+        Self {
+            url,
+            network,
+            trusted_block_hash: None,
+            coins_prefix,
+        }
+    }
+
+    /// Accepts the snapshot at the given block instead of the embedded checkpoints.
+    pub fn with_trusted_block_hash(mut self, trusted_block_hash: BlockHash) -> Self {
+        self.trusted_block_hash.replace(trusted_block_hash);
+        self
+    }
+
+    /// Downloads and verifies the artifact, then imports the snapshot into `store`.
+    pub async fn run(self, store: &dyn SnapshotStore) -> Result<ArtifactManifest, Error> {
+        let Self {
+            url,
+            network,
+            trusted_block_hash,
+            coins_prefix,
+        } = self;
+
+        let manifest = ArtifactManifest::decode(
+            &mut fetch_with_timeout(&url.join(MANIFEST_FILE), MAX_MANIFEST_SIZE)
+                .await?
+                .as_ref(),
+        )?;
+
+        let trusted = match trusted_block_hash {
+            Some(trusted_block_hash) => trusted_block_hash == manifest.block_hash,
+            None => checkpoint(network, manifest.height) == Some(manifest.block_hash),
+        };
+
+        if !trusted {
+            return Err(Error::UntrustedSnapshot(manifest.block_hash));
+        }
+
+        if let Some(signer) = manifest.signer()? {
+            tracing::info!("Snapshot artifact is signed by 0x{}", hex::encode(signer));
+        }
+
+        tracing::info!(
+            "Downloading snapshot at #{},{} from {url}",
+            manifest.height,
+            manifest.block_hash
+        );
+
+        let genesis = bitcoin::constants::genesis_block(network).header;
+
+        let headers = decode_headers(
+            &manifest,
+            &fetch_with_timeout(
+                &url.join(&manifest.headers.name()),
+                manifest.headers.size as usize,
+            )
+            .await?,
+        )?;
+
+        if headers.first().map(|header| header.prev_blockhash) != Some(genesis.block_hash()) {
+            return Err(Error::InvalidArtifact(
+                "headers: not started from the genesis block".to_string(),
+            ));
+        }
+
+        let chain_work = headers.iter().fold(genesis.work(), |chain_work, header| {
+            chain_work + header.work()
+        });
+
+        let mut header_chain = Vec::with_capacity(headers.len() + 1);
+        header_chain.push(genesis);
+        header_chain.extend(headers);
+        let tip = header_chain.len() - 1;
+        let ancestors = header_chain[tip.saturating_sub(ANCESTOR_HEADERS)..tip].to_vec();
+
+        let mut chunk_hashes = ChunkHashes::new(&manifest, &coins_prefix);
+
+        for (index, file) in manifest.files.iter().enumerate() {
+            let compressed =
+                fetch_with_timeout(&url.join(&file.name()), file.size as usize).await?;
+            let file_chunks = decode_artifact_file(file, &compressed)?;
+            chunk_hashes.add_file(file, &file_chunks)?;
+            for chunk in &file_chunks {
+                store.stage_chunk(chunk)?;
+            }
+
+            tracing::info!(
+                "Downloaded snapshot file {}/{}",
+                index + 1,
+                manifest.files.len()
+            );
+        }
+
+        store.import_snapshot(DownloadedSnapshot {
+            manifest: chunk_hashes.finish()?,
+            chain_work,
+            ancestors,
+        })?;
+
+        tracing::info!(
+            "Imported snapshot at #{},{}",
+            manifest.height,
+            manifest.block_hash
+        );
+
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_url() {
+        let url = "https://example.com/snapshots/2016/"
+            .parse::<SnapshotUrl>()
+            .unwrap();
+        assert_eq!(url.to_string(), "https://example.com:443/snapshots/2016");
+        assert_eq!(url.host_header(), "example.com");
+        assert_eq!(
+            url.join(MANIFEST_FILE).path,
+            "/snapshots/2016/manifest.scale"
+        );
+        assert_eq!(
+            url.redirect("/mirror/manifest.scale").unwrap().to_string(),
+            "https://example.com:443/mirror/manifest.scale"
+        );
+
+        let url = "http://127.0.0.1:8080".parse::<SnapshotUrl>().unwrap();
+        assert_eq!(url.host_header(), "127.0.0.1:8080");
+        assert_eq!(url.join(MANIFEST_FILE).path, "/manifest.scale");
+
+        assert!("ftp://example.com".parse::<SnapshotUrl>().is_err());
+        assert!("http://:8080".parse::<SnapshotUrl>().is_err());
+        assert!("http://example.com:port".parse::<SnapshotUrl>().is_err());
+    }
+}
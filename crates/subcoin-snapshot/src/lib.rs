@@ -0,0 +1,94 @@
+//! # UTXO Set Snapshot
+//!
+//! This crate provides the snapshots of the state at finalized blocks, which allow the new
+//! subcoin nodes to skip downloading and executing the entire history of the Bitcoin chain.
+//!
+//! ## Chunks
+//!
+//! The state is split into [`TOTAL_CHUNKS`] deterministic chunks: the Coins map is split into
+//! [`COINS_CHUNKS`] chunks by the first two bytes of the txid, the rest of the state (runtime
+//! code, system storage, etc) is in the last one. The chunks are transferred independently, each
+//! of them can be verified against the [`SnapshotManifest`] on arrival. The chunks are sent to
+//! the peers supporting it as [`CompressedChunk`]s.
+//!
+//! ## Manifest
+//!
+//! The manifest of a snapshot contains the Substrate header of the snapshot block and the hashes
+//! of all chunks, the merkle root of them is announced by the providers and committed in the aux
+//! storage of the provider. The imported state is checked against the state root in the header
+//! in the end.
+//!
+//! ## Trust
+//!
+//! The state itself can not be verified without executing the history, the requester only
+//! trusts the snapshot announced by enough providers, and the chain of headers leading to the
+//! snapshot block is downloaded and verified from the Bitcoin network.
+//!
+//! ## Artifacts
+//!
+//! A snapshot can also be exported to an [`ArtifactManifest`] and a set of compressed files
+//! for the out of band distribution, see [`create_artifact`] and [`verify_artifact`]. A node can
+//! bootstrap from an artifact served over HTTP(S) with [`HttpBootstrap`].
+
+mod artifact;
+mod checkpoint;
+mod chunk;
+mod generator;
+mod http;
+mod manifest;
+mod store;
+
+pub use self::artifact::{
+    create_artifact, decode_artifact_file, decode_headers, read_artifact_file, verify_artifact,
+    ArtifactFile, ArtifactManifest, ArtifactSignature, HeadersFile, MANIFEST_FILE,
+};
+pub use self::checkpoint::checkpoint;
+pub use self::chunk::{
+    chunk_prefix, CompressedChunk, SnapshotChunk, COINS_CHUNKS, DEFAULT_CHUNK_COMPRESSION_LEVEL,
+    TOTAL_CHUNKS,
+};
+pub use self::generator::{snapshot_generator, SNAPSHOT_INTERVAL};
+pub use self::http::{HttpBootstrap, SnapshotUrl};
+pub use self::manifest::{SnapshotInfo, SnapshotManifest};
+pub use self::store::{ClientSnapshotStore, DownloadedSnapshot, SnapshotStore, ANCESTOR_HEADERS};
+
+use bitcoin::BlockHash;
+
+/// Snapshot error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Snapshot at block {0} not found")]
+    SnapshotNotFound(BlockHash),
+    #[error("Chunk {0} does not match the manifest")]
+    InvalidChunk(u32),
+    #[error("Checksum of compressed chunk {0} mismatch")]
+    ChunkChecksumMismatch(u32),
+    #[error("Chunk {0} exceeds the maximum decompressed size")]
+    ChunkTooLarge(u32),
+    #[error("Chunk {0} is missing")]
+    MissingChunk(u32),
+    #[error("Invalid snapshot header: {0}")]
+    InvalidHeader(String),
+    #[error("Snapshot import failed: {0}")]
+    ImportFailed(String),
+    #[error("Invalid snapshot artifact: {0}")]
+    InvalidArtifact(String),
+    #[error("Invalid snapshot url {0}, expected http(s)://host[:port][/path]")]
+    InvalidUrl(String),
+    #[error("Snapshot block {0} is neither the trusted block nor a checkpoint")]
+    UntrustedSnapshot(BlockHash),
+    #[error("HTTP request failed: {0}")]
+    Http(String),
+    #[error(transparent)]
+    Blockchain(#[from] sp_blockchain::Error),
+    #[error(transparent)]
+    Database(#[from] subcoin_db::Error),
+    #[error(transparent)]
+    Consensus(#[from] sp_consensus::Error),
+    #[error(transparent)]
+    Codec(#[from] codec::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+}
@@ -0,0 +1,153 @@
+use crate::chunk::{SnapshotChunk, TOTAL_CHUNKS};
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::BlockHash;
+use codec::{Decode, Encode, Input, Output};
+
+/// Snapshot announced by the providers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SnapshotInfo {
+    /// Bitcoin block hash of the snapshot block.
+    pub block_hash: BlockHash,
+    /// Height of the snapshot block.
+    pub height: u32,
+    /// Merkle root of the manifest.
+    pub root: [u8; 32],
+}
+
+impl Encode for SnapshotInfo {
+    fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+        self.block_hash.to_byte_array().encode_to(dest);
+        self.height.encode_to(dest);
+        self.root.encode_to(dest);
+    }
+}
+
+impl Decode for SnapshotInfo {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        Ok(Self {
+            block_hash: BlockHash::from_byte_array(Decode::decode(input)?),
+            height: Decode::decode(input)?,
+            root: Decode::decode(input)?,
+        })
+    }
+}
+
+/// Manifest of a snapshot, committing to the snapshot block and all the chunks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    /// Bitcoin block hash of the snapshot block.
+    pub block_hash: BlockHash,
+    /// Height of the snapshot block.
+    pub height: u32,
+    /// Encoded Substrate header of the snapshot block.
+    pub header: Vec<u8>,
+    /// Hashes of the chunks, ordered by the chunk index.
+    pub chunk_hashes: Vec<[u8; 32]>,
+}
+
+impl Encode for SnapshotManifest {
+    fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+        self.block_hash.to_byte_array().encode_to(dest);
+        self.height.encode_to(dest);
+        self.header.encode_to(dest);
+        self.chunk_hashes.encode_to(dest);
+    }
+}
+
+impl Decode for SnapshotManifest {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        Ok(Self {
+            block_hash: BlockHash::from_byte_array(Decode::decode(input)?),
+            height: Decode::decode(input)?,
+            header: Decode::decode(input)?,
+            chunk_hashes: Decode::decode(input)?,
+        })
+    }
+}
+
+impl SnapshotManifest {
+    /// Returns the merkle root of the header and the chunk hashes.
+    pub fn root(&self) -> [u8; 32] {
+        let leaves = std::iter::once(sha256d::Hash::hash(&self.header)).chain(
+            self.chunk_hashes
+                .iter()
+                .copied()
+                .map(sha256d::Hash::from_byte_array),
+        );
+
+        bitcoin::merkle_tree::calculate_root(leaves)
+            .expect("Leaves must be non-empty as the header is always included; qed")
+            .to_byte_array()
+    }
+
+    /// Returns the info of this snapshot.
+    pub fn info(&self) -> SnapshotInfo {
+        SnapshotInfo {
+            block_hash: self.block_hash,
+            height: self.height,
+            root: self.root(),
+        }
+    }
+
+    /// Returns `true` if the manifest has the hashes of all chunks.
+    pub fn is_complete(&self) -> bool {
+        self.chunk_hashes.len() == TOTAL_CHUNKS as usize
+    }
+
+    /// Returns `true` if the chunk matches the hash in the manifest.
+    pub fn verify_chunk(&self, chunk: &SnapshotChunk) -> bool {
+        self.chunk_hashes
+            .get(chunk.index as usize)
+            .is_some_and(|hash| *hash == chunk.hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let chunks = (0..3)
+            .map(|index| SnapshotChunk {
+                index,
+                entries: vec![(vec![index as u8], vec![1, 2, 3])],
+            })
+            .collect::<Vec<_>>();
+
+        let manifest = SnapshotManifest {
+            block_hash: BlockHash::from_byte_array([1u8; 32]),
+            height: 2016,
+            header: vec![0u8; 100],
+            chunk_hashes: chunks.iter().map(SnapshotChunk::hash).collect(),
+        };
+
+        assert_eq!(
+            SnapshotManifest::decode(&mut manifest.encode().as_slice()).unwrap(),
+            manifest
+        );
+
+        let info = manifest.info();
+        assert_eq!(
+            SnapshotInfo::decode(&mut info.encode().as_slice()).unwrap(),
+            info
+        );
+
+        assert!(chunks.iter().all(|chunk| manifest.verify_chunk(chunk)));
+
+        let mut tampered = chunks[1].clone();
+        tampered.entries[0].1.push(4);
+        assert!(!manifest.verify_chunk(&tampered));
+
+        // Chunk out of range.
+        let unknown = SnapshotChunk {
+            index: 3,
+            entries: Vec::new(),
+        };
+        assert!(!manifest.verify_chunk(&unknown));
+
+        let mut other = manifest.clone();
+        other.chunk_hashes[2] = tampered.hash();
+        assert_ne!(other.root(), manifest.root());
+    }
+}
@@ -0,0 +1,420 @@
+use crate::chunk::{chunk_prefix, SnapshotChunk, TOTAL_CHUNKS};
+use crate::manifest::{SnapshotInfo, SnapshotManifest};
+use crate::Error;
+use bitcoin::blockdata::block::Header as BitcoinHeader;
+use bitcoin::{BlockHash, Work};
+use codec::{Decode, Encode};
+use parking_lot::Mutex;
+use sc_client_api::{AuxStore, Backend, StorageKey, StorageProvider};
+use sc_consensus::{
+    BlockImport, BlockImportParams, ForkChoiceStrategy, ImportResult, ImportedState, StateAction,
+    StorageChanges,
+};
+use sc_consensus_nakamoto::insert_bitcoin_block_hash_mapping;
+use sp_blockchain::HeaderBackend;
+use sp_consensus::BlockOrigin;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_runtime::SaturatedConversion;
+use sp_state_machine::{KeyValueStates, KeyValueStorageLevel};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use subcoin_db::{columns, SubcoinDb, Transaction};
+use subcoin_primitives::{
+    chain_work_key, extract_bitcoin_block_hash, extract_bitcoin_header, header_key, CoinStorageKey,
+    HeaderEntry,
+};
+
+/// Number of the headers prior to the snapshot block imported along with the snapshot.
+///
+/// Enough for verifying the headers after the snapshot block, which require the headers of
+/// the last retarget period at most.
+pub const ANCESTOR_HEADERS: usize = 2016;
+
+/// Key of the latest snapshot in [`columns::SNAPSHOTS`].
+const LATEST_SNAPSHOT_KEY: &[u8] = b"snapshot_latest";
+
+/// Prefix of the keys of the snapshot manifests in [`columns::SNAPSHOTS`].
+const MANIFEST_PREFIX: &[u8] = b"snapshot_manifest";
+
+/// Prefix of the keys of the downloaded chunks in [`columns::SNAPSHOTS`], staged until the
+/// snapshot is imported.
+const STAGED_CHUNK_PREFIX: &[u8] = b"snapshot_staged_chunk";
+
+fn manifest_key(block_hash: BlockHash) -> Vec<u8> {
+    let mut key = MANIFEST_PREFIX.to_vec();
+    key.extend_from_slice(block_hash.as_ref());
+    key
+}
+
+fn staged_chunk_key(index: u32) -> Vec<u8> {
+    let mut key = STAGED_CHUNK_PREFIX.to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Removes the staged chunks when dropped.
+struct RemoveStagedChunks<'a>(&'a SubcoinDb);
+
+impl Drop for RemoveStagedChunks<'_> {
+    fn drop(&mut self) {
+        let mut transaction = Transaction::new();
+        for index in 0..TOTAL_CHUNKS {
+            transaction.remove(columns::SNAPSHOTS, &staged_chunk_key(index));
+        }
+        if let Err(err) = self.0.commit(transaction) {
+            tracing::warn!(?err, "Failed to remove the staged snapshot chunks");
+        }
+    }
+}
+
+/// Snapshot downloaded from the network, ready for import.
+///
+/// The chunks have been staged with [`SnapshotStore::stage_chunk`].
+#[derive(Debug)]
+pub struct DownloadedSnapshot {
+    pub manifest: SnapshotManifest,
+    /// Cumulative chainwork of the snapshot block.
+    pub chain_work: Work,
+    /// Headers prior to the snapshot block, up to [`ANCESTOR_HEADERS`].
+    pub ancestors: Vec<BitcoinHeader>,
+}
+
+/// Interface of the snapshots used by the network.
+pub trait SnapshotStore: Send + Sync {
+    /// Returns the latest snapshot available for serving.
+    fn latest_snapshot(&self) -> Option<SnapshotInfo>;
+
+    /// Returns the manifest of the snapshot at given block.
+    fn manifest(&self, block_hash: BlockHash) -> Option<SnapshotManifest>;
+
+    /// Returns the chunk of the snapshot at given block.
+    fn chunk(&self, block_hash: BlockHash, index: u32) -> Result<SnapshotChunk, Error>;
+
+    /// Writes a verified chunk of the snapshot being downloaded to the disk, where it stays
+    /// until the snapshot is imported.
+    fn stage_chunk(&self, chunk: &SnapshotChunk) -> Result<(), Error>;
+
+    /// Verifies the downloaded snapshot and imports the snapshot block with the state.
+    ///
+    /// The staged chunks are read one by one into the imported state and removed afterwards,
+    /// whether the import succeeds or not.
+    fn import_snapshot(&self, snapshot: DownloadedSnapshot) -> Result<(), Error>;
+}
+
+/// [`SnapshotStore`] backed by the client.
+///
+/// The chunks are read from the state at the snapshot block on demand, the state of the
+/// snapshot block must not be pruned.
+pub struct ClientSnapshotStore<Block, Client, BE, BI> {
+    client: Arc<Client>,
+    db: SubcoinDb,
+    block_import: Mutex<BI>,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    _phantom: PhantomData<(Block, BE)>,
+}
+
+impl<Block, Client, BE, BI> ClientSnapshotStore<Block, Client, BE, BI>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block> + StorageProvider<Block, BE> + AuxStore + Send + Sync,
+    BI: BlockImport<Block> + Send + Sync,
+{
+    /// Constructs a new instance of [`ClientSnapshotStore`].
+    ///
+    /// The snapshot stored in the aux storage by the older versions is migrated to `db`.
+    pub fn new(
+        client: Arc<Client>,
+        db: SubcoinDb,
+        block_import: BI,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+    ) -> Result<Self, Error> {
+        if let Some(info) = client.get_aux(LATEST_SNAPSHOT_KEY)? {
+            let info = SnapshotInfo::decode(&mut info.as_slice())?;
+            db.migrate_from_aux(
+                client.as_ref(),
+                columns::SNAPSHOTS,
+                &[
+                    manifest_key(info.block_hash).as_slice(),
+                    LATEST_SNAPSHOT_KEY,
+                ],
+            )?;
+        }
+
+        Ok(Self {
+            client,
+            db,
+            block_import: Mutex::new(block_import),
+            coin_storage_key,
+            _phantom: Default::default(),
+        })
+    }
+
+    /// Generates the snapshot at given height, replacing the previous one.
+    ///
+    /// This reads the entire state at the block, which takes a while on mainnet.
+    pub fn generate_snapshot(&self, height: u32) -> Result<SnapshotInfo, Error> {
+        let substrate_block_hash = self
+            .client
+            .hash(height.into())?
+            .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("#{height}")))?;
+        let header = self
+            .client
+            .header(substrate_block_hash)?
+            .ok_or_else(|| sp_blockchain::Error::MissingHeader(format!("#{height}")))?;
+        let block_hash = extract_bitcoin_block_hash::<Block>(&header)
+            .map_err(|err| Error::InvalidHeader(format!("{err:?}")))?;
+
+        let chunk_hashes = (0..TOTAL_CHUNKS)
+            .map(|index| {
+                self.read_chunk(substrate_block_hash, index)
+                    .map(|chunk| chunk.hash())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let manifest = SnapshotManifest {
+            block_hash,
+            height,
+            header: header.encode(),
+            chunk_hashes,
+        };
+        let info = manifest.info();
+
+        // Only the latest snapshot is kept.
+        let mut transaction = Transaction::new();
+        if let Some(previous) = self
+            .latest_snapshot()
+            .filter(|previous| previous.block_hash != block_hash)
+        {
+            transaction.remove(columns::SNAPSHOTS, &manifest_key(previous.block_hash));
+        }
+        transaction.set(
+            columns::SNAPSHOTS,
+            &manifest_key(block_hash),
+            &manifest.encode(),
+        );
+        transaction.set(columns::SNAPSHOTS, LATEST_SNAPSHOT_KEY, &info.encode());
+        self.db.commit(transaction)?;
+
+        Ok(info)
+    }
+
+    fn read_chunk(
+        &self,
+        substrate_block_hash: Block::Hash,
+        index: u32,
+    ) -> Result<SnapshotChunk, Error> {
+        read_chunk(
+            self.client.as_ref(),
+            substrate_block_hash,
+            &self.coin_storage_key.storage_prefix(),
+            index,
+        )
+    }
+}
+
+/// Reads the chunk at `index` from the state at the given block.
+pub(crate) fn read_chunk<Block, BE, Client>(
+    client: &Client,
+    substrate_block_hash: Block::Hash,
+    coins_prefix: &[u8; 32],
+    index: u32,
+) -> Result<SnapshotChunk, Error>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: StorageProvider<Block, BE>,
+{
+    let entries = match chunk_prefix(coins_prefix, index) {
+        Some(prefix) => client
+            .storage_pairs(substrate_block_hash, Some(&StorageKey(prefix)), None)?
+            .map(|(key, value)| (key.0, value.0))
+            .collect(),
+        None => {
+            // Everything before and after Coins.
+            let mut entries = client
+                .storage_pairs(substrate_block_hash, None, None)?
+                .take_while(|(key, _)| key.0.as_slice() < coins_prefix.as_slice())
+                .map(|(key, value)| (key.0, value.0))
+                .collect::<Vec<_>>();
+
+            let mut last_coin_key = coins_prefix.to_vec();
+            last_coin_key.extend([u8::MAX; 36]);
+
+            entries.extend(
+                client
+                    .storage_pairs(substrate_block_hash, None, Some(&StorageKey(last_coin_key)))?
+                    .filter(|(key, _)| !key.0.starts_with(coins_prefix))
+                    .map(|(key, value)| (key.0, value.0)),
+            );
+
+            entries
+        }
+    };
+
+    Ok(SnapshotChunk { index, entries })
+}
+
+impl<Block, Client, BE, BI> SnapshotStore for ClientSnapshotStore<Block, Client, BE, BI>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block> + StorageProvider<Block, BE> + AuxStore + Send + Sync,
+    BI: BlockImport<Block> + Send + Sync,
+{
+    fn latest_snapshot(&self) -> Option<SnapshotInfo> {
+        self.db
+            .get(columns::SNAPSHOTS, LATEST_SNAPSHOT_KEY)
+            .and_then(|info| SnapshotInfo::decode(&mut info.as_slice()).ok())
+    }
+
+    fn manifest(&self, block_hash: BlockHash) -> Option<SnapshotManifest> {
+        self.db
+            .get(columns::SNAPSHOTS, &manifest_key(block_hash))
+            .and_then(|manifest| SnapshotManifest::decode(&mut manifest.as_slice()).ok())
+    }
+
+    fn chunk(&self, block_hash: BlockHash, index: u32) -> Result<SnapshotChunk, Error> {
+        if index >= TOTAL_CHUNKS {
+            return Err(Error::MissingChunk(index));
+        }
+
+        let manifest = self
+            .manifest(block_hash)
+            .ok_or(Error::SnapshotNotFound(block_hash))?;
+        let substrate_block_hash = self
+            .client
+            .hash(manifest.height.into())?
+            .ok_or(Error::SnapshotNotFound(block_hash))?;
+
+        self.read_chunk(substrate_block_hash, index)
+    }
+
+    fn stage_chunk(&self, chunk: &SnapshotChunk) -> Result<(), Error> {
+        if chunk.index >= TOTAL_CHUNKS {
+            return Err(Error::InvalidChunk(chunk.index));
+        }
+
+        self.db.insert(
+            columns::SNAPSHOTS,
+            &staged_chunk_key(chunk.index),
+            &chunk.encode(),
+        )?;
+
+        Ok(())
+    }
+
+    fn import_snapshot(&self, snapshot: DownloadedSnapshot) -> Result<(), Error> {
+        let _remove_staged_chunks = RemoveStagedChunks(&self.db);
+
+        let DownloadedSnapshot {
+            manifest,
+            chain_work,
+            ancestors,
+        } = snapshot;
+
+        let header = Block::Header::decode(&mut manifest.header.as_slice())?;
+        let bitcoin_header = extract_bitcoin_header::<Block>(&header)
+            .map_err(|err| Error::InvalidHeader(format!("{err:?}")))?;
+
+        if bitcoin_header.block_hash() != manifest.block_hash
+            || (*header.number()).saturated_into::<u32>() != manifest.height
+        {
+            return Err(Error::InvalidHeader(
+                "header does not match the snapshot block".to_string(),
+            ));
+        }
+
+        if !manifest.is_complete() {
+            return Err(Error::MissingChunk(manifest.chunk_hashes.len() as u32));
+        }
+
+        let coins_prefix = self.coin_storage_key.storage_prefix();
+
+        // The imported state has to be passed to the client as a whole, only a single copy
+        // of the entries is held in memory by decoding the staged chunks one at a time.
+        let mut key_values = Vec::new();
+
+        for index in 0..TOTAL_CHUNKS {
+            let encoded = self
+                .db
+                .get(columns::SNAPSHOTS, &staged_chunk_key(index))
+                .ok_or(Error::MissingChunk(index))?;
+            let chunk = SnapshotChunk::decode(&mut encoded.as_slice())?;
+
+            if chunk.index != index
+                || !manifest.verify_chunk(&chunk)
+                || !chunk.has_valid_keys(&coins_prefix)
+            {
+                return Err(Error::InvalidChunk(index));
+            }
+            key_values.extend(chunk.entries);
+        }
+
+        let substrate_block_hash = header.hash();
+
+        // The state root is checked against the header by the client.
+        let mut block_import_params =
+            BlockImportParams::new(BlockOrigin::NetworkInitialSync, header);
+        block_import_params.finalized = true;
+        block_import_params.fork_choice = Some(ForkChoiceStrategy::Custom(true));
+        block_import_params.state_action =
+            StateAction::ApplyChanges(StorageChanges::Import(ImportedState {
+                block: substrate_block_hash,
+                state: KeyValueStates(vec![KeyValueStorageLevel {
+                    state_root: Vec::new(),
+                    parent_storage_keys: Vec::new(),
+                    key_values,
+                }]),
+            }));
+
+        insert_bitcoin_block_hash_mapping(
+            &mut block_import_params,
+            manifest.block_hash,
+            substrate_block_hash,
+        );
+        block_import_params.auxiliary.push((
+            chain_work_key(manifest.block_hash),
+            Some(chain_work.to_be_bytes().to_vec()),
+        ));
+        block_import_params
+            .auxiliary
+            .extend(ancestors.iter().map(|ancestor| {
+                (
+                    header_key(ancestor.block_hash()),
+                    Some(bitcoin::consensus::serialize(ancestor)),
+                )
+            }));
+
+        // The chainwork of the ancestors is derived backwards from the snapshot block.
+        let mut entry = HeaderEntry {
+            header: bitcoin_header,
+            height: manifest.height,
+            chain_work,
+        };
+        let mut header_entries = vec![entry];
+        for ancestor in ancestors.iter().rev() {
+            entry = HeaderEntry {
+                header: *ancestor,
+                height: entry.height.saturating_sub(1),
+                chain_work: entry.chain_work - entry.header.work(),
+            };
+            header_entries.push(entry);
+        }
+        block_import_params
+            .auxiliary
+            .extend(header_entries.iter().map(|entry| {
+                let (key, value) = entry.aux_item();
+                (key, Some(value))
+            }));
+
+        let import_result =
+            futures::executor::block_on(self.block_import.lock().import_block(block_import_params))
+                .map_err(|err| Error::ImportFailed(err.to_string()))?;
+
+        match import_result {
+            ImportResult::Imported(_) => Ok(()),
+            import_result => Err(Error::ImportFailed(format!("{import_result:?}"))),
+        }
+    }
+}
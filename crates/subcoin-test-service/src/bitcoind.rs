@@ -0,0 +1,132 @@
+//! Regtest bitcoind managed by the tests.
+//!
+//! The binaries are not bundled, the tests depending on bitcoind are expected to be skipped
+//! if [`Bitcoind::from_env`] returns `None`.
+
+use crate::network::free_local_addr;
+use bitcoin::BlockHash;
+use sc_service::BasePath;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// Environment variable of the path to the bitcoind binary.
+pub const BITCOIND_EXE_ENV: &str = "BITCOIND_EXE";
+
+/// Time to wait for the RPC of bitcoind to become available.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A bitcoind process in regtest mode, killed on drop.
+pub struct Bitcoind {
+    process: Child,
+    bitcoin_cli: PathBuf,
+    datadir: BasePath,
+    p2p_addr: SocketAddr,
+    rpc_port: u16,
+}
+
+impl Bitcoind {
+    /// Starts the bitcoind specified by [`BITCOIND_EXE_ENV`], `None` if the variable is not
+    /// set.
+    pub fn from_env() -> Option<io::Result<Self>> {
+        std::env::var_os(BITCOIND_EXE_ENV).map(|exe| Self::spawn(exe.as_ref()))
+    }
+
+    /// Starts a bitcoind with an empty chain, `bitcoin-cli` is expected to be in the same
+    /// directory as `bitcoind_exe`.
+    ///
+    /// Only the connections from the local nodes are accepted, no outbound connections are
+    /// made.
+    pub fn spawn(bitcoind_exe: &Path) -> io::Result<Self> {
+        let datadir = BasePath::new_temp_dir()?;
+        let p2p_addr = free_local_addr();
+        let rpc_port = free_local_addr().port();
+
+        let process = Command::new(bitcoind_exe)
+            .arg("-regtest")
+            .arg(format!("-datadir={}", datadir.path().display()))
+            .arg(format!("-bind={p2p_addr}"))
+            .arg(format!("-rpcport={rpc_port}"))
+            .args([
+                "-server=1",
+                "-listen=1",
+                "-connect=0",
+                "-fallbackfee=0.0001",
+            ])
+            .stdout(Stdio::null())
+            .spawn()?;
+
+        let bitcoind = Self {
+            process,
+            bitcoin_cli: bitcoind_exe.with_file_name("bitcoin-cli"),
+            datadir,
+            p2p_addr,
+            rpc_port,
+        };
+
+        let started_at = Instant::now();
+        while !bitcoind.cli(&["getblockchaininfo"])?.status.success() {
+            if started_at.elapsed() > STARTUP_TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "bitcoind RPC is not available",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        Ok(bitcoind)
+    }
+
+    /// Address bitcoind accepts the P2P connections on.
+    pub fn p2p_addr(&self) -> SocketAddr {
+        self.p2p_addr
+    }
+
+    /// Mines `count` blocks paying to an anyone-can-spend output, like the blocks mined by
+    /// [`crate::TestNode::mine_blocks`].
+    pub fn generate(&self, count: usize) -> io::Result<Vec<BlockHash>> {
+        let output = self.checked_cli(&["generatetodescriptor", &count.to_string(), "raw(51)"])?;
+        serde_json::from_slice(&output)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Returns the height of the best block.
+    pub fn block_count(&self) -> io::Result<u32> {
+        let output = self.checked_cli(&["getblockcount"])?;
+        String::from_utf8_lossy(&output)
+            .trim()
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Runs the `bitcoin-cli` command and returns its stdout, fails if the command does.
+    pub fn checked_cli(&self, args: &[&str]) -> io::Result<Vec<u8>> {
+        let output = self.cli(args)?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ))
+        }
+    }
+
+    fn cli(&self, args: &[&str]) -> io::Result<Output> {
+        Command::new(&self.bitcoin_cli)
+            .arg("-regtest")
+            .arg(format!("-datadir={}", self.datadir.path().display()))
+            .arg(format!("-rpcport={}", self.rpc_port))
+            .args(args)
+            .output()
+    }
+}
+
+impl Drop for Bitcoind {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
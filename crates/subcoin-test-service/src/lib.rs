@@ -1,3 +1,9 @@
+mod bitcoind;
+mod network;
+
+pub use self::bitcoind::{Bitcoind, BITCOIND_EXE_ENV};
+pub use self::network::{TestNetwork, TestNode};
+
 use bitcoin::consensus::Decodable;
 use bitcoin::hex::FromHex;
 use bitcoin::Block;
@@ -32,6 +38,15 @@ pub fn block_data() -> Vec<Block> {
 }
 
 pub fn test_configuration(tokio_handle: tokio::runtime::Handle) -> Configuration {
+    test_configuration_for(bitcoin::Network::Bitcoin, tokio_handle)
+}
+
+/// Returns the configuration of a test node following `network`, with the database in a new
+/// temporary directory.
+pub fn test_configuration_for(
+    network: bitcoin::Network,
+    tokio_handle: tokio::runtime::Handle,
+) -> Configuration {
     let base_path = BasePath::new_temp_dir()
         .expect("getting the base path of a temporary path doesn't fail; qed");
     let root = base_path.path().to_path_buf();
@@ -43,8 +58,7 @@ pub fn test_configuration(tokio_handle: tokio::runtime::Handle) -> Configuration
         None,
     );
 
-    let spec = subcoin_service::chain_spec::config(bitcoin::Network::Bitcoin)
-        .expect("Failed to create chain spec");
+    let spec = subcoin_service::chain_spec::config(network).expect("Failed to create chain spec");
 
     Configuration {
         impl_name: "subcoin-test-node".to_string(),
@@ -107,5 +121,7 @@ pub fn new_test_node(tokio_handle: tokio::runtime::Handle) -> Result<NodeCompone
         config: &config,
         no_hardware_benchmarks: true,
         storage_monitor: Default::default(),
+        in_memory_backend: Default::default(),
+        executor: Default::default(),
     })
 }
@@ -0,0 +1,373 @@
+//! In-process regtest network of subcoin nodes.
+//!
+//! Each node listens on its own local port and the nodes are connected to each other on
+//! demand, the blocks are mined locally without any external miner:
+//!
+//! ```ignore
+//! let mut network = TestNetwork::new(tokio::runtime::Handle::current());
+//! let alice = network.add_node();
+//! network.mine_blocks(alice, 10).await;
+//! let bob = network.add_node();
+//! network.connect(bob, alice);
+//! assert!(network.wait_for_sync(Duration::from_secs(30)).await);
+//! ```
+//!
+//! A node syncs with its peers when the connection is established. The blocks mined after
+//! that are not announced to the connected peers yet, mine the blocks before connecting the
+//! nodes.
+
+use crate::test_configuration_for;
+use bitcoin::blockdata::opcodes::all::OP_PUSHNUM_1;
+use bitcoin::hashes::Hash;
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::{
+    absolute, transaction, Amount, Block as BitcoinBlock, BlockHash, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxMerkleNode, TxOut, Witness,
+};
+use sc_consensus_nakamoto::{BlockVerification, BlockVerifier, ImportStatus};
+use sp_blockchain::HeaderBackend;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use subcoin_network::{SendTransactionResult, SyncStrategy};
+use subcoin_node::{SubcoinNode, SubcoinNodeBuilder};
+use subcoin_primitives::BackendExt;
+use subcoin_runtime::interface::OpaqueBlock as Block;
+use subcoin_service::FullBackend;
+
+/// Interval of polling the best block of the nodes.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A subcoin node in the [`TestNetwork`].
+pub struct TestNode {
+    /// Index of the node in the network, tagged in the coinbase of the mined blocks so that
+    /// the nodes mining on the same parent produce different blocks.
+    pub index: usize,
+    /// Address the node accepts the Bitcoin connections on.
+    pub listen_addr: SocketAddr,
+    pub node: SubcoinNode,
+}
+
+impl TestNode {
+    /// Returns the height and hash of the best block.
+    pub fn best_block(&self) -> (u32, BlockHash) {
+        let best_number = self.node.client.info().best_number;
+        let best_hash = BackendExt::<Block>::block_hash(&self.node.client, best_number)
+            .expect("Best block must exist; qed");
+        (best_number, best_hash)
+    }
+
+    /// Mines `count` blocks on top of the best block, including the unconfirmed transactions
+    /// known to the node.
+    ///
+    /// The coinbase outputs are anyone-can-spend so that they can be spent in the tests
+    /// without any key. Panics if any mined block fails to import.
+    pub async fn mine_blocks(&self, count: usize) -> Vec<BlockHash> {
+        let verifier = BlockVerifier::<_, _, FullBackend>::new(
+            self.node.client.clone(),
+            bitcoin::Network::Regtest,
+            BlockVerification::Full,
+            Arc::new(subcoin_service::CoinStorageKey),
+            true,
+        );
+
+        let mut mined = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let transactions = self.node.network_handle.transactions().await;
+
+            // The block is timestamped at the earliest time allowed to be deterministic.
+            let template = verifier
+                .create_block_template(transactions, 0)
+                .expect("Failed to create block template");
+
+            let mut txdata = vec![self.coinbase(
+                template.height,
+                template.coinbase_value,
+                template.default_witness_commitment,
+            )];
+            txdata.extend(template.transactions.into_iter().map(|tx| tx.transaction));
+
+            let mut block = BitcoinBlock {
+                header: bitcoin::block::Header {
+                    version: template.version,
+                    prev_blockhash: template.previous_block_hash,
+                    merkle_root: TxMerkleNode::all_zeros(),
+                    time: template.cur_time,
+                    bits: template.bits,
+                    nonce: 0,
+                },
+                txdata,
+            };
+            block.header.merkle_root = block
+                .compute_merkle_root()
+                .expect("Block contains the coinbase; qed");
+
+            while block.header.validate_pow(template.target).is_err() {
+                block.header.nonce += 1;
+            }
+
+            let block_hash = block.block_hash();
+
+            match self.node.local_block_import.import_block(block).await {
+                Ok(ImportStatus::Imported { .. }) => mined.push(block_hash),
+                result => panic!("Failed to import the mined block {block_hash}: {result:?}"),
+            }
+        }
+
+        mined
+    }
+
+    fn coinbase(
+        &self,
+        height: u32,
+        coinbase_value: u64,
+        witness_commitment: Option<ScriptBuf>,
+    ) -> Transaction {
+        // BIP34 height, always a data push even for the heights up to 16.
+        let mut height_bytes = height.to_le_bytes().to_vec();
+        while height_bytes.last() == Some(&0) {
+            height_bytes.pop();
+        }
+        if height_bytes.last().is_some_and(|byte| byte & 0x80 != 0) {
+            height_bytes.push(0);
+        }
+
+        let script_sig = Builder::new()
+            .push_slice(PushBytesBuf::try_from(height_bytes).expect("At most 5 bytes; qed"))
+            .push_slice(
+                PushBytesBuf::try_from((self.index as u32).to_le_bytes().to_vec())
+                    .expect("4 bytes; qed"),
+            )
+            .into_script();
+
+        let mut output = vec![TxOut {
+            value: Amount::from_sat(coinbase_value),
+            script_pubkey: Builder::new().push_opcode(OP_PUSHNUM_1).into_script(),
+        }];
+
+        let witness = match witness_commitment {
+            Some(script_pubkey) => {
+                output.push(TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey,
+                });
+                // The default witness reserved value.
+                Witness::from_slice(&[[0u8; 32]])
+            }
+            None => Witness::new(),
+        };
+
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig,
+                sequence: Sequence::MAX,
+                witness,
+            }],
+            output,
+        }
+    }
+
+    /// Submits the transaction to the node, which broadcasts it to the peers.
+    pub async fn submit_transaction(&self, transaction: Transaction) -> SendTransactionResult {
+        self.node.network_handle.send_transaction(transaction).await
+    }
+
+    /// Waits until the block is imported, returns `false` on timeout.
+    pub async fn wait_for_block(&self, block_hash: BlockHash, timeout: Duration) -> bool {
+        let client = self.node.client.clone();
+        poll_until(timeout, move || {
+            BackendExt::<Block>::block_number(&client, block_hash).is_some()
+        })
+        .await
+    }
+}
+
+/// Local regtest network of the subcoin nodes and optionally a [`crate::Bitcoind`].
+pub struct TestNetwork {
+    tokio_handle: tokio::runtime::Handle,
+    nodes: Vec<TestNode>,
+}
+
+impl TestNetwork {
+    /// Constructs an empty network running the nodes on the given runtime.
+    pub fn new(tokio_handle: tokio::runtime::Handle) -> Self {
+        Self {
+            tokio_handle,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Starts a new node not connected to any peer, returns its index.
+    pub fn add_node(&mut self) -> usize {
+        self.add_node_with(|builder| builder)
+    }
+
+    /// Starts a new node customized by `configure`, e.g., serving the snapshots.
+    pub fn add_node_with(
+        &mut self,
+        configure: impl FnOnce(SubcoinNodeBuilder) -> SubcoinNodeBuilder,
+    ) -> usize {
+        let index = self.nodes.len();
+        let listen_addr = free_local_addr();
+
+        let config = test_configuration_for(bitcoin::Network::Regtest, self.tokio_handle.clone());
+
+        let network_params = subcoin_network::Params {
+            network: bitcoin::Network::Regtest,
+            custom_network: None,
+            listen_on: listen_addr,
+            listen_transports: vec![subcoin_network::Transport::Tcp],
+            v2_transport: true,
+            port_mapping: false,
+            i2p_sam: None,
+            cjdns_reachable: false,
+            seednodes: Vec::new(),
+            seednode_only: false,
+            ipv4_only: true,
+            max_outbound_peers: 8,
+            max_block_relay_only_peers: 0,
+            max_inbound_peers: 8,
+            // No automatic outbound connections are made as no addresses are known on regtest.
+            connect: Vec::new(),
+            addnode: Vec::new(),
+            max_upload_target: None,
+            max_upload_rate: None,
+            max_download_rate: None,
+            max_peer_message_rate: None,
+            import_memory_budget: subcoin_network::DEFAULT_IMPORT_MEMORY_BUDGET,
+            sync_strategy: SyncStrategy::HeadersFirst,
+            snapshot: None,
+            db: None,
+            network_tip: None,
+            mempool_path: None,
+            full_rbf: true,
+            utxo_provider: None,
+            stop_at: None,
+            verification_threads: None,
+            sync_stall_timeout: None,
+            rotate_stalled_sync_peer: false,
+            blocks_only: false,
+        };
+
+        let builder = SubcoinNodeBuilder::new(config)
+            .with_bitcoin_network(network_params)
+            .with_persist_mempool(false)
+            .with_rpc(false)
+            .with_informant(false)
+            .with_hardware_benchmarks(false);
+
+        let node = configure(builder)
+            .build()
+            .expect("Failed to build the test node");
+
+        self.nodes.push(TestNode {
+            index,
+            listen_addr,
+            node,
+        });
+
+        index
+    }
+
+    /// Returns the node at `index`.
+    pub fn node(&self, index: usize) -> &TestNode {
+        &self.nodes[index]
+    }
+
+    /// Returns all the nodes.
+    pub fn nodes(&self) -> &[TestNode] {
+        &self.nodes
+    }
+
+    /// Connects the node `from` to the node `to`, which is reconnected on disconnection.
+    pub fn connect(&self, from: usize, to: usize) {
+        self.nodes[from]
+            .node
+            .network_handle
+            .add_node(self.nodes[to].listen_addr);
+    }
+
+    /// Connects the node to the bitcoind.
+    pub fn connect_bitcoind(&self, from: usize, bitcoind: &crate::Bitcoind) {
+        self.nodes[from]
+            .node
+            .network_handle
+            .add_node(bitcoind.p2p_addr());
+    }
+
+    /// Mines `count` blocks on the node, see [`TestNode::mine_blocks`].
+    pub async fn mine_blocks(&self, index: usize, count: usize) -> Vec<BlockHash> {
+        self.nodes[index].mine_blocks(count).await
+    }
+
+    /// Submits the transaction to the node, see [`TestNode::submit_transaction`].
+    pub async fn submit_transaction(
+        &self,
+        index: usize,
+        transaction: Transaction,
+    ) -> SendTransactionResult {
+        self.nodes[index].submit_transaction(transaction).await
+    }
+
+    /// Waits until all the nodes have the same best block, returns `false` on timeout.
+    pub async fn wait_for_sync(&self, timeout: Duration) -> bool {
+        poll_until(timeout, || {
+            let mut best_blocks = self.nodes.iter().map(TestNode::best_block);
+            match best_blocks.next() {
+                Some(first) => best_blocks.all(|best| best == first),
+                None => true,
+            }
+        })
+        .await
+    }
+
+    /// Waits until all the nodes have imported the block, returns `false` on timeout.
+    pub async fn wait_for_block(&self, block_hash: BlockHash, timeout: Duration) -> bool {
+        poll_until(timeout, || {
+            self.nodes.iter().all(|node| {
+                BackendExt::<Block>::block_number(&node.node.client, block_hash).is_some()
+            })
+        })
+        .await
+    }
+}
+
+/// Returns a local address with a port not in use.
+pub(crate) fn free_local_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to find a free local port")
+}
+
+async fn poll_until(timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    tokio::time::timeout(timeout, async {
+        while !condition() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    })
+    .await
+    .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_nodes_sync_mined_blocks() {
+        let mut network = TestNetwork::new(tokio::runtime::Handle::current());
+
+        let alice = network.add_node();
+        let mined = network.mine_blocks(alice, 5).await;
+        assert_eq!(network.node(alice).best_block(), (5, mined[4]));
+
+        let bob = network.add_node();
+        network.connect(bob, alice);
+        assert!(network.wait_for_sync(Duration::from_secs(60)).await);
+        assert_eq!(network.node(bob).best_block(), (5, mined[4]));
+    }
+}
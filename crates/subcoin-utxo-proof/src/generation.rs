@@ -0,0 +1,176 @@
+use crate::{coin_storage_key, UtxoProof, UtxoProofChunk, UtxoProofHeaders};
+use bitcoin::hashes::Hash;
+use bitcoin::OutPoint;
+use codec::Encode;
+use sc_client_api::{HeaderBackend, ProofProvider};
+use sp_core::Bytes;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, SaturatedConversion};
+use subcoin_primitives::extract_bitcoin_header;
+
+fn header_at<Block, Client>(client: &Client, number: u32) -> sp_blockchain::Result<Block::Header>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block>,
+{
+    let hash = client
+        .hash(number.into())?
+        .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("#{number}")))?;
+
+    client
+        .header(hash)?
+        .ok_or_else(|| sp_blockchain::Error::MissingHeader(hash.to_string()))
+}
+
+/// Returns the best chain block with `confirmations` and the Bitcoin headers on top of it.
+fn proof_headers<Block, Client>(
+    client: &Client,
+    confirmations: u32,
+) -> sp_blockchain::Result<(Block::Header, Vec<Bytes>)>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block>,
+{
+    let best_number: u32 = client.info().best_number.saturated_into();
+
+    let number = (best_number + 1)
+        .checked_sub(confirmations.max(1))
+        .ok_or_else(|| {
+            sp_blockchain::Error::Application(
+                format!("No block has {confirmations} confirmations at #{best_number}").into(),
+            )
+        })?;
+
+    let header = header_at::<Block, _>(client, number)?;
+
+    let descendants = (number + 1..=best_number)
+        .map(|number| {
+            let header = header_at::<Block, _>(client, number)?;
+            let bitcoin_header = extract_bitcoin_header::<Block>(&header).map_err(|err| {
+                sp_blockchain::Error::Application(
+                    format!("Invalid Bitcoin header at #{number}: {err:?}").into(),
+                )
+            })?;
+            Ok(bitcoin::consensus::serialize(&bitcoin_header).into())
+        })
+        .collect::<sp_blockchain::Result<Vec<_>>>()?;
+
+    Ok((header, descendants))
+}
+
+fn read_proof<Block, Client>(
+    client: &Client,
+    at: Block::Hash,
+    outpoints: &[OutPoint],
+) -> sp_blockchain::Result<Vec<Bytes>>
+where
+    Block: BlockT,
+    Client: ProofProvider<Block>,
+{
+    let keys = outpoints.iter().map(coin_storage_key).collect::<Vec<_>>();
+
+    let proof = client.read_proof(at, &mut keys.iter().map(Vec::as_slice))?;
+
+    Ok(proof.into_iter_nodes().map(Into::into).collect())
+}
+
+/// Generates the proof of `outpoints` at the best chain block with `confirmations`, the best
+/// block itself if `confirmations` is `1`.
+///
+/// The state of the block must not be pruned.
+pub fn generate_utxo_proof<Block, Client>(
+    client: &Client,
+    outpoints: Vec<OutPoint>,
+    confirmations: u32,
+) -> sp_blockchain::Result<UtxoProof>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + ProofProvider<Block>,
+{
+    let (header, descendants) = proof_headers::<Block, _>(client, confirmations)?;
+
+    let proof = read_proof::<Block, _>(client, header.hash(), &outpoints)?;
+
+    Ok(UtxoProof {
+        header: header.encode().into(),
+        descendants,
+        outpoints,
+        proof,
+    })
+}
+
+/// Same as [`generate_utxo_proof`], but the coins are proven lazily in chunks of at most
+/// `chunk_size` outputs, only one of which is held in memory at a time.
+///
+/// The outputs are sorted and deduplicated first so that each chunk covers an adjacent range
+/// of the trie, the trie nodes shared by the chunks are repeated in their proofs.
+pub fn generate_utxo_proof_chunks<Block, Client>(
+    client: &Client,
+    mut outpoints: Vec<OutPoint>,
+    confirmations: u32,
+    chunk_size: usize,
+) -> sp_blockchain::Result<(UtxoProofHeaders, UtxoProofChunks<'_, Block, Client>)>
+where
+    Block: BlockT,
+    Client: HeaderBackend<Block> + ProofProvider<Block>,
+{
+    let (header, descendants) = proof_headers::<Block, _>(client, confirmations)?;
+
+    outpoints.sort_unstable_by_key(|outpoint| {
+        (outpoint.txid.to_byte_array(), outpoint.vout.to_le_bytes())
+    });
+    outpoints.dedup();
+
+    let chunks = UtxoProofChunks {
+        client,
+        at: header.hash(),
+        outpoints: outpoints.into_iter(),
+        chunk_size: chunk_size.max(1),
+    };
+
+    let headers = UtxoProofHeaders {
+        header: header.encode().into(),
+        descendants,
+    };
+
+    Ok((headers, chunks))
+}
+
+/// Iterator of the [`UtxoProofChunk`]s created by [`generate_utxo_proof_chunks`].
+pub struct UtxoProofChunks<'a, Block: BlockT, Client> {
+    client: &'a Client,
+    at: Block::Hash,
+    outpoints: std::vec::IntoIter<OutPoint>,
+    chunk_size: usize,
+}
+
+impl<'a, Block: BlockT, Client> UtxoProofChunks<'a, Block, Client> {
+    /// Returns the number of the outputs not yet proven.
+    pub fn remaining(&self) -> usize {
+        self.outpoints.len()
+    }
+}
+
+impl<'a, Block, Client> Iterator for UtxoProofChunks<'a, Block, Client>
+where
+    Block: BlockT,
+    Client: ProofProvider<Block>,
+{
+    type Item = sp_blockchain::Result<UtxoProofChunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let outpoints = self
+            .outpoints
+            .by_ref()
+            .take(self.chunk_size)
+            .collect::<Vec<_>>();
+
+        if outpoints.is_empty() {
+            return None;
+        }
+
+        Some(
+            read_proof::<Block, _>(self.client, self.at, &outpoints)
+                .map(|proof| UtxoProofChunk { outpoints, proof }),
+        )
+    }
+}
@@ -0,0 +1,80 @@
+//! Trustless verification of the coins in the UTXO set of Subcoin.
+//!
+//! The state root of each Substrate block commits to the UTXO set and the Substrate header
+//! embeds the Bitcoin header. A [`UtxoProof`] carries the Substrate header, the Bitcoin headers
+//! built on top of it and the storage proof of the queried coins, [`verify_utxo_proof`] checks
+//! it against the proof of work of the headers and the state root without trusting any node.
+//!
+//! Proving thousands of coins at once, e.g., all the outputs of an address, is done in chunks
+//! with bounded memory by [`UtxoProofChunks`] instead, each [`UtxoProofChunk`] is verified
+//! against the shared [`UtxoProofHeaders`] by [`UtxoProofVerifier`].
+//!
+//! The difficulty transitions are not checked as that requires the whole header chain, the
+//! confidence comes from the accumulated work on top of the block and the trusted block
+//! instead, see [`VerifyOptions`].
+
+mod generation;
+mod verification;
+
+pub use generation::{generate_utxo_proof, generate_utxo_proof_chunks, UtxoProofChunks};
+pub use verification::{
+    verify_utxo_proof, verify_utxo_proof_chunks, UtxoProofVerifier, VerifiedUtxos, VerifyError,
+    VerifyOptions,
+};
+
+use bitcoin::hashes::Hash;
+use bitcoin::OutPoint;
+use serde::{Deserialize, Serialize};
+use sp_core::Bytes;
+
+/// Substrate header of Subcoin.
+pub type Header = sp_runtime::generic::Header<u32, sp_runtime::traits::BlakeTwo256>;
+
+/// Proof of the coins in the UTXO set at a block.
+///
+/// This is also the JSON file format of `subcoin verify-utxo`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UtxoProof {
+    /// SCALE encoded Substrate header of the block.
+    pub header: Bytes,
+    /// Consensus encoded Bitcoin headers built on top of the block, in the chain order.
+    pub descendants: Vec<Bytes>,
+    /// Proven outputs, unspent or not.
+    pub outpoints: Vec<OutPoint>,
+    /// Trie nodes of the storage proof of the coins.
+    pub proof: Vec<Bytes>,
+}
+
+/// Header chain of a chunked proof, shared by all the [`UtxoProofChunk`]s.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UtxoProofHeaders {
+    /// SCALE encoded Substrate header of the block.
+    pub header: Bytes,
+    /// Consensus encoded Bitcoin headers built on top of the block, in the chain order.
+    pub descendants: Vec<Bytes>,
+}
+
+/// Proof of a chunk of the coins at the block of [`UtxoProofHeaders`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UtxoProofChunk {
+    /// Proven outputs of this chunk, unspent or not.
+    pub outpoints: Vec<OutPoint>,
+    /// Trie nodes of the storage proof of the coins in this chunk.
+    pub proof: Vec<Bytes>,
+}
+
+/// Returns the storage key of the coin in `Coins` of pallet-bitcoin.
+///
+/// The key is `twox128("Bitcoin") ++ twox128("Coins") ++ txid ++ vout`, the txid and the
+/// little-endian vout are not hashed.
+pub fn coin_storage_key(outpoint: &OutPoint) -> Vec<u8> {
+    let mut key = Vec::with_capacity(72);
+    key.extend(sp_core::hashing::twox_128(b"Bitcoin"));
+    key.extend(sp_core::hashing::twox_128(b"Coins"));
+    key.extend(outpoint.txid.to_byte_array());
+    key.extend(outpoint.vout.to_le_bytes());
+    key
+}
@@ -0,0 +1,458 @@
+use crate::{coin_storage_key, Header, UtxoProof, UtxoProofChunk, UtxoProofHeaders};
+use bitcoin::block::{Header as BitcoinHeader, ValidationError};
+use bitcoin::{BlockHash, Network, OutPoint, Target, Work};
+use codec::Decode;
+use sp_core::{Blake2Hasher, Bytes, H256};
+use sp_runtime::traits::Header as HeaderT;
+use sp_state_machine::{read_proof_check, StorageProof};
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{bitcoin_header_from_digest, HeaderError};
+
+/// Error of verifying a [`UtxoProof`].
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    #[error("Invalid Substrate header: {0}")]
+    InvalidHeader(codec::Error),
+    #[error("Invalid Bitcoin header in the Substrate header: {0:?}")]
+    InvalidHeaderDigest(HeaderError),
+    #[error("Invalid Bitcoin header at #{0}")]
+    InvalidBitcoinHeader(u32),
+    #[error("Bitcoin header at #{0} does not extend the previous header")]
+    DisconnectedHeader(u32),
+    #[error("Invalid proof of work of block {block_hash}: {err}")]
+    InvalidProofOfWork {
+        block_hash: BlockHash,
+        err: ValidationError,
+    },
+    #[error("Target of block {0} exceeds the proof of work limit")]
+    TargetAboveLimit(BlockHash),
+    #[error("Trusted block {0} is not in the header chain of the proof")]
+    TrustedBlockNotFound(BlockHash),
+    #[error("Insufficient confirmations (required: {required}, got: {got})")]
+    InsufficientConfirmations { required: u32, got: u32 },
+    #[error("Invalid storage proof: {0}")]
+    InvalidStorageProof(String),
+    #[error("Invalid coin {0} in the storage proof")]
+    InvalidCoin(OutPoint),
+}
+
+/// Requirements of the header chain of a [`UtxoProof`].
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// Network of the proof, determining the proof of work limit.
+    pub network: Network,
+    /// Block known to be in the best chain, which must be the proven block or one of the
+    /// headers on top of it.
+    pub trusted_block: Option<BlockHash>,
+    /// Minimum confirmations of the proven block, including itself.
+    pub min_confirmations: u32,
+}
+
+/// Coins proven by a [`UtxoProof`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedUtxos {
+    /// Bitcoin block hash of the proven block.
+    pub block_hash: BlockHash,
+    pub height: u32,
+    /// Substrate block hash of the proven block.
+    pub substrate_hash: H256,
+    /// Confirmations of the proven block, including itself.
+    pub confirmations: u32,
+    /// Work of the proven block and the headers on top of it.
+    pub work: Work,
+    /// Proven coins, `None` if the output is not in the UTXO set.
+    pub coins: Vec<(OutPoint, Option<Coin>)>,
+}
+
+fn check_proof_of_work(header: &BitcoinHeader, max_target: Target) -> Result<Work, VerifyError> {
+    let target = header.target();
+
+    if target > max_target {
+        return Err(VerifyError::TargetAboveLimit(header.block_hash()));
+    }
+
+    header
+        .validate_pow(target)
+        .map_err(|err| VerifyError::InvalidProofOfWork {
+            block_hash: header.block_hash(),
+            err,
+        })?;
+
+    Ok(header.work())
+}
+
+/// Verifier of the coins against a verified header chain.
+///
+/// The header chain is verified once on construction, then each [`UtxoProofChunk`] is verified
+/// against the state root independently.
+#[derive(Debug, Clone)]
+pub struct UtxoProofVerifier {
+    state_root: H256,
+    block_hash: BlockHash,
+    height: u32,
+    substrate_hash: H256,
+    confirmations: u32,
+    work: Work,
+}
+
+impl UtxoProofVerifier {
+    /// Verifies the header chain of a chunked proof.
+    pub fn new(headers: &UtxoProofHeaders, options: &VerifyOptions) -> Result<Self, VerifyError> {
+        Self::verify_headers(&headers.header, &headers.descendants, options)
+    }
+
+    fn verify_headers(
+        encoded_header: &[u8],
+        descendants: &[Bytes],
+        options: &VerifyOptions,
+    ) -> Result<Self, VerifyError> {
+        let header =
+            Header::decode(&mut &encoded_header[..]).map_err(VerifyError::InvalidHeader)?;
+        let bitcoin_header = bitcoin_header_from_digest(header.digest())
+            .map_err(VerifyError::InvalidHeaderDigest)?;
+        let height = *header.number();
+
+        let max_target = bitcoin::params::Params::new(options.network).max_attainable_target;
+
+        let block_hash = bitcoin_header.block_hash();
+        let mut work = check_proof_of_work(&bitcoin_header, max_target)?;
+        let mut tip_hash = block_hash;
+        let mut trusted = options.trusted_block == Some(block_hash);
+
+        for (index, encoded_header) in descendants.iter().enumerate() {
+            let descendant_height = height + 1 + index as u32;
+
+            let descendant: BitcoinHeader = bitcoin::consensus::deserialize(encoded_header)
+                .map_err(|_| VerifyError::InvalidBitcoinHeader(descendant_height))?;
+
+            if descendant.prev_blockhash != tip_hash {
+                return Err(VerifyError::DisconnectedHeader(descendant_height));
+            }
+
+            work = work + check_proof_of_work(&descendant, max_target)?;
+            tip_hash = descendant.block_hash();
+            trusted |= options.trusted_block == Some(tip_hash);
+        }
+
+        if let Some(trusted_block) = options.trusted_block.filter(|_| !trusted) {
+            return Err(VerifyError::TrustedBlockNotFound(trusted_block));
+        }
+
+        let confirmations = descendants.len() as u32 + 1;
+
+        if confirmations < options.min_confirmations {
+            return Err(VerifyError::InsufficientConfirmations {
+                required: options.min_confirmations,
+                got: confirmations,
+            });
+        }
+
+        Ok(Self {
+            state_root: header.state_root,
+            block_hash,
+            height,
+            substrate_hash: header.hash(),
+            confirmations,
+            work,
+        })
+    }
+
+    fn verify_coins(
+        &self,
+        outpoints: &[OutPoint],
+        proof: &[Bytes],
+    ) -> Result<Vec<(OutPoint, Option<Coin>)>, VerifyError> {
+        let keys = outpoints.iter().map(coin_storage_key).collect::<Vec<_>>();
+
+        let values = read_proof_check::<Blake2Hasher, _>(
+            self.state_root,
+            StorageProof::new(proof.iter().map(|node| node.to_vec())),
+            &keys,
+        )
+        .map_err(|err| VerifyError::InvalidStorageProof(err.to_string()))?;
+
+        outpoints
+            .iter()
+            .zip(&keys)
+            .map(|(outpoint, key)| {
+                let coin = values
+                    .get(key)
+                    .cloned()
+                    .flatten()
+                    .map(|value| {
+                        Coin::decode(&mut value.as_slice())
+                            .map_err(|_| VerifyError::InvalidCoin(*outpoint))
+                    })
+                    .transpose()?;
+                Ok((*outpoint, coin))
+            })
+            .collect()
+    }
+
+    /// Verifies the storage proof of `chunk`, returning the proven coins.
+    pub fn verify_chunk(
+        &self,
+        chunk: &UtxoProofChunk,
+    ) -> Result<Vec<(OutPoint, Option<Coin>)>, VerifyError> {
+        self.verify_coins(&chunk.outpoints, &chunk.proof)
+    }
+
+    /// Converts into [`VerifiedUtxos`] with the coins verified by this verifier.
+    pub fn into_verified(self, coins: Vec<(OutPoint, Option<Coin>)>) -> VerifiedUtxos {
+        VerifiedUtxos {
+            block_hash: self.block_hash,
+            height: self.height,
+            substrate_hash: self.substrate_hash,
+            confirmations: self.confirmations,
+            work: self.work,
+            coins,
+        }
+    }
+}
+
+/// Verifies the header chain and the storage proof of `proof`.
+pub fn verify_utxo_proof(
+    proof: &UtxoProof,
+    options: &VerifyOptions,
+) -> Result<VerifiedUtxos, VerifyError> {
+    let verifier = UtxoProofVerifier::verify_headers(&proof.header, &proof.descendants, options)?;
+    let coins = verifier.verify_coins(&proof.outpoints, &proof.proof)?;
+    Ok(verifier.into_verified(coins))
+}
+
+/// Verifies the header chain of `headers` and then all the `chunks` against it.
+///
+/// Only the proofs of one chunk are held at a time if `chunks` is lazy, the verified coins are
+/// accumulated in the chunk order.
+pub fn verify_utxo_proof_chunks(
+    headers: &UtxoProofHeaders,
+    chunks: impl IntoIterator<Item = UtxoProofChunk>,
+    options: &VerifyOptions,
+) -> Result<VerifiedUtxos, VerifyError> {
+    let verifier = UtxoProofVerifier::new(headers, options)?;
+
+    let mut coins = Vec::new();
+    for chunk in chunks {
+        coins.extend(verifier.verify_chunk(&chunk)?);
+    }
+
+    Ok(verifier.into_verified(coins))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+    use codec::Encode;
+    use sp_core::storage::StateVersion;
+    use sp_state_machine::{prove_read, prove_read_on_trie_backend, InMemoryBackend};
+
+    fn mine(parent: &BitcoinHeader) -> BitcoinHeader {
+        let mut header = BitcoinHeader {
+            prev_blockhash: parent.block_hash(),
+            nonce: 0,
+            ..*parent
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    fn test_state(coins: &[(OutPoint, Coin)]) -> InMemoryBackend<Blake2Hasher> {
+        let storage = coins
+            .iter()
+            .map(|(outpoint, coin)| (coin_storage_key(outpoint), Some(coin.encode())))
+            .collect();
+        InMemoryBackend::<Blake2Hasher>::from((vec![(None, storage)], StateVersion::V0))
+    }
+
+    fn test_headers(state_root: H256) -> UtxoProofHeaders {
+        let genesis = bitcoin::constants::genesis_block(Network::Regtest).header;
+        let header = Header::new(
+            0,
+            Default::default(),
+            state_root,
+            Default::default(),
+            subcoin_primitives::substrate_header_digest(&genesis),
+        );
+        let child = mine(&genesis);
+
+        UtxoProofHeaders {
+            header: header.encode().into(),
+            descendants: vec![bitcoin::consensus::serialize(&child).into()],
+        }
+    }
+
+    fn test_proof(coin: &Coin, outpoints: Vec<OutPoint>) -> UtxoProof {
+        let backend = test_state(&[(outpoints[0], coin.clone())]);
+        let headers = test_headers(*backend.root());
+
+        let keys = outpoints.iter().map(coin_storage_key).collect::<Vec<_>>();
+        let storage_proof = prove_read(backend, &keys).unwrap();
+
+        UtxoProof {
+            header: headers.header,
+            descendants: headers.descendants,
+            outpoints,
+            proof: storage_proof.into_iter_nodes().map(Into::into).collect(),
+        }
+    }
+
+    fn regtest_options() -> VerifyOptions {
+        VerifyOptions {
+            network: Network::Regtest,
+            trusted_block: None,
+            min_confirmations: 2,
+        }
+    }
+
+    #[test]
+    fn test_verify_utxo_proof() {
+        let coin = Coin {
+            is_coinbase: false,
+            amount: 1000,
+            height: 0,
+            script_pubkey: vec![1, 2, 3],
+        };
+        let unspent = OutPoint {
+            txid: Txid::from_byte_array([1; 32]),
+            vout: 0,
+        };
+        let spent = OutPoint {
+            txid: Txid::from_byte_array([1; 32]),
+            vout: 1,
+        };
+
+        let proof = test_proof(&coin, vec![unspent, spent]);
+
+        let verified = verify_utxo_proof(&proof, &regtest_options()).unwrap();
+        assert_eq!(verified.height, 0);
+        assert_eq!(verified.confirmations, 2);
+        assert_eq!(verified.coins, vec![(unspent, Some(coin)), (spent, None)]);
+
+        let trusted_block = bitcoin::consensus::deserialize::<BitcoinHeader>(&proof.descendants[0])
+            .unwrap()
+            .block_hash();
+        let options = VerifyOptions {
+            trusted_block: Some(trusted_block),
+            ..regtest_options()
+        };
+        assert!(verify_utxo_proof(&proof, &options).is_ok());
+    }
+
+    #[test]
+    fn test_verify_utxo_proof_rejects_invalid_proofs() {
+        let coin = Coin {
+            is_coinbase: true,
+            amount: 5000,
+            height: 0,
+            script_pubkey: vec![],
+        };
+        let outpoint = OutPoint {
+            txid: Txid::from_byte_array([2; 32]),
+            vout: 0,
+        };
+
+        let proof = test_proof(&coin, vec![outpoint]);
+
+        let options = VerifyOptions {
+            min_confirmations: 3,
+            ..regtest_options()
+        };
+        assert!(matches!(
+            verify_utxo_proof(&proof, &options),
+            Err(VerifyError::InsufficientConfirmations {
+                required: 3,
+                got: 2
+            })
+        ));
+
+        let options = VerifyOptions {
+            trusted_block: Some(BlockHash::all_zeros()),
+            ..options
+        };
+        assert!(matches!(
+            verify_utxo_proof(&proof, &options),
+            Err(VerifyError::TrustedBlockNotFound(_))
+        ));
+
+        let mut disconnected = proof.clone();
+        disconnected
+            .descendants
+            .push(disconnected.descendants[0].clone());
+        assert!(matches!(
+            verify_utxo_proof(&disconnected, &regtest_options()),
+            Err(VerifyError::DisconnectedHeader(2))
+        ));
+
+        let mut incomplete = proof;
+        incomplete.proof.clear();
+        assert!(matches!(
+            verify_utxo_proof(&incomplete, &regtest_options()),
+            Err(VerifyError::InvalidStorageProof(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_utxo_proof_chunks() {
+        let coins = (0..10u8)
+            .map(|i| {
+                let outpoint = OutPoint {
+                    txid: Txid::from_byte_array([i; 32]),
+                    vout: i.into(),
+                };
+                let coin = Coin {
+                    is_coinbase: false,
+                    amount: 1000 * u64::from(i),
+                    height: 0,
+                    script_pubkey: vec![i],
+                };
+                (outpoint, coin)
+            })
+            .collect::<Vec<_>>();
+
+        let backend = test_state(&coins);
+        let headers = test_headers(*backend.root());
+
+        let spent = OutPoint {
+            txid: Txid::from_byte_array([0xff; 32]),
+            vout: 0,
+        };
+        let outpoints = coins
+            .iter()
+            .map(|(outpoint, _)| *outpoint)
+            .chain(std::iter::once(spent))
+            .collect::<Vec<_>>();
+
+        let chunks = outpoints
+            .chunks(3)
+            .map(|outpoints| {
+                let keys = outpoints.iter().map(coin_storage_key).collect::<Vec<_>>();
+                let proof = prove_read_on_trie_backend(&backend, &keys).unwrap();
+                UtxoProofChunk {
+                    outpoints: outpoints.to_vec(),
+                    proof: proof.into_iter_nodes().map(Into::into).collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let verified =
+            verify_utxo_proof_chunks(&headers, chunks.clone(), &regtest_options()).unwrap();
+        let expected = coins
+            .into_iter()
+            .map(|(outpoint, coin)| (outpoint, Some(coin)))
+            .chain(std::iter::once((spent, None)))
+            .collect::<Vec<_>>();
+        assert_eq!(verified.coins, expected);
+
+        // The proof of another chunk is rejected.
+        let mut chunks = chunks;
+        chunks[1].proof = chunks[0].proof.clone();
+        assert!(matches!(
+            verify_utxo_proof_chunks(&headers, chunks, &regtest_options()),
+            Err(VerifyError::InvalidStorageProof(_))
+        ));
+    }
+}
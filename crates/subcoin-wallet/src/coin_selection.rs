@@ -0,0 +1,612 @@
+//! Coin selection.
+//!
+//! The candidates are compared by their effective values, i.e., the amount minus the fee of
+//! spending them at the target fee rate, the candidates not worth spending are never selected.
+//!
+//! - [`BranchAndBound`] searches for a changeless selection whose excess over the target stays
+//!   within the cost of the change, minimizing the waste.
+//! - [`Knapsack`] approximates the smallest selection leaving at least the change target.
+//! - [`LargestFirst`] selects the largest candidates until the target is met.
+
+use bitcoin::{Amount, FeeRate, Script, Weight};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of the branches explored by [`BranchAndBound`], same as Bitcoin Core.
+const BNB_TOTAL_TRIES: usize = 100_000;
+
+/// Number of the random subsets evaluated by [`Knapsack`].
+const KNAPSACK_ITERATIONS: usize = 1000;
+
+/// Weight of the segwit marker and flag.
+const SEGWIT_OVERHEAD: Weight = Weight::from_wu(2);
+
+/// Output available for the selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub value: Amount,
+    /// Weight of the input spending the output, including the satisfaction.
+    pub weight: Weight,
+    /// Whether the input spending the output has a witness.
+    pub is_segwit: bool,
+}
+
+/// Parameters of the coin selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoinSelectionParams {
+    /// Fee rate of the transaction.
+    pub fee_rate: FeeRate,
+    /// Fee rate expected to spend the outputs in the future, the inputs are preferred to be
+    /// consolidated when the current fee rate is lower.
+    pub long_term_fee_rate: FeeRate,
+    /// Weight of the transaction without any input and the change output.
+    pub base_weight: Weight,
+    /// Weight of the change output.
+    pub change_weight: Weight,
+    /// Weight of the input spending the change output.
+    pub change_spend_weight: Weight,
+    /// Minimum value of the change output, the change below it goes to the fee.
+    pub min_change: Amount,
+    /// Value of the change [`Knapsack`] aims to leave.
+    pub change_target: Amount,
+}
+
+impl CoinSelectionParams {
+    /// Constructs the parameters with a P2WPKH change output.
+    pub fn new(fee_rate: FeeRate, base_weight: Weight) -> Self {
+        Self {
+            fee_rate,
+            long_term_fee_rate: FeeRate::from_sat_per_vb_unchecked(10),
+            base_weight,
+            change_weight: Weight::from_wu(31 * 4),
+            change_spend_weight: Weight::from_wu(272),
+            min_change: Amount::from_sat(294),
+            change_target: Amount::from_sat(1_000_000),
+        }
+    }
+
+    /// Sets the long-term fee rate.
+    pub fn with_long_term_fee_rate(mut self, long_term_fee_rate: FeeRate) -> Self {
+        self.long_term_fee_rate = long_term_fee_rate;
+        self
+    }
+
+    /// Sets the change output to `script_pubkey`, spent by an input of `spend_weight`.
+    pub fn with_change_script(mut self, script_pubkey: &Script, spend_weight: Weight) -> Self {
+        let size = 8 + bitcoin::VarInt(script_pubkey.len() as u64).size() + script_pubkey.len();
+        self.change_weight = Weight::from_vb_unchecked(size as u64);
+        self.change_spend_weight = spend_weight;
+        self.min_change = script_pubkey.minimal_non_dust();
+        self
+    }
+
+    /// Sets the value of the change [`Knapsack`] aims to leave.
+    pub fn with_change_target(mut self, change_target: Amount) -> Self {
+        self.change_target = change_target;
+        self
+    }
+
+    /// Returns the cost of creating the change output now and spending it later.
+    pub fn cost_of_change(&self) -> Amount {
+        fee(self.fee_rate, self.change_weight)
+            + fee(self.long_term_fee_rate, self.change_spend_weight)
+    }
+}
+
+/// Result of the coin selection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// Indices of the selected candidates, in ascending order.
+    pub selected: Vec<usize>,
+    pub fee: Amount,
+    /// Value of the change output, `None` if there is no change.
+    pub change: Option<Amount>,
+}
+
+/// Coin selection algorithm.
+pub trait CoinSelection {
+    /// Selects the candidates paying `target` plus the fee, `None` if the candidates are
+    /// insufficient.
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        params: &CoinSelectionParams,
+    ) -> Option<Selection>;
+}
+
+/// Coin selection algorithms available in the wallet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinSelectionAlgorithm {
+    /// [`BranchAndBound`], falling back to [`Knapsack`] if there is no changeless match.
+    #[default]
+    Auto,
+    BranchAndBound,
+    Knapsack,
+    LargestFirst,
+}
+
+impl CoinSelection for CoinSelectionAlgorithm {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        params: &CoinSelectionParams,
+    ) -> Option<Selection> {
+        match self {
+            Self::Auto => BranchAndBound
+                .select(candidates, target, params)
+                .or_else(|| Knapsack.select(candidates, target, params)),
+            Self::BranchAndBound => BranchAndBound.select(candidates, target, params),
+            Self::Knapsack => Knapsack.select(candidates, target, params),
+            Self::LargestFirst => LargestFirst.select(candidates, target, params),
+        }
+    }
+}
+
+/// Effective value of a candidate.
+#[derive(Debug, Clone, Copy)]
+struct Utxo {
+    index: usize,
+    effective_value: i64,
+    /// Fee of spending the candidate now minus the fee of spending it at the long-term fee rate.
+    waste: i64,
+}
+
+/// Returns the candidates with a positive effective value, in descending order of it.
+fn effective_values(candidates: &[Candidate], params: &CoinSelectionParams) -> Vec<Utxo> {
+    let mut utxos = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| {
+            let fee_now = fee(params.fee_rate, candidate.weight).to_sat() as i64;
+            let fee_long_term = fee(params.long_term_fee_rate, candidate.weight).to_sat() as i64;
+            Utxo {
+                index,
+                effective_value: candidate.value.to_sat() as i64 - fee_now,
+                waste: fee_now - fee_long_term,
+            }
+        })
+        .filter(|utxo| utxo.effective_value > 0)
+        .collect::<Vec<_>>();
+
+    utxos.sort_by(|a, b| b.effective_value.cmp(&a.effective_value));
+
+    utxos
+}
+
+/// Returns the weight of the transaction without the inputs, assuming a segwit input is spent
+/// if any candidate is segwit.
+fn base_weight(candidates: &[Candidate], params: &CoinSelectionParams) -> Weight {
+    if candidates.iter().any(|candidate| candidate.is_segwit) {
+        params.base_weight + SEGWIT_OVERHEAD
+    } else {
+        params.base_weight
+    }
+}
+
+/// Computes the fee and the change of the selection, `None` if the target and the fee are not
+/// covered.
+fn finish(
+    candidates: &[Candidate],
+    mut selected: Vec<usize>,
+    target: Amount,
+    params: &CoinSelectionParams,
+    allow_change: bool,
+) -> Option<Selection> {
+    selected.sort_unstable();
+
+    let inputs = selected.iter().map(|index| candidates[*index]);
+    let value = inputs
+        .clone()
+        .map(|candidate| candidate.value)
+        .sum::<Amount>();
+    let mut weight = params.base_weight + inputs.clone().map(|c| c.weight).sum::<Weight>();
+    if inputs.clone().any(|candidate| candidate.is_segwit) {
+        weight += SEGWIT_OVERHEAD;
+    }
+
+    let excess = value.checked_sub(target)?;
+    let fee_without_change = fee(params.fee_rate, weight);
+    excess.checked_sub(fee_without_change)?;
+
+    let fee_with_change = fee(params.fee_rate, weight + params.change_weight);
+
+    let selection = match excess.checked_sub(fee_with_change) {
+        Some(change) if allow_change && change >= params.min_change => Selection {
+            selected,
+            fee: fee_with_change,
+            change: Some(change),
+        },
+        _ => Selection {
+            selected,
+            fee: excess,
+            change: None,
+        },
+    };
+
+    Some(selection)
+}
+
+/// Depth-first search for the changeless selection with the least waste, as in Bitcoin Core.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BranchAndBound;
+
+impl CoinSelection for BranchAndBound {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        params: &CoinSelectionParams,
+    ) -> Option<Selection> {
+        let utxos = effective_values(candidates, params);
+        let selection_target =
+            (target + fee(params.fee_rate, base_weight(candidates, params))).to_sat() as i64;
+        let cost_of_change = params.cost_of_change().to_sat() as i64;
+        let is_fee_rate_high = params.fee_rate > params.long_term_fee_rate;
+
+        let mut available = utxos.iter().map(|utxo| utxo.effective_value).sum::<i64>();
+        if available < selection_target {
+            return None;
+        }
+
+        let mut current_value = 0i64;
+        let mut current_waste = 0i64;
+        let mut current_selection = Vec::<usize>::new();
+        let mut best_selection = None::<Vec<usize>>;
+        let mut best_waste = i64::MAX;
+        let mut index = 0;
+
+        for _ in 0..BNB_TOTAL_TRIES {
+            let mut backtrack = false;
+
+            if current_value + available < selection_target
+                || current_value > selection_target + cost_of_change
+                || (current_waste > best_waste && is_fee_rate_high)
+            {
+                backtrack = true;
+            } else if current_value >= selection_target {
+                // The excess is added to the fee as there is no change.
+                let waste = current_waste + (current_value - selection_target);
+                if waste <= best_waste {
+                    best_selection.replace(current_selection.clone());
+                    best_waste = waste;
+                }
+                backtrack = true;
+            }
+
+            if backtrack {
+                let Some(&last) = current_selection.last() else {
+                    break;
+                };
+
+                // Restore the lookahead of the omitted UTXOs before trying to omit the last
+                // included one.
+                index -= 1;
+                while index > last {
+                    available += utxos[index].effective_value;
+                    index -= 1;
+                }
+
+                current_value -= utxos[index].effective_value;
+                current_waste -= utxos[index].waste;
+                current_selection.pop();
+            } else {
+                let utxo = utxos[index];
+                available -= utxo.effective_value;
+
+                // Skip the UTXO equivalent to the previous one if the previous one was omitted,
+                // the branch has been explored already.
+                if current_selection.is_empty()
+                    || index - 1 == *current_selection.last().expect("Not empty; qed")
+                    || utxo.effective_value != utxos[index - 1].effective_value
+                    || utxo.waste != utxos[index - 1].waste
+                {
+                    current_selection.push(index);
+                    current_value += utxo.effective_value;
+                    current_waste += utxo.waste;
+                }
+            }
+
+            index += 1;
+        }
+
+        let selected = best_selection?
+            .into_iter()
+            .map(|index| utxos[index].index)
+            .collect();
+
+        finish(candidates, selected, target, params, false)
+    }
+}
+
+/// Stochastic approximation of the smallest selection leaving the change target, as in Bitcoin
+/// Core.
+///
+/// The selection is deterministic as the pseudo-random generator has a fixed seed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Knapsack;
+
+impl CoinSelection for Knapsack {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        params: &CoinSelectionParams,
+    ) -> Option<Selection> {
+        let utxos = effective_values(candidates, params);
+        let target_value = (target
+            + fee(
+                params.fee_rate,
+                base_weight(candidates, params) + params.change_weight,
+            ))
+        .to_sat() as i64;
+        let change_target = params.change_target.to_sat() as i64;
+
+        let mut applicable = Vec::new();
+        let mut total_lower = 0i64;
+        let mut lowest_larger = None::<Utxo>;
+
+        for utxo in utxos {
+            if utxo.effective_value == target_value {
+                return finish(candidates, vec![utxo.index], target, params, true);
+            } else if utxo.effective_value < target_value + change_target {
+                applicable.push(utxo);
+                total_lower += utxo.effective_value;
+            } else if !lowest_larger
+                .is_some_and(|lowest| lowest.effective_value <= utxo.effective_value)
+            {
+                lowest_larger.replace(utxo);
+            }
+        }
+
+        let select_lowest_larger =
+            |lowest: Utxo| finish(candidates, vec![lowest.index], target, params, true);
+
+        if total_lower == target_value {
+            let selected = applicable.iter().map(|utxo| utxo.index).collect();
+            return finish(candidates, selected, target, params, true);
+        }
+
+        if total_lower < target_value {
+            return lowest_larger.and_then(select_lowest_larger);
+        }
+
+        let values = applicable
+            .iter()
+            .map(|utxo| utxo.effective_value)
+            .collect::<Vec<_>>();
+
+        let mut rng = XorShift::default();
+        let (mut best, mut best_value) =
+            approximate_best_subset(&mut rng, &values, total_lower, target_value);
+        if best_value != target_value && total_lower >= target_value + change_target {
+            (best, best_value) = approximate_best_subset(
+                &mut rng,
+                &values,
+                total_lower,
+                target_value + change_target,
+            );
+        }
+
+        if let Some(lowest) = lowest_larger {
+            if (best_value != target_value && best_value < target_value + change_target)
+                || lowest.effective_value <= best_value
+            {
+                return select_lowest_larger(lowest);
+            }
+        }
+
+        let selected = applicable
+            .iter()
+            .zip(best)
+            .filter_map(|(utxo, included)| included.then_some(utxo.index))
+            .collect();
+
+        finish(candidates, selected, target, params, true)
+    }
+}
+
+/// Returns the subset of `values` with the smallest sum not lower than `target`.
+fn approximate_best_subset(
+    rng: &mut XorShift,
+    values: &[i64],
+    total_lower: i64,
+    target: i64,
+) -> (Vec<bool>, i64) {
+    let mut best = vec![true; values.len()];
+    let mut best_value = total_lower;
+
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_value == target {
+            break;
+        }
+
+        let mut included = vec![false; values.len()];
+        let mut total = 0;
+        let mut reached_target = false;
+
+        for pass in 0..2 {
+            if reached_target {
+                break;
+            }
+
+            for (i, value) in values.iter().enumerate() {
+                // The first pass includes the values randomly, the second pass includes the
+                // remaining ones until the target is reached.
+                let include = if pass == 0 {
+                    rng.next_bool()
+                } else {
+                    !included[i]
+                };
+
+                if include {
+                    total += value;
+                    included[i] = true;
+                    if total >= target {
+                        reached_target = true;
+                        if total < best_value {
+                            best_value = total;
+                            best.clone_from(&included);
+                        }
+                        total -= value;
+                        included[i] = false;
+                    }
+                }
+            }
+        }
+    }
+
+    (best, best_value)
+}
+
+/// Selects the candidates in descending order of the effective value until the target and the
+/// fee are covered.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargestFirst;
+
+impl CoinSelection for LargestFirst {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        params: &CoinSelectionParams,
+    ) -> Option<Selection> {
+        let mut selected = Vec::new();
+
+        for utxo in effective_values(candidates, params) {
+            selected.push(utxo.index);
+            if let Some(selection) = finish(candidates, selected.clone(), target, params, true) {
+                return Some(selection);
+            }
+        }
+
+        None
+    }
+}
+
+/// Minimal xorshift generator, the selection does not need to be unpredictable.
+struct XorShift(u64);
+
+impl Default for XorShift {
+    fn default() -> Self {
+        Self(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl XorShift {
+    fn next_bool(&mut self) -> bool {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 & 1 == 1
+    }
+}
+
+/// Returns the fee of `weight` at `fee_rate`, rounded up.
+fn fee(fee_rate: FeeRate, weight: Weight) -> Amount {
+    Amount::from_sat((fee_rate.to_sat_per_kwu() * weight.to_wu()).div_ceil(1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT_WEIGHT: Weight = Weight::from_wu(272);
+
+    fn candidates(values: &[u64]) -> Vec<Candidate> {
+        values
+            .iter()
+            .map(|value| Candidate {
+                value: Amount::from_sat(*value),
+                weight: INPUT_WEIGHT,
+                is_segwit: true,
+            })
+            .collect()
+    }
+
+    fn params() -> CoinSelectionParams {
+        // 4 * (4 + 4 + 1 + 1 + 31) = 164 WU, one P2WPKH payment.
+        CoinSelectionParams::new(FeeRate::from_sat_per_vb_unchecked(10), Weight::from_wu(164))
+    }
+
+    #[test]
+    fn test_branch_and_bound_changeless_match() {
+        let params = params();
+        // Base fee: (164 + 2) / 4 * 10 = 415, input fee: 680.
+        let candidates = candidates(&[100_000, 30_680, 20_680, 5_680]);
+        let target = Amount::from_sat(50_000 - 415);
+
+        let selection = BranchAndBound.select(&candidates, target, &params).unwrap();
+        assert_eq!(selection.selected, vec![1, 2]);
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, Amount::from_sat(415 + 2 * 680));
+
+        // No changeless match within the cost of the change.
+        let target = Amount::from_sat(60_000);
+        assert_eq!(BranchAndBound.select(&candidates, target, &params), None);
+    }
+
+    #[test]
+    fn test_knapsack_and_auto_fallback() {
+        let params = params();
+        let candidates = candidates(&[10_000, 60_000, 30_000]);
+        let target = Amount::from_sat(50_000);
+
+        let selection = Knapsack.select(&candidates, target, &params).unwrap();
+        assert_eq!(selection.selected, vec![1]);
+        assert!(selection.change.is_some());
+
+        assert_eq!(
+            CoinSelectionAlgorithm::Auto.select(&candidates, target, &params),
+            Some(selection)
+        );
+
+        // Single candidate matching the target and the fee with change exactly.
+        // Fee with change: (164 + 2 + 124) / 4 * 10 = 725, input fee: 680.
+        let candidates = self::candidates(&[20_000, 31_405, 1_000_000_000]);
+        let selection = Knapsack
+            .select(&candidates, Amount::from_sat(30_000), &params)
+            .unwrap();
+        assert_eq!(selection.selected, vec![1]);
+
+        // The smallest larger candidate is preferred if no subset leaves the change target.
+        let candidates = self::candidates(&[40_000, 30_000, 20_000, 1_000_000_000]);
+        let selection = Knapsack
+            .select(&candidates, Amount::from_sat(30_000), &params)
+            .unwrap();
+        assert_eq!(selection.selected, vec![3]);
+    }
+
+    #[test]
+    fn test_largest_first() {
+        let params = params();
+        let candidates = candidates(&[10_000, 60_000, 30_000]);
+
+        let selection = LargestFirst
+            .select(&candidates, Amount::from_sat(80_000), &params)
+            .unwrap();
+        assert_eq!(selection.selected, vec![1, 2]);
+
+        assert_eq!(
+            LargestFirst.select(&candidates, Amount::from_sat(100_000), &params),
+            None
+        );
+    }
+
+    #[test]
+    fn test_uneconomical_candidates_are_ignored() {
+        let params = params();
+        let candidates = candidates(&[500, 60_000]);
+
+        for algorithm in [
+            CoinSelectionAlgorithm::Knapsack,
+            CoinSelectionAlgorithm::LargestFirst,
+        ] {
+            let selection = algorithm
+                .select(&candidates, Amount::from_sat(58_000), &params)
+                .unwrap();
+            assert_eq!(selection.selected, vec![1]);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! # Subcoin Wallet
+//!
+//! This crate provides a watch-only wallet tracking the outputs of the imported output
+//! descriptors. The wallet scans the UTXO set on import and follows the new best blocks
+//! afterwards to keep the balances and history up to date.
+//!
+//! There is no key management or signing, the wallet is purely watch-only.
+
+mod coin_selection;
+mod psbt;
+mod wallet;
+
+use bitcoin::{Amount, BlockHash};
+use subcoin_primitives::HeaderError;
+
+pub use self::coin_selection::{
+    BranchAndBound, Candidate, CoinSelection, CoinSelectionAlgorithm, CoinSelectionParams,
+    Knapsack, LargestFirst, Selection,
+};
+pub use self::psbt::{finalize_psbt, FundedPsbt};
+pub use self::wallet::{
+    Balances, ImportedDescriptor, RescanOutcome, RescanProgress, UnspentOutput, Wallet,
+    WalletState, WalletTransaction, WalletUtxo,
+};
+
+/// Wallet error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Descriptor(#[from] subcoin_primitives::descriptor::Error),
+    #[error("Invalid rescan range [{0}, {1}]")]
+    InvalidRescanRange(u32, u32),
+    #[error("No descriptors imported")]
+    NoDescriptors,
+    #[error("Another rescan is in progress")]
+    RescanInProgress,
+    #[error("Block {0} not found")]
+    BlockNotFound(BlockHash),
+    #[error("Block #{0} not available (pruned data)")]
+    BlockPruned(u32),
+    #[error("Invalid block: {0:?}")]
+    InvalidBlock(HeaderError),
+    #[error("Insufficient funds, available: {available}, required: {required} excluding fee")]
+    InsufficientFunds { available: Amount, required: Amount },
+    #[error("No change address available, import a ranged descriptor or specify one")]
+    NoChangeAddress,
+    #[error("Input {0} is missing signatures or has an unknown script type")]
+    IncompletePsbt(usize),
+    #[error(transparent)]
+    Psbt(#[from] bitcoin::psbt::Error),
+    #[error(transparent)]
+    Blockchain(#[from] sp_blockchain::Error),
+    #[error(transparent)]
+    Database(#[from] subcoin_db::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
@@ -0,0 +1,398 @@
+//! PSBT (BIP-174) construction and finalization.
+//!
+//! The wallet acts as the creator, updater, finalizer and extractor, the signing is left to
+//! the external signers such as hardware wallets.
+
+use crate::coin_selection::{Candidate, CoinSelection, CoinSelectionParams};
+use crate::wallet::WalletUtxo;
+use crate::Error;
+use bitcoin::absolute::LockTime;
+use bitcoin::psbt::{Input, Output, Psbt};
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, FeeRate, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight, Witness};
+use subcoin_primitives::descriptor::{parse_multisig, Descriptor, PsbtFields};
+
+/// Result of funding a PSBT.
+#[derive(Debug, Clone)]
+pub struct FundedPsbt {
+    pub psbt: Psbt,
+    pub fee: Amount,
+    /// Position of the change output, `None` if there is no change.
+    pub change_position: Option<usize>,
+}
+
+/// Destination of the change.
+#[derive(Debug, Clone)]
+pub(crate) struct ChangeOutput {
+    pub script_pubkey: ScriptBuf,
+    /// Descriptor and derivation index of the change script if it's owned by the wallet.
+    pub descriptor: Option<(Descriptor, u32)>,
+}
+
+/// Creates a PSBT paying to `outputs` with the inputs selected from `candidates`.
+///
+/// The change output is omitted if it would be dust or the selection is changeless.
+pub(crate) fn fund_psbt(
+    candidates: Vec<(WalletUtxo, Descriptor)>,
+    outputs: Vec<TxOut>,
+    fee_rate: FeeRate,
+    long_term_fee_rate: Option<FeeRate>,
+    algorithm: &dyn CoinSelection,
+    change: ChangeOutput,
+) -> Result<FundedPsbt, Error> {
+    let target = outputs.iter().map(|txout| txout.value).sum::<Amount>();
+
+    let available = candidates
+        .iter()
+        .map(|(utxo, _)| utxo.amount)
+        .sum::<Amount>();
+
+    // The change is paid to the same script type, assume it is spent as of the largest input.
+    let change_spend_weight = candidates
+        .iter()
+        .map(|(_, descriptor)| descriptor.max_satisfaction_weight())
+        .max()
+        .unwrap_or(Weight::from_wu(272));
+
+    let mut params = CoinSelectionParams::new(fee_rate, base_weight(&outputs))
+        .with_change_script(&change.script_pubkey, change_spend_weight);
+    if let Some(long_term_fee_rate) = long_term_fee_rate {
+        params = params.with_long_term_fee_rate(long_term_fee_rate);
+    }
+
+    let coins = candidates
+        .iter()
+        .map(|(utxo, descriptor)| Candidate {
+            value: utxo.amount,
+            weight: descriptor.max_satisfaction_weight(),
+            is_segwit: descriptor.is_segwit(),
+        })
+        .collect::<Vec<_>>();
+
+    let Some(selection) = algorithm.select(&coins, target, &params) else {
+        return Err(Error::InsufficientFunds {
+            available,
+            required: target,
+        });
+    };
+
+    let fee = selection.fee;
+    let change_value = selection.change;
+
+    let mut candidates = candidates.into_iter().map(Some).collect::<Vec<_>>();
+    let selected = selection
+        .selected
+        .iter()
+        .map(|index| {
+            candidates[*index]
+                .take()
+                .expect("Selected indices are unique; qed")
+        })
+        .collect::<Vec<_>>();
+
+    let mut tx_outputs = outputs;
+    let change_position = change_value.map(|value| {
+        tx_outputs.push(TxOut {
+            value,
+            script_pubkey: change.script_pubkey,
+        });
+        tx_outputs.len() - 1
+    });
+
+    let unsigned_tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: selected
+            .iter()
+            .map(|(utxo, _)| TxIn {
+                previous_output: utxo.outpoint,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            })
+            .collect(),
+        output: tx_outputs,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+
+    for (input, (utxo, descriptor)) in psbt.inputs.iter_mut().zip(selected) {
+        // TODO: `non_witness_utxo` is required to spend the legacy outputs with some signers,
+        // but the full previous transactions are not available without a transaction index.
+        input.witness_utxo = Some(TxOut {
+            value: utxo.amount,
+            script_pubkey: utxo.script_pubkey,
+        });
+        update_input(input, descriptor.psbt_fields(utxo.index)?);
+    }
+
+    if let (Some(position), Some((descriptor, index))) = (change_position, change.descriptor) {
+        update_output(&mut psbt.outputs[position], descriptor.psbt_fields(index)?);
+    }
+
+    Ok(FundedPsbt {
+        psbt,
+        fee,
+        change_position,
+    })
+}
+
+fn update_input(input: &mut Input, fields: PsbtFields) {
+    input.redeem_script = fields.redeem_script;
+    input.witness_script = fields.witness_script;
+    input.bip32_derivation = fields
+        .bip32_derivation
+        .into_iter()
+        .map(|(pk, key_source)| (pk.inner, key_source))
+        .collect();
+    if let Some((internal_key, key_source)) = fields.tap_internal_key {
+        input.tap_internal_key = Some(internal_key);
+        if let Some(key_source) = key_source {
+            input
+                .tap_key_origins
+                .insert(internal_key, (Vec::new(), key_source));
+        }
+    }
+}
+
+fn update_output(output: &mut Output, fields: PsbtFields) {
+    output.redeem_script = fields.redeem_script;
+    output.witness_script = fields.witness_script;
+    output.bip32_derivation = fields
+        .bip32_derivation
+        .into_iter()
+        .map(|(pk, key_source)| (pk.inner, key_source))
+        .collect();
+    if let Some((internal_key, key_source)) = fields.tap_internal_key {
+        output.tap_internal_key = Some(internal_key);
+        if let Some(key_source) = key_source {
+            output
+                .tap_key_origins
+                .insert(internal_key, (Vec::new(), key_source));
+        }
+    }
+}
+
+/// Returns the weight of a transaction paying to `outputs` without any input.
+///
+/// The input count is assumed to be encoded in one byte, i.e., less than 253 inputs.
+fn base_weight(outputs: &[TxOut]) -> Weight {
+    let compact_size = |n: usize| bitcoin::VarInt(n as u64).size();
+
+    let outputs_size = outputs
+        .iter()
+        .map(|txout| 8 + compact_size(txout.script_pubkey.len()) + txout.script_pubkey.len())
+        .sum::<usize>();
+
+    // Version + lock time + input count.
+    let base_size = 4 + 4 + 1 + compact_size(outputs.len()) + outputs_size;
+
+    Weight::from_vb_unchecked(base_size as u64)
+}
+
+/// Finalizes the inputs with the collected signatures and extracts the network transaction.
+///
+/// The inputs already finalized are kept as is. Only the single key and multisig inputs can be
+/// finalized, the miniscript inputs have to be finalized by the signers.
+pub fn finalize_psbt(mut psbt: Psbt) -> Result<Transaction, Error> {
+    for (index, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+
+        let (script_sig, witness) = satisfy(input).ok_or(Error::IncompletePsbt(index))?;
+
+        // As recommended by BIP-174, all other data except the UTXO fields are cleared.
+        *input = Input {
+            witness_utxo: input.witness_utxo.take(),
+            non_witness_utxo: input.non_witness_utxo.take(),
+            final_script_sig: script_sig,
+            final_script_witness: witness,
+            ..Default::default()
+        };
+    }
+
+    Ok(psbt.extract_tx_unchecked_fee_rate())
+}
+
+/// Returns the final `scriptSig` and witness of the input, `None` if the signatures in the
+/// input are insufficient or the script type is unknown.
+fn satisfy(input: &Input) -> Option<(Option<ScriptBuf>, Option<Witness>)> {
+    if let Some(signature) = input.tap_key_sig {
+        return Some((None, Some(Witness::from_slice(&[signature.to_vec()]))));
+    }
+
+    let single_sig = || {
+        input
+            .partial_sigs
+            .iter()
+            .next()
+            .map(|(pk, signature)| vec![signature.to_vec(), pk.to_bytes()])
+    };
+
+    if let Some(witness_script) = &input.witness_script {
+        let mut witness = multisig_satisfaction(input, witness_script)?;
+        witness.push(witness_script.to_bytes());
+        let script_sig = match &input.redeem_script {
+            Some(redeem_script) => Some(push_only(&[redeem_script.to_bytes()])?),
+            None => None,
+        };
+        return Some((script_sig, Some(Witness::from_slice(&witness))));
+    }
+
+    if let Some(redeem_script) = &input.redeem_script {
+        let script_sig_redeem = push_only(&[redeem_script.to_bytes()])?;
+
+        if redeem_script.is_p2wpkh() {
+            return Some((
+                Some(script_sig_redeem),
+                Some(Witness::from_slice(&single_sig()?)),
+            ));
+        }
+
+        let mut items = multisig_satisfaction(input, redeem_script)?;
+        items.push(redeem_script.to_bytes());
+        return Some((Some(push_only(&items)?), None));
+    }
+
+    let script_pubkey = &input.witness_utxo.as_ref()?.script_pubkey;
+
+    if script_pubkey.is_p2wpkh() {
+        Some((None, Some(Witness::from_slice(&single_sig()?))))
+    } else if script_pubkey.is_p2pkh() {
+        Some((Some(push_only(&single_sig()?)?), None))
+    } else {
+        let items = multisig_satisfaction(input, script_pubkey)?;
+        Some((Some(push_only(&items)?), None))
+    }
+}
+
+/// Returns the stack items satisfying the multisig script, in the order of the keys.
+fn multisig_satisfaction(input: &Input, script: &ScriptBuf) -> Option<Vec<Vec<u8>>> {
+    let (threshold, keys) = parse_multisig(script)?;
+
+    let signatures = keys
+        .iter()
+        .filter_map(|key| input.partial_sigs.get(key).map(|sig| sig.to_vec()))
+        .take(threshold)
+        .collect::<Vec<_>>();
+
+    if signatures.len() < threshold {
+        return None;
+    }
+
+    // Dummy element consumed by OP_CHECKMULTISIG.
+    Some(std::iter::once(Vec::new()).chain(signatures).collect())
+}
+
+fn push_only(items: &[Vec<u8>]) -> Option<ScriptBuf> {
+    items
+        .iter()
+        .try_fold(Builder::new(), |builder, item| {
+            PushBytesBuf::try_from(item.clone())
+                .ok()
+                .map(|data| builder.push_slice(data))
+        })
+        .map(Builder::into_script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin_selection::CoinSelectionAlgorithm;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, Txid};
+    use std::str::FromStr;
+
+    const WPKH: &str = "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)";
+
+    fn utxo(vout: u32, amount: u64, descriptor: &Descriptor) -> (WalletUtxo, Descriptor) {
+        let utxo = WalletUtxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), vout),
+            amount: Amount::from_sat(amount),
+            script_pubkey: descriptor.script_pubkey(0).unwrap(),
+            height: 1,
+            is_coinbase: false,
+            descriptor: 0,
+            index: 0,
+        };
+        (utxo, descriptor.clone())
+    }
+
+    #[test]
+    fn test_fund_psbt() {
+        let descriptor = Descriptor::from_str(WPKH).unwrap();
+        let change = ChangeOutput {
+            script_pubkey: descriptor.script_pubkey(0).unwrap(),
+            descriptor: Some((descriptor.clone(), 0)),
+        };
+        let payment = TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey: ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros()),
+        };
+        let fee_rate = FeeRate::from_sat_per_vb(10).unwrap();
+
+        let candidates = vec![
+            utxo(0, 10_000, &descriptor),
+            utxo(1, 60_000, &descriptor),
+            utxo(2, 30_000, &descriptor),
+        ];
+
+        let funded = fund_psbt(
+            candidates.clone(),
+            vec![payment.clone()],
+            fee_rate,
+            None,
+            &CoinSelectionAlgorithm::Auto,
+            change.clone(),
+        )
+        .unwrap();
+
+        // No changeless match, the smallest UTXO covering the payment and the change is used.
+        let tx = &funded.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output.vout, 1);
+        assert_eq!(funded.change_position, Some(1));
+        assert_eq!(tx.output[1].value + funded.fee, Amount::from_sat(10_000));
+        assert!(funded.psbt.inputs[0].witness_utxo.is_some());
+
+        // 272 (input) + 4 * (4 + 4 + 1 + 1 + 34 + 31) + 2 (segwit marker) = 574 WU.
+        assert_eq!(funded.fee, Amount::from_sat(1435));
+
+        // Insufficient funds.
+        let too_much = TxOut {
+            value: Amount::from_sat(100_000),
+            ..payment.clone()
+        };
+        assert!(matches!(
+            fund_psbt(
+                candidates.clone(),
+                vec![too_much],
+                fee_rate,
+                None,
+                &CoinSelectionAlgorithm::Auto,
+                change.clone()
+            ),
+            Err(Error::InsufficientFunds { .. })
+        ));
+
+        // Changeless match of the two smaller UTXOs: 2 * 272 (inputs) + 4 * (4 + 4 + 1 + 1 + 34)
+        // + 2 (segwit marker) = 722 WU, the fee is 1805.
+        let changeless = TxOut {
+            value: Amount::from_sat(40_000 - 1805),
+            ..payment
+        };
+        let funded = fund_psbt(
+            candidates,
+            vec![changeless],
+            fee_rate,
+            None,
+            &CoinSelectionAlgorithm::BranchAndBound,
+            change,
+        )
+        .unwrap();
+        assert_eq!(funded.psbt.unsigned_tx.input.len(), 2);
+        assert_eq!(funded.change_position, None);
+        assert_eq!(funded.fee, Amount::from_sat(1805));
+    }
+}
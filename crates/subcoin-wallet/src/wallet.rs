@@ -0,0 +1,1212 @@
+use crate::coin_selection::CoinSelectionAlgorithm;
+use crate::psbt::{fund_psbt, ChangeOutput, FundedPsbt};
+use crate::Error;
+use bitcoin::{
+    Address, Amount, Block as BitcoinBlock, BlockHash, FeeRate, OutPoint, ScriptBuf, TxOut, Txid,
+};
+use codec::Decode;
+use futures::StreamExt;
+use parking_lot::RwLock;
+use sc_client_api::{AuxStore, Backend, BlockBackend, BlockchainEvents, StorageProvider};
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use subcoin_db::{columns, SubcoinDb};
+use subcoin_primitives::descriptor::{with_checksum, Descriptor};
+use subcoin_primitives::runtime::Coin;
+use subcoin_primitives::{
+    convert_to_bitcoin_block, BackendExt, BitcoinTransactionAdapter, ClientExt, CoinStorageKey,
+};
+
+/// Key of the persisted wallet state in [`columns::WALLET`].
+const WALLET_STATE_KEY: &[u8] = b"subcoin_wallet_state";
+
+/// Number of blocks rescanned between two checkpoints of the rescan progress.
+const RESCAN_CHECKPOINT_INTERVAL: u32 = 1000;
+
+/// Number of confirmations required for the coinbase outputs to be spendable.
+const COINBASE_MATURITY: u32 = 100;
+
+/// Number of recent blocks whose undo data is kept for handling the reorgs.
+const MAX_UNDO_BLOCKS: usize = 100;
+
+/// Output descriptor imported into the wallet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedDescriptor {
+    /// Descriptor with checksum.
+    pub desc: String,
+    /// Inclusive derivation range, `None` if the descriptor is not ranged.
+    pub range: Option<(u32, u32)>,
+    /// Next derivation index which has not been used, for deriving the change scripts.
+    #[serde(default)]
+    pub next_index: u32,
+}
+
+/// Unspent output owned by the wallet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletUtxo {
+    pub outpoint: OutPoint,
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub amount: Amount,
+    pub script_pubkey: ScriptBuf,
+    pub height: u32,
+    pub is_coinbase: bool,
+    /// Index of the descriptor matching the output script.
+    pub descriptor: usize,
+    /// Derivation index of the output script.
+    pub index: u32,
+}
+
+/// Transaction affecting the wallet balance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletTransaction {
+    pub txid: Txid,
+    pub block_hash: BlockHash,
+    pub height: u32,
+    /// Sum of the wallet outputs created by the transaction.
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub received: Amount,
+    /// Sum of the wallet outputs spent by the transaction.
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub sent: Amount,
+}
+
+/// Wallet balances in BTC, no mempool is available so nothing is pending.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Balances {
+    /// Confirmed and spendable.
+    pub trusted: Amount,
+    /// Unconfirmed, always zero for now.
+    pub untrusted_pending: Amount,
+    /// Coinbase outputs that have not reached the maturity.
+    pub immature: Amount,
+}
+
+/// Progress of a rescan over the historical blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RescanProgress {
+    pub start_height: u32,
+    /// Next block to scan.
+    pub current_height: u32,
+    /// Last block to scan, inclusive.
+    pub stop_height: u32,
+}
+
+/// Result of a rescan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RescanOutcome {
+    /// All the blocks in the range have been scanned.
+    Completed(RescanProgress),
+    /// The rescan was aborted and can be resumed later.
+    Aborted(RescanProgress),
+}
+
+/// Checkpoint of an interrupted rescan, persisted for resuming.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct RescanCursor {
+    progress: Option<RescanProgress>,
+    /// Wallet outputs found by the rescan so far, for detecting the spends.
+    found_outputs: Vec<(OutPoint, u64)>,
+}
+
+/// State of the rescan shared between the running rescan and the observers.
+#[derive(Debug, Default)]
+struct RescanStatus {
+    progress: parking_lot::Mutex<Option<RescanProgress>>,
+    abort: AtomicBool,
+}
+
+/// Changes made by a block to the wallet, for undoing the block on reorg.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlockUndo {
+    block_hash: BlockHash,
+    /// Best block of the wallet before applying the block.
+    prev_best_block: Option<(u32, BlockHash)>,
+    /// Wallet UTXOs spent by the block.
+    spent: Vec<WalletUtxo>,
+    /// Wallet UTXOs created by the block.
+    created: Vec<OutPoint>,
+}
+
+/// Location of a script derived from an imported descriptor.
+#[derive(Debug, Clone, Copy)]
+struct ScriptOrigin {
+    descriptor: usize,
+    index: u32,
+}
+
+/// Watch-only wallet state.
+///
+/// The state is tied to [`WalletState::best_block`], the wallet is up to date once
+/// `best_block` catches up with the best block of the client.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WalletState {
+    descriptors: Vec<ImportedDescriptor>,
+    utxos: Vec<WalletUtxo>,
+    history: Vec<WalletTransaction>,
+    best_block: Option<(u32, BlockHash)>,
+    /// Checkpoint of the interrupted rescan.
+    #[serde(default)]
+    rescan: Option<RescanCursor>,
+    /// Undo data of the recent blocks applied by [`WalletState::apply_block`], oldest first.
+    #[serde(default)]
+    undo: VecDeque<BlockUndo>,
+    /// Scripts derived from the descriptors, rebuilt on loading.
+    #[serde(skip)]
+    scripts: HashMap<ScriptBuf, ScriptOrigin>,
+    /// Index of the UTXOs by outpoint, rebuilt on loading.
+    #[serde(skip)]
+    utxo_index: BTreeMap<OutPoint, usize>,
+}
+
+impl WalletState {
+    fn rebuild_index(&mut self) -> Result<(), Error> {
+        self.scripts.clear();
+        for index in 0..self.descriptors.len() {
+            self.derive_scripts(index)?;
+        }
+        self.utxo_index = self
+            .utxos
+            .iter()
+            .enumerate()
+            .map(|(i, utxo)| (utxo.outpoint, i))
+            .collect();
+        Ok(())
+    }
+
+    /// Derives the scripts of descriptor at `index`, returns the newly derived scripts.
+    fn derive_scripts(&mut self, index: usize) -> Result<Vec<ScriptBuf>, Error> {
+        let imported = &self.descriptors[index];
+        let descriptor = Descriptor::from_str(&imported.desc)?;
+
+        let mut new_scripts = Vec::new();
+        for (i, script) in descriptor.script_pubkeys(imported.range)? {
+            if self
+                .scripts
+                .insert(
+                    script.clone(),
+                    ScriptOrigin {
+                        descriptor: index,
+                        index: i,
+                    },
+                )
+                .is_none()
+            {
+                new_scripts.push(script);
+            }
+        }
+
+        Ok(new_scripts)
+    }
+
+    fn is_mine(&self, script: &ScriptBuf) -> Option<ScriptOrigin> {
+        self.scripts.get(script).copied()
+    }
+
+    fn add_utxo(&mut self, utxo: WalletUtxo) {
+        if self.utxo_index.contains_key(&utxo.outpoint) {
+            return;
+        }
+        let imported = &mut self.descriptors[utxo.descriptor];
+        if imported.range.is_some() {
+            imported.next_index = imported.next_index.max(utxo.index + 1);
+        }
+        self.utxo_index.insert(utxo.outpoint, self.utxos.len());
+        self.utxos.push(utxo);
+    }
+
+    fn remove_utxo(&mut self, outpoint: &OutPoint) -> Option<WalletUtxo> {
+        let index = self.utxo_index.remove(outpoint)?;
+        let utxo = self.utxos.swap_remove(index);
+        if let Some(moved) = self.utxos.get(index) {
+            self.utxo_index.insert(moved.outpoint, index);
+        }
+        Some(utxo)
+    }
+
+    /// Applies a new best block to the wallet.
+    pub fn apply_block(&mut self, height: u32, block: &BitcoinBlock) {
+        let block_hash = block.block_hash();
+
+        let mut undo = BlockUndo {
+            block_hash,
+            prev_best_block: self.best_block,
+            spent: Vec::new(),
+            created: Vec::new(),
+        };
+
+        for (tx_index, tx) in block.txdata.iter().enumerate() {
+            let txid = tx.compute_txid();
+            let is_coinbase = tx_index == 0;
+
+            let mut sent = Amount::ZERO;
+            if !is_coinbase {
+                for input in &tx.input {
+                    if let Some(utxo) = self.remove_utxo(&input.previous_output) {
+                        sent += utxo.amount;
+                        undo.spent.push(utxo);
+                    }
+                }
+            }
+
+            let mut received = Amount::ZERO;
+            for (vout, txout) in tx.output.iter().enumerate() {
+                if let Some(origin) = self.is_mine(&txout.script_pubkey) {
+                    received += txout.value;
+                    let outpoint = OutPoint {
+                        txid,
+                        vout: vout as u32,
+                    };
+                    if !self.utxo_index.contains_key(&outpoint) {
+                        undo.created.push(outpoint);
+                    }
+                    self.add_utxo(WalletUtxo {
+                        outpoint,
+                        amount: txout.value,
+                        script_pubkey: txout.script_pubkey.clone(),
+                        height,
+                        is_coinbase,
+                        descriptor: origin.descriptor,
+                        index: origin.index,
+                    });
+                }
+            }
+
+            if sent > Amount::ZERO || received > Amount::ZERO {
+                self.history.push(WalletTransaction {
+                    txid,
+                    block_hash,
+                    height,
+                    received,
+                    sent,
+                });
+            }
+        }
+
+        self.best_block.replace((height, block_hash));
+
+        if self.undo.len() == MAX_UNDO_BLOCKS {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(undo);
+    }
+
+    /// Undoes the best block of the wallet, returns `false` if there is no undo data for it.
+    fn undo_block(&mut self) -> bool {
+        let Some(undo) = self.undo.pop_back() else {
+            return false;
+        };
+
+        // The outputs created and spent within the block are added back before being removed.
+        for utxo in undo.spent.into_iter().rev() {
+            self.add_utxo(utxo);
+        }
+        for outpoint in &undo.created {
+            self.remove_utxo(outpoint);
+        }
+
+        self.history.retain(|tx| tx.block_hash != undo.block_hash);
+        self.best_block = undo.prev_best_block;
+
+        true
+    }
+
+    /// Undoes the blocks no longer in the best chain until the best block of the wallet is
+    /// the common ancestor of the new best chain.
+    ///
+    /// Returns `false` if the reorg is deeper than the undo data.
+    fn revert_to_best_chain(&mut self, is_in_best_chain: impl Fn(u32, BlockHash) -> bool) -> bool {
+        while let Some((number, block_hash)) = self.best_block {
+            if is_in_best_chain(number, block_hash) {
+                return true;
+            }
+
+            if !self.undo_block() {
+                return false;
+            }
+
+            tracing::debug!("Undone wallet block #{number},{block_hash}");
+        }
+
+        true
+    }
+
+    /// Returns the transactions in the block affecting the wallet without updating the UTXOs.
+    ///
+    /// `found_outputs` tracks the wallet outputs seen during the rescan, outputs spent before
+    /// the rescan starting block are unknown and not counted in [`WalletTransaction::sent`].
+    fn scan_block(
+        &self,
+        height: u32,
+        block: &BitcoinBlock,
+        found_outputs: &mut HashMap<OutPoint, Amount>,
+    ) -> Vec<WalletTransaction> {
+        let block_hash = block.block_hash();
+
+        let mut transactions = Vec::new();
+
+        for (tx_index, tx) in block.txdata.iter().enumerate() {
+            let txid = tx.compute_txid();
+
+            let sent = if tx_index == 0 {
+                Amount::ZERO
+            } else {
+                tx.input
+                    .iter()
+                    .filter_map(|input| {
+                        found_outputs.remove(&input.previous_output).or_else(|| {
+                            self.utxo_index
+                                .get(&input.previous_output)
+                                .map(|index| self.utxos[*index].amount)
+                        })
+                    })
+                    .sum()
+            };
+
+            let mut received = Amount::ZERO;
+            for (vout, txout) in tx.output.iter().enumerate() {
+                if self.is_mine(&txout.script_pubkey).is_some() {
+                    received += txout.value;
+                    found_outputs.insert(
+                        OutPoint {
+                            txid,
+                            vout: vout as u32,
+                        },
+                        txout.value,
+                    );
+                }
+            }
+
+            if sent > Amount::ZERO || received > Amount::ZERO {
+                transactions.push(WalletTransaction {
+                    txid,
+                    block_hash,
+                    height,
+                    received,
+                    sent,
+                });
+            }
+        }
+
+        transactions
+    }
+
+    /// Merges the rescanned transactions into the history, ordered by height.
+    fn merge_history(&mut self, transactions: Vec<WalletTransaction>) {
+        let known = self
+            .history
+            .iter()
+            .map(|tx| tx.txid)
+            .collect::<std::collections::HashSet<_>>();
+
+        let len = self.history.len();
+        self.history.extend(
+            transactions
+                .into_iter()
+                .filter(|tx| !known.contains(&tx.txid)),
+        );
+
+        if self.history.len() > len {
+            self.history.sort_by_key(|tx| tx.height);
+        }
+    }
+
+    /// Returns the balances at the best block of the wallet.
+    pub fn balances(&self) -> Balances {
+        let best_number = self
+            .best_block
+            .map(|(number, _)| number)
+            .unwrap_or_default();
+
+        self.utxos
+            .iter()
+            .fold(Balances::default(), |mut balances, utxo| {
+                if is_mature(utxo, best_number) {
+                    balances.trusted += utxo.amount;
+                } else {
+                    balances.immature += utxo.amount;
+                }
+                balances
+            })
+    }
+}
+
+fn confirmations(best_number: u32, height: u32) -> u32 {
+    (best_number + 1).saturating_sub(height)
+}
+
+fn is_mature(utxo: &WalletUtxo, best_number: u32) -> bool {
+    !utxo.is_coinbase || confirmations(best_number, utxo.height) >= COINBASE_MATURITY
+}
+
+/// Unspent output returned by [`Wallet::list_unspent`].
+#[derive(Debug, Clone)]
+pub struct UnspentOutput {
+    pub utxo: WalletUtxo,
+    pub address: Option<Address>,
+    pub confirmations: u32,
+    /// Descriptor matching the output script.
+    pub desc: String,
+    pub spendable: bool,
+}
+
+/// Watch-only wallet tracking the outputs of the imported descriptors.
+///
+/// No keys are involved, the wallet is unable to sign any transaction.
+pub struct Wallet<Block, Client, BE> {
+    client: Arc<Client>,
+    db: SubcoinDb,
+    network: bitcoin::Network,
+    coin_storage_key: Arc<dyn CoinStorageKey>,
+    state: Arc<RwLock<WalletState>>,
+    rescan: Arc<RescanStatus>,
+    _phantom: PhantomData<(Block, BE)>,
+}
+
+impl<Block, Client, BE> Clone for Wallet<Block, Client, BE> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            db: self.db.clone(),
+            network: self.network,
+            coin_storage_key: self.coin_storage_key.clone(),
+            state: self.state.clone(),
+            rescan: self.rescan.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<Block, Client, BE> Wallet<Block, Client, BE>
+where
+    Block: BlockT,
+    BE: Backend<Block>,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore,
+{
+    /// Constructs a new instance of [`Wallet`], loading the persisted state if any.
+    ///
+    /// The state persisted in the aux storage by the older versions is migrated to `db`.
+    pub fn new(
+        client: Arc<Client>,
+        db: SubcoinDb,
+        network: bitcoin::Network,
+        coin_storage_key: Arc<dyn CoinStorageKey>,
+    ) -> Result<Self, Error> {
+        db.migrate_from_aux(client.as_ref(), columns::WALLET, &[WALLET_STATE_KEY])?;
+
+        let mut state = match db.get(columns::WALLET, WALLET_STATE_KEY) {
+            Some(encoded) => serde_json::from_slice::<WalletState>(&encoded)?,
+            None => WalletState::default(),
+        };
+
+        state.rebuild_index()?;
+
+        Ok(Self {
+            client,
+            db,
+            network,
+            coin_storage_key,
+            state: Arc::new(RwLock::new(state)),
+            rescan: Arc::default(),
+            _phantom: PhantomData,
+        })
+    }
+
+    fn persist(&self, state: &WalletState) -> Result<(), Error> {
+        let encoded = serde_json::to_vec(state)?;
+        self.db
+            .insert(columns::WALLET, WALLET_STATE_KEY, &encoded)?;
+        Ok(())
+    }
+
+    /// Imports a descriptor and scans the UTXO set for the matching outputs.
+    ///
+    /// `range` is the inclusive derivation range of a ranged descriptor, `[0, 999]` by default.
+    ///
+    /// Only the UTXO set is scanned, the history before the import is not available.
+    pub fn import_descriptor(&self, desc: &str, range: Option<(u32, u32)>) -> Result<(), Error> {
+        let descriptor = Descriptor::from_str(desc)?;
+
+        let range = descriptor.derivation_range(range)?;
+
+        let desc = with_checksum(desc)?;
+
+        let mut state = self.state.write();
+
+        let (best_number, best_hash) = match state.best_block {
+            Some((number, bitcoin_block_hash)) => {
+                let hash = self
+                    .client
+                    .substrate_block_hash_for(bitcoin_block_hash)
+                    .ok_or(Error::BlockNotFound(bitcoin_block_hash))?;
+                (number, hash)
+            }
+            None => (self.client.best_number(), self.client.info().best_hash),
+        };
+
+        let index = match state.descriptors.iter().position(|d| d.desc == desc) {
+            Some(index) => {
+                state.descriptors[index].range = range;
+                index
+            }
+            None => {
+                state.descriptors.push(ImportedDescriptor {
+                    desc,
+                    range,
+                    next_index: 0,
+                });
+                state.descriptors.len() - 1
+            }
+        };
+
+        let new_scripts = state.derive_scripts(index)?;
+
+        if !new_scripts.is_empty() {
+            // The undo data does not cover the outputs of the new scripts fetched below.
+            state.undo.clear();
+
+            let storage_prefix = self.coin_storage_key.storage_prefix();
+            let storage_key = sc_client_api::StorageKey(storage_prefix.to_vec());
+
+            for (key, value) in self
+                .client
+                .storage_pairs(best_hash, Some(&storage_key), None)?
+            {
+                let Ok(coin) = Coin::decode(&mut value.0.as_slice()) else {
+                    continue;
+                };
+
+                let script_pubkey = ScriptBuf::from_bytes(coin.script_pubkey);
+
+                let Some(origin) = state.is_mine(&script_pubkey) else {
+                    continue;
+                };
+
+                let Some(outpoint) = self.coin_storage_key.outpoint(&key.0) else {
+                    continue;
+                };
+
+                state.add_utxo(WalletUtxo {
+                    outpoint,
+                    amount: Amount::from_sat(coin.amount),
+                    script_pubkey,
+                    height: coin.height,
+                    is_coinbase: coin.is_coinbase,
+                    descriptor: origin.descriptor,
+                    index: origin.index,
+                });
+            }
+        }
+
+        if state.best_block.is_none() {
+            let bitcoin_block_hash = self
+                .client
+                .bitcoin_block_hash_for(best_hash)
+                .ok_or(sp_blockchain::Error::MissingHeader(best_hash.to_string()))?;
+            state.best_block.replace((best_number, bitcoin_block_hash));
+        }
+
+        self.persist(&state)
+    }
+
+    /// Returns the imported descriptors.
+    pub fn descriptors(&self) -> Vec<ImportedDescriptor> {
+        self.state.read().descriptors.clone()
+    }
+
+    /// Returns the wallet UTXOs with confirmations in `[min_conf, max_conf]`.
+    pub fn list_unspent(&self, min_conf: u32, max_conf: u32) -> Vec<UnspentOutput> {
+        let state = self.state.read();
+        let best_number = state
+            .best_block
+            .map(|(number, _)| number)
+            .unwrap_or_default();
+
+        let mut unspent = state
+            .utxos
+            .iter()
+            .filter_map(|utxo| {
+                let confirmations = confirmations(best_number, utxo.height);
+                if confirmations < min_conf || confirmations > max_conf {
+                    return None;
+                }
+                Some(UnspentOutput {
+                    address: Address::from_script(&utxo.script_pubkey, self.network).ok(),
+                    confirmations,
+                    desc: state.descriptors[utxo.descriptor].desc.clone(),
+                    spendable: false,
+                    utxo: utxo.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        unspent.sort_by_key(|output| (output.utxo.height, output.utxo.outpoint));
+
+        unspent
+    }
+
+    /// Creates a PSBT paying to `outputs`, funded by the mature wallet UTXOs selected with
+    /// `algorithm`.
+    ///
+    /// The change goes to `change_script` if specified, otherwise to the next unused script of
+    /// the first ranged descriptor which is reserved once the PSBT is created.
+    pub fn create_funded_psbt(
+        &self,
+        outputs: Vec<TxOut>,
+        fee_rate: FeeRate,
+        long_term_fee_rate: Option<FeeRate>,
+        algorithm: CoinSelectionAlgorithm,
+        change_script: Option<ScriptBuf>,
+    ) -> Result<FundedPsbt, Error> {
+        let mut state = self.state.write();
+        let best_number = state
+            .best_block
+            .map(|(number, _)| number)
+            .unwrap_or_default();
+
+        let descriptors = state
+            .descriptors
+            .iter()
+            .map(|imported| Descriptor::from_str(&imported.desc))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let candidates = state
+            .utxos
+            .iter()
+            .filter(|utxo| is_mature(utxo, best_number))
+            .map(|utxo| (utxo.clone(), descriptors[utxo.descriptor].clone()))
+            .collect();
+
+        let (change, reserved) = match change_script {
+            Some(script_pubkey) => {
+                let descriptor = state
+                    .is_mine(&script_pubkey)
+                    .map(|origin| (descriptors[origin.descriptor].clone(), origin.index));
+                (
+                    ChangeOutput {
+                        script_pubkey,
+                        descriptor,
+                    },
+                    None,
+                )
+            }
+            None => {
+                let (index, derivation_index) = state
+                    .descriptors
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, imported)| {
+                        let (start, end) = imported.range?;
+                        let next_index = imported.next_index.max(start);
+                        (next_index <= end).then_some((index, next_index))
+                    })
+                    .ok_or(Error::NoChangeAddress)?;
+                let descriptor = descriptors[index].clone();
+                (
+                    ChangeOutput {
+                        script_pubkey: descriptor.script_pubkey(derivation_index)?,
+                        descriptor: Some((descriptor, derivation_index)),
+                    },
+                    Some((index, derivation_index)),
+                )
+            }
+        };
+
+        let funded = fund_psbt(
+            candidates,
+            outputs,
+            fee_rate,
+            long_term_fee_rate,
+            &algorithm,
+            change,
+        )?;
+
+        if let (Some((index, derivation_index)), Some(_)) = (reserved, funded.change_position) {
+            state.descriptors[index].next_index = derivation_index + 1;
+            self.persist(&state)?;
+        }
+
+        Ok(funded)
+    }
+
+    /// Returns the current balances.
+    pub fn balances(&self) -> Balances {
+        self.state.read().balances()
+    }
+
+    /// Returns the transactions affecting the wallet since the descriptors were imported.
+    pub fn history(&self) -> Vec<WalletTransaction> {
+        self.state.read().history.clone()
+    }
+
+    /// Returns the best block number and hash processed by the wallet.
+    pub fn best_block(&self) -> Option<(u32, BlockHash)> {
+        self.state.read().best_block
+    }
+
+    /// Returns the progress of the running rescan.
+    pub fn rescan_progress(&self) -> Option<RescanProgress> {
+        *self.rescan.progress.lock()
+    }
+
+    /// Returns the checkpoint of the interrupted rescan which can be resumed.
+    pub fn interrupted_rescan(&self) -> Option<RescanProgress> {
+        self.state
+            .read()
+            .rescan
+            .as_ref()
+            .and_then(|cursor| cursor.progress)
+    }
+
+    /// Requests the running rescan to stop, returns `false` if there is no rescan running.
+    pub fn abort_rescan(&self) -> bool {
+        if self.rescan.progress.lock().is_none() {
+            return false;
+        }
+        self.rescan.abort.store(true, Ordering::SeqCst);
+        true
+    }
+
+    /// Scans the blocks in `[from_height, stop_height]` for the transactions affecting the
+    /// wallet and merges them into the history.
+    ///
+    /// `stop_height` defaults to the best block of the wallet. The UTXOs are not touched as
+    /// they have been fetched from the UTXO set on import.
+    ///
+    /// The progress is checkpointed periodically, an aborted or interrupted rescan can be
+    /// continued with [`Self::resume_rescan`].
+    pub fn rescan<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        from_height: u32,
+        stop_height: Option<u32>,
+    ) -> Result<RescanOutcome, Error> {
+        let best_number = self
+            .best_block()
+            .map(|(number, _)| number)
+            .ok_or(Error::NoDescriptors)?;
+        let stop_height = stop_height.unwrap_or(best_number).min(best_number);
+
+        if from_height > stop_height {
+            return Err(Error::InvalidRescanRange(from_height, stop_height));
+        }
+
+        let progress = RescanProgress {
+            start_height: from_height,
+            current_height: from_height,
+            stop_height,
+        };
+
+        self.run_rescan::<TransactionAdapter>(progress, HashMap::new())
+    }
+
+    /// Resumes the interrupted rescan, returns `None` if there is nothing to resume.
+    pub fn resume_rescan<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+    ) -> Result<Option<RescanOutcome>, Error> {
+        let Some(cursor) = self.state.read().rescan.clone() else {
+            return Ok(None);
+        };
+
+        let Some(progress) = cursor.progress else {
+            return Ok(None);
+        };
+
+        let found_outputs = cursor
+            .found_outputs
+            .into_iter()
+            .map(|(outpoint, amount)| (outpoint, Amount::from_sat(amount)))
+            .collect();
+
+        self.run_rescan::<TransactionAdapter>(progress, found_outputs)
+            .map(Some)
+    }
+
+    fn run_rescan<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        mut progress: RescanProgress,
+        mut found_outputs: HashMap<OutPoint, Amount>,
+    ) -> Result<RescanOutcome, Error> {
+        {
+            let mut running = self.rescan.progress.lock();
+            if running.is_some() {
+                return Err(Error::RescanInProgress);
+            }
+            running.replace(progress);
+        }
+
+        self.rescan.abort.store(false, Ordering::SeqCst);
+
+        tracing::info!(
+            "Rescanning wallet from #{} to #{}",
+            progress.current_height,
+            progress.stop_height
+        );
+
+        let result = (|| {
+            let mut transactions = Vec::new();
+
+            while progress.current_height <= progress.stop_height {
+                let aborted = self.rescan.abort.swap(false, Ordering::SeqCst);
+
+                if aborted
+                    || (progress.current_height - progress.start_height)
+                        % RESCAN_CHECKPOINT_INTERVAL
+                        == 0
+                {
+                    let mut state = self.state.write();
+                    state.merge_history(std::mem::take(&mut transactions));
+                    state.rescan.replace(RescanCursor {
+                        progress: Some(progress),
+                        found_outputs: found_outputs
+                            .iter()
+                            .map(|(outpoint, amount)| (*outpoint, amount.to_sat()))
+                            .collect(),
+                    });
+                    self.persist(&state)?;
+
+                    if aborted {
+                        tracing::info!("Wallet rescan aborted at #{}", progress.current_height);
+                        return Ok(RescanOutcome::Aborted(progress));
+                    }
+
+                    tracing::info!(
+                        "Wallet rescan progress: #{}/{}",
+                        progress.current_height,
+                        progress.stop_height
+                    );
+                }
+
+                let block = self.bitcoin_block::<TransactionAdapter>(progress.current_height)?;
+
+                transactions.extend(self.state.read().scan_block(
+                    progress.current_height,
+                    &block,
+                    &mut found_outputs,
+                ));
+
+                progress.current_height += 1;
+                self.rescan.progress.lock().replace(progress);
+            }
+
+            let mut state = self.state.write();
+            state.merge_history(transactions);
+            state.rescan.take();
+            self.persist(&state)?;
+
+            tracing::info!("Wallet rescan completed at #{}", progress.stop_height);
+
+            Ok(RescanOutcome::Completed(progress))
+        })();
+
+        self.rescan.progress.lock().take();
+
+        result
+    }
+
+    fn bitcoin_block<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        number: u32,
+    ) -> Result<BitcoinBlock, Error> {
+        let block_hash = self
+            .client
+            .hash(number.into())?
+            .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("#{number}")))?;
+
+        let block = match self.client.block(block_hash)? {
+            Some(signed_block) => signed_block.block,
+            None if self.client.header(block_hash)?.is_some() => {
+                return Err(Error::BlockPruned(number));
+            }
+            None => return Err(sp_blockchain::Error::UnknownBlock(block_hash.to_string()).into()),
+        };
+
+        convert_to_bitcoin_block::<Block, TransactionAdapter>(block).map_err(Error::InvalidBlock)
+    }
+
+    /// Returns a future following the new best blocks.
+    ///
+    /// The future needs to be spawned in the background.
+    pub async fn run<TransactionAdapter: BitcoinTransactionAdapter<Block>>(self) {
+        // Receive the notifications during the major sync too, otherwise the wallet falls behind.
+        let mut block_import_stream = self.client.every_import_notification_stream();
+
+        while let Some(notification) = block_import_stream.next().await {
+            if !notification.is_new_best {
+                continue;
+            }
+
+            let Ok(new_best) = (*notification.header.number()).try_into() else {
+                continue;
+            };
+
+            if let Err(err) = self.catch_up::<TransactionAdapter>(new_best) {
+                tracing::error!(?err, "Failed to update wallet");
+            }
+        }
+    }
+
+    fn catch_up<TransactionAdapter: BitcoinTransactionAdapter<Block>>(
+        &self,
+        new_best: u32,
+    ) -> Result<(), Error> {
+        let mut state = self.state.write();
+
+        // Nothing to track until the first descriptor is imported.
+        let Some(prev_best_block) = state.best_block else {
+            return Ok(());
+        };
+
+        if !state.revert_to_best_chain(|number, hash| self.client.block_hash(number) == Some(hash))
+        {
+            if let Some((best_number, best_hash)) = state.best_block {
+                tracing::warn!(
+                    "Wallet best block #{best_number},{best_hash} is no longer in the best chain, \
+                    the reorg is deeper than the undo data"
+                );
+            }
+        }
+
+        let Some((best_number, _)) = state.best_block else {
+            return self.persist(&state);
+        };
+
+        for number in best_number + 1..=new_best {
+            let block = self.bitcoin_block::<TransactionAdapter>(number)?;
+            state.apply_block(number, &block);
+        }
+
+        if state.best_block == Some(prev_best_block) {
+            return Ok(());
+        }
+
+        self.persist(&state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Transaction, TxIn, TxOut};
+
+    fn tx(inputs: Vec<OutPoint>, outputs: Vec<(u64, ScriptBuf)>) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output: outputs
+                .into_iter()
+                .map(|(value, script_pubkey)| TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_apply_block() {
+        let mut state = WalletState {
+            descriptors: vec![ImportedDescriptor {
+                desc: with_checksum(
+                    "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)",
+                )
+                .unwrap(),
+                range: None,
+                next_index: 0,
+            }],
+            ..Default::default()
+        };
+        state.rebuild_index().unwrap();
+
+        let mine = state.scripts.keys().next().unwrap().clone();
+        let other = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros());
+
+        let coinbase = tx(vec![OutPoint::null()], vec![(50, mine.clone())]);
+        let payment = tx(
+            vec![OutPoint::new(Txid::all_zeros(), 7)],
+            vec![(30, mine.clone()), (20, other.clone())],
+        );
+        let payment_txid = payment.compute_txid();
+
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let block = BitcoinBlock {
+            header: genesis.header,
+            txdata: vec![coinbase, payment],
+        };
+
+        state.apply_block(1, &block);
+
+        assert_eq!(state.utxos.len(), 2);
+        assert_eq!(state.history.len(), 2);
+        assert_eq!(
+            state.balances(),
+            Balances {
+                trusted: Amount::from_sat(30),
+                untrusted_pending: Amount::ZERO,
+                immature: Amount::from_sat(50),
+            }
+        );
+
+        let spend = tx(
+            vec![OutPoint::new(payment_txid, 0)],
+            vec![(25, other.clone())],
+        );
+        let block = BitcoinBlock {
+            header: genesis.header,
+            txdata: vec![tx(vec![OutPoint::null()], vec![(50, other)]), spend],
+        };
+
+        state.apply_block(2, &block);
+
+        assert_eq!(state.utxos.len(), 1);
+        assert_eq!(state.history.last().unwrap().sent, Amount::from_sat(30));
+        assert_eq!(state.balances().trusted, Amount::ZERO);
+        assert_eq!(state.best_block.unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_reorg_funded_output() {
+        let mut state = WalletState {
+            descriptors: vec![ImportedDescriptor {
+                desc: with_checksum(
+                    "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)",
+                )
+                .unwrap(),
+                range: None,
+                next_index: 0,
+            }],
+            ..Default::default()
+        };
+        state.rebuild_index().unwrap();
+
+        let mine = state.scripts.keys().next().unwrap().clone();
+        let other = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros());
+
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        state.best_block.replace((0, genesis.block_hash()));
+
+        let block = |nonce, txdata| BitcoinBlock {
+            header: bitcoin::block::Header {
+                nonce,
+                ..genesis.header
+            },
+            txdata,
+        };
+
+        let funding = tx(vec![OutPoint::new(Txid::all_zeros(), 7)], vec![(30, mine)]);
+        let funding_txid = funding.compute_txid();
+        let block_1 = block(
+            1,
+            vec![
+                tx(vec![OutPoint::null()], vec![(50, other.clone())]),
+                funding,
+            ],
+        );
+        state.apply_block(1, &block_1);
+
+        // Spent in block #2.
+        let spend = tx(
+            vec![OutPoint::new(funding_txid, 0)],
+            vec![(25, other.clone())],
+        );
+        let block_2 = block(
+            2,
+            vec![tx(vec![OutPoint::null()], vec![(50, other.clone())]), spend],
+        );
+        state.apply_block(2, &block_2);
+        assert!(state.utxos.is_empty());
+        assert_eq!(state.history.len(), 2);
+
+        // Block #2 is reorged out, the funded output is unspent again.
+        let block_1_hash = block_1.block_hash();
+        assert!(state.revert_to_best_chain(|_, hash| hash == block_1_hash));
+        assert_eq!(state.best_block, Some((1, block_1_hash)));
+        assert_eq!(state.balances().trusted, Amount::from_sat(30));
+        assert_eq!(state.history.len(), 1);
+
+        // Block #1 is reorged out by a block without the funding transaction.
+        let genesis_hash = genesis.block_hash();
+        assert!(state.revert_to_best_chain(|_, hash| hash == genesis_hash));
+        assert_eq!(state.best_block, Some((0, genesis_hash)));
+        assert!(state.utxos.is_empty());
+        assert!(state.utxo_index.is_empty());
+        assert!(state.history.is_empty());
+
+        let new_block_1 = block(3, vec![tx(vec![OutPoint::null()], vec![(50, other)])]);
+        state.apply_block(1, &new_block_1);
+        assert_eq!(state.best_block, Some((1, new_block_1.block_hash())));
+        assert_eq!(state.balances(), Balances::default());
+
+        // No undo data beyond the first applied block.
+        assert!(!state.revert_to_best_chain(|_, _| false));
+        assert_eq!(state.best_block, Some((0, genesis_hash)));
+    }
+
+    #[test]
+    fn test_scan_block() {
+        let mut state = WalletState {
+            descriptors: vec![ImportedDescriptor {
+                desc: with_checksum(
+                    "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)",
+                )
+                .unwrap(),
+                range: None,
+                next_index: 0,
+            }],
+            ..Default::default()
+        };
+        state.rebuild_index().unwrap();
+
+        let mine = state.scripts.keys().next().unwrap().clone();
+        let other = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros());
+
+        let payment = tx(vec![OutPoint::new(Txid::all_zeros(), 7)], vec![(30, mine)]);
+        let spend = tx(
+            vec![OutPoint::new(payment.compute_txid(), 0)],
+            vec![(25, other.clone())],
+        );
+
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let block = BitcoinBlock {
+            header: genesis.header,
+            txdata: vec![
+                tx(vec![OutPoint::null()], vec![(50, other)]),
+                payment,
+                spend,
+            ],
+        };
+
+        let mut found_outputs = HashMap::new();
+        let transactions = state.scan_block(10, &block, &mut found_outputs);
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].received, Amount::from_sat(30));
+        assert_eq!(transactions[1].sent, Amount::from_sat(30));
+        assert!(found_outputs.is_empty());
+
+        // The UTXOs are untouched by the rescan.
+        assert!(state.utxos.is_empty());
+
+        state.merge_history(transactions.clone());
+        state.merge_history(transactions);
+        assert_eq!(state.history.len(), 2);
+    }
+}